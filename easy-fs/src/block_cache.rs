@@ -1,6 +1,8 @@
 use super::{BlockDevice, BLOCK_SZ};
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
 use lazy_static::*;
 use spin::Mutex;
 /// Cached block inside memory
@@ -16,10 +18,12 @@ pub struct BlockCache {
 }
 
 impl BlockCache {
-    /// Load a new BlockCache from disk.
-    pub fn new(block_id: usize, block_device: Arc<dyn BlockDevice>) -> Self {
-        let mut cache = [0u8; BLOCK_SZ];
-        block_device.read_block(block_id, &mut cache);
+    /// A BlockCache of `cache` read from disk already.
+    fn with_data(
+        block_id: usize,
+        cache: [u8; BLOCK_SZ],
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Self {
         Self {
             cache,
             block_id,
@@ -74,11 +78,19 @@ impl Drop for BlockCache {
         self.sync()
     }
 }
-/// Use a block cache of 16 blocks
-const BLOCK_CACHE_SIZE: usize = 16;
+/// Use a block cache of 64 blocks
+const BLOCK_CACHE_SIZE: usize = 64;
+/// Blocks read ahead along with a missing one, those following it
+const READ_AHEAD: usize = 8;
+
+/// A cached block by the address of its device and its block id, as the
+/// same block id may be cached for several devices
+type CacheKey = (usize, usize);
+/// A cached block with its key
+type CacheEntry = (CacheKey, Arc<Mutex<BlockCache>>);
 
 pub struct BlockCacheManager {
-    queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+    queue: VecDeque<CacheEntry>,
 }
 
 impl BlockCacheManager {
@@ -93,31 +105,65 @@ impl BlockCacheManager {
         block_id: usize,
         block_device: Arc<dyn BlockDevice>,
     ) -> Arc<Mutex<BlockCache>> {
-        if let Some(pair) = self.queue.iter().find(|pair| pair.0 == block_id) {
-            Arc::clone(&pair.1)
-        } else {
-            // substitute
-            if self.queue.len() == BLOCK_CACHE_SIZE {
-                // from front to tail
-                if let Some((idx, _)) = self
-                    .queue
-                    .iter()
-                    .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
-                {
-                    self.queue.drain(idx..=idx);
-                } else {
-                    panic!("Run out of BlockCache!");
+        let device = device_address(&block_device);
+        if let Some(pair) = self.queue.iter().find(|pair| pair.0 == (device, block_id)) {
+            return Arc::clone(&pair.1);
+        }
+        if !self.make_room(0) {
+            panic!("Run out of BlockCache!");
+        }
+        // the blocks following a missing one are likely to be read next, e.g.
+        // those of a file, so they are read along with it while there is room
+        let end = block_device
+            .block_count()
+            .map_or(block_id + 1, |count| count.min(block_id + 1 + READ_AHEAD));
+        let mut block_ids = vec![block_id];
+        for id in block_id + 1..end {
+            if self.queue.iter().any(|pair| pair.0 == (device, id))
+                || !self.make_room(block_ids.len())
+            {
+                break;
+            }
+            block_ids.push(id);
+        }
+        let mut data = vec![[0u8; BLOCK_SZ]; block_ids.len()];
+        let mut requests: Vec<(usize, &mut [u8])> = block_ids
+            .iter()
+            .copied()
+            .zip(data.iter_mut().map(|cache| &mut cache[..]))
+            .collect();
+        trace!(
+            "read block {} and {} following ones",
+            block_id,
+            block_ids.len() - 1
+        );
+        block_device.read_blocks(&mut requests);
+        drop(requests);
+        // load blocks into mem and push back, the missing one first
+        let first = self.queue.len();
+        for (id, cache) in block_ids.into_iter().zip(data) {
+            let block_cache = BlockCache::with_data(id, cache, Arc::clone(&block_device));
+            self.queue
+                .push_back(((device, id), Arc::new(Mutex::new(block_cache))));
+        }
+        Arc::clone(&self.queue[first].1)
+    }
+    /// Substitute unused blocks from front to tail until there is room for
+    /// one more than `pending` blocks. Return false if all are in use.
+    fn make_room(&mut self, pending: usize) -> bool {
+        while self.queue.len() + pending >= BLOCK_CACHE_SIZE {
+            match self
+                .queue
+                .iter()
+                .position(|pair| Arc::strong_count(&pair.1) == 1)
+            {
+                Some(idx) => {
+                    self.queue.remove(idx);
                 }
+                None => return false,
             }
-            // load block into mem and push back
-            let block_cache = Arc::new(Mutex::new(BlockCache::new(
-                block_id,
-                Arc::clone(&block_device),
-            )));
-            self.queue.push_back((block_id, Arc::clone(&block_cache)));
-            block_cache
         }
+        true
     }
 }
 
@@ -135,10 +181,75 @@ pub fn get_block_cache(
         .lock()
         .get_block_cache(block_id, block_device)
 }
+/// State of the block cache
+pub struct BlockCacheStats {
+    /// blocks the cache holds at most
+    pub capacity: usize,
+    /// blocks cached
+    pub cached: usize,
+    /// cached blocks referenced outside the cache, which stay cached
+    pub in_use: usize,
+    /// cached blocks modified since read or written back
+    pub dirty: usize,
+    /// cached blocks locked, not known to be dirty or not
+    pub locked: usize,
+}
+/// State of the block cache, without waiting for any lock, e.g. one held by
+/// a task stuck in the filesystem. `None` if the cache itself is locked.
+pub fn block_cache_stats() -> Option<BlockCacheStats> {
+    let manager = BLOCK_CACHE_MANAGER.try_lock()?;
+    let mut stats = BlockCacheStats {
+        capacity: BLOCK_CACHE_SIZE,
+        cached: manager.queue.len(),
+        in_use: 0,
+        dirty: 0,
+        locked: 0,
+    };
+    for (_, cache) in manager.queue.iter() {
+        if Arc::strong_count(cache) > 1 {
+            stats.in_use += 1;
+        }
+        match cache.try_lock() {
+            Some(cache) if cache.modified => stats.dirty += 1,
+            Some(_) => {}
+            None => stats.locked += 1,
+        }
+    }
+    Some(stats)
+}
 /// Sync all block cache to block device
 pub fn block_cache_sync_all() {
     let manager = BLOCK_CACHE_MANAGER.lock();
-    for (_, cache) in manager.queue.iter() {
-        cache.lock().sync();
+    let mut dirty: Vec<_> = manager
+        .queue
+        .iter()
+        .map(|(_, cache)| cache.lock())
+        .filter(|cache| cache.modified)
+        .collect();
+    // the dirty blocks of each device are written at once
+    while let Some(first) = dirty.first() {
+        let device = Arc::clone(&first.block_device);
+        let (mut batch, rest): (Vec<_>, Vec<_>) = dirty
+            .into_iter()
+            .partition(|cache| same_device(&cache.block_device, &device));
+        let requests: Vec<(usize, &[u8])> = batch
+            .iter()
+            .map(|cache| (cache.block_id, &cache.cache[..]))
+            .collect();
+        debug!("writing back {} dirty blocks", requests.len());
+        device.write_blocks(&requests);
+        drop(requests);
+        for cache in batch.iter_mut() {
+            cache.modified = false;
+        }
+        dirty = rest;
     }
 }
+/// Whether `a` and `b` are the same device, by address only
+fn same_device(a: &Arc<dyn BlockDevice>, b: &Arc<dyn BlockDevice>) -> bool {
+    device_address(a) == device_address(b)
+}
+/// Address of `device`, telling it apart from the other devices
+fn device_address(device: &Arc<dyn BlockDevice>) -> usize {
+    Arc::as_ptr(device) as *const u8 as usize
+}
@@ -6,4 +6,24 @@ pub trait BlockDevice: Send + Sync + Any {
     fn read_block(&self, block_id: usize, buf: &mut [u8]);
     ///Write data from buffer to block
     fn write_block(&self, block_id: usize, buf: &[u8]);
+    ///Read blocks into the buffers of `requests`, at once on devices taking several requests
+    fn read_blocks(&self, requests: &mut [(usize, &mut [u8])]) {
+        for (block_id, buf) in requests.iter_mut() {
+            self.read_block(*block_id, buf);
+        }
+    }
+    ///Write the buffers of `requests` to blocks, at once on devices taking several requests
+    fn write_blocks(&self, requests: &[(usize, &[u8])]) {
+        for (block_id, buf) in requests.iter() {
+            self.write_block(*block_id, buf);
+        }
+    }
+    ///Number of blocks of the device, `None` if unknown
+    fn block_count(&self) -> Option<usize> {
+        None
+    }
+    ///Handle an interrupt of the device, for devices completing requests by interrupts
+    fn handle_irq(&self) {}
+    ///Finish the requests completed by the interrupts, deferred by the interrupt handler
+    fn handle_softirq(&self) {}
 }
@@ -15,6 +15,12 @@ pub struct EasyFileSystem {
     pub data_bitmap: Bitmap,
     inode_area_start_block: u32,
     data_area_start_block: u32,
+    clock: fn() -> u32,
+}
+
+/// The clock of a filesystem until [`EasyFileSystem::set_clock`]
+fn no_clock() -> u32 {
+    0
 }
 
 type DataBlock = [u8; BLOCK_SZ];
@@ -45,6 +51,7 @@ impl EasyFileSystem {
             data_bitmap,
             inode_area_start_block: 1 + inode_bitmap_blocks,
             data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
+            clock: no_clock,
         };
         // clear all blocks
         for i in 0..total_blocks {
@@ -88,6 +95,10 @@ impl EasyFileSystem {
             .lock()
             .read(0, |super_block: &SuperBlock| {
                 assert!(super_block.is_valid(), "Error loading EFS!");
+                debug!(
+                    "{} inode blocks, {} data blocks",
+                    super_block.inode_area_blocks, super_block.data_area_blocks
+                );
                 let inode_total_blocks =
                     super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
                 let efs = Self {
@@ -99,10 +110,19 @@ impl EasyFileSystem {
                     ),
                     inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
                     data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
+                    clock: no_clock,
                 };
                 Arc::new(Mutex::new(efs))
             })
     }
+    /// Stamp the inodes modified with the seconds since the Unix epoch given by `clock`
+    pub fn set_clock(&mut self, clock: fn() -> u32) {
+        self.clock = clock;
+    }
+    /// Seconds since the Unix epoch, 0 without a clock
+    pub fn now(&self) -> u32 {
+        (self.clock)()
+    }
     /// Get the root inode of the filesystem
     pub fn root_inode(efs: &Arc<Mutex<Self>>) -> Inode {
         let block_device = Arc::clone(&efs.lock().block_device);
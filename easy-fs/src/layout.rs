@@ -3,10 +3,11 @@ use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt::{Debug, Formatter, Result};
 
-/// Magic number for sanity check
-const EFS_MAGIC: u32 = 0x3b800001;
+/// Magic number for sanity check, bumped whenever the on-disk layout changes so
+/// that images of an older layout are rejected. 0x3b800002 adds the inode mtime.
+const EFS_MAGIC: u32 = 0x3b800002;
 /// The max number of direct inodes
-const INODE_DIRECT_COUNT: usize = 27;
+const INODE_DIRECT_COUNT: usize = 26;
 /// The max length of inode name
 const NAME_LENGTH_LIMIT: usize = 27;
 /// The max number of indirect1 inodes
@@ -87,6 +88,8 @@ pub struct DiskInode {
     pub indirect1: u32,
     pub indirect2: u32,
     pub indirect3: u32,
+    /// Last modification, seconds since the Unix epoch
+    pub mtime: u32,
     type_: DiskInodeType,
 }
 
@@ -99,6 +102,7 @@ impl DiskInode {
         self.indirect1 = 0;
         self.indirect2 = 0;
         self.indirect3 = 0;
+        self.mtime = 0;
         self.type_ = type_;
     }
     /// Whether this inode is a directory
@@ -2,6 +2,8 @@
 #![no_std]
 #![deny(missing_docs)]
 extern crate alloc;
+#[macro_use]
+extern crate log;
 mod bitmap;
 mod block_cache;
 mod block_dev;
@@ -11,7 +13,8 @@ mod vfs;
 /// Use a block size of 512 bytes
 pub const BLOCK_SZ: usize = 512;
 use bitmap::Bitmap;
-use block_cache::{block_cache_sync_all, get_block_cache};
+use block_cache::get_block_cache;
+pub use block_cache::{block_cache_stats, block_cache_sync_all, BlockCacheStats};
 pub use block_dev::BlockDevice;
 pub use efs::EasyFileSystem;
 use layout::*;
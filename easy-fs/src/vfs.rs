@@ -111,6 +111,7 @@ impl Inode {
             .lock()
             .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
                 new_inode.initialize(DiskInodeType::File);
+                new_inode.mtime = fs.now();
             });
         self.modify_disk_inode(|root_inode| {
             // append file in the dirent
@@ -125,6 +126,7 @@ impl Inode {
                 dirent.as_bytes(),
                 &self.block_device,
             );
+            root_inode.mtime = fs.now();
         });
 
         let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
@@ -155,6 +157,12 @@ impl Inode {
             v
         })
     }
+    /// Size of the data of current inode in bytes and the time it was last
+    /// modified in seconds since the Unix epoch
+    pub fn stat(&self) -> (u32, u32) {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| (disk_inode.size, disk_inode.mtime))
+    }
     /// Read data from current inode
     pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
         let _fs = self.fs.lock();
@@ -165,6 +173,7 @@ impl Inode {
         let mut fs = self.fs.lock();
         let size = self.modify_disk_inode(|disk_inode| {
             self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
+            disk_inode.mtime = fs.now();
             disk_inode.write_at(offset, buf, &self.block_device)
         });
         block_cache_sync_all();
@@ -180,6 +189,7 @@ impl Inode {
             for data_block in data_blocks_dealloc.into_iter() {
                 fs.dealloc_data(data_block);
             }
+            disk_inode.mtime = fs.now();
         });
         block_cache_sync_all();
     }
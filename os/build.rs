@@ -1,6 +1,15 @@
+use std::fs;
+use std::path::Path;
+
 static TARGET_PATH: &str = "../user/target/riscv64gc-unknown-none-elf/release/";
+/// symbols of the kernel linked into it, generated by the Makefile after the first build
+static KERNEL_SYMBOLS: &str = "target/kernel.sym";
 
 fn main() {
     println!("cargo:rerun-if-changed=../user/src/");
     println!("cargo:rerun-if-changed={}", TARGET_PATH);
+    if !Path::new(KERNEL_SYMBOLS).exists() {
+        fs::create_dir_all("target").unwrap();
+        fs::write(KERNEL_SYMBOLS, "").unwrap();
+    }
 }
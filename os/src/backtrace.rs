@@ -0,0 +1,160 @@
+//! Backtraces of the kernel stack by its frame pointers, printed on panic.
+//!
+//! The kernel is built with `-Cforce-frame-pointers=yes`, so every function
+//! saves `ra` at `fp - 8` and the frame pointer of its caller at `fp - 16`.
+use crate::config::{KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE};
+use crate::fdt::memory_end;
+use crate::kaslr;
+use core::arch::asm;
+
+/// Most frames printed, in case the frame pointers are corrupted into a loop
+const MAX_FRAMES: usize = 32;
+/// Size of the boot stack of each hart, as set up in `entry.asm`
+const BOOT_STACK_SIZE: usize = 4096 * 16;
+
+/// Text symbols of the kernel as printed by `nm -n`, generated by the Makefile
+/// once the kernel is linked and linked in by building it again. Symbols are at
+/// the same addresses then, as the text comes before the table. Empty until then.
+static KERNEL_SYMBOLS: &[u8] = include_bytes!("../target/kernel.sym");
+
+/// The symbol containing `pc` and the offset of `pc` in it. The symbols are
+/// at their link addresses, below `pc` by the offset the kernel moved by.
+pub fn lookup(pc: usize) -> Option<(&'static str, usize)> {
+    let pc = pc.checked_sub(kaslr::offset())?;
+    let symbols = core::str::from_utf8(KERNEL_SYMBOLS).ok()?;
+    let mut found = None;
+    // lines of `address type name`, sorted by address
+    for line in symbols.lines() {
+        let mut fields = line.splitn(3, ' ');
+        let addr = match fields.next().map(|addr| usize::from_str_radix(addr, 16)) {
+            Some(Ok(addr)) => addr,
+            _ => continue,
+        };
+        if addr > pc {
+            break;
+        }
+        if let Some(name) = fields.nth(1) {
+            found = Some((name, pc - addr));
+        }
+    }
+    found
+}
+
+/// Top of the stack containing `sp`, a boot stack or a kernel stack
+fn stack_top(sp: usize) -> Option<usize> {
+    extern "C" {
+        fn boot_stack_lower_bound();
+        fn boot_stack_top();
+    }
+    let boot_stacks = boot_stack_lower_bound as usize..boot_stack_top as usize;
+    if boot_stacks.contains(&sp) {
+        let index = (sp - boot_stacks.start) / BOOT_STACK_SIZE;
+        Some(boot_stacks.start + (index + 1) * BOOT_STACK_SIZE)
+    } else if sp > memory_end() && sp < TRAMPOLINE {
+        // kernel stacks below the trampoline, each above a guard page
+        let slot = KERNEL_STACK_SIZE + PAGE_SIZE;
+        Some(TRAMPOLINE - (TRAMPOLINE - sp) / slot * slot)
+    } else {
+        None
+    }
+}
+
+/// Print frame `depth` returning to `ra`, with the symbol containing the call
+fn print_frame(depth: usize, ra: usize) {
+    // `ra` follows the call, which may be the last instruction of the caller
+    match lookup(ra - 1) {
+        Some((name, offset)) => println!("  #{} {:#x} {}+{:#x}", depth, ra, name, offset + 1),
+        None => println!("  #{} {:#x}", depth, ra),
+    }
+}
+
+/// Return addresses of the frames chained by frame pointers from `fp` on the
+/// stack at `sp`, innermost first
+fn frames(sp: usize, mut fp: usize) -> impl Iterator<Item = usize> {
+    // no frame is found on an unknown stack
+    let top = stack_top(sp).unwrap_or(0);
+    core::iter::from_fn(move || {
+        if fp % 8 != 0 || fp < sp + 16 || fp > top {
+            return None;
+        }
+        let (ra, prev_fp) = unsafe { (*((fp - 8) as *const usize), *((fp - 16) as *const usize)) };
+        if ra == 0 {
+            return None;
+        }
+        // frames of callers are above, the walk ends at the next one otherwise
+        fp = if prev_fp > fp { prev_fp } else { 0 };
+        Some(ra)
+    })
+}
+
+/// Print the frames chained by frame pointers from `fp` on the stack at `sp`,
+/// numbered from `depth`
+fn print_frames(sp: usize, fp: usize, depth: usize) {
+    for (depth, ra) in (depth..MAX_FRAMES).zip(frames(sp, fp)) {
+        print_frame(depth, ra);
+    }
+}
+
+/// `sp` and the frame pointer of the caller
+#[inline(always)]
+fn current_frame() -> (usize, usize) {
+    let (sp, fp): (usize, usize);
+    unsafe {
+        asm!("mv {}, sp", out(reg) sp);
+        asm!("mv {}, s0", out(reg) fp);
+    }
+    (sp, fp)
+}
+
+/// Print the return addresses on the current stack with their symbols,
+/// innermost first, without leaving the stack
+pub fn print_backtrace() {
+    let (sp, fp) = current_frame();
+    error!("Backtrace:");
+    print_frames(sp, fp, 0);
+}
+
+/// Fill `addrs` with the return addresses on the current stack, innermost
+/// first, the first one in the caller, and 0 past the outermost one. Nothing
+/// is allocated, for the heap allocator to call it.
+pub fn return_addresses(addrs: &mut [usize]) {
+    let (sp, fp) = current_frame();
+    addrs.fill(0);
+    for (addr, ra) in addrs.iter_mut().zip(frames(sp, fp)) {
+        *addr = ra;
+    }
+}
+
+/// Print the stack of a task switched out by `__switch`, which resumes at `ra`
+/// with `sp` and the frame pointer `fp` saved in its `TaskContext`
+pub fn print_switched_out(ra: usize, sp: usize, fp: usize) {
+    print_frame(0, ra);
+    print_frames(sp, fp, 1);
+}
+
+/// Print the words of the kernel stack ending at `top` that look like return
+/// addresses into the kernel text, innermost first. The stack is in use by
+/// another hart, so its frame pointers cannot be followed, and some of the
+/// addresses may be left over from calls returned already.
+pub fn print_stack_scan(top: usize) {
+    extern "C" {
+        fn stext();
+        fn etext();
+    }
+    let text = stext as usize..etext as usize;
+    let mut found = 0;
+    for addr in (top - KERNEL_STACK_SIZE..top).step_by(8) {
+        let word = unsafe { *(addr as *const usize) };
+        if !text.contains(&word) {
+            continue;
+        }
+        match lookup(word - 1) {
+            Some((name, offset)) => println!("  ? {:#x} {}+{:#x}", word, name, offset + 1),
+            None => continue,
+        }
+        found += 1;
+        if found == MAX_FRAMES {
+            break;
+        }
+    }
+}
@@ -1,12 +1,69 @@
+//! The virt machine of QEMU. Its memory, harts and devices are found in the
+//! device tree, the constants below are used without one.
+use crate::fdt::PciHost;
+
 pub const CLOCK_FREQ: usize = 12500000;
 pub const MEMORY_END: usize = 0x801000000;
 
 pub const MMIO: &[(usize, usize)] = &[
     (0x0010_0000, 0x00_2000), // VIRT_TEST/RTC  in virt machine
+    (0x0C00_0000, 0x21_0000), // VIRT_PLIC in virt machine
+    (0x1000_0000, 0x00_1000), // VIRT_UART0 in virt machine
     (0x1000_1000, 0x00_1000), // Virtio Block in virt machine
+    (0x1000_2000, 0x00_1000), // Virtio Net in virt machine
+    (0x1000_3000, 0x00_1000), // Virtio GPU in virt machine
+    (0x1000_4000, 0x00_2000), // Virtio Input (keyboard and mouse) in virt machine
+    (0x1000_6000, 0x00_2000), // Virtio Block (more disks) in virt machine
+    (0x1000_8000, 0x00_1000), // Virtio Console in virt machine
+];
+
+/// RTC of the wall clock, `None` on boards without one
+pub const VIRT_RTC: Option<usize> = Some(0x0010_1000);
+pub const VIRT_PLIC: usize = 0x0C00_0000;
+/// number of interrupt sources of the PLIC, including the unused source 0
+pub const PLIC_SOURCES: usize = 128;
+pub const VIRT_UART: usize = 0x1000_0000;
+/// interrupt source of the UART at the PLIC
+pub const UART_IRQ: usize = 10;
+/// the registers of the 16550s are bytes, 1 byte apart
+pub const UART_REG_SHIFT: usize = 0;
+
+/// Interrupt context of the supervisor mode of `hart` at the PLIC, after its
+/// machine mode one
+pub fn plic_context(hart: usize) -> usize {
+    hart * 2 + 1
+}
+
+/// virtio MMIO slots with their interrupt sources at the PLIC, probed for
+/// devices of each type
+pub const VIRTIO_MMIO: &[(usize, usize)] = &[
+    (0x1000_1000, 1),
+    (0x1000_2000, 2),
+    (0x1000_3000, 3),
+    (0x1000_4000, 4),
+    (0x1000_5000, 5),
+    (0x1000_6000, 6),
+    (0x1000_7000, 7),
+    (0x1000_8000, 8),
 ];
 
-pub type BlockDeviceImpl = crate::drivers::block::VirtIOBlock;
+/// PCI host bridge, with the virtio devices QEMU is given on PCI
+pub const VIRT_PCI: Option<PciHost> = Some(PciHost {
+    ecam: 0x3000_0000,
+    mem: 0x4000_0000,
+    mem_pci: 0x4000_0000,
+    mem_size: 0x4000_0000,
+    io: 0x0300_0000,
+    io_pci: 0,
+    io_size: 0x1_0000,
+    irqs: [32, 33, 34, 35],
+});
+
+/// block device holding the root filesystem, a disk like `vda` or a partition like `vda1`
+pub const ROOT_DEVICE: &str = "vda";
+
+/// the harts set the accessed and dirty bits of page-table entries
+pub const HARDWARE_PTE_AD: bool = true;
 
 //ref:: https://github.com/andre-richter/qemu-exit
 use core::arch::asm;
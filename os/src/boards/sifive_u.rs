@@ -0,0 +1,80 @@
+//! The SiFive FU540 of the HiFive Unleashed, also emulated by the sifive_u
+//! machine of QEMU, booting from an SD card in the slot on its SPI controller.
+//! Hart 0 is a monitor core without supervisor mode, the kernel runs on the
+//! others. The memory and harts are found in the device tree, the constants
+//! below are used without one.
+use crate::fdt::PciHost;
+
+/// frequency of the timer, RTCCLK of the HiFive boards
+pub const CLOCK_FREQ: usize = 1_000_000;
+pub const MEMORY_END: usize = 0x9000_0000;
+
+pub const MMIO: &[(usize, usize)] = &[
+    (0x0C00_0000, 0x40_0000), // PLIC
+    (0x1001_0000, 0x00_1000), // UART0
+    (0x1005_0000, 0x00_1000), // SPI2, the SD card slot
+];
+
+/// RTC of the wall clock, `None` on boards without one
+pub const VIRT_RTC: Option<usize> = None;
+pub const VIRT_PLIC: usize = 0x0C00_0000;
+/// number of interrupt sources of the PLIC, including the unused source 0
+pub const PLIC_SOURCES: usize = 54;
+pub const VIRT_UART: usize = 0x1001_0000;
+/// interrupt source of the UART at the PLIC
+pub const UART_IRQ: usize = 4;
+
+/// Interrupt context of the supervisor mode of `hart` at the PLIC, after its
+/// machine mode one, hart 0 having only the latter
+pub fn plic_context(hart: usize) -> usize {
+    hart * 2
+}
+
+/// no virtio MMIO slots on this board
+pub const VIRTIO_MMIO: &[(usize, usize)] = &[];
+/// no PCI host bridge on the FU540
+pub const VIRT_PCI: Option<PciHost> = None;
+
+/// SPI controller of the SD card slot, the card is `mmcblk0`
+pub const SD_SPI: usize = 0x1005_0000;
+/// frequency of the input clock of the SPI controllers, half the core clock.
+/// If the actual one is lower, the serial clock is only slower than asked.
+pub const SPI_CLOCK_FREQ: usize = 500_000_000;
+
+/// block device holding the root filesystem, a disk like `mmcblk0` or a
+/// partition like `mmcblk0p1`
+pub const ROOT_DEVICE: &str = "mmcblk0";
+
+/// the U54 cores raise a page fault on an access to a page whose accessed
+/// bit, or dirty bit for a store, is clear, rather than setting them
+pub const HARDWARE_PTE_AD: bool = false;
+
+/// Power off the board, which can only report whether the run failed to the
+/// firmware, not an exit code, without the test device of the QEMU virt machine
+pub trait QEMUExit {
+    /// Power off after a run ending with `code`, a failure unless 0
+    fn exit(&self, code: u32) -> !;
+    /// Power off after a successful run
+    fn exit_success(&self) -> !;
+    /// Power off after a failed run
+    fn exit_failure(&self) -> !;
+}
+
+/// Powering off by the SBI
+pub struct SbiExit;
+
+impl QEMUExit for SbiExit {
+    fn exit(&self, code: u32) -> ! {
+        // the legacy shutdown is left for firmware without system reset
+        crate::sbi::poweroff(code != 0);
+        crate::sbi::shutdown()
+    }
+    fn exit_success(&self) -> ! {
+        self.exit(0)
+    }
+    fn exit_failure(&self) -> ! {
+        self.exit(1)
+    }
+}
+
+pub const QEMU_EXIT_HANDLE: SbiExit = SbiExit;
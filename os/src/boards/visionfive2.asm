@@ -0,0 +1,27 @@
+    # header of a RISC-V Linux kernel image, for `booti` of U-Boot to start
+    # the kernel at the start of the image with the hart id and device tree
+    .section .text.head
+    .globl _head
+_head:
+    .option push
+    .option norvc
+    j _start
+    .option pop
+    .word 0
+    # text_offset: where the image goes past the start of the memory
+    .dword 0x200000
+    # image_size: with the memory cleared for .bss
+    .dword ekernel - _head
+    # flags: little endian
+    .dword 0
+    # version 0.2 of the header
+    .word 2
+    .word 0
+    .dword 0
+    # magic, deprecated for magic2
+    .ascii "RISCV"
+    .byte 0, 0, 0
+    # magic2
+    .ascii "RSC"
+    .byte 0x05
+    .word 0
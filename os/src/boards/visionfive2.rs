@@ -0,0 +1,88 @@
+//! The StarFive VisionFive 2, with the JH7110, booting from an SD card.
+//! U-Boot loads the kernel image at 0x4020_0000, where it is linked, and
+//! starts it with `booti` and its device tree, e.g.
+//! `tftpboot 0x40200000 os.bin; booti 0x40200000 - ${fdtcontroladdr}`.
+//! Hart 0 is a monitor core without supervisor mode, the kernel runs on the
+//! others, any of them booting it. The timer and the IPIs go through the SBI,
+//! the CLINT being left to the firmware. The memory and harts are found in
+//! the device tree, the constants below are used without one.
+use crate::fdt::PciHost;
+
+core::arch::global_asm!(include_str!("visionfive2.asm"));
+
+/// frequency of the timer, the 4 MHz of `timebase-frequency` of the JH7110
+pub const CLOCK_FREQ: usize = 4_000_000;
+/// end of the memory of the 4 GiB model
+pub const MEMORY_END: usize = 0x1_4000_0000;
+
+pub const MMIO: &[(usize, usize)] = &[
+    (0x0C00_0000, 0x400_0000), // PLIC
+    (0x1000_0000, 0x01_0000),  // UART0
+    (0x1602_0000, 0x01_0000),  // SDIO1, the SD card slot
+];
+
+/// RTC of the wall clock, `None` on boards without one
+pub const VIRT_RTC: Option<usize> = None;
+pub const VIRT_PLIC: usize = 0x0C00_0000;
+/// number of interrupt sources of the PLIC, including the unused source 0
+pub const PLIC_SOURCES: usize = 137;
+pub const VIRT_UART: usize = 0x1000_0000;
+/// interrupt source of the UART at the PLIC
+pub const UART_IRQ: usize = 32;
+/// the registers of the DesignWare UARTs are 32-bit words, 4 bytes apart
+pub const UART_REG_SHIFT: usize = 2;
+
+/// Interrupt context of the supervisor mode of `hart` at the PLIC, after its
+/// machine mode one, hart 0 having only the latter
+pub fn plic_context(hart: usize) -> usize {
+    hart * 2
+}
+
+/// no virtio MMIO slots on this board
+pub const VIRTIO_MMIO: &[(usize, usize)] = &[];
+/// the PCIe controllers of the JH7110 are not generic ECAM host bridges
+pub const VIRT_PCI: Option<PciHost> = None;
+
+/// SD controller of the card slot, the card is `mmcblk0`
+pub const SD_MMC: usize = 0x1602_0000;
+/// frequency of the input clock of the SD controller, as the firmware sets
+/// it up. If the actual one is lower, the card clock is only slower than asked.
+pub const SDIO_CLOCK_FREQ: usize = 49_500_000;
+
+/// block device holding the root filesystem, a disk like `mmcblk0` or a
+/// partition like `mmcblk0p1`
+pub const ROOT_DEVICE: &str = "mmcblk0";
+
+/// the U74 cores raise a page fault on an access to a page whose accessed
+/// bit, or dirty bit for a store, is clear, rather than setting them
+pub const HARDWARE_PTE_AD: bool = false;
+
+/// Power off the board, which can only report whether the run failed to the
+/// firmware, not an exit code, without the test device of the QEMU virt machine
+pub trait QEMUExit {
+    /// Power off after a run ending with `code`, a failure unless 0
+    fn exit(&self, code: u32) -> !;
+    /// Power off after a successful run
+    fn exit_success(&self) -> !;
+    /// Power off after a failed run
+    fn exit_failure(&self) -> !;
+}
+
+/// Powering off by the SBI
+pub struct SbiExit;
+
+impl QEMUExit for SbiExit {
+    fn exit(&self, code: u32) -> ! {
+        // the legacy shutdown is left for firmware without system reset
+        crate::sbi::poweroff(code != 0);
+        crate::sbi::shutdown()
+    }
+    fn exit_success(&self) -> ! {
+        self.exit(0)
+    }
+    fn exit_failure(&self) -> ! {
+        self.exit(1)
+    }
+}
+
+pub const QEMU_EXIT_HANDLE: SbiExit = SbiExit;
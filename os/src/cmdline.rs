@@ -0,0 +1,81 @@
+//! Options of the kernel command line, given by the bootloader in the
+//! `/chosen` node of the device tree, e.g. by `-append` of QEMU
+//!
+//! The command line is a list of words separated by spaces, each an option
+//! `key=value`, or `key` alone for a flag. When an option is given several
+//! times, the last one counts. The options are:
+//!
+//! - `console=ttyS<n>`: UART of the kernel console, `ttyS0` by default
+//! - `shell=ttyS<n>`: UART of the standard input and output of user programs
+//! - `log=<filter>`: which kernel log records are printed, see [`crate::logging`]
+//! - `sched=priority|stride|mlfq|cfs`: scheduling policy
+//! - `timeslice=<ms>`: scheduling quantum
+//! - `root=<device>`: block device of the root filesystem, e.g. `vdb1`
+//! - `init=<program>`: first user program, `initproc` by default
+//! - `test`: run `usertests` as the first user program, powering off with
+//!   its result
+//! - `ktest[=<filter>]`: run the kernel unit tests instead, see [`crate::ktest`]
+//! - `norandmaps`: start the heaps of programs right after them rather than
+//!   at random
+//! - `nokaslr`: keep the kernel where it is loaded, see [`crate::kaslr`]
+//! - `allocstat`: count heap allocations by call site, listed in
+//!   `/proc/allocstat`
+use crate::fdt::MACHINE;
+use core::str::FromStr;
+
+/// Options the kernel knows, others are reported at boot
+const OPTIONS: &[&str] = &[
+    "console",
+    "shell",
+    "log",
+    "sched",
+    "timeslice",
+    "root",
+    "init",
+    "test",
+    "ktest",
+    "allocstat",
+    "norandmaps",
+    "nokaslr",
+];
+
+/// Words of the command line as keys and values, `""` for the flags
+fn options() -> impl Iterator<Item = (&'static str, &'static str)> {
+    MACHINE
+        .bootargs
+        .split_whitespace()
+        .map(|word| word.split_once('=').unwrap_or((word, "")))
+}
+
+/// Value of option `key`, `None` if it is not given
+pub fn get(key: &str) -> Option<&'static str> {
+    options()
+        .filter(|&(other, _)| other == key)
+        .map(|(_, value)| value)
+        .last()
+}
+
+/// Whether flag `key` is given
+pub fn flag(key: &str) -> bool {
+    get(key).is_some()
+}
+
+/// Value of option `key` parsed, `None` if it is not given or invalid, the
+/// latter being reported
+pub fn parse<T: FromStr>(key: &str) -> Option<T> {
+    let value = get(key)?;
+    let parsed = value.parse().ok();
+    if parsed.is_none() {
+        warn!("invalid {}={} on the command line", key, value);
+    }
+    parsed
+}
+
+/// Report the command line and the options the kernel does not know, once
+/// the machine is discovered
+pub fn init() {
+    info!("command line: {}", MACHINE.bootargs);
+    for (key, _) in options().filter(|(key, _)| !OPTIONS.contains(key)) {
+        warn!("unknown option {} on the command line", key);
+    }
+}
@@ -2,13 +2,54 @@
 #[allow(unused)]
 
 pub const USER_STACK_SIZE: usize = 4096 * 2;
-pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
+/// with room for the interrupt handlers nesting on the kernel stack of a task
+pub const KERNEL_STACK_SIZE: usize = 4096 * 4;
 pub const KERNEL_HEAP_SIZE: usize = 0x20_0000;
 
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
 
+/// harts with a smaller id may run the kernel, the four application cores
+/// after the monitor hart 0 of the SiFive and StarFive SoCs included, see the
+/// boot stacks in `entry.asm`
+pub const MAX_HARTS: usize = 5;
+
+/// use Sv48 page tables if the hardware supports them, Sv39 otherwise
+pub const PREFER_SV48: bool = true;
+
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
+/// lowest address of the user stacks of threads, each preceded by a guard page
+pub const USER_STACK_BASE: usize = 0x10_0000_0000;
+/// user page holding the code signal handlers return to, which calls sigreturn
+pub const SIGRETURN_TRAMPOLINE: usize = USER_STACK_BASE - PAGE_SIZE;
+/// lowest address of the area used for anonymous mappings
+pub const MMAP_BASE: usize = 0x20_0000_0000;
+/// size of the range the heap of a program starts in past its end, unless
+/// `norandmaps` is on the command line
+pub const BRK_RANDOM_SIZE: usize = 0x200_0000;
+/// default limit on the bytes mapped in a user address space
+pub const USER_AS_LIMIT: usize = 0x100_0000;
+/// default limit on the number of open files of a process
+pub const USER_NOFILE_LIMIT: usize = 64;
+/// priority of a new task, a larger value runs first
+pub const DEFAULT_PRIORITY: usize = 16;
+/// range of priorities accepted by `sys_set_priority`
+pub const MIN_PRIORITY: usize = 2;
+pub const MAX_PRIORITY: usize = 1024;
+/// policy of the task manager
+pub const SCHED_POLICY: SchedPolicy = SchedPolicy::Stride;
+/// default scheduling quantum in milliseconds, see `task::set_time_slice_ms`
+pub const DEFAULT_TIME_SLICE_MS: usize = 10;
+/// number of ready queues of the MLFQ policy
+pub const MLFQ_LEVELS: usize = 3;
+/// interval in milliseconds at which the MLFQ policy moves every task to the top queue
+pub const MLFQ_BOOST_INTERVAL_MS: usize = 1000;
+/// number of context switches kept by the trace of each hart, see `sys_sched_trace`
+pub const SCHED_TRACE_LEN: usize = 256;
+/// area of kernel space used for dynamic mappings, see `mm::vmalloc`
+pub const VMALLOC_START: usize = 0xffff_ffc0_0000_0000;
+pub const VMALLOC_END: usize = 0xffff_ffe0_0000_0000;
 
-pub use crate::board::{CLOCK_FREQ, MEMORY_END, MMIO};
+pub use crate::board::{CLOCK_FREQ, HARDWARE_PTE_AD, MEMORY_END, ROOT_DEVICE};
+use crate::task::SchedPolicy;
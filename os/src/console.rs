@@ -1,11 +1,23 @@
-//! SBI console driver, for text output
+//! Kernel console, for text output through the SBI until the UART chosen for
+//! it is set up, and kept in the ring buffer of [`crate::logging`]
+use crate::drivers::uart::Uart;
+use crate::logging::record;
 use crate::sbi::console_putchar;
+use crate::sync::{Once, SpinNoIrqLock};
 use core::fmt::{self, Write};
 
 struct Stdout;
 
+/// UART the console writes to once set
+static UART: Once<&'static Uart> = Once::new();
+
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> fmt::Result {
+        record(s.as_bytes());
+        if let Some(uart) = UART.get() {
+            uart.write(s.as_bytes());
+            return Ok(());
+        }
         for c in s.chars() {
             console_putchar(c as usize);
         }
@@ -13,8 +25,17 @@ impl Write for Stdout {
     }
 }
 
+/// Write the console to `uart` from now on, once it is set up
+pub fn set_uart(uart: &'static Uart) {
+    UART.call_once(|| uart);
+}
+
+/// serializes output so that lines printed by different harts, or by an
+/// interrupt handler, do not interleave
+static STDOUT: SpinNoIrqLock<Stdout> = SpinNoIrqLock::new(Stdout);
+
 pub fn print(args: fmt::Arguments) {
-    Stdout.write_fmt(args).unwrap();
+    STDOUT.lock().write_fmt(args).unwrap();
 }
 
 #[macro_export]
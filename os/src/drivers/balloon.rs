@@ -0,0 +1,143 @@
+//! Driver of the virtio memory balloon, on either virtio transport
+//!
+//! The host sets the number of pages it wants in the balloon in the
+//! configuration space, e.g. by `balloon` in the QEMU monitor, and raises a
+//! configuration change interrupt. The worker thread then inflates the
+//! balloon by allocating frames and giving their numbers to the host on the
+//! inflate queue, or deflates it by giving them back on the deflate queue and
+//! freeing them. With the deflate-on-OOM feature, the frame allocator also
+//! deflates it whenever it runs out of frames. Requests are polled, which the
+//! host answers right away.
+use super::virtio_mmio::{Transport, VirtQueue, INTERRUPT_CONFIG};
+use crate::config::PAGE_SIZE;
+use crate::mm::{frame_alloc_for, FrameKind, FrameTracker};
+use crate::sync::SpinNoIrqLock;
+use alloc::vec::Vec;
+use core::hint::spin_loop;
+
+const DEVICE_BALLOON: u32 = 5;
+const INFLATE_QUEUE: u32 = 0;
+const DEFLATE_QUEUE: u32 = 1;
+/// Descriptors of each queue, for one request at a time
+const QUEUE_SIZE: u16 = 1;
+/// Feature: the balloon may be deflated when the guest runs out of memory
+const FEATURE_DEFLATE_ON_OOM: u64 = 1 << 2;
+/// Configuration space: pages the host wants in the balloon
+const CONFIG_NUM_PAGES: usize = 0;
+/// Configuration space: pages in the balloon, written by the driver
+const CONFIG_ACTUAL: usize = 4;
+/// Page numbers of a request, filling its buffer
+const PFNS_PER_REQUEST: usize = PAGE_SIZE / 4;
+/// Pages freed each time the frame allocator runs out, 1 MiB
+const OOM_DEFLATE_PAGES: usize = 256;
+
+/// A virtio memory balloon, holding the frames given to the host
+pub struct VirtIOBalloon {
+    transport: Transport,
+    inflate_queue: SpinNoIrqLock<VirtQueue>,
+    deflate_queue: SpinNoIrqLock<VirtQueue>,
+    frames: SpinNoIrqLock<Vec<FrameTracker>>,
+    /// whether the host lets the balloon deflate when out of memory
+    deflate_on_oom: bool,
+}
+
+/// Give the numbers of `frames` to the host on `queue`, waiting for it to take
+/// each request
+fn tell_host(queue: &SpinNoIrqLock<VirtQueue>, frames: &[FrameTracker]) {
+    let mut queue = queue.lock();
+    for chunk in frames.chunks(PFNS_PER_REQUEST) {
+        let buf = queue.buffer(0);
+        for (pfn, frame) in buf.chunks_mut(4).zip(chunk) {
+            pfn.copy_from_slice(&(frame.ppn.0 as u32).to_le_bytes());
+        }
+        queue.push(0, chunk.len() * 4, 0);
+        queue.notify();
+        while queue.pop_used().is_none() {
+            spin_loop();
+        }
+    }
+}
+
+impl VirtIOBalloon {
+    /// Set up the balloon reached by `transport`, empty, `None` if there is none
+    pub fn probe(transport: Transport) -> Option<Self> {
+        if !transport.is_device(DEVICE_BALLOON) {
+            return None;
+        }
+        let features = transport.begin_init(FEATURE_DEFLATE_ON_OOM)?;
+        let mut inflate_queue = VirtQueue::setup(&transport, INFLATE_QUEUE, QUEUE_SIZE);
+        let mut deflate_queue = VirtQueue::setup(&transport, DEFLATE_QUEUE, QUEUE_SIZE);
+        inflate_queue.disable_interrupts();
+        deflate_queue.disable_interrupts();
+        transport.finish_init();
+        transport.write_config_u32(CONFIG_ACTUAL, 0);
+        Some(Self {
+            transport,
+            inflate_queue: SpinNoIrqLock::new(inflate_queue),
+            deflate_queue: SpinNoIrqLock::new(deflate_queue),
+            frames: SpinNoIrqLock::new(Vec::new()),
+            deflate_on_oom: features & FEATURE_DEFLATE_ON_OOM != 0,
+        })
+    }
+    /// Whether the host lets the balloon deflate when out of memory
+    pub fn deflate_on_oom(&self) -> bool {
+        self.deflate_on_oom
+    }
+    /// Acknowledge the interrupt, returning whether the host changed the
+    /// size it wants
+    pub fn handle_irq(&self) -> bool {
+        self.transport.ack_interrupt() & INTERRUPT_CONFIG != 0
+    }
+    /// Inflate or deflate the balloon to the size the host wants, as far as
+    /// there are free frames
+    pub fn adjust(&self) {
+        let target = self.transport.read_config_u32(CONFIG_NUM_PAGES) as usize;
+        let pages = self.frames.lock().len();
+        if target > pages {
+            self.inflate(target - pages);
+        } else if target < pages {
+            self.deflate(pages - target);
+        }
+    }
+    /// Free frames of the balloon once out of memory, if the host allows it.
+    /// Return whether any were freed.
+    pub fn reclaim(&self) -> bool {
+        self.deflate_on_oom && self.deflate(OOM_DEFLATE_PAGES) > 0
+    }
+    /// Put `pages` more frames in the balloon, fewer if they run out
+    fn inflate(&self, pages: usize) {
+        let mut inflated = 0;
+        while inflated < pages {
+            let count = (pages - inflated).min(PFNS_PER_REQUEST);
+            let frames: Vec<FrameTracker> = (0..count)
+                .map_while(|_| frame_alloc_for(FrameKind::Balloon))
+                .collect();
+            if frames.is_empty() {
+                break;
+            }
+            tell_host(&self.inflate_queue, &frames);
+            inflated += frames.len();
+            self.frames.lock().extend(frames);
+            self.update_actual();
+        }
+    }
+    /// Take up to `pages` frames out of the balloon and free them, return
+    /// their number
+    fn deflate(&self, pages: usize) -> usize {
+        let frames = {
+            let mut frames = self.frames.lock();
+            let keep = frames.len().saturating_sub(pages);
+            frames.split_off(keep)
+        };
+        if !frames.is_empty() {
+            tell_host(&self.deflate_queue, &frames);
+            self.update_actual();
+        }
+        frames.len()
+    }
+    /// Tell the host the size of the balloon
+    fn update_actual(&self) {
+        let pages = self.frames.lock().len();
+        self.transport.write_config_u32(CONFIG_ACTUAL, pages as u32);
+    }
+}
@@ -0,0 +1,435 @@
+//! Driver of an SD card on the DesignWare mobile storage host controller of
+//! the StarFive JH7110, which the card slot of the VisionFive 2 is wired to.
+//! The controller is polled and its FIFO read and written by the CPU, one
+//! block per command, on a 4-bit bus at the default speed.
+use super::sd::{
+    csd_blocks, APP_CMD, BLOCK_SIZE, GO_IDLE_STATE, IF_COND, INIT_RETRIES, OCR_CCS,
+    READ_SINGLE_BLOCK, SD_SEND_OP_COND, SEND_CSD, SEND_IF_COND, SET_BLOCKLEN, WRITE_BLOCK,
+};
+use super::BlockDevice;
+use crate::sync::SpinLock;
+use core::ptr::{read_volatile, write_volatile};
+
+/// Control register
+const CTRL: usize = 0x00;
+/// Power enable, a bit per card
+const PWREN: usize = 0x04;
+/// Card clock divisor, the clock is the input one divided by `2 * div`
+const CLKDIV: usize = 0x08;
+/// Card clock source, a divisor per card
+const CLKSRC: usize = 0x0c;
+/// Card clock enable, a bit per card
+const CLKENA: usize = 0x10;
+/// Response and data timeouts
+const TMOUT: usize = 0x14;
+/// Card bus width, a bit per card
+const CTYPE: usize = 0x18;
+/// Block size of a transfer
+const BLKSIZ: usize = 0x1c;
+/// Byte count of a transfer
+const BYTCNT: usize = 0x20;
+/// Interrupt mask
+const INTMASK: usize = 0x24;
+/// Command argument
+const CMDARG: usize = 0x28;
+/// Command, started by its `CMD_START` bit
+const CMD: usize = 0x2c;
+/// Response, the first of four words
+const RESP0: usize = 0x30;
+/// Raw interrupt status, set whatever `INTMASK` is, cleared by writing 1s
+const RINTSTS: usize = 0x44;
+/// Status
+const STATUS: usize = 0x48;
+/// Hardware configuration, read
+const HCON: usize = 0x70;
+/// Version, read
+const VERID: usize = 0x6c;
+/// Bus mode of the internal DMA controller
+const BMOD: usize = 0x80;
+
+/// `CTRL`: reset the controller, the FIFO and the DMA interface
+const CTRL_RESET: u32 = 0x7;
+/// `CTRL`: reset the FIFO
+const CTRL_FIFO_RESET: u32 = 1 << 1;
+/// `CTRL`: raise interrupts
+const CTRL_INT_ENABLE: u32 = 1 << 4;
+/// `CTRL`: transfer by the internal DMA controller
+const CTRL_USE_IDMAC: u32 = 1 << 25;
+/// `CLKENA`: enable the clock of card 0
+const CLKENA_ENABLE: u32 = 1;
+/// `CTYPE`: 4-bit bus for card 0
+const CTYPE_4BIT: u32 = 1;
+
+/// `CMD`: a response is expected
+const CMD_RESPONSE: u32 = 1 << 6;
+/// `CMD`: the response is a long one, of 136 bits
+const CMD_LONG_RESPONSE: u32 = 1 << 7;
+/// `CMD`: check the CRC of the response
+const CMD_CHECK_CRC: u32 = 1 << 8;
+/// `CMD`: the command transfers data
+const CMD_DATA: u32 = 1 << 9;
+/// `CMD`: the data is written to the card
+const CMD_WRITE: u32 = 1 << 10;
+/// `CMD`: wait for the transfer of the previous command to complete
+const CMD_WAIT_PREVIOUS: u32 = 1 << 13;
+/// `CMD`: send the 80 initialization clocks before the command
+const CMD_SEND_INIT: u32 = 1 << 15;
+/// `CMD`: only update the card clock from `CLKDIV`, `CLKSRC` and `CLKENA`
+const CMD_UPDATE_CLOCK: u32 = 1 << 21;
+/// `CMD`: send through the hold register
+const CMD_USE_HOLD_REG: u32 = 1 << 29;
+/// `CMD`: start the command, cleared once the controller takes it
+const CMD_START: u32 = 1 << 31;
+
+/// Responses: R1, R6 and R7 of 48 bits
+const RESPONSE_SHORT: u32 = CMD_RESPONSE | CMD_CHECK_CRC;
+/// Responses: R2, of the CID and CSD registers
+const RESPONSE_LONG: u32 = CMD_RESPONSE | CMD_LONG_RESPONSE | CMD_CHECK_CRC;
+/// Responses: R3, of the OCR, without a CRC
+const RESPONSE_OCR: u32 = CMD_RESPONSE;
+
+/// `RINTSTS`: response error
+const RINTSTS_RE: u32 = 1 << 1;
+/// `RINTSTS`: command done
+const RINTSTS_CD: u32 = 1 << 2;
+/// `RINTSTS`: data transfer over
+const RINTSTS_DTO: u32 = 1 << 3;
+/// `RINTSTS`: response CRC error
+const RINTSTS_RCRC: u32 = 1 << 6;
+/// `RINTSTS`: data CRC error
+const RINTSTS_DCRC: u32 = 1 << 7;
+/// `RINTSTS`: response timeout
+const RINTSTS_RTO: u32 = 1 << 8;
+/// `RINTSTS`: data read timeout
+const RINTSTS_DRTO: u32 = 1 << 9;
+/// `RINTSTS`: data starvation by host timeout
+const RINTSTS_HTO: u32 = 1 << 10;
+/// `RINTSTS`: FIFO underrun or overrun
+const RINTSTS_FRUN: u32 = 1 << 11;
+/// `RINTSTS`: hardware locked write error, a command written while busy
+const RINTSTS_HLE: u32 = 1 << 12;
+/// `RINTSTS`: start bit error
+const RINTSTS_SBE: u32 = 1 << 13;
+/// `RINTSTS`: end bit error, or no CRC status from the card on a write
+const RINTSTS_EBE: u32 = 1 << 15;
+/// `RINTSTS`: the errors of a command
+const COMMAND_ERRORS: u32 = RINTSTS_RE | RINTSTS_RCRC | RINTSTS_RTO | RINTSTS_HLE;
+/// `RINTSTS`: the errors of a transfer
+const DATA_ERRORS: u32 =
+    RINTSTS_DCRC | RINTSTS_DRTO | RINTSTS_HTO | RINTSTS_FRUN | RINTSTS_SBE | RINTSTS_EBE;
+
+/// `STATUS`: the FIFO is full
+const STATUS_FIFO_FULL: u32 = 1 << 3;
+/// `STATUS`: the card holds its data line low, busy programming
+const STATUS_DATA_BUSY: u32 = 1 << 9;
+/// `STATUS`: shift of the number of words in the FIFO
+const STATUS_FIFO_COUNT_SHIFT: u32 = 17;
+/// `STATUS`: mask of the number of words in the FIFO
+const STATUS_FIFO_COUNT_MASK: u32 = 0x1fff;
+/// `HCON`: shift of the width of the FIFO
+const HCON_DATA_WIDTH_SHIFT: u32 = 7;
+/// `HCON`: width of the FIFO, 32 bits
+const HCON_DATA_WIDTH_32: u32 = 1;
+/// `VERID`: the first version with the FIFO at `0x200` rather than `0x100`
+const VERID_240A: u32 = 0x240a;
+
+const ALL_SEND_CID: u8 = 2;
+const SEND_RELATIVE_ADDR: u8 = 3;
+const SELECT_CARD: u8 = 7;
+/// Application specific command, following `APP_CMD`
+const SET_BUS_WIDTH: u8 = 6;
+
+/// Argument of `SET_BUS_WIDTH`: 4 bits
+const BUS_WIDTH_4: u32 = 2;
+/// Argument of `SD_SEND_OP_COND` and bits of the OCR: 2.7 to 3.6 V
+const OCR_VOLTAGES: u32 = 0x00ff_8000;
+/// Bit of the OCR: the card is ready
+const OCR_READY: u32 = 1 << 31;
+/// Bits of the card status in an R1 response: errors, but for `CARD_IS_LOCKED`
+const CARD_STATUS_ERRORS: u32 = 0xfdf8_0000;
+
+/// Card clock while the card is identified
+const INIT_FREQ: usize = 400_000;
+/// Card clock once the card is ready, the default speed
+const DATA_FREQ: usize = 25_000_000;
+
+/// Register reads polled for a command to be taken or done
+const COMMAND_POLLS: usize = 1_000_000;
+/// Register reads polled for a transfer or for the card not to be busy
+const BUSY_POLLS: usize = 10_000_000;
+
+/// The controller at a physical address, identity mapped in kernel space
+struct Host {
+    base: usize,
+    /// frequency of the input clock of the controller
+    input_freq: usize,
+    /// offset of the FIFO, depending on the version
+    fifo: usize,
+}
+
+impl Host {
+    fn read_reg(&self, reg: usize) -> u32 {
+        unsafe { read_volatile((self.base + reg) as *const u32) }
+    }
+    fn write_reg(&self, reg: usize, value: u32) {
+        unsafe {
+            write_volatile((self.base + reg) as *mut u32, value);
+        }
+    }
+    /// Poll `done` up to `polls` times, false if it never holds
+    fn wait(&self, polls: usize, done: impl Fn(&Self) -> bool) -> bool {
+        (0..polls).any(|_| done(self))
+    }
+    /// Have the controller take the card clock registers
+    fn update_clock(&self) -> bool {
+        self.write_reg(CMDARG, 0);
+        self.write_reg(CMD, CMD_START | CMD_UPDATE_CLOCK | CMD_WAIT_PREVIOUS);
+        self.wait(COMMAND_POLLS, |host| host.read_reg(CMD) & CMD_START == 0)
+    }
+    /// Run the card clock at most at `freq`
+    fn set_freq(&self, freq: usize) -> bool {
+        let div = (self.input_freq + 2 * freq - 1) / (2 * freq);
+        self.write_reg(CLKENA, 0);
+        if !self.update_clock() {
+            return false;
+        }
+        self.write_reg(CLKDIV, div as u32);
+        self.write_reg(CLKSRC, 0);
+        if !self.update_clock() {
+            return false;
+        }
+        self.write_reg(CLKENA, CLKENA_ENABLE);
+        self.update_clock()
+    }
+    /// Send command `cmd` with `arg`, expecting the `response` given by the
+    /// `CMD` bits, and return the response, `None` on an error or a timeout
+    fn command(&self, cmd: u8, arg: u32, response: u32) -> Option<[u32; 4]> {
+        self.write_reg(RINTSTS, u32::MAX);
+        self.write_reg(CMDARG, arg);
+        self.write_reg(
+            CMD,
+            CMD_START | CMD_USE_HOLD_REG | CMD_WAIT_PREVIOUS | response | cmd as u32,
+        );
+        let done = |host: &Self| host.read_reg(RINTSTS) & (RINTSTS_CD | RINTSTS_HLE) != 0;
+        if !self.wait(COMMAND_POLLS, done) || self.read_reg(RINTSTS) & COMMAND_ERRORS != 0 {
+            return None;
+        }
+        let mut resp = [0; 4];
+        for (i, word) in resp.iter_mut().enumerate() {
+            *word = self.read_reg(RESP0 + i * 4);
+        }
+        Some(resp)
+    }
+    /// Send command `cmd` with `arg` and an R1 response, with the `CMD` bits
+    /// of a transfer in `data`, false on an error reported by the card too
+    fn command_r1(&self, cmd: u8, arg: u32, data: u32) -> bool {
+        self.command(cmd, arg, RESPONSE_SHORT | data)
+            .map_or(false, |resp| resp[0] & CARD_STATUS_ERRORS == 0)
+    }
+    /// Wait for the card to release its data line
+    fn wait_not_busy(&self) -> bool {
+        self.wait(BUSY_POLLS, |host| {
+            host.read_reg(STATUS) & STATUS_DATA_BUSY == 0
+        })
+    }
+    /// Set up a transfer of `len` bytes, dropping what a failed one left in
+    /// the FIFO
+    fn start_transfer(&self, len: usize) -> bool {
+        self.write_reg(BLKSIZ, BLOCK_SIZE as u32);
+        self.write_reg(BYTCNT, len as u32);
+        self.write_reg(CTRL, self.read_reg(CTRL) | CTRL_FIFO_RESET);
+        self.wait(COMMAND_POLLS, |host| {
+            host.read_reg(CTRL) & CTRL_FIFO_RESET == 0
+        })
+    }
+    /// Number of words in the FIFO
+    fn fifo_count(&self) -> usize {
+        (self.read_reg(STATUS) >> STATUS_FIFO_COUNT_SHIFT & STATUS_FIFO_COUNT_MASK) as usize
+    }
+    /// Read `buf` by command `cmd` with `arg`, false on an error
+    fn read_data(&self, cmd: u8, arg: u32, buf: &mut [u8]) -> bool {
+        if !self.start_transfer(buf.len()) || !self.command_r1(cmd, arg, CMD_DATA) {
+            return false;
+        }
+        let mut words = buf.chunks_exact_mut(4);
+        for _ in 0..BUSY_POLLS {
+            // the FIFO holds the last words once the transfer is over
+            let status = self.read_reg(RINTSTS);
+            if status & DATA_ERRORS != 0 {
+                return false;
+            }
+            for _ in 0..self.fifo_count() {
+                let word = self.read_reg(self.fifo);
+                if let Some(bytes) = words.next() {
+                    bytes.copy_from_slice(&word.to_le_bytes());
+                }
+            }
+            if status & RINTSTS_DTO != 0 {
+                return words.next().is_none();
+            }
+        }
+        false
+    }
+    /// Write `buf` by command `cmd` with `arg`, false on an error
+    fn write_data(&self, cmd: u8, arg: u32, buf: &[u8]) -> bool {
+        if !self.start_transfer(buf.len()) || !self.command_r1(cmd, arg, CMD_DATA | CMD_WRITE) {
+            return false;
+        }
+        let mut words = buf.chunks_exact(4).peekable();
+        for _ in 0..BUSY_POLLS {
+            let status = self.read_reg(RINTSTS);
+            if status & DATA_ERRORS != 0 {
+                return false;
+            }
+            if status & RINTSTS_DTO != 0 {
+                // the card holds its data line low while programming the block
+                return words.peek().is_none() && self.wait_not_busy();
+            }
+            while self.read_reg(STATUS) & STATUS_FIFO_FULL == 0 {
+                match words.next() {
+                    Some(bytes) => {
+                        self.write_reg(self.fifo, u32::from_le_bytes(bytes.try_into().unwrap()))
+                    }
+                    None => break,
+                }
+            }
+        }
+        false
+    }
+}
+
+/// An SD card on a DesignWare controller
+pub struct DwMmc {
+    host: SpinLock<Host>,
+    /// whether commands address blocks rather than bytes
+    high_capacity: bool,
+    /// capacity in blocks
+    blocks: usize,
+}
+
+impl DwMmc {
+    /// Identify the card on the controller at `base`, whose input clock runs
+    /// at `input_freq`, `None` if there is no usable card
+    pub fn probe(base: usize, input_freq: usize) -> Option<Self> {
+        let mut host = Host {
+            base,
+            input_freq,
+            fifo: 0,
+        };
+        if host.read_reg(HCON) >> HCON_DATA_WIDTH_SHIFT & 0x7 != HCON_DATA_WIDTH_32 {
+            return None;
+        }
+        host.fifo = if host.read_reg(VERID) & 0xffff < VERID_240A {
+            0x100
+        } else {
+            0x200
+        };
+        // polled, by the CPU rather than the DMA controller the firmware may
+        // have used
+        host.write_reg(CTRL, CTRL_RESET);
+        if !host.wait(COMMAND_POLLS, |host| host.read_reg(CTRL) & CTRL_RESET == 0) {
+            return None;
+        }
+        host.write_reg(
+            CTRL,
+            host.read_reg(CTRL) & !(CTRL_INT_ENABLE | CTRL_USE_IDMAC),
+        );
+        host.write_reg(BMOD, 0);
+        host.write_reg(INTMASK, 0);
+        host.write_reg(RINTSTS, u32::MAX);
+        host.write_reg(TMOUT, u32::MAX);
+        host.write_reg(PWREN, 1);
+        host.write_reg(CTYPE, 0);
+        if !host.set_freq(INIT_FREQ) {
+            return None;
+        }
+        let (high_capacity, blocks) = Self::identify(&host)?;
+        host.write_reg(CTYPE, CTYPE_4BIT);
+        if !host.set_freq(DATA_FREQ) {
+            return None;
+        }
+        Some(Self {
+            host: SpinLock::new(host),
+            high_capacity,
+            blocks,
+        })
+    }
+    /// Bring the card to the transfer state on a 4-bit bus, whether it is
+    /// high capacity and its capacity in blocks
+    fn identify(host: &Host) -> Option<(bool, usize)> {
+        host.command(GO_IDLE_STATE, 0, CMD_SEND_INIT)?;
+        // cards of version 1 do not answer
+        let version2 = match host.command(SEND_IF_COND, IF_COND, RESPONSE_SHORT) {
+            Some(resp) if resp[0] & 0xfff == IF_COND => true,
+            Some(_) => return None,
+            None => false,
+        };
+        let arg = OCR_VOLTAGES | if version2 { OCR_CCS } else { 0 };
+        let ocr = (0..INIT_RETRIES).find_map(|_| {
+            host.command(APP_CMD, 0, RESPONSE_SHORT)?;
+            let ocr = host.command(SD_SEND_OP_COND, arg, RESPONSE_OCR)?[0];
+            if ocr & OCR_READY != 0 {
+                Some(ocr)
+            } else {
+                None
+            }
+        })?;
+        let high_capacity = version2 && ocr & OCR_CCS != 0;
+        host.command(ALL_SEND_CID, 0, RESPONSE_LONG)?;
+        let rca = host.command(SEND_RELATIVE_ADDR, 0, RESPONSE_SHORT)?[0] & 0xffff_0000;
+        // the words of the response from the least significant one
+        let resp = host.command(SEND_CSD, rca, RESPONSE_LONG)?;
+        let mut csd = [0u8; 16];
+        for (bytes, word) in csd.chunks_exact_mut(4).zip(resp.iter().rev()) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        if !host.command_r1(SELECT_CARD, rca, 0) || !host.wait_not_busy() {
+            return None;
+        }
+        if !high_capacity && !host.command_r1(SET_BLOCKLEN, BLOCK_SIZE as u32, 0) {
+            return None;
+        }
+        if !host.command_r1(APP_CMD, rca, 0) || !host.command_r1(SET_BUS_WIDTH, BUS_WIDTH_4, 0) {
+            return None;
+        }
+        Some((high_capacity, csd_blocks(&csd)))
+    }
+    /// Capacity in blocks
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+    /// Argument of a command addressing block `block_id`
+    fn address(&self, block_id: usize) -> u32 {
+        assert!(
+            block_id < self.blocks,
+            "block {} beyond the SD card",
+            block_id
+        );
+        if self.high_capacity {
+            block_id as u32
+        } else {
+            (block_id * BLOCK_SIZE) as u32
+        }
+    }
+}
+
+impl BlockDevice for DwMmc {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        let ok = self.host.lock().read_data(
+            READ_SINGLE_BLOCK,
+            self.address(block_id),
+            &mut buf[..BLOCK_SIZE],
+        );
+        assert!(ok, "Error when reading block {} of DwMmc", block_id);
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let ok =
+            self.host
+                .lock()
+                .write_data(WRITE_BLOCK, self.address(block_id), &buf[..BLOCK_SIZE]);
+        assert!(ok, "Error when writing block {} of DwMmc", block_id);
+    }
+    fn block_count(&self) -> Option<usize> {
+        Some(self.blocks)
+    }
+}
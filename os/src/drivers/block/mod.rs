@@ -1,16 +1,120 @@
+//! Block devices: the disks of the board, virtio ones or an SD card, and the
+//! partitions on them
+#[cfg(feature = "board_visionfive2")]
+mod dw_mmc;
+mod partition;
+#[cfg(any(feature = "board_sifive_u", feature = "board_visionfive2"))]
+mod sd;
+#[cfg(feature = "board_sifive_u")]
+mod sdcard;
 mod virtio_blk;
 
-pub use virtio_blk::VirtIOBlock;
+pub use virtio_blk::{VirtIOBlock, VirtioHal};
 
-use crate::board::BlockDeviceImpl;
+use super::virtio_mmio::probe_transports;
+use crate::cmdline;
+use crate::config::ROOT_DEVICE;
+use crate::sync::Lazy;
+use alloc::format;
+use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use easy_fs::BlockDevice;
-use lazy_static::*;
+use partition::find_partitions;
 
-lazy_static! {
-    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new());
+/// A disk or a partition of one
+pub struct BlockDeviceEntry {
+    /// `vda`, `vdb` and so on for the virtio disks, those on PCI first,
+    /// `mmcblk0` for an SD card, with the number of the
+    /// partition appended for a partition, e.g. `vda1` or `mmcblk0p1`
+    pub name: String,
+    pub device: Arc<dyn BlockDevice>,
+    /// capacity in blocks
+    pub blocks: usize,
+    /// interrupt source of a disk at the PLIC, `None` for a partition or a
+    /// polled disk
+    pub irq: Option<usize>,
 }
 
+/// The disks found at boot, each followed by its partitions
+pub static BLOCK_DEVICES: Lazy<Vec<BlockDeviceEntry>> = Lazy::new(|| {
+    let disks = probe_transports(VirtIOBlock::probe)
+        .enumerate()
+        .map(|(i, disk)| BlockDeviceEntry {
+            name: format!("vd{}", (b'a' + i as u8) as char),
+            blocks: disk.blocks(),
+            device: Arc::new(disk.device),
+            irq: Some(disk.irq),
+        })
+        .chain(sd_card());
+    let mut entries = Vec::new();
+    for disk in disks {
+        let partitions = find_partitions(&disk.device, disk.blocks);
+        // like Linux, a disk name ending with a digit is followed by `p`
+        let separator = if disk.name.ends_with(|c: char| c.is_ascii_digit()) {
+            "p"
+        } else {
+            ""
+        };
+        let name = disk.name.clone();
+        entries.push(disk);
+        for (number, partition) in partitions {
+            entries.push(BlockDeviceEntry {
+                name: format!("{}{}{}", name, separator, number),
+                blocks: partition.blocks(),
+                device: Arc::new(partition),
+                irq: None,
+            });
+        }
+    }
+    entries
+});
+
+/// The SD card of the board, polled
+#[cfg(feature = "board_sifive_u")]
+fn sd_card() -> Option<BlockDeviceEntry> {
+    use crate::board::{SD_SPI, SPI_CLOCK_FREQ};
+    sdcard::SdCard::probe(SD_SPI, SPI_CLOCK_FREQ).map(|card| BlockDeviceEntry {
+        name: String::from("mmcblk0"),
+        blocks: card.blocks(),
+        device: Arc::new(card),
+        irq: None,
+    })
+}
+
+/// The SD card of the board, polled
+#[cfg(feature = "board_visionfive2")]
+fn sd_card() -> Option<BlockDeviceEntry> {
+    use crate::board::{SDIO_CLOCK_FREQ, SD_MMC};
+    dw_mmc::DwMmc::probe(SD_MMC, SDIO_CLOCK_FREQ).map(|card| BlockDeviceEntry {
+        name: String::from("mmcblk0"),
+        blocks: card.blocks(),
+        device: Arc::new(card),
+        irq: None,
+    })
+}
+
+/// The SD card of the board, none for boards without one
+#[cfg(not(any(feature = "board_sifive_u", feature = "board_visionfive2")))]
+fn sd_card() -> Option<BlockDeviceEntry> {
+    None
+}
+
+/// The block device named `name`, e.g. `vda1`
+pub fn find_block_device(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    BLOCK_DEVICES
+        .iter()
+        .find(|entry| entry.name == name)
+        .map(|entry| entry.device.clone())
+}
+
+/// The block device holding the root filesystem, `root=` on the command line
+/// or [`ROOT_DEVICE`]
+pub static BLOCK_DEVICE: Lazy<Arc<dyn BlockDevice>> = Lazy::new(|| {
+    let root = cmdline::get("root").unwrap_or(ROOT_DEVICE);
+    find_block_device(root).unwrap_or_else(|| panic!("no root block device {}", root))
+});
+
 #[allow(unused)]
 pub fn block_device_test() {
     let block_device = BLOCK_DEVICE.clone();
@@ -24,5 +128,5 @@ pub fn block_device_test() {
         block_device.read_block(i as usize, &mut read_buffer);
         assert_eq!(write_buffer, read_buffer);
     }
-    println!("block device test passed!");
+    info!("block device test passed!");
 }
@@ -0,0 +1,163 @@
+//! Partition tables, an MBR with its primary partitions or a GPT, and the
+//! partitions found in them
+use super::BlockDevice;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+const BLOCK_SIZE: usize = 512;
+
+/// Offset of the partition entries in the MBR
+const MBR_ENTRIES: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+/// Partition type of the entry of an MBR protecting a GPT
+const MBR_TYPE_GPT: u8 = 0xee;
+/// Partition types of extended partitions, whose logical partitions are not
+/// looked for
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// Block of the GPT header
+const GPT_HEADER_LBA: usize = 1;
+/// Largest number of GPT entries looked at
+const GPT_MAX_ENTRIES: usize = 128;
+
+/// A partition of a disk, whose blocks are numbered from its first one
+pub struct Partition {
+    disk: Arc<dyn BlockDevice>,
+    start: usize,
+    blocks: usize,
+}
+
+impl Partition {
+    /// Capacity in blocks
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+    fn check(&self, block_id: usize) {
+        assert!(
+            block_id < self.blocks,
+            "block {} beyond the partition of {} blocks",
+            block_id,
+            self.blocks
+        );
+    }
+}
+
+impl BlockDevice for Partition {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        self.check(block_id);
+        self.disk.read_block(self.start + block_id, buf);
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        self.check(block_id);
+        self.disk.write_block(self.start + block_id, buf);
+    }
+    fn read_blocks(&self, requests: &mut [(usize, &mut [u8])]) {
+        let mut on_disk: Vec<(usize, &mut [u8])> = requests
+            .iter_mut()
+            .map(|(block_id, buf)| {
+                self.check(*block_id);
+                (self.start + *block_id, &mut **buf)
+            })
+            .collect();
+        self.disk.read_blocks(&mut on_disk);
+    }
+    fn write_blocks(&self, requests: &[(usize, &[u8])]) {
+        let on_disk: Vec<(usize, &[u8])> = requests
+            .iter()
+            .map(|&(block_id, buf)| {
+                self.check(block_id);
+                (self.start + block_id, buf)
+            })
+            .collect();
+        self.disk.write_blocks(&on_disk);
+    }
+    fn block_count(&self) -> Option<usize> {
+        Some(self.blocks)
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> usize {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap()) as usize
+}
+
+/// The partitions listed by the partition table of `disk` of `blocks`
+/// blocks, each with its number from 1, empty if the disk has none. The
+/// checksums of a GPT are not verified.
+pub fn find_partitions(disk: &Arc<dyn BlockDevice>, blocks: usize) -> Vec<(usize, Partition)> {
+    let mut mbr = [0u8; BLOCK_SIZE];
+    disk.read_block(0, &mut mbr);
+    if mbr[BLOCK_SIZE - 2..] != MBR_SIGNATURE {
+        return Vec::new();
+    }
+    let entries: Vec<&[u8]> = mbr[MBR_ENTRIES..MBR_ENTRIES + 4 * MBR_ENTRY_SIZE]
+        .chunks(MBR_ENTRY_SIZE)
+        .collect();
+    let ranges = if entries.iter().any(|entry| entry[4] == MBR_TYPE_GPT) {
+        gpt_ranges(disk, blocks)
+    } else {
+        entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry[4] != 0 && !MBR_TYPES_EXTENDED.contains(&entry[4]))
+            .map(|(i, entry)| (i + 1, u32_at(entry, 8), u32_at(entry, 12)))
+            .collect()
+    };
+    ranges
+        .into_iter()
+        .filter(|&(_, start, len)| {
+            len > 0 && start > 0 && start.checked_add(len).map_or(false, |end| end <= blocks)
+        })
+        .map(|(number, start, len)| {
+            (
+                number,
+                Partition {
+                    disk: disk.clone(),
+                    start,
+                    blocks: len,
+                },
+            )
+        })
+        .collect()
+}
+
+/// The number, first block and length of the partitions of the GPT of `disk`
+fn gpt_ranges(disk: &Arc<dyn BlockDevice>, blocks: usize) -> Vec<(usize, usize, usize)> {
+    let mut header = [0u8; BLOCK_SIZE];
+    disk.read_block(GPT_HEADER_LBA, &mut header);
+    if header[..8] != GPT_SIGNATURE[..] {
+        return Vec::new();
+    }
+    let entries_lba = u64_at(&header, 72);
+    let entry_count = u32_at(&header, 80).min(GPT_MAX_ENTRIES);
+    let entry_size = u32_at(&header, 84);
+    if entry_size < 128 || BLOCK_SIZE % entry_size != 0 {
+        return Vec::new();
+    }
+    let per_block = BLOCK_SIZE / entry_size;
+    if entries_lba >= blocks || (entry_count + per_block - 1) / per_block > blocks - entries_lba {
+        return Vec::new();
+    }
+    let mut block = [0u8; BLOCK_SIZE];
+    let mut ranges = Vec::new();
+    for i in 0..entry_count {
+        if i % per_block == 0 {
+            disk.read_block(entries_lba + i / per_block, &mut block);
+        }
+        let entry = &block[i % per_block * entry_size..][..entry_size];
+        // an unused entry has a zero type
+        if entry[..16].iter().all(|&byte| byte == 0) {
+            continue;
+        }
+        let (first, last) = (u64_at(entry, 32), u64_at(entry, 40));
+        if last >= first && first > 0 {
+            ranges.push((i + 1, first, last - first + 1));
+        }
+    }
+    ranges
+}
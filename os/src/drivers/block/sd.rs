@@ -0,0 +1,46 @@
+//! What the drivers of SD cards share: the commands of the SD protocol, in
+//! SPI mode or not, and the capacity described by the CSD register
+pub const BLOCK_SIZE: usize = 512;
+
+pub const GO_IDLE_STATE: u8 = 0;
+pub const SEND_IF_COND: u8 = 8;
+pub const SEND_CSD: u8 = 9;
+pub const SET_BLOCKLEN: u8 = 16;
+pub const READ_SINGLE_BLOCK: u8 = 17;
+pub const WRITE_BLOCK: u8 = 24;
+/// The next command is an application specific one
+pub const APP_CMD: u8 = 55;
+/// Application specific command, following `APP_CMD`
+pub const SD_SEND_OP_COND: u8 = 41;
+
+/// Argument of `SEND_IF_COND`: 2.7 to 3.6 V, with the check pattern `0xaa`
+pub const IF_COND: u32 = 0x1aa;
+/// Argument of `SD_SEND_OP_COND` and bit of the OCR: high capacity, block
+/// addressed (SDHC or SDXC)
+pub const OCR_CCS: u32 = 1 << 30;
+
+/// Attempts of `SD_SEND_OP_COND` until the card leaves the idle state
+pub const INIT_RETRIES: usize = 1000;
+
+/// Bits `hi` down to `lo` of the big endian register `reg`
+fn bits(reg: &[u8], hi: usize, lo: usize) -> usize {
+    let top = reg.len() * 8 - 1;
+    (lo..=hi).rev().fold(0, |value, bit| {
+        let byte = reg[(top - bit) / 8];
+        value << 1 | (byte >> (bit % 8) & 1) as usize
+    })
+}
+
+/// Capacity in blocks of `BLOCK_SIZE` bytes described by the CSD register
+pub fn csd_blocks(csd: &[u8; 16]) -> usize {
+    match bits(csd, 127, 126) {
+        // version 2 of SDHC and SDXC cards, in units of 512 KiB
+        1 => (bits(csd, 69, 48) + 1) * 1024,
+        _ => {
+            let c_size = bits(csd, 73, 62);
+            let c_size_mult = bits(csd, 49, 47);
+            let read_bl_len = bits(csd, 83, 80);
+            ((c_size + 1) << (c_size_mult + 2 + read_bl_len)) / BLOCK_SIZE
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! Driver of an SD card in SPI mode, on the SPI controller of the SiFive
+//! FU540 and FU740 the card slot of the HiFive boards is wired to. The
+//! controller is polled a byte at a time, one block per command.
+use super::sd::{
+    csd_blocks, APP_CMD, BLOCK_SIZE, GO_IDLE_STATE, IF_COND, INIT_RETRIES, OCR_CCS,
+    READ_SINGLE_BLOCK, SD_SEND_OP_COND, SEND_CSD, SEND_IF_COND, SET_BLOCKLEN, WRITE_BLOCK,
+};
+use super::BlockDevice;
+use crate::sync::SpinLock;
+use core::ptr::{read_volatile, write_volatile};
+
+/// Serial clock divisor, the clock is the input one divided by `2 * (div + 1)`
+const SCKDIV: usize = 0x00;
+/// Chip select mode
+const CSMODE: usize = 0x18;
+/// Frame format
+const FMT: usize = 0x40;
+/// Transmit data, write
+const TXDATA: usize = 0x48;
+/// Receive data, read
+const RXDATA: usize = 0x4c;
+
+/// `CSMODE`: assert the chip select during each frame
+const CSMODE_AUTO: u32 = 0;
+/// `CSMODE`: keep the chip select asserted after the first frame
+const CSMODE_HOLD: u32 = 2;
+/// `CSMODE`: never assert the chip select
+const CSMODE_OFF: u32 = 3;
+/// `FMT`: frames of 8 bits on a single line, most significant bit first
+const FMT_8BIT: u32 = 8 << 16;
+/// `TXDATA`: the transmit FIFO is full
+const TXDATA_FULL: u32 = 1 << 31;
+/// `RXDATA`: the receive FIFO is empty
+const RXDATA_EMPTY: u32 = 1 << 31;
+
+/// Serial clock while the card is identified
+const INIT_FREQ: usize = 400_000;
+/// Serial clock once the card is ready
+const DATA_FREQ: usize = 20_000_000;
+
+const READ_OCR: u8 = 58;
+
+/// R1: the card is initializing
+const R1_IDLE: u8 = 1 << 0;
+/// R1: the command is not supported, by cards of version 1 for `SEND_IF_COND`
+const R1_ILLEGAL_COMMAND: u8 = 1 << 2;
+/// Token starting a data block
+const DATA_START: u8 = 0xfe;
+/// Data response token of a written block, under the mask `0x1f`: accepted
+const DATA_ACCEPTED: u8 = 0x05;
+
+/// Bytes polled for the response of a command
+const RESPONSE_POLLS: usize = 8;
+/// Bytes polled for a data block or for the card not to be busy
+const BUSY_POLLS: usize = 1_000_000;
+
+/// The SPI controller at a physical address, identity mapped in kernel space
+struct Spi {
+    base: usize,
+    /// frequency of the input clock of the controller
+    input_freq: usize,
+}
+
+impl Spi {
+    fn read_reg(&self, reg: usize) -> u32 {
+        unsafe { read_volatile((self.base + reg) as *const u32) }
+    }
+    fn write_reg(&self, reg: usize, value: u32) {
+        unsafe {
+            write_volatile((self.base + reg) as *mut u32, value);
+        }
+    }
+    /// Run the serial clock at most at `freq`
+    fn set_freq(&self, freq: usize) {
+        let div = (self.input_freq + 2 * freq - 1) / (2 * freq);
+        self.write_reg(SCKDIV, div.saturating_sub(1) as u32);
+    }
+    /// Send `byte` and return the one received meanwhile
+    fn transfer(&self, byte: u8) -> u8 {
+        while self.read_reg(TXDATA) & TXDATA_FULL != 0 {}
+        self.write_reg(TXDATA, byte as u32);
+        loop {
+            let data = self.read_reg(RXDATA);
+            if data & RXDATA_EMPTY == 0 {
+                return data as u8;
+            }
+        }
+    }
+    fn select(&self) {
+        self.write_reg(CSMODE, CSMODE_HOLD);
+    }
+    /// Release the chip select, then clock a byte for the card to release its output
+    fn deselect(&self) {
+        self.write_reg(CSMODE, CSMODE_OFF);
+        self.transfer(0xff);
+        self.write_reg(CSMODE, CSMODE_AUTO);
+    }
+    /// Send command `cmd` with `arg` and return its R1 response, `0xff` if
+    /// the card does not answer
+    fn command(&self, cmd: u8, arg: u32) -> u8 {
+        // the CRC is only checked for these two commands until the card is ready
+        let crc = match cmd {
+            GO_IDLE_STATE => 0x95,
+            SEND_IF_COND => 0x87,
+            _ => 0x01,
+        };
+        self.transfer(0xff);
+        self.transfer(0x40 | cmd);
+        for byte in arg.to_be_bytes() {
+            self.transfer(byte);
+        }
+        self.transfer(crc);
+        for _ in 0..RESPONSE_POLLS {
+            let r1 = self.transfer(0xff);
+            if r1 & 0x80 == 0 {
+                return r1;
+            }
+        }
+        0xff
+    }
+    /// Read the 4 bytes following R1 in the R3 and R7 responses
+    fn read_u32(&self) -> u32 {
+        let mut bytes = [0u8; 4];
+        for byte in bytes.iter_mut() {
+            *byte = self.transfer(0xff);
+        }
+        u32::from_be_bytes(bytes)
+    }
+    /// Receive a data block into `buf`, false if it does not start
+    fn read_data(&self, buf: &mut [u8]) -> bool {
+        if !(0..BUSY_POLLS).any(|_| self.transfer(0xff) == DATA_START) {
+            return false;
+        }
+        for byte in buf.iter_mut() {
+            *byte = self.transfer(0xff);
+        }
+        // the CRC is not checked
+        self.transfer(0xff);
+        self.transfer(0xff);
+        true
+    }
+    /// Send the data block `buf`, false if the card rejects it
+    fn write_data(&self, buf: &[u8]) -> bool {
+        self.transfer(DATA_START);
+        for &byte in buf {
+            self.transfer(byte);
+        }
+        self.transfer(0xff);
+        self.transfer(0xff);
+        if self.transfer(0xff) & 0x1f != DATA_ACCEPTED {
+            return false;
+        }
+        // the card holds its output low while programming the block
+        (0..BUSY_POLLS).any(|_| self.transfer(0xff) == 0xff)
+    }
+}
+
+/// An SD card behind an SPI controller
+pub struct SdCard {
+    spi: SpinLock<Spi>,
+    /// whether commands address blocks rather than bytes
+    high_capacity: bool,
+    /// capacity in blocks
+    blocks: usize,
+}
+
+impl SdCard {
+    /// Identify the card behind the SPI controller at `base`, whose input
+    /// clock runs at `input_freq`, `None` if there is no usable card
+    pub fn probe(base: usize, input_freq: usize) -> Option<Self> {
+        let spi = Spi { base, input_freq };
+        spi.write_reg(FMT, FMT_8BIT);
+        spi.set_freq(INIT_FREQ);
+        // at least 74 clocks with the chip select released put the card in SPI mode
+        spi.write_reg(CSMODE, CSMODE_OFF);
+        for _ in 0..10 {
+            spi.transfer(0xff);
+        }
+        spi.select();
+        let card = Self::identify(&spi);
+        spi.deselect();
+        let (high_capacity, blocks) = card?;
+        spi.set_freq(DATA_FREQ);
+        Some(Self {
+            spi: SpinLock::new(spi),
+            high_capacity,
+            blocks,
+        })
+    }
+    /// Bring the selected card out of the idle state, whether it is high
+    /// capacity and its capacity in blocks
+    fn identify(spi: &Spi) -> Option<(bool, usize)> {
+        if spi.command(GO_IDLE_STATE, 0) != R1_IDLE {
+            return None;
+        }
+        let version2 = spi.command(SEND_IF_COND, IF_COND) & R1_ILLEGAL_COMMAND == 0;
+        if version2 && spi.read_u32() & 0xfff != IF_COND {
+            return None;
+        }
+        let arg = if version2 { OCR_CCS } else { 0 };
+        let ready = (0..INIT_RETRIES).any(|_| {
+            spi.command(APP_CMD, 0) & !R1_IDLE == 0 && spi.command(SD_SEND_OP_COND, arg) == 0
+        });
+        if !ready {
+            return None;
+        }
+        let high_capacity =
+            version2 && spi.command(READ_OCR, 0) == 0 && { spi.read_u32() & OCR_CCS != 0 };
+        if !high_capacity && spi.command(SET_BLOCKLEN, BLOCK_SIZE as u32) != 0 {
+            return None;
+        }
+        let mut csd = [0u8; 16];
+        if spi.command(SEND_CSD, 0) != 0 || !spi.read_data(&mut csd) {
+            return None;
+        }
+        Some((high_capacity, csd_blocks(&csd)))
+    }
+    /// Capacity in blocks
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+    /// Argument of a command addressing block `block_id`
+    fn address(&self, block_id: usize) -> u32 {
+        assert!(
+            block_id < self.blocks,
+            "block {} beyond the SD card",
+            block_id
+        );
+        if self.high_capacity {
+            block_id as u32
+        } else {
+            (block_id * BLOCK_SIZE) as u32
+        }
+    }
+}
+
+impl BlockDevice for SdCard {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        let spi = self.spi.lock();
+        spi.select();
+        let ok = spi.command(READ_SINGLE_BLOCK, self.address(block_id)) == 0
+            && spi.read_data(&mut buf[..BLOCK_SIZE]);
+        spi.deselect();
+        assert!(ok, "Error when reading block {} of SdCard", block_id);
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let spi = self.spi.lock();
+        spi.select();
+        let ok = spi.command(WRITE_BLOCK, self.address(block_id)) == 0
+            && spi.write_data(&buf[..BLOCK_SIZE]);
+        spi.deselect();
+        assert!(ok, "Error when writing block {} of SdCard", block_id);
+    }
+    fn block_count(&self) -> Option<usize> {
+        Some(self.blocks)
+    }
+}
@@ -1,45 +1,261 @@
+//! Driver of the virtio block device, on either virtio transport
+//!
+//! A request is a chain of three descriptors of its [`VirtQueue`]: the header,
+//! the data of one block and the status written by the device. The requests
+//! of a batch of blocks are all put in flight before the device is notified,
+//! as many as the queue holds, and complete in any order. Tasks sleep while
+//! their requests are in flight, woken up by the interrupt completing them;
+//! while booting, the device is polled.
 use super::BlockDevice;
+use crate::drivers::virtio_mmio::{Transport, VirtQueue, DESC_F_WRITE};
 use crate::mm::{
     frame_alloc, frame_dealloc, kernel_token, FrameTracker, PageTable, PhysAddr, PhysPageNum,
     StepByOne, VirtAddr,
 };
-use crate::sync::UPSafeCell;
+use crate::sync::SpinNoIrqLock;
+use crate::task::{current_task, WaitQueue};
+use crate::trap::{raise_softirq, Softirq};
+use alloc::collections::VecDeque;
+use alloc::vec;
 use alloc::vec::Vec;
+use core::hint::spin_loop;
 use lazy_static::*;
-use virtio_drivers::{Hal, VirtIOBlk, VirtIOHeader};
+use virtio_drivers::Hal;
 
-#[allow(unused)]
-const VIRTIO0: usize = 0x10001000;
+const DEVICE_BLOCK: u32 = 2;
+const BLOCK_SIZE: usize = 512;
+/// Descriptors of the queue, for 21 requests in flight
+const QUEUE_SIZE: u16 = 64;
+/// Descriptors of a request
+const REQUEST_DESCS: usize = 3;
 
-pub struct VirtIOBlock(UPSafeCell<VirtIOBlk<'static, VirtioHal>>);
+/// Request type: read blocks
+const REQ_IN: u32 = 0;
+/// Request type: write blocks
+const REQ_OUT: u32 = 1;
+/// Length of the request header: its type, a reserved word and the sector
+const REQ_HEADER_LEN: usize = 16;
+/// Status of a successful request
+const STATUS_OK: u8 = 0;
+
+/// A virtio block device. Tasks sleep while their requests are in flight,
+/// woken up by the interrupt completing them.
+pub struct VirtIOBlock {
+    transport: Transport,
+    queue: SpinNoIrqLock<VirtQueue>,
+    /// whether the request headed by each descriptor completed
+    done: SpinNoIrqLock<Vec<bool>>,
+    /// the task waiting for the request headed by each descriptor
+    wait_queues: Vec<WaitQueue>,
+    /// heads of the requests completed but whose tasks are not woken up yet
+    completed: SpinNoIrqLock<Vec<u16>>,
+    /// tasks waiting for free descriptors
+    free_wait_queue: WaitQueue,
+    /// capacity in blocks
+    blocks: usize,
+}
 
 lazy_static! {
-    static ref QUEUE_FRAMES: UPSafeCell<Vec<FrameTracker>> = unsafe { UPSafeCell::new(Vec::new()) };
+    static ref QUEUE_FRAMES: SpinNoIrqLock<Vec<FrameTracker>> = SpinNoIrqLock::new(Vec::new());
 }
 
 impl BlockDevice for VirtIOBlock {
     fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-        self.0
-            .exclusive_access()
-            .read_block(block_id, buf)
-            .expect("Error when reading VirtIOBlk");
+        self.read_blocks(&mut [(block_id, buf)]);
     }
     fn write_block(&self, block_id: usize, buf: &[u8]) {
-        self.0
-            .exclusive_access()
-            .write_block(block_id, buf)
-            .expect("Error when writing VirtIOBlk");
+        self.write_blocks(&[(block_id, buf)]);
+    }
+    fn read_blocks(&self, requests: &mut [(usize, &mut [u8])]) {
+        let block_ids: Vec<usize> = requests.iter().map(|(block_id, _)| *block_id).collect();
+        let ok = self.batch(
+            REQ_IN,
+            &block_ids,
+            |_, _| {},
+            |i, data| requests[i].1[..BLOCK_SIZE].copy_from_slice(data),
+        );
+        assert!(ok, "Error when reading VirtIOBlk");
+    }
+    fn write_blocks(&self, requests: &[(usize, &[u8])]) {
+        let block_ids: Vec<usize> = requests.iter().map(|(block_id, _)| *block_id).collect();
+        let ok = self.batch(
+            REQ_OUT,
+            &block_ids,
+            |i, data| data.copy_from_slice(&requests[i].1[..BLOCK_SIZE]),
+            |_, _| {},
+        );
+        assert!(ok, "Error when writing VirtIOBlk");
+    }
+    fn block_count(&self) -> Option<usize> {
+        Some(self.blocks)
+    }
+    fn handle_irq(&self) {
+        self.transport.ack_interrupt();
+        let mut queue = self.queue.lock();
+        let mut completed = self.completed.lock();
+        while let Some((head, _)) = queue.pop_used() {
+            self.done.lock()[head as usize] = true;
+            completed.push(head);
+        }
+        if !completed.is_empty() {
+            raise_softirq(Softirq::Block);
+        }
+    }
+    fn handle_softirq(&self) {
+        let completed = core::mem::take(&mut *self.completed.lock());
+        for head in completed {
+            self.wait_queues[head as usize].wake_one();
+        }
     }
 }
 
 impl VirtIOBlock {
-    #[allow(unused)]
-    pub fn new() -> Self {
-        unsafe {
-            Self(UPSafeCell::new(
-                VirtIOBlk::<VirtioHal>::new(&mut *(VIRTIO0 as *mut VirtIOHeader)).unwrap(),
-            ))
+    /// Set up the block device reached by `transport`, `None` if there is none
+    pub fn probe(transport: Transport) -> Option<Self> {
+        if !transport.is_device(DEVICE_BLOCK) {
+            return None;
+        }
+        transport.begin_init(0)?;
+        // the capacity in 512-byte sectors starts the configuration space
+        let blocks =
+            transport.read_config_u32(0) as usize | (transport.read_config_u32(4) as usize) << 32;
+        let queue = VirtQueue::setup(&transport, 0, QUEUE_SIZE);
+        transport.finish_init();
+        Some(Self {
+            transport,
+            queue: SpinNoIrqLock::new(queue),
+            done: SpinNoIrqLock::new(vec![false; QUEUE_SIZE as usize]),
+            wait_queues: (0..QUEUE_SIZE).map(|_| WaitQueue::new()).collect(),
+            completed: SpinNoIrqLock::new(Vec::with_capacity(QUEUE_SIZE as usize)),
+            free_wait_queue: WaitQueue::new(),
+            blocks,
+        })
+    }
+    /// Capacity in blocks
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+    /// Take the descriptors of a request, `None` if there are not enough free ones
+    fn try_alloc_descs(&self) -> Option<[u16; REQUEST_DESCS]> {
+        let mut queue = self.queue.lock();
+        if queue.free.len() < REQUEST_DESCS {
+            return None;
+        }
+        let mut descs = [0; REQUEST_DESCS];
+        for desc in descs.iter_mut() {
+            *desc = queue.free.pop().unwrap();
+        }
+        Some(descs)
+    }
+    /// Take the descriptors of a request, waiting for requests in flight to
+    /// complete if there are not enough free ones
+    fn alloc_descs(&self) -> [u16; REQUEST_DESCS] {
+        loop {
+            if let Some(descs) = self.try_alloc_descs() {
+                return descs;
+            }
+            self.free_wait_queue
+                .wait_until_uninterruptible(|| self.queue.lock().free.len() >= REQUEST_DESCS);
+        }
+    }
+    /// Make requests of type `kind` on the blocks `block_ids` and wait for
+    /// them to complete. The data of the `i`th block is filled in by
+    /// `fill(i, data)` before and given to `take(i, data)` after. Return
+    /// whether all succeeded.
+    fn batch(
+        &self,
+        kind: u32,
+        block_ids: &[usize],
+        mut fill: impl FnMut(usize, &mut [u8]),
+        mut take: impl FnMut(usize, &[u8]),
+    ) -> bool {
+        let mut in_flight = VecDeque::new();
+        let mut ok = true;
+        for (i, &block_id) in block_ids.iter().enumerate() {
+            let descs = loop {
+                if let Some(descs) = self.try_alloc_descs() {
+                    break descs;
+                }
+                // the queue is full: complete the oldest request of the batch,
+                // or wait for those of other tasks if it has none in flight
+                match in_flight.pop_front() {
+                    Some((j, descs)) => {
+                        self.queue.lock().notify();
+                        ok &= self.complete(descs, |data| take(j, data));
+                    }
+                    None => break self.alloc_descs(),
+                }
+            };
+            self.submit(descs, kind, block_id, |data| fill(i, data));
+            in_flight.push_back((i, descs));
+        }
+        self.queue.lock().notify();
+        for (i, descs) in in_flight {
+            ok &= self.complete(descs, |data| take(i, data));
+        }
+        ok
+    }
+    /// Give the device a request of type `kind` on block `block_id` in
+    /// `descs`, the data of the block filled in by `fill`. The device is not
+    /// notified.
+    fn submit(
+        &self,
+        descs: [u16; REQUEST_DESCS],
+        kind: u32,
+        block_id: usize,
+        fill: impl FnOnce(&mut [u8]),
+    ) {
+        let [header, data, status] = descs;
+        let mut queue = self.queue.lock();
+        let header_buf = queue.buffer(header);
+        header_buf[..4].copy_from_slice(&kind.to_le_bytes());
+        header_buf[4..8].fill(0);
+        header_buf[8..16].copy_from_slice(&(block_id as u64).to_le_bytes());
+        fill(&mut queue.buffer(data)[..BLOCK_SIZE]);
+        queue.buffer(status)[0] = !STATUS_OK;
+        let data_flags = if kind == REQ_IN { DESC_F_WRITE } else { 0 };
+        queue.push_chain(&[
+            (header, REQ_HEADER_LEN, 0),
+            (data, BLOCK_SIZE, data_flags),
+            (status, 1, DESC_F_WRITE),
+        ]);
+    }
+    /// Wait for the request in `descs` to complete, give the data of its block
+    /// to `take` and free the descriptors. Return whether it succeeded.
+    fn complete(&self, descs: [u16; REQUEST_DESCS], take: impl FnOnce(&[u8])) -> bool {
+        let [header, data, status] = descs;
+        self.wait_for(header);
+        let mut queue = self.queue.lock();
+        let ok = queue.buffer(status)[0] == STATUS_OK;
+        if ok {
+            take(&queue.buffer(data)[..BLOCK_SIZE]);
+        }
+        queue.free.extend([header, data, status]);
+        drop(queue);
+        self.free_wait_queue.wake_one();
+        ok
+    }
+    /// Block the current task until the request headed by `head` completes.
+    /// The buffers of the request are in use by the device until then, so
+    /// signals are ignored.
+    fn wait_for(&self, head: u16) {
+        let head = head as usize;
+        if current_task().is_none() {
+            // while booting, poll as there is nothing else to run
+            loop {
+                let mut queue = self.queue.lock();
+                while let Some((used, _)) = queue.pop_used() {
+                    self.done.lock()[used as usize] = true;
+                }
+                drop(queue);
+                if core::mem::take(&mut self.done.lock()[head]) {
+                    return;
+                }
+                spin_loop();
+            }
         }
+        self.wait_queues[head]
+            .wait_until_uninterruptible(|| core::mem::take(&mut self.done.lock()[head]));
     }
 }
 
@@ -54,7 +270,7 @@ impl Hal for VirtioHal {
                 ppn_base = frame.ppn;
             }
             assert_eq!(frame.ppn.0, ppn_base.0 + i);
-            QUEUE_FRAMES.exclusive_access().push(frame);
+            QUEUE_FRAMES.lock().push(frame);
         }
         let pa: PhysAddr = ppn_base.into();
         pa.0
@@ -0,0 +1,363 @@
+//! Driver of the virtio GPU device, through the legacy virtio MMIO interface,
+//! showing a 2D framebuffer on the first scanout
+//!
+//! The framebuffer is a resource of the device backed by consecutive frames,
+//! so that it can be mapped to user space as a whole. What is drawn into it
+//! shows once the rectangle changed is transferred to the host and flushed
+//! by [`VirtIOGpu::flush`]. Commands are sent one at a time on the control
+//! queue, polling for their responses, which QEMU gives right away.
+use super::block::VirtioHal;
+use super::virtio_mmio::*;
+use crate::config::PAGE_SIZE;
+use crate::mm::{frame_alloc, frame_alloc_contiguous, FrameTracker, PhysAddr, PhysPageNum};
+use crate::sync::SpinNoIrqLock;
+use alloc::vec::Vec;
+use core::hint::spin_loop;
+use core::mem::size_of;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+use virtio_drivers::Hal;
+
+const DEVICE_GPU: u32 = 16;
+const CONTROL_QUEUE: u32 = 0;
+/// Descriptors of the control queue: a request and its response
+const QUEUE_SIZE: u16 = 2;
+
+const CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+const CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+const CMD_SET_SCANOUT: u32 = 0x0103;
+const CMD_RESOURCE_FLUSH: u32 = 0x0104;
+const CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+const CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+const RESP_OK_NODATA: u32 = 0x1100;
+const RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+
+/// Pixel format: blue, green, red and an unused byte, i.e. `0x00RRGGBB` in a
+/// little-endian `u32`
+const FORMAT_B8G8R8X8_UNORM: u32 = 2;
+/// Bytes of a pixel of the framebuffer
+pub const BYTES_PER_PIXEL: usize = 4;
+/// Id of the resource of the framebuffer, 0 meaning none
+const FB_RESOURCE_ID: u32 = 1;
+/// Resolution if the device reports no enabled scanout
+const DEFAULT_WIDTH: u32 = 1280;
+const DEFAULT_HEIGHT: u32 = 800;
+const MAX_SCANOUTS: usize = 16;
+
+/// Header of every request and response
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct CtrlHeader {
+    ty: u32,
+    flags: u32,
+    fence_id: u64,
+    ctx_id: u32,
+    padding: u32,
+}
+
+impl CtrlHeader {
+    fn new(ty: u32) -> Self {
+        Self {
+            ty,
+            ..Self::default()
+        }
+    }
+}
+
+/// A rectangle of pixels
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Rect {
+    /// column of the left edge
+    pub x: u32,
+    /// row of the top edge
+    pub y: u32,
+    /// width in pixels
+    pub width: u32,
+    /// height in pixels
+    pub height: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct DisplayOne {
+    rect: Rect,
+    enabled: u32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct RespDisplayInfo {
+    header: CtrlHeader,
+    pmodes: [DisplayOne; MAX_SCANOUTS],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ResourceCreate2d {
+    header: CtrlHeader,
+    resource_id: u32,
+    format: u32,
+    width: u32,
+    height: u32,
+}
+
+/// Attach a single entry of memory to a resource
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ResourceAttachBacking {
+    header: CtrlHeader,
+    resource_id: u32,
+    nr_entries: u32,
+    addr: u64,
+    length: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct SetScanout {
+    header: CtrlHeader,
+    rect: Rect,
+    scanout_id: u32,
+    resource_id: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct TransferToHost2d {
+    header: CtrlHeader,
+    rect: Rect,
+    offset: u64,
+    resource_id: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ResourceFlush {
+    header: CtrlHeader,
+    rect: Rect,
+    resource_id: u32,
+    padding: u32,
+}
+
+/// The control queue in the legacy layout, the descriptor table and the
+/// available ring on the first page, the used ring on the second one, with a
+/// page for the request and one for the response
+struct ControlQueue {
+    /// physical address of the device registers, identity mapped
+    device: usize,
+    /// physical address of the queue, identity mapped
+    base: usize,
+    request: FrameTracker,
+    response: FrameTracker,
+    avail_idx: u16,
+    last_used: u16,
+}
+
+impl ControlQueue {
+    fn new(device: usize) -> Self {
+        Self {
+            device,
+            base: VirtioHal::dma_alloc(2),
+            request: frame_alloc().expect("out of frames for the GPU device"),
+            response: frame_alloc().expect("out of frames for the GPU device"),
+            avail_idx: 0,
+            last_used: 0,
+        }
+    }
+    fn avail(&self, offset: usize) -> *mut u16 {
+        (self.base + QUEUE_SIZE as usize * size_of::<Descriptor>() + offset) as *mut u16
+    }
+    fn used(&self, offset: usize) -> *mut u16 {
+        (self.base + PAGE_SIZE + offset) as *mut u16
+    }
+    /// Send `request` and spin until the device answers, return the response
+    fn command<Req: Copy, Resp: Copy>(&mut self, request: Req) -> Resp {
+        let request_pa: PhysAddr = self.request.ppn.into();
+        let response_pa: PhysAddr = self.response.ppn.into();
+        unsafe {
+            write_volatile(request_pa.0 as *mut Req, request);
+            let descriptors = self.base as *mut Descriptor;
+            write_volatile(
+                descriptors,
+                Descriptor {
+                    addr: request_pa.0 as u64,
+                    len: size_of::<Req>() as u32,
+                    flags: DESC_F_NEXT,
+                    next: 1,
+                },
+            );
+            write_volatile(
+                descriptors.add(1),
+                Descriptor {
+                    addr: response_pa.0 as u64,
+                    len: size_of::<Resp>() as u32,
+                    flags: DESC_F_WRITE,
+                    next: 0,
+                },
+            );
+            write_volatile(
+                self.avail(4 + 2 * (self.avail_idx % QUEUE_SIZE) as usize),
+                0,
+            );
+            // the device sees the entry before the index
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            write_volatile(self.avail(2), self.avail_idx);
+        }
+        write_reg(self.device, QUEUE_NOTIFY, CONTROL_QUEUE);
+        while unsafe { read_volatile(self.used(2)) } == self.last_used {
+            spin_loop();
+        }
+        fence(Ordering::SeqCst);
+        self.last_used = self.last_used.wrapping_add(1);
+        unsafe { read_volatile(response_pa.0 as *const Resp) }
+    }
+    /// Send `request` expecting no data back, return whether it succeeded
+    fn command_nodata<Req: Copy>(&mut self, request: Req) -> bool {
+        self.command::<Req, CtrlHeader>(request).ty == RESP_OK_NODATA
+    }
+}
+
+/// A virtio GPU device at a physical address, identity mapped in kernel space,
+/// showing a framebuffer
+pub struct VirtIOGpu {
+    width: u32,
+    height: u32,
+    framebuffer: Vec<FrameTracker>,
+    control: SpinNoIrqLock<ControlQueue>,
+}
+
+impl VirtIOGpu {
+    /// Set up the GPU device at `base` to show a framebuffer of the resolution
+    /// of its first scanout, `None` if there is none or out of frames
+    pub fn probe(base: usize) -> Option<Self> {
+        if !is_device(base, DEVICE_GPU) {
+            return None;
+        }
+        write_reg(base, STATUS, 0);
+        write_reg(base, STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        // neither 3D nor EDID
+        write_reg(base, GUEST_FEATURES, 0);
+        write_reg(base, GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        write_reg(base, QUEUE_SEL, CONTROL_QUEUE);
+        assert!(read_reg(base, QUEUE_NUM_MAX) >= QUEUE_SIZE as u32);
+        let mut control = ControlQueue::new(base);
+        write_reg(base, QUEUE_NUM, QUEUE_SIZE as u32);
+        write_reg(base, QUEUE_ALIGN, PAGE_SIZE as u32);
+        write_reg(base, QUEUE_PFN, (control.base / PAGE_SIZE) as u32);
+        unsafe {
+            write_volatile(control.avail(0), AVAIL_F_NO_INTERRUPT);
+        }
+        write_reg(
+            base,
+            STATUS,
+            STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK,
+        );
+
+        let info: RespDisplayInfo = control.command(CtrlHeader::new(CMD_GET_DISPLAY_INFO));
+        let (width, height) = match info.pmodes[0] {
+            mode if info.header.ty == RESP_OK_DISPLAY_INFO
+                && mode.enabled != 0
+                && mode.rect.width != 0
+                && mode.rect.height != 0 =>
+            {
+                (mode.rect.width, mode.rect.height)
+            }
+            _ => (DEFAULT_WIDTH, DEFAULT_HEIGHT),
+        };
+        let len = width as usize * height as usize * BYTES_PER_PIXEL;
+        let framebuffer = frame_alloc_contiguous((len + PAGE_SIZE - 1) / PAGE_SIZE)?;
+        let fb_pa: PhysAddr = framebuffer[0].ppn.into();
+        let screen = Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        let ok = control.command_nodata(ResourceCreate2d {
+            header: CtrlHeader::new(CMD_RESOURCE_CREATE_2D),
+            resource_id: FB_RESOURCE_ID,
+            format: FORMAT_B8G8R8X8_UNORM,
+            width,
+            height,
+        }) && control.command_nodata(ResourceAttachBacking {
+            header: CtrlHeader::new(CMD_RESOURCE_ATTACH_BACKING),
+            resource_id: FB_RESOURCE_ID,
+            nr_entries: 1,
+            addr: fb_pa.0 as u64,
+            length: len as u32,
+            padding: 0,
+        }) && control.command_nodata(SetScanout {
+            header: CtrlHeader::new(CMD_SET_SCANOUT),
+            rect: screen,
+            scanout_id: 0,
+            resource_id: FB_RESOURCE_ID,
+        });
+        if !ok {
+            return None;
+        }
+        let gpu = Self {
+            width,
+            height,
+            framebuffer,
+            control: SpinNoIrqLock::new(control),
+        };
+        gpu.flush(screen);
+        Some(gpu)
+    }
+    /// Width of the framebuffer in pixels
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    /// Height of the framebuffer in pixels
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    /// Bytes of a row of pixels
+    pub fn stride(&self) -> usize {
+        self.width as usize * BYTES_PER_PIXEL
+    }
+    /// The first of the consecutive frames of the framebuffer and their number
+    pub fn frames(&self) -> (PhysPageNum, usize) {
+        (self.framebuffer[0].ppn, self.framebuffer.len())
+    }
+    /// The pixels of the framebuffer, row by row
+    pub fn framebuffer(&self) -> &'static mut [u8] {
+        let pa: PhysAddr = self.framebuffer[0].ppn.into();
+        let len = self.stride() * self.height as usize;
+        unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut u8, len) }
+    }
+    /// Show the pixels of `rect` of the framebuffer, clipped to it. Return
+    /// false if the device fails to.
+    pub fn flush(&self, rect: Rect) -> bool {
+        let x = rect.x.min(self.width);
+        let y = rect.y.min(self.height);
+        let rect = Rect {
+            x,
+            y,
+            width: rect.width.min(self.width - x),
+            height: rect.height.min(self.height - y),
+        };
+        if rect.width == 0 || rect.height == 0 {
+            return true;
+        }
+        let offset = y as usize * self.stride() + x as usize * BYTES_PER_PIXEL;
+        let mut control = self.control.lock();
+        control.command_nodata(TransferToHost2d {
+            header: CtrlHeader::new(CMD_TRANSFER_TO_HOST_2D),
+            rect,
+            offset: offset as u64,
+            resource_id: FB_RESOURCE_ID,
+            padding: 0,
+        }) && control.command_nodata(ResourceFlush {
+            header: CtrlHeader::new(CMD_RESOURCE_FLUSH),
+            rect,
+            resource_id: FB_RESOURCE_ID,
+            padding: 0,
+        })
+    }
+}
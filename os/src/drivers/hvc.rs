@@ -0,0 +1,284 @@
+//! Driver of the virtio console device, through the legacy virtio MMIO
+//! interface, with up to [`MAX_PORTS`] serial ports, `/dev/hvc0` and so on
+//!
+//! Each port has a receive queue kept full of page buffers, emptied by the
+//! interrupt handler into a buffer of input, and a transmit queue reclaimed on
+//! later writes. With the multiport feature, the ports are announced by the
+//! device on the control queues once the driver is ready; a port is usable
+//! after the driver answers that it is ready and open.
+use super::virtio_mmio::*;
+use crate::config::PAGE_SIZE;
+use crate::sync::SpinNoIrqLock;
+use crate::task::WaitQueue;
+use crate::trap::{raise_softirq, Softirq};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::hint::spin_loop;
+use core::mem::size_of;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+const DEVICE_CONSOLE: u32 = 3;
+/// Feature: several ports, announced on the control queues
+const FEATURE_MULTIPORT: u32 = 1 << 1;
+/// Configuration space: the largest number of ports, with `FEATURE_MULTIPORT`
+const CONFIG_MAX_NR_PORTS: usize = CONFIG + 4;
+/// Ports set up, those beyond are refused
+pub const MAX_PORTS: usize = 4;
+
+const CONTROL_RX_QUEUE: u32 = 2;
+const CONTROL_TX_QUEUE: u32 = 3;
+/// Descriptors of the receive queue of a port
+const RX_QUEUE_SIZE: u16 = 4;
+/// Descriptors of the transmit queue of a port
+const TX_QUEUE_SIZE: u16 = 4;
+/// Descriptors of each control queue, enough for the device to announce its
+/// ports at once
+const CONTROL_QUEUE_SIZE: u16 = 8;
+/// Number of received bytes of a port kept until read, further ones are dropped
+const INPUT_BUFFER_SIZE: usize = 4096;
+
+/// Control event from the driver: ready for the ports to be announced
+const DEVICE_READY: u16 = 0;
+/// Control event from the device: a port exists
+const DEVICE_ADD: u16 = 1;
+/// Control event from the device: a port is gone
+const DEVICE_REMOVE: u16 = 2;
+/// Control event from the driver: a port is set up, or failed to be if 0
+const PORT_READY: u16 = 3;
+/// Control event from either side: a port is opened, or closed if 0
+const PORT_OPEN: u16 = 6;
+
+/// Message on the control queues
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ControlMessage {
+    id: u32,
+    event: u16,
+    value: u16,
+}
+
+/// The receive and transmit queues of port `port`
+fn port_queues(port: usize) -> (u32, u32) {
+    // the control queues come between those of port 0 and port 1
+    let rx = if port == 0 { 0 } else { 2 * port as u32 + 2 };
+    (rx, rx + 1)
+}
+
+struct Port {
+    rx: SpinNoIrqLock<VirtQueue>,
+    tx: SpinNoIrqLock<VirtQueue>,
+    /// received bytes not read yet
+    input: SpinNoIrqLock<VecDeque<u8>>,
+    /// tasks waiting for input
+    wait_queue: WaitQueue,
+    /// whether the device announced the port, always for port 0 without multiport
+    added: AtomicBool,
+}
+
+struct ControlQueues {
+    rx: VirtQueue,
+    tx: VirtQueue,
+}
+
+/// A virtio console device at a physical address, identity mapped in kernel space
+pub struct VirtIOConsole {
+    base: usize,
+    ports: Vec<Port>,
+    /// `None` without multiport
+    control: Option<SpinNoIrqLock<ControlQueues>>,
+}
+
+impl VirtIOConsole {
+    /// Set up the console device at `base`, `None` if there is none
+    pub fn probe(base: usize) -> Option<Self> {
+        if !is_device(base, DEVICE_CONSOLE) {
+            return None;
+        }
+        write_reg(base, STATUS, 0);
+        write_reg(base, STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        let features = read_reg(base, HOST_FEATURES) & FEATURE_MULTIPORT;
+        write_reg(base, GUEST_FEATURES, features);
+        write_reg(base, GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        let multiport = features != 0;
+        let transport = Transport::Mmio(base);
+        let nr_ports = if multiport {
+            (read_reg(base, CONFIG_MAX_NR_PORTS) as usize).clamp(1, MAX_PORTS)
+        } else {
+            1
+        };
+        let ports: Vec<Port> = (0..nr_ports)
+            .map(|port| {
+                let (rx_index, tx_index) = port_queues(port);
+                let mut rx = VirtQueue::setup(&transport, rx_index, RX_QUEUE_SIZE);
+                while let Some(id) = rx.free.pop() {
+                    rx.push(id, PAGE_SIZE, DESC_F_WRITE);
+                }
+                let mut tx = VirtQueue::setup(&transport, tx_index, TX_QUEUE_SIZE);
+                tx.disable_interrupts();
+                Port {
+                    rx: SpinNoIrqLock::new(rx),
+                    tx: SpinNoIrqLock::new(tx),
+                    input: SpinNoIrqLock::new(VecDeque::new()),
+                    wait_queue: WaitQueue::new(),
+                    added: AtomicBool::new(!multiport),
+                }
+            })
+            .collect();
+        let control = if multiport {
+            let mut rx = VirtQueue::setup(&transport, CONTROL_RX_QUEUE, CONTROL_QUEUE_SIZE);
+            while let Some(id) = rx.free.pop() {
+                rx.push(id, size_of::<ControlMessage>(), DESC_F_WRITE);
+            }
+            let mut tx = VirtQueue::setup(&transport, CONTROL_TX_QUEUE, CONTROL_QUEUE_SIZE);
+            tx.disable_interrupts();
+            Some(SpinNoIrqLock::new(ControlQueues { rx, tx }))
+        } else {
+            None
+        };
+        write_reg(
+            base,
+            STATUS,
+            STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK,
+        );
+        for port in 0..nr_ports {
+            write_reg(base, QUEUE_NOTIFY, port_queues(port).0);
+        }
+        let console = Self {
+            base,
+            ports,
+            control,
+        };
+        if let Some(control) = console.control.as_ref() {
+            write_reg(base, QUEUE_NOTIFY, CONTROL_RX_QUEUE);
+            // QEMU announces the ports right away, before interrupts are enabled
+            console.send_control(&mut control.lock(), 0, DEVICE_READY, 1);
+            console.handle_control();
+        }
+        Some(console)
+    }
+    /// Whether port `port` is announced by the device and set up
+    pub fn has_port(&self, port: usize) -> bool {
+        port < self.ports.len() && self.ports[port].added.load(Ordering::Relaxed)
+    }
+    /// Send a control message about port `id`
+    fn send_control(&self, control: &mut ControlQueues, id: u32, event: u16, value: u16) {
+        let tx = &mut control.tx;
+        let id_desc = loop {
+            while let Some((used, _)) = tx.pop_used() {
+                tx.free.push(used);
+            }
+            match tx.free.pop() {
+                Some(id_desc) => break id_desc,
+                None => spin_loop(),
+            }
+        };
+        unsafe {
+            write_volatile(
+                tx.buffer(id_desc).as_mut_ptr() as *mut ControlMessage,
+                ControlMessage { id, event, value },
+            );
+        }
+        tx.push(id_desc, size_of::<ControlMessage>(), 0);
+        write_reg(self.base, QUEUE_NOTIFY, CONTROL_TX_QUEUE);
+    }
+    /// Answer the control messages of the device, setting up the ports it announces
+    fn handle_control(&self) {
+        let control = match self.control.as_ref() {
+            Some(control) => control,
+            None => return,
+        };
+        let mut control = control.lock();
+        let mut received = false;
+        while let Some((id_desc, len)) = control.rx.pop_used() {
+            let message = unsafe {
+                read_volatile(control.rx.buffer(id_desc).as_ptr() as *const ControlMessage)
+            };
+            control
+                .rx
+                .push(id_desc, size_of::<ControlMessage>(), DESC_F_WRITE);
+            received = true;
+            if len < size_of::<ControlMessage>() {
+                continue;
+            }
+            let port = message.id as usize;
+            match message.event {
+                DEVICE_ADD if port < self.ports.len() => {
+                    self.ports[port].added.store(true, Ordering::Relaxed);
+                    self.send_control(&mut control, message.id, PORT_READY, 1);
+                    self.send_control(&mut control, message.id, PORT_OPEN, 1);
+                }
+                DEVICE_ADD => self.send_control(&mut control, message.id, PORT_READY, 0),
+                DEVICE_REMOVE if port < self.ports.len() => {
+                    self.ports[port].added.store(false, Ordering::Relaxed);
+                }
+                _ => {}
+            }
+        }
+        if received {
+            write_reg(self.base, QUEUE_NOTIFY, CONTROL_RX_QUEUE);
+        }
+    }
+    /// Move the received bytes to the input of their ports and handle the
+    /// control messages, the tasks waiting for input are woken up by
+    /// [`VirtIOConsole::handle_softirq`]
+    pub fn handle_irq(&self) {
+        let status = read_reg(self.base, INTERRUPT_STATUS);
+        write_reg(self.base, INTERRUPT_ACK, status);
+        let mut received = false;
+        for (i, port) in self.ports.iter().enumerate() {
+            let mut rx = port.rx.lock();
+            let mut input = port.input.lock();
+            let mut used = false;
+            while let Some((id, len)) = rx.pop_used() {
+                let len = len.min(PAGE_SIZE).min(INPUT_BUFFER_SIZE - input.len());
+                input.extend(&rx.buffer(id)[..len]);
+                rx.push(id, PAGE_SIZE, DESC_F_WRITE);
+                used = true;
+            }
+            if used {
+                write_reg(self.base, QUEUE_NOTIFY, port_queues(i).0);
+                received = true;
+            }
+        }
+        self.handle_control();
+        if received {
+            raise_softirq(Softirq::Tty);
+        }
+    }
+    /// Wake up the tasks waiting for the input received by the interrupts
+    pub fn handle_softirq(&self) {
+        for port in self.ports.iter() {
+            port.wait_queue.wake_all();
+        }
+    }
+    /// Tasks waiting for input on port `port`
+    pub fn wait_queue(&self, port: usize) -> &WaitQueue {
+        &self.ports[port].wait_queue
+    }
+    /// Take at most `len` received bytes of port `port`
+    pub fn read(&self, port: usize, len: usize) -> Vec<u8> {
+        let mut input = self.ports[port].input.lock();
+        let len = len.min(input.len());
+        input.drain(..len).collect()
+    }
+    /// Transmit `data` on port `port`, waiting for the device to take it
+    pub fn write(&self, port: usize, data: &[u8]) {
+        let mut tx = self.ports[port].tx.lock();
+        let (_, tx_index) = port_queues(port);
+        for chunk in data.chunks(PAGE_SIZE) {
+            let id = loop {
+                while let Some((used, _)) = tx.pop_used() {
+                    tx.free.push(used);
+                }
+                match tx.free.pop() {
+                    Some(id) => break id,
+                    None => spin_loop(),
+                }
+            };
+            tx.buffer(id)[..chunk.len()].copy_from_slice(chunk);
+            tx.push(id, chunk.len(), 0);
+            write_reg(self.base, QUEUE_NOTIFY, tx_index);
+        }
+    }
+}
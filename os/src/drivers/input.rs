@@ -0,0 +1,223 @@
+//! Driver of the virtio input device, e.g. a keyboard or a mouse, through the
+//! legacy virtio MMIO interface, receiving events by interrupts
+//!
+//! The event queue is kept full of buffers of one `virtio_input_event` each,
+//! all on one page. The interrupt handler stamps the events the device wrote
+//! with the time, moves them to a buffer and gives their buffers back; the
+//! tasks waiting for them are woken up by [`Softirq::Input`].
+use super::block::VirtioHal;
+use super::virtio_mmio::*;
+use crate::config::PAGE_SIZE;
+use crate::mm::{frame_alloc, FrameTracker, PhysAddr};
+use crate::sync::SpinNoIrqLock;
+use crate::task::WaitQueue;
+use crate::timer::{get_time, ticks_to_us};
+use crate::trap::{raise_softirq, Softirq};
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use core::mem::size_of;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+use virtio_drivers::Hal;
+
+const DEVICE_INPUT: u32 = 18;
+const EVENT_QUEUE: u32 = 0;
+/// Descriptors of the event queue, filling the first page with the available ring
+const QUEUE_SIZE: u16 = 64;
+/// Number of events kept until read, further ones are dropped
+const EVENT_BUFFER_SIZE: usize = 256;
+
+/// Configuration space: what to report, write
+const CONFIG_SELECT: usize = CONFIG;
+/// Configuration space: detail of what to report, write
+const CONFIG_SUBSEL: usize = CONFIG + 1;
+/// Configuration space: bytes of the report
+const CONFIG_SIZE: usize = CONFIG + 2;
+/// Configuration space: the report
+const CONFIG_DATA: usize = CONFIG + 8;
+/// `CONFIG_SELECT`: name of the device
+const CONFIG_ID_NAME: u8 = 1;
+
+/// Event as written by the device
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct VirtioInputEvent {
+    ty: u16,
+    code: u16,
+    value: u32,
+}
+
+/// An input event with the time it was received, as read from `/dev/input/event*`
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct InputEvent {
+    /// microseconds since boot
+    pub time_us: u64,
+    /// `EV_SYN`, `EV_KEY`, `EV_REL` or `EV_ABS`, as in Linux
+    pub ty: u16,
+    /// key, button or axis, as in Linux
+    pub code: u16,
+    /// 1 for a key pressed and 0 for released, the motion or the position
+    pub value: i32,
+}
+
+/// The event queue in the legacy layout, the descriptor table and the
+/// available ring on the first page, the used ring on the second one, with a
+/// page holding the events of all the descriptors
+struct EventQueue {
+    /// physical address, identity mapped
+    base: usize,
+    /// buffers of the descriptors, one event each
+    events: FrameTracker,
+    /// next index of the available ring
+    avail_idx: u16,
+    /// next index of the used ring to take
+    last_used: u16,
+}
+
+impl EventQueue {
+    fn new() -> Self {
+        Self {
+            base: VirtioHal::dma_alloc(2),
+            events: frame_alloc().expect("out of frames for the input device"),
+            avail_idx: 0,
+            last_used: 0,
+        }
+    }
+    fn avail(&self, offset: usize) -> *mut u16 {
+        (self.base + QUEUE_SIZE as usize * size_of::<Descriptor>() + offset) as *mut u16
+    }
+    fn used(&self, offset: usize) -> *mut u16 {
+        (self.base + PAGE_SIZE + offset) as *mut u16
+    }
+    fn event(&self, id: u16) -> *mut VirtioInputEvent {
+        let pa: PhysAddr = self.events.ppn.into();
+        (pa.0 as *mut VirtioInputEvent).wrapping_add(id as usize)
+    }
+    /// Give the buffer of descriptor `id` to the device
+    fn push(&mut self, id: u16) {
+        unsafe {
+            write_volatile(
+                (self.base as *mut Descriptor).add(id as usize),
+                Descriptor {
+                    addr: self.event(id) as u64,
+                    len: size_of::<VirtioInputEvent>() as u32,
+                    flags: DESC_F_WRITE,
+                    next: 0,
+                },
+            );
+            write_volatile(
+                self.avail(4 + 2 * (self.avail_idx % QUEUE_SIZE) as usize),
+                id,
+            );
+            // the device sees the entry before the index
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            write_volatile(self.avail(2), self.avail_idx);
+        }
+    }
+    /// Take the descriptor of an event written by the device
+    fn pop_used(&mut self) -> Option<u16> {
+        fence(Ordering::SeqCst);
+        if unsafe { read_volatile(self.used(2)) } == self.last_used {
+            return None;
+        }
+        let elem = self.used(4 + 8 * (self.last_used % QUEUE_SIZE) as usize) as *const u32;
+        self.last_used = self.last_used.wrapping_add(1);
+        Some(unsafe { read_volatile(elem) } as u16)
+    }
+}
+
+/// A virtio input device at a physical address, identity mapped in kernel space
+pub struct VirtIOInput {
+    base: usize,
+    name: String,
+    queue: SpinNoIrqLock<EventQueue>,
+    /// received events not read yet
+    events: SpinNoIrqLock<VecDeque<InputEvent>>,
+    /// tasks waiting for events
+    pub wait_queue: WaitQueue,
+}
+
+impl VirtIOInput {
+    /// Set up the input device at `base`, `None` if there is none
+    pub fn probe(base: usize) -> Option<Self> {
+        if !is_device(base, DEVICE_INPUT) {
+            return None;
+        }
+        write_reg(base, STATUS, 0);
+        write_reg(base, STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        write_reg(base, GUEST_FEATURES, 0);
+        write_reg(base, GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        write_reg(base, QUEUE_SEL, EVENT_QUEUE);
+        assert!(read_reg(base, QUEUE_NUM_MAX) >= QUEUE_SIZE as u32);
+        let mut queue = EventQueue::new();
+        write_reg(base, QUEUE_NUM, QUEUE_SIZE as u32);
+        write_reg(base, QUEUE_ALIGN, PAGE_SIZE as u32);
+        write_reg(base, QUEUE_PFN, (queue.base / PAGE_SIZE) as u32);
+        for id in 0..QUEUE_SIZE {
+            queue.push(id);
+        }
+        let name = unsafe {
+            write_volatile((base + CONFIG_SELECT) as *mut u8, CONFIG_ID_NAME);
+            write_volatile((base + CONFIG_SUBSEL) as *mut u8, 0);
+            let size = read_volatile((base + CONFIG_SIZE) as *const u8) as usize;
+            (0..size)
+                .map(|i| read_volatile((base + CONFIG_DATA + i) as *const u8) as char)
+                .collect()
+        };
+        write_reg(
+            base,
+            STATUS,
+            STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK,
+        );
+        write_reg(base, QUEUE_NOTIFY, EVENT_QUEUE);
+        Some(Self {
+            base,
+            name,
+            queue: SpinNoIrqLock::new(queue),
+            events: SpinNoIrqLock::new(VecDeque::with_capacity(EVENT_BUFFER_SIZE)),
+            wait_queue: WaitQueue::new(),
+        })
+    }
+    /// Name reported by the device
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Move the events written by the device to the buffer, the tasks waiting
+    /// for them are woken up by [`VirtIOInput::handle_softirq`]
+    pub fn handle_irq(&self) {
+        let status = read_reg(self.base, INTERRUPT_STATUS);
+        write_reg(self.base, INTERRUPT_ACK, status);
+        let time_us = ticks_to_us(get_time()) as u64;
+        let mut queue = self.queue.lock();
+        let mut events = self.events.lock();
+        let mut received = false;
+        while let Some(id) = queue.pop_used() {
+            let event = unsafe { read_volatile(queue.event(id)) };
+            if events.len() < EVENT_BUFFER_SIZE {
+                events.push_back(InputEvent {
+                    time_us,
+                    ty: event.ty,
+                    code: event.code,
+                    value: event.value as i32,
+                });
+            }
+            queue.push(id);
+            received = true;
+        }
+        drop(events);
+        if received {
+            write_reg(self.base, QUEUE_NOTIFY, EVENT_QUEUE);
+            raise_softirq(Softirq::Input);
+        }
+    }
+    /// Wake up the tasks waiting for the events received by the interrupts
+    pub fn handle_softirq(&self) {
+        self.wait_queue.wake_all();
+    }
+    /// Take the oldest received event, `None` if there is none
+    pub fn read(&self) -> Option<InputEvent> {
+        self.events.lock().pop_front()
+    }
+}
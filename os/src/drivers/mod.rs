@@ -1,3 +1,213 @@
+pub mod balloon;
 pub mod block;
+pub mod gpu;
+pub mod hvc;
+pub mod input;
+pub mod net;
+pub mod pci;
+pub mod plic;
+pub mod rng;
+pub mod rtc;
+#[cfg(any(feature = "board_qemu", feature = "board_visionfive2"))]
+pub mod uart;
+#[cfg(feature = "board_sifive_u")]
+#[path = "sifive_uart.rs"]
+pub mod uart;
+mod virtio_mmio;
+mod virtio_pci;
 
-pub use block::BLOCK_DEVICE;
+pub use block::{BLOCK_DEVICE, BLOCK_DEVICES};
+pub use plic::{exit_hart, handle_irq, init_hart, register_irq};
+
+use crate::cmdline;
+use crate::fdt::MACHINE;
+use crate::mm::set_reclaim;
+use crate::sync::Lazy;
+use crate::task::queue_work;
+use crate::trap::{open_softirq, Softirq};
+use alloc::vec::Vec;
+use balloon::VirtIOBalloon;
+use core::ptr;
+use easy_fs::BlockDevice;
+use gpu::VirtIOGpu;
+use hvc::{VirtIOConsole, MAX_PORTS};
+use input::VirtIOInput;
+use net::VirtIONet;
+use pci::PCI_FUNCTIONS;
+use rng::VirtIORng;
+use rtc::GoldfishRtc;
+use uart::Uart;
+use virtio_mmio::{probe_slots, probe_transports, VirtioSlot};
+
+/// UARTs of the machine, `ttyS0` and so on, then those on PCI, with their
+/// interrupt sources
+pub static UARTS: Lazy<Vec<(Uart, usize)>> = Lazy::new(|| {
+    let mut uarts = MACHINE.uarts.clone();
+    #[cfg(any(feature = "board_qemu", feature = "board_visionfive2"))]
+    uarts.extend(uart::pci_uarts());
+    uarts
+        .into_iter()
+        .map(|(base, irq)| (Uart::new(base), irq))
+        .collect()
+});
+/// UART of the kernel console, chosen by `console=ttyS<n>` on the command line
+pub static CONSOLE_UART: Lazy<&'static Uart> = Lazy::new(|| tty_param("console"));
+/// UART of the standard input and output of user programs, chosen by
+/// `shell=ttyS<n>` on the command line
+pub static SHELL_UART: Lazy<&'static Uart> = Lazy::new(|| tty_param("shell"));
+
+/// The UART `ttyS<n>` given by `option=ttyS<n>` on the command line, `ttyS0`
+/// if it is missing or there is no such UART
+fn tty_param(option: &str) -> &'static Uart {
+    let n = cmdline::get(option)
+        .and_then(|tty| tty.strip_prefix("ttyS"))
+        .and_then(|n| n.parse::<usize>().ok())
+        .filter(|&n| n < UARTS.len())
+        .unwrap_or(0);
+    &UARTS[n].0
+}
+/// wall clock of the machine, `None` if it has none
+pub static RTC: Lazy<Option<GoldfishRtc>> = Lazy::new(|| MACHINE.rtc.map(GoldfishRtc::new));
+/// first network device of the machine, on PCI or not, `None` if QEMU is
+/// run without one
+pub static NET_DEVICE: Lazy<Option<VirtioSlot<VirtIONet>>> =
+    Lazy::new(|| probe_transports(VirtIONet::probe).next());
+/// first entropy device of the machine, on PCI or not, `None` if QEMU is run
+/// without one
+pub static RNG_DEVICE: Lazy<Option<VirtioSlot<VirtIORng>>> =
+    Lazy::new(|| probe_transports(VirtIORng::probe).next());
+/// first memory balloon of the machine, on PCI or not, `None` if QEMU is run
+/// without one
+pub static BALLOON_DEVICE: Lazy<Option<VirtioSlot<VirtIOBalloon>>> =
+    Lazy::new(|| probe_transports(VirtIOBalloon::probe).next());
+/// first display of the machine, polled, `None` if QEMU is run without one
+pub static GPU_DEVICE: Lazy<Option<VirtioSlot<VirtIOGpu>>> =
+    Lazy::new(|| probe_slots(VirtIOGpu::probe).next());
+/// first two input devices of the machine, `/dev/input/event0` and `event1`,
+/// `None` for those QEMU is run without
+pub static INPUT_DEVICES: Lazy<[Option<VirtioSlot<VirtIOInput>>; 2]> = Lazy::new(|| {
+    let mut inputs = probe_slots(VirtIOInput::probe);
+    [inputs.next(), inputs.next()]
+});
+
+/// first console device of the machine with its ports `/dev/hvc0` and so
+/// on, `None` if QEMU is run without one
+pub static HVC_DEVICE: Lazy<Option<VirtioSlot<VirtIOConsole>>> =
+    Lazy::new(|| probe_slots(VirtIOConsole::probe).next());
+
+/// Set up the devices raising interrupts and register their handlers, once by the boot hart
+pub fn init() {
+    for (uart, _) in UARTS.iter() {
+        uart.init();
+    }
+    // as for disks, the interrupt of a UART polls all of them
+    let mut irqs: Vec<usize> = UARTS.iter().map(|&(_, irq)| irq).collect();
+    irqs.sort_unstable();
+    irqs.dedup();
+    for irq in irqs {
+        register_irq(irq, 1, || {
+            for (uart, _) in UARTS.iter() {
+                uart.handle_irq();
+            }
+        });
+    }
+    let tty = |uart: &Uart| UARTS.iter().position(|(other, _)| ptr::eq(other, uart));
+    info!(
+        "{} UARTs, console on ttyS{}, shell on ttyS{}",
+        UARTS.len(),
+        tty(*CONSOLE_UART).unwrap(),
+        tty(*SHELL_UART).unwrap()
+    );
+    crate::console::set_uart(*CONSOLE_UART);
+    for function in PCI_FUNCTIONS.iter() {
+        info!(
+            "pci 00:{:02x}.{}: {:04x}:{:04x}",
+            function.device,
+            function.function,
+            function.vendor_id(),
+            function.device_id()
+        );
+    }
+    // the interrupt of a disk polls all of them, which are few, and disks on
+    // PCI may share one
+    let mut irqs: Vec<usize> = BLOCK_DEVICES.iter().filter_map(|entry| entry.irq).collect();
+    irqs.sort_unstable();
+    irqs.dedup();
+    for irq in irqs {
+        register_irq(irq, 1, || {
+            for entry in BLOCK_DEVICES.iter() {
+                entry.device.handle_irq();
+            }
+        });
+    }
+    open_softirq(Softirq::Tty, || {
+        for (uart, _) in UARTS.iter() {
+            uart.handle_softirq();
+        }
+        if let Some(hvc) = HVC_DEVICE.as_ref() {
+            hvc.handle_softirq();
+        }
+    });
+    if let Some(hvc) = HVC_DEVICE.as_ref() {
+        register_irq(hvc.irq, 1, || HVC_DEVICE.as_ref().unwrap().handle_irq());
+        for port in (0..MAX_PORTS).filter(|&port| hvc.has_port(port)) {
+            info!("hvc{}", port);
+        }
+    }
+    open_softirq(Softirq::Block, || {
+        for entry in BLOCK_DEVICES.iter() {
+            entry.device.handle_softirq();
+        }
+    });
+    for entry in BLOCK_DEVICES.iter() {
+        info!("{}: {} blocks", entry.name, entry.blocks);
+    }
+    // set up at boot rather than by the first reseed of the entropy pool
+    if RNG_DEVICE.is_some() {
+        info!("virtio-rng");
+    }
+    if let Some(balloon) = BALLOON_DEVICE.as_ref() {
+        let oom = if balloon.deflate_on_oom() {
+            ", deflating on OOM"
+        } else {
+            ""
+        };
+        info!("virtio-balloon{}", oom);
+        // resized in the worker thread, as it may take many frames
+        register_irq(balloon.irq, 1, || {
+            if BALLOON_DEVICE.as_ref().unwrap().handle_irq() {
+                queue_work(|| BALLOON_DEVICE.as_ref().unwrap().adjust());
+            }
+        });
+        set_reclaim(|| BALLOON_DEVICE.as_ref().unwrap().reclaim());
+        // the host may want pages in it from the start
+        queue_work(|| BALLOON_DEVICE.as_ref().unwrap().adjust());
+    }
+    if let Some(net) = NET_DEVICE.as_ref() {
+        register_irq(net.irq, 1, || NET_DEVICE.as_ref().unwrap().handle_irq());
+    }
+    open_softirq(Softirq::Input, || {
+        for input in INPUT_DEVICES.iter().flatten() {
+            input.handle_softirq();
+        }
+    });
+    if let Some(input) = INPUT_DEVICES[0].as_ref() {
+        register_irq(input.irq, 1, || {
+            INPUT_DEVICES[0].as_ref().unwrap().handle_irq()
+        });
+    }
+    if let Some(input) = INPUT_DEVICES[1].as_ref() {
+        register_irq(input.irq, 1, || {
+            INPUT_DEVICES[1].as_ref().unwrap().handle_irq()
+        });
+    }
+    for (i, input) in INPUT_DEVICES.iter().enumerate() {
+        if let Some(input) = input {
+            info!("input{}: {}", i, input.name());
+        }
+    }
+    // set up at boot rather than by the first open of /dev/fb0
+    if let Some(gpu) = GPU_DEVICE.as_ref() {
+        info!("framebuffer {}x{}", gpu.width(), gpu.height());
+    }
+}
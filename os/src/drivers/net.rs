@@ -0,0 +1,118 @@
+//! Driver of the virtio network device, on either virtio transport,
+//! receiving frames by interrupts
+//!
+//! Each [`VirtQueue`] descriptor points to a page buffering one frame behind
+//! the `virtio_net_hdr`. The receive queue is kept full of buffers; the interrupt
+//! handler raises [`Softirq::NetRx`], which takes the received frames and
+//! gives their buffers back to the device. Transmitted frames are copied to a
+//! free buffer, reclaimed on later transmissions without interrupts.
+use super::virtio_mmio::*;
+use crate::config::PAGE_SIZE;
+use crate::sync::SpinNoIrqLock;
+use crate::trap::{raise_softirq, Softirq};
+
+const DEVICE_NET: u32 = 1;
+/// Feature: the MAC address is in the configuration space
+const FEATURE_MAC: u64 = 1 << 5;
+
+const RX_QUEUE: u32 = 0;
+const TX_QUEUE: u32 = 1;
+/// Descriptors of each queue, filling the first page with the available ring
+const QUEUE_SIZE: u16 = 16;
+/// Length of `virtio_net_hdr` without offloads, all zero
+const NET_HDR_LEN: usize = 10;
+/// Length of `virtio_net_hdr` from version 1 on, always with `num_buffers`
+const NET_HDR_LEN_V1: usize = 12;
+/// Largest Ethernet frame, without the checksum
+pub const MAX_FRAME_LEN: usize = 1514;
+
+/// A virtio network device
+pub struct VirtIONet {
+    transport: Transport,
+    mac: [u8; 6],
+    /// length of the header before each frame
+    hdr_len: usize,
+    rx: SpinNoIrqLock<VirtQueue>,
+    tx: SpinNoIrqLock<VirtQueue>,
+}
+
+impl VirtIONet {
+    /// Set up the network device reached by `transport`, `None` if there is none
+    pub fn probe(transport: Transport) -> Option<Self> {
+        if !transport.is_device(DEVICE_NET) {
+            return None;
+        }
+        let features = transport.begin_init(FEATURE_MAC)?;
+        let mut rx = VirtQueue::setup(&transport, RX_QUEUE, QUEUE_SIZE);
+        let mut tx = VirtQueue::setup(&transport, TX_QUEUE, QUEUE_SIZE);
+        tx.disable_interrupts();
+        // the default address of QEMU if the device has none
+        let mut mac = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+        if features & FEATURE_MAC != 0 {
+            for (i, byte) in mac.iter_mut().enumerate() {
+                *byte = transport.read_config_u8(i);
+            }
+        }
+        while let Some(id) = rx.free.pop() {
+            rx.push(id, PAGE_SIZE, DESC_F_WRITE);
+        }
+        transport.finish_init();
+        rx.notify();
+        let hdr_len = if features & FEATURE_VERSION_1 != 0 {
+            NET_HDR_LEN_V1
+        } else {
+            NET_HDR_LEN
+        };
+        Some(Self {
+            transport,
+            mac,
+            hdr_len,
+            rx: SpinNoIrqLock::new(rx),
+            tx: SpinNoIrqLock::new(tx),
+        })
+    }
+    /// MAC address of the device
+    pub fn mac(&self) -> [u8; 6] {
+        self.mac
+    }
+    /// Acknowledge the interrupt, deferring the received frames to the softirq
+    pub fn handle_irq(&self) {
+        self.transport.ack_interrupt();
+        raise_softirq(Softirq::NetRx);
+    }
+    /// Pass the frames received to `handler` and give their buffers back to the device
+    pub fn receive(&self, mut handler: impl FnMut(&[u8])) {
+        let mut rx = self.rx.lock();
+        let mut received = false;
+        while let Some((id, len)) = rx.pop_used() {
+            let len = len.clamp(self.hdr_len, PAGE_SIZE);
+            handler(&rx.buffer(id)[self.hdr_len..len]);
+            rx.push(id, PAGE_SIZE, DESC_F_WRITE);
+            received = true;
+        }
+        if received {
+            rx.notify();
+        }
+    }
+    /// Transmit `frame`, without waiting for it to be sent. Return false if it
+    /// is longer than [`MAX_FRAME_LEN`] or all the buffers are in flight.
+    pub fn transmit(&self, frame: &[u8]) -> bool {
+        if frame.len() > MAX_FRAME_LEN {
+            return false;
+        }
+        let mut tx = self.tx.lock();
+        while let Some((id, _)) = tx.pop_used() {
+            tx.free.push(id);
+        }
+        let id = match tx.free.pop() {
+            Some(id) => id,
+            None => return false,
+        };
+        let buffer = tx.buffer(id);
+        buffer[..self.hdr_len].fill(0);
+        buffer[self.hdr_len..self.hdr_len + frame.len()].copy_from_slice(frame);
+        tx.push(id, self.hdr_len + frame.len(), 0);
+        tx.notify();
+        true
+    }
+}
@@ -0,0 +1,238 @@
+//! Enumeration of the PCI devices behind the host bridge of the machine,
+//! through its memory-mapped configuration space (ECAM)
+//!
+//! Only bus 0 is scanned, where QEMU puts the devices it is given; bridges are
+//! not followed. No firmware assigns the BARs on RISC-V boards, so the BARs of
+//! each function are assigned from the 32-bit memory window of the host
+//! bridge, or from its I/O window for I/O BARs, which the bridge maps in
+//! memory too, and mapped in kernel space by [`vmap`].
+use crate::config::PAGE_SIZE;
+use crate::fdt::{PciHost, MACHINE};
+use crate::mm::{vmap, MapPermission, PhysPageNum, VmMapping};
+use crate::sync::Lazy;
+use alloc::vec::Vec;
+use core::ptr::{read_volatile, write_volatile};
+
+const VENDOR_ID: usize = 0x00;
+const DEVICE_ID: usize = 0x02;
+const COMMAND: usize = 0x04;
+const STATUS: usize = 0x06;
+const HEADER_TYPE: usize = 0x0e;
+const BAR0: usize = 0x10;
+const SUBSYSTEM_ID: usize = 0x2e;
+/// Offset of the first capability
+const CAPABILITIES: usize = 0x34;
+const INTERRUPT_PIN: usize = 0x3d;
+
+/// `VENDOR_ID` of a missing function
+const NO_VENDOR: u16 = 0xffff;
+/// `COMMAND`: decode accesses to the I/O BARs
+const COMMAND_IO: u16 = 1 << 0;
+/// `COMMAND`: decode accesses to the memory BARs
+const COMMAND_MEMORY: u16 = 1 << 1;
+/// `COMMAND`: let the function access memory, e.g. for virtqueues
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+/// `STATUS`: `CAPABILITIES` is valid
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+/// `HEADER_TYPE`: the device has functions other than 0
+const HEADER_MULTIFUNCTION: u8 = 1 << 7;
+/// `HEADER_TYPE` of an endpoint, rather than a bridge
+const HEADER_ENDPOINT: u8 = 0;
+/// BAR: in I/O space
+const BAR_IO: u32 = 1 << 0;
+/// BAR: the flags below the address of an I/O BAR
+const BAR_IO_FLAGS: u32 = 0x3;
+/// BAR: the flags below the address of a memory BAR
+const BAR_MEM_FLAGS: u32 = 0xf;
+/// BAR: the type of a memory BAR
+const BAR_TYPE: u32 = 3 << 1;
+/// `BAR_TYPE`: 64 bits, taking the next BAR for the high half
+const BAR_TYPE_64: u32 = 2 << 1;
+
+const BARS: usize = 6;
+const DEVICES: usize = 32;
+const FUNCTIONS: usize = 8;
+/// Size of the configuration space of a function in the ECAM
+const CONFIG_SIZE: usize = 0x1000;
+/// Most capabilities followed, in case their list loops
+const MAX_CAPABILITIES: usize = 48;
+
+/// The configuration space of bus 0 mapped in kernel space, `None` without a
+/// host bridge
+static ECAM: Lazy<Option<VmMapping>> = Lazy::new(|| {
+    let host = MACHINE.pci?;
+    let pages = DEVICES * FUNCTIONS * CONFIG_SIZE / PAGE_SIZE;
+    let ppns: Vec<PhysPageNum> = (0..pages)
+        .map(|page| PhysPageNum(host.ecam / PAGE_SIZE + page))
+        .collect();
+    vmap(&ppns, MapPermission::R | MapPermission::W)
+});
+
+/// The functions found on bus 0 at boot, in the order of their numbers
+pub static PCI_FUNCTIONS: Lazy<Vec<PciFunction>> =
+    Lazy::new(|| match (MACHINE.pci, ECAM.as_ref()) {
+        (Some(host), Some(ecam)) => enumerate(&host, ecam.start()),
+        _ => Vec::new(),
+    });
+
+/// A function of a PCI device, with its memory BARs mapped and allowed to
+/// master the bus
+pub struct PciFunction {
+    /// number of the device on bus 0
+    pub device: usize,
+    /// number of the function in the device
+    pub function: usize,
+    /// kernel address of its configuration space
+    config: usize,
+    /// mapping of each BAR, `None` if it is not assigned
+    bars: [Option<VmMapping>; BARS],
+    /// interrupt source of its pin at the PLIC, `None` if it raises none
+    pub irq: Option<usize>,
+}
+
+impl PciFunction {
+    /// Read the byte at `offset` of the configuration space
+    pub fn read_u8(&self, offset: usize) -> u8 {
+        unsafe { read_volatile((self.config + offset) as *const u8) }
+    }
+    /// Read the half word at `offset` of the configuration space
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        unsafe { read_volatile((self.config + offset) as *const u16) }
+    }
+    /// Read the word at `offset` of the configuration space
+    pub fn read_u32(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.config + offset) as *const u32) }
+    }
+    fn write_u16(&self, offset: usize, value: u16) {
+        unsafe {
+            write_volatile((self.config + offset) as *mut u16, value);
+        }
+    }
+    fn write_u32(&self, offset: usize, value: u32) {
+        unsafe {
+            write_volatile((self.config + offset) as *mut u32, value);
+        }
+    }
+    /// Vendor of the function
+    pub fn vendor_id(&self) -> u16 {
+        self.read_u16(VENDOR_ID)
+    }
+    /// Kind of the function, among those of its vendor
+    pub fn device_id(&self) -> u16 {
+        self.read_u16(DEVICE_ID)
+    }
+    /// Kind of the function, refining `device_id`
+    pub fn subsystem_id(&self) -> u16 {
+        self.read_u16(SUBSYSTEM_ID)
+    }
+    /// Kernel address of BAR `bar`, in memory or I/O space, `None` if it is
+    /// not assigned
+    pub fn bar(&self, bar: usize) -> Option<usize> {
+        self.bars.get(bar)?.as_ref().map(|mapping| mapping.start())
+    }
+    /// Offsets of the capabilities in the configuration space, each starting
+    /// with its id
+    pub fn capabilities(&self) -> impl Iterator<Item = usize> + '_ {
+        let first = if self.read_u16(STATUS) & STATUS_CAPABILITIES != 0 {
+            self.read_u8(CAPABILITIES) as usize & !3
+        } else {
+            0
+        };
+        let next = move |&offset: &usize| Some(self.read_u8(offset + 1) as usize & !3);
+        core::iter::successors(Some(first), next)
+            .take_while(|&offset| offset != 0)
+            .take(MAX_CAPABILITIES)
+    }
+    /// Assign the BARs from the windows of `host` past `next_mem` and
+    /// `next_io`, offsets in them moved past the BARs, and map them
+    fn assign_bars(&mut self, host: &PciHost, next_mem: &mut usize, next_io: &mut usize) {
+        let mut bar = 0;
+        while bar < BARS {
+            let reg = BAR0 + bar * 4;
+            let value = self.read_u32(reg);
+            let io = value & BAR_IO != 0;
+            let wide = !io && value & BAR_TYPE == BAR_TYPE_64;
+            // the bits of the address the function ignores read as 0
+            self.write_u32(reg, !0);
+            let flags = if io { BAR_IO_FLAGS } else { BAR_MEM_FLAGS };
+            let mut mask = (self.read_u32(reg) & !flags) as u64;
+            if wide {
+                self.write_u32(reg + 4, !0);
+                mask |= (self.read_u32(reg + 4) as u64) << 32;
+            } else if mask != 0 && io {
+                // some functions decode only 16 bits of I/O addresses
+                mask |= 0xffff_ffff_ffff_0000;
+            } else if mask != 0 {
+                mask |= 0xffff_ffff << 32;
+            }
+            let size = (!mask).wrapping_add(1) as usize;
+            let (next, window, window_pci, window_size) = if io {
+                (&mut *next_io, host.io, host.io_pci, host.io_size)
+            } else {
+                (&mut *next_mem, host.mem, host.mem_pci, host.mem_size)
+            };
+            // each BAR gets pages of its own
+            let align = size.max(PAGE_SIZE);
+            let start = (*next + align - 1) & !(align - 1);
+            if mask != 0 && start + size <= window_size {
+                *next = start + size;
+                let address = window_pci + start;
+                self.write_u32(reg, address as u32);
+                if wide {
+                    self.write_u32(reg + 4, (address >> 32) as u32);
+                }
+                let pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+                let ppns: Vec<PhysPageNum> = (0..pages)
+                    .map(|page| PhysPageNum((window + start) / PAGE_SIZE + page))
+                    .collect();
+                self.bars[bar] = vmap(&ppns, MapPermission::R | MapPermission::W);
+            } else {
+                self.write_u32(reg, 0);
+            }
+            bar += if wide { 2 } else { 1 };
+        }
+    }
+}
+
+/// Find the endpoints on bus 0 of `host`, whose configuration space is at
+/// `ecam` in kernel space, and set them up
+fn enumerate(host: &PciHost, ecam: usize) -> Vec<PciFunction> {
+    let mut functions = Vec::new();
+    let mut next_mem = 0;
+    // I/O address 0 counts as unassigned, so the first page is skipped
+    let mut next_io = PAGE_SIZE;
+    for device in 0..DEVICES {
+        for function in 0..FUNCTIONS {
+            let mut found = PciFunction {
+                device,
+                function,
+                config: ecam + (device * FUNCTIONS + function) * CONFIG_SIZE,
+                bars: Default::default(),
+                irq: None,
+            };
+            if found.vendor_id() == NO_VENDOR {
+                if function == 0 {
+                    break;
+                }
+                continue;
+            }
+            let header = found.read_u8(HEADER_TYPE);
+            if header & !HEADER_MULTIFUNCTION == HEADER_ENDPOINT {
+                found.assign_bars(host, &mut next_mem, &mut next_io);
+                // the pins of the slots are rotated at the host bridge
+                let pin = found.read_u8(INTERRUPT_PIN) as usize;
+                found.irq = (1..=4)
+                    .contains(&pin)
+                    .then(|| host.irqs[(device + pin - 1) % 4]);
+                let command = found.read_u16(COMMAND);
+                let enable = COMMAND_IO | COMMAND_MEMORY | COMMAND_BUS_MASTER;
+                found.write_u16(COMMAND, command | enable);
+                functions.push(found);
+            }
+            if function == 0 && header & HEADER_MULTIFUNCTION == 0 {
+                break;
+            }
+        }
+    }
+    functions
+}
@@ -0,0 +1,165 @@
+//! Driver of the platform-level interrupt controller, routing the interrupts of
+//! devices to the harts. Drivers register a handler for each interrupt source
+//! they raise, called by [`handle_irq`] on the hart claiming it. Devices may
+//! share a source, e.g. the pins of PCI slots, whose handlers are then all
+//! called, each checking its own device.
+//!
+//! Handlers run with interrupts enabled and the threshold of the hart raised to
+//! the priority of their source, so that only sources of higher priority and
+//! the timer interrupt them.
+use crate::board::PLIC_SOURCES;
+use crate::config::MAX_HARTS;
+use crate::fdt::MACHINE;
+use crate::hart::{hart_id, online_hart_mask};
+use crate::random::add_interrupt_randomness;
+use crate::sync::{Lazy, SpinNoIrqLock};
+use core::ptr::{read_volatile, write_volatile};
+use riscv::register::sstatus;
+
+/// interrupt controller of the machine
+static PLIC: Lazy<Plic> = Lazy::new(|| Plic::new(MACHINE.plic));
+/// Most devices sharing an interrupt source
+const MAX_SHARED: usize = 4;
+
+/// handlers of each interrupt source, all `None` while it is disabled
+static IRQ_HANDLERS: SpinNoIrqLock<[[Option<fn()>; MAX_SHARED]; PLIC_SOURCES]> =
+    SpinNoIrqLock::new([[None; MAX_SHARED]; PLIC_SOURCES]);
+
+/// Call `handler` on interrupts of source `irq`, along with those registered
+/// before, routed to every hart with `priority` above 0 once given by the
+/// first one. Panic if `MAX_SHARED` handlers share it already.
+pub fn register_irq(irq: usize, priority: u32, handler: fn()) {
+    assert!(irq > 0 && irq < PLIC_SOURCES && priority > 0);
+    let mut handlers = IRQ_HANDLERS.lock();
+    match handlers[irq].iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => *slot = Some(handler),
+        None => panic!("IRQ {} shared by too many devices", irq),
+    }
+    if handlers[irq][1].is_some() {
+        return;
+    }
+    PLIC.set_priority(irq, priority);
+    // harts coming online later enable it in `init_hart`
+    let online = online_hart_mask();
+    for hart in (0..MAX_HARTS).filter(|hart| online & (1 << hart) != 0) {
+        PLIC.enable(hart, irq);
+    }
+}
+
+/// Route the registered interrupts to the current hart, once it is online
+pub fn init_hart() {
+    let hart = hart_id();
+    let handlers = IRQ_HANDLERS.lock();
+    for irq in (1..PLIC_SOURCES).filter(|irq| handlers[*irq][0].is_some()) {
+        PLIC.enable(hart, irq);
+    }
+    PLIC.set_threshold(hart, 0);
+}
+
+/// Stop routing interrupts to the current hart, going offline
+pub fn exit_hart() {
+    let hart = hart_id();
+    for irq in 1..PLIC_SOURCES {
+        PLIC.disable(hart, irq);
+    }
+}
+
+/// Handle the pending device interrupts routed to the current hart
+pub fn handle_irq() {
+    let hart = hart_id();
+    while let Some(irq) = PLIC.claim(hart) {
+        add_interrupt_randomness(irq);
+        let handlers = IRQ_HANDLERS.lock()[irq];
+        if handlers[0].is_none() {
+            panic!("Unsupported external interrupt {}", irq);
+        }
+        let threshold = PLIC.threshold(hart);
+        PLIC.set_threshold(hart, PLIC.priority(irq));
+        unsafe {
+            sstatus::set_sie();
+        }
+        for handler in handlers.iter().flatten() {
+            handler();
+        }
+        unsafe {
+            sstatus::clear_sie();
+        }
+        PLIC.set_threshold(hart, threshold);
+        PLIC.complete(hart, irq);
+    }
+}
+
+/// The PLIC of a board at a physical address, identity mapped in kernel space
+struct Plic {
+    base: usize,
+}
+
+impl Plic {
+    /// The PLIC at `base`
+    const fn new(base: usize) -> Self {
+        Self { base }
+    }
+    /// Interrupt context of the supervisor mode of `hart`, as described by
+    /// the device tree or laid out by the board
+    fn context(hart: usize) -> usize {
+        MACHINE.plic_context(hart)
+    }
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.base + offset) as *mut u32
+    }
+    /// Set the priority of interrupt source `irq`, 0 never interrupts
+    fn set_priority(&self, irq: usize, priority: u32) {
+        unsafe {
+            write_volatile(self.reg(irq * 4), priority);
+        }
+    }
+    /// Priority of interrupt source `irq`
+    fn priority(&self, irq: usize) -> u32 {
+        unsafe { read_volatile(self.reg(irq * 4)) }
+    }
+    /// Route interrupt source `irq` to the supervisor mode of `hart`
+    fn enable(&self, hart: usize, irq: usize) {
+        let reg = self.reg(0x2000 + 0x80 * Self::context(hart) + irq / 32 * 4);
+        unsafe {
+            write_volatile(reg, read_volatile(reg) | 1 << (irq % 32));
+        }
+    }
+    /// Stop routing interrupt source `irq` to the supervisor mode of `hart`
+    fn disable(&self, hart: usize, irq: usize) {
+        let reg = self.reg(0x2000 + 0x80 * Self::context(hart) + irq / 32 * 4);
+        unsafe {
+            write_volatile(reg, read_volatile(reg) & !(1 << (irq % 32)));
+        }
+    }
+    /// Interrupt the supervisor mode of `hart` only for priorities above `threshold`
+    fn set_threshold(&self, hart: usize, threshold: u32) {
+        unsafe {
+            write_volatile(
+                self.reg(0x20_0000 + 0x1000 * Self::context(hart)),
+                threshold,
+            );
+        }
+    }
+    /// Threshold of the supervisor mode of `hart`
+    fn threshold(&self, hart: usize) -> u32 {
+        unsafe { read_volatile(self.reg(0x20_0000 + 0x1000 * Self::context(hart))) }
+    }
+    /// Take the pending interrupt of the highest priority for `hart`, `None` if
+    /// there is none or another hart took it
+    fn claim(&self, hart: usize) -> Option<usize> {
+        let irq = unsafe { read_volatile(self.reg(0x20_0004 + 0x1000 * Self::context(hart))) };
+        match irq {
+            0 => None,
+            irq => Some(irq as usize),
+        }
+    }
+    /// Signal that interrupt `irq` claimed by `hart` has been handled
+    fn complete(&self, hart: usize, irq: usize) {
+        unsafe {
+            write_volatile(
+                self.reg(0x20_0004 + 0x1000 * Self::context(hart)),
+                irq as u32,
+            );
+        }
+    }
+}
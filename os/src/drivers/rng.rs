@@ -0,0 +1,51 @@
+//! Driver of the virtio entropy device, on either virtio transport
+//!
+//! The device fills the buffers given to its only queue with random bytes
+//! from the host. They are only asked for when the entropy pool reseeds, so
+//! the device is polled, which QEMU answers right away.
+use super::virtio_mmio::{Transport, VirtQueue, DESC_F_WRITE};
+use crate::config::PAGE_SIZE;
+use crate::sync::SpinNoIrqLock;
+use core::hint::spin_loop;
+
+const DEVICE_ENTROPY: u32 = 4;
+const REQUEST_QUEUE: u32 = 0;
+/// Descriptors of the queue, for one request at a time
+const QUEUE_SIZE: u16 = 1;
+
+/// A virtio entropy device
+pub struct VirtIORng {
+    queue: SpinNoIrqLock<VirtQueue>,
+}
+
+impl VirtIORng {
+    /// Set up the entropy device reached by `transport`, `None` if there is none
+    pub fn probe(transport: Transport) -> Option<Self> {
+        if !transport.is_device(DEVICE_ENTROPY) {
+            return None;
+        }
+        transport.begin_init(0)?;
+        let mut queue = VirtQueue::setup(&transport, REQUEST_QUEUE, QUEUE_SIZE);
+        queue.disable_interrupts();
+        transport.finish_init();
+        Some(Self {
+            queue: SpinNoIrqLock::new(queue),
+        })
+    }
+    /// Fill the start of `buf` with random bytes, at most a page of them, and
+    /// return their number
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        let mut queue = self.queue.lock();
+        let len = buf.len().min(PAGE_SIZE);
+        queue.push(0, len, DESC_F_WRITE);
+        queue.notify();
+        let written = loop {
+            if let Some((_, written)) = queue.pop_used() {
+                break written.min(len);
+            }
+            spin_loop();
+        };
+        buf[..written].copy_from_slice(&queue.buffer(0)[..written]);
+        written
+    }
+}
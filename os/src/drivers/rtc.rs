@@ -0,0 +1,27 @@
+//! Driver of the Goldfish RTC, the wall clock of the virt machine
+use core::ptr::read_volatile;
+
+/// nanoseconds since the epoch, bits 0..32, latching the high bits
+const TIME_LOW: usize = 0x00;
+/// nanoseconds since the epoch, bits 32..64, as latched by reading `TIME_LOW`
+const TIME_HIGH: usize = 0x04;
+
+/// The RTC at a physical address, identity mapped in kernel space
+pub struct GoldfishRtc {
+    base: usize,
+}
+
+impl GoldfishRtc {
+    /// The RTC at `base`
+    pub const fn new(base: usize) -> Self {
+        Self { base }
+    }
+    /// Nanoseconds since the Unix epoch
+    pub fn read_ns(&self) -> u64 {
+        unsafe {
+            let low = read_volatile((self.base + TIME_LOW) as *const u32) as u64;
+            let high = read_volatile((self.base + TIME_HIGH) as *const u32) as u64;
+            high << 32 | low
+        }
+    }
+}
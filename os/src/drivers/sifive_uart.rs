@@ -0,0 +1,123 @@
+//! Driver of the SiFive UARTs, receiving input by interrupts
+use crate::monitor::Monitor;
+use crate::sync::SpinNoIrqLock;
+use crate::task::WaitQueue;
+use crate::trap::{raise_softirq, Softirq};
+use alloc::collections::VecDeque;
+use core::ptr::{read_volatile, write_volatile};
+
+/// Compatible strings of the UART in the device tree
+pub const COMPATIBLE: &[&str] = &["sifive,uart0"];
+
+/// Transmit data register
+const TXDATA: usize = 0x00;
+/// Receive data register, read
+const RXDATA: usize = 0x04;
+/// Transmit control register
+const TXCTRL: usize = 0x08;
+/// Receive control register
+const RXCTRL: usize = 0x0c;
+/// Interrupt enable register
+const IE: usize = 0x10;
+
+/// `TXDATA`: the transmit FIFO is full
+const TXDATA_FULL: u32 = 1 << 31;
+/// `TXCTRL`: enable the transmitter
+const TXCTRL_TXEN: u32 = 1 << 0;
+/// `RXDATA`: the receive FIFO is empty
+const RXDATA_EMPTY: u32 = 1 << 31;
+/// `RXCTRL`: enable the receiver, with a watermark of 0 so that any received
+/// byte raises the interrupt
+const RXCTRL_RXEN: u32 = 1 << 0;
+/// `IE`: interrupt when the receive FIFO is above the watermark
+const IE_RXWM: u32 = 1 << 1;
+
+/// Number of received bytes kept until read, further ones are dropped
+const RX_BUFFER_SIZE: usize = 256;
+
+/// The UART at a physical address, identity mapped in kernel space
+pub struct Uart {
+    base: usize,
+    /// taken while writing, so that the bytes of a write are not interleaved
+    tx_lock: SpinNoIrqLock<()>,
+    /// received bytes not read yet
+    rx_buffer: SpinNoIrqLock<VecDeque<u8>>,
+    /// tasks waiting for input
+    pub rx_wait_queue: WaitQueue,
+    /// escape sequences of the debug monitor in the input
+    monitor: Monitor,
+}
+
+impl Uart {
+    /// The UART at `base`, to be set up by [`Uart::init`]
+    pub fn new(base: usize) -> Self {
+        Self {
+            base,
+            tx_lock: SpinNoIrqLock::new(()),
+            rx_buffer: SpinNoIrqLock::new(VecDeque::with_capacity(RX_BUFFER_SIZE)),
+            rx_wait_queue: WaitQueue::new(),
+            monitor: Monitor::new(),
+        }
+    }
+    fn read_reg(&self, reg: usize) -> u32 {
+        unsafe { read_volatile((self.base + reg) as *const u32) }
+    }
+    fn write_reg(&self, reg: usize, value: u32) {
+        unsafe {
+            write_volatile((self.base + reg) as *mut u32, value);
+        }
+    }
+    /// Raise an interrupt when a byte is received, keeping the baud rate of the firmware
+    pub fn init(&self) {
+        // the firmware enables only the transmitter of the UART it uses
+        self.write_reg(TXCTRL, self.read_reg(TXCTRL) | TXCTRL_TXEN);
+        self.write_reg(RXCTRL, RXCTRL_RXEN);
+        self.write_reg(IE, IE_RXWM);
+    }
+    /// Move the received bytes to the buffer, the tasks waiting for them are
+    /// woken up by [`Uart::handle_softirq`]
+    pub fn handle_irq(&self) {
+        let mut received = false;
+        loop {
+            let data = self.read_reg(RXDATA);
+            if data & RXDATA_EMPTY != 0 {
+                break;
+            }
+            received |= self.receive(data as u8);
+        }
+        if received {
+            raise_softirq(Softirq::Tty);
+        }
+    }
+    /// Keep `byte` received for the readers unless it is for the debug
+    /// monitor, true if it is kept
+    fn receive(&self, byte: u8) -> bool {
+        // the buffer is not locked while a command of the monitor runs
+        let byte = match self.monitor.filter(byte) {
+            Some(byte) => byte,
+            None => return false,
+        };
+        let mut rx_buffer = self.rx_buffer.lock();
+        if rx_buffer.len() >= RX_BUFFER_SIZE {
+            return false;
+        }
+        rx_buffer.push_back(byte);
+        true
+    }
+    /// Wake up the tasks waiting for the bytes received by the interrupts
+    pub fn handle_softirq(&self) {
+        self.rx_wait_queue.wake_all();
+    }
+    /// Take the oldest received byte, `None` if there is none
+    pub fn read(&self) -> Option<u8> {
+        self.rx_buffer.lock().pop_front()
+    }
+    /// Send `bytes`, spinning while the transmit FIFO is full
+    pub fn write(&self, bytes: &[u8]) {
+        let _tx = self.tx_lock.lock();
+        for &byte in bytes {
+            while self.read_reg(TXDATA) & TXDATA_FULL != 0 {}
+            self.write_reg(TXDATA, byte as u32);
+        }
+    }
+}
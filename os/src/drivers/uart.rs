@@ -0,0 +1,160 @@
+//! Driver of the NS16550A UARTs, that of the machine and those on PCI,
+//! receiving input by interrupts. The DesignWare UARTs of the StarFive JH7110
+//! are 16550s with registers 4 bytes apart, read and written a word at a time.
+use super::pci::PCI_FUNCTIONS;
+use crate::board::UART_REG_SHIFT;
+use crate::monitor::Monitor;
+use crate::sync::SpinNoIrqLock;
+use crate::task::WaitQueue;
+use crate::trap::{raise_softirq, Softirq};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::ptr::{read_volatile, write_volatile};
+
+/// Compatible strings of the UART in the device tree
+pub const COMPATIBLE: &[&str] = &["ns16550a", "snps,dw-apb-uart"];
+
+/// Vendor and device of the 16550 of QEMU on PCI, `pci-serial`, in I/O BAR 0
+const PCI_SERIAL: (u16, u16) = (0x1b36, 0x0002);
+
+/// Receiver buffer register, read
+const RBR: usize = 0;
+/// Transmitter holding register, write
+const THR: usize = 0;
+/// Interrupt enable register
+const IER: usize = 1;
+/// FIFO control register, write
+const FCR: usize = 2;
+/// Line control register
+const LCR: usize = 3;
+/// Modem control register
+const MCR: usize = 4;
+/// Line status register
+const LSR: usize = 5;
+
+/// `IER`: interrupt when received data is available
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+/// `FCR`: enable the FIFOs
+const FCR_ENABLE: u8 = 1 << 0;
+/// `LCR`: 8 data bits, no parity and one stop bit
+const LCR_8N1: u8 = 3;
+/// `MCR`: auxiliary output 2, gating the interrupt line on a real 16550
+const MCR_OUT2: u8 = 1 << 3;
+/// `LSR`: received data is ready in `RBR`
+const LSR_DATA_READY: u8 = 1 << 0;
+/// `LSR`: `THR` is empty
+const LSR_THR_EMPTY: u8 = 1 << 5;
+/// `LSR`: nothing left to send, `THR` and the shift register being empty
+const LSR_TX_IDLE: u8 = 1 << 6;
+
+/// Number of received bytes kept until read, further ones are dropped
+const RX_BUFFER_SIZE: usize = 256;
+
+/// The UART at an address in kernel space
+pub struct Uart {
+    base: usize,
+    /// taken while writing, so that the bytes of a write are not interleaved
+    tx_lock: SpinNoIrqLock<()>,
+    /// received bytes not read yet
+    rx_buffer: SpinNoIrqLock<VecDeque<u8>>,
+    /// tasks waiting for input
+    pub rx_wait_queue: WaitQueue,
+    /// escape sequences of the debug monitor in the input
+    monitor: Monitor,
+}
+
+impl Uart {
+    /// The UART at `base`, to be set up by [`Uart::init`]
+    pub fn new(base: usize) -> Self {
+        Self {
+            base,
+            tx_lock: SpinNoIrqLock::new(()),
+            rx_buffer: SpinNoIrqLock::new(VecDeque::with_capacity(RX_BUFFER_SIZE)),
+            rx_wait_queue: WaitQueue::new(),
+            monitor: Monitor::new(),
+        }
+    }
+    fn read_reg(&self, reg: usize) -> u8 {
+        let addr = self.base + (reg << UART_REG_SHIFT);
+        unsafe {
+            if UART_REG_SHIFT == 0 {
+                read_volatile(addr as *const u8)
+            } else {
+                read_volatile(addr as *const u32) as u8
+            }
+        }
+    }
+    fn write_reg(&self, reg: usize, value: u8) {
+        let addr = self.base + (reg << UART_REG_SHIFT);
+        unsafe {
+            if UART_REG_SHIFT == 0 {
+                write_volatile(addr as *mut u8, value);
+            } else {
+                write_volatile(addr as *mut u32, value as u32);
+            }
+        }
+    }
+    /// Raise an interrupt when a byte is received, keeping the baud rate of
+    /// the firmware, if any
+    pub fn init(&self) {
+        // a DesignWare UART ignores `LCR` while busy, e.g. sending what the
+        // firmware printed for the kernel, and raises an interrupt instead
+        while self.read_reg(LSR) & LSR_TX_IDLE == 0 {}
+        self.write_reg(LCR, LCR_8N1);
+        self.write_reg(FCR, FCR_ENABLE);
+        self.write_reg(MCR, self.read_reg(MCR) | MCR_OUT2);
+        self.write_reg(IER, IER_RX_AVAILABLE);
+    }
+    /// Move the received bytes to the buffer, the tasks waiting for them are
+    /// woken up by [`Uart::handle_softirq`]
+    pub fn handle_irq(&self) {
+        let mut received = false;
+        while self.read_reg(LSR) & LSR_DATA_READY != 0 {
+            let byte = self.read_reg(RBR);
+            received |= self.receive(byte);
+        }
+        if received {
+            raise_softirq(Softirq::Tty);
+        }
+    }
+    /// Keep `byte` received for the readers unless it is for the debug
+    /// monitor, true if it is kept
+    fn receive(&self, byte: u8) -> bool {
+        // the buffer is not locked while a command of the monitor runs
+        let byte = match self.monitor.filter(byte) {
+            Some(byte) => byte,
+            None => return false,
+        };
+        let mut rx_buffer = self.rx_buffer.lock();
+        if rx_buffer.len() >= RX_BUFFER_SIZE {
+            return false;
+        }
+        rx_buffer.push_back(byte);
+        true
+    }
+    /// Wake up the tasks waiting for the bytes received by the interrupts
+    pub fn handle_softirq(&self) {
+        self.rx_wait_queue.wake_all();
+    }
+    /// Take the oldest received byte, `None` if there is none
+    pub fn read(&self) -> Option<u8> {
+        self.rx_buffer.lock().pop_front()
+    }
+    /// Send `bytes`, spinning while the transmitter is busy
+    pub fn write(&self, bytes: &[u8]) {
+        let _tx = self.tx_lock.lock();
+        for &byte in bytes {
+            while self.read_reg(LSR) & LSR_THR_EMPTY == 0 {}
+            self.write_reg(THR, byte);
+        }
+    }
+}
+
+/// Kernel addresses of the 16550s on PCI and their interrupt sources
+pub fn pci_uarts() -> Vec<(usize, usize)> {
+    PCI_FUNCTIONS
+        .iter()
+        .filter(|function| (function.vendor_id(), function.device_id()) == PCI_SERIAL)
+        .filter_map(|function| Some((function.bar(0)?, function.irq?)))
+        .collect()
+}
@@ -0,0 +1,380 @@
+//! Registers of the legacy virtio MMIO interface, shared by the drivers
+//! setting up their virtqueues by hand, a [`Transport`] reaching a device
+//! either there or on PCI, and a virtqueue with a page buffer for each
+//! descriptor
+use super::block::VirtioHal;
+use super::pci::PCI_FUNCTIONS;
+use super::virtio_pci::PciTransport;
+use crate::config::PAGE_SIZE;
+use crate::fdt::MACHINE;
+use crate::mm::{frame_alloc, FrameTracker, PhysAddr};
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ops::Deref;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+use virtio_drivers::Hal;
+
+/// Magic value "virt"
+pub const MAGIC_VALUE: usize = 0x000;
+/// Version of the interface, 1 for legacy
+pub const VERSION: usize = 0x004;
+/// Device type
+pub const DEVICE_ID: usize = 0x008;
+/// Features offered by the device
+pub const HOST_FEATURES: usize = 0x010;
+/// Features accepted by the driver
+pub const GUEST_FEATURES: usize = 0x020;
+/// Page size for `QUEUE_PFN`
+pub const GUEST_PAGE_SIZE: usize = 0x028;
+/// Virtqueue set up by the queue registers
+pub const QUEUE_SEL: usize = 0x030;
+/// Largest size of the queue
+pub const QUEUE_NUM_MAX: usize = 0x034;
+/// Size of the queue
+pub const QUEUE_NUM: usize = 0x038;
+/// Alignment of the used ring
+pub const QUEUE_ALIGN: usize = 0x03c;
+/// Page number of the queue, 0 to disable it
+pub const QUEUE_PFN: usize = 0x040;
+/// Index of the queue with new available buffers, write
+pub const QUEUE_NOTIFY: usize = 0x050;
+/// Causes of the interrupt
+pub const INTERRUPT_STATUS: usize = 0x060;
+/// Causes of the interrupt handled, write
+pub const INTERRUPT_ACK: usize = 0x064;
+/// Device status
+pub const STATUS: usize = 0x070;
+/// Configuration space of the device
+pub const CONFIG: usize = 0x100;
+
+const MAGIC: u32 = 0x7472_6976;
+/// `STATUS`: the device is noticed
+pub const STATUS_ACKNOWLEDGE: u32 = 1 << 0;
+/// `STATUS`: a driver for it is found
+pub const STATUS_DRIVER: u32 = 1 << 1;
+/// `STATUS`: the driver is ready
+pub const STATUS_DRIVER_OK: u32 = 1 << 2;
+/// `STATUS`: the features are accepted, needed from version 1 on
+const STATUS_FEATURES_OK: u32 = 1 << 3;
+
+/// Feature: the device follows version 1 of the specification, always
+/// accepted on PCI and never offered by legacy devices
+pub const FEATURE_VERSION_1: u64 = 1 << 32;
+
+/// Descriptor flag: the buffer continues with the `next` one
+pub const DESC_F_NEXT: u16 = 1 << 0;
+/// Descriptor flag: the device writes the buffer
+pub const DESC_F_WRITE: u16 = 1 << 1;
+/// Available ring flag: no interrupt for used buffers
+pub const AVAIL_F_NO_INTERRUPT: u16 = 1 << 0;
+/// Cause of the interrupt: the configuration space of the device changed
+pub const INTERRUPT_CONFIG: u32 = 1 << 1;
+
+/// Entry of the descriptor table
+#[repr(C)]
+pub struct Descriptor {
+    pub addr: u64,
+    pub len: u32,
+    pub flags: u16,
+    pub next: u16,
+}
+
+/// Read register `reg` of the device at `base`
+pub fn read_reg(base: usize, reg: usize) -> u32 {
+    unsafe { read_volatile((base + reg) as *const u32) }
+}
+
+/// Write `value` to register `reg` of the device at `base`
+pub fn write_reg(base: usize, reg: usize, value: u32) {
+    unsafe {
+        write_volatile((base + reg) as *mut u32, value);
+    }
+}
+
+/// Whether a legacy device of type `device_id` is at `base`
+pub fn is_device(base: usize, device_id: u32) -> bool {
+    read_reg(base, MAGIC_VALUE) == MAGIC
+        && read_reg(base, VERSION) == 1
+        && read_reg(base, DEVICE_ID) == device_id
+}
+
+/// How a virtio device is reached
+#[derive(Clone, Copy)]
+pub enum Transport {
+    /// legacy MMIO slot at a physical address, identity mapped in kernel space
+    Mmio(usize),
+    /// function on PCI
+    Pci(PciTransport),
+}
+
+impl Transport {
+    /// Whether the device is of type `device_id`
+    pub fn is_device(&self, device_id: u32) -> bool {
+        match self {
+            Self::Mmio(base) => is_device(*base, device_id),
+            Self::Pci(pci) => pci.device_type() == device_id,
+        }
+    }
+    /// Reset the device and accept those of `features` it offers, with
+    /// [`FEATURE_VERSION_1`] on PCI. Return the accepted ones, `None` if the
+    /// device refuses them.
+    pub fn begin_init(&self, features: u64) -> Option<u64> {
+        match self {
+            Self::Mmio(base) => {
+                write_reg(*base, STATUS, 0);
+                write_reg(*base, STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+                let accepted = read_reg(*base, HOST_FEATURES) as u64 & features;
+                write_reg(*base, GUEST_FEATURES, accepted as u32);
+                write_reg(*base, GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+                Some(accepted)
+            }
+            Self::Pci(pci) => {
+                pci.set_status(0);
+                pci.set_status((STATUS_ACKNOWLEDGE | STATUS_DRIVER) as u8);
+                let accepted = pci.device_features() & (features | FEATURE_VERSION_1);
+                pci.set_driver_features(accepted);
+                let status = STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK;
+                pci.set_status(status as u8);
+                let ok = accepted & FEATURE_VERSION_1 != 0
+                    && pci.status() & STATUS_FEATURES_OK as u8 != 0;
+                ok.then(|| accepted)
+            }
+        }
+    }
+    /// Tell the device the driver is ready, once its queues are set up
+    pub fn finish_init(&self) {
+        let status = STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK;
+        match self {
+            Self::Mmio(base) => write_reg(*base, STATUS, status),
+            Self::Pci(pci) => pci.set_status((status | STATUS_FEATURES_OK) as u8),
+        }
+    }
+    /// Acknowledge the interrupt of the device, returning its causes
+    pub fn ack_interrupt(&self) -> u32 {
+        match self {
+            Self::Mmio(base) => {
+                let status = read_reg(*base, INTERRUPT_STATUS);
+                write_reg(*base, INTERRUPT_ACK, status);
+                status
+            }
+            Self::Pci(pci) => pci.ack_interrupt(),
+        }
+    }
+    /// Address of the byte at `offset` of the configuration space of the device
+    fn config(&self, offset: usize) -> usize {
+        match self {
+            Self::Mmio(base) => base + CONFIG + offset,
+            Self::Pci(pci) => pci.config(offset),
+        }
+    }
+    /// Read the byte at `offset` of the configuration space of the device
+    pub fn read_config_u8(&self, offset: usize) -> u8 {
+        unsafe { read_volatile(self.config(offset) as *const u8) }
+    }
+    /// Read the word at `offset` of the configuration space of the device
+    pub fn read_config_u32(&self, offset: usize) -> u32 {
+        unsafe { read_volatile(self.config(offset) as *const u32) }
+    }
+    /// Write the word at `offset` of the configuration space of the device
+    pub fn write_config_u32(&self, offset: usize, value: u32) {
+        unsafe {
+            write_volatile(self.config(offset) as *mut u32, value);
+        }
+    }
+    /// Largest size of queue `index`
+    fn queue_size_max(&self, index: u32) -> u32 {
+        match self {
+            Self::Mmio(base) => {
+                write_reg(*base, QUEUE_SEL, index);
+                read_reg(*base, QUEUE_NUM_MAX)
+            }
+            Self::Pci(pci) => pci.queue_size_max(index),
+        }
+    }
+    /// Set up queue `index` of `size` descriptors in the legacy layout at
+    /// `base`, returning the address notifying it
+    fn set_queue(&self, index: u32, size: u16, base: usize) -> usize {
+        match self {
+            Self::Mmio(mmio) => {
+                write_reg(*mmio, QUEUE_SEL, index);
+                write_reg(*mmio, QUEUE_NUM, size as u32);
+                write_reg(*mmio, QUEUE_ALIGN, PAGE_SIZE as u32);
+                write_reg(*mmio, QUEUE_PFN, (base / PAGE_SIZE) as u32);
+                mmio + QUEUE_NOTIFY
+            }
+            Self::Pci(pci) => {
+                let avail = base + size as usize * size_of::<Descriptor>();
+                pci.set_queue(index, size, base, avail, base + PAGE_SIZE)
+            }
+        }
+    }
+    /// Notify queue `index` at `address`, as returned by `set_queue`
+    fn notify(&self, address: usize, index: u32) {
+        unsafe {
+            match self {
+                Self::Mmio(_) => write_volatile(address as *mut u32, index),
+                Self::Pci(_) => write_volatile(address as *mut u16, index as u16),
+            }
+        }
+    }
+}
+
+/// A virtio device found on the machine, with its interrupt source
+pub struct VirtioSlot<T> {
+    pub device: T,
+    pub irq: usize,
+}
+
+impl<T> Deref for VirtioSlot<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.device
+    }
+}
+
+/// The devices `probe` finds in the virtio MMIO slots of the machine, in the
+/// order of their addresses
+pub fn probe_slots<T>(probe: fn(usize) -> Option<T>) -> impl Iterator<Item = VirtioSlot<T>> {
+    MACHINE
+        .virtio
+        .iter()
+        .filter_map(move |&(base, irq)| probe(base).map(|device| VirtioSlot { device, irq }))
+}
+
+/// The devices `probe` finds on either transport: the virtio functions on PCI
+/// in the order of their numbers, then the virtio MMIO slots
+pub fn probe_transports<T>(
+    probe: fn(Transport) -> Option<T>,
+) -> impl Iterator<Item = VirtioSlot<T>> {
+    let pci = PCI_FUNCTIONS.iter().filter_map(|function| {
+        let transport = PciTransport::new(function)?;
+        Some((Transport::Pci(transport), function.irq?))
+    });
+    let mmio = MACHINE
+        .virtio
+        .iter()
+        .map(|&(base, irq)| (Transport::Mmio(base), irq));
+    pci.chain(mmio).filter_map(move |(transport, irq)| {
+        probe(transport).map(|device| VirtioSlot { device, irq })
+    })
+}
+
+/// A virtqueue in the legacy layout: the descriptor table and the available
+/// ring on the first page, the used ring on the second one, with a page
+/// buffer for each descriptor
+pub struct VirtQueue {
+    transport: Transport,
+    index: u32,
+    /// address notifying the queue
+    notify: usize,
+    /// physical address, identity mapped
+    base: usize,
+    /// number of descriptors
+    size: u16,
+    /// buffer of each descriptor
+    buffers: Vec<FrameTracker>,
+    /// descriptors not given to the device
+    pub free: Vec<u16>,
+    /// next index of the available ring
+    avail_idx: u16,
+    /// next index of the used ring to take
+    last_used: u16,
+}
+
+impl VirtQueue {
+    /// Set up queue `index` of `size` descriptors of the device reached by `transport`
+    pub fn setup(transport: &Transport, index: u32, size: u16) -> Self {
+        assert!(
+            size as usize * (size_of::<Descriptor>() + 2) + 4 <= PAGE_SIZE,
+            "the available ring does not fit in the first page"
+        );
+        assert!(transport.queue_size_max(index) >= size as u32);
+        let base = VirtioHal::dma_alloc(2);
+        Self {
+            transport: *transport,
+            index,
+            notify: transport.set_queue(index, size, base),
+            base,
+            size,
+            buffers: (0..size)
+                .map(|_| frame_alloc().expect("out of frames for a virtio device"))
+                .collect(),
+            free: (0..size).collect(),
+            avail_idx: 0,
+            last_used: 0,
+        }
+    }
+    fn avail(&self, offset: usize) -> *mut u16 {
+        (self.base + self.size as usize * size_of::<Descriptor>() + offset) as *mut u16
+    }
+    fn used(&self, offset: usize) -> *mut u16 {
+        (self.base + PAGE_SIZE + offset) as *mut u16
+    }
+    /// Ask the device not to interrupt for the buffers it uses
+    pub fn disable_interrupts(&mut self) {
+        unsafe {
+            write_volatile(self.avail(0), AVAIL_F_NO_INTERRUPT);
+        }
+    }
+    /// The buffer of descriptor `id`
+    pub fn buffer(&self, id: u16) -> &'static mut [u8] {
+        self.buffers[id as usize].ppn.get_bytes_array()
+    }
+    /// Give the buffer of descriptor `id` to the device, `len` bytes of it
+    pub fn push(&mut self, id: u16, len: usize, flags: u16) {
+        self.push_chain(&[(id, len, flags)]);
+    }
+    /// Give the buffers of the descriptors of `chain` to the device as one
+    /// request, each with its length and flags. The device returns it by the
+    /// first descriptor.
+    pub fn push_chain(&mut self, chain: &[(u16, usize, u16)]) {
+        for (i, &(id, len, flags)) in chain.iter().enumerate() {
+            let pa: PhysAddr = self.buffers[id as usize].ppn.into();
+            let (flags, next) = match chain.get(i + 1) {
+                Some(&(next, _, _)) => (flags | DESC_F_NEXT, next),
+                None => (flags, 0),
+            };
+            unsafe {
+                write_volatile(
+                    (self.base as *mut Descriptor).add(id as usize),
+                    Descriptor {
+                        addr: pa.0 as u64,
+                        len: len as u32,
+                        flags,
+                        next,
+                    },
+                );
+            }
+        }
+        unsafe {
+            write_volatile(
+                self.avail(4 + 2 * (self.avail_idx % self.size) as usize),
+                chain[0].0,
+            );
+            // the device sees the entry before the index
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            write_volatile(self.avail(2), self.avail_idx);
+        }
+    }
+    /// Tell the device about the buffers given to it
+    pub fn notify(&self) {
+        self.transport.notify(self.notify, self.index);
+    }
+    /// Take a buffer used by the device, its descriptor and the length written
+    pub fn pop_used(&mut self) -> Option<(u16, usize)> {
+        fence(Ordering::SeqCst);
+        if unsafe { read_volatile(self.used(2)) } == self.last_used {
+            return None;
+        }
+        let elem = self.used(4 + 8 * (self.last_used % self.size) as usize) as *const u32;
+        self.last_used = self.last_used.wrapping_add(1);
+        unsafe {
+            Some((
+                read_volatile(elem) as u16,
+                read_volatile(elem.add(1)) as usize,
+            ))
+        }
+    }
+}
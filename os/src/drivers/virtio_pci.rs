@@ -0,0 +1,188 @@
+//! The virtio PCI transport of version 1, through the structures the
+//! capabilities of a function point to in its memory BARs. Interrupts are
+//! raised on the pin of the function, MSI-X is not used.
+use super::pci::PciFunction;
+use core::ptr::{read_volatile, write_volatile};
+
+const VENDOR_VIRTIO: u16 = 0x1af4;
+/// Device ids of transitional devices, whose subsystem id is the device type
+const TRANSITIONAL_IDS: core::ops::Range<u16> = 0x1000..0x1040;
+/// Device id of the device type 0, the others following it
+const MODERN_ID_BASE: u16 = 0x1040;
+
+/// Capability id of the vendor specific ones
+const CAP_VENDOR: u8 = 0x09;
+/// Offsets in a virtio capability: the structure it describes, the BAR it
+/// is in, its offset in the BAR and, for the notification one, the multiplier
+/// of the notification offsets of the queues
+const CAP_CFG_TYPE: usize = 3;
+const CAP_BAR: usize = 4;
+const CAP_OFFSET: usize = 8;
+const CAP_NOTIFY_MULTIPLIER: usize = 16;
+/// `CAP_CFG_TYPE` of the common configuration
+const CFG_COMMON: u8 = 1;
+/// `CAP_CFG_TYPE` of the notification area
+const CFG_NOTIFY: u8 = 2;
+/// `CAP_CFG_TYPE` of the interrupt status
+const CFG_ISR: u8 = 3;
+/// `CAP_CFG_TYPE` of the configuration space of the device
+const CFG_DEVICE: u8 = 4;
+
+/// Common configuration: features of the device, as words selected by
+/// `DEVICE_FEATURE_SELECT`
+const DEVICE_FEATURE_SELECT: usize = 0x00;
+const DEVICE_FEATURE: usize = 0x04;
+/// Common configuration: features accepted by the driver, as words selected by
+/// `DRIVER_FEATURE_SELECT`
+const DRIVER_FEATURE_SELECT: usize = 0x08;
+const DRIVER_FEATURE: usize = 0x0c;
+const DEVICE_STATUS: usize = 0x14;
+/// Common configuration: queue set up by the queue registers
+const QUEUE_SELECT: usize = 0x16;
+/// Common configuration: size of the queue, its largest one at reset
+const QUEUE_SIZE: usize = 0x18;
+const QUEUE_ENABLE: usize = 0x1c;
+/// Common configuration: offset of the notification address of the queue,
+/// in units of the notification multiplier
+const QUEUE_NOTIFY_OFF: usize = 0x1e;
+/// Common configuration: physical addresses of the descriptor table, the
+/// available ring and the used ring
+const QUEUE_DESC: usize = 0x20;
+const QUEUE_DRIVER: usize = 0x28;
+const QUEUE_DEVICE: usize = 0x30;
+
+/// A virtio device on PCI, by the kernel addresses of its structures
+#[derive(Clone, Copy)]
+pub struct PciTransport {
+    device_type: u32,
+    common: usize,
+    notify: usize,
+    notify_multiplier: usize,
+    isr: usize,
+    config: usize,
+}
+
+/// Write a 64-bit field of the common configuration, as two words
+fn write_u64(address: usize, value: u64) {
+    write(address, value as u32);
+    write(address + 4, (value >> 32) as u32);
+}
+
+fn read<T>(address: usize) -> T {
+    unsafe { read_volatile(address as *const T) }
+}
+
+fn write<T>(address: usize, value: T) {
+    unsafe {
+        write_volatile(address as *mut T, value);
+    }
+}
+
+impl PciTransport {
+    /// The transport of `function`, `None` if it is not a virtio device of
+    /// version 1 with its structures in assigned BARs
+    pub fn new(function: &PciFunction) -> Option<Self> {
+        if function.vendor_id() != VENDOR_VIRTIO {
+            return None;
+        }
+        let id = function.device_id();
+        let device_type = if TRANSITIONAL_IDS.contains(&id) {
+            function.subsystem_id()
+        } else {
+            id.checked_sub(MODERN_ID_BASE)?
+        };
+        let (mut common, mut notify, mut isr, mut config) = (None, None, None, None);
+        let mut notify_multiplier = 0;
+        for cap in function.capabilities() {
+            if function.read_u8(cap) != CAP_VENDOR {
+                continue;
+            }
+            let address = function
+                .bar(function.read_u8(cap + CAP_BAR) as usize)
+                .map(|bar| bar + function.read_u32(cap + CAP_OFFSET) as usize);
+            // the first capability of each type is the preferred one
+            match function.read_u8(cap + CAP_CFG_TYPE) {
+                CFG_COMMON => common = common.or(address),
+                CFG_NOTIFY if notify.is_none() => {
+                    notify = address;
+                    notify_multiplier = function.read_u32(cap + CAP_NOTIFY_MULTIPLIER) as usize;
+                }
+                CFG_ISR => isr = isr.or(address),
+                CFG_DEVICE => config = config.or(address),
+                _ => {}
+            }
+        }
+        Some(Self {
+            device_type: device_type as u32,
+            common: common?,
+            notify: notify?,
+            notify_multiplier,
+            isr: isr?,
+            config: config?,
+        })
+    }
+    /// Device type, as in the `DEVICE_ID` register of the MMIO transport
+    pub fn device_type(&self) -> u32 {
+        self.device_type
+    }
+    /// Device status
+    pub fn status(&self) -> u8 {
+        read(self.common + DEVICE_STATUS)
+    }
+    /// Write the device status, 0 to reset the device
+    pub fn set_status(&self, status: u8) {
+        write(self.common + DEVICE_STATUS, status);
+        // a reset is complete once the status reads 0
+        if status == 0 {
+            while self.status() != 0 {}
+        }
+    }
+    /// Features offered by the device
+    pub fn device_features(&self) -> u64 {
+        write(self.common + DEVICE_FEATURE_SELECT, 0u32);
+        let low: u32 = read(self.common + DEVICE_FEATURE);
+        write(self.common + DEVICE_FEATURE_SELECT, 1u32);
+        let high: u32 = read(self.common + DEVICE_FEATURE);
+        (high as u64) << 32 | low as u64
+    }
+    /// Accept `features`
+    pub fn set_driver_features(&self, features: u64) {
+        write(self.common + DRIVER_FEATURE_SELECT, 0u32);
+        write(self.common + DRIVER_FEATURE, features as u32);
+        write(self.common + DRIVER_FEATURE_SELECT, 1u32);
+        write(self.common + DRIVER_FEATURE, (features >> 32) as u32);
+    }
+    /// Largest size of queue `index`, 0 if there is no such queue
+    pub fn queue_size_max(&self, index: u32) -> u32 {
+        write(self.common + QUEUE_SELECT, index as u16);
+        read::<u16>(self.common + QUEUE_SIZE) as u32
+    }
+    /// Set up queue `index` of `size` descriptors at physical addresses
+    /// `desc`, `driver` and `device` for its parts, and return the address
+    /// notifying it
+    pub fn set_queue(
+        &self,
+        index: u32,
+        size: u16,
+        desc: usize,
+        driver: usize,
+        device: usize,
+    ) -> usize {
+        write(self.common + QUEUE_SELECT, index as u16);
+        write(self.common + QUEUE_SIZE, size);
+        write_u64(self.common + QUEUE_DESC, desc as u64);
+        write_u64(self.common + QUEUE_DRIVER, driver as u64);
+        write_u64(self.common + QUEUE_DEVICE, device as u64);
+        write(self.common + QUEUE_ENABLE, 1u16);
+        let offset: u16 = read(self.common + QUEUE_NOTIFY_OFF);
+        self.notify + offset as usize * self.notify_multiplier
+    }
+    /// Read and clear the causes of the interrupt
+    pub fn ack_interrupt(&self) -> u32 {
+        read::<u8>(self.isr) as u32
+    }
+    /// Address of the byte at `offset` of the configuration space of the device
+    pub fn config(&self, offset: usize) -> usize {
+        self.config + offset
+    }
+}
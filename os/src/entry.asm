@@ -1,12 +1,41 @@
     .section .text.entry
     .globl _start
 _start:
-    la sp, boot_stack_top
+    # a0: hart id, kept in tp while in the kernel, a1: the device tree
+    mv tp, a0
+    mv s1, a1
+    call set_boot_stack
+    # move the kernel to a random place, see src/kaslr.rs, and continue there
+    mv a0, s1
+    call kaslr_relocate
+    mv a2, a0
+    lla t0, 1f
+    add t0, t0, a2
+    jr t0
+1:
+    call set_boot_stack
+    mv a0, tp
+    mv a1, s1
     call rust_main
 
+    .globl _start_secondary
+_start_secondary:
+    mv tp, a0
+    call set_boot_stack
+    call rust_main_secondary
+
+    # every hart gets its own 64 KiB boot stack
+set_boot_stack:
+    addi t0, tp, 1
+    slli t0, t0, 16
+    la sp, boot_stack_lower_bound
+    add sp, sp, t0
+    ret
+
     .section .bss.stack
     .globl boot_stack_lower_bound
 boot_stack_lower_bound:
-    .space 4096 * 16
+    # 4096 * 16 bytes for each of MAX_HARTS harts
+    .space 4096 * 16 * 5
     .globl boot_stack_top
-boot_stack_top:
\ No newline at end of file
+boot_stack_top:
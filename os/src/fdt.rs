@@ -0,0 +1,548 @@
+//! Hardware discovery from the flattened device tree the firmware passes to
+//! the boot hart, so that the same kernel runs on differently configured
+//! machines of a board. What the tree does not describe, or all of it if there
+//! is no tree, is taken from the constants of the board.
+//!
+//! The tree is parsed once at boot, before the frame allocator may reuse the
+//! memory it lies in, and only what the kernel uses is kept in [`MACHINE`].
+use crate::board::{
+    plic_context, MMIO, UART_IRQ, VIRTIO_MMIO, VIRT_PCI, VIRT_PLIC, VIRT_RTC, VIRT_UART,
+};
+use crate::config::{MAX_HARTS, MEMORY_END, PAGE_SIZE};
+use crate::drivers::uart;
+use crate::sync::Once;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::str::from_utf8;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+/// Largest tree accepted, to reject garbage taken for one
+const FDT_MAX_SIZE: usize = 0x10_0000;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+
+/// Cause of the supervisor external interrupt, the context of a hart at the
+/// PLIC routing to it
+const IRQ_S_EXT: u32 = 9;
+
+/// Compatible strings of the devices whose registers are mapped
+const PLIC_COMPATIBLE: &[&str] = &["riscv,plic0", "sifive,plic-1.0.0"];
+const RTC_COMPATIBLE: &str = "google,goldfish-rtc";
+const VIRTIO_COMPATIBLE: &str = "virtio,mmio";
+const PCI_COMPATIBLE: &str = "pci-host-ecam-generic";
+/// Other devices used at fixed addresses of the board: the test device
+/// powering off QEMU, the SPI controllers and the SD controllers
+const OTHER_COMPATIBLE: &[&str] = &[
+    "sifive,test0",
+    "sifive,spi0",
+    "snps,dw-mshc",
+    "starfive,jh7110-mmc",
+    "starfive,jh7110-sdio",
+];
+
+/// `ranges` of a PCI host bridge: the space of a window in the first cell
+const PCI_SPACE: u32 = 3 << 24;
+/// `PCI_SPACE`: I/O space
+const PCI_SPACE_IO: u32 = 1 << 24;
+/// `PCI_SPACE`: 32-bit memory space
+const PCI_SPACE_MEM32: u32 = 2 << 24;
+
+/// A PCI host bridge with its configuration space memory-mapped (ECAM)
+#[derive(Clone, Copy)]
+pub struct PciHost {
+    /// physical address of the configuration space of bus 0
+    pub ecam: usize,
+    /// physical address of the window of 32-bit memory space BARs are
+    /// assigned from
+    pub mem: usize,
+    /// address of the window on the bus
+    pub mem_pci: usize,
+    /// size of the window
+    pub mem_size: usize,
+    /// physical address of the window of I/O space BARs are assigned from,
+    /// the I/O space being memory-mapped by the host bridge
+    pub io: usize,
+    /// address of the I/O window on the bus
+    pub io_pci: usize,
+    /// size of the I/O window, 0 if there is none
+    pub io_size: usize,
+    /// interrupt sources at the PLIC of the pins INTA to INTD of the device in
+    /// slot 0, rotated by one for each next slot
+    pub irqs: [usize; 4],
+}
+
+/// The hardware the kernel runs on
+pub struct Machine {
+    /// end of the physical memory the kernel is loaded in
+    pub memory_end: usize,
+    /// ids of the harts able to run the kernel, with an MMU
+    pub harts: Vec<usize>,
+    /// base of the PLIC
+    pub plic: usize,
+    /// interrupt context of the supervisor mode of each hart at the PLIC,
+    /// those of the board if empty
+    plic_contexts: Vec<(usize, usize)>,
+    /// bases of the UARTs and their interrupt sources, in the order of their
+    /// addresses, at least one
+    pub uarts: Vec<(usize, usize)>,
+    /// base of the RTC, `None` if there is none
+    pub rtc: Option<usize>,
+    /// virtio MMIO slots and their interrupt sources, in the order of their
+    /// addresses
+    pub virtio: Vec<(usize, usize)>,
+    /// PCI host bridge, `None` if there is none
+    pub pci: Option<PciHost>,
+    /// regions of memory-mapped registers, to be mapped in kernel space
+    pub mmio: Vec<(usize, usize)>,
+    /// command line given by the bootloader, e.g. `-append` of QEMU
+    pub bootargs: String,
+}
+
+impl Machine {
+    /// The machine described by the constants of the board
+    fn from_board() -> Self {
+        Self {
+            memory_end: MEMORY_END,
+            harts: (0..MAX_HARTS).collect(),
+            plic: VIRT_PLIC,
+            plic_contexts: Vec::new(),
+            uarts: vec![(VIRT_UART, UART_IRQ)],
+            rtc: VIRT_RTC,
+            virtio: VIRTIO_MMIO.to_vec(),
+            pci: VIRT_PCI,
+            mmio: MMIO.to_vec(),
+            bootargs: String::new(),
+        }
+    }
+    /// Interrupt context of the supervisor mode of `hart` at the PLIC
+    pub fn plic_context(&self, hart: usize) -> usize {
+        if self.plic_contexts.is_empty() {
+            return plic_context(hart);
+        }
+        self.plic_contexts
+            .iter()
+            .find(|&&(id, _)| id == hart)
+            .map(|&(_, context)| context)
+            .unwrap_or_else(|| panic!("no PLIC context for hart {}", hart))
+    }
+}
+
+/// The hardware the kernel runs on, set up by [`init`]
+pub static MACHINE: Once<Machine> = Once::new();
+
+/// End of the physical memory, that of the board before [`init`]
+pub fn memory_end() -> usize {
+    MACHINE
+        .get()
+        .map_or(MEMORY_END, |machine| machine.memory_end)
+}
+
+/// Discover the machine from the device tree at physical address `dtb`, 0 if
+/// the firmware passed none
+pub fn init(dtb: usize) {
+    let tree = unsafe { blob(dtb) }.and_then(parse);
+    let machine = match tree.as_ref().and_then(discover) {
+        Some(machine) => {
+            info!(
+                "device tree: memory up to {:#x}, {} harts, {} virtio slots",
+                machine.memory_end,
+                machine.harts.len(),
+                machine.virtio.len()
+            );
+            machine
+        }
+        None => {
+            warn!("no usable device tree, using the devices of the board");
+            Machine::from_board()
+        }
+    };
+    MACHINE.call_once(|| machine);
+}
+
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// The tree at physical address `dtb`, checked by its header
+unsafe fn blob(dtb: usize) -> Option<&'static [u8]> {
+    if dtb == 0 || dtb % 8 != 0 {
+        return None;
+    }
+    let header = core::slice::from_raw_parts(dtb as *const u8, 8);
+    let size = be32(header, 4)? as usize;
+    if be32(header, 0)? != FDT_MAGIC || size > FDT_MAX_SIZE {
+        return None;
+    }
+    Some(core::slice::from_raw_parts(dtb as *const u8, size))
+}
+
+/// A node of the tree, borrowing its properties from the blob
+struct Node<'a> {
+    name: &'a str,
+    props: Vec<(&'a str, &'a [u8])>,
+    children: Vec<Node<'a>>,
+}
+
+impl<'a> Node<'a> {
+    fn prop(&self, name: &str) -> Option<&'a [u8]> {
+        self.props
+            .iter()
+            .find(|(prop, _)| *prop == name)
+            .map(|(_, value)| *value)
+    }
+    fn u32_prop(&self, name: &str) -> Option<u32> {
+        be32(self.prop(name)?, 0)
+    }
+    /// The strings of a property holding a list of them
+    fn strings(&self, name: &str) -> impl Iterator<Item = &'a [u8]> {
+        let value = self.prop(name).unwrap_or(&[]);
+        value.split(|&byte| byte == 0).filter(|s| !s.is_empty())
+    }
+    fn is_compatible(&self, compatible: &[&str]) -> bool {
+        self.strings("compatible")
+            .any(|s| compatible.iter().any(|c| c.as_bytes() == s))
+    }
+    /// Whether the device is present, `status` being absent or "okay"
+    fn is_enabled(&self) -> bool {
+        self.strings("status")
+            .next()
+            .map_or(true, |status| status == b"okay" || status == b"ok")
+    }
+    /// `#address-cells` and `#size-cells` of the children
+    fn cells(&self) -> (usize, usize) {
+        (
+            self.u32_prop("#address-cells").unwrap_or(2) as usize,
+            self.u32_prop("#size-cells").unwrap_or(1) as usize,
+        )
+    }
+    /// Address and size of each region of `reg`, in the `cells` of the parent
+    fn reg(&self, (address_cells, size_cells): (usize, usize)) -> Vec<(usize, usize)> {
+        let value = self.prop("reg").unwrap_or(&[]);
+        let read = |cells: &[u8]| {
+            cells
+                .chunks(4)
+                .fold(0, |n, cell| n << 32 | be32(cell, 0).unwrap() as usize)
+        };
+        let entry = (address_cells + size_cells) * 4;
+        if entry == 0 {
+            return Vec::new();
+        }
+        value
+            .chunks_exact(entry)
+            .map(|e| (read(&e[..address_cells * 4]), read(&e[address_cells * 4..])))
+            .collect()
+    }
+    /// First interrupt source of the device at its interrupt controller
+    fn irq(&self) -> Option<usize> {
+        self.u32_prop("interrupts").map(|irq| irq as usize)
+    }
+}
+
+/// Reads the structure block of a tree
+struct Cursor<'a> {
+    structs: &'a [u8],
+    strings: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn u32(&mut self) -> Option<u32> {
+        let value = be32(self.structs, self.pos)?;
+        self.pos += 4;
+        Some(value)
+    }
+    /// `len` bytes, up to the next 4-byte boundary
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.structs.get(self.pos..self.pos + len)?;
+        self.pos += (len + 3) & !3;
+        Some(bytes)
+    }
+    /// A nul-terminated string in `bytes` at `offset`
+    fn str_at(bytes: &'a [u8], offset: usize) -> Option<&'a str> {
+        let bytes = bytes.get(offset..)?;
+        let len = bytes.iter().position(|&byte| byte == 0)?;
+        from_utf8(&bytes[..len]).ok()
+    }
+    /// The node whose `FDT_BEGIN_NODE` token was just read
+    fn node(&mut self) -> Option<Node<'a>> {
+        let name = Self::str_at(self.structs, self.pos)?;
+        self.bytes(name.len() + 1)?;
+        let mut node = Node {
+            name,
+            props: Vec::new(),
+            children: Vec::new(),
+        };
+        loop {
+            match self.u32()? {
+                FDT_BEGIN_NODE => node.children.push(self.node()?),
+                FDT_PROP => {
+                    let len = self.u32()? as usize;
+                    let name = Self::str_at(self.strings, self.u32()? as usize)?;
+                    node.props.push((name, self.bytes(len)?));
+                }
+                FDT_NOP => {}
+                FDT_END_NODE => return Some(node),
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// The root node of the tree in `blob`, `None` if it is malformed
+fn parse(blob: &[u8]) -> Option<Node> {
+    let structs = be32(blob, 8)? as usize;
+    let strings = be32(blob, 12)? as usize;
+    let mut cursor = Cursor {
+        structs: blob.get(structs..)?,
+        strings: blob.get(strings..)?,
+        pos: 0,
+    };
+    loop {
+        match cursor.u32()? {
+            FDT_NOP => {}
+            FDT_BEGIN_NODE => return cursor.node(),
+            _ => return None,
+        }
+    }
+}
+
+/// Call `f` with the depth and name of each node of the tree at physical
+/// address `dtb`, the root at depth 0 with an empty name, and the name and
+/// value of each of its properties, in the order of the tree, without
+/// allocating. For the boot hart before the heap is set up. Returns the size
+/// of the tree, `None` if there is no usable one.
+pub fn scan(dtb: usize, mut f: impl FnMut(usize, &str, &str, &[u8])) -> Option<usize> {
+    let blob = unsafe { blob(dtb) }?;
+    let mut cursor = Cursor {
+        structs: blob.get(be32(blob, 8)? as usize..)?,
+        strings: blob.get(be32(blob, 12)? as usize..)?,
+        pos: 0,
+    };
+    // the properties of a node come before its children, so only the name of
+    // the innermost node is needed
+    let mut depth = 0;
+    let mut name = "";
+    loop {
+        match cursor.u32()? {
+            FDT_BEGIN_NODE => {
+                name = Cursor::str_at(cursor.structs, cursor.pos)?;
+                cursor.bytes(name.len() + 1)?;
+                depth += 1;
+            }
+            FDT_PROP => {
+                let len = cursor.u32()? as usize;
+                let prop = Cursor::str_at(cursor.strings, cursor.u32()? as usize)?;
+                f(depth.checked_sub(1)?, name, prop, cursor.bytes(len)?);
+            }
+            FDT_NOP => {}
+            FDT_END_NODE => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    return Some(blob.len());
+                }
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Call `f` on `node` and its descendants, with the cells of their parents
+fn visit<'a>(
+    node: &Node<'a>,
+    cells: (usize, usize),
+    f: &mut impl FnMut(&Node<'a>, (usize, usize)),
+) {
+    f(node, cells);
+    for child in node.children.iter() {
+        visit(child, node.cells(), f);
+    }
+}
+
+/// The machine the tree of `root` describes, `None` if it lacks the PLIC or
+/// the UART. The harts and the memory of the board are kept if it lacks them.
+fn discover(root: &Node) -> Option<Machine> {
+    extern "C" {
+        fn skernel();
+    }
+    let kernel = skernel as usize;
+    let mut memory_end = None;
+    let mut harts = Vec::new();
+    // phandles of the interrupt controllers of the harts, by hart id
+    let mut hart_intcs = Vec::new();
+    // phandle of the interrupt controller of each context of the PLIC, 0 for
+    // those of machine mode
+    let mut plic_intcs = Vec::new();
+    let mut plic = None;
+    let mut uarts = Vec::new();
+    let mut rtc = None;
+    let mut virtio = Vec::new();
+    let mut pci = None;
+    let mut mmio = Vec::new();
+    visit(root, root.cells(), &mut |node, cells| {
+        if !node.is_enabled() {
+            return;
+        }
+        let reg = node.reg(cells);
+        if node.strings("device_type").any(|s| s == b"memory") {
+            for &(start, size) in reg.iter() {
+                if (start..start + size).contains(&kernel) {
+                    memory_end = Some(start + size);
+                }
+            }
+        } else if node.strings("device_type").any(|s| s == b"cpu") {
+            // harts without an MMU, like the monitor hart of a SiFive SoC, are skipped
+            let mmu = node.strings("mmu-type").next();
+            if let (Some(&(hart, _)), Some(mmu)) = (reg.first(), mmu) {
+                if mmu != b"riscv,none" {
+                    harts.push(hart);
+                }
+                let intc = node
+                    .children
+                    .iter()
+                    .find_map(|child| child.u32_prop("phandle"));
+                if let Some(phandle) = intc {
+                    hart_intcs.push((phandle, hart));
+                }
+            }
+        } else if node.is_compatible(PLIC_COMPATIBLE) {
+            plic = reg.first().map(|&(base, _)| base);
+            // a pair of cells for each context: the interrupt controller of a
+            // hart and the cause raised there
+            let contexts = node.prop("interrupts-extended").unwrap_or(&[]);
+            plic_intcs = contexts
+                .chunks_exact(8)
+                .map(|pair| match be32(pair, 4) {
+                    Some(IRQ_S_EXT) => be32(pair, 0).unwrap(),
+                    _ => 0,
+                })
+                .collect();
+            mmio.extend(reg.iter().copied());
+        } else if node.is_compatible(uart::COMPATIBLE) {
+            if let (Some(&(base, _)), Some(irq)) = (reg.first(), node.irq()) {
+                uarts.push((base, irq));
+                mmio.extend(reg.iter().copied());
+            }
+        } else if node.is_compatible(&[RTC_COMPATIBLE]) {
+            rtc = reg.first().map(|&(base, _)| base);
+            mmio.extend(reg.iter().copied());
+        } else if node.is_compatible(&[VIRTIO_COMPATIBLE]) {
+            if let (Some(&(base, _)), Some(irq)) = (reg.first(), node.irq()) {
+                virtio.push((base, irq));
+                mmio.extend(reg.iter().copied());
+            }
+        } else if node.is_compatible(&[PCI_COMPATIBLE]) {
+            if let Some(&(ecam, _)) = reg.first() {
+                pci = pci_host(node, cells, ecam);
+            }
+        } else if node.is_compatible(OTHER_COMPATIBLE) {
+            mmio.extend(reg.iter().copied());
+        }
+    });
+    if harts.is_empty() {
+        harts = (0..MAX_HARTS).collect();
+    }
+    harts.sort_unstable();
+    let plic_contexts = plic_intcs
+        .iter()
+        .enumerate()
+        .filter(|&(_, &phandle)| phandle != 0)
+        .filter_map(|(context, phandle)| {
+            hart_intcs
+                .iter()
+                .find(|(intc, _)| intc == phandle)
+                .map(|&(_, hart)| (hart, context))
+        })
+        .collect();
+    if uarts.is_empty() {
+        return None;
+    }
+    // nodes are not in the order of their addresses, e.g. on QEMU
+    virtio.sort_unstable();
+    uarts.sort_unstable();
+    let bootargs = root
+        .children
+        .iter()
+        .find(|node| node.name == "chosen")
+        .and_then(|chosen| chosen.strings("bootargs").next())
+        .and_then(|bootargs| from_utf8(bootargs).ok())
+        .map_or_else(String::new, String::from);
+    Some(Machine {
+        memory_end: memory_end.unwrap_or(MEMORY_END),
+        harts,
+        plic: plic?,
+        plic_contexts,
+        uarts,
+        rtc,
+        virtio,
+        pci,
+        mmio: merge_pages(mmio),
+        bootargs,
+    })
+}
+
+/// The PCI host bridge of `node` with its configuration space at `ecam`,
+/// `None` without a 32-bit memory window. The interrupt controller of
+/// `interrupt-map` is taken to be the PLIC, with one cell for a source.
+fn pci_host(node: &Node, parent_cells: (usize, usize), ecam: usize) -> Option<PciHost> {
+    let (address_cells, size_cells) = node.cells();
+    let parent = address_cells + parent_cells.0;
+    let ranges = node.prop("ranges").unwrap_or(&[]);
+    // the address on the bus, the physical one and the size of the window of `space`
+    let window = |space: u32| {
+        ranges
+            .chunks_exact((parent + size_cells) * 4)
+            .find_map(|range| {
+                let cells: Vec<u32> = range.chunks(4).map(|cell| be32(cell, 0).unwrap()).collect();
+                let read = |cells: &[u32]| cells.iter().fold(0, |n, &cell| n << 32 | cell as usize);
+                (cells[0] & PCI_SPACE == space).then(|| {
+                    (
+                        read(&cells[1..address_cells]),
+                        read(&cells[address_cells..parent]),
+                        read(&cells[parent..]),
+                    )
+                })
+            })
+    };
+    let (mem_pci, mem, mem_size) = window(PCI_SPACE_MEM32)?;
+    let (io_pci, io, io_size) = window(PCI_SPACE_IO).unwrap_or((0, 0, 0));
+    // the unit address of a device and its pin, then the PLIC and its source
+    let mut irqs = [0; 4];
+    let map = node.prop("interrupt-map").unwrap_or(&[]);
+    for entry in map.chunks_exact((address_cells + 3) * 4) {
+        let device = be32(entry, 0).unwrap() >> 11 & 0x1f;
+        let pin = be32(entry, address_cells * 4).unwrap() as usize;
+        if device == 0 && (1..=4).contains(&pin) {
+            irqs[pin - 1] = be32(entry, (address_cells + 2) * 4).unwrap() as usize;
+        }
+    }
+    Some(PciHost {
+        ecam,
+        mem,
+        mem_pci,
+        mem_size,
+        io,
+        io_pci,
+        io_size,
+        irqs,
+    })
+}
+
+/// The pages covering `regions`, merged where they overlap so that each is
+/// mapped once
+fn merge_pages(mut regions: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    regions.sort_unstable();
+    let mut pages: Vec<(usize, usize)> = Vec::new();
+    for (base, size) in regions {
+        let start = base & !(PAGE_SIZE - 1);
+        let end = (base + size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        match pages.last_mut() {
+            Some((last, len)) if *last + *len >= start => *len = (*len).max(end - *last),
+            _ => pages.push((start, end - start)),
+        }
+    }
+    pages
+}
@@ -0,0 +1,269 @@
+//! Device files under `/dev/`: the framebuffer `/dev/fb0`, the input
+//! devices `/dev/input/event0` and `event1`, the ports of the virtio
+//! console `/dev/hvc0` and so on, and `/dev/random` and `/dev/urandom`
+use super::{File, OpenFlags};
+use crate::drivers::gpu::{Rect, VirtIOGpu};
+use crate::drivers::hvc::VirtIOConsole;
+use crate::drivers::input::{InputEvent, VirtIOInput};
+use crate::drivers::{GPU_DEVICE, HVC_DEVICE, INPUT_DEVICES};
+use crate::mm::{PhysPageNum, UserBuffer};
+use crate::random;
+use crate::sync::ExclusiveCell;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+/// Directory prefix of device files
+pub const DEV_PREFIX: &str = "/dev/";
+
+/// Open the device file `name` under `/dev/`, `None` if there is no such device
+pub fn open_dev(name: &str, flags: OpenFlags) -> Option<Arc<dyn File + Send + Sync>> {
+    match name {
+        "fb0" => GPU_DEVICE
+            .as_ref()
+            .map(|gpu| Arc::new(Framebuffer::new(gpu)) as Arc<dyn File + Send + Sync>),
+        "random" | "urandom" => Some(Arc::new(RandomFile) as Arc<dyn File + Send + Sync>),
+        "input/event0" | "input/event1" => {
+            let index = (name.as_bytes()[name.len() - 1] - b'0') as usize;
+            INPUT_DEVICES[index].as_ref().map(|device| {
+                Arc::new(InputFile {
+                    device,
+                    nonblock: flags.contains(OpenFlags::NONBLOCK),
+                }) as Arc<dyn File + Send + Sync>
+            })
+        }
+        _ => {
+            let port: usize = name.strip_prefix("hvc")?.parse().ok()?;
+            HVC_DEVICE
+                .as_ref()
+                .filter(|hvc| hvc.has_port(port))
+                .map(|device| {
+                    Arc::new(HvcFile {
+                        device,
+                        port,
+                        nonblock: flags.contains(OpenFlags::NONBLOCK),
+                    }) as Arc<dyn File + Send + Sync>
+                })
+        }
+    }
+}
+
+/// The framebuffer of the GPU, whose bytes are the pixels row by row. Bytes
+/// written show right away, those stored to a mapping once flushed.
+pub struct Framebuffer {
+    gpu: &'static VirtIOGpu,
+    offset: ExclusiveCell<usize>,
+}
+
+impl Framebuffer {
+    fn new(gpu: &'static VirtIOGpu) -> Self {
+        Self {
+            gpu,
+            offset: ExclusiveCell::new(0),
+        }
+    }
+    /// Width and height in pixels
+    pub fn resolution(&self) -> (u32, u32) {
+        (self.gpu.width(), self.gpu.height())
+    }
+    /// Bytes of a row of pixels
+    pub fn stride(&self) -> usize {
+        self.gpu.stride()
+    }
+    /// The first of the consecutive frames of the pixels and their number
+    pub fn frames(&self) -> (PhysPageNum, usize) {
+        self.gpu.frames()
+    }
+    /// Show the pixels of `rect`, clipped to the screen
+    pub fn flush(&self, rect: Rect) -> bool {
+        self.gpu.flush(rect)
+    }
+    /// Show the rows holding the bytes of `[start, end)`
+    fn flush_bytes(&self, start: usize, end: usize) {
+        let (width, _) = self.resolution();
+        let first = start / self.stride();
+        let last = (end - 1) / self.stride();
+        self.flush(Rect {
+            x: 0,
+            y: first as u32,
+            width,
+            height: (last - first + 1) as u32,
+        });
+    }
+}
+
+impl File for Framebuffer {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let pixels = self.gpu.framebuffer();
+        let mut offset = self.offset.exclusive_access();
+        let mut total = 0;
+        for slice in buf.buffers.iter_mut() {
+            let len = slice.len().min(pixels.len() - *offset);
+            if len == 0 {
+                break;
+            }
+            slice[..len].copy_from_slice(&pixels[*offset..*offset + len]);
+            *offset += len;
+            total += len;
+        }
+        total
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        let pixels = self.gpu.framebuffer();
+        let mut offset = self.offset.exclusive_access();
+        let start = *offset;
+        for slice in buf.buffers.iter() {
+            let len = slice.len().min(pixels.len() - *offset);
+            if len == 0 {
+                break;
+            }
+            pixels[*offset..*offset + len].copy_from_slice(&slice[..len]);
+            *offset += len;
+        }
+        if *offset > start {
+            self.flush_bytes(start, *offset);
+        }
+        *offset - start
+    }
+    fn as_framebuffer(&self) -> Option<&Framebuffer> {
+        Some(self)
+    }
+}
+
+/// The entropy pool, read for random bytes and written to mix bytes into it
+pub struct RandomFile;
+
+impl File for RandomFile {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        for slice in buf.buffers.iter_mut() {
+            random::fill(slice);
+        }
+        buf.len()
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        for slice in buf.buffers.iter() {
+            random::add_entropy(slice);
+        }
+        buf.len()
+    }
+}
+
+/// The events of an input device, read as whole [`InputEvent`]s. Files of the
+/// same device share its events, each read by one of them.
+pub struct InputFile {
+    device: &'static VirtIOInput,
+    /// return 0 rather than wait if there is no event
+    nonblock: bool,
+}
+
+impl File for InputFile {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    /// Read as many events as fit, blocking until there is one unless opened
+    /// with `NONBLOCK`. Return 0 if there is none or a signal arrives first.
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let count = buf.len() / size_of::<InputEvent>();
+        if count == 0 {
+            return 0;
+        }
+        let mut events = Vec::new();
+        // filled by the interrupts of the device
+        let mut take = || {
+            while events.len() < count {
+                match self.device.read() {
+                    Some(event) => events.push(event),
+                    None => break,
+                }
+            }
+            !events.is_empty()
+        };
+        if self.nonblock {
+            take();
+        } else {
+            self.device.wait_queue.wait_until(take);
+        }
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                events.as_ptr() as *const u8,
+                events.len() * size_of::<InputEvent>(),
+            )
+        };
+        let mut copied = 0;
+        for slice in buf.buffers.iter_mut() {
+            let len = slice.len().min(bytes.len() - copied);
+            slice[..len].copy_from_slice(&bytes[copied..copied + len]);
+            copied += len;
+        }
+        copied
+    }
+    fn write(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+}
+
+/// A port of the virtio console, a stream of bytes both ways. Files of the
+/// same port share its input, each byte read by one of them.
+pub struct HvcFile {
+    device: &'static VirtIOConsole,
+    port: usize,
+    /// return 0 rather than wait if there is no input
+    nonblock: bool,
+}
+
+impl File for HvcFile {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    /// Read the input received so far, blocking until there is some unless
+    /// opened with `NONBLOCK`. Return 0 if there is none or a signal arrives
+    /// first.
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let len = buf.len();
+        if len == 0 {
+            return 0;
+        }
+        let mut bytes = Vec::new();
+        // filled by the interrupts of the device
+        let mut take = || {
+            bytes = self.device.read(self.port, len);
+            !bytes.is_empty()
+        };
+        if self.nonblock {
+            take();
+        } else {
+            self.device.wait_queue(self.port).wait_until(take);
+        }
+        let mut copied = 0;
+        for slice in buf.buffers.iter_mut() {
+            let len = slice.len().min(bytes.len() - copied);
+            slice[..len].copy_from_slice(&bytes[copied..copied + len]);
+            copied += len;
+        }
+        copied
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        for slice in buf.buffers.iter() {
+            self.device.write(self.port, slice);
+        }
+        buf.len()
+    }
+}
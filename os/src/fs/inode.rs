@@ -2,64 +2,146 @@
 //! we need to wrap `Inode` into `Arc`,but `Mutex` in `Inode` prevents
 //! file systems from being accessed simultaneously
 //!
-//! `UPSafeCell<OSInodeInner>` -> `OSInode`: for static `ROOT_INODE`,we
-//! need to wrap `OSInodeInner` into `UPSafeCell`
-use super::File;
+//! `ExclusiveCell<OSInodeInner>` -> `OSInode`: for static `ROOT_INODE`,we
+//! need to wrap `OSInodeInner` into `ExclusiveCell`
+use super::{File, Stat, S_IFREG};
+use crate::config::PAGE_SIZE;
 use crate::drivers::BLOCK_DEVICE;
-use crate::mm::UserBuffer;
-use crate::sync::UPSafeCell;
+use crate::kernel_test;
+use crate::mm::{frame_alloc, FrameTracker, ObjectCache, UserBuffer};
+use crate::sync::{ExclusiveCell, Lazy, Once, SleepLock};
+use crate::timer::{realtime_ns, TimeSpec};
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
-use easy_fs::{EasyFileSystem, Inode};
-use lazy_static::*;
+use easy_fs::{BlockDevice, EasyFileSystem, Inode, BLOCK_SZ};
 /// A wrapper around a filesystem inode
 /// to implement File trait atop
 pub struct OSInode {
     readable: bool,
     writable: bool,
-    inner: UPSafeCell<OSInodeInner>,
+    inner: ExclusiveCell<OSInodeInner>,
 }
-/// The OS inode inner in 'UPSafeCell'
+/// The OS inode inner in 'ExclusiveCell'
 pub struct OSInodeInner {
     offset: usize,
     inode: Arc<Inode>,
 }
 
+/// Slab cache of opened inodes
+pub(super) static OS_INODE_CACHE: ObjectCache<OSInode> = ObjectCache::new();
+
 impl OSInode {
-    /// Construct an OS inode from a inode
-    pub fn new(readable: bool, writable: bool, inode: Arc<Inode>) -> Self {
-        Self {
+    /// Construct an OS inode from a inode, allocated from its slab cache
+    pub fn new(readable: bool, writable: bool, inode: Arc<Inode>) -> Arc<Self> {
+        OS_INODE_CACHE.new_arc(Self {
             readable,
             writable,
-            inner: unsafe { UPSafeCell::new(OSInodeInner { offset: 0, inode }) },
-        }
+            inner: ExclusiveCell::new(OSInodeInner { offset: 0, inode }),
+        })
     }
     /// Read all data inside a inode into vector
     pub fn read_all(&self) -> Vec<u8> {
-        let mut inner = self.inner.exclusive_access();
+        let _fs = FS_LOCK.lock();
+        let (mut offset, inode) = self.position();
         let mut buffer = [0u8; 512];
         let mut v: Vec<u8> = Vec::new();
         loop {
-            let len = inner.inode.read_at(inner.offset, &mut buffer);
+            let len = inode.read_at(offset, &mut buffer);
             if len == 0 {
                 break;
             }
-            inner.offset += len;
+            offset += len;
             v.extend_from_slice(&buffer[..len]);
         }
+        self.inner.exclusive_access().offset = offset;
         v
     }
+    /// The offset and the inode, not to hold `inner` while the disk is accessed
+    fn position(&self) -> (usize, Arc<Inode>) {
+        let inner = self.inner.exclusive_access();
+        (inner.offset, Arc::clone(&inner.inode))
+    }
 }
 
-lazy_static! {
-    pub static ref ROOT_INODE: Arc<Inode> = {
+/// Serializes the accesses to the filesystem. Tasks block on the disk while
+/// holding the locks of easy-fs, which would spin on the same hart forever.
+static FS_LOCK: Lazy<SleepLock<()>> = Lazy::new(|| SleepLock::new(()));
+
+/// The root directory, set up by [`super::init`]
+pub static ROOT_INODE: Once<Arc<Inode>> = Once::new();
+/// Open the filesystem on the block device and set up `ROOT_INODE`
+pub fn init_root_inode() {
+    let _fs = FS_LOCK.lock();
+    ROOT_INODE.call_once(|| {
         let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
+        efs.lock()
+            .set_clock(|| TimeSpec::from_nanos(realtime_ns()).tv_sec as u32);
         Arc::new(EasyFileSystem::root_inode(&efs))
-    };
+    });
 }
+/// A disk in frames, for tests
+struct RamDisk(Vec<FrameTracker>);
+
+/// Blocks in a frame of a [`RamDisk`]
+const BLOCKS_PER_FRAME: usize = PAGE_SIZE / BLOCK_SZ;
+
+impl RamDisk {
+    fn block(&self, block_id: usize) -> &mut [u8] {
+        let offset = block_id % BLOCKS_PER_FRAME * BLOCK_SZ;
+        &mut self.0[block_id / BLOCKS_PER_FRAME].ppn.get_bytes_array()[offset..offset + BLOCK_SZ]
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        buf.copy_from_slice(self.block(block_id));
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        self.block(block_id).copy_from_slice(buf);
+    }
+    fn block_count(&self) -> Option<usize> {
+        Some(self.0.len() * BLOCKS_PER_FRAME)
+    }
+}
+
+/// Write a file to a new filesystem on a RAM disk through the block cache,
+/// which also caches the blocks of the same ids of the root filesystem, and
+/// read it back once the filesystem is opened again
+fn block_cache_test() {
+    const BLOCKS: usize = 2048;
+    let _fs = FS_LOCK.lock();
+    let frames = (0..BLOCKS / BLOCKS_PER_FRAME)
+        .map(|_| frame_alloc().unwrap())
+        .collect();
+    let disk: Arc<dyn BlockDevice> = Arc::new(RamDisk(frames));
+    let data: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+    let efs = EasyFileSystem::create(disk.clone(), BLOCKS as u32, 1);
+    let file = EasyFileSystem::root_inode(&efs)
+        .create("block_cache_test")
+        .unwrap();
+    assert_eq!(file.write_at(0, &data), data.len());
+    // the write is synced, so the first block of the file is on the disk
+    let mut block = [0u8; BLOCK_SZ];
+    assert!((0..BLOCKS).any(|block_id| {
+        disk.read_block(block_id, &mut block);
+        block[..] == data[..BLOCK_SZ]
+    }));
+    let efs = EasyFileSystem::open(disk);
+    let file = EasyFileSystem::root_inode(&efs)
+        .find("block_cache_test")
+        .unwrap();
+    let mut buf = vec![0u8; data.len() + 1];
+    assert_eq!(file.read_at(0, &mut buf), data.len());
+    assert_eq!(buf[..data.len()], data[..]);
+    assert!(ROOT_INODE.find("block_cache_test").is_none());
+}
+kernel_test!(block_cache_test);
+
 /// List all files in the filesystems
 pub fn list_apps() {
+    let _fs = FS_LOCK.lock();
     println!("/**** APPS ****");
     for app in ROOT_INODE.ls() {
         println!("{}", app);
@@ -80,6 +162,8 @@ bitflags! {
         const CREATE = 1 << 9;
         ///Clear file and return an empty one
         const TRUNC = 1 << 10;
+        ///Return from reads rather than wait for data, on devices
+        const NONBLOCK = 1 << 11;
     }
 }
 
@@ -87,9 +171,10 @@ impl OpenFlags {
     /// Do not check validity for simplicity
     /// Return (readable, writable)
     pub fn read_write(&self) -> (bool, bool) {
-        if self.is_empty() {
+        let mode = *self - Self::NONBLOCK;
+        if mode.is_empty() {
             (true, false)
-        } else if self.contains(Self::WRONLY) {
+        } else if mode.contains(Self::WRONLY) {
             (false, true)
         } else {
             (true, true)
@@ -99,23 +184,24 @@ impl OpenFlags {
 ///Open file with flags
 pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
     let (readable, writable) = flags.read_write();
+    let _fs = FS_LOCK.lock();
     if flags.contains(OpenFlags::CREATE) {
         if let Some(inode) = ROOT_INODE.find(name) {
             // clear size
             inode.clear();
-            Some(Arc::new(OSInode::new(readable, writable, inode)))
+            Some(OSInode::new(readable, writable, inode))
         } else {
             // create file
             ROOT_INODE
                 .create(name)
-                .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
+                .map(|inode| OSInode::new(readable, writable, inode))
         }
     } else {
         ROOT_INODE.find(name).map(|inode| {
             if flags.contains(OpenFlags::TRUNC) {
                 inode.clear();
             }
-            Arc::new(OSInode::new(readable, writable, inode))
+            OSInode::new(readable, writable, inode)
         })
     }
 }
@@ -128,27 +214,45 @@ impl File for OSInode {
         self.writable
     }
     fn read(&self, mut buf: UserBuffer) -> usize {
-        let mut inner = self.inner.exclusive_access();
+        let _fs = FS_LOCK.lock();
+        let (mut offset, inode) = self.position();
         let mut total_read_size = 0usize;
         for slice in buf.buffers.iter_mut() {
-            let read_size = inner.inode.read_at(inner.offset, *slice);
+            let read_size = inode.read_at(offset, *slice);
             if read_size == 0 {
                 break;
             }
-            inner.offset += read_size;
+            offset += read_size;
             total_read_size += read_size;
         }
+        self.inner.exclusive_access().offset = offset;
         total_read_size
     }
     fn write(&self, buf: UserBuffer) -> usize {
-        let mut inner = self.inner.exclusive_access();
+        let _fs = FS_LOCK.lock();
+        let (mut offset, inode) = self.position();
         let mut total_write_size = 0usize;
         for slice in buf.buffers.iter() {
-            let write_size = inner.inode.write_at(inner.offset, *slice);
+            let write_size = inode.write_at(offset, *slice);
             assert_eq!(write_size, slice.len());
-            inner.offset += write_size;
+            offset += write_size;
             total_write_size += write_size;
         }
+        self.inner.exclusive_access().offset = offset;
         total_write_size
     }
+    fn stat(&self) -> Stat {
+        let _fs = FS_LOCK.lock();
+        let (_, inode) = self.position();
+        let (size, mtime) = inode.stat();
+        Stat {
+            mode: S_IFREG,
+            size: size as u64,
+            mtime: TimeSpec {
+                tv_sec: mtime as usize,
+                tv_nsec: 0,
+            },
+            ..Stat::default()
+        }
+    }
 }
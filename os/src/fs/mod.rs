@@ -1,8 +1,14 @@
 //! File system in os
+mod dev;
 mod inode;
+mod procfs;
 mod stdio;
 
-use crate::mm::UserBuffer;
+use crate::mm::{create_arc_cache, UserBuffer};
+use crate::net::{TcpSocket, UdpSocket};
+use crate::timer::TimeSpec;
+use alloc::sync::Arc;
+use easy_fs::Inode;
 /// File trait
 pub trait File: Send + Sync {
     /// If readable
@@ -13,7 +19,67 @@ pub trait File: Send + Sync {
     fn read(&self, buf: UserBuffer) -> usize;
     /// Write `UserBuffer` to file
     fn write(&self, buf: UserBuffer) -> usize;
+    /// The UDP socket if the file is one
+    fn as_udp_socket(&self) -> Option<&UdpSocket> {
+        None
+    }
+    /// The TCP socket if the file is one
+    fn as_tcp_socket(&self) -> Option<&TcpSocket> {
+        None
+    }
+    /// The framebuffer if the file is one
+    fn as_framebuffer(&self) -> Option<&Framebuffer> {
+        None
+    }
+    /// Type, size and modification time, all zero unless the file is on the filesystem
+    fn stat(&self) -> Stat {
+        Stat::default()
+    }
 }
 
+/// `mode` of [`Stat`]: a regular file
+pub const S_IFREG: u32 = 0o100000;
+
+/// Status of a file, as written by `sys_fstat`
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct Stat {
+    /// type of the file, [`S_IFREG`] or 0
+    pub mode: u32,
+    padding: u32,
+    /// bytes of data
+    pub size: u64,
+    /// last modification, since the Unix epoch
+    pub mtime: TimeSpec,
+}
+
+pub use dev::{open_dev, Framebuffer, DEV_PREFIX};
 pub use inode::{list_apps, open_file, OSInode, OpenFlags};
+pub use procfs::{open_proc, ProcFile, PROC_PREFIX};
 pub use stdio::{Stdin, Stdout};
+
+/// Open a file by path, either a procfs file, a device file or a file in the
+/// root directory
+pub fn open(path: &str, flags: OpenFlags) -> Option<Arc<dyn File + Send + Sync>> {
+    if let Some(name) = path.strip_prefix(DEV_PREFIX) {
+        if flags.contains(OpenFlags::CREATE) {
+            return None;
+        }
+        return open_dev(name, flags);
+    }
+    if let Some(name) = path.strip_prefix(PROC_PREFIX) {
+        if flags.contains(OpenFlags::CREATE) || flags.read_write().1 {
+            return None;
+        }
+        return open_proc(name).map(|file| file as Arc<dyn File + Send + Sync>);
+    }
+    open_file(path, flags).map(|file| file as Arc<dyn File + Send + Sync>)
+}
+
+/// Create the slab caches of opened inodes and open the root directory
+pub fn init() {
+    inode::OS_INODE_CACHE.init_arc();
+    // allocated inside easy-fs, so served by a cache shared by their layout
+    create_arc_cache::<Inode>();
+    inode::init_root_inode();
+}
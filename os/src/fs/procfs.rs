@@ -0,0 +1,149 @@
+//! A minimal procfs: read-only files under `/proc/` whose content is
+//! generated by the kernel when they are opened
+use super::File;
+use crate::config::MAX_HARTS;
+use crate::config::PAGE_SIZE;
+use crate::drivers::BLOCK_DEVICES;
+use crate::fdt::MACHINE;
+use crate::mm::{alloc_stats, frame_allocator_contentions, frame_stats, heap_stats, UserBuffer};
+use crate::sync::ExclusiveCell;
+use crate::task::{current_process, pid2process, ready_queue_contentions};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Directory prefix of procfs files
+pub const PROC_PREFIX: &str = "/proc/";
+
+/// A snapshot of some kernel state
+pub struct ProcFile {
+    content: Vec<u8>,
+    offset: ExclusiveCell<usize>,
+}
+
+impl ProcFile {
+    fn new(content: String) -> Self {
+        Self {
+            content: content.into_bytes(),
+            offset: ExclusiveCell::new(0),
+        }
+    }
+}
+
+impl File for ProcFile {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let mut offset = self.offset.exclusive_access();
+        let mut total_read_size = 0usize;
+        for slice in buf.buffers.iter_mut() {
+            let read_size = slice.len().min(self.content.len() - *offset);
+            if read_size == 0 {
+                break;
+            }
+            slice[..read_size].copy_from_slice(&self.content[*offset..*offset + read_size]);
+            *offset += read_size;
+            total_read_size += read_size;
+        }
+        total_read_size
+    }
+    fn write(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+}
+
+/// Memory usage of the whole system
+fn meminfo() -> String {
+    let frames = frame_stats();
+    let heap = heap_stats();
+    let kb = |frames: usize| frames * PAGE_SIZE / 1024;
+    format!(
+        "MemTotal:     {:>8} kB\n\
+         MemFree:      {:>8} kB\n\
+         MemUsed:      {:>8} kB\n\
+         MemPeak:      {:>8} kB\n\
+         UserPages:    {:>8} kB\n\
+         PageTables:   {:>8} kB\n\
+         KernelPages:  {:>8} kB\n\
+         Balloon:      {:>8} kB\n\
+         HeapTotal:    {:>8} kB\n\
+         HeapUsed:     {:>8} kB\n",
+        kb(frames.total),
+        kb(frames.free()),
+        kb(frames.used),
+        kb(frames.peak_used),
+        kb(frames.user),
+        kb(frames.page_table),
+        kb(frames.kernel),
+        kb(frames.balloon),
+        heap.total_bytes / 1024,
+        heap.used_bytes / 1024,
+    )
+}
+
+/// Number of times a hart waited for each of the contended kernel locks
+fn lockstat() -> String {
+    let mut content = format!("frame_allocator  {}\n", frame_allocator_contentions());
+    for cpu in 0..MAX_HARTS {
+        content += &format!("ready_queue{}     {}\n", cpu, ready_queue_contentions(cpu));
+    }
+    content
+}
+
+/// The disks and their partitions with their capacity
+fn partitions() -> String {
+    let mut content = String::from("blocks  name\n");
+    for entry in BLOCK_DEVICES.iter() {
+        content += &format!("{:>8}  {}\n", entry.blocks, entry.name);
+    }
+    content
+}
+
+/// Memory usage of the process `pid`, or of the current one for `self`
+fn process_status(pid: &str) -> Option<String> {
+    let process = match pid {
+        "self" => current_process(),
+        pid => pid2process(pid.parse().ok()?)?,
+    };
+    let ppid = process.getppid();
+    let inner = process.inner_exclusive_access();
+    let usage = inner.address_space.exclusive_access().memory_set.usage();
+    let kb = |pages: usize| pages * PAGE_SIZE / 1024;
+    Some(format!(
+        "Pid:          {:>8}\n\
+         PPid:         {:>8}\n\
+         VmHWM:        {:>8} kB\n\
+         VmRSS:        {:>8} kB\n\
+         VmHeap:       {:>8} kB\n\
+         VmMmap:       {:>8} kB\n",
+        process.getpid(),
+        ppid,
+        kb(usage.peak_resident_pages),
+        kb(usage.resident_pages),
+        kb(usage.heap_pages),
+        kb(usage.mmap_pages),
+    ))
+}
+
+/// Open the procfs file `name`, the path without [`PROC_PREFIX`]: a file of
+/// the whole system or `<pid>/status` of a process, `self/status` for the
+/// current one
+pub fn open_proc(name: &str) -> Option<Arc<ProcFile>> {
+    if let Some(pid) = name.strip_suffix("/status") {
+        return Some(Arc::new(ProcFile::new(process_status(pid)?)));
+    }
+    let content = match name {
+        "meminfo" => meminfo(),
+        "lockstat" => lockstat(),
+        "partitions" => partitions(),
+        "cmdline" => format!("{}\n", MACHINE.bootargs),
+        "allocstat" => alloc_stats()?,
+        _ => return None,
+    };
+    Some(Arc::new(ProcFile::new(content)))
+}
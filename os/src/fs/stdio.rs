@@ -1,8 +1,7 @@
 //!Stdin & Stdout
 use super::File;
+use crate::drivers::SHELL_UART;
 use crate::mm::UserBuffer;
-use crate::sbi::console_getchar;
-use crate::task::suspend_current_and_run_next;
 ///Standard input
 pub struct Stdin;
 ///Standard output
@@ -15,20 +14,23 @@ impl File for Stdin {
     fn writable(&self) -> bool {
         false
     }
+    /// Read one character, blocking until there is one. Return 0 if a signal arrives first.
     fn read(&self, mut user_buf: UserBuffer) -> usize {
         assert_eq!(user_buf.len(), 1);
-        // busy loop
-        let mut c: usize;
-        loop {
-            c = console_getchar();
-            if c == 0 {
-                suspend_current_and_run_next();
-                continue;
-            } else {
-                break;
-            }
+        let mut ch = 0;
+        // filled by the interrupts of the UART
+        let got = SHELL_UART
+            .rx_wait_queue
+            .wait_until(|| match SHELL_UART.read() {
+                Some(c) => {
+                    ch = c;
+                    true
+                }
+                None => false,
+            });
+        if !got {
+            return 0;
         }
-        let ch = c as u8;
         unsafe {
             user_buf.buffers[0].as_mut_ptr().write_volatile(ch);
         }
@@ -51,7 +53,7 @@ impl File for Stdout {
     }
     fn write(&self, user_buf: UserBuffer) -> usize {
         for buffer in user_buf.buffers.iter() {
-            print!("{}", core::str::from_utf8(*buffer).unwrap());
+            SHELL_UART.write(buffer);
         }
         user_buf.len()
     }
@@ -0,0 +1,195 @@
+//! Harts and SMP bring-up
+//!
+//! Every hart keeps its hart id in `tp` while running in the kernel. The boot
+//! hart initializes the kernel and then starts the other harts with the SBI
+//! HSM extension, which enter the kernel at `_start_secondary`.
+//!
+//! A hart other than the last online one can be taken offline by
+//! [`hart_offline`]: once it has no task running, it stops taking interrupts,
+//! moves its ready tasks to the online harts and stops by the HSM extension.
+//! [`hart_online`] starts it again at `_start_secondary`.
+use crate::config::MAX_HARTS;
+use crate::fdt::MACHINE;
+use crate::sbi::{hart_start, hart_status, hart_stop, send_ipi, SBI_HSM_STOPPED};
+use crate::sync::SpinNoIrqLock;
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of harts running the kernel
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Harts running the kernel, bit i for hart i
+static ONLINE_MASK: AtomicUsize = AtomicUsize::new(0);
+
+/// Harts waiting for an interrupt in the idle loop, bit i for hart i
+static IDLE_MASK: AtomicUsize = AtomicUsize::new(0);
+
+/// Online harts asked to go offline, bit i for hart i
+static OFFLINE_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Held while a hart is taken offline or brought online, so that the last
+/// online hart is never asked to go offline
+static HOTPLUG_LOCK: SpinNoIrqLock<()> = SpinNoIrqLock::new(());
+
+/// Why a hart cannot be taken offline or brought online
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HotplugError {
+    /// the machine has no such hart, or the kernel does not support it
+    NoSuchHart,
+    /// it is the last online hart
+    LastHart,
+    /// it is on its way offline or online
+    Busy,
+    /// the firmware did not start it
+    Firmware,
+}
+
+/// Supervisor software interrupt pending bit of `sip`, set by an IPI
+const SIP_SSIP: usize = 1 << 1;
+
+/// Mask with a bit for every hart the kernel supports
+pub const ALL_HARTS: usize = (1 << MAX_HARTS) - 1;
+
+/// Id of the current hart
+pub fn hart_id() -> usize {
+    let id;
+    unsafe {
+        asm!("mv {}, tp", out(reg) id);
+    }
+    id
+}
+
+/// Number of harts running the kernel
+pub fn online_harts() -> usize {
+    ONLINE_HARTS.load(Ordering::Acquire)
+}
+
+/// Harts running the kernel, bit i for hart i
+pub fn online_hart_mask() -> usize {
+    ONLINE_MASK.load(Ordering::Acquire)
+}
+
+/// Count the current hart as online, before it enables paging, so that
+/// mapping changes from then on are fenced on it as well
+pub fn set_online() {
+    ONLINE_HARTS.fetch_add(1, Ordering::AcqRel);
+    ONLINE_MASK.fetch_or(1 << hart_id(), Ordering::AcqRel);
+}
+
+/// Whether the current hart is asked to go offline
+pub fn offline_requested() -> bool {
+    OFFLINE_PENDING.load(Ordering::Acquire) & (1 << hart_id()) != 0
+}
+
+/// Count the current hart as offline, for no task to be queued on it and no
+/// hart to wait for it from then on
+pub fn set_offline() {
+    let mask = 1 << hart_id();
+    ONLINE_MASK.fetch_and(!mask, Ordering::AcqRel);
+    ONLINE_HARTS.fetch_sub(1, Ordering::AcqRel);
+    IDLE_MASK.fetch_and(!mask, Ordering::AcqRel);
+    OFFLINE_PENDING.fetch_and(!mask, Ordering::AcqRel);
+}
+
+/// Stop the current hart, once it is offline, until [`hart_online`] starts
+/// it again
+pub fn stop_current() -> ! {
+    hart_stop();
+    panic!("hart {} failed to stop", hart_id());
+}
+
+fn check_hart(hart: usize) -> Result<(), HotplugError> {
+    if hart < MAX_HARTS && MACHINE.harts.contains(&hart) {
+        Ok(())
+    } else {
+        Err(HotplugError::NoSuchHart)
+    }
+}
+
+/// Ask `hart` to go offline, which it does once it has no task running. It
+/// may be the current hart. Nothing is done if it is offline already.
+pub fn hart_offline(hart: usize) -> Result<(), HotplugError> {
+    check_hart(hart)?;
+    let _guard = HOTPLUG_LOCK.lock();
+    let online = online_hart_mask() & !OFFLINE_PENDING.load(Ordering::Acquire);
+    if online & (1 << hart) == 0 {
+        return Ok(());
+    }
+    if online == 1 << hart {
+        return Err(HotplugError::LastHart);
+    }
+    OFFLINE_PENDING.fetch_or(1 << hart, Ordering::AcqRel);
+    // out of the idle loop, or into the kernel from a task
+    send_ipi(1 << hart);
+    Ok(())
+}
+
+/// Start `hart` again once it is offline and stopped. Nothing is done if it
+/// is online and not asked to go offline.
+pub fn hart_online(hart: usize) -> Result<(), HotplugError> {
+    extern "C" {
+        fn _start_secondary();
+    }
+    check_hart(hart)?;
+    let _guard = HOTPLUG_LOCK.lock();
+    if online_hart_mask() & (1 << hart) != 0 {
+        return match OFFLINE_PENDING.load(Ordering::Acquire) & (1 << hart) {
+            0 => Ok(()),
+            _ => Err(HotplugError::Busy),
+        };
+    }
+    // it may still be on its way to stop, or already starting
+    if hart_status(hart) != Some(SBI_HSM_STOPPED) {
+        return Err(HotplugError::Busy);
+    }
+    if !hart_start(hart, _start_secondary as usize, 0) {
+        return Err(HotplugError::Firmware);
+    }
+    info!("starting hart {}", hart);
+    Ok(())
+}
+
+/// Mark the current hart as waiting for an interrupt in the idle loop or not
+pub fn set_idle(idle: bool) {
+    if idle {
+        IDLE_MASK.fetch_or(1 << hart_id(), Ordering::AcqRel);
+    } else {
+        IDLE_MASK.fetch_and(!(1 << hart_id()), Ordering::AcqRel);
+    }
+}
+
+/// Wake up an idle hart in `mask` other than the current one with an IPI,
+/// `hart` if it is idle
+pub fn wake_idle_hart(hart: usize, mask: usize) {
+    let idle = IDLE_MASK.load(Ordering::Acquire) & mask & !(1 << hart_id());
+    if idle == 0 {
+        return;
+    }
+    let target = if idle & (1 << hart) != 0 {
+        hart
+    } else {
+        idle.trailing_zeros() as usize
+    };
+    send_ipi(1 << target);
+}
+
+/// Clear the pending IPI of the current hart
+pub fn clear_ipi() {
+    unsafe {
+        asm!("csrc sip, {}", in(reg) SIP_SSIP);
+    }
+}
+
+/// Start the harts of the machine other than the current one, up to [`MAX_HARTS`]
+pub fn start_secondary_harts() {
+    extern "C" {
+        fn _start_secondary();
+    }
+    let harts = MACHINE.harts.iter().copied();
+    for hart in harts.filter(|&hart| hart < MAX_HARTS && hart != hart_id()) {
+        // harts missing on the machine fail to start
+        if hart_start(hart, _start_secondary as usize, 0) {
+            info!("starting hart {}", hart);
+        }
+    }
+}
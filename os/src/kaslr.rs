@@ -0,0 +1,167 @@
+//! Kernel address space layout randomization (KASLR): the boot hart moves the
+//! kernel to a random place in memory before anything else, so that its code
+//! and data are not where an attack would expect them
+//!
+//! The kernel is linked as a position-independent executable at the address
+//! the firmware loads it at. Its code reaches everything relative to the pc,
+//! and the absolute addresses in its data are listed in `.rela.dyn` as
+//! relative relocations. `entry.asm` calls `kaslr_relocate` first, which
+//! copies the image to a random 2 MiB-aligned place past the loaded one,
+//! applies the relocations to the copy and returns the offset for the boot
+//! hart to continue there. The kernel maps itself and the physical memory at
+//! their physical addresses, so its virtual addresses are as random as its
+//! physical ones. The memory it was loaded in goes to the frame allocator.
+//!
+//! The offset is drawn from `rng-seed` or `kaslr-seed` in `/chosen` of the
+//! device tree, which QEMU provides, mixed with the timer, the only entropy
+//! without one. `nokaslr` on the command line keeps the kernel where it is
+//! loaded, e.g. for a debugger. Symbols stay at their link addresses,
+//! [`offset()`] below the running kernel.
+use crate::config::MEMORY_END;
+use crate::fdt;
+use core::arch::asm;
+use core::mem::size_of;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use riscv::register::time;
+
+/// Alignment of the place the kernel moves to, that of a 2 MiB page
+const ALIGN: usize = 0x20_0000;
+/// Most places the kernel may move to, fewer if the memory ends before
+const SLOTS: usize = 64;
+/// Type of a relocation adding the offset to the word at its address
+const R_RISCV_RELATIVE: usize = 3;
+
+/// A relocation of `.rela.dyn`
+#[repr(C)]
+struct Rela {
+    offset: usize,
+    info: usize,
+    addend: usize,
+}
+
+extern "C" {
+    fn skernel();
+    fn edata();
+    fn ekernel();
+    fn srela_dyn();
+    fn erela_dyn();
+}
+
+/// Bytes the kernel moved by, set up by [`init`]
+static OFFSET: AtomicUsize = AtomicUsize::new(0);
+
+/// Mix `x` into bits depending on all of its own, the finalizer of splitmix64
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Big-endian number of up to 8 bytes
+fn be(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |n, &byte| n << 8 | byte as u64)
+}
+
+/// Move the kernel loaded at its link address to a random place, given the
+/// device tree at physical address `dtb`, and return the offset, 0 if it
+/// stays. Runs on the boot stack of the loaded kernel with paging off, before
+/// `.bss` is cleared, so it must not use the heap nor statics written later.
+#[no_mangle]
+extern "C" fn kaslr_relocate(dtb: usize) -> usize {
+    let kernel = skernel as usize;
+    let mut nokaslr = false;
+    let mut seed = 0;
+    let mut memory_end = MEMORY_END;
+    let mut cells = (2, 1);
+    let tree_size = fdt::scan(dtb, |depth, node, prop, value| match (depth, prop) {
+        (0, "#address-cells") => cells.0 = be(value) as usize,
+        (0, "#size-cells") => cells.1 = be(value) as usize,
+        (1, "reg") if node.split('@').next() == Some("memory") && cells.0 + cells.1 > 0 => {
+            let (address_cells, size_cells) = cells;
+            for reg in value.chunks_exact((address_cells + size_cells) * 4) {
+                let (start, size) = reg.split_at(address_cells * 4);
+                let (start, size) = (be(start) as usize, be(size) as usize);
+                if (start..start + size).contains(&kernel) {
+                    memory_end = start + size;
+                }
+            }
+        }
+        (1, "bootargs") if node == "chosen" => {
+            nokaslr = value
+                .split(|&byte| byte == b' ' || byte == 0)
+                .any(|word| word == b"nokaslr");
+        }
+        (1, "rng-seed" | "kaslr-seed") if node == "chosen" => {
+            seed = value
+                .chunks(8)
+                .fold(seed, |seed, word| mix(seed ^ be(word)));
+        }
+        _ => {}
+    });
+    let tree = dtb..dtb + tree_size.unwrap_or(0);
+    let size = (ekernel as usize - kernel + ALIGN - 1) & !(ALIGN - 1);
+    if nokaslr || memory_end < kernel + 2 * size {
+        return 0;
+    }
+    // places past the loaded kernel up to the end of memory, from a random one
+    // on to the first clear of the tree
+    let slots = ((memory_end - kernel - 2 * size) / ALIGN + 1).min(SLOTS);
+    let first = mix(seed ^ time::read() as u64) as usize % slots;
+    let offset = (0..slots)
+        .map(|slot| size + (first + slot) % slots * ALIGN)
+        .find(|&offset| kernel + offset >= tree.end || kernel + offset + size <= tree.start);
+    let offset = match offset {
+        Some(offset) => offset,
+        None => return 0,
+    };
+    unsafe {
+        // `.bss` is cleared where the kernel continues
+        let image = edata as usize - kernel;
+        core::ptr::copy_nonoverlapping(kernel as *const u8, (kernel + offset) as *mut u8, image);
+        let relocs = core::slice::from_raw_parts(
+            srela_dyn as usize as *const Rela,
+            (erela_dyn as usize - srela_dyn as usize) / size_of::<Rela>(),
+        );
+        // the kernel has no dynamic symbols, so no other relocations
+        for rela in relocs.iter().filter(|rela| rela.info == R_RISCV_RELATIVE) {
+            *((rela.offset + offset) as *mut usize) = rela.addend + offset;
+        }
+        asm!("fence.i");
+    }
+    offset
+}
+
+/// Record that the kernel moved by `offset` bytes, as returned by
+/// `kaslr_relocate`, once `.bss` is cleared
+pub fn init(offset: usize) {
+    OFFSET.store(offset, Ordering::Relaxed);
+    if offset == 0 {
+        info!("kernel at its load address {:#x}", skernel as usize);
+    } else {
+        info!("kernel moved by {:#x} to {:#x}", offset, skernel as usize);
+    }
+}
+
+/// Bytes the kernel moved by from its link address
+pub fn offset() -> usize {
+    OFFSET.load(Ordering::Relaxed)
+}
+
+/// The memory the kernel was loaded in, and any below where it moved to,
+/// free for frames. Empty if it did not move.
+pub fn freed() -> Range<usize> {
+    let kernel = skernel as usize;
+    kernel - offset()..kernel
+}
+
+/// A function pointer in the data, relocated when the kernel moved
+static RELOCATED: fn() -> usize = offset;
+
+fn relocation_test() {
+    // the address of `offset` is computed from the pc, the pointer is relocated
+    assert_eq!(RELOCATED as usize, offset as usize);
+    assert_eq!(offset() % ALIGN, 0);
+}
+
+crate::kernel_test!(relocation_test);
@@ -0,0 +1,109 @@
+//! Coverage of the kernel code run by tasks (kcov-lite), for the tests to
+//! report which kernel paths they exercise
+//!
+//! Built with `KCOV=1`, the kernel is instrumented by LLVM to call
+//! `__sanitizer_cov_trace_pc` at the start of each basic block. A task
+//! enabling coverage with `sys_kcov` gets a [`KcovArea`], a bitmap of the
+//! kernel text with a bit per 2 bytes of instructions, shared with the tasks
+//! it creates from then on. While such a task runs on a hart, the callback
+//! sets the bit of its return address in the area of the task. Without the
+//! instrumentation, coverage cannot be enabled.
+use crate::config::MAX_HARTS;
+use crate::hart::hart_id;
+use crate::kaslr;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+#[cfg(feature = "kcov")]
+core::arch::global_asm!(
+    r#"
+    .section .text.kcov
+    .globl __sanitizer_cov_trace_pc
+# set the bit of ra in the area of the current task, not instrumented itself
+__sanitizer_cov_trace_pc:
+    la t0, KCOV_AREAS
+    slli t1, tp, 3
+    add t0, t0, t1
+    ld t0, 0(t0)
+    beqz t0, 1f
+    la t1, stext
+    sub t1, ra, t1
+    srli t1, t1, 1
+    srli t2, t1, 6
+    slli t2, t2, 3
+    add t0, t0, t2
+    li t2, 1
+    sll t2, t2, t1
+    amoor.d zero, t2, (t0)
+1:
+    ret
+"#
+);
+
+/// Words of the area of the task running on each hart, 0 if it collects no
+/// coverage, read by `__sanitizer_cov_trace_pc`
+#[no_mangle]
+static KCOV_AREAS: [AtomicUsize; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NONE: AtomicUsize = AtomicUsize::new(0);
+    [NONE; MAX_HARTS]
+};
+
+/// Bounds of the kernel text
+fn text() -> (usize, usize) {
+    extern "C" {
+        fn stext();
+        fn etext();
+    }
+    (stext as usize, etext as usize)
+}
+
+/// The kernel code covered by tasks, a bit per 2 bytes of the kernel text
+pub struct KcovArea {
+    bits: Vec<AtomicU64>,
+}
+
+impl KcovArea {
+    /// An area with nothing covered, `None` if the kernel is not instrumented
+    pub fn new() -> Option<Arc<Self>> {
+        if !cfg!(feature = "kcov") {
+            return None;
+        }
+        let (start, end) = text();
+        let words = ((end - start) / 2 + 63) / 64;
+        let mut bits = Vec::with_capacity(words);
+        bits.resize_with(words, || AtomicU64::new(0));
+        Some(Arc::new(Self { bits }))
+    }
+    /// The first `max` addresses covered, in order, at their link addresses
+    /// whatever the kernel moved by, and the number of all
+    pub fn collect(&self, max: usize) -> (Vec<usize>, usize) {
+        let start = text().0 - kaslr::offset();
+        let mut pcs = Vec::new();
+        let mut count = 0;
+        for (i, word) in self.bits.iter().enumerate() {
+            let mut bits = word.load(Ordering::Relaxed);
+            count += bits.count_ones() as usize;
+            while bits != 0 && pcs.len() < max {
+                let bit = bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                pcs.push(start + (i * 64 + bit) * 2);
+            }
+        }
+        (pcs, count)
+    }
+}
+
+/// Collect the coverage of the task switched to on the current hart into
+/// `area`, if any. The task holds it until it is switched out.
+pub fn kcov_switch_in(area: Option<&Arc<KcovArea>>) {
+    let words = area.map_or(0, |area| area.bits.as_ptr() as usize);
+    KCOV_AREAS[hart_id()].store(words, Ordering::Relaxed);
+}
+
+/// Stop collecting the coverage on the current hart, before the task it runs
+/// is switched out or drops its area
+pub fn kcov_switch_out() {
+    KCOV_AREAS[hart_id()].store(0, Ordering::Relaxed);
+}
@@ -0,0 +1,83 @@
+//! Kernel unit tests, run inside QEMU
+//!
+//! A test is a function registered by [`kernel_test!`](crate::kernel_test)
+//! next to the code it tests, which puts it in the `.kernel_tests` section.
+//! With `ktest` on the command line, the kernel runs them one at a time once
+//! it is set up, instead of starting the init program, or only those whose
+//! path contains the filter given by `ktest=<filter>`. A test passes by
+//! returning and fails by panicking. The machine then powers off, with the
+//! exit code of failure at the first failing test.
+use crate::board::{QEMUExit, QEMU_EXIT_HANDLE};
+use crate::cmdline;
+use crate::timer::{get_time, ticks_to_us};
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A test in the `.kernel_tests` section
+pub struct KernelTest {
+    /// path of the test function
+    pub name: &'static str,
+    /// the test, panicking if it fails
+    pub func: fn(),
+}
+
+/// Register the function `$func` in the module as a kernel test
+#[macro_export]
+macro_rules! kernel_test {
+    ($func: ident) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".kernel_tests"]
+            static TEST: $crate::ktest::KernelTest = $crate::ktest::KernelTest {
+                name: concat!(module_path!(), "::", stringify!($func)),
+                func: $func,
+            };
+        };
+    };
+}
+
+/// Address of the test running, 0 if none
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+
+/// The registered tests, in link order
+fn tests() -> &'static [KernelTest] {
+    extern "C" {
+        fn skernel_tests();
+        fn ekernel_tests();
+    }
+    let start = skernel_tests as usize;
+    let len = (ekernel_tests as usize - start) / size_of::<KernelTest>();
+    unsafe { core::slice::from_raw_parts(start as *const KernelTest, len) }
+}
+
+/// Whether the command line asks for the tests to run
+pub fn enabled() -> bool {
+    cmdline::flag("ktest")
+}
+
+/// Name of the test running, for the panic handler to report it failed
+pub fn current() -> Option<&'static str> {
+    let test = CURRENT.load(Ordering::Relaxed) as *const KernelTest;
+    unsafe { test.as_ref() }.map(|test| test.name)
+}
+
+/// Run the tests the command line selects and power off with their result
+pub fn run() -> ! {
+    let filter = cmdline::get("ktest").unwrap_or("");
+    let selected: Vec<&KernelTest> = tests()
+        .iter()
+        .filter(|test| test.name.contains(filter))
+        .collect();
+    info!("running {} kernel tests", selected.len());
+    for test in selected.iter() {
+        CURRENT.store(*test as *const KernelTest as usize, Ordering::Relaxed);
+        let start = get_time();
+        (test.func)();
+        let us = ticks_to_us(get_time() - start);
+        info!("test {} ... ok ({} us)", test.name, us);
+    }
+    CURRENT.store(0, Ordering::Relaxed);
+    info!("{} kernel tests passed", selected.len());
+    QEMU_EXIT_HANDLE.exit_success()
+}
@@ -1,18 +1,32 @@
 //! The panic handler
+use crate::backtrace::print_backtrace;
+use crate::board::{QEMUExit, QEMU_EXIT_HANDLE};
+use crate::ktest;
 use crate::sbi::shutdown;
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// set by the first panic, a panic while printing the backtrace prints none
+static PANICKING: AtomicBool = AtomicBool::new(false);
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     if let Some(location) = info.location() {
-        println!(
-            "[kernel] Panicked at {}:{} {}",
+        error!(
+            "Panicked at {}:{} {}",
             location.file(),
             location.line(),
             info.message().unwrap()
         );
     } else {
-        println!("[kernel] Panicked: {}", info.message().unwrap());
+        error!("Panicked: {}", info.message().unwrap());
+    }
+    if !PANICKING.swap(true, Ordering::Relaxed) {
+        print_backtrace();
+    }
+    if let Some(test) = ktest::current() {
+        error!("test {} ... FAILED", test);
+        QEMU_EXIT_HANDLE.exit_failure();
     }
     shutdown()
 }
@@ -0,0 +1,170 @@
+//! Kernel logger behind the macros of the `log` crate, used by the kernel and
+//! by `easy-fs`
+//!
+//! Each record is printed on the console with the time since boot, the hart
+//! and the level. Which records are printed is set by `log=` on the command
+//! line, a list of directives separated by commas: a level alone sets the
+//! default, `target=level` the level of the modules whose path starts with
+//! `target`, e.g. `log=warn,easy_fs=debug,os::drivers::pci=trace`. The most
+//! specific directive applies. Until the command line is read, records up to
+//! `INFO` are printed. Errors, e.g. panics, are printed whatever the filter.
+//!
+//! All that the kernel prints on the console, records or not, is also kept in
+//! a ring buffer, read by `syslog` e.g. for `dmesg`.
+use crate::hart::hart_id;
+use crate::sync::{Once, SpinNoIrqLock};
+use crate::timer::{get_time, ticks_to_us};
+use alloc::string::String;
+use alloc::vec::Vec;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Default level without a directive for it
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+struct Logger;
+
+/// Size of the ring buffer of kernel messages
+pub const LOG_BUFFER_SIZE: usize = 1 << 16;
+
+/// The last bytes printed by the kernel
+struct LogBuffer {
+    bytes: [u8; LOG_BUFFER_SIZE],
+    /// bytes printed since boot, the next one going at `end % LOG_BUFFER_SIZE`
+    end: usize,
+    /// bytes printed until the buffer was last cleared
+    cleared: usize,
+}
+
+static LOG_BUFFER: SpinNoIrqLock<LogBuffer> = SpinNoIrqLock::new(LogBuffer {
+    bytes: [0; LOG_BUFFER_SIZE],
+    end: 0,
+    cleared: 0,
+});
+
+/// Directives of the command line, the most specific ones first
+static DIRECTIVES: Once<Vec<(String, LevelFilter)>> = Once::new();
+
+static LOGGER: Logger = Logger;
+
+/// Keep `bytes` printed by the kernel in the ring buffer, in place of the
+/// oldest ones
+pub fn record(bytes: &[u8]) {
+    let mut buffer = LOG_BUFFER.lock();
+    for &byte in bytes {
+        let end = buffer.end;
+        buffer.bytes[end % LOG_BUFFER_SIZE] = byte;
+        buffer.end += 1;
+    }
+}
+
+/// Bytes in the ring buffer printed since it was last cleared, oldest first,
+/// then clear it if `clear`
+pub fn read_log(clear: bool) -> Vec<u8> {
+    let mut buffer = LOG_BUFFER.lock();
+    let start = buffer
+        .cleared
+        .max(buffer.end.saturating_sub(LOG_BUFFER_SIZE));
+    let log = (start..buffer.end)
+        .map(|i| buffer.bytes[i % LOG_BUFFER_SIZE])
+        .collect();
+    if clear {
+        buffer.cleared = buffer.end;
+    }
+    log
+}
+
+/// Number of bytes [`read_log`] would return
+pub fn log_len() -> usize {
+    let buffer = LOG_BUFFER.lock();
+    (buffer.end - buffer.cleared).min(LOG_BUFFER_SIZE)
+}
+
+/// Drop the messages in the ring buffer
+pub fn clear_log() {
+    let mut buffer = LOG_BUFFER.lock();
+    buffer.cleared = buffer.end;
+}
+
+/// Level a record of `target` must be within to be printed
+fn level_of(target: &str) -> LevelFilter {
+    let directives = match DIRECTIVES.get() {
+        Some(directives) => directives,
+        None => return DEFAULT_LEVEL,
+    };
+    directives
+        .iter()
+        .find(|(prefix, _)| {
+            // whole components only, `os::fs` is not a prefix of `os::fsck`
+            target.starts_with(prefix.as_str())
+                && (prefix.is_empty()
+                    || target.len() == prefix.len()
+                    || target[prefix.len()..].starts_with("::"))
+        })
+        .map_or(DEFAULT_LEVEL, |&(_, level)| level)
+}
+
+/// ANSI color of the messages of `level`
+fn color(level: Level) -> u8 {
+    match level {
+        Level::Error => 31,
+        Level::Warn => 93,
+        Level::Info => 34,
+        Level::Debug => 32,
+        Level::Trace => 90,
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() == Level::Error || metadata.level() <= level_of(metadata.target())
+    }
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let us = ticks_to_us(get_time());
+        println!(
+            "\x1b[{}m[{:>5}.{:06} {}] {:<5} {}: {}\x1b[0m",
+            color(record.level()),
+            us / 1_000_000,
+            us % 1_000_000,
+            hart_id(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+    }
+    fn flush(&self) {}
+}
+
+/// Print records from now on, up to `INFO` until [`set_filter`]
+pub fn init() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(DEFAULT_LEVEL);
+}
+
+/// Filter records by the directives of `spec`, the value of `log=`.
+/// Directives with unknown levels are ignored.
+pub fn set_filter(spec: &str) {
+    let mut directives: Vec<(String, LevelFilter)> = spec
+        .split(',')
+        .filter(|directive| !directive.is_empty())
+        .filter_map(|directive| {
+            let (target, level) = directive.rsplit_once('=').unwrap_or(("", directive));
+            Some((String::from(target), level.parse().ok()?))
+        })
+        .collect();
+    // the longest prefix is the most specific one
+    directives.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+    let default = if directives.iter().any(|(target, _)| target.is_empty()) {
+        LevelFilter::Error
+    } else {
+        DEFAULT_LEVEL
+    };
+    let max = directives
+        .iter()
+        .map(|&(_, level)| level)
+        .fold(default, Ord::max);
+    DIRECTIVES.call_once(|| directives);
+    log::set_max_level(max);
+}
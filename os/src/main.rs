@@ -6,17 +6,27 @@
 //! - [`trap`]: Handles all cases of switching from userspace to the kernel
 //! - [`task`]: Task management
 //! - [`syscall`]: System call handling and implementation
-//! - [`mm`]: Address map using SV39
+//! - [`mm`]: Address map using SV39 or SV48
 //! - [`sync`]: Wrap a static data structure inside it so that we are able to access it without any `unsafe`.
 //! - [`fs`]: Separate user from file system with some structures
+//! - [`hart`]: Hart ids and bringing up the other harts
+//! - [`net`]: UDP over IPv4 on the network device
+//! - [`fdt`]: Memory, harts and devices of the machine from its device tree
+//! - [`random`]: The entropy pool behind `getrandom` and `/dev/urandom`
+//! - [`logging`]: Kernel log, filtered by module from the command line
+//! - [`cmdline`]: Options given to the kernel by the bootloader
+//! - [`ktest`]: Kernel unit tests, run in QEMU with `ktest` on the command line
+//! - [`monitor`]: Debug monitor on the UARTs, behind `Ctrl-]`
+//! - [`kcov`]: Coverage of the kernel code run by tasks, built with `KCOV=1`
+//! - [`kaslr`]: Moving the kernel to a random place at boot
 //!
 //! The operating system also starts in this module. Kernel code starts
-//! executing from `entry.asm`, after which [`rust_main()`] is called to
-//! initialize various pieces of functionality. (See its source code for
-//! details.)
+//! executing from `entry.asm`, which moves the kernel to a random place in
+//! memory, after which [`rust_main()`] is called to initialize various pieces
+//! of functionality. (See its source code for details.)
 //!
-//! We then call [`task::run_tasks()`] and for the first time go to
-//! userspace.
+//! We then start the other harts, which enter [`rust_main_secondary()`], and
+//! call [`task::run_tasks()`] on every hart to go to userspace.
 
 #![deny(missing_docs)]
 #![deny(warnings)]
@@ -30,17 +40,37 @@ extern crate alloc;
 
 #[macro_use]
 extern crate bitflags;
+#[macro_use]
+extern crate log;
 
+#[cfg(feature = "board_qemu")]
 #[path = "boards/qemu.rs"]
 mod board;
+#[cfg(feature = "board_sifive_u")]
+#[path = "boards/sifive_u.rs"]
+mod board;
+#[cfg(feature = "board_visionfive2")]
+#[path = "boards/visionfive2.rs"]
+mod board;
 
 #[macro_use]
 mod console;
+pub mod backtrace;
+pub mod cmdline;
 mod config;
 mod drivers;
+pub mod fdt;
 pub mod fs;
+pub mod hart;
+pub mod kaslr;
+pub mod kcov;
+pub mod ktest;
 pub mod lang_items;
+pub mod logging;
 pub mod mm;
+pub mod monitor;
+pub mod net;
+pub mod random;
 pub mod sbi;
 pub mod sync;
 pub mod syscall;
@@ -64,17 +94,53 @@ fn clear_bss() {
 }
 
 #[no_mangle]
-/// the rust entry-point of os
-pub fn rust_main() -> ! {
+/// the rust entry-point of os, given the id of the boot hart and the physical
+/// address of the device tree by the SBI, and the offset the kernel moved by
+pub fn rust_main(_hart_id: usize, dtb: usize, kaslr_offset: usize) -> ! {
     clear_bss();
-    println!("[kernel] Hello, world!");
+    hart::set_online();
+    logging::init();
+    info!("Hello, world!");
+    kaslr::init(kaslr_offset);
+    mm::init_heap();
+    fdt::init(dtb);
+    logging::set_filter(cmdline::get("log").unwrap_or(""));
+    cmdline::init();
     mm::init();
     mm::remap_test();
+    timer::init();
+    task::init();
+    fs::init();
+    drivers::init();
+    random::init();
+    net::init();
+    drivers::init_hart();
     trap::init();
     trap::enable_timer_interrupt();
-    timer::set_next_trigger();
+    trap::enable_software_interrupt();
+    trap::enable_external_interrupt();
+    if ktest::enabled() {
+        ktest::run();
+    }
     fs::list_apps();
     task::add_initproc();
+    task::workqueue_init();
+    hart::start_secondary_harts();
     task::run_tasks();
     panic!("Unreachable in rust_main!");
 }
+
+#[no_mangle]
+/// the rust entry-point of the harts started by [`hart::start_secondary_harts()`]
+pub fn rust_main_secondary() -> ! {
+    hart::set_online();
+    mm::init_secondary();
+    drivers::init_hart();
+    trap::init();
+    trap::enable_timer_interrupt();
+    trap::enable_software_interrupt();
+    trap::enable_external_interrupt();
+    info!("hart {} online", hart::hart_id());
+    task::run_tasks();
+    panic!("Unreachable in rust_main_secondary!");
+}
@@ -1,12 +1,11 @@
 //! Implementation of physical and virtual address and page number.
+use super::paging::{paging_mode, MAX_PAGING_LEVELS};
 use super::PageTableEntry;
 use crate::config::{PAGE_SIZE, PAGE_SIZE_BITS};
 use core::fmt::{self, Debug, Formatter};
 
 const PA_WIDTH_SV39: usize = 56;
-const VA_WIDTH_SV39: usize = 39;
 const PPN_WIDTH_SV39: usize = PA_WIDTH_SV39 - PAGE_SIZE_BITS;
-const VPN_WIDTH_SV39: usize = VA_WIDTH_SV39 - PAGE_SIZE_BITS;
 
 /// Definitions
 #[repr(C)]
@@ -67,12 +66,12 @@ impl From<usize> for PhysPageNum {
 }
 impl From<usize> for VirtAddr {
     fn from(v: usize) -> Self {
-        Self(v & ((1 << VA_WIDTH_SV39) - 1))
+        Self(v & ((1 << paging_mode().va_width()) - 1))
     }
 }
 impl From<usize> for VirtPageNum {
     fn from(v: usize) -> Self {
-        Self(v & ((1 << VPN_WIDTH_SV39) - 1))
+        Self(v & ((1 << (paging_mode().va_width() - PAGE_SIZE_BITS)) - 1))
     }
 }
 impl From<PhysAddr> for usize {
@@ -87,8 +86,9 @@ impl From<PhysPageNum> for usize {
 }
 impl From<VirtAddr> for usize {
     fn from(v: VirtAddr) -> Self {
-        if v.0 >= (1 << (VA_WIDTH_SV39 - 1)) {
-            v.0 | (!((1 << VA_WIDTH_SV39) - 1))
+        let va_width = paging_mode().va_width();
+        if v.0 >= (1 << (va_width - 1)) {
+            v.0 | (!((1 << va_width) - 1))
         } else {
             v.0
         }
@@ -160,11 +160,13 @@ impl From<PhysPageNum> for PhysAddr {
 }
 
 impl VirtPageNum {
-    ///Return VPN 3 level index
-    pub fn indexes(&self) -> [usize; 3] {
+    ///Return VPN index of every level from the root, only the first
+    ///`paging_mode().levels()` ones are used
+    pub fn indexes(&self) -> [usize; MAX_PAGING_LEVELS] {
+        let levels = paging_mode().levels();
         let mut vpn = self.0;
-        let mut idx = [0usize; 3];
-        for i in (0..3).rev() {
+        let mut idx = [0usize; MAX_PAGING_LEVELS];
+        for i in (0..levels).rev() {
             idx[i] = vpn & 511;
             vpn >>= 9;
         }
@@ -0,0 +1,236 @@
+//! Heap allocations counted by call site, for leak hunting
+//!
+//! With `allocstat` on the command line, each heap allocation is counted to
+//! its call site, the innermost return addresses of its stack, and its size
+//! is kept until it is freed. Only so many allocations are tracked at once,
+//! further ones are counted as untracked, and so are those of sites beyond
+//! the table of sites. `/proc/allocstat` lists the sites by the bytes they
+//! hold, each shown by its innermost frame out of the allocator.
+use crate::backtrace::{lookup, return_addresses};
+use crate::cmdline;
+use crate::sync::{Once, SpinNoIrqLock};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Return addresses telling a call site apart, deep enough to get out of
+/// the `alloc` crate
+const SITE_DEPTH: usize = 12;
+/// Slots of the table of sites, a power of 2
+const SITE_SLOTS: usize = 1 << 9;
+/// Slots of the table of the allocations not freed yet, a power of 2
+const LIVE_SLOTS: usize = 1 << 15;
+
+/// A call site
+#[derive(Copy, Clone)]
+struct Site {
+    /// return addresses, all 0 for a free slot
+    addrs: [usize; SITE_DEPTH],
+    allocs: usize,
+    frees: usize,
+    /// bytes of its allocations not freed yet
+    live_bytes: usize,
+}
+
+/// An allocation not freed yet
+#[derive(Copy, Clone)]
+struct Live {
+    /// address, 0 for a free slot
+    ptr: usize,
+    size: usize,
+    /// slot of its site
+    site: usize,
+}
+
+/// Open-addressing tables of the sites and of the live allocations, both
+/// probed linearly and filled up to 3/4
+struct AllocStats {
+    sites: Vec<Site>,
+    nr_sites: usize,
+    live: Vec<Live>,
+    nr_live: usize,
+    /// allocations not counted to a site
+    untracked: usize,
+}
+
+static ALLOC_STATS: Once<SpinNoIrqLock<AllocStats>> = Once::new();
+
+/// Home slot of `key` in a table of `slots` slots
+fn home(key: usize, slots: usize) -> usize {
+    (key.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) & (slots - 1)
+}
+
+impl AllocStats {
+    /// Slot of the site `addrs`, added if new, `None` if the table is full
+    fn site(&mut self, addrs: &[usize; SITE_DEPTH]) -> Option<usize> {
+        // no frame is found on an unknown stack
+        if addrs[0] == 0 {
+            return None;
+        }
+        let key = addrs.iter().fold(0, |key, &addr| key ^ addr.rotate_left(7));
+        let mut slot = home(key, SITE_SLOTS);
+        loop {
+            let site = &mut self.sites[slot];
+            if site.addrs == *addrs {
+                return Some(slot);
+            }
+            if site.addrs[0] == 0 {
+                if self.nr_sites >= SITE_SLOTS / 4 * 3 {
+                    return None;
+                }
+                site.addrs = *addrs;
+                self.nr_sites += 1;
+                return Some(slot);
+            }
+            slot = (slot + 1) & (SITE_SLOTS - 1);
+        }
+    }
+    fn alloc(&mut self, ptr: usize, size: usize, addrs: &[usize; SITE_DEPTH]) {
+        let site = match self.site(addrs) {
+            Some(site) if self.nr_live < LIVE_SLOTS / 4 * 3 => site,
+            _ => {
+                self.untracked += 1;
+                return;
+            }
+        };
+        self.sites[site].allocs += 1;
+        self.sites[site].live_bytes += size;
+        let mut slot = home(ptr, LIVE_SLOTS);
+        while self.live[slot].ptr != 0 {
+            slot = (slot + 1) & (LIVE_SLOTS - 1);
+        }
+        self.live[slot] = Live { ptr, size, site };
+        self.nr_live += 1;
+    }
+    fn free(&mut self, ptr: usize) {
+        let mut slot = home(ptr, LIVE_SLOTS);
+        loop {
+            match self.live[slot].ptr {
+                // allocated before the statistics were on, or untracked
+                0 => return,
+                found if found == ptr => break,
+                _ => slot = (slot + 1) & (LIVE_SLOTS - 1),
+            }
+        }
+        let Live { size, site, .. } = self.live[slot];
+        self.sites[site].frees += 1;
+        self.sites[site].live_bytes -= size;
+        self.nr_live -= 1;
+        // move back the following entries that may no longer be found past the hole
+        let mut hole = slot;
+        let mut next = (hole + 1) & (LIVE_SLOTS - 1);
+        while self.live[next].ptr != 0 {
+            let home = home(self.live[next].ptr, LIVE_SLOTS);
+            let from_home = next.wrapping_sub(home) & (LIVE_SLOTS - 1);
+            let from_hole = next.wrapping_sub(hole) & (LIVE_SLOTS - 1);
+            if from_home >= from_hole {
+                self.live[hole] = self.live[next];
+                hole = next;
+            }
+            next = (next + 1) & (LIVE_SLOTS - 1);
+        }
+        self.live[hole].ptr = 0;
+    }
+}
+
+/// Start counting allocations if `allocstat` is on the command line
+pub fn init() {
+    if !cmdline::flag("allocstat") {
+        return;
+    }
+    let sites = vec![
+        Site {
+            addrs: [0; SITE_DEPTH],
+            allocs: 0,
+            frees: 0,
+            live_bytes: 0,
+        };
+        SITE_SLOTS
+    ];
+    let live = vec![
+        Live {
+            ptr: 0,
+            size: 0,
+            site: 0,
+        };
+        LIVE_SLOTS
+    ];
+    ALLOC_STATS.call_once(|| {
+        SpinNoIrqLock::new(AllocStats {
+            sites,
+            nr_sites: 0,
+            live,
+            nr_live: 0,
+            untracked: 0,
+        })
+    });
+    info!("counting heap allocations by call site");
+}
+
+/// Count the allocation of `size` bytes at `ptr` to the call site of the
+/// caller. Nothing is allocated, the caller being the heap allocator.
+pub fn record_alloc(ptr: *mut u8, size: usize) {
+    if let Some(stats) = ALLOC_STATS.get() {
+        let mut addrs = [0; SITE_DEPTH];
+        return_addresses(&mut addrs);
+        stats.lock().alloc(ptr as usize, size, &addrs);
+    }
+}
+
+/// Count freeing the allocation at `ptr`
+pub fn record_free(ptr: *mut u8) {
+    if let Some(stats) = ALLOC_STATS.get() {
+        stats.lock().free(ptr as usize);
+    }
+}
+
+/// Whether `symbol` is in the allocator or the `alloc` crate, called on the
+/// way to the heap rather than being a call site
+fn in_allocator(symbol: &str) -> bool {
+    symbol.starts_with("__rust_")
+        || symbol.starts_with("__rg_")
+        || symbol.starts_with("alloc::")
+        || symbol.starts_with("<alloc::")
+        || symbol.contains(" as alloc::")
+        || symbol.contains("os::mm::heap_allocator")
+        || symbol.contains("os::mm::alloc_stats")
+}
+
+/// The innermost frame of `addrs` out of the allocator, with its symbol
+fn site_name(addrs: &[usize; SITE_DEPTH]) -> String {
+    let frame = addrs.iter().take_while(|&&addr| addr != 0).find_map(|&ra| {
+        let (name, offset) = lookup(ra - 1)?;
+        (!in_allocator(name)).then(|| (ra, name, offset))
+    });
+    match frame {
+        Some((ra, name, offset)) => format!("{:#x} {}+{:#x}", ra, name, offset + 1),
+        // no symbols, or a stack deeper than kept
+        None => format!("{:#x}", addrs[0]),
+    }
+}
+
+/// The call sites by the bytes they hold, most first, `None` if allocations
+/// are not counted
+pub fn alloc_stats() -> Option<String> {
+    let stats = ALLOC_STATS.get()?;
+    // copied out first, as allocating takes the lock of the statistics
+    let mut sites: Vec<Site> = Vec::with_capacity(SITE_SLOTS);
+    let stats = stats.lock();
+    let untracked = stats.untracked;
+    sites.extend(stats.sites.iter().filter(|site| site.allocs != 0));
+    drop(stats);
+    sites.sort_unstable_by(|a, b| b.live_bytes.cmp(&a.live_bytes));
+    let mut content = String::from("     bytes     live   allocs  site\n");
+    for site in sites.iter() {
+        content += &format!(
+            "{:>10} {:>8} {:>8}  {}\n",
+            site.live_bytes,
+            site.allocs - site.frees,
+            site.allocs,
+            site_name(&site.addrs)
+        );
+    }
+    content += &format!("untracked allocations: {}\n", untracked);
+    Some(content)
+}
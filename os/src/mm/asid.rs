@@ -0,0 +1,91 @@
+//! Implementation of [`AsidAllocator`]
+//!
+//! Every user address space gets its own ASID, which tags its TLB entries so
+//! that switching `satp` no longer needs a full `sfence.vma`. ASID 0 belongs to
+//! the kernel space and is also handed out when ASIDs run out; address spaces
+//! with ASID 0 are flushed on every switch in `trap.S`.
+use super::tlb::flush_asid;
+use crate::sync::ExclusiveCell;
+use alloc::vec::Vec;
+use core::arch::asm;
+use lazy_static::*;
+
+/// Position of the ASID field in `satp`
+pub const SATP_ASID_SHIFT: usize = 44;
+/// Widest ASID field of Sv39 and Sv48
+const SATP_ASID_MASK: usize = 0xffff;
+
+///Asid Allocator struct
+pub struct AsidAllocator {
+    current: usize,
+    max: usize,
+    recycled: Vec<usize>,
+}
+
+impl AsidAllocator {
+    ///Create an empty `AsidAllocator` without any ASID to hand out
+    pub fn new() -> Self {
+        AsidAllocator {
+            current: 1,
+            max: 0,
+            recycled: Vec::new(),
+        }
+    }
+    ///Allocate an asid, 0 if run out of asids
+    pub fn alloc(&mut self) -> AsidHandle {
+        if let Some(asid) = self.recycled.pop() {
+            // drop stale entries left by the previous owner
+            flush_asid(asid);
+            AsidHandle(asid)
+        } else if self.current <= self.max {
+            self.current += 1;
+            AsidHandle(self.current - 1)
+        } else {
+            AsidHandle(0)
+        }
+    }
+    ///Recycle an asid
+    pub fn dealloc(&mut self, asid: usize) {
+        assert!(asid < self.current);
+        assert!(
+            !self.recycled.iter().any(|&v| v == asid),
+            "asid {} has been deallocated!",
+            asid
+        );
+        self.recycled.push(asid);
+    }
+}
+
+lazy_static! {
+    pub static ref ASID_ALLOCATOR: ExclusiveCell<AsidAllocator> =
+        ExclusiveCell::new(AsidAllocator::new());
+}
+///Bind asid lifetime to `AsidHandle`
+pub struct AsidHandle(pub usize);
+
+impl Drop for AsidHandle {
+    fn drop(&mut self) {
+        if self.0 != 0 {
+            ASID_ALLOCATOR.exclusive_access().dealloc(self.0);
+        }
+    }
+}
+///Allocate an asid from ASID_ALLOCATOR
+pub fn asid_alloc() -> AsidHandle {
+    ASID_ALLOCATOR.exclusive_access().alloc()
+}
+
+/// Probe how many ASID bits the hart implements by writing all ones to the field
+pub fn init_asid_allocator() {
+    let satp: usize;
+    let probed: usize;
+    unsafe {
+        asm!("csrr {}, satp", out(reg) satp);
+        asm!("csrw satp, {}", in(reg) satp | SATP_ASID_MASK << SATP_ASID_SHIFT);
+        asm!("csrr {}, satp", out(reg) probed);
+        asm!("csrw satp, {}", "sfence.vma", in(reg) satp);
+    }
+    let max = probed >> SATP_ASID_SHIFT & SATP_ASID_MASK;
+    ASID_ALLOCATOR.exclusive_access().max = max;
+    info!("{} ASIDs available.", max);
+}
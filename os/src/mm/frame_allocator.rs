@@ -1,27 +1,77 @@
 //! Implementation of [`FrameAllocator`] which
 //! controls all the frames in the operating system.
 use super::{PhysAddr, PhysPageNum};
-use crate::config::MEMORY_END;
-use crate::sync::UPSafeCell;
+use crate::fdt::MACHINE;
+use crate::kaslr;
+use crate::kernel_test;
+use crate::sync::{Lazy, Once, SpinNoIrqLock, TicketLock};
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
-use lazy_static::*;
+
+/// Subsystems that frames are accounted to
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FrameKind {
+    /// Data pages of user address spaces
+    User,
+    /// Page-table pages
+    PageTable,
+    /// Everything else used by the kernel, like kernel stacks and DMA buffers
+    Kernel,
+    /// Frames given back to the host by the memory balloon
+    Balloon,
+}
+
+/// Statistics of the frame allocator, counted in frames
+#[derive(Copy, Clone, Default, Debug)]
+pub struct FrameStats {
+    /// Number of frames managed by the allocator
+    pub total: usize,
+    /// Number of frames currently allocated
+    pub used: usize,
+    /// Maximum of `used` ever observed
+    pub peak_used: usize,
+    /// Number of frames held by [`FrameKind::User`] trackers
+    pub user: usize,
+    /// Number of frames held by [`FrameKind::PageTable`] trackers
+    pub page_table: usize,
+    /// Number of frames held by [`FrameKind::Kernel`] trackers
+    pub kernel: usize,
+    /// Number of frames held by [`FrameKind::Balloon`] trackers
+    pub balloon: usize,
+}
+
+impl FrameStats {
+    /// Number of frames still available
+    pub fn free(&self) -> usize {
+        self.total - self.used
+    }
+    fn kind_mut(&mut self, kind: FrameKind) -> &mut usize {
+        match kind {
+            FrameKind::User => &mut self.user,
+            FrameKind::PageTable => &mut self.page_table,
+            FrameKind::Kernel => &mut self.kernel,
+            FrameKind::Balloon => &mut self.balloon,
+        }
+    }
+}
 
 /// manage a frame which has the same lifecycle as the tracker
 pub struct FrameTracker {
     ///
     pub ppn: PhysPageNum,
+    kind: FrameKind,
 }
 
 impl FrameTracker {
-    ///Create an empty `FrameTracker`
-    pub fn new(ppn: PhysPageNum) -> Self {
+    ///Create an empty `FrameTracker` accounted to `kind`
+    pub fn new(ppn: PhysPageNum, kind: FrameKind) -> Self {
         // page cleaning
         let bytes_array = ppn.get_bytes_array();
         for i in bytes_array {
             *i = 0;
         }
-        Self { ppn }
+        *FRAME_STATS.lock().kind_mut(kind) += 1;
+        Self { ppn, kind }
     }
 }
 
@@ -33,6 +83,7 @@ impl Debug for FrameTracker {
 
 impl Drop for FrameTracker {
     fn drop(&mut self) {
+        *FRAME_STATS.lock().kind_mut(self.kind) -= 1;
         frame_dealloc(self.ppn);
     }
 }
@@ -40,20 +91,39 @@ impl Drop for FrameTracker {
 trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhysPageNum>;
+    /// Allocate `pages` consecutive frames, return the first
+    fn alloc_contiguous(&mut self, pages: usize) -> Option<PhysPageNum>;
     fn dealloc(&mut self, ppn: PhysPageNum);
 }
 /// an implementation for frame allocator
 pub struct StackFrameAllocator {
     current: usize,
     end: usize,
-    recycled: Vec<usize>,
+    /// first freed frame, 0 if none, each holding the next one in its first
+    /// word, so that freeing a frame never allocates from the heap, which may
+    /// take frames to grow
+    recycled: usize,
 }
 
 impl StackFrameAllocator {
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
         self.current = l.0;
         self.end = r.0;
-        println!("last {} Physical Frames.", self.end - self.current);
+        info!("last {} Physical Frames.", self.end - self.current);
+    }
+    /// Add frames `[l, r)` below those never allocated as freed ones
+    pub fn add_freed(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        for ppn in l.0..r.0 {
+            *PhysPageNum(ppn).get_mut::<usize>() = self.recycled;
+            self.recycled = ppn;
+        }
+    }
+    /// Whether frame `ppn` is among the freed ones
+    fn is_recycled(&self, ppn: usize) -> bool {
+        let next = |&recycled: &usize| Some(*PhysPageNum(recycled).get_mut::<usize>());
+        core::iter::successors(Some(self.recycled), next)
+            .take_while(|&recycled| recycled != 0)
+            .any(|recycled| recycled == ppn)
     }
 }
 impl FrameAllocator for StackFrameAllocator {
@@ -61,12 +131,14 @@ impl FrameAllocator for StackFrameAllocator {
         Self {
             current: 0,
             end: 0,
-            recycled: Vec::new(),
+            recycled: 0,
         }
     }
     fn alloc(&mut self) -> Option<PhysPageNum> {
-        if let Some(ppn) = self.recycled.pop() {
-            Some(ppn.into())
+        if self.recycled != 0 {
+            let ppn = PhysPageNum(self.recycled);
+            self.recycled = *ppn.get_mut::<usize>();
+            Some(ppn)
         } else if self.current == self.end {
             None
         } else {
@@ -74,44 +146,124 @@ impl FrameAllocator for StackFrameAllocator {
             Some((self.current - 1).into())
         }
     }
+    fn alloc_contiguous(&mut self, pages: usize) -> Option<PhysPageNum> {
+        // only from the frames never allocated, the recycled ones are scattered
+        if self.end - self.current < pages {
+            return None;
+        }
+        self.current += pages;
+        Some((self.current - pages).into())
+    }
     fn dealloc(&mut self, ppn: PhysPageNum) {
         let ppn = ppn.0;
-        // validity check
-        if ppn >= self.current || self.recycled.iter().any(|&v| v == ppn) {
+        // validity check, walking the freed frames in debug builds only
+        if ppn >= self.current || (cfg!(debug_assertions) && self.is_recycled(ppn)) {
             panic!("Frame ppn={:#x} has not been allocated!", ppn);
         }
         // recycle
-        self.recycled.push(ppn);
+        *PhysPageNum(ppn).get_mut::<usize>() = self.recycled;
+        self.recycled = ppn;
     }
 }
 
 type FrameAllocatorImpl = StackFrameAllocator;
 
-lazy_static! {
-    /// frame allocator instance through lazy_static!
-    pub static ref FRAME_ALLOCATOR: UPSafeCell<FrameAllocatorImpl> =
-        unsafe { UPSafeCell::new(FrameAllocatorImpl::new()) };
-}
-/// initiate the frame allocator using `ekernel` and `MEMORY_END`
+/// frame allocator instance, set up by [`init_frame_allocator`]. Frames may be
+/// freed by interrupt handlers, e.g. along with finished DMA requests. Every hart
+/// allocates from it, so they take turns with a ticket lock.
+pub static FRAME_ALLOCATOR: Once<SpinNoIrqLock<FrameAllocatorImpl, TicketLock>> = Once::new();
+/// frees frames once the allocator runs out of them, returning whether it
+/// freed any, set up by [`set_reclaim`]
+static RECLAIM: Once<fn() -> bool> = Once::new();
+/// frame statistics instance
+static FRAME_STATS: Lazy<SpinNoIrqLock<FrameStats>> =
+    Lazy::new(|| SpinNoIrqLock::new(FrameStats::default()));
+/// initiate the frame allocator using `ekernel` and the end of memory, and
+/// the memory the kernel was loaded in if it moved, see [`crate::kaslr`]
 pub fn init_frame_allocator() {
     extern "C" {
         fn ekernel();
     }
-    FRAME_ALLOCATOR.exclusive_access().init(
-        PhysAddr::from(ekernel as usize).ceil(),
-        PhysAddr::from(MEMORY_END).floor(),
-    );
+    let start: PhysPageNum = PhysAddr::from(ekernel as usize).ceil();
+    let end: PhysPageNum = PhysAddr::from(MACHINE.memory_end).floor();
+    let mut allocator = FrameAllocatorImpl::new();
+    allocator.init(start, end);
+    let loaded = kaslr::freed();
+    let loaded_start: PhysPageNum = PhysAddr::from(loaded.start).floor();
+    let loaded_end: PhysPageNum = PhysAddr::from(loaded.end).floor();
+    allocator.add_freed(loaded_start, loaded_end);
+    FRAME_ALLOCATOR.call_once(|| SpinNoIrqLock::new(allocator));
+    FRAME_STATS.lock().total = end.0 - start.0 + loaded_end.0 - loaded_start.0;
 }
-/// allocate a frame
+/// allocate a frame for the kernel
 pub fn frame_alloc() -> Option<FrameTracker> {
-    FRAME_ALLOCATOR
-        .exclusive_access()
-        .alloc()
-        .map(FrameTracker::new)
+    frame_alloc_for(FrameKind::Kernel)
+}
+/// Free frames by `reclaim` whenever the allocator runs out of them, e.g. by
+/// deflating the memory balloon
+pub fn set_reclaim(reclaim: fn() -> bool) {
+    RECLAIM.call_once(|| reclaim);
+}
+/// allocate a frame accounted to `kind`
+pub fn frame_alloc_for(kind: FrameKind) -> Option<FrameTracker> {
+    let mut ppn = FRAME_ALLOCATOR.lock().alloc();
+    // the balloon is not deflated to make room for itself
+    if ppn.is_none()
+        && kind != FrameKind::Balloon
+        && RECLAIM.get().map_or(false, |reclaim| reclaim())
+    {
+        ppn = FRAME_ALLOCATOR.lock().alloc();
+    }
+    let ppn = ppn?;
+    let mut stats = FRAME_STATS.lock();
+    stats.used += 1;
+    stats.peak_used = stats.peak_used.max(stats.used);
+    drop(stats);
+    Some(FrameTracker::new(ppn, kind))
+}
+/// allocate `pages` consecutive frames for the kernel, e.g. a DMA buffer
+/// larger than a page
+pub fn frame_alloc_contiguous(pages: usize) -> Option<Vec<FrameTracker>> {
+    let first = FRAME_ALLOCATOR.lock().alloc_contiguous(pages)?;
+    let mut stats = FRAME_STATS.lock();
+    stats.used += pages;
+    stats.peak_used = stats.peak_used.max(stats.used);
+    drop(stats);
+    Some(
+        (first.0..first.0 + pages)
+            .map(|ppn| FrameTracker::new(PhysPageNum(ppn), FrameKind::Kernel))
+            .collect(),
+    )
+}
+/// allocate `pages` consecutive frames for the kernel heap for good, without
+/// allocating from the heap, `None` if there are none or the frame allocator
+/// is not set up yet. A single frame may be a recycled one.
+pub(super) fn frame_alloc_heap(pages: usize) -> Option<PhysPageNum> {
+    let mut allocator = FRAME_ALLOCATOR.get()?.lock();
+    let first = if pages == 1 {
+        allocator.alloc()
+    } else {
+        allocator.alloc_contiguous(pages)
+    }?;
+    drop(allocator);
+    let mut stats = FRAME_STATS.lock();
+    stats.used += pages;
+    stats.peak_used = stats.peak_used.max(stats.used);
+    stats.kernel += pages;
+    Some(first)
 }
 /// deallocate a frame
 pub fn frame_dealloc(ppn: PhysPageNum) {
-    FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
+    FRAME_ALLOCATOR.lock().dealloc(ppn);
+    FRAME_STATS.lock().used -= 1;
+}
+/// Number of times a hart waited for the frame allocator
+pub fn frame_allocator_contentions() -> usize {
+    FRAME_ALLOCATOR.contentions()
+}
+/// Report the statistics of the frame allocator
+pub fn frame_stats() -> FrameStats {
+    *FRAME_STATS.lock()
 }
 
 #[allow(unused)]
@@ -120,15 +272,16 @@ pub fn frame_allocator_test() {
     let mut v: Vec<FrameTracker> = Vec::new();
     for i in 0..5 {
         let frame = frame_alloc().unwrap();
-        println!("{:?}", frame);
+        debug!("{:?}", frame);
         v.push(frame);
     }
     v.clear();
     for i in 0..5 {
         let frame = frame_alloc().unwrap();
-        println!("{:?}", frame);
+        debug!("{:?}", frame);
         v.push(frame);
     }
     drop(v);
-    println!("frame_allocator_test passed!");
+    info!("frame_allocator_test passed!");
 }
+kernel_test!(frame_allocator_test);
@@ -1,8 +1,41 @@
 //! The global allocator
-use crate::config::KERNEL_HEAP_SIZE;
+//!
+//! The heap starts as an array in `.bss`, which the kernel allocates from
+//! before it knows the memory of the machine. Once the heap is full, it grows
+//! by frames of the frame allocator, never given back.
+use super::alloc_stats::{record_alloc, record_free};
+use super::frame_allocator::frame_alloc_heap;
+use super::slab::{slab_alloc, slab_dealloc};
+use super::PhysAddr;
+use crate::config::{KERNEL_HEAP_SIZE, PAGE_SIZE};
+use crate::kernel_test;
 use buddy_system_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::null_mut;
+
+/// kernel allocator: slab caches first, buddy heap for everything else
+struct KernelAllocator;
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = slab_alloc(layout).unwrap_or_else(|| heap_alloc(layout));
+        if !ptr.is_null() {
+            record_alloc(ptr, layout.size());
+        }
+        ptr
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        record_free(ptr);
+        if !slab_dealloc(ptr, layout) {
+            HEAP_ALLOCATOR.dealloc(ptr, layout);
+        }
+    }
+}
 
 #[global_allocator]
+/// global allocator instance
+static KERNEL_ALLOCATOR: KernelAllocator = KernelAllocator;
+
 /// heap allocator instance
 static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
 
@@ -21,19 +54,72 @@ pub fn init_heap() {
             .init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
     }
 }
+/// Pages the heap grows by at least
+const HEAP_GROW_PAGES: usize = 64;
+
+/// allocate `layout` from the heap, grown as needed, null if out of memory
+fn heap_alloc(layout: Layout) -> *mut u8 {
+    loop {
+        if let Ok(ptr) = HEAP_ALLOCATOR.lock().alloc(layout) {
+            return ptr.as_ptr();
+        }
+        if !grow_heap(layout) {
+            return null_mut();
+        }
+    }
+}
+/// Add frames to the heap for `layout` to fit, false if there are none
+fn grow_heap(layout: Layout) -> bool {
+    // any range of twice the size holds a block of the size aligned to it
+    let size = layout.size().max(layout.align()).next_power_of_two();
+    let pages = (2 * size / PAGE_SIZE).max(HEAP_GROW_PAGES);
+    // scattered frames are left for small allocations
+    let (first, pages) = match frame_alloc_heap(pages) {
+        Some(first) => (first, pages),
+        None if size <= PAGE_SIZE => match frame_alloc_heap(1) {
+            Some(first) => (first, 1),
+            None => return false,
+        },
+        None => return false,
+    };
+    // physical memory is identity mapped in kernel space
+    let start = PhysAddr::from(first).0;
+    unsafe {
+        HEAP_ALLOCATOR
+            .lock()
+            .add_to_heap(start, start + pages * PAGE_SIZE);
+    }
+    debug!("heap grown by {} pages", pages);
+    true
+}
+/// allocate a `size`-aligned block of `size` bytes for a slab, null if out of memory
+pub(super) fn heap_alloc_slab(size: usize) -> *mut u8 {
+    heap_alloc(Layout::from_size_align(size, size).unwrap())
+}
+
+/// Statistics of the kernel heap
+#[derive(Copy, Clone, Debug)]
+pub struct HeapStats {
+    /// Size of the heap in bytes, grown frames included
+    pub total_bytes: usize,
+    /// Bytes currently allocated, including slabs and allocator overhead
+    pub used_bytes: usize,
+}
+/// Report the statistics of the kernel heap
+pub fn heap_stats() -> HeapStats {
+    let heap = HEAP_ALLOCATOR.lock();
+    HeapStats {
+        total_bytes: heap.stats_total_bytes(),
+        used_bytes: heap.stats_alloc_actual(),
+    }
+}
 
-#[allow(unused)]
+/// a simple test for the kernel heap
 pub fn heap_test() {
     use alloc::boxed::Box;
     use alloc::vec::Vec;
-    extern "C" {
-        fn sbss();
-        fn ebss();
-    }
-    let bss_range = sbss as usize..ebss as usize;
     let a = Box::new(5);
     assert_eq!(*a, 5);
-    assert!(bss_range.contains(&(a.as_ref() as *const _ as usize)));
     drop(a);
     let mut v: Vec<usize> = Vec::new();
     for i in 0..500 {
@@ -42,7 +128,14 @@ pub fn heap_test() {
     for (i, val) in v.iter().take(500).enumerate() {
         assert_eq!(*val, i);
     }
-    assert!(bss_range.contains(&(v.as_ptr() as usize)));
     drop(v);
-    println!("heap_test passed!");
+    // larger than the heap in `.bss`, so only fits once the heap has grown
+    let total = heap_stats().total_bytes;
+    let mut big: Vec<u8> = Vec::with_capacity(KERNEL_HEAP_SIZE);
+    big.resize(KERNEL_HEAP_SIZE, 0xa5);
+    assert!(big.iter().all(|&byte| byte == 0xa5));
+    assert!(heap_stats().total_bytes > total);
+    drop(big);
+    info!("heap_test passed!");
 }
+kernel_test!(heap_test);
@@ -1,15 +1,25 @@
 //! Implementation of [`MapArea`] and [`MemorySet`].
-use super::{frame_alloc, FrameTracker};
+use super::asid::{asid_alloc, AsidHandle, SATP_ASID_SHIFT};
+use super::paging::user_space_end;
+use super::tlb::{flush_local_page, flush_page, flush_range};
+use super::{frame_alloc_for, FrameKind, FrameTracker};
 use super::{PTEFlags, PageTable, PageTableEntry};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use super::{StepByOne, VPNRange};
-use crate::config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE};
-use crate::sync::UPSafeCell;
+use crate::cmdline;
+use crate::config::{
+    BRK_RANDOM_SIZE, MMAP_BASE, PAGE_SIZE, SIGRETURN_TRAMPOLINE, TRAMPOLINE, USER_STACK_BASE,
+};
+use crate::fdt::MACHINE;
+use crate::kaslr;
+use crate::kernel_test;
+use crate::random::random_u32;
+use crate::sync::{ExclusiveCell, Once};
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::arch::asm;
-use lazy_static::*;
+use core::ops::Range;
 use riscv::register::satp;
 
 extern "C" {
@@ -23,46 +33,137 @@ extern "C" {
     fn ebss();
     fn ekernel();
     fn strampoline();
+    fn ssigreturn();
 }
 
-lazy_static! {
-    /// a memory set instance through lazy_static! managing kernel space
-    pub static ref KERNEL_SPACE: Arc<UPSafeCell<MemorySet>> =
-        Arc::new(unsafe { UPSafeCell::new(MemorySet::new_kernel()) });
-}
+/// a memory set instance managing kernel space, set up by [`super::init`] once
+/// frames can be allocated
+pub static KERNEL_SPACE: Once<Arc<ExclusiveCell<MemorySet>>> = Once::new();
 ///Get kernelspace root ppn
 pub fn kernel_token() -> usize {
     KERNEL_SPACE.exclusive_access().token()
 }
+/// 1 GiB slots covering the kernel image and physical memory, from where the
+/// kernel was loaded.
+///
+/// The subtrees below their page-table entries are shared by all address
+/// spaces, so a kernel mapping update is visible everywhere at once.
+fn kernel_shared_gibs() -> Range<usize> {
+    let start = kaslr::freed().start >> 30;
+    let end = ((MACHINE.memory_end - 1) >> 30) + 1;
+    start..end
+}
+/// Check whether `[start_vpn, end_vpn)` overlaps with the shared kernel subtrees
+fn overlaps_kernel(start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+    let shared = kernel_shared_gibs();
+    let shared_start = VirtPageNum(shared.start << 18);
+    let shared_end = VirtPageNum(shared.end << 18);
+    shared_start < end_vpn && start_vpn < shared_end
+}
+/// Bytes between the end of a program and its heap, a random number of
+/// pages below `BRK_RANDOM_SIZE` unless `norandmaps` is on the command line
+fn random_brk_offset() -> usize {
+    if cmdline::flag("norandmaps") {
+        return 0;
+    }
+    random_u32() as usize % (BRK_RANDOM_SIZE / PAGE_SIZE) * PAGE_SIZE
+}
 /// memory set structure, controls virtual-memory space
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    /// first page of the heap grown by sbrk, if the address space has one
+    heap_start: Option<VirtPageNum>,
+    usage: MemoryUsage,
+    asid: AsidHandle,
+}
+
+/// Memory usage of an address space, counted as frames are mapped and unmapped
+#[derive(Copy, Clone, Default, Debug)]
+pub struct MemoryUsage {
+    /// Number of frames currently backing the areas
+    pub resident_pages: usize,
+    /// Maximum of `resident_pages` ever reached
+    pub peak_resident_pages: usize,
+    /// Number of frames backing the heap
+    pub heap_pages: usize,
+    /// Number of frames backing the areas created by mmap
+    pub mmap_pages: usize,
+}
+
+/// Why [`MemorySet::from_elf`] could not load a program
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LoadError {
+    /// Out of frames
+    OutOfMemory,
+    /// A segment is both writable and executable
+    WritableExecutable,
+}
+
+/// What an area holds, for the counter of [`MemoryUsage`] its frames go to
+#[derive(Copy, Clone, PartialEq)]
+enum AreaKind {
+    Heap,
+    Mmap,
+    Other,
+}
+
+impl MemoryUsage {
+    /// Count `frames` newly mapped frames of an area holding `kind`
+    fn map(&mut self, kind: AreaKind, frames: usize) {
+        self.resident_pages += frames;
+        self.peak_resident_pages = self.peak_resident_pages.max(self.resident_pages);
+        match kind {
+            AreaKind::Heap => self.heap_pages += frames,
+            AreaKind::Mmap => self.mmap_pages += frames,
+            AreaKind::Other => {}
+        }
+    }
+    /// Count `frames` unmapped frames of an area holding `kind`
+    fn unmap(&mut self, kind: AreaKind, frames: usize) {
+        self.resident_pages -= frames;
+        match kind {
+            AreaKind::Heap => self.heap_pages -= frames,
+            AreaKind::Mmap => self.mmap_pages -= frames,
+            AreaKind::Other => {}
+        }
+    }
 }
 
 impl MemorySet {
-    ///Create an empty `MemorySet`
-    pub fn new_bare() -> Self {
-        Self {
-            page_table: PageTable::new(),
+    ///Create an empty `MemorySet`, `None` if out of frames
+    pub fn new_bare() -> Option<Self> {
+        Some(Self {
+            page_table: PageTable::new()?,
             areas: Vec::new(),
-        }
+            heap_start: None,
+            usage: MemoryUsage::default(),
+            asid: AsidHandle(0),
+        })
     }
-    ///Get pagetable `root_ppn`
+    ///Get pagetable `root_ppn` tagged with the asid
     pub fn token(&self) -> usize {
-        self.page_table.token()
+        self.page_table.token() | self.asid.0 << SATP_ASID_SHIFT
+    }
+    /// Flush the TLB entries of `range` after changing its mappings
+    fn flush_tlb(&self, range: VPNRange) {
+        flush_range(range, self.asid.0);
     }
-    /// Assume that no conflicts.
+    /// Assume that no conflicts. Return false if out of frames.
     pub fn insert_framed_area(
         &mut self,
         start_va: VirtAddr,
         end_va: VirtAddr,
         permission: MapPermission,
-    ) {
-        self.push(
+    ) -> bool {
+        if !self.try_push(
             MapArea::new(start_va, end_va, MapType::Framed, permission),
             None,
-        );
+        ) {
+            return false;
+        }
+        self.flush_tlb(VPNRange::new(start_va.floor(), end_va.ceil()));
+        true
     }
     ///Remove `MapArea` that starts with `start_vpn`
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
@@ -72,39 +173,326 @@ impl MemorySet {
             .enumerate()
             .find(|(_, area)| area.vpn_range.get_start() == start_vpn)
         {
+            self.usage
+                .unmap(area.kind(self.heap_start), area.data_frames.len());
             area.unmap(&mut self.page_table);
+            let range = area.vpn_range;
             self.areas.remove(idx);
+            self.flush_tlb(range);
         }
     }
-    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
-        map_area.map(&mut self.page_table);
+    /// Map `map_area` and add it, return false and leave `self` unchanged if out of frames
+    /// or a user area overlaps with the shared kernel subtrees.
+    fn try_push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) -> bool {
+        if map_area.map_perm.contains(MapPermission::U)
+            && overlaps_kernel(map_area.vpn_range.get_start(), map_area.vpn_range.get_end())
+        {
+            return false;
+        }
+        if !map_area.map(&mut self.page_table) {
+            return false;
+        }
         if let Some(data) = data {
             map_area.copy_data(&mut self.page_table, data);
         }
+        self.usage
+            .map(map_area.kind(self.heap_start), map_area.data_frames.len());
         self.areas.push(map_area);
+        true
+    }
+    /// Map `map_area` and add it, only used when running out of frames is fatal
+    fn push(&mut self, map_area: MapArea, data: Option<&[u8]>) {
+        assert!(self.try_push(map_area, data), "Run out of frames!");
+    }
+    /// Report the memory usage of this address space
+    pub fn usage(&self) -> MemoryUsage {
+        self.usage
+    }
+    /// Total size of the areas accessible in U mode, in bytes
+    pub fn mapped_size(&self) -> usize {
+        self.areas
+            .iter()
+            .filter(|area| area.map_perm.contains(MapPermission::U))
+            .map(|area| area.size())
+            .sum()
+    }
+    /// Print the areas accessible in U mode by address as `start-end perms resident
+    /// name`, naming the heap starting at `heap_bottom`, e.g. for a fault report
+    pub fn print_areas(&self, heap_bottom: usize) {
+        let mut areas: Vec<&MapArea> = self
+            .areas
+            .iter()
+            .filter(|area| area.map_perm.contains(MapPermission::U))
+            .collect();
+        areas.sort_by_key(|area| area.vpn_range.get_start());
+        for area in areas {
+            let start: VirtAddr = area.vpn_range.get_start().into();
+            let end: VirtAddr = area.vpn_range.get_end().into();
+            let name = if start.0 == heap_bottom {
+                "[heap]"
+            } else if area.is_mmap() {
+                "[mmap]"
+            } else if start.0 == SIGRETURN_TRAMPOLINE {
+                "[sigreturn]"
+            } else if start.0 >= USER_STACK_BASE {
+                "[stack]"
+            } else {
+                "[program]"
+            };
+            let perm = |flag, c| if area.map_perm.contains(flag) { c } else { '-' };
+            println!(
+                "  {:#x}-{:#x} {}{}{} {:>5} pages resident {}",
+                start.0,
+                end.0,
+                perm(MapPermission::R, 'r'),
+                perm(MapPermission::W, 'w'),
+                perm(MapPermission::X, 'x'),
+                area.data_frames.len(),
+                name
+            );
+        }
+    }
+    /// Shrink the area starting at `start` so that it ends at `new_end`
+    pub fn shrink_to(&mut self, start: VirtAddr, new_end: VirtAddr) -> bool {
+        if let Some(area) = self
+            .areas
+            .iter_mut()
+            .find(|area| area.vpn_range.get_start() == start.floor())
+        {
+            let old_end = area.vpn_range.get_end();
+            let old_frames = area.data_frames.len();
+            area.shrink_to(&mut self.page_table, new_end.ceil());
+            self.usage.unmap(
+                area.kind(self.heap_start),
+                old_frames - area.data_frames.len(),
+            );
+            self.flush_tlb(VPNRange::new(new_end.ceil(), old_end));
+            true
+        } else {
+            false
+        }
+    }
+    /// Extend the area starting at `start` so that it ends at `new_end`.
+    /// Return false if there is no such area, it would grow into the shared
+    /// kernel subtrees, or out of frames.
+    pub fn append_to(&mut self, start: VirtAddr, new_end: VirtAddr) -> bool {
+        if overlaps_kernel(start.floor(), new_end.ceil()) {
+            return false;
+        }
+        if let Some(area) = self
+            .areas
+            .iter_mut()
+            .find(|area| area.vpn_range.get_start() == start.floor())
+        {
+            let old_end = area.vpn_range.get_end();
+            let old_frames = area.data_frames.len();
+            if !area.append_to(&mut self.page_table, new_end.ceil()) {
+                return false;
+            }
+            self.usage.map(
+                area.kind(self.heap_start),
+                area.data_frames.len() - old_frames,
+            );
+            self.flush_tlb(VPNRange::new(old_end, new_end.ceil()));
+            true
+        } else {
+            false
+        }
+    }
+    /// Check whether `[start_vpn, end_vpn)` overlaps with any area
+    fn overlaps(&self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        self.areas.iter().any(|area| {
+            area.vpn_range.get_start() < end_vpn && start_vpn < area.vpn_range.get_end()
+        })
+    }
+    /// Map an anonymous area of `len` bytes at `start`, or right after the
+    /// existing mmap areas if `start` is 0. Return the start address, `None`
+    /// if the range is invalid or out of frames.
+    pub fn mmap(&mut self, start: usize, len: usize, permission: MapPermission) -> Option<usize> {
+        let (start_va, end_va) = self.mmap_range(start, len)?;
+        if !self.insert_framed_area(start_va, end_va, permission | MapPermission::U) {
+            return None;
+        }
+        Some(start_va.0)
+    }
+    /// Like [`MemorySet::mmap`], mapping the consecutive frames from `first`
+    /// instead, not owned by the area, e.g. those of a framebuffer
+    pub fn mmap_frames(
+        &mut self,
+        start: usize,
+        len: usize,
+        permission: MapPermission,
+        first: PhysPageNum,
+    ) -> Option<usize> {
+        let (start_va, end_va) = self.mmap_range(start, len)?;
+        let area = MapArea::new(
+            start_va,
+            end_va,
+            MapType::Linear(first),
+            permission | MapPermission::U,
+        );
+        if !self.try_push(area, None) {
+            return None;
+        }
+        self.flush_tlb(VPNRange::new(start_va.floor(), end_va.ceil()));
+        Some(start_va.0)
+    }
+    /// The range of a new mmap area of `len` bytes at `start`, or right after
+    /// the existing ones if `start` is 0. `None` if it is invalid or taken.
+    fn mmap_range(&self, start: usize, len: usize) -> Option<(VirtAddr, VirtAddr)> {
+        let start = if start == 0 {
+            self.areas
+                .iter()
+                .filter(|area| area.is_mmap())
+                .map(|area| VirtAddr::from(area.vpn_range.get_end()).0)
+                .max()
+                .unwrap_or(MMAP_BASE)
+        } else {
+            start
+        };
+        let end = start.checked_add(len)?;
+        if start % PAGE_SIZE != 0 || start < MMAP_BASE || end > user_space_end() {
+            return None;
+        }
+        let start_va = VirtAddr::from(start);
+        let end_va = VirtAddr::from(end);
+        if self.overlaps(start_va.floor(), end_va.ceil()) {
+            return None;
+        }
+        Some((start_va, end_va))
+    }
+    /// Unmap the pages of mmap areas in `[start, start + len)`, return false if the range is
+    /// empty or invalid
+    pub fn munmap(&mut self, start: usize, len: usize) -> bool {
+        if len == 0 {
+            return false;
+        }
+        let start_va = VirtAddr::from(start);
+        let end = match start.checked_add(len) {
+            Some(end) => end,
+            None => return false,
+        };
+        if !start_va.aligned() || start < MMAP_BASE || end > user_space_end() {
+            return false;
+        }
+        self.unmap_range(start_va.floor(), VirtAddr::from(end).ceil());
+        true
+    }
+    /// Unmap the pages in `[start_vpn, end_vpn)`, splitting the areas only
+    /// partially covered into up to two remaining pieces
+    pub fn unmap_range(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) {
+        let mut remaining = Vec::new();
+        for mut area in self.areas.drain(..) {
+            let area_start = area.vpn_range.get_start();
+            let area_end = area.vpn_range.get_end();
+            if end_vpn <= area_start || area_end <= start_vpn {
+                remaining.push(area);
+                continue;
+            }
+            let kind = area.kind(self.heap_start);
+            let right = if end_vpn < area_end {
+                Some(area.split_off(end_vpn))
+            } else {
+                None
+            };
+            if area_start < start_vpn {
+                let mut middle = area.split_off(start_vpn);
+                self.usage.unmap(kind, middle.data_frames.len());
+                middle.unmap(&mut self.page_table);
+                remaining.push(area);
+            } else {
+                self.usage.unmap(kind, area.data_frames.len());
+                area.unmap(&mut self.page_table);
+            }
+            if let Some(right) = right {
+                remaining.push(right);
+            }
+        }
+        self.areas = remaining;
+        self.flush_tlb(VPNRange::new(start_vpn, end_vpn));
+    }
+    /// Map `ppns` to consecutive pages from `start_vpn` without tracking them in areas.
+    /// Return false and undo the mapping if out of frames.
+    pub fn map_pages(
+        &mut self,
+        start_vpn: VirtPageNum,
+        ppns: &[PhysPageNum],
+        permission: MapPermission,
+    ) -> bool {
+        let pte_flags = PTEFlags::from_bits(permission.bits).unwrap();
+        let mut vpn = start_vpn;
+        for (mapped, ppn) in ppns.iter().enumerate() {
+            if !self.page_table.map(vpn, *ppn, pte_flags) {
+                self.unmap_pages(start_vpn, mapped);
+                return false;
+            }
+            vpn.step();
+        }
+        self.flush_tlb(VPNRange::new(start_vpn, vpn));
+        true
+    }
+    /// Unmap `count` pages from `start_vpn` mapped by [`MemorySet::map_pages`]
+    pub fn unmap_pages(&mut self, start_vpn: VirtPageNum, count: usize) {
+        let range = VPNRange::new(start_vpn, VirtPageNum(start_vpn.0 + count));
+        for vpn in range {
+            self.page_table.unmap(vpn);
+        }
+        self.flush_tlb(range);
+    }
+    /// Create an empty user address space holding the trampoline, the sigreturn
+    /// trampoline and the shared kernel subtrees, `None` if out of frames
+    fn new_user() -> Option<Self> {
+        let mut memory_set = Self::new_bare()?;
+        memory_set.asid = asid_alloc();
+        if !memory_set.map_trampoline() || !memory_set.map_sigreturn_trampoline() {
+            return None;
+        }
+        if !memory_set.page_table.share_gib_entries(
+            &mut KERNEL_SPACE.exclusive_access().page_table,
+            kernel_shared_gibs(),
+        ) {
+            return None;
+        }
+        Some(memory_set)
     }
-    /// Mention that trampoline is not collected by areas.
-    fn map_trampoline(&mut self) {
+    /// Mention that trampoline is not collected by areas. Return false if out of frames.
+    fn map_trampoline(&mut self) -> bool {
         self.page_table.map(
             VirtAddr::from(TRAMPOLINE).into(),
             PhysAddr::from(strampoline as usize).into(),
             PTEFlags::R | PTEFlags::X,
-        );
+        )
+    }
+    /// Map the kernel page calling sigreturn for user signal handlers to return to,
+    /// not collected by areas either. Return false if out of frames.
+    fn map_sigreturn_trampoline(&mut self) -> bool {
+        self.page_table.map(
+            VirtAddr::from(SIGRETURN_TRAMPOLINE).into(),
+            PhysAddr::from(ssigreturn as usize).into(),
+            PTEFlags::R | PTEFlags::X | PTEFlags::U,
+        )
     }
     /// Without kernel stacks.
     pub fn new_kernel() -> Self {
-        let mut memory_set = Self::new_bare();
+        let mut memory_set = Self::new_bare().expect("Run out of frames!");
         // map trampoline
-        memory_set.map_trampoline();
+        assert!(memory_set.map_trampoline(), "Run out of frames!");
+        // fix the page-table entries shared with user address spaces
+        assert!(
+            memory_set
+                .page_table
+                .populate_gib_entries(kernel_shared_gibs()),
+            "Run out of frames!"
+        );
         // map kernel sections
-        println!(".text [{:#x}, {:#x})", stext as usize, etext as usize);
-        println!(".rodata [{:#x}, {:#x})", srodata as usize, erodata as usize);
-        println!(".data [{:#x}, {:#x})", sdata as usize, edata as usize);
-        println!(
+        debug!(".text [{:#x}, {:#x})", stext as usize, etext as usize);
+        debug!(".rodata [{:#x}, {:#x})", srodata as usize, erodata as usize);
+        debug!(".data [{:#x}, {:#x})", sdata as usize, edata as usize);
+        debug!(
             ".bss [{:#x}, {:#x})",
             sbss_with_stack as usize, ebss as usize
         );
-        println!("mapping .text section");
+        debug!("mapping .text section");
         memory_set.push(
             MapArea::new(
                 (stext as usize).into(),
@@ -114,7 +502,7 @@ impl MemorySet {
             ),
             None,
         );
-        println!("mapping .rodata section");
+        debug!("mapping .rodata section");
         memory_set.push(
             MapArea::new(
                 (srodata as usize).into(),
@@ -124,7 +512,7 @@ impl MemorySet {
             ),
             None,
         );
-        println!("mapping .data section");
+        debug!("mapping .data section");
         memory_set.push(
             MapArea::new(
                 (sdata as usize).into(),
@@ -134,7 +522,7 @@ impl MemorySet {
             ),
             None,
         );
-        println!("mapping .bss section");
+        debug!("mapping .bss section");
         memory_set.push(
             MapArea::new(
                 (sbss_with_stack as usize).into(),
@@ -144,18 +532,31 @@ impl MemorySet {
             ),
             None,
         );
-        println!("mapping physical memory");
+        let loaded = kaslr::freed();
+        if !loaded.is_empty() {
+            debug!("mapping the memory the kernel was loaded in");
+            memory_set.push(
+                MapArea::new(
+                    loaded.start.into(),
+                    loaded.end.into(),
+                    MapType::Identical,
+                    MapPermission::R | MapPermission::W,
+                ),
+                None,
+            );
+        }
+        debug!("mapping physical memory");
         memory_set.push(
             MapArea::new(
                 (ekernel as usize).into(),
-                MEMORY_END.into(),
+                MACHINE.memory_end.into(),
                 MapType::Identical,
                 MapPermission::R | MapPermission::W,
             ),
             None,
         );
-        println!("mapping memory-mapped registers");
-        for pair in MMIO {
+        debug!("mapping memory-mapped registers");
+        for pair in MACHINE.mmio.iter() {
             memory_set.push(
                 MapArea::new(
                     (*pair).0.into(),
@@ -168,12 +569,12 @@ impl MemorySet {
         }
         memory_set
     }
-    /// Include sections in elf and trampoline and TrapContext and user stack,
-    /// also returns user_sp and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
-        let mut memory_set = Self::new_bare();
-        // map trampoline
-        memory_set.map_trampoline();
+    /// Include sections in elf and trampoline and an empty heap area, also
+    /// returns the heap bottom and entry point.
+    /// User stacks and TrapContexts are mapped per thread by `TaskUserRes`.
+    pub fn from_elf(elf_data: &[u8]) -> Result<(Self, usize, usize), LoadError> {
+        // map trampoline and kernel
+        let mut memory_set = Self::new_user().ok_or(LoadError::OutOfMemory)?;
         // map program headers of elf, with U flag
         let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
         let elf_header = elf.header;
@@ -197,54 +598,58 @@ impl MemorySet {
                 if ph_flags.is_execute() {
                     map_perm |= MapPermission::X;
                 }
+                if map_perm.contains(MapPermission::W | MapPermission::X) {
+                    warn!(
+                        "segment at {:#x} is writable and executable, not loaded",
+                        start_va.0
+                    );
+                    return Err(LoadError::WritableExecutable);
+                }
                 let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
                 max_end_vpn = map_area.vpn_range.get_end();
-                memory_set.push(
+                if !memory_set.try_push(
                     map_area,
                     Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-                );
+                ) {
+                    return Err(LoadError::OutOfMemory);
+                }
             }
         }
-        // map user stack with U flags
-        let max_end_va: VirtAddr = max_end_vpn.into();
-        let mut user_stack_bottom: usize = max_end_va.into();
-        // guard page
-        user_stack_bottom += PAGE_SIZE;
-        let user_stack_top = user_stack_bottom + USER_STACK_SIZE;
-        memory_set.push(
+        // used in sbrk, at a random page past the program
+        let heap_bottom = VirtAddr::from(max_end_vpn).0 + random_brk_offset();
+        memory_set.heap_start = Some(VirtAddr::from(heap_bottom).floor());
+        if !memory_set.try_push(
             MapArea::new(
-                user_stack_bottom.into(),
-                user_stack_top.into(),
+                heap_bottom.into(),
+                heap_bottom.into(),
                 MapType::Framed,
                 MapPermission::R | MapPermission::W | MapPermission::U,
             ),
             None,
-        );
-        // map TrapContext
-        memory_set.push(
-            MapArea::new(
-                TRAP_CONTEXT.into(),
-                TRAMPOLINE.into(),
-                MapType::Framed,
-                MapPermission::R | MapPermission::W,
-            ),
-            None,
-        );
-        (
+        ) {
+            return Err(LoadError::OutOfMemory);
+        }
+        Ok((
             memory_set,
-            user_stack_top,
+            heap_bottom,
             elf.header.pt2.entry_point() as usize,
-        )
+        ))
     }
-    ///Clone a same `MemorySet`
-    pub fn from_existed_user(user_space: &MemorySet) -> MemorySet {
-        let mut memory_set = Self::new_bare();
-        // map trampoline
-        memory_set.map_trampoline();
+    ///Clone a same `MemorySet`, `None` if out of frames
+    pub fn from_existed_user(user_space: &MemorySet) -> Option<MemorySet> {
+        // map trampoline and kernel
+        let mut memory_set = Self::new_user()?;
+        memory_set.heap_start = user_space.heap_start;
         // copy data sections/trap_context/user_stack
         for area in user_space.areas.iter() {
             let new_area = MapArea::from_another(area);
-            memory_set.push(new_area, None);
+            if !memory_set.try_push(new_area, None) {
+                return None;
+            }
+            if area.map_type != MapType::Framed {
+                // the same frames are shared
+                continue;
+            }
             // copy data from another space
             for vpn in area.vpn_range {
                 let src_ppn = user_space.translate(vpn).unwrap().ppn();
@@ -254,11 +659,11 @@ impl MemorySet {
                     .copy_from_slice(src_ppn.get_bytes_array());
             }
         }
-        memory_set
+        Some(memory_set)
     }
     ///Refresh TLB with `sfence.vma`
     pub fn activate(&self) {
-        let satp = self.page_table.token();
+        let satp = self.token();
         unsafe {
             satp::write(satp);
             asm!("sfence.vma");
@@ -268,10 +673,44 @@ impl MemorySet {
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.translate(vpn)
     }
+    /// Get the flags of the mapping of `vpn`, including the accessed and dirty bits
+    pub fn get_flags(&self, vpn: VirtPageNum) -> Option<PTEFlags> {
+        self.page_table.get_flags(vpn)
+    }
+    /// Clear the accessed bit of `vpn`, return false if not mapped
+    pub fn clear_accessed(&mut self, vpn: VirtPageNum) -> bool {
+        if !self.page_table.clear_accessed(vpn) {
+            return false;
+        }
+        flush_page(vpn, self.asid.0);
+        true
+    }
+    /// Clear the dirty bit of `vpn`, return false if not mapped
+    pub fn clear_dirty(&mut self, vpn: VirtPageNum) -> bool {
+        if !self.page_table.clear_dirty(vpn) {
+            return false;
+        }
+        flush_page(vpn, self.asid.0);
+        true
+    }
+    /// Resolve a page fault on `vpn` for `access` raised only because the
+    /// accessed or dirty bit is clear, return false if it has another cause
+    pub fn handle_accessed_dirty_fault(&mut self, vpn: VirtPageNum, access: PTEFlags) -> bool {
+        if !self.page_table.set_accessed_dirty(vpn, access) {
+            return false;
+        }
+        // the other harts fault on their stale entries and find the bits set
+        flush_local_page(vpn, self.asid.0);
+        true
+    }
     ///Remove all `MapArea`
     pub fn recycle_data_pages(&mut self) {
         //*self = Self::new_bare();
         self.areas.clear();
+        self.usage = MemoryUsage {
+            peak_resident_pages: self.usage.peak_resident_pages,
+            ..MemoryUsage::default()
+        };
     }
 }
 /// map area structure, controls a contiguous piece of virtual memory
@@ -306,20 +745,36 @@ impl MapArea {
             map_perm: another.map_perm,
         }
     }
-    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+    /// Map a single page, return false if out of frames
+    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
         let ppn: PhysPageNum;
         match self.map_type {
             MapType::Identical => {
                 ppn = PhysPageNum(vpn.0);
             }
+            MapType::Linear(first) => {
+                ppn = PhysPageNum(first.0 + vpn.0 - self.vpn_range.get_start().0);
+            }
             MapType::Framed => {
-                let frame = frame_alloc().unwrap();
+                let kind = if self.map_perm.contains(MapPermission::U) {
+                    FrameKind::User
+                } else {
+                    FrameKind::Kernel
+                };
+                let frame = match frame_alloc_for(kind) {
+                    Some(frame) => frame,
+                    None => return false,
+                };
                 ppn = frame.ppn;
                 self.data_frames.insert(vpn, frame);
             }
         }
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
+        if !page_table.map(vpn, ppn, pte_flags) {
+            self.data_frames.remove(&vpn);
+            return false;
+        }
+        true
     }
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
         if self.map_type == MapType::Framed {
@@ -327,16 +782,78 @@ impl MapArea {
         }
         page_table.unmap(vpn);
     }
-    pub fn map(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range {
-            self.map_one(page_table, vpn);
+    /// Map the pages in `range`, undo the partial mapping and return false if out of frames
+    fn map_range(&mut self, page_table: &mut PageTable, range: VPNRange) -> bool {
+        for vpn in range {
+            if !self.map_one(page_table, vpn) {
+                for mapped in VPNRange::new(range.get_start(), vpn) {
+                    self.unmap_one(page_table, mapped);
+                }
+                return false;
+            }
         }
+        true
+    }
+    /// Map the whole area, return false if out of frames
+    pub fn map(&mut self, page_table: &mut PageTable) -> bool {
+        self.map_range(page_table, self.vpn_range)
     }
     pub fn unmap(&mut self, page_table: &mut PageTable) {
         for vpn in self.vpn_range {
             self.unmap_one(page_table, vpn);
         }
     }
+    /// Unmap the pages from `new_end` on
+    pub fn shrink_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) {
+        for vpn in VPNRange::new(new_end, self.vpn_range.get_end()) {
+            self.unmap_one(page_table, vpn)
+        }
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), new_end);
+    }
+    /// Map the pages up to `new_end`, return false and keep the old end if out of frames
+    pub fn append_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) -> bool {
+        if !self.map_range(page_table, VPNRange::new(self.vpn_range.get_end(), new_end)) {
+            return false;
+        }
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), new_end);
+        true
+    }
+    /// Split the area at `at`, keep `[start, at)` and return `[at, end)` with its frames
+    pub fn split_off(&mut self, at: VirtPageNum) -> MapArea {
+        let map_type = match self.map_type {
+            MapType::Linear(first) => {
+                MapType::Linear(PhysPageNum(first.0 + at.0 - self.vpn_range.get_start().0))
+            }
+            map_type => map_type,
+        };
+        let right = MapArea {
+            vpn_range: VPNRange::new(at, self.vpn_range.get_end()),
+            data_frames: self.data_frames.split_off(&at),
+            map_type,
+            map_perm: self.map_perm,
+        };
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), at);
+        right
+    }
+    /// Size of the area in bytes
+    pub fn size(&self) -> usize {
+        (self.vpn_range.get_end().0 - self.vpn_range.get_start().0) * PAGE_SIZE
+    }
+    /// Whether the area was created by mmap
+    fn is_mmap(&self) -> bool {
+        let start: VirtAddr = self.vpn_range.get_start().into();
+        start.0 >= MMAP_BASE && start.0 < user_space_end()
+    }
+    /// What the area holds, given the first page of the heap
+    fn kind(&self, heap_start: Option<VirtPageNum>) -> AreaKind {
+        if Some(self.vpn_range.get_start()) == heap_start {
+            AreaKind::Heap
+        } else if self.is_mmap() {
+            AreaKind::Mmap
+        } else {
+            AreaKind::Other
+        }
+    }
     /// data: start-aligned but maybe with shorter length
     /// assume that all frames were cleared before
     pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8]) {
@@ -362,10 +879,12 @@ impl MapArea {
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
-/// map type for memory set: identical or framed
+/// map type for memory set: identical, framed, or linear to frames owned elsewhere
 pub enum MapType {
     Identical,
     Framed,
+    /// to the consecutive frames from the given one, for the start of the area
+    Linear(PhysPageNum),
 }
 
 bitflags! {
@@ -382,13 +901,13 @@ bitflags! {
     }
 }
 
-#[allow(unused)]
 ///Check PageTable running correctly
 pub fn remap_test() {
     let mut kernel_space = KERNEL_SPACE.exclusive_access();
     let mid_text: VirtAddr = ((stext as usize + etext as usize) / 2).into();
     let mid_rodata: VirtAddr = ((srodata as usize + erodata as usize) / 2).into();
     let mid_data: VirtAddr = ((sdata as usize + edata as usize) / 2).into();
+    let mid_bss: VirtAddr = ((sbss_with_stack as usize + ebss as usize) / 2).into();
     assert!(!kernel_space
         .page_table
         .translate(mid_text.floor())
@@ -399,10 +918,51 @@ pub fn remap_test() {
         .translate(mid_rodata.floor())
         .unwrap()
         .writable(),);
+    assert!(!kernel_space
+        .page_table
+        .translate(mid_rodata.floor())
+        .unwrap()
+        .executable(),);
     assert!(!kernel_space
         .page_table
         .translate(mid_data.floor())
         .unwrap()
         .executable(),);
-    println!("remap_test passed!");
+    assert!(!kernel_space
+        .page_table
+        .translate(mid_bss.floor())
+        .unwrap()
+        .executable(),);
+    // W^X over all the kernel mappings, physical memory and registers included
+    for area in kernel_space.areas.iter() {
+        for vpn in area.vpn_range {
+            if let Some(pte) = kernel_space.page_table.translate(vpn) {
+                assert!(!(pte.writable() && pte.executable()), "vpn {:?}", vpn);
+            }
+        }
+    }
+    let physical_memory: VirtAddr = (ekernel as usize).into();
+    assert!(!kernel_space
+        .page_table
+        .translate(physical_memory.ceil())
+        .unwrap()
+        .executable(),);
+    // nor is the code left where the kernel was loaded, if it moved
+    let loaded = kaslr::freed();
+    if !loaded.is_empty() {
+        let loaded: VirtAddr = loaded.start.into();
+        assert!(!kernel_space
+            .page_table
+            .translate(loaded.floor())
+            .unwrap()
+            .executable(),);
+    }
+    drop(kernel_space);
+    // nor in the kernel subtrees shared with the user address spaces
+    let mut user_space = MemorySet::new_user().unwrap();
+    user_space
+        .page_table
+        .assert_wx_in_gibs(kernel_shared_gibs());
+    info!("remap_test passed!");
 }
+kernel_test!(remap_test);
@@ -1,29 +1,63 @@
 //! Memory management implementation
 //!
-//! SV39 page-based virtual-memory architecture for RV64 systems, and
+//! SV39/SV48 page-based virtual-memory architecture for RV64 systems, and
 //! everything about memory management, like frame allocator, page table,
 //! map area and memory set, is implemented here.
 //!
 //! Every task or process has a memory_set to control its virtual memory.
 mod address;
+mod alloc_stats;
+mod asid;
 mod frame_allocator;
 mod heap_allocator;
 mod memory_set;
 mod page_table;
+mod paging;
+mod slab;
+mod tlb;
+mod user_access;
+mod vmalloc;
 
+use crate::sync::ExclusiveCell;
 use address::VPNRange;
 pub use address::{PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
-pub use frame_allocator::{frame_alloc, frame_dealloc, FrameTracker};
+use alloc::sync::Arc;
+pub use alloc_stats::alloc_stats;
+pub use frame_allocator::{
+    frame_alloc, frame_alloc_contiguous, frame_alloc_for, frame_allocator_contentions,
+    frame_dealloc, frame_stats, set_reclaim, FrameKind, FrameStats, FrameTracker,
+};
+pub use heap_allocator::{heap_stats, HeapStats};
 pub use memory_set::remap_test;
-pub use memory_set::{kernel_token, MapPermission, MemorySet, KERNEL_SPACE};
-use page_table::PTEFlags;
-pub use page_table::{
-    translated_byte_buffer, translated_ref, translated_refmut, translated_str, PageTable,
-    PageTableEntry, UserBuffer, UserBufferIterator,
+pub use memory_set::{
+    kernel_token, LoadError, MapPermission, MemorySet, MemoryUsage, KERNEL_SPACE,
 };
-/// initiate heap allocator, frame allocator and kernel space
-pub fn init() {
+pub use page_table::PTEFlags;
+pub use page_table::{PageTable, PageTableEntry, UserBuffer, UserBufferIterator};
+pub use paging::{paging_mode, user_space_end, PagingMode};
+pub use slab::{
+    create_arc_cache, create_box_cache, for_each_slab_cache, print_slab_stats, ObjectCache,
+    SlabStats,
+};
+pub use user_access::{
+    copy_from_user, copy_slice_to_user, copy_str_from_user, copy_to_user, force_copy_to_user,
+};
+pub use vmalloc::{vmalloc, vmap, VmMapping};
+/// initiate the heap allocator, before anything is allocated
+pub fn init_heap() {
     heap_allocator::init_heap();
+}
+/// initiate paging mode, frame allocator and kernel space, after the memory
+/// of the machine is found by [`crate::fdt::init`]
+pub fn init() {
+    paging::init_paging_mode();
     frame_allocator::init_frame_allocator();
+    alloc_stats::init();
+    KERNEL_SPACE.call_once(|| Arc::new(ExclusiveCell::new(MemorySet::new_kernel())));
+    KERNEL_SPACE.exclusive_access().activate();
+    asid::init_asid_allocator();
+}
+/// turn on paging on a hart started after [`init`]
+pub fn init_secondary() {
     KERNEL_SPACE.exclusive_access().activate();
 }
@@ -1,11 +1,18 @@
 //! Implementation of [`PageTableEntry`] and [`PageTable`].
-use super::{frame_alloc, FrameTracker, PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
-use alloc::string::String;
+use super::paging::paging_mode;
+use super::{
+    frame_alloc, frame_alloc_for, FrameKind, FrameTracker, PhysAddr, PhysPageNum, VirtAddr,
+    VirtPageNum,
+};
+use crate::config::HARDWARE_PTE_AD;
+use crate::kernel_test;
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
+use core::ops::Range;
 
 bitflags! {
+    /// page table entry flags
     pub struct PTEFlags: u8 {
         const V = 1 << 0;
         const R = 1 << 1;
@@ -61,6 +68,23 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    ///Check PTE accessible in U mode
+    pub fn is_user(&self) -> bool {
+        (self.flags() & PTEFlags::U) != PTEFlags::empty()
+    }
+    ///Check PTE maps a page rather than pointing at a table of the next level
+    pub fn is_leaf(&self) -> bool {
+        self.flags()
+            .intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X)
+    }
+    ///Check PTE accessed since the A bit was last cleared
+    pub fn accessed(&self) -> bool {
+        (self.flags() & PTEFlags::A) != PTEFlags::empty()
+    }
+    ///Check PTE written since the D bit was last cleared
+    pub fn dirty(&self) -> bool {
+        (self.flags() & PTEFlags::D) != PTEFlags::empty()
+    }
 }
 ///Record root ppn and has the same lifetime as 1 and 2 level `PageTableEntry`
 pub struct PageTable {
@@ -68,15 +92,15 @@ pub struct PageTable {
     frames: Vec<FrameTracker>,
 }
 
-/// Assume that it won't oom when creating/mapping.
+/// Creating/mapping fails gracefully when running out of frames.
 impl PageTable {
-    /// Create an empty `PageTable`
-    pub fn new() -> Self {
-        let frame = frame_alloc().unwrap();
-        PageTable {
+    /// Create an empty `PageTable`, `None` if out of frames
+    pub fn new() -> Option<Self> {
+        let frame = frame_alloc_for(FrameKind::PageTable)?;
+        Some(PageTable {
             root_ppn: frame.ppn,
             frames: vec![frame],
-        }
+        })
     }
     /// Temporarily used to get arguments from user space.
     pub fn from_token(satp: usize) -> Self {
@@ -85,19 +109,21 @@ impl PageTable {
             frames: Vec::new(),
         }
     }
-    /// Find phsical address by virtual address, create a frame if not exist
+    /// Find phsical address by virtual address, create a frame if not exist.
+    /// Return `None` if out of frames.
     fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
         let idxs = vpn.indexes();
+        let levels = paging_mode().levels();
         let mut ppn = self.root_ppn;
         let mut result: Option<&mut PageTableEntry> = None;
-        for (i, idx) in idxs.iter().enumerate() {
+        for (i, idx) in idxs.iter().take(levels).enumerate() {
             let pte = &mut ppn.get_pte_array()[*idx];
-            if i == 2 {
+            if i == levels - 1 {
                 result = Some(pte);
                 break;
             }
             if !pte.is_valid() {
-                let frame = frame_alloc().unwrap();
+                let frame = frame_alloc_for(FrameKind::PageTable)?;
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
                 self.frames.push(frame);
             }
@@ -108,11 +134,12 @@ impl PageTable {
     /// Find phsical address by virtual address
     fn find_pte(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
         let idxs = vpn.indexes();
+        let levels = paging_mode().levels();
         let mut ppn = self.root_ppn;
         let mut result: Option<&mut PageTableEntry> = None;
-        for (i, idx) in idxs.iter().enumerate() {
+        for (i, idx) in idxs.iter().take(levels).enumerate() {
             let pte = &mut ppn.get_pte_array()[*idx];
-            if i == 2 {
+            if i == levels - 1 {
                 result = Some(pte);
                 break;
             }
@@ -123,12 +150,99 @@ impl PageTable {
         }
         result
     }
+    /// Find the table holding the entry that maps the 1 GiB slot `gib`,
+    /// which is the root table in Sv39. Create the tables on the way if
+    /// `create` is set, return `None` if missing or out of frames.
+    fn find_gib_table(&mut self, gib: usize, create: bool) -> Option<PhysPageNum> {
+        let idxs = VirtPageNum(gib << 18).indexes();
+        let mut ppn = self.root_ppn;
+        for idx in idxs.iter().take(paging_mode().levels() - 3) {
+            let pte = &mut ppn.get_pte_array()[*idx];
+            if !pte.is_valid() {
+                if !create {
+                    return None;
+                }
+                let frame = frame_alloc_for(FrameKind::PageTable)?;
+                *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
+                self.frames.push(frame);
+            }
+            ppn = pte.ppn();
+        }
+        Some(ppn)
+    }
+    /// Allocate the tables below the entries of the 1 GiB slots in `gibs` in advance,
+    /// so that these entries never change afterwards. Return false if out of frames.
+    pub fn populate_gib_entries(&mut self, gibs: Range<usize>) -> bool {
+        for gib in gibs {
+            let table = match self.find_gib_table(gib, true) {
+                Some(table) => table,
+                None => return false,
+            };
+            let pte = &mut table.get_pte_array()[gib & 511];
+            if !pte.is_valid() {
+                let frame = match frame_alloc_for(FrameKind::PageTable) {
+                    Some(frame) => frame,
+                    None => return false,
+                };
+                *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
+                self.frames.push(frame);
+            }
+        }
+        true
+    }
+    /// Point the entries of the 1 GiB slots in `gibs` at the same subtrees as in `other`,
+    /// so that later changes inside these subtrees are visible in both page tables.
+    /// The subtrees stay owned by `other`, which must have populated them.
+    /// Return false if out of frames.
+    pub fn share_gib_entries(&mut self, other: &mut PageTable, gibs: Range<usize>) -> bool {
+        for gib in gibs {
+            let src = other.find_gib_table(gib, false).unwrap();
+            let dst = match self.find_gib_table(gib, true) {
+                Some(table) => table,
+                None => return false,
+            };
+            let pte = src.get_pte_array()[gib & 511];
+            // a table, whose pages were all mapped by `map`, so none is writable and executable
+            assert!(
+                pte.is_valid() && !pte.is_leaf(),
+                "gib {:#x} shared without a table",
+                gib
+            );
+            dst.get_pte_array()[gib & 511] = pte;
+        }
+        true
+    }
+    /// Check that no page in the 1 GiB slots of `gibs` is both writable and
+    /// executable, including the pages in the subtrees shared with other tables
+    pub fn assert_wx_in_gibs(&mut self, gibs: Range<usize>) {
+        for gib in gibs {
+            if let Some(table) = self.find_gib_table(gib, false) {
+                assert_wx(table.get_pte_array()[gib & 511], 2);
+            }
+        }
+    }
     #[allow(unused)]
-    /// Create a mapping form `vpn` to `ppn`
-    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
-        let pte = self.find_pte_create(vpn).unwrap();
-        assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
-        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+    /// Create a mapping form `vpn` to `ppn`, return false if out of frames.
+    /// No page is ever both writable and executable. On harts not setting
+    /// the accessed and dirty bits, the mapping has them set from the start.
+    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) -> bool {
+        assert!(
+            !flags.contains(PTEFlags::W | PTEFlags::X),
+            "vpn {:?} mapped writable and executable",
+            vpn
+        );
+        let flags = if HARDWARE_PTE_AD {
+            flags
+        } else {
+            flags | PTEFlags::A | PTEFlags::D
+        };
+        if let Some(pte) = self.find_pte_create(vpn) {
+            assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
+            *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+            true
+        } else {
+            false
+        }
     }
     #[allow(unused)]
     /// Delete a mapping form `vpn`
@@ -141,6 +255,57 @@ impl PageTable {
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.find_pte(vpn).map(|pte| *pte)
     }
+    /// Get the flags of the valid mapping of `vpn`
+    pub fn get_flags(&self, vpn: VirtPageNum) -> Option<PTEFlags> {
+        self.find_pte(vpn)
+            .filter(|pte| pte.is_valid())
+            .map(|pte| pte.flags())
+    }
+    /// Clear `flags` in the valid mapping of `vpn`, return false if not mapped.
+    /// The caller has to flush the TLB entry of `vpn` afterwards.
+    fn clear_flags(&mut self, vpn: VirtPageNum, flags: PTEFlags) -> bool {
+        match self.find_pte(vpn) {
+            Some(pte) if pte.is_valid() => {
+                pte.bits &= !(flags.bits as usize);
+                true
+            }
+            _ => false,
+        }
+    }
+    /// Clear the accessed bit of `vpn`, return false if not mapped.
+    /// The caller has to flush the TLB entry of `vpn` afterwards.
+    pub fn clear_accessed(&mut self, vpn: VirtPageNum) -> bool {
+        self.clear_flags(vpn, PTEFlags::A)
+    }
+    /// Clear the dirty bit of `vpn`, return false if not mapped.
+    /// The caller has to flush the TLB entry of `vpn` afterwards.
+    pub fn clear_dirty(&mut self, vpn: VirtPageNum) -> bool {
+        self.clear_flags(vpn, PTEFlags::D)
+    }
+    /// Set the accessed bit, and the dirty bit for a write, of the valid user
+    /// mapping of `vpn` permitting `access` (one of R, W and X), as harts not
+    /// setting them raise a page fault instead. Return false if they are set
+    /// already or the mapping forbids the access, i.e. the fault has another cause.
+    /// The caller has to flush the TLB entry of `vpn` afterwards.
+    pub fn set_accessed_dirty(&mut self, vpn: VirtPageNum, access: PTEFlags) -> bool {
+        let bits = if access.contains(PTEFlags::W) {
+            PTEFlags::A | PTEFlags::D
+        } else {
+            PTEFlags::A
+        };
+        match self.find_pte(vpn) {
+            Some(pte)
+                if pte.is_valid()
+                    && pte.is_user()
+                    && pte.flags().contains(access)
+                    && !pte.flags().contains(bits) =>
+            {
+                pte.bits |= bits.bits as usize;
+                true
+            }
+            _ => false,
+        }
+    }
     /// Translate `VirtAddr` to `PhysAddr`
     pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
         self.find_pte(va.clone().floor()).map(|pte| {
@@ -152,69 +317,59 @@ impl PageTable {
     }
     /// Get root ppn
     pub fn token(&self) -> usize {
-        8usize << 60 | self.root_ppn.0
+        paging_mode().satp_mode() << 60 | self.root_ppn.0
     }
 }
-/// Translate a pointer to a mutable u8 Vec through page table
-pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
-    let page_table = PageTable::from_token(token);
-    let mut start = ptr as usize;
-    let end = start + len;
-    let mut v = Vec::new();
-    while start < end {
-        let start_va = VirtAddr::from(start);
-        let mut vpn = start_va.floor();
-        let ppn = page_table.translate(vpn).unwrap().ppn();
-        vpn.step();
-        let mut end_va: VirtAddr = vpn.into();
-        end_va = end_va.min(VirtAddr::from(end));
-        if end_va.page_offset() == 0 {
-            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..]);
-        } else {
-            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..end_va.page_offset()]);
-        }
-        start = end_va.into();
-    }
-    v
-}
 
-/// Translate a pointer to a mutable u8 Vec end with `\0` through page table to a `String`
-pub fn translated_str(token: usize, ptr: *const u8) -> String {
-    let page_table = PageTable::from_token(token);
-    let mut string = String::new();
-    let mut va = ptr as usize;
-    loop {
-        let ch: u8 = *(page_table
-            .translate_va(VirtAddr::from(va))
-            .unwrap()
-            .get_mut());
-        if ch == 0 {
-            break;
+/// Check that the page mapped by `pte`, or every page below it if it points at a
+/// table, is not both writable and executable. `level` is 0 for the entries of 4
+/// KiB pages and one more for each level above.
+fn assert_wx(pte: PageTableEntry, level: usize) {
+    if !pte.is_valid() {
+        return;
+    }
+    if pte.is_leaf() {
+        assert!(
+            !(pte.writable() && pte.executable()),
+            "ppn {:?} mapped writable and executable",
+            pte.ppn()
+        );
+    } else if level > 0 {
+        for entry in pte.ppn().get_pte_array().iter() {
+            assert_wx(*entry, level - 1);
         }
-        string.push(ch as char);
-        va += 1;
     }
-    string
 }
 
-#[allow(unused)]
-///Translate a generic through page table and return a reference
-pub fn translated_ref<T>(token: usize, ptr: *const T) -> &'static T {
-    let page_table = PageTable::from_token(token);
-    page_table
-        .translate_va(VirtAddr::from(ptr as usize))
-        .unwrap()
-        .get_ref()
-}
-///Translate a generic through page table and return a mutable reference
-pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
-    let page_table = PageTable::from_token(token);
-    let va = ptr as usize;
-    page_table
-        .translate_va(VirtAddr::from(va))
-        .unwrap()
-        .get_mut()
+/// Map, translate and unmap a page in a page table of its own
+fn page_table_test() {
+    let mut page_table = PageTable::new().unwrap();
+    let frame = frame_alloc().unwrap();
+    let vpn = VirtPageNum(0x12345);
+    assert!(page_table.get_flags(vpn).is_none());
+    assert!(page_table.map(vpn, frame.ppn, PTEFlags::R | PTEFlags::W | PTEFlags::U));
+    let pte = page_table.translate(vpn).unwrap();
+    assert!(pte.is_valid() && pte.readable() && pte.writable() && !pte.executable());
+    assert_eq!(pte.ppn(), frame.ppn);
+    let va = VirtAddr::from(vpn).0 + 0x123;
+    let pa: usize = page_table.translate_va(va.into()).unwrap().into();
+    assert_eq!(pa, PhysAddr::from(frame.ppn).0 + 0x123);
+    // the faults after clearing the accessed and dirty bits set them back
+    assert!(page_table.clear_accessed(vpn) && page_table.clear_dirty(vpn));
+    let pte = page_table.translate(vpn).unwrap();
+    assert!(pte.is_valid() && !pte.accessed() && !pte.dirty());
+    assert!(!page_table.set_accessed_dirty(vpn, PTEFlags::X));
+    assert!(page_table.set_accessed_dirty(vpn, PTEFlags::R));
+    let pte = page_table.translate(vpn).unwrap();
+    assert!(pte.accessed() && !pte.dirty());
+    assert!(page_table.set_accessed_dirty(vpn, PTEFlags::W));
+    let pte = page_table.translate(vpn).unwrap();
+    assert!(pte.accessed() && pte.dirty());
+    assert!(!page_table.set_accessed_dirty(vpn, PTEFlags::W));
+    page_table.unmap(vpn);
+    assert!(page_table.get_flags(vpn).is_none());
 }
+kernel_test!(page_table_test);
 ///Array of u8 slice that user communicate with os
 pub struct UserBuffer {
     ///U8 vec
@@ -0,0 +1,104 @@
+//! Paging mode selection
+//!
+//! The kernel runs with 4-level Sv48 page tables if [`PREFER_SV48`] is set and
+//! the hart implements Sv48, and with 3-level Sv39 page tables otherwise. The
+//! mode is chosen once at boot, before any page table is built.
+use crate::config::PREFER_SV48;
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Maximum number of page-table levels of the supported modes
+pub const MAX_PAGING_LEVELS: usize = 4;
+
+/// Supported paging modes
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PagingMode {
+    /// 3-level page tables, 39-bit virtual addresses
+    Sv39,
+    /// 4-level page tables, 48-bit virtual addresses
+    Sv48,
+}
+
+impl PagingMode {
+    /// Number of page-table levels
+    pub fn levels(self) -> usize {
+        match self {
+            PagingMode::Sv39 => 3,
+            PagingMode::Sv48 => 4,
+        }
+    }
+    /// Width of a virtual address in bits
+    pub fn va_width(self) -> usize {
+        12 + 9 * self.levels()
+    }
+    /// Value of the MODE field of `satp`
+    pub fn satp_mode(self) -> usize {
+        match self {
+            PagingMode::Sv39 => 8,
+            PagingMode::Sv48 => 9,
+        }
+    }
+}
+
+/// Number of levels of the selected mode, Sv39 until [`init_paging_mode`] runs
+static PAGING_LEVELS: AtomicUsize = AtomicUsize::new(3);
+
+/// The selected paging mode
+pub fn paging_mode() -> PagingMode {
+    if PAGING_LEVELS.load(Ordering::Relaxed) == 4 {
+        PagingMode::Sv48
+    } else {
+        PagingMode::Sv39
+    }
+}
+
+/// End of the lower half of the virtual address space, which belongs to user
+pub fn user_space_end() -> usize {
+    1 << (paging_mode().va_width() - 1)
+}
+
+/// Page table used only to probe Sv48 support
+#[repr(C, align(4096))]
+struct ProbeTable([usize; 512]);
+
+static mut PROBE_ROOT: ProbeTable = ProbeTable([0; 512]);
+static mut PROBE_TABLE: ProbeTable = ProbeTable([0; 512]);
+
+/// Check whether the hart implements Sv48 by turning it on with a page table
+/// that maps the kernel's gigabyte to itself. `satp` is WARL, so the write
+/// has no effect if the mode is not supported. Must run with paging off.
+fn probe_sv48() -> bool {
+    extern "C" {
+        fn stext();
+    }
+    // V | R | W | X | A | D
+    const LEAF_FLAGS: usize = 0xcf;
+    let gib = stext as usize >> 30;
+    let satp: usize;
+    unsafe {
+        let root = &PROBE_ROOT as *const _ as usize;
+        let table = &PROBE_TABLE as *const _ as usize;
+        // the root entry points at the table holding the 1 GiB leaf
+        PROBE_ROOT.0[gib >> 9 & 511] = (table >> 12) << 10 | 0x1;
+        PROBE_TABLE.0[gib & 511] = (gib << 30 >> 12) << 10 | LEAF_FLAGS;
+        let probe = PagingMode::Sv48.satp_mode() << 60 | root >> 12;
+        asm!(
+            "csrw satp, {probe}",
+            "sfence.vma",
+            "csrr {satp}, satp",
+            "csrw satp, zero",
+            "sfence.vma",
+            probe = in(reg) probe,
+            satp = out(reg) satp,
+        );
+    }
+    satp >> 60 == PagingMode::Sv48.satp_mode()
+}
+
+/// Select the paging mode, must be called before building any page table
+pub fn init_paging_mode() {
+    if PREFER_SV48 && probe_sv48() {
+        PAGING_LEVELS.store(PagingMode::Sv48.levels(), Ordering::Relaxed);
+    }
+    info!("paging mode: {:?}", paging_mode());
+}
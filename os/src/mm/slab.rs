@@ -0,0 +1,389 @@
+//! Implementation of [`SlabCache`], object caches layered on top of the kernel heap.
+//!
+//! Kernel objects such as task control blocks and OS inodes are allocated and
+//! freed all the time and always have the same size. Serving them from caches
+//! of equally sized slots keeps them out of the buddy heap, so they no longer
+//! fragment it.
+//!
+//! An [`ObjectCache`] belongs to a single type, whose objects it allocates
+//! through its own constructors and keeps statistics of. The constructors
+//! point the global allocator at the cache while `Box::new`/`Arc::new` run.
+//!
+//! Objects allocated where no constructor can be used, e.g. inside easy-fs, are
+//! served by shared caches instead, looked up by [`Layout`] in the global
+//! allocator: once a shared cache for some type is created, every allocation
+//! with the same layout is served by that cache, whatever its type.
+use super::heap_allocator::heap_alloc_slab;
+use crate::config::{MAX_HARTS, PAGE_SIZE};
+use crate::hart::hart_id;
+use crate::kernel_test;
+use crate::sync::{pop_off, push_off, ExclusiveCell};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::alloc::Layout;
+use core::any::type_name;
+use core::marker::PhantomData;
+use core::mem::size_of;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Size (and alignment) of a single slab taken from the heap
+const SLAB_SIZE: usize = PAGE_SIZE;
+/// Maximum number of caches that can be created
+const MAX_CACHES: usize = 16;
+/// No cache, for [`ObjectCache`] not created yet and harts outside its constructors
+const NO_CACHE: usize = usize::MAX;
+
+/// Header at the start of every slab, linking all slabs of a cache
+struct SlabHeader {
+    next: *mut SlabHeader,
+}
+
+/// An unused object slot, linking all free slots of a cache
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// Statistics of a single slab cache
+#[derive(Copy, Clone, Default, Debug)]
+pub struct SlabStats {
+    /// Name of the type the cache was created for
+    pub name: &'static str,
+    /// Size of one object slot in bytes
+    pub object_size: usize,
+    /// Number of object slots in one slab
+    pub objects_per_slab: usize,
+    /// Number of slabs taken from the heap
+    pub slabs: usize,
+    /// Number of objects currently in use
+    pub active: usize,
+    /// Maximum of `active` ever observed
+    pub peak_active: usize,
+    /// Number of allocations served
+    pub total_allocs: usize,
+    /// Number of frees served
+    pub total_frees: usize,
+}
+
+/// A cache of equally sized object slots carved out of heap slabs
+pub struct SlabCache {
+    layout: Layout,
+    /// Whether it serves every allocation of `layout`, or only those of the
+    /// constructors of its [`ObjectCache`]
+    shared: bool,
+    slot_offset: usize,
+    slabs: *mut SlabHeader,
+    free_list: *mut FreeObject,
+    stats: SlabStats,
+}
+
+impl SlabCache {
+    /// Create an empty cache for objects of `layout` named `name`
+    fn new(name: &'static str, layout: Layout, shared: bool) -> Self {
+        let slot = layout
+            .align_to(core::mem::align_of::<FreeObject>())
+            .unwrap()
+            .pad_to_align();
+        let slot_size = slot.size();
+        // keep the slab header in front of the first slot
+        let slot_offset = (size_of::<SlabHeader>() + slot.align() - 1) & !(slot.align() - 1);
+        let objects_per_slab = (SLAB_SIZE - slot_offset) / slot_size;
+        assert!(
+            objects_per_slab > 0,
+            "object of {:?} does not fit in a slab",
+            layout
+        );
+        Self {
+            layout,
+            shared,
+            slot_offset,
+            slabs: null_mut(),
+            free_list: null_mut(),
+            stats: SlabStats {
+                name,
+                object_size: slot_size,
+                objects_per_slab,
+                ..Default::default()
+            },
+        }
+    }
+    /// Take a new slab from the heap and put all its slots on the free list
+    fn grow(&mut self) -> bool {
+        let slab = heap_alloc_slab(SLAB_SIZE);
+        if slab.is_null() {
+            return false;
+        }
+        let header = slab as *mut SlabHeader;
+        unsafe {
+            (*header).next = self.slabs;
+        }
+        self.slabs = header;
+        for i in (0..self.stats.objects_per_slab).rev() {
+            let obj =
+                (slab as usize + self.slot_offset + i * self.stats.object_size) as *mut FreeObject;
+            unsafe {
+                (*obj).next = self.free_list;
+            }
+            self.free_list = obj;
+        }
+        self.stats.slabs += 1;
+        true
+    }
+    /// Check whether `ptr` lies inside one of the slabs of this cache
+    fn owns(&self, ptr: *mut u8) -> bool {
+        let base = ptr as usize & !(SLAB_SIZE - 1);
+        let mut slab = self.slabs;
+        while !slab.is_null() {
+            if slab as usize == base {
+                return true;
+            }
+            slab = unsafe { (*slab).next };
+        }
+        false
+    }
+    /// Allocate an object slot, growing the cache if needed
+    fn alloc(&mut self) -> *mut u8 {
+        if self.free_list.is_null() && !self.grow() {
+            return null_mut();
+        }
+        let obj = self.free_list;
+        self.free_list = unsafe { (*obj).next };
+        self.stats.total_allocs += 1;
+        self.stats.active += 1;
+        self.stats.peak_active = self.stats.peak_active.max(self.stats.active);
+        obj as *mut u8
+    }
+    /// Return an object slot to the cache
+    fn dealloc(&mut self, ptr: *mut u8) {
+        let obj = ptr as *mut FreeObject;
+        unsafe {
+            (*obj).next = self.free_list;
+        }
+        self.free_list = obj;
+        self.stats.total_frees += 1;
+        self.stats.active -= 1;
+    }
+}
+
+/// All created caches; must not allocate since it is used by the global allocator.
+static SLAB_CACHES: ExclusiveCell<[Option<SlabCache>; MAX_CACHES]> = {
+    const NONE: Option<SlabCache> = None;
+    ExclusiveCell::new([NONE; MAX_CACHES])
+};
+
+/// The cache each hart is in a constructor of [`ObjectCache`] for, or [`NO_CACHE`]
+static CONSTRUCTING: [AtomicUsize; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NONE: AtomicUsize = AtomicUsize::new(NO_CACHE);
+    [NONE; MAX_HARTS]
+};
+
+/// Create a cache for objects of `layout` named `name` and return its index.
+/// A shared cache is returned instead of created if there is one for `layout`.
+fn create_cache(name: &'static str, layout: Layout, shared: bool) -> usize {
+    let mut caches = SLAB_CACHES.exclusive_access();
+    if shared {
+        if let Some(id) = caches.iter().position(
+            |cache| matches!(cache, Some(cache) if cache.shared && cache.layout == layout),
+        ) {
+            return id;
+        }
+    }
+    let id = caches
+        .iter()
+        .position(|cache| cache.is_none())
+        .expect("Run out of slab caches!");
+    caches[id] = Some(SlabCache::new(name, layout, shared));
+    id
+}
+
+/// Layout of the allocation of `Arc<T>`, which also holds the two reference
+/// counters in front of `T`
+fn arc_layout<T>() -> Layout {
+    let (layout, _) = Layout::new::<[usize; 2]>()
+        .extend(Layout::new::<T>())
+        .unwrap();
+    layout.pad_to_align()
+}
+
+/// Create a shared cache for the layout of `Box<T>`, serving objects of type `T`
+/// allocated with `Box::new` and anything else of the same layout
+#[allow(unused)]
+pub fn create_box_cache<T>() {
+    create_cache(type_name::<T>(), Layout::new::<T>(), true);
+}
+
+/// Create a shared cache for the layout of `Arc<T>`, serving objects of type `T`
+/// allocated with `Arc::new` and anything else of the same layout
+pub fn create_arc_cache<T>() {
+    create_cache(type_name::<T>(), arc_layout::<T>(), true);
+}
+
+/// A cache of the objects of type `T` allocated by its constructors, kept
+/// apart from the objects of other types of the same layout
+pub struct ObjectCache<T> {
+    id: AtomicUsize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> ObjectCache<T> {
+    /// A cache to be created by [`Self::init_box`] or [`Self::init_arc`]; until
+    /// then its constructors allocate from the heap
+    pub const fn new() -> Self {
+        Self {
+            id: AtomicUsize::new(NO_CACHE),
+            _marker: PhantomData,
+        }
+    }
+    /// Create the cache for the objects of [`Self::new_box`]
+    #[allow(unused)]
+    pub fn init_box(&self) {
+        let id = create_cache(type_name::<T>(), Layout::new::<T>(), false);
+        self.id.store(id, Ordering::Relaxed);
+    }
+    /// Create the cache for the objects of [`Self::new_arc`]
+    pub fn init_arc(&self) {
+        let id = create_cache(type_name::<T>(), arc_layout::<T>(), false);
+        self.id.store(id, Ordering::Relaxed);
+    }
+    /// Allocate `value` in a `Box` from the cache
+    #[allow(unused)]
+    pub fn new_box(&self, value: T) -> Box<T> {
+        self.construct(|| Box::new(value))
+    }
+    /// Allocate `value` in an `Arc` from the cache
+    pub fn new_arc(&self, value: T) -> Arc<T> {
+        self.construct(|| Arc::new(value))
+    }
+    /// Run `f` with the allocations of the layout of the cache served from it.
+    /// Interrupts are disabled, so that their handlers allocate as usual.
+    fn construct<R>(&self, f: impl FnOnce() -> R) -> R {
+        push_off();
+        let constructing = &CONSTRUCTING[hart_id()];
+        constructing.store(self.id.load(Ordering::Relaxed), Ordering::Relaxed);
+        let ret = f();
+        constructing.store(NO_CACHE, Ordering::Relaxed);
+        pop_off();
+        ret
+    }
+}
+
+/// Allocate from the cache of the constructor running on this hart if it is
+/// for `layout`, or else from the shared cache for `layout`. `None` if there is
+/// no such cache or it is out of memory, leaving the allocation to the heap.
+pub(super) fn slab_alloc(layout: Layout) -> Option<*mut u8> {
+    let mut caches = SLAB_CACHES.exclusive_access();
+    let constructing = CONSTRUCTING[hart_id()].load(Ordering::Relaxed);
+    let cache = match caches.get_mut(constructing) {
+        Some(Some(cache)) if cache.layout == layout => cache,
+        _ => caches
+            .iter_mut()
+            .flatten()
+            .find(|cache| cache.shared && cache.layout == layout)?,
+    };
+    let ptr = cache.alloc();
+    if ptr.is_null() {
+        None
+    } else {
+        Some(ptr)
+    }
+}
+
+/// Free `ptr` into its cache, `false` if it was not allocated from a cache
+pub(super) fn slab_dealloc(ptr: *mut u8, layout: Layout) -> bool {
+    let mut caches = SLAB_CACHES.exclusive_access();
+    if let Some(cache) = caches
+        .iter_mut()
+        .flatten()
+        .find(|cache| cache.layout == layout && cache.owns(ptr))
+    {
+        cache.dealloc(ptr);
+        true
+    } else {
+        false
+    }
+}
+
+/// Call `f` with the layout and statistics of every cache
+pub fn for_each_slab_cache(mut f: impl FnMut(Layout, SlabStats)) {
+    // copy out first so that `f` is free to allocate
+    let mut snapshot: [Option<(Layout, SlabStats)>; MAX_CACHES] = [None; MAX_CACHES];
+    for (slot, cache) in snapshot
+        .iter_mut()
+        .zip(SLAB_CACHES.exclusive_access().iter())
+    {
+        *slot = cache.as_ref().map(|cache| (cache.layout, cache.stats));
+    }
+    for (layout, stats) in snapshot.iter().flatten() {
+        f(*layout, *stats);
+    }
+}
+
+/// Print statistics of all slab caches, by the type they were created for
+pub fn print_slab_stats() {
+    println!("size  align  slot  per-slab  slabs  active  peak  allocs  frees  name");
+    for_each_slab_cache(|layout, stats| {
+        println!(
+            "{:>4}  {:>5}  {:>4}  {:>8}  {:>5}  {:>6}  {:>4}  {:>6}  {:>5}  {}",
+            layout.size(),
+            layout.align(),
+            stats.object_size,
+            stats.objects_per_slab,
+            stats.slabs,
+            stats.active,
+            stats.peak_active,
+            stats.total_allocs,
+            stats.total_frees,
+            stats.name
+        );
+    });
+}
+
+/// a simple test for slab caches
+pub fn slab_test() {
+    use alloc::vec::Vec;
+    struct Object([usize; 7]);
+    struct Typed([usize; 7]);
+    static TYPED_CACHE: ObjectCache<Typed> = ObjectCache::new();
+    create_box_cache::<Object>();
+    TYPED_CACHE.init_box();
+    let stats = |name: &str| {
+        let mut found = None;
+        for_each_slab_cache(|_, stats| {
+            if stats.name == name {
+                found = Some(stats);
+            }
+        });
+        found.unwrap()
+    };
+    let before = stats(type_name::<Object>());
+    // the shared cache serves anything else of the same layout too
+    let mut v: Vec<Box<Object>> = Vec::new();
+    for i in 0..200 {
+        v.push(Box::new(Object([i; 7])));
+    }
+    for (i, obj) in v.iter().enumerate() {
+        assert_eq!(obj.0[6], i);
+    }
+    assert!(stats(type_name::<Object>()).total_allocs >= before.total_allocs + 200);
+    drop(v);
+    assert!(stats(type_name::<Object>()).total_frees >= before.total_frees + 200);
+    // but not the objects of the constructors of an object cache of the same layout
+    let before = stats(type_name::<Object>());
+    let typed: Vec<Box<Typed>> = (0..100)
+        .map(|i| TYPED_CACHE.new_box(Typed([i; 7])))
+        .collect();
+    let typed_stats = stats(type_name::<Typed>());
+    assert_eq!((typed_stats.active, typed_stats.total_allocs), (100, 100));
+    assert_eq!(
+        stats(type_name::<Object>()).total_allocs,
+        before.total_allocs
+    );
+    for (i, obj) in typed.iter().enumerate() {
+        assert_eq!(obj.0[6], i);
+    }
+    drop(typed);
+    assert_eq!(stats(type_name::<Typed>()).active, 0);
+    info!("slab_test passed!");
+}
+kernel_test!(slab_test);
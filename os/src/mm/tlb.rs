@@ -0,0 +1,87 @@
+//! TLB maintenance with `sfence.vma`
+//!
+//! Mapping changes only fence the pages they touch, tagged with the asid of
+//! the address space, so other entries survive in the TLB. Once other harts
+//! are online, the fences are sent to all harts through SBI.
+use super::{VPNRange, VirtAddr, VirtPageNum};
+use crate::config::PAGE_SIZE;
+use crate::hart::online_harts;
+use crate::sbi::{remote_sfence_vma, remote_sfence_vma_asid};
+use core::arch::asm;
+
+/// Ranges with more pages than this are flushed with a single per-asid fence
+const FLUSH_PAGES_LIMIT: usize = 16;
+
+/// Flush the whole TLB
+pub fn flush_all() {
+    if online_harts() > 1 {
+        return remote_sfence_vma(0, usize::MAX);
+    }
+    unsafe {
+        asm!("sfence.vma");
+    }
+}
+
+/// Flush the TLB entries tagged with `asid`, or the whole TLB if `asid` is 0
+pub fn flush_asid(asid: usize) {
+    if asid == 0 {
+        flush_all();
+    } else if online_harts() > 1 {
+        remote_sfence_vma_asid(0, usize::MAX, asid);
+    } else {
+        unsafe {
+            asm!("sfence.vma zero, {}", in(reg) asid);
+        }
+    }
+}
+
+/// Like [`flush_page`], but on the current hart only
+pub fn flush_local_page(vpn: VirtPageNum, asid: usize) {
+    let va: VirtAddr = vpn.into();
+    unsafe {
+        if asid == 0 {
+            asm!("sfence.vma {}, zero", in(reg) va.0);
+        } else {
+            asm!("sfence.vma {}, {}", in(reg) va.0, in(reg) asid);
+        }
+    }
+}
+
+/// Flush the TLB entries of page `vpn` tagged with `asid`, or of every asid if `asid` is 0
+pub fn flush_page(vpn: VirtPageNum, asid: usize) {
+    let va: VirtAddr = vpn.into();
+    if online_harts() > 1 {
+        return if asid == 0 {
+            remote_sfence_vma(va.0, PAGE_SIZE)
+        } else {
+            remote_sfence_vma_asid(va.0, PAGE_SIZE, asid)
+        };
+    }
+    unsafe {
+        if asid == 0 {
+            asm!("sfence.vma {}, zero", in(reg) va.0);
+        } else {
+            asm!("sfence.vma {}, {}", in(reg) va.0, in(reg) asid);
+        }
+    }
+}
+
+/// Flush the TLB entries of the pages in `range` tagged with `asid`
+pub fn flush_range(range: VPNRange, asid: usize) {
+    let pages = range.get_end().0 - range.get_start().0;
+    if pages > FLUSH_PAGES_LIMIT {
+        flush_asid(asid);
+    } else if online_harts() > 1 {
+        // a single remote fence covers the whole range
+        let start: VirtAddr = range.get_start().into();
+        if asid == 0 {
+            remote_sfence_vma(start.0, pages * PAGE_SIZE);
+        } else {
+            remote_sfence_vma_asid(start.0, pages * PAGE_SIZE, asid);
+        }
+    } else {
+        for vpn in range {
+            flush_page(vpn, asid);
+        }
+    }
+}
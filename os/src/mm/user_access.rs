@@ -0,0 +1,135 @@
+//! Checked access to user memory from syscalls.
+//!
+//! Every user pointer handed to the kernel goes through the helpers here,
+//! which walk the page table of the user address space and make sure each
+//! page touched is mapped, accessible in U mode, and readable or writable as
+//! required. A bad pointer makes the helper return `None` instead of
+//! panicking the kernel.
+use super::paging::user_space_end;
+use super::{PageTable, PhysPageNum, StepByOne, UserBuffer, VirtAddr};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem::{size_of, MaybeUninit};
+
+/// Check that `[start, start + len)` lies in user space
+fn user_range_valid(start: usize, len: usize) -> bool {
+    match start.checked_add(len) {
+        Some(end) => end <= user_space_end(),
+        None => false,
+    }
+}
+
+/// Translate the user page containing `va`, `None` if it is not accessible
+fn translate_user_page(page_table: &PageTable, va: usize, write: bool) -> Option<PhysPageNum> {
+    let pte = page_table.translate(VirtAddr::from(va).floor())?;
+    if !pte.is_valid() || !pte.is_user() || !pte.readable() || (write && !pte.writable()) {
+        return None;
+    }
+    Some(pte.ppn())
+}
+
+/// Split the user range `[start, start + len)` into pieces within single pages
+/// and translate them, `None` if any page is not accessible
+fn translate_user_range(
+    token: usize,
+    start: usize,
+    len: usize,
+    write: bool,
+) -> Option<Vec<&'static mut [u8]>> {
+    if !user_range_valid(start, len) {
+        return None;
+    }
+    let page_table = PageTable::from_token(token);
+    let end = start + len;
+    let mut start = start;
+    let mut v = Vec::new();
+    while start < end {
+        let start_va = VirtAddr::from(start);
+        let mut vpn = start_va.floor();
+        let ppn = translate_user_page(&page_table, start, write)?;
+        vpn.step();
+        let mut end_va: VirtAddr = vpn.into();
+        end_va = end_va.min(VirtAddr::from(end));
+        if end_va.page_offset() == 0 {
+            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..]);
+        } else {
+            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..end_va.page_offset()]);
+        }
+        start = end_va.into();
+    }
+    Some(v)
+}
+
+/// Copy a `T` from user address `src`
+pub fn copy_from_user<T: Copy>(token: usize, src: *const T) -> Option<T> {
+    let mut value = MaybeUninit::<T>::uninit();
+    let dst =
+        unsafe { core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>()) };
+    let mut copied = 0;
+    for piece in translate_user_range(token, src as usize, size_of::<T>(), false)? {
+        dst[copied..copied + piece.len()].copy_from_slice(piece);
+        copied += piece.len();
+    }
+    Some(unsafe { value.assume_init() })
+}
+
+/// Copy `value` to user address `dst`
+pub fn copy_to_user<T: Copy>(token: usize, dst: *mut T, value: &T) -> Option<()> {
+    copy_slice_to_user(token, dst, core::slice::from_ref(value))
+}
+
+/// Copy the elements of `values` to the user array at `dst`
+pub fn copy_slice_to_user<T: Copy>(token: usize, dst: *mut T, values: &[T]) -> Option<()> {
+    let src = unsafe {
+        core::slice::from_raw_parts(values.as_ptr() as *const u8, size_of::<T>() * values.len())
+    };
+    let mut copied = 0;
+    for piece in translate_user_range(token, dst as usize, src.len(), true)? {
+        let len = piece.len();
+        piece.copy_from_slice(&src[copied..copied + len]);
+        copied += len;
+    }
+    Some(())
+}
+
+/// Copy `value` to user address `dst` even if the pages are read-only, e.g. for
+/// a debugger setting a breakpoint in code. They must still be readable.
+pub fn force_copy_to_user<T: Copy>(token: usize, dst: *mut T, value: &T) -> Option<()> {
+    let src =
+        unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+    let mut copied = 0;
+    for piece in translate_user_range(token, dst as usize, src.len(), false)? {
+        let len = piece.len();
+        piece.copy_from_slice(&src[copied..copied + len]);
+        copied += len;
+    }
+    Some(())
+}
+
+/// Copy a `\0`-terminated string from user address `src`
+pub fn copy_str_from_user(token: usize, src: *const u8) -> Option<String> {
+    let page_table = PageTable::from_token(token);
+    let mut string = String::new();
+    let mut va = src as usize;
+    loop {
+        if !user_range_valid(va, 1) {
+            return None;
+        }
+        let ppn = translate_user_page(&page_table, va, false)?;
+        for &ch in &ppn.get_bytes_array()[VirtAddr::from(va).page_offset()..] {
+            if ch == 0 {
+                return Some(string);
+            }
+            string.push(ch as char);
+            va += 1;
+        }
+    }
+}
+
+impl UserBuffer {
+    /// Translate the user buffer `[ptr, ptr + len)`, which the kernel reads from,
+    /// or writes to if `write` is set
+    pub fn from_user(token: usize, ptr: *const u8, len: usize, write: bool) -> Option<Self> {
+        translate_user_range(token, ptr as usize, len, write).map(Self::new)
+    }
+}
@@ -0,0 +1,132 @@
+//! vmalloc-style dynamic mappings in kernel space
+//!
+//! The area [`VMALLOC_START`, `VMALLOC_END`) of kernel space is handed out on
+//! demand to map arbitrary, possibly non-contiguous frames at contiguous
+//! virtual addresses, e.g. large driver buffers or user pages that the kernel
+//! accesses temporarily. Each mapping is followed by an unmapped guard page.
+use super::KERNEL_SPACE;
+use super::{frame_alloc, FrameTracker, MapPermission, PhysPageNum, VirtAddr, VirtPageNum};
+use crate::config::{PAGE_SIZE, VMALLOC_END, VMALLOC_START};
+use crate::kernel_test;
+use crate::sync::ExclusiveCell;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// First-fit allocator of page ranges inside the vmalloc area
+struct VmallocAllocator {
+    /// start vpn -> number of pages, including the guard page
+    used: BTreeMap<usize, usize>,
+}
+
+impl VmallocAllocator {
+    fn new() -> Self {
+        Self {
+            used: BTreeMap::new(),
+        }
+    }
+    /// Find room for `pages` pages plus a guard page
+    fn alloc(&mut self, pages: usize) -> Option<VirtPageNum> {
+        let size = pages + 1;
+        let mut start = VirtAddr::from(VMALLOC_START).floor().0;
+        let end = VirtAddr::from(VMALLOC_END).floor().0;
+        for (&used_start, &used_size) in self.used.iter() {
+            if start + size <= used_start {
+                break;
+            }
+            start = used_start + used_size;
+        }
+        if start + size > end {
+            return None;
+        }
+        self.used.insert(start, size);
+        Some(VirtPageNum(start))
+    }
+    fn dealloc(&mut self, start: VirtPageNum) {
+        self.used.remove(&start.0);
+    }
+}
+
+lazy_static! {
+    static ref VMALLOC_ALLOCATOR: ExclusiveCell<VmallocAllocator> =
+        ExclusiveCell::new(VmallocAllocator::new());
+}
+
+/// A range of kernel space mapped by [`vmap`] or [`vmalloc`], unmapped on drop
+pub struct VmMapping {
+    start_vpn: VirtPageNum,
+    pages: usize,
+    /// frames allocated by `vmalloc`, empty for `vmap`
+    frames: Vec<FrameTracker>,
+}
+
+impl VmMapping {
+    /// Start address of the mapping, usable by the kernel
+    pub fn start(&self) -> usize {
+        let area_start_vpn = VirtAddr::from(VMALLOC_START).floor();
+        VMALLOC_START + (self.start_vpn.0 - area_start_vpn.0) * PAGE_SIZE
+    }
+    /// Size of the mapping in bytes
+    pub fn size(&self) -> usize {
+        self.pages * PAGE_SIZE
+    }
+    /// Access the whole mapping as a byte slice
+    pub fn as_bytes_mut(&self) -> &'static mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.start() as *mut u8, self.size()) }
+    }
+}
+
+impl Drop for VmMapping {
+    fn drop(&mut self) {
+        KERNEL_SPACE
+            .exclusive_access()
+            .unmap_pages(self.start_vpn, self.pages);
+        VMALLOC_ALLOCATOR.exclusive_access().dealloc(self.start_vpn);
+    }
+}
+
+/// Map `ppns` to contiguous kernel virtual addresses. The frames are not owned
+/// by the mapping. Return `None` if out of virtual space or frames.
+pub fn vmap(ppns: &[PhysPageNum], permission: MapPermission) -> Option<VmMapping> {
+    let start_vpn = VMALLOC_ALLOCATOR.exclusive_access().alloc(ppns.len())?;
+    if !KERNEL_SPACE
+        .exclusive_access()
+        .map_pages(start_vpn, ppns, permission)
+    {
+        VMALLOC_ALLOCATOR.exclusive_access().dealloc(start_vpn);
+        return None;
+    }
+    Some(VmMapping {
+        start_vpn,
+        pages: ppns.len(),
+        frames: Vec::new(),
+    })
+}
+
+/// Allocate `pages` frames and map them readable and writable to contiguous
+/// kernel virtual addresses. Return `None` if out of virtual space or frames.
+pub fn vmalloc(pages: usize) -> Option<VmMapping> {
+    let mut frames = Vec::with_capacity(pages);
+    for _ in 0..pages {
+        frames.push(frame_alloc()?);
+    }
+    let ppns: Vec<PhysPageNum> = frames.iter().map(|frame| frame.ppn).collect();
+    let mut mapping = vmap(&ppns, MapPermission::R | MapPermission::W)?;
+    mapping.frames = frames;
+    Some(mapping)
+}
+
+/// a simple test for vmalloc
+pub fn vmalloc_test() {
+    let a = vmalloc(3).unwrap();
+    let b = vmalloc(2).unwrap();
+    assert_eq!(b.start(), a.start() + a.size() + PAGE_SIZE);
+    a.as_bytes_mut().fill(0x5a);
+    assert!(a.as_bytes_mut().iter().all(|&byte| byte == 0x5a));
+    let start = a.start();
+    drop(a);
+    let c = vmalloc(1).unwrap();
+    assert_eq!(c.start(), start);
+    info!("vmalloc_test passed!");
+}
+kernel_test!(vmalloc_test);
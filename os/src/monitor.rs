@@ -0,0 +1,183 @@
+//! Debug monitor on the UARTs, for when the shell is wedged
+//!
+//! Typing the escape key `Ctrl-]` on a UART, then a command key, runs the
+//! command in the interrupt handler of the UART, whatever the tasks are doing,
+//! instead of passing the keys to the readers. Typing the escape key twice
+//! passes it once. The commands print on the kernel console:
+//!
+//! - `t`: the tasks
+//! - `m`: memory usage
+//! - `l`: the locks each hart holds, tracked in debug builds only
+//! - `b`: the state of the block cache
+//! - `o` then a hart id: take the hart offline
+//! - `n` then a hart id: bring the hart back online
+//! - `c`: panic
+//! - `r`: reboot
+//! - any other key: the commands
+use crate::config::PAGE_SIZE;
+use crate::hart::{hart_offline, hart_online};
+use crate::mm::{frame_stats, heap_stats, print_slab_stats};
+use crate::sbi::reboot;
+use crate::sync::lockdep::print_held_locks;
+use crate::task::{all_processes, TaskStatus};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use easy_fs::block_cache_stats;
+
+/// `Ctrl-]`, starting a command
+const ESCAPE: u8 = 0x1d;
+
+/// Commands with their keys
+const COMMANDS: &[(u8, &str)] = &[
+    (b't', "show the tasks"),
+    (b'm', "show memory usage"),
+    (b'l', "show the locks held"),
+    (b'b', "show the block cache"),
+    (b'o', "take hart <n> offline"),
+    (b'n', "bring hart <n> online"),
+    (b'c', "panic"),
+    (b'r', "reboot"),
+];
+
+/// Where an input is in an escape sequence
+#[derive(Default)]
+pub struct Monitor {
+    /// the escape key was typed last
+    escaped: AtomicBool,
+    /// key of the command waiting for its hart id, 0 if none
+    command: AtomicU8,
+}
+
+impl Monitor {
+    /// An input outside any escape sequence
+    pub const fn new() -> Self {
+        Self {
+            escaped: AtomicBool::new(false),
+            command: AtomicU8::new(0),
+        }
+    }
+    /// Take `byte` received on the input, in an interrupt handler, and run
+    /// the command it ends if any. `Some` of the byte for the readers,
+    /// `None` if it belongs to an escape sequence.
+    pub fn filter(&self, byte: u8) -> Option<u8> {
+        match self.command.swap(0, Ordering::Relaxed) {
+            0 => {}
+            command => {
+                hotplug(command, byte);
+                return None;
+            }
+        }
+        if self.escaped.swap(false, Ordering::Relaxed) {
+            match byte {
+                ESCAPE => return Some(byte),
+                b'o' | b'n' => self.command.store(byte, Ordering::Relaxed),
+                _ => run(byte),
+            }
+            return None;
+        }
+        if byte == ESCAPE {
+            self.escaped.store(true, Ordering::Relaxed);
+            return None;
+        }
+        Some(byte)
+    }
+}
+
+/// Run the command of `key`
+fn run(key: u8) {
+    match key {
+        b't' => print_tasks(),
+        b'm' => print_memory(),
+        b'l' => print_held_locks(),
+        b'b' => print_block_cache(),
+        b'c' => panic!("panic forced by the debug monitor"),
+        b'r' => {
+            warn!("reboot forced by the debug monitor");
+            reboot();
+            error!("the firmware cannot reboot");
+        }
+        _ => {
+            println!("debug monitor, Ctrl-] then:");
+            for &(key, help) in COMMANDS {
+                println!("  {}  {}", key as char, help);
+            }
+        }
+    }
+}
+
+/// Take the hart of digit `key` offline with command `o`, or online with `n`
+fn hotplug(command: u8, key: u8) {
+    if !key.is_ascii_digit() {
+        println!("no hart {}", key as char);
+        return;
+    }
+    let hart = (key - b'0') as usize;
+    let (result, state) = match command {
+        b'o' => (hart_offline(hart), "offline"),
+        _ => (hart_online(hart), "online"),
+    };
+    match result {
+        Ok(()) => println!("hart {} going {}", hart, state),
+        Err(err) => println!("hart {} cannot go {}: {:?}", hart, state, err),
+    }
+}
+
+fn print_tasks() {
+    println!("  pid   tid  state  cpu  prio  comm");
+    for process in all_processes() {
+        let pid = process.getpid();
+        let inner = process.inner_exclusive_access();
+        let tasks: Vec<_> = inner.tasks.iter().flatten().cloned().collect();
+        drop(inner);
+        for task in tasks {
+            let inner = task.inner_exclusive_access();
+            let state = match inner.task_status {
+                TaskStatus::Running => 'R',
+                TaskStatus::Ready => 'r',
+                TaskStatus::Blocked if inner.uninterruptible => 'D',
+                TaskStatus::Blocked => 'S',
+                TaskStatus::Exited => 'Z',
+            };
+            println!(
+                "{:>5} {:>5}  {:>5}  {:>3}  {:>4}  {}",
+                pid,
+                inner.res.as_ref().map_or(0, |res| res.tid),
+                state,
+                inner.cpu,
+                inner.effective_priority(),
+                inner.comm()
+            );
+        }
+    }
+}
+
+fn print_memory() {
+    let frames = frame_stats();
+    let heap = heap_stats();
+    let kb = |frames: usize| frames * PAGE_SIZE / 1024;
+    println!(
+        "frames: {} kB total, {} kB free, {} kB user, {} kB page tables, {} kB kernel, {} kB balloon",
+        kb(frames.total),
+        kb(frames.free()),
+        kb(frames.user),
+        kb(frames.page_table),
+        kb(frames.kernel),
+        kb(frames.balloon)
+    );
+    println!(
+        "heap: {} kB used of {} kB",
+        heap.used_bytes / 1024,
+        heap.total_bytes / 1024
+    );
+    print_slab_stats();
+}
+
+fn print_block_cache() {
+    match block_cache_stats() {
+        Some(stats) => println!(
+            "block cache: {} of {} blocks cached, {} in use, {} dirty, {} locked",
+            stats.cached, stats.capacity, stats.in_use, stats.dirty, stats.locked
+        ),
+        None => println!("block cache: locked"),
+    }
+}
@@ -0,0 +1,104 @@
+//! ARP, resolving the MAC addresses of the hosts on the network of the guest
+use super::{
+    send_frame, Ipv4Addr, MacAddr, SendError, BROADCAST_MAC, ETHERTYPE_ARP, ETHERTYPE_IPV4,
+    LOCAL_IP,
+};
+use crate::config::CLOCK_FREQ;
+use crate::drivers::NET_DEVICE;
+use crate::sync::{Lazy, SpinNoIrqLock};
+use crate::task::{current_has_signal, WaitQueue};
+use crate::timer::get_time;
+use alloc::collections::BTreeMap;
+
+/// ARP packet for IPv4 over Ethernet
+const ARP_LEN: usize = 28;
+const HTYPE_ETHERNET: u16 = 1;
+const OP_REQUEST: u16 = 1;
+const OP_REPLY: u16 = 2;
+/// Requests sent for an address before giving up
+const ARP_RETRIES: usize = 3;
+/// Time to wait for a reply to each request
+const ARP_TIMEOUT_MS: usize = 500;
+
+/// MAC addresses resolved, never expiring
+static ARP_CACHE: Lazy<SpinNoIrqLock<BTreeMap<Ipv4Addr, MacAddr>>> =
+    Lazy::new(|| SpinNoIrqLock::new(BTreeMap::new()));
+/// Tasks waiting for replies
+static ARP_WAIT: Lazy<WaitQueue> = Lazy::new(WaitQueue::new);
+
+/// The MAC address of `ip`, sending requests and blocking until a reply
+/// arrives, a signal or the last request times out
+pub fn resolve(ip: Ipv4Addr) -> Result<MacAddr, SendError> {
+    for _ in 0..ARP_RETRIES {
+        if let Some(mac) = ARP_CACHE.lock().get(&ip) {
+            return Ok(*mac);
+        }
+        send_arp(OP_REQUEST, BROADCAST_MAC, [0; 6], ip)?;
+        let expire = get_time() + ARP_TIMEOUT_MS * CLOCK_FREQ / 1000;
+        if !ARP_WAIT.wait_until_timeout(expire, || ARP_CACHE.lock().contains_key(&ip))
+            && current_has_signal()
+        {
+            break;
+        }
+    }
+    ARP_CACHE
+        .lock()
+        .get(&ip)
+        .copied()
+        .ok_or(SendError::Unreachable)
+}
+
+/// The MAC address of `ip` if resolved, sending a request otherwise without
+/// waiting for the reply
+pub fn lookup(ip: Ipv4Addr) -> Result<MacAddr, SendError> {
+    if let Some(mac) = ARP_CACHE.lock().get(&ip) {
+        return Ok(*mac);
+    }
+    send_arp(OP_REQUEST, BROADCAST_MAC, [0; 6], ip)?;
+    Err(SendError::Unreachable)
+}
+
+/// Learn the address of the sender of a packet to the guest, replying to requests
+pub fn handle_arp(packet: &[u8]) {
+    if packet.len() < ARP_LEN
+        || u16::from_be_bytes([packet[0], packet[1]]) != HTYPE_ETHERNET
+        || u16::from_be_bytes([packet[2], packet[3]]) != ETHERTYPE_IPV4
+        || packet[4] != 6
+        || packet[5] != 4
+    {
+        return;
+    }
+    let op = u16::from_be_bytes([packet[6], packet[7]]);
+    let sender_mac: MacAddr = packet[8..14].try_into().unwrap();
+    let sender_ip: Ipv4Addr = packet[14..18].try_into().unwrap();
+    if packet[24..28] != LOCAL_IP {
+        return;
+    }
+    ARP_CACHE.lock().insert(sender_ip, sender_mac);
+    ARP_WAIT.wake_all();
+    if op == OP_REQUEST {
+        // nothing to do if the device is busy, the sender asks again
+        let _ = send_arp(OP_REPLY, sender_mac, sender_mac, sender_ip);
+    }
+}
+
+/// Send an ARP packet of `op` about the guest to `dst`, for `target_ip` at `target_mac`
+fn send_arp(
+    op: u16,
+    dst: MacAddr,
+    target_mac: MacAddr,
+    target_ip: Ipv4Addr,
+) -> Result<(), SendError> {
+    let device = NET_DEVICE.as_ref().ok_or(SendError::NoDevice)?;
+    let mut packet = [0u8; ARP_LEN];
+    packet[0..2].copy_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+    packet[2..4].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+    packet[4] = 6;
+    packet[5] = 4;
+    packet[6..8].copy_from_slice(&op.to_be_bytes());
+    packet[8..14].copy_from_slice(&device.mac());
+    packet[14..18].copy_from_slice(&LOCAL_IP);
+    packet[18..24].copy_from_slice(&target_mac);
+    packet[24..28].copy_from_slice(&target_ip);
+    send_frame(dst, ETHERTYPE_ARP, &packet)
+}
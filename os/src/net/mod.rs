@@ -0,0 +1,261 @@
+//! A minimal network stack: UDP and TCP over IPv4 with ARP, on the virtio
+//! network device
+//!
+//! The addresses are those the user networking of QEMU gives the guest, whose
+//! gateway forwards the traffic to the host. Frames are received in the
+//! [`Softirq::NetRx`] softirq. Packets to the local address or a loopback one
+//! are delivered without going through the device, which may be missing.
+//! Fragmented packets, IP options and ICMP are not supported.
+//!
+//! [`Softirq::NetRx`]: crate::trap::Softirq::NetRx
+mod arp;
+mod tcp;
+mod udp;
+
+pub use tcp::{TcpError, TcpSocket};
+pub use udp::{UdpSocket, MAX_UDP_PAYLOAD};
+
+use crate::drivers::NET_DEVICE;
+
+use crate::sync::{Lazy, SpinNoIrqLock};
+use crate::trap::{open_softirq, Softirq};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+
+/// IPv4 address, in network byte order
+pub type Ipv4Addr = [u8; 4];
+/// Ethernet address
+type MacAddr = [u8; 6];
+
+/// Address of the guest in the user networking of QEMU
+pub const LOCAL_IP: Ipv4Addr = [10, 0, 2, 15];
+/// Address of the host in the user networking of QEMU, routing the other ones
+const GATEWAY_IP: Ipv4Addr = [10, 0, 2, 2];
+const NETMASK: Ipv4Addr = [255, 255, 255, 0];
+const BROADCAST_IP: Ipv4Addr = [255, 255, 255, 255];
+const BROADCAST_MAC: MacAddr = [0xff; 6];
+
+const ETH_HEADER_LEN: usize = 14;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+/// IPv4 header without options
+const IPV4_HEADER_LEN: usize = 20;
+/// Largest IPv4 packet, fitting in an Ethernet frame
+const MTU: usize = 1500;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+const TTL: u8 = 64;
+/// Flag of the IPv4 header: do not fragment
+const IP_DF: u16 = 0x4000;
+
+/// Identification of the next IPv4 packet sent
+static IP_ID: AtomicU16 = AtomicU16::new(0);
+/// Packets delivered locally and not handled yet
+static LOOPBACK: Lazy<SpinNoIrqLock<VecDeque<Vec<u8>>>> =
+    Lazy::new(|| SpinNoIrqLock::new(VecDeque::new()));
+/// Whether a hart is handling the packets of [`LOOPBACK`]
+static LOOPBACK_BUSY: AtomicBool = AtomicBool::new(false);
+
+/// Why a packet could not be sent
+#[derive(Copy, Clone, Debug)]
+pub enum SendError {
+    /// there is no network device to send it
+    NoDevice,
+    /// the MAC address of the next hop is not resolved
+    Unreachable,
+    /// all the transmit buffers of the device are in flight
+    Busy,
+}
+
+/// Receive frames in the softirq of the network device, if there is one
+pub fn init() {
+    if NET_DEVICE.is_some() {
+        open_softirq(Softirq::NetRx, || {
+            NET_DEVICE.as_ref().unwrap().receive(handle_frame)
+        });
+    }
+}
+
+fn is_loopback(ip: Ipv4Addr) -> bool {
+    ip[0] == 127
+}
+
+/// Whether packets to `ip` are delivered locally
+fn is_local(ip: Ipv4Addr) -> bool {
+    ip == LOCAL_IP || is_loopback(ip)
+}
+
+/// Source address of the packets to `dst`
+fn source_ip(dst: Ipv4Addr) -> Ipv4Addr {
+    if is_loopback(dst) {
+        dst
+    } else {
+        LOCAL_IP
+    }
+}
+
+/// Whether `ip` is on the network of the guest, reached without the gateway
+fn on_link(ip: Ipv4Addr) -> bool {
+    (0..4).all(|i| ip[i] & NETMASK[i] == LOCAL_IP[i] & NETMASK[i])
+}
+
+/// Internet checksum of `data` continuing the 32-bit `sum` of 16-bit words,
+/// e.g. of a pseudo-header. It is 0 over data including a correct checksum.
+fn checksum(data: &[u8], mut sum: u32) -> u16 {
+    for word in data.chunks(2) {
+        sum += u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u32;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Sum of the pseudo-header of the checksums of UDP and TCP, for a segment of
+/// `protocol` and `len` bytes
+fn pseudo_header_sum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, len: usize) -> u32 {
+    let word = |bytes: &[u8]| u16::from_be_bytes([bytes[0], bytes[1]]) as u32;
+    word(&src[..2])
+        + word(&src[2..])
+        + word(&dst[..2])
+        + word(&dst[2..])
+        + protocol as u32
+        + len as u32
+}
+
+fn handle_frame(frame: &[u8]) {
+    if frame.len() < ETH_HEADER_LEN {
+        return;
+    }
+    let payload = &frame[ETH_HEADER_LEN..];
+    match u16::from_be_bytes([frame[12], frame[13]]) {
+        ETHERTYPE_ARP => arp::handle_arp(payload),
+        ETHERTYPE_IPV4 => handle_ipv4(payload),
+        _ => {}
+    }
+}
+
+/// Send `payload` in an Ethernet frame of `ethertype` to `dst`
+fn send_frame(dst: MacAddr, ethertype: u16, payload: &[u8]) -> Result<(), SendError> {
+    let device = NET_DEVICE.as_ref().ok_or(SendError::NoDevice)?;
+    let mut frame = Vec::with_capacity(ETH_HEADER_LEN + payload.len());
+    frame.extend_from_slice(&dst);
+    frame.extend_from_slice(&device.mac());
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    if device.transmit(&frame) {
+        Ok(())
+    } else {
+        Err(SendError::Busy)
+    }
+}
+
+fn handle_ipv4(packet: &[u8]) {
+    if packet.len() < IPV4_HEADER_LEN || packet[0] >> 4 != 4 {
+        return;
+    }
+    let header_len = (packet[0] & 0xf) as usize * 4;
+    let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if header_len < IPV4_HEADER_LEN
+        || total_len < header_len
+        || total_len > packet.len()
+        || checksum(&packet[..header_len], 0) != 0
+    {
+        return;
+    }
+    // neither more fragments nor an offset: not a fragment
+    if u16::from_be_bytes([packet[6], packet[7]]) & 0x3fff != 0 {
+        return;
+    }
+    let src: Ipv4Addr = packet[12..16].try_into().unwrap();
+    let dst: Ipv4Addr = packet[16..20].try_into().unwrap();
+    if !is_local(dst) && dst != BROADCAST_IP {
+        return;
+    }
+    let payload = &packet[header_len..total_len];
+    match packet[9] {
+        IPPROTO_TCP => tcp::handle_tcp(src, dst, payload),
+        IPPROTO_UDP => udp::handle_udp(src, dst, payload),
+        _ => {}
+    }
+}
+
+/// Handle `packet` sent to a local address. A packet sent by a handler is
+/// queued and handled once it returns, so that handlers are not nested,
+/// e.g. holding the lock of a connection.
+fn deliver_local(packet: Vec<u8>) {
+    LOOPBACK.lock().push_back(packet);
+    // the hart handling the packets may have given up before the push
+    while !LOOPBACK.lock().is_empty() && !LOOPBACK_BUSY.swap(true, Ordering::Acquire) {
+        loop {
+            let packet = LOOPBACK.lock().pop_front();
+            match packet {
+                Some(packet) => handle_ipv4(&packet),
+                None => break,
+            }
+        }
+        LOOPBACK_BUSY.store(false, Ordering::Release);
+    }
+}
+
+/// Block until the MAC address of the next hop to `dst` is resolved, so that
+/// packets sent by [`send_ipv4_nowait`] are not dropped
+fn resolve_route(dst: Ipv4Addr) -> Result<(), SendError> {
+    if is_local(dst) || dst == BROADCAST_IP {
+        return Ok(());
+    }
+    arp::resolve(next_hop(dst)).map(|_| ())
+}
+
+/// The host on the network of the guest to send packets to `dst` to
+fn next_hop(dst: Ipv4Addr) -> Ipv4Addr {
+    if on_link(dst) {
+        dst
+    } else {
+        GATEWAY_IP
+    }
+}
+
+/// Send `payload` of `protocol` to `dst` in an IPv4 packet, blocking while the
+/// MAC address of the next hop is resolved. The payload fits in [`MTU`].
+fn send_ipv4(dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Result<(), SendError> {
+    send_ipv4_with(dst, protocol, payload, arp::resolve)
+}
+
+/// Like [`send_ipv4`] without blocking, e.g. in an interrupt handler, failing
+/// with [`SendError::Unreachable`] while the next hop is not resolved
+fn send_ipv4_nowait(dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Result<(), SendError> {
+    send_ipv4_with(dst, protocol, payload, arp::lookup)
+}
+
+fn send_ipv4_with(
+    dst: Ipv4Addr,
+    protocol: u8,
+    payload: &[u8],
+    resolve: fn(Ipv4Addr) -> Result<MacAddr, SendError>,
+) -> Result<(), SendError> {
+    let total_len = (IPV4_HEADER_LEN + payload.len()) as u16;
+    let id = IP_ID.fetch_add(1, Ordering::Relaxed);
+    let mut packet = Vec::with_capacity(total_len as usize);
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&total_len.to_be_bytes());
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&IP_DF.to_be_bytes());
+    packet.extend_from_slice(&[TTL, protocol, 0, 0]);
+    packet.extend_from_slice(&source_ip(dst));
+    packet.extend_from_slice(&dst);
+    let sum = checksum(&packet, 0);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    if is_local(dst) {
+        deliver_local(packet);
+        return Ok(());
+    }
+    let mac = if dst == BROADCAST_IP {
+        BROADCAST_MAC
+    } else {
+        resolve(next_hop(dst))?
+    };
+    send_frame(mac, ETHERTYPE_IPV4, &packet)
+}
@@ -0,0 +1,475 @@
+//! TCP sockets
+//!
+//! Each connection is a [`Tcb`] in the table of connections, kept after its
+//! socket is closed until the FINs are exchanged. Segments received out of
+//! order are dropped, the peer retransmits them. Segments not acknowledged
+//! are retransmitted by a kernel timer with exponential backoff, until the
+//! connection times out after a few retransmissions. The window advertised
+//! is the room left in the receive buffer. Tasks block on the wait queue of
+//! the connection for the handshake, data, room to write and connections to
+//! accept.
+mod tcb;
+
+use super::{
+    checksum, pseudo_header_sum, resolve_route, send_ipv4_nowait, source_ip, Ipv4Addr, SendError,
+    IPPROTO_TCP,
+};
+use crate::fs::File;
+use crate::mm::UserBuffer;
+use crate::random::random_u32;
+use crate::sync::{Lazy, SpinNoIrqLock};
+use crate::timer::get_time;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use tcb::{ConnKey, State, Tcb};
+
+const FIN: u8 = 1 << 0;
+const SYN: u8 = 1 << 1;
+const RST: u8 = 1 << 2;
+const PSH: u8 = 1 << 3;
+const ACK: u8 = 1 << 4;
+
+const TCP_HEADER_LEN: usize = 20;
+/// Option kind of the maximum segment size
+const OPTION_MSS: u8 = 2;
+/// First of the ports bound when connecting or listening on a socket not bound yet
+const EPHEMERAL_PORT_START: u16 = 49152;
+/// Largest backlog of a listening socket
+const MAX_BACKLOG: usize = 128;
+
+/// Connections by their addresses and ports, not closed yet
+static CONNECTIONS: Lazy<SpinNoIrqLock<BTreeMap<ConnKey, Arc<Tcb>>>> =
+    Lazy::new(|| SpinNoIrqLock::new(BTreeMap::new()));
+/// Listening sockets by their ports
+static LISTENERS: Lazy<SpinNoIrqLock<BTreeMap<u16, Arc<Tcb>>>> =
+    Lazy::new(|| SpinNoIrqLock::new(BTreeMap::new()));
+/// Ports bound by sockets
+static PORTS: Lazy<SpinNoIrqLock<BTreeSet<u16>>> =
+    Lazy::new(|| SpinNoIrqLock::new(BTreeSet::new()));
+
+/// Why an operation on a TCP socket failed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TcpError {
+    /// the peer has no listening socket on the port
+    Refused,
+    /// the peer reset the connection
+    Reset,
+    /// the peer stopped acknowledging
+    TimedOut,
+    /// the socket is connected or listening already
+    IsConnected,
+    /// the socket is not listening
+    NotListening,
+    /// no port is free, or the connection exists already
+    AddrInUse,
+    /// a signal arrived while blocked
+    Interrupted,
+    /// the SYN cannot be sent
+    Send(SendError),
+}
+
+/// Fields of a TCP header
+pub struct TcpHeader {
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    /// the maximum segment size option, only in a SYN
+    mss: Option<u16>,
+}
+
+/// A segment to send, built with the lock of its connection held
+pub struct Segment {
+    dst: Ipv4Addr,
+    header: TcpHeader,
+    data: Vec<u8>,
+}
+
+impl TcpHeader {
+    /// Parse the header of `segment` from `src` to `dst` and check its
+    /// checksum. Return it and the offset of the data.
+    fn parse(src: Ipv4Addr, dst: Ipv4Addr, segment: &[u8]) -> Option<(Self, usize)> {
+        if segment.len() < TCP_HEADER_LEN {
+            return None;
+        }
+        let data_offset = (segment[12] >> 4) as usize * 4;
+        if data_offset < TCP_HEADER_LEN
+            || data_offset > segment.len()
+            || checksum(
+                segment,
+                pseudo_header_sum(src, dst, IPPROTO_TCP, segment.len()),
+            ) != 0
+        {
+            return None;
+        }
+        let mut mss = None;
+        let mut options = &segment[TCP_HEADER_LEN..data_offset];
+        while let Some(&kind) = options.first() {
+            match kind {
+                0 => break,
+                1 => options = &options[1..],
+                _ => {
+                    let len = *options.get(1)? as usize;
+                    if len < 2 || len > options.len() {
+                        return None;
+                    }
+                    if kind == OPTION_MSS && len == 4 {
+                        mss = Some(u16::from_be_bytes([options[2], options[3]]));
+                    }
+                    options = &options[len..];
+                }
+            }
+        }
+        let word = |at: usize| {
+            u32::from_be_bytes([
+                segment[at],
+                segment[at + 1],
+                segment[at + 2],
+                segment[at + 3],
+            ])
+        };
+        let header = Self {
+            src_port: u16::from_be_bytes([segment[0], segment[1]]),
+            dst_port: u16::from_be_bytes([segment[2], segment[3]]),
+            seq: word(4),
+            ack: word(8),
+            flags: segment[13],
+            window: u16::from_be_bytes([segment[14], segment[15]]),
+            mss,
+        };
+        Some((header, data_offset))
+    }
+}
+
+/// Send `segment` without blocking. If the next hop is not resolved, it is
+/// lost and retransmitted later.
+fn send_segment(segment: Segment) {
+    let header = &segment.header;
+    let header_len = if header.mss.is_some() {
+        TCP_HEADER_LEN + 4
+    } else {
+        TCP_HEADER_LEN
+    };
+    let mut bytes = Vec::with_capacity(header_len + segment.data.len());
+    bytes.extend_from_slice(&header.src_port.to_be_bytes());
+    bytes.extend_from_slice(&header.dst_port.to_be_bytes());
+    bytes.extend_from_slice(&header.seq.to_be_bytes());
+    bytes.extend_from_slice(&header.ack.to_be_bytes());
+    bytes.extend_from_slice(&[((header_len / 4) as u8) << 4, header.flags]);
+    bytes.extend_from_slice(&header.window.to_be_bytes());
+    // checksum and urgent pointer
+    bytes.extend_from_slice(&[0; 4]);
+    if let Some(mss) = header.mss {
+        bytes.extend_from_slice(&[OPTION_MSS, 4]);
+        bytes.extend_from_slice(&mss.to_be_bytes());
+    }
+    bytes.extend_from_slice(&segment.data);
+    let dst = segment.dst;
+    let sum = checksum(
+        &bytes,
+        pseudo_header_sum(source_ip(dst), dst, IPPROTO_TCP, bytes.len()),
+    );
+    bytes[16..18].copy_from_slice(&sum.to_be_bytes());
+    let _ = send_ipv4_nowait(dst, IPPROTO_TCP, &bytes);
+}
+
+/// Initial sequence number of a new connection, following the clock from a
+/// random offset so that it cannot be guessed
+fn new_iss() -> u32 {
+    (get_time() as u32).wrapping_add(random_u32())
+}
+
+/// Forget the closed connection `tcb` of `key`
+fn remove_connection(key: ConnKey, tcb: &Tcb) {
+    let mut connections = CONNECTIONS.lock();
+    if connections
+        .get(&key)
+        .map_or(false, |conn| core::ptr::eq(Arc::as_ptr(conn), tcb))
+    {
+        connections.remove(&key);
+    }
+}
+
+/// Pass `segment` from `src` to `dst` to its connection, start a connection
+/// for a SYN to a listening socket, or reset the sender otherwise
+pub fn handle_tcp(src: Ipv4Addr, dst: Ipv4Addr, segment: &[u8]) {
+    let (header, data_offset) = match TcpHeader::parse(src, dst, segment) {
+        Some(parsed) => parsed,
+        None => return,
+    };
+    let payload = &segment[data_offset..];
+    let key = (dst, header.dst_port, src, header.src_port);
+    let conn = CONNECTIONS.lock().get(&key).cloned();
+    if let Some(conn) = conn {
+        conn.receive(&header, payload);
+        return;
+    }
+    if header.flags & RST != 0 {
+        return;
+    }
+    let listener = LISTENERS.lock().get(&header.dst_port).cloned();
+    if let Some(listener) = listener {
+        if header.flags & (SYN | ACK) == SYN {
+            let inner = listener.inner.lock();
+            // dropped if the queue is full, the peer retries
+            if inner.state != State::Listen || inner.accept_queue.len() >= inner.backlog {
+                return;
+            }
+            drop(inner);
+            let conn = Tcb::new(key, State::SynReceived, new_iss());
+            CONNECTIONS.lock().insert(key, Arc::clone(&conn));
+            let mut inner = conn.inner.lock();
+            inner.listener = Some(Arc::downgrade(&listener));
+            inner.accept_syn(&header);
+            inner.send_syn(true);
+            conn.finish(inner);
+            return;
+        }
+    }
+    // no such connection
+    let (seq, ack, flags) = if header.flags & ACK != 0 {
+        (header.ack, 0, RST)
+    } else {
+        let len =
+            payload.len() + (header.flags & SYN != 0) as usize + (header.flags & FIN != 0) as usize;
+        (0, header.seq.wrapping_add(len as u32), RST | ACK)
+    };
+    send_segment(Segment {
+        dst: src,
+        header: TcpHeader {
+            src_port: header.dst_port,
+            dst_port: header.src_port,
+            seq,
+            ack,
+            flags,
+            window: 0,
+            mss: None,
+        },
+        data: Vec::new(),
+    });
+}
+
+/// A TCP socket: a connection, a listening socket, or neither yet
+pub struct TcpSocket {
+    /// the port bound by the socket, 0 if none, e.g. for an accepted connection
+    port: SpinNoIrqLock<u16>,
+    /// the connection or the listening socket, `None` until either
+    tcb: SpinNoIrqLock<Option<Arc<Tcb>>>,
+}
+
+impl TcpSocket {
+    /// A socket neither bound nor connected
+    pub fn new() -> Self {
+        Self {
+            port: SpinNoIrqLock::new(0),
+            tcb: SpinNoIrqLock::new(None),
+        }
+    }
+    /// The port bound, 0 if none
+    pub fn port(&self) -> u16 {
+        *self.port.lock()
+    }
+    /// Bind the socket to `port`, to a free ephemeral port if 0. Return false
+    /// if the socket is bound already or the port is taken.
+    pub fn bind(&self, port: u16) -> bool {
+        let mut bound = self.port.lock();
+        let mut ports = PORTS.lock();
+        if *bound != 0 {
+            return false;
+        }
+        let port = match port {
+            0 => match (EPHEMERAL_PORT_START..=u16::MAX).find(|port| !ports.contains(port)) {
+                Some(port) => port,
+                None => return false,
+            },
+            port if ports.contains(&port) => return false,
+            port => port,
+        };
+        ports.insert(port);
+        *bound = port;
+        true
+    }
+    /// The connection of the socket, `None` if not connected
+    fn connection(&self) -> Option<Arc<Tcb>> {
+        self.tcb
+            .lock()
+            .as_ref()
+            .filter(|tcb| tcb.inner.lock().state != State::Listen)
+            .cloned()
+    }
+    /// Connect to port `dst_port` of `dst`, binding to an ephemeral port
+    /// first if not bound, and block until the connection is established
+    pub fn connect(&self, dst: Ipv4Addr, dst_port: u16) -> Result<(), TcpError> {
+        if self.tcb.lock().is_some() {
+            return Err(TcpError::IsConnected);
+        }
+        // so that the SYN is not lost
+        resolve_route(dst).map_err(TcpError::Send)?;
+        if self.port() == 0 && !self.bind(0) {
+            return Err(TcpError::AddrInUse);
+        }
+        let key = (source_ip(dst), self.port(), dst, dst_port);
+        let conn = Tcb::new(key, State::SynSent, new_iss());
+        {
+            let mut tcb = self.tcb.lock();
+            if tcb.is_some() {
+                return Err(TcpError::IsConnected);
+            }
+            let mut connections = CONNECTIONS.lock();
+            if connections.contains_key(&key) {
+                return Err(TcpError::AddrInUse);
+            }
+            connections.insert(key, Arc::clone(&conn));
+            *tcb = Some(Arc::clone(&conn));
+        }
+        let mut inner = conn.inner.lock();
+        inner.send_syn(false);
+        conn.finish(inner);
+        if !conn
+            .wait_queue
+            .wait_until(|| conn.inner.lock().state != State::SynSent)
+        {
+            return Err(TcpError::Interrupted);
+        }
+        match conn.inner.lock().error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+    /// Listen for connections, queueing up to `backlog` of them until accepted,
+    /// binding to an ephemeral port first if not bound. Listening again
+    /// changes the backlog.
+    pub fn listen(&self, backlog: usize) -> Result<(), TcpError> {
+        let backlog = backlog.clamp(1, MAX_BACKLOG);
+        let mut tcb = self.tcb.lock();
+        if let Some(tcb) = tcb.as_ref() {
+            let mut inner = tcb.inner.lock();
+            if inner.state != State::Listen {
+                return Err(TcpError::IsConnected);
+            }
+            inner.backlog = backlog;
+            return Ok(());
+        }
+        if self.port() == 0 && !self.bind(0) {
+            return Err(TcpError::AddrInUse);
+        }
+        let port = self.port();
+        let listener = Tcb::new(([0; 4], port, [0; 4], 0), State::Listen, 0);
+        listener.inner.lock().backlog = backlog;
+        LISTENERS.lock().insert(port, Arc::clone(&listener));
+        *tcb = Some(listener);
+        Ok(())
+    }
+    /// Block until a connection is established to the listening socket and
+    /// return a socket of it, with the address and port of the peer
+    pub fn accept(&self) -> Result<(TcpSocket, Ipv4Addr, u16), TcpError> {
+        let listener = match self.tcb.lock().as_ref() {
+            Some(tcb) if tcb.inner.lock().state == State::Listen => Arc::clone(tcb),
+            _ => return Err(TcpError::NotListening),
+        };
+        let mut conn = None;
+        if !listener.wait_queue.wait_until(|| {
+            conn = listener.inner.lock().accept_queue.pop_front();
+            conn.is_some()
+        }) {
+            return Err(TcpError::Interrupted);
+        }
+        let conn = conn.unwrap();
+        let (_, _, peer, peer_port) = conn.inner.lock().key;
+        let socket = TcpSocket {
+            port: SpinNoIrqLock::new(0),
+            tcb: SpinNoIrqLock::new(Some(conn)),
+        };
+        Ok((socket, peer, peer_port))
+    }
+}
+
+impl Drop for TcpSocket {
+    fn drop(&mut self) {
+        let tcb = self.tcb.lock().take();
+        if let Some(tcb) = tcb {
+            let mut inner = tcb.inner.lock();
+            if inner.state == State::Listen {
+                LISTENERS.lock().remove(&inner.key.1);
+                let queued = core::mem::take(&mut inner.accept_queue);
+                drop(inner);
+                tcb.close();
+                for conn in queued {
+                    conn.abort();
+                }
+            } else {
+                drop(inner);
+                tcb.close();
+            }
+        }
+        let port = *self.port.lock();
+        if port != 0 {
+            PORTS.lock().remove(&port);
+        }
+    }
+}
+
+impl File for TcpSocket {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    /// Block until data arrives and read it, return 0 at the end of the data,
+    /// if the connection failed or a signal arrives first
+    fn read(&self, buf: UserBuffer) -> usize {
+        let conn = match self.connection() {
+            Some(conn) => conn,
+            None => return 0,
+        };
+        if !conn.wait_queue.wait_until(|| conn.inner.lock().readable()) {
+            return 0;
+        }
+        let mut total = 0;
+        for slice in buf.buffers {
+            let len = conn.read(slice);
+            total += len;
+            if len < slice.len() {
+                break;
+            }
+        }
+        total
+    }
+    /// Block until all the data is taken to send, return less if the connection
+    /// fails, is closed by the peer, or a signal arrives first
+    fn write(&self, buf: UserBuffer) -> usize {
+        let conn = match self.connection() {
+            Some(conn) => conn,
+            None => return 0,
+        };
+        let mut total = 0;
+        for slice in buf.buffers {
+            let mut written = 0;
+            while written < slice.len() {
+                if !conn.wait_queue.wait_until(|| {
+                    let inner = conn.inner.lock();
+                    inner.writable()
+                        || inner.error.is_some()
+                        || !matches!(inner.state, State::Established | State::CloseWait)
+                }) {
+                    return total;
+                }
+                match conn.write(&slice[written..]) {
+                    Some(len) => {
+                        written += len;
+                        total += len;
+                    }
+                    None => return total,
+                }
+            }
+        }
+        total
+    }
+    fn as_tcp_socket(&self) -> Option<&TcpSocket> {
+        Some(self)
+    }
+}
@@ -0,0 +1,576 @@
+//! Transmission control blocks, the state of each TCP connection
+use super::{Segment, TcpError, TcpHeader, ACK, FIN, PSH, RST, SYN};
+use crate::config::CLOCK_FREQ;
+use crate::net::Ipv4Addr;
+use crate::sync::{SpinLockGuard, SpinNoIrqLock};
+use crate::task::WaitQueue;
+use crate::timer::{cancel_timer, get_time, start_timer, TimerId};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+/// Bytes written and not acknowledged yet kept per connection
+const SEND_BUF_SIZE: usize = 16384;
+/// Bytes received and not read yet kept per connection, the largest window
+const RECV_BUF_SIZE: usize = 16384;
+/// Largest segment sent when the peer does not tell
+const DEFAULT_MSS: usize = 536;
+/// Largest segment received, filling an Ethernet frame
+pub const LOCAL_MSS: u16 = 1460;
+/// First retransmission timeout
+const INITIAL_RTO_MS: usize = 1000;
+const MAX_RTO_MS: usize = 60000;
+/// Retransmissions of a segment before the connection times out
+const MAX_RETRIES: usize = 6;
+/// Time in `TimeWait` before the connection is forgotten, twice the maximum
+/// segment lifetime, short for the sake of tests
+const TIME_WAIT_MS: usize = 1000;
+
+/// States of a connection of RFC 793
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum State {
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+/// Local address and port, then those of the peer
+pub type ConnKey = (Ipv4Addr, u16, Ipv4Addr, u16);
+
+/// `a` is before `b` in sequence space
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    !seq_lt(b, a)
+}
+
+fn ms_to_ticks(ms: usize) -> usize {
+    ms * CLOCK_FREQ / 1000
+}
+
+/// State of a connection, or of a listening socket
+pub struct TcbInner {
+    pub state: State,
+    pub key: ConnKey,
+    /// initial send sequence number
+    iss: u32,
+    /// oldest byte not acknowledged
+    snd_una: u32,
+    /// next byte to send
+    snd_nxt: u32,
+    /// window advertised by the peer
+    snd_wnd: usize,
+    /// largest segment the peer receives
+    mss: usize,
+    /// next byte expected from the peer
+    rcv_nxt: u32,
+    /// window advertised last
+    rcv_wnd: usize,
+    /// bytes from `snd_una` on, sent or not
+    send_buf: VecDeque<u8>,
+    /// bytes received in order and not read yet
+    recv_buf: VecDeque<u8>,
+    /// whether the socket is closed, sending a FIN after the data
+    close_requested: bool,
+    /// whether the FIN is sent, counted in `snd_nxt`
+    fin_sent: bool,
+    /// whether the FIN of the peer is received: no more bytes to read
+    pub fin_received: bool,
+    /// why the connection ended, if it failed
+    pub error: Option<TcpError>,
+    /// an acknowledgement is due
+    ack_pending: bool,
+    /// retransmission or `TimeWait` timer
+    timer: Option<TimerId>,
+    /// retransmission timeout in ticks
+    rto: usize,
+    retries: usize,
+    /// the listening socket of a connection not established yet, `None` once
+    /// queued to it or if connected by the guest
+    pub listener: Option<Weak<Tcb>>,
+    /// connections established and not accepted yet, of a listening socket
+    pub accept_queue: VecDeque<Arc<Tcb>>,
+    /// length limit of `accept_queue`
+    pub backlog: usize,
+    /// segments to send once the lock is released
+    out: Vec<Segment>,
+}
+
+/// A connection, kept in the table of connections until closed
+pub struct Tcb {
+    pub inner: SpinNoIrqLock<TcbInner>,
+    /// tasks waiting for data, room to write, the connection or a connection to accept
+    pub wait_queue: WaitQueue,
+    me: Weak<Tcb>,
+}
+
+impl Tcb {
+    /// A connection of `key` in `state`, whose first segment has sequence number `iss`
+    pub fn new(key: ConnKey, state: State, iss: u32) -> Arc<Self> {
+        Arc::new_cyclic(|me| Self {
+            inner: SpinNoIrqLock::new(TcbInner {
+                state,
+                key,
+                iss,
+                snd_una: iss,
+                snd_nxt: iss,
+                snd_wnd: 0,
+                mss: DEFAULT_MSS,
+                rcv_nxt: 0,
+                rcv_wnd: RECV_BUF_SIZE,
+                send_buf: VecDeque::new(),
+                recv_buf: VecDeque::new(),
+                close_requested: false,
+                fin_sent: false,
+                fin_received: false,
+                error: None,
+                ack_pending: false,
+                timer: None,
+                rto: ms_to_ticks(INITIAL_RTO_MS),
+                retries: 0,
+                listener: None,
+                accept_queue: VecDeque::new(),
+                backlog: 0,
+                out: Vec::new(),
+            }),
+            wait_queue: WaitQueue::new(),
+            me: me.clone(),
+        })
+    }
+    /// Arm the timer of the connection to call [`Tcb::on_timer`] in `ticks`
+    fn arm_timer(&self, inner: &mut TcbInner, ticks: usize) {
+        if let Some(id) = inner.timer.take() {
+            cancel_timer(id);
+        }
+        let me = self.me.clone();
+        inner.timer = Some(start_timer(
+            get_time() + ticks,
+            0,
+            Box::new(move || {
+                if let Some(tcb) = me.upgrade() {
+                    tcb.on_timer();
+                }
+            }),
+        ));
+    }
+    /// Retransmit what is not acknowledged, or forget a connection in `TimeWait`
+    fn on_timer(&self) {
+        let mut inner = self.inner.lock();
+        inner.timer = None;
+        match inner.state {
+            State::TimeWait => inner.state = State::Closed,
+            State::Closed | State::Listen => {}
+            _ if inner.snd_una == inner.snd_nxt && inner.send_buf.is_empty() => {}
+            _ => {
+                // no limit on probing a zero window
+                if inner.snd_wnd != 0 || matches!(inner.state, State::SynSent | State::SynReceived)
+                {
+                    inner.retries += 1;
+                }
+                if inner.retries > MAX_RETRIES {
+                    inner.send_rst();
+                    inner.fail(TcpError::TimedOut);
+                } else {
+                    inner.rto = (inner.rto * 2).min(ms_to_ticks(MAX_RTO_MS));
+                    // go back to the oldest byte not acknowledged
+                    match inner.state {
+                        State::SynSent => inner.send_syn(false),
+                        State::SynReceived => inner.send_syn(true),
+                        _ => {
+                            inner.snd_nxt = inner.snd_una;
+                            inner.fin_sent = false;
+                            inner.output(true);
+                        }
+                    }
+                }
+            }
+        }
+        self.finish(inner);
+    }
+    /// Rearm or cancel the timer after a change of `inner`, release it and
+    /// send its segments, waking up the waiting tasks
+    pub fn finish(&self, mut inner: SpinLockGuard<'_, TcbInner>) {
+        let unacked = inner.snd_una != inner.snd_nxt || !inner.send_buf.is_empty();
+        match inner.state {
+            State::TimeWait => {
+                if inner.timer.is_none() {
+                    self.arm_timer(&mut inner, ms_to_ticks(TIME_WAIT_MS));
+                }
+            }
+            State::Closed | State::Listen => {
+                if let Some(id) = inner.timer.take() {
+                    cancel_timer(id);
+                }
+            }
+            _ if unacked || matches!(inner.state, State::SynSent | State::SynReceived) => {
+                if inner.timer.is_none() {
+                    let rto = inner.rto;
+                    self.arm_timer(&mut inner, rto);
+                }
+            }
+            _ => {
+                if let Some(id) = inner.timer.take() {
+                    cancel_timer(id);
+                }
+            }
+        }
+        let out = core::mem::take(&mut inner.out);
+        let closed = inner.state == State::Closed;
+        let key = inner.key;
+        drop(inner);
+        for segment in out {
+            super::send_segment(segment);
+        }
+        if closed {
+            super::remove_connection(key, self);
+        }
+        self.wait_queue.wake_all();
+    }
+    /// Handle `segment` of the connection with `payload`
+    pub fn receive(&self, header: &TcpHeader, payload: &[u8]) {
+        let mut inner = self.inner.lock();
+        inner.receive(header, payload);
+        let listener = if matches!(inner.state, State::SynReceived | State::Closed) {
+            None
+        } else {
+            inner.listener.take()
+        };
+        self.finish(inner);
+        if let Some(listener) = listener {
+            // the handshake completed, to be accepted
+            match listener.upgrade() {
+                Some(listener) if listener.enqueue(self.me.upgrade().unwrap()) => {}
+                _ => self.abort(),
+            }
+        }
+    }
+    /// Queue `conn` to be accepted from this listening socket, false if it is closed
+    fn enqueue(&self, conn: Arc<Tcb>) -> bool {
+        let mut inner = self.inner.lock();
+        if inner.state != State::Listen {
+            return false;
+        }
+        inner.accept_queue.push_back(conn);
+        drop(inner);
+        self.wait_queue.wake_all();
+        true
+    }
+    /// Reset the connection, e.g. not accepted before its listening socket is closed
+    pub fn abort(&self) {
+        let mut inner = self.inner.lock();
+        if !matches!(inner.state, State::Closed | State::TimeWait | State::Listen) {
+            inner.send_rst();
+        }
+        inner.state = State::Closed;
+        self.finish(inner);
+    }
+    /// Close the socket of the connection: send a FIN once the data is sent
+    pub fn close(&self) {
+        let mut inner = self.inner.lock();
+        inner.close_requested = true;
+        match inner.state {
+            State::SynSent | State::Listen => inner.state = State::Closed,
+            State::SynReceived | State::Established | State::CloseWait => inner.output(false),
+            _ => {}
+        }
+        self.finish(inner);
+    }
+    /// Append up to the room in the send buffer of `data` and send what the
+    /// window allows. Return the length taken, `None` if the connection
+    /// cannot send any more.
+    pub fn write(&self, data: &[u8]) -> Option<usize> {
+        let mut inner = self.inner.lock();
+        if inner.error.is_some()
+            || inner.close_requested
+            || !matches!(inner.state, State::Established | State::CloseWait)
+        {
+            return None;
+        }
+        let len = data.len().min(SEND_BUF_SIZE - inner.send_buf.len());
+        inner.send_buf.extend(data[..len].iter());
+        inner.output(false);
+        self.finish(inner);
+        Some(len)
+    }
+    /// Move up to `buf.len()` bytes received to `buf`, opening the window
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        let mut inner = self.inner.lock();
+        let len = buf.len().min(inner.recv_buf.len());
+        for (dst, src) in buf.iter_mut().zip(inner.recv_buf.drain(..len)) {
+            *dst = src;
+        }
+        // tell the peer once the window opens by a segment, avoiding silly windows
+        let free = RECV_BUF_SIZE - inner.recv_buf.len();
+        if len > 0 && free.saturating_sub(inner.rcv_wnd) >= LOCAL_MSS as usize {
+            inner.ack_pending = true;
+            inner.output(false);
+        }
+        self.finish(inner);
+        len
+    }
+}
+
+impl TcbInner {
+    /// Whether a write would take some bytes
+    pub fn writable(&self) -> bool {
+        self.send_buf.len() < SEND_BUF_SIZE
+    }
+    /// Whether a read returns without blocking: data, the end of it or an error
+    pub fn readable(&self) -> bool {
+        !self.recv_buf.is_empty() || self.fin_received || self.error.is_some()
+    }
+    fn segment(&mut self, seq: u32, flags: u8, data: Vec<u8>) {
+        let free = RECV_BUF_SIZE - self.recv_buf.len();
+        self.rcv_wnd = free;
+        let (_, src_port, dst, dst_port) = self.key;
+        self.out.push(Segment {
+            dst,
+            header: TcpHeader {
+                src_port,
+                dst_port,
+                seq,
+                ack: if flags & ACK != 0 { self.rcv_nxt } else { 0 },
+                flags,
+                window: free.min(u16::MAX as usize) as u16,
+                mss: if flags & SYN != 0 {
+                    Some(LOCAL_MSS)
+                } else {
+                    None
+                },
+            },
+            data,
+        });
+        if flags & ACK != 0 {
+            self.ack_pending = false;
+        }
+    }
+    /// Send the SYN, acknowledging that of the peer in `SynReceived`
+    pub fn send_syn(&mut self, ack: bool) {
+        let flags = if ack { SYN | ACK } else { SYN };
+        self.segment(self.iss, flags, Vec::new());
+        self.snd_nxt = self.iss.wrapping_add(1);
+    }
+    fn send_rst(&mut self) {
+        self.segment(self.snd_nxt, RST | ACK, Vec::new());
+    }
+    /// Take the initial sequence number, window and options of the SYN of the peer
+    pub fn accept_syn(&mut self, header: &TcpHeader) {
+        self.rcv_nxt = header.seq.wrapping_add(1);
+        self.snd_wnd = header.window as usize;
+        if let Some(mss) = header.mss {
+            self.mss = (mss as usize).min(LOCAL_MSS as usize);
+        }
+    }
+    /// End the connection with `error`
+    fn fail(&mut self, error: TcpError) {
+        self.error = Some(error);
+        self.state = State::Closed;
+    }
+    /// Send the data the window allows, then the FIN once requested and all
+    /// data is sent, or an acknowledgement if due. `probe` sends a byte into
+    /// a zero window.
+    fn output(&mut self, probe: bool) {
+        if matches!(
+            self.state,
+            State::Established
+                | State::CloseWait
+                | State::FinWait1
+                | State::Closing
+                | State::LastAck
+        ) {
+            let window = if probe {
+                self.snd_wnd.max(1)
+            } else {
+                self.snd_wnd
+            };
+            loop {
+                let offset = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+                if offset >= self.send_buf.len() {
+                    break;
+                }
+                let len = (self.send_buf.len() - offset)
+                    .min(self.mss)
+                    .min(window.saturating_sub(offset));
+                if len == 0 {
+                    break;
+                }
+                let data = self.send_buf.range(offset..offset + len).copied().collect();
+                self.segment(self.snd_nxt, ACK | PSH, data);
+                self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+            }
+            let all_sent = self.snd_nxt.wrapping_sub(self.snd_una) as usize == self.send_buf.len();
+            if self.close_requested && !self.fin_sent && all_sent {
+                self.segment(self.snd_nxt, FIN | ACK, Vec::new());
+                self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                self.fin_sent = true;
+                self.state = match self.state {
+                    State::Established => State::FinWait1,
+                    State::CloseWait => State::LastAck,
+                    state => state,
+                };
+            }
+        }
+        if self.ack_pending {
+            self.segment(self.snd_nxt, ACK, Vec::new());
+        }
+    }
+    fn receive(&mut self, header: &TcpHeader, payload: &[u8]) {
+        if self.state == State::SynSent {
+            self.receive_syn_sent(header);
+            return;
+        }
+        if matches!(self.state, State::Closed | State::Listen) {
+            return;
+        }
+        let syn = header.flags & SYN != 0;
+        if syn && self.state == State::SynReceived && header.seq.wrapping_add(1) == self.rcv_nxt {
+            // the SYN again, our SYN-ACK was lost
+            self.send_syn(true);
+            return;
+        }
+        // only segments starting at or before the next byte expected are
+        // taken, trimmed of what was received already
+        let mut seq = header.seq;
+        let mut payload = payload;
+        let mut fin = header.flags & FIN != 0;
+        if seq_lt(seq, self.rcv_nxt) {
+            let old = self.rcv_nxt.wrapping_sub(seq) as usize;
+            if old > payload.len() {
+                // the FIN, if any, is received already
+                fin = false;
+                payload = &[];
+            } else {
+                payload = &payload[old..];
+            }
+            seq = self.rcv_nxt;
+            // a retransmission: our acknowledgement was lost
+            self.ack_pending = true;
+        }
+        if seq != self.rcv_nxt {
+            if header.flags & RST == 0 {
+                // out of order: dropped, acknowledging what was received
+                self.ack_pending = true;
+                self.output(false);
+            }
+            return;
+        }
+        if header.flags & RST != 0 {
+            if self.state == State::SynReceived {
+                self.state = State::Closed;
+            } else {
+                self.fail(TcpError::Reset);
+            }
+            return;
+        }
+        if syn {
+            self.ack_pending = true;
+            self.output(false);
+            return;
+        }
+        if header.flags & ACK == 0 {
+            return;
+        }
+        self.receive_ack(header);
+        if self.state == State::Closed {
+            return;
+        }
+        if !payload.is_empty() {
+            if matches!(
+                self.state,
+                State::Established | State::FinWait1 | State::FinWait2
+            ) {
+                let room = RECV_BUF_SIZE - self.recv_buf.len();
+                let len = payload.len().min(room);
+                self.recv_buf.extend(payload[..len].iter());
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(len as u32);
+                // the FIN follows the bytes not taken, retransmitted later
+                fin = fin && len == payload.len();
+            }
+            self.ack_pending = true;
+        }
+        if fin && !self.fin_received {
+            self.fin_received = true;
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.ack_pending = true;
+            self.state = match self.state {
+                State::SynReceived | State::Established => State::CloseWait,
+                State::FinWait1 => State::Closing,
+                State::FinWait2 => State::TimeWait,
+                state => state,
+            };
+        }
+        self.output(false);
+    }
+    fn receive_syn_sent(&mut self, header: &TcpHeader) {
+        let ack_ok = header.flags & ACK != 0 && header.ack == self.iss.wrapping_add(1);
+        if header.flags & ACK != 0 && !ack_ok {
+            if header.flags & RST == 0 {
+                self.segment(header.ack, RST, Vec::new());
+            }
+            return;
+        }
+        if header.flags & RST != 0 {
+            if ack_ok {
+                self.fail(TcpError::Refused);
+            }
+            return;
+        }
+        if header.flags & SYN != 0 && ack_ok {
+            self.accept_syn(header);
+            self.snd_una = header.ack;
+            self.state = State::Established;
+            self.retries = 0;
+            self.rto = ms_to_ticks(INITIAL_RTO_MS);
+            self.ack_pending = true;
+            self.output(false);
+        }
+    }
+    fn receive_ack(&mut self, header: &TcpHeader) {
+        let ack = header.ack;
+        if self.state == State::SynReceived {
+            if ack != self.iss.wrapping_add(1) {
+                self.segment(ack, RST, Vec::new());
+                return;
+            }
+            self.snd_una = ack;
+            self.state = State::Established;
+        }
+        if seq_lt(self.snd_nxt, ack) {
+            // acknowledging what was not sent
+            self.ack_pending = true;
+            return;
+        }
+        if seq_lt(self.snd_una, ack) {
+            let acked = ack.wrapping_sub(self.snd_una) as usize;
+            let data = acked.min(self.send_buf.len());
+            self.send_buf.drain(..data);
+            self.snd_una = ack;
+            self.retries = 0;
+            self.rto = ms_to_ticks(INITIAL_RTO_MS);
+            if let Some(id) = self.timer.take() {
+                cancel_timer(id);
+            }
+        }
+        if seq_le(self.snd_una, ack) {
+            self.snd_wnd = header.window as usize;
+        }
+        let fin_acked = self.fin_sent && self.snd_una == self.snd_nxt;
+        match self.state {
+            State::FinWait1 if fin_acked => self.state = State::FinWait2,
+            State::Closing if fin_acked => self.state = State::TimeWait,
+            State::LastAck if fin_acked => self.state = State::Closed,
+            _ => {}
+        }
+    }
+}
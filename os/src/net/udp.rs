@@ -0,0 +1,191 @@
+//! UDP sockets, the datagrams received for each bound port queued until read
+use super::{
+    checksum, pseudo_header_sum, send_ipv4, source_ip, Ipv4Addr, SendError, IPPROTO_UDP,
+    IPV4_HEADER_LEN, MTU,
+};
+use crate::fs::File;
+use crate::mm::UserBuffer;
+use crate::sync::{Lazy, SpinNoIrqLock};
+use crate::task::WaitQueue;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+const UDP_HEADER_LEN: usize = 8;
+/// Largest payload of a datagram, sent in a single IPv4 packet
+pub const MAX_UDP_PAYLOAD: usize = MTU - IPV4_HEADER_LEN - UDP_HEADER_LEN;
+/// First of the ports bound when sending on a socket not bound yet
+const EPHEMERAL_PORT_START: u16 = 49152;
+/// Datagrams queued on a socket until read, further ones are dropped
+const RX_QUEUE_LEN: usize = 64;
+
+/// A datagram received
+struct Datagram {
+    src: Ipv4Addr,
+    src_port: u16,
+    data: Vec<u8>,
+}
+
+/// Datagrams received for a port
+struct Receiver {
+    datagrams: SpinNoIrqLock<VecDeque<Datagram>>,
+    /// tasks waiting for a datagram
+    wait_queue: WaitQueue,
+}
+
+/// Receiver of each bound port
+static PORTS: Lazy<SpinNoIrqLock<BTreeMap<u16, Arc<Receiver>>>> =
+    Lazy::new(|| SpinNoIrqLock::new(BTreeMap::new()));
+
+/// A UDP socket, bound to a local port on every address of the guest
+pub struct UdpSocket {
+    /// the bound port, 0 until bound
+    port: SpinNoIrqLock<u16>,
+    receiver: Arc<Receiver>,
+}
+
+/// Queue the datagram in `segment` from `src` to the socket bound to its port
+pub fn handle_udp(src: Ipv4Addr, dst: Ipv4Addr, segment: &[u8]) {
+    if segment.len() < UDP_HEADER_LEN {
+        return;
+    }
+    let src_port = u16::from_be_bytes([segment[0], segment[1]]);
+    let dst_port = u16::from_be_bytes([segment[2], segment[3]]);
+    let len = u16::from_be_bytes([segment[4], segment[5]]) as usize;
+    if len < UDP_HEADER_LEN || len > segment.len() {
+        return;
+    }
+    let segment = &segment[..len];
+    // a checksum of 0 is not computed
+    if segment[6..8] != [0, 0]
+        && checksum(segment, pseudo_header_sum(src, dst, IPPROTO_UDP, len)) != 0
+    {
+        return;
+    }
+    let receiver = match PORTS.lock().get(&dst_port) {
+        Some(receiver) => Arc::clone(receiver),
+        None => return,
+    };
+    let mut datagrams = receiver.datagrams.lock();
+    if datagrams.len() < RX_QUEUE_LEN {
+        datagrams.push_back(Datagram {
+            src,
+            src_port,
+            data: segment[UDP_HEADER_LEN..].to_vec(),
+        });
+    }
+    drop(datagrams);
+    receiver.wait_queue.wake_all();
+}
+
+impl UdpSocket {
+    /// An unbound socket
+    pub fn new() -> Self {
+        Self {
+            port: SpinNoIrqLock::new(0),
+            receiver: Arc::new(Receiver {
+                datagrams: SpinNoIrqLock::new(VecDeque::new()),
+                wait_queue: WaitQueue::new(),
+            }),
+        }
+    }
+    /// The bound port, 0 if not bound
+    pub fn port(&self) -> u16 {
+        *self.port.lock()
+    }
+    /// Bind the socket to `port`, to a free ephemeral port if 0. Return false
+    /// if the socket is bound already or the port is taken.
+    pub fn bind(&self, port: u16) -> bool {
+        let mut bound = self.port.lock();
+        let mut ports = PORTS.lock();
+        if *bound != 0 {
+            return false;
+        }
+        let port = match port {
+            0 => match (EPHEMERAL_PORT_START..=u16::MAX).find(|port| !ports.contains_key(port)) {
+                Some(port) => port,
+                None => return false,
+            },
+            port if ports.contains_key(&port) => return false,
+            port => port,
+        };
+        ports.insert(port, Arc::clone(&self.receiver));
+        *bound = port;
+        true
+    }
+    /// Send `data` of at most [`MAX_UDP_PAYLOAD`] bytes to port `dst_port` of
+    /// `dst`, binding the socket to an ephemeral port first if not bound
+    pub fn send_to(&self, dst: Ipv4Addr, dst_port: u16, data: &[u8]) -> Result<(), SendError> {
+        assert!(data.len() <= MAX_UDP_PAYLOAD);
+        if self.port() == 0 {
+            // another thread may have bound it meanwhile
+            self.bind(0);
+        }
+        let len = UDP_HEADER_LEN + data.len();
+        let mut segment = Vec::with_capacity(len);
+        segment.extend_from_slice(&self.port().to_be_bytes());
+        segment.extend_from_slice(&dst_port.to_be_bytes());
+        segment.extend_from_slice(&(len as u16).to_be_bytes());
+        segment.extend_from_slice(&[0, 0]);
+        segment.extend_from_slice(data);
+        let sum = match checksum(
+            &segment,
+            pseudo_header_sum(source_ip(dst), dst, IPPROTO_UDP, len),
+        ) {
+            // 0 means no checksum
+            0 => 0xffff,
+            sum => sum,
+        };
+        segment[6..8].copy_from_slice(&sum.to_be_bytes());
+        send_ipv4(dst, IPPROTO_UDP, &segment)
+    }
+    /// Block until a datagram arrives and copy it to `buf`, truncated to its
+    /// length. Return the length copied and the address and port of the
+    /// sender, `None` if a signal arrives first.
+    pub fn recv_from(&self, buf: UserBuffer) -> Option<(usize, Ipv4Addr, u16)> {
+        let mut datagram = None;
+        if !self.receiver.wait_queue.wait_until(|| {
+            datagram = self.receiver.datagrams.lock().pop_front();
+            datagram.is_some()
+        }) {
+            return None;
+        }
+        let datagram = datagram.unwrap();
+        let mut copied = 0;
+        for (dst, src) in buf.into_iter().zip(datagram.data.iter()) {
+            unsafe {
+                *dst = *src;
+            }
+            copied += 1;
+        }
+        Some((copied, datagram.src, datagram.src_port))
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        let port = *self.port.lock();
+        if port != 0 {
+            PORTS.lock().remove(&port);
+        }
+    }
+}
+
+impl File for UdpSocket {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, buf: UserBuffer) -> usize {
+        self.recv_from(buf).map_or(0, |(len, _, _)| len)
+    }
+    fn write(&self, _buf: UserBuffer) -> usize {
+        // no peer to send to without `sys_sendto`
+        0
+    }
+    fn as_udp_socket(&self) -> Option<&UdpSocket> {
+        Some(self)
+    }
+}
@@ -0,0 +1,284 @@
+//! The entropy pool of the kernel, behind `sys_getrandom`, `/dev/urandom`,
+//! the random placement of user heaps and the initial sequence numbers of TCP
+//!
+//! Entropy is collected in an input pool, a sponge over the ChaCha
+//! permutation, from
+//! - the jitter of the timer around memory accesses, sampled at boot and
+//!   whenever a caller waits for the pool to be seeded
+//! - the times of device interrupts
+//! - the virtio entropy device, if the machine has one
+//! - bytes written to `/dev/urandom`, credited with no entropy
+//!
+//! Random bytes come from a ChaCha20 generator, keyed from the input pool
+//! once it holds [`SEED_BITS`] bits of entropy, and rekeyed every
+//! [`RESEED_INTERVAL_MS`] once it holds as many again. After each request, the
+//! key is replaced by output of the generator, so that the bytes given out
+//! cannot be found from a later state. Before the pool is first seeded, early
+//! at boot on machines without an entropy device, bytes are given out all the
+//! same, from the generator keyed with what was collected.
+use crate::config::CLOCK_FREQ;
+use crate::drivers::RNG_DEVICE;
+use crate::kernel_test;
+use crate::sync::SpinNoIrqLock;
+use crate::timer::get_time;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Bits of entropy collected to key the generator
+const SEED_BITS: usize = 256;
+/// Time between two rekeys of the generator
+const RESEED_INTERVAL_MS: usize = 60_000;
+/// Bytes taken from the entropy device by a rekey
+const DEVICE_SEED_BYTES: usize = 32;
+/// Entropy is credited in eighths of a bit
+const CREDIT_SHIFT: usize = 3;
+/// Words of the input pool the inputs are added to between two permutations,
+/// the others keeping what was collected out of reach
+const RATE_WORDS: usize = 8;
+/// Jitter samples taken at boot, and at once while waiting for the seed
+const JITTER_SAMPLES: usize = 1024;
+/// Bytes touched by a jitter sample
+const JITTER_BUFFER: usize = 1024;
+/// "expand 32-byte k", the first words of a ChaCha block
+const CHACHA_CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+/// The ChaCha quarter round on words `a`, `b`, `c` and `d` of `x`
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(16);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(12);
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(8);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(7);
+}
+
+/// The 20 rounds of ChaCha, a permutation of the 16 words
+fn permute(x: &mut [u32; 16]) {
+    for _ in 0..10 {
+        quarter_round(x, 0, 4, 8, 12);
+        quarter_round(x, 1, 5, 9, 13);
+        quarter_round(x, 2, 6, 10, 14);
+        quarter_round(x, 3, 7, 11, 15);
+        quarter_round(x, 0, 5, 10, 15);
+        quarter_round(x, 1, 6, 11, 12);
+        quarter_round(x, 2, 7, 8, 13);
+        quarter_round(x, 3, 4, 9, 14);
+    }
+}
+
+/// Block `counter` of the ChaCha20 stream of `key`, with a zero nonce
+fn chacha20_block(key: &[u32; 8], counter: u64) -> [u32; 16] {
+    let mut input = [0u32; 16];
+    input[..4].copy_from_slice(&CHACHA_CONSTANTS);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter as u32;
+    input[13] = (counter >> 32) as u32;
+    let mut block = input;
+    permute(&mut block);
+    for (word, input) in block.iter_mut().zip(input.iter()) {
+        *word = word.wrapping_add(*input);
+    }
+    block
+}
+
+struct Pool {
+    /// state of the sponge collecting entropy
+    input: [u32; 16],
+    /// word of the rate the next input goes to
+    input_pos: usize,
+    /// entropy collected since the generator was last keyed, estimated in
+    /// eighths of a bit
+    credit: usize,
+    /// key of the generator
+    key: [u32; 8],
+    /// block of the generator given out next
+    counter: u64,
+    /// the generator was keyed with `SEED_BITS` bits of entropy
+    seeded: bool,
+    /// the generator was keyed, from whatever was collected
+    keyed: bool,
+    /// time in timer ticks of the last rekey
+    keyed_at: usize,
+}
+
+static POOL: SpinNoIrqLock<Pool> = SpinNoIrqLock::new(Pool {
+    input: [0; 16],
+    input_pos: 0,
+    credit: 0,
+    key: [0; 8],
+    counter: 0,
+    seeded: false,
+    keyed: false,
+    keyed_at: 0,
+});
+
+/// Whether bytes were given out before the pool was seeded, reported once
+static UNSEEDED_REPORTED: AtomicBool = AtomicBool::new(false);
+
+impl Pool {
+    /// Add `word` to the input pool, crediting `credit` eighths of a bit
+    fn absorb(&mut self, word: u32, credit: usize) {
+        self.input[self.input_pos] ^= word;
+        self.input_pos += 1;
+        if self.input_pos == RATE_WORDS {
+            permute(&mut self.input);
+            self.input_pos = 0;
+        }
+        self.credit += credit;
+    }
+    /// Add `bytes` to the input pool, crediting `credit` eighths of a bit
+    fn absorb_bytes(&mut self, bytes: &[u8], credit: usize) {
+        for chunk in bytes.chunks(4) {
+            let mut word = [0u8; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.absorb(u32::from_le_bytes(word), 0);
+        }
+        self.credit += credit;
+    }
+    /// Key the generator from the input pool and what was there before,
+    /// taking bytes of the entropy device first
+    fn rekey(&mut self) {
+        let mut seed = [0u8; DEVICE_SEED_BYTES];
+        let filled = RNG_DEVICE.as_ref().map_or(0, |rng| rng.read(&mut seed));
+        self.absorb_bytes(&seed[..filled], (filled * 8) << CREDIT_SHIFT);
+        self.absorb(get_time() as u32, 0);
+        permute(&mut self.input);
+        self.input_pos = 0;
+        for (key, input) in self.key.iter_mut().zip(self.input.iter()) {
+            *key ^= *input;
+        }
+        // the next inputs are not added to the words taken into the key
+        permute(&mut self.input);
+        if self.credit >= SEED_BITS << CREDIT_SHIFT && !self.seeded {
+            self.seeded = true;
+            info!("entropy pool seeded");
+        }
+        self.credit = 0;
+        self.keyed = true;
+        self.keyed_at = get_time();
+        self.erase_key();
+    }
+    /// Replace the key by output of the generator
+    fn erase_key(&mut self) {
+        let block = chacha20_block(&self.key, self.counter);
+        self.key.copy_from_slice(&block[..8]);
+        self.counter = 0;
+    }
+    /// Whether the generator is to be keyed before giving out bytes
+    fn needs_rekey(&self) -> bool {
+        let enough = self.credit >= SEED_BITS << CREDIT_SHIFT;
+        let due = get_time() >= self.keyed_at + CLOCK_FREQ / 1000 * RESEED_INTERVAL_MS;
+        // the entropy device gives enough whenever asked
+        !self.keyed || (enough && !self.seeded) || (due && (enough || RNG_DEVICE.is_some()))
+    }
+    fn fill(&mut self, buf: &mut [u8]) {
+        if self.needs_rekey() {
+            self.rekey();
+        }
+        if !self.seeded && !UNSEEDED_REPORTED.swap(true, Ordering::Relaxed) {
+            warn!("random bytes given out before the entropy pool is seeded");
+        }
+        for chunk in buf.chunks_mut(64) {
+            let block = chacha20_block(&self.key, self.counter);
+            self.counter += 1;
+            for (bytes, word) in chunk.chunks_mut(4).zip(block.iter()) {
+                bytes.copy_from_slice(&word.to_le_bytes()[..bytes.len()]);
+            }
+        }
+        self.erase_key();
+    }
+}
+
+/// Sample the jitter of the timer `samples` times into the pool. Each sample
+/// times touching memory, which takes longer or shorter with the state of
+/// the caches and of the host, and is credited with an eighth of a bit if
+/// it differs from the previous one.
+fn add_jitter(samples: usize) {
+    let mut buffer = [0u8; JITTER_BUFFER];
+    let mut last_delta = 0;
+    for _ in 0..samples {
+        let start = get_time();
+        for i in (0..JITTER_BUFFER).step_by(64) {
+            let byte = unsafe { read_volatile(&buffer[i]) };
+            unsafe { write_volatile(&mut buffer[i], byte.wrapping_add(start as u8)) };
+        }
+        let end = get_time();
+        let delta = end - start;
+        let credit = (delta != last_delta) as usize;
+        last_delta = delta;
+        POOL.lock().absorb(end as u32, credit);
+    }
+}
+
+/// Collect entropy from the timer jitter and the entropy device, and key the
+/// generator, once the timer and the devices are set up at boot
+pub fn init() {
+    add_jitter(JITTER_SAMPLES);
+    let mut pool = POOL.lock();
+    pool.rekey();
+    if !pool.seeded {
+        info!("entropy pool not seeded yet");
+    }
+}
+
+/// Whether the generator was keyed with enough entropy
+pub fn is_seeded() -> bool {
+    POOL.lock().seeded
+}
+
+/// Collect the jitter of the timer until the generator is keyed with enough
+/// entropy, e.g. for `getrandom` to return only then
+pub fn wait_seeded() {
+    loop {
+        let mut pool = POOL.lock();
+        if pool.seeded {
+            return;
+        }
+        if pool.credit >= SEED_BITS << CREDIT_SHIFT {
+            pool.rekey();
+            continue;
+        }
+        drop(pool);
+        add_jitter(JITTER_SAMPLES);
+    }
+}
+
+/// Fill `buf` with random bytes, best effort before the pool is seeded
+pub fn fill(buf: &mut [u8]) {
+    POOL.lock().fill(buf);
+}
+
+/// A random word
+pub fn random_u32() -> u32 {
+    let mut bytes = [0u8; 4];
+    fill(&mut bytes);
+    u32::from_le_bytes(bytes)
+}
+
+/// Mix `bytes` into the pool without crediting them, e.g. written to
+/// `/dev/urandom`
+pub fn add_entropy(bytes: &[u8]) {
+    POOL.lock().absorb_bytes(bytes, 0);
+}
+
+/// Mix the time of interrupt `irq` into the pool, credited with an eighth of
+/// a bit
+pub fn add_interrupt_randomness(irq: usize) {
+    POOL.lock()
+        .absorb(get_time() as u32 ^ (irq as u32).rotate_left(24), 1);
+}
+
+/// ChaCha20 against the key stream of test vector 1 of RFC 7539, appendix A.1
+pub fn chacha20_test() {
+    let block = chacha20_block(&[0; 8], 0);
+    assert_eq!(
+        block[..4],
+        [0xade0_b876, 0x903d_f1a0, 0xe56a_5d40, 0x28bd_8653]
+    );
+    let block = chacha20_block(&[0; 8], 1);
+    assert_eq!(block[0].to_le_bytes(), [0x9f, 0x07, 0xe7, 0xbe]);
+    info!("chacha20_test passed!");
+}
+kernel_test!(chacha20_test);
@@ -13,6 +13,30 @@ const SBI_REMOTE_SFENCE_VMA: usize = 6;
 const SBI_REMOTE_SFENCE_VMA_ASID: usize = 7;
 const SBI_SHUTDOWN: usize = 8;
 
+/// Hart State Management extension
+const SBI_EXT_HSM: usize = 0x48534D;
+const SBI_HSM_HART_START: usize = 0;
+const SBI_HSM_HART_STOP: usize = 1;
+const SBI_HSM_HART_GET_STATUS: usize = 2;
+/// status of `SBI_HSM_HART_GET_STATUS`: the hart is stopped
+pub const SBI_HSM_STOPPED: usize = 1;
+/// IPI extension
+const SBI_EXT_IPI: usize = 0x735049;
+const SBI_IPI_SEND_IPI: usize = 0;
+/// Remote fence extension
+const SBI_EXT_RFENCE: usize = 0x52464E43;
+const SBI_RFENCE_SFENCE_VMA: usize = 1;
+const SBI_RFENCE_SFENCE_VMA_ASID: usize = 2;
+/// System reset extension
+const SBI_EXT_SRST: usize = 0x53525354;
+const SBI_SRST_SYSTEM_RESET: usize = 0;
+/// reset type of `SBI_SRST_SYSTEM_RESET`: shutdown
+const SBI_SRST_SHUTDOWN: usize = 0;
+/// reset type of `SBI_SRST_SYSTEM_RESET`: cold reboot
+const SBI_SRST_COLD_REBOOT: usize = 1;
+/// reset reason of `SBI_SRST_SYSTEM_RESET`: system failure
+const SBI_SRST_REASON_FAILURE: usize = 1;
+
 /// general sbi call
 #[inline(always)]
 fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
@@ -29,6 +53,68 @@ fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
     }
     ret
 }
+/// sbi call of an extension of SBI v0.2 and later, returns (error, value)
+#[inline(always)]
+fn sbi_call_ext(eid: usize, fid: usize, args: [usize; 5]) -> (isize, usize) {
+    let (error, value);
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("x10") args[0] => error,
+            inlateout("x11") args[1] => value,
+            in("x12") args[2],
+            in("x13") args[3],
+            in("x14") args[4],
+            in("x16") fid,
+            in("x17") eid,
+        );
+    }
+    (error, value)
+}
+/// use sbi call to start hart `hartid` at `start_addr` with `opaque` in a1, false if failed
+pub fn hart_start(hartid: usize, start_addr: usize, opaque: usize) -> bool {
+    sbi_call_ext(
+        SBI_EXT_HSM,
+        SBI_HSM_HART_START,
+        [hartid, start_addr, opaque, 0, 0],
+    )
+    .0 == 0
+}
+/// use sbi call to stop the current hart, returning only if it failed
+pub fn hart_stop() {
+    sbi_call_ext(SBI_EXT_HSM, SBI_HSM_HART_STOP, [0; 5]);
+}
+/// use sbi call to get the HSM status of hart `hartid`, e.g. `SBI_HSM_STOPPED`,
+/// `None` if there is no such hart
+pub fn hart_status(hartid: usize) -> Option<usize> {
+    match sbi_call_ext(SBI_EXT_HSM, SBI_HSM_HART_GET_STATUS, [hartid, 0, 0, 0, 0]) {
+        (0, status) => Some(status),
+        _ => None,
+    }
+}
+/// use sbi call to send a supervisor software interrupt to the harts in `hart_mask`
+pub fn send_ipi(hart_mask: usize) {
+    sbi_call_ext(SBI_EXT_IPI, SBI_IPI_SEND_IPI, [hart_mask, 0, 0, 0, 0]);
+}
+/// use sbi call to flush the TLB entries of `[start, start + size)` on all harts,
+/// the whole TLB if `size` is `usize::MAX`
+pub fn remote_sfence_vma(start: usize, size: usize) {
+    // a hart mask base of -1 selects all harts
+    sbi_call_ext(
+        SBI_EXT_RFENCE,
+        SBI_RFENCE_SFENCE_VMA,
+        [0, usize::MAX, start, size, 0],
+    );
+}
+/// use sbi call to flush the TLB entries of `[start, start + size)` tagged with `asid`
+/// on all harts, all of them if `size` is `usize::MAX`
+pub fn remote_sfence_vma_asid(start: usize, size: usize, asid: usize) {
+    sbi_call_ext(
+        SBI_EXT_RFENCE,
+        SBI_RFENCE_SFENCE_VMA_ASID,
+        [0, usize::MAX, start, size, asid],
+    );
+}
 /// use sbi call to set timer
 pub fn set_timer(timer: usize) {
     sbi_call(SBI_SET_TIMER, timer, 0, 0);
@@ -38,9 +124,28 @@ pub fn console_putchar(c: usize) {
     sbi_call(SBI_CONSOLE_PUTCHAR, c, 0, 0);
 }
 /// use sbi call to getchar from console (qemu uart handler)
+#[allow(unused)]
 pub fn console_getchar() -> usize {
     sbi_call(SBI_CONSOLE_GETCHAR, 0, 0, 0)
 }
+/// use sbi call to reboot the machine, returning if the firmware cannot
+pub fn reboot() {
+    sbi_call_ext(
+        SBI_EXT_SRST,
+        SBI_SRST_SYSTEM_RESET,
+        [SBI_SRST_COLD_REBOOT, 0, 0, 0, 0],
+    );
+}
+/// use sbi call to power off the machine, reporting a failure to the firmware if
+/// `failure`, returning if the firmware cannot
+pub fn poweroff(failure: bool) {
+    let reason = if failure { SBI_SRST_REASON_FAILURE } else { 0 };
+    sbi_call_ext(
+        SBI_EXT_SRST,
+        SBI_SRST_SYSTEM_RESET,
+        [SBI_SRST_SHUTDOWN, reason, 0, 0, 0],
+    );
+}
 /// use sbi call to shutdown the kernel
 pub fn shutdown() -> ! {
     sbi_call(SBI_SHUTDOWN, 0, 0, 0);
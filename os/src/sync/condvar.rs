@@ -0,0 +1,51 @@
+//! Condition variables, e.g. for the condition variables of user programs
+use super::{ExclusiveCell, Mutex};
+use crate::task::{suspend_current_and_run_next, WaitQueue};
+
+/// Tasks waiting, with a mutex released, until another task signals that what
+/// they wait for may have changed
+pub struct Condvar {
+    /// number of times the condition variable was signaled, a waiter returns
+    /// once it changes
+    seq: ExclusiveCell<usize>,
+    wait_queue: WaitQueue,
+}
+
+impl Condvar {
+    /// Create a condition variable without waiters
+    pub fn new() -> Self {
+        Self {
+            seq: ExclusiveCell::new(0),
+            wait_queue: WaitQueue::new(),
+        }
+    }
+    /// Wake up the task waiting the longest
+    pub fn signal(&self) {
+        *self.seq.exclusive_access() += 1;
+        self.wait_queue.wake_one();
+    }
+    /// Wake up all the waiting tasks
+    pub fn broadcast(&self) {
+        *self.seq.exclusive_access() += 1;
+        self.wait_queue.wake_all();
+    }
+    /// Release `mutex`, held by the current task, and wait until signaled, then
+    /// lock `mutex` again. Return `None` if the current task does not hold `mutex`
+    /// and `Some(false)` if a signal arrives before the condition variable is signaled.
+    pub fn wait(&self, mutex: &dyn Mutex) -> Option<bool> {
+        // taken before releasing the mutex, so a signal sent once it is released
+        // is not missed
+        let seq = *self.seq.exclusive_access();
+        if !mutex.unlock() {
+            return None;
+        }
+        let signaled = self
+            .wait_queue
+            .wait_until(|| *self.seq.exclusive_access() != seq);
+        // the mutex is held on return even if a signal arrived
+        while !mutex.lock() {
+            suspend_current_and_run_next();
+        }
+        Some(signaled)
+    }
+}
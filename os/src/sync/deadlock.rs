@@ -0,0 +1,108 @@
+//! Deadlock detection for the mutexes and semaphores of user programs, by the
+//! banker's algorithm
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// A mutex or semaphore of a process, by id
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Resource {
+    Mutex(usize),
+    Semaphore(usize),
+}
+
+/// Resources held and waited for by a thread
+#[derive(Default)]
+struct ThreadResources {
+    /// number of units of each resource held
+    allocation: BTreeMap<Resource, usize>,
+    /// number of units of each resource waited for
+    need: BTreeMap<Resource, usize>,
+}
+
+/// Who holds and who waits for the resources of a process. The units of each
+/// resource are counted even while detection is disabled, so that it can be
+/// enabled at any time.
+pub struct DeadlockDetector {
+    /// whether `request` refuses requests which may deadlock
+    pub enabled: bool,
+    /// number of units of each resource not held by any thread, negative for a
+    /// moment if a waiter takes a unit before its release is recorded
+    available: BTreeMap<Resource, isize>,
+    /// indexed by tid
+    threads: Vec<ThreadResources>,
+}
+
+impl DeadlockDetector {
+    /// Create a disabled detector without resources
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            available: BTreeMap::new(),
+            threads: Vec::new(),
+        }
+    }
+    /// Add resource `res` with `count` units, none of them held
+    pub fn add_resource(&mut self, res: Resource, count: usize) {
+        self.available.insert(res, count as isize);
+    }
+    fn thread(&mut self, tid: usize) -> &mut ThreadResources {
+        if self.threads.len() <= tid {
+            self.threads.resize_with(tid + 1, Default::default);
+        }
+        &mut self.threads[tid]
+    }
+    /// Record that thread `tid` waits for a unit of `res`. If detection is enabled
+    /// and the threads may then never all finish, undo it and return false.
+    pub fn request(&mut self, tid: usize, res: Resource) -> bool {
+        *self.thread(tid).need.entry(res).or_insert(0) += 1;
+        if self.enabled && !self.is_safe() {
+            self.cancel(tid, res);
+            return false;
+        }
+        true
+    }
+    /// Record that thread `tid` stopped waiting for a unit of `res` without taking it
+    pub fn cancel(&mut self, tid: usize, res: Resource) {
+        if let Some(need) = self.thread(tid).need.get_mut(&res) {
+            *need -= 1;
+        }
+    }
+    /// Record that thread `tid` took the unit of `res` it waited for
+    pub fn acquire(&mut self, tid: usize, res: Resource) {
+        self.cancel(tid, res);
+        *self.thread(tid).allocation.entry(res).or_insert(0) += 1;
+        *self.available.entry(res).or_insert(0) -= 1;
+    }
+    /// Record that thread `tid` released a unit of `res`, held by it or, e.g. for
+    /// semaphores signaling events, not
+    pub fn release(&mut self, tid: usize, res: Resource) {
+        if let Some(allocation) = self.thread(tid).allocation.get_mut(&res) {
+            *allocation = allocation.saturating_sub(1);
+        }
+        *self.available.entry(res).or_insert(0) += 1;
+    }
+    /// Whether there is an order in which every thread gets what it waits for,
+    /// each finishing and releasing what it holds before the next
+    fn is_safe(&self) -> bool {
+        let mut work = self.available.clone();
+        let mut finish = alloc::vec![false; self.threads.len()];
+        loop {
+            let next = self.threads.iter().enumerate().position(|(tid, thread)| {
+                !finish[tid]
+                    && thread
+                        .need
+                        .iter()
+                        .all(|(res, &need)| need as isize <= work.get(res).copied().unwrap_or(0))
+            });
+            match next {
+                Some(tid) => {
+                    finish[tid] = true;
+                    for (&res, &allocation) in self.threads[tid].allocation.iter() {
+                        *work.entry(res).or_insert(0) += allocation as isize;
+                    }
+                }
+                None => return finish.iter().all(|&finished| finished),
+            }
+        }
+    }
+}
@@ -0,0 +1,34 @@
+//! Interior mutability primitives shared by all harts
+use super::{SpinLockGuard, SpinNoIrqLock};
+
+/// Wrap a static data structure inside it so that we are
+/// able to access it without any `unsafe`.
+///
+/// It is a [`SpinNoIrqLock`]: accesses from different harts are serialized by
+/// spinning and accessing the data again on the hart already holding it
+/// panics. Interrupts stay disabled while it is held, so that an interrupt
+/// handler nesting in another one never finds it held on its own hart.
+///
+/// In order to get mutable reference of inner data, call
+/// `exclusive_access`.
+pub struct ExclusiveCell<T> {
+    /// inner data
+    inner: SpinNoIrqLock<T>,
+}
+
+/// Exclusive access to the data of an [`ExclusiveCell`], released on drop
+pub type ExclusiveCellGuard<'a, T> = SpinLockGuard<'a, T>;
+
+impl<T> ExclusiveCell<T> {
+    /// Wrap `value`, only accessed through `exclusive_access` from now on
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: SpinNoIrqLock::new(value),
+        }
+    }
+    /// Exclusive access inner data in ExclusiveCell, spinning while another hart holds it.
+    /// Panic if the data is already held by the current hart.
+    pub fn exclusive_access(&self) -> ExclusiveCellGuard<'_, T> {
+        self.inner.lock()
+    }
+}
@@ -0,0 +1,229 @@
+//! A lock dependency checker for debug builds, reporting the lock orders and
+//! interrupt states that may deadlock before they do.
+//!
+//! Locks are grouped into classes by the type of the data they protect. Each
+//! hart keeps the classes of the locks it holds, and taking a lock of class B
+//! while holding one of class A records that A goes before B. If B went before
+//! A already, directly or through other classes, two harts taking them in these
+//! orders may deadlock. A class taken by interrupt handlers and also with
+//! interrupts enabled may deadlock with itself on one hart. Each problem is
+//! reported once.
+use super::{RawSpinLock, TasLock};
+use crate::config::MAX_HARTS;
+use crate::hart::{hart_id, online_hart_mask};
+use core::cell::UnsafeCell;
+use riscv::register::sstatus;
+
+/// Number of lock classes tracked, one bit each in a `u64`
+const MAX_CLASSES: usize = 64;
+/// Number of locks a hart holds at most
+const MAX_HELD: usize = 16;
+
+struct LockdepState {
+    /// name of each class, the type of the data
+    classes: [&'static str; MAX_CLASSES],
+    nr_classes: usize,
+    /// bit `b` of `after[a]` is set once class `b` was taken while holding class `a`
+    after: [u64; MAX_CLASSES],
+    /// bit `b` of `reported[a]` is set once taking `b` while holding `a` was reported
+    reported: [u64; MAX_CLASSES],
+    /// classes taken by interrupt handlers
+    used_in_irq: u64,
+    /// classes taken with interrupts enabled
+    used_irq_on: u64,
+    irq_reported: u64,
+    /// classes of the locks held by each hart, in the order taken
+    held: [[usize; MAX_HELD]; MAX_HARTS],
+    nr_held: [usize; MAX_HARTS],
+    /// number of nested interrupt handlers running on each hart
+    irq_depth: [usize; MAX_HARTS],
+}
+
+impl LockdepState {
+    /// Id of class `name`, registered if new, `None` if there are too many
+    fn class_id(&mut self, name: &'static str) -> Option<usize> {
+        let known = self.classes[..self.nr_classes]
+            .iter()
+            .position(|&class| class == name);
+        if known.is_some() || self.nr_classes == MAX_CLASSES {
+            return known;
+        }
+        self.classes[self.nr_classes] = name;
+        self.nr_classes += 1;
+        Some(self.nr_classes - 1)
+    }
+    /// Whether class `to` was taken after class `from`, directly or through other classes
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        let mut visited = 0u64;
+        let mut frontier = 1u64 << from;
+        while frontier != 0 {
+            if frontier & (1 << to) != 0 {
+                return true;
+            }
+            visited |= frontier;
+            let next = (0..self.nr_classes)
+                .filter(|class| frontier & (1 << class) != 0)
+                .fold(0, |next, class| next | self.after[class]);
+            frontier = next & !visited;
+        }
+        false
+    }
+}
+
+/// A problem found when taking a lock, printed once the state is released
+enum Report {
+    /// class `.1` taken while holding `.0`, though taken before it elsewhere
+    Inversion(&'static str, &'static str),
+    /// class taken both by interrupt handlers and with interrupts enabled
+    IrqUnsafe(&'static str),
+}
+
+/// The checker state, guarded by a raw lock so that checking takes no checked lock
+struct Lockdep {
+    raw: TasLock,
+    state: UnsafeCell<LockdepState>,
+}
+
+unsafe impl Sync for Lockdep {}
+
+static LOCKDEP: Lockdep = Lockdep {
+    raw: TasLock::INIT,
+    state: UnsafeCell::new(LockdepState {
+        classes: [""; MAX_CLASSES],
+        nr_classes: 0,
+        after: [0; MAX_CLASSES],
+        reported: [0; MAX_CLASSES],
+        used_in_irq: 0,
+        used_irq_on: 0,
+        irq_reported: 0,
+        held: [[0; MAX_HELD]; MAX_HARTS],
+        nr_held: [0; MAX_HARTS],
+        irq_depth: [0; MAX_HARTS],
+    }),
+};
+
+/// Run `f` on the checker state, with interrupts disabled
+fn with_state<R>(f: impl FnOnce(&mut LockdepState, usize) -> R) -> R {
+    let irq_enabled = sstatus::read().sie();
+    unsafe {
+        sstatus::clear_sie();
+    }
+    let hart = hart_id();
+    LOCKDEP.raw.lock(hart);
+    let ret = f(unsafe { &mut *LOCKDEP.state.get() }, hart);
+    LOCKDEP.raw.unlock(hart);
+    if irq_enabled {
+        unsafe {
+            sstatus::set_sie();
+        }
+    }
+    ret
+}
+
+/// Check and record taking a lock of class `name` on the current hart, before
+/// spinning for it so that a deadlock is reported rather than hit
+pub fn lock_acquire(name: &'static str) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    let irq_on = sstatus::read().sie();
+    let report = with_state(|state, hart| {
+        let class = state.class_id(name)?;
+        let bit = 1u64 << class;
+        let mut report = None;
+        if state.irq_depth[hart] > 0 {
+            state.used_in_irq |= bit;
+        }
+        if irq_on {
+            state.used_irq_on |= bit;
+        }
+        if state.used_in_irq & state.used_irq_on & !state.irq_reported & bit != 0 {
+            state.irq_reported |= bit;
+            report = Some(Report::IrqUnsafe(name));
+        }
+        for i in 0..state.nr_held[hart] {
+            let held = state.held[hart][i];
+            // nesting locks of a class, e.g. of two tasks, is not checked
+            if held == class || state.after[held] & bit != 0 {
+                continue;
+            }
+            state.after[held] |= bit;
+            if state.reaches(class, held) && state.reported[held] & bit == 0 {
+                state.reported[held] |= bit;
+                report = Some(Report::Inversion(state.classes[held], name));
+            }
+        }
+        let nr_held = state.nr_held[hart];
+        if nr_held < MAX_HELD {
+            state.held[hart][nr_held] = class;
+            state.nr_held[hart] += 1;
+        }
+        report
+    });
+    match report {
+        Some(Report::Inversion(held, taken)) => warn!(
+            "possible deadlock: {} taken while holding {}, which is taken after it elsewhere",
+            taken, held
+        ),
+        Some(Report::IrqUnsafe(taken)) => warn!(
+            "possible deadlock: {} taken both by interrupt handlers and with interrupts enabled",
+            taken
+        ),
+        None => {}
+    }
+}
+
+/// Record releasing a lock of class `name`, held by the current hart
+pub fn lock_release(name: &'static str) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    with_state(|state, hart| {
+        let class = match state.classes[..state.nr_classes]
+            .iter()
+            .position(|&class| class == name)
+        {
+            Some(class) => class,
+            None => return,
+        };
+        // locks are not always released in the order taken
+        let nr_held = state.nr_held[hart];
+        let held = &mut state.held[hart][..nr_held];
+        if let Some(i) = held.iter().rposition(|&held| held == class) {
+            held.copy_within(i + 1.., i);
+            state.nr_held[hart] -= 1;
+        }
+    });
+}
+
+/// Print the classes of the locks each online hart holds, in the order taken
+pub fn print_held_locks() {
+    if !cfg!(debug_assertions) {
+        println!("held locks are tracked in debug builds only");
+        return;
+    }
+    // copied out, as printing takes checked locks
+    let (classes, held, nr_held) =
+        with_state(|state, _| (state.classes, state.held, state.nr_held));
+    let online = online_hart_mask();
+    for hart in (0..MAX_HARTS).filter(|hart| online & (1 << hart) != 0) {
+        println!("hart {}: {} locks held", hart, nr_held[hart]);
+        for &class in &held[hart][..nr_held[hart]] {
+            println!("  {}", classes[class]);
+        }
+    }
+}
+
+/// Mark the current hart as running an interrupt handler until `irq_exit`
+pub fn irq_enter() {
+    if cfg!(debug_assertions) {
+        with_state(|state, hart| state.irq_depth[hart] += 1);
+    }
+}
+
+/// Mark the end of the interrupt handler entered last by `irq_enter`
+pub fn irq_exit() {
+    if cfg!(debug_assertions) {
+        with_state(|state, hart| state.irq_depth[hart] -= 1);
+    }
+}
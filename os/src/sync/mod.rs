@@ -1,4 +1,25 @@
 //! Synchronization and interior mutability primitives
-mod up;
+mod condvar;
+mod deadlock;
+mod exclusive;
+pub mod lockdep;
+mod mutex;
+mod once;
+mod raw_lock;
+mod rcu;
+mod rwlock;
+mod semaphore;
+mod sleep_lock;
+mod spin;
 
-pub use up::UPSafeCell;
+pub use condvar::Condvar;
+pub use deadlock::{DeadlockDetector, Resource};
+pub use exclusive::{ExclusiveCell, ExclusiveCellGuard};
+pub use mutex::{Mutex, MutexBlocking, MutexSpin};
+pub use once::{Lazy, Once};
+pub use raw_lock::{McsLock, RawSpinLock, TasLock, TicketLock};
+pub use rcu::{rcu_quiescent, RcuCell, RcuReadGuard};
+pub use rwlock::RwLock;
+pub use semaphore::Semaphore;
+pub use sleep_lock::{SleepLock, SleepLockGuard};
+pub use spin::{pop_off, push_off, SpinLock, SpinLockGuard, SpinNoIrqLock};
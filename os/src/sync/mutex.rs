@@ -0,0 +1,174 @@
+//! Mutexes held by tasks, e.g. for the mutexes of user programs
+use super::ExclusiveCell;
+use crate::hart::hart_id;
+use crate::task::{
+    block_current_and_run_next, current_has_signal, current_task, suspend_current_and_run_next,
+    wakeup_task, TaskControlBlock,
+};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::hint::spin_loop;
+
+/// A lock held by one task at a time, released by the same task
+pub trait Mutex: Sync + Send {
+    /// Lock it for the current task, waiting while another task holds it.
+    /// Return false if a signal arrives first.
+    fn lock(&self) -> bool;
+    /// Release it, false if the current task does not hold it
+    fn unlock(&self) -> bool;
+}
+
+/// A mutex whose waiters yield the CPU until it is released
+pub struct MutexSpin {
+    owner: ExclusiveCell<Option<Arc<TaskControlBlock>>>,
+}
+
+impl MutexSpin {
+    /// Create a released mutex
+    pub fn new() -> Self {
+        Self {
+            owner: ExclusiveCell::new(None),
+        }
+    }
+}
+
+impl Mutex for MutexSpin {
+    fn lock(&self) -> bool {
+        let task = current_task().unwrap();
+        loop {
+            let mut owner = self.owner.exclusive_access();
+            if owner.is_none() {
+                *owner = Some(task);
+                return true;
+            }
+            drop(owner);
+            if current_has_signal() {
+                return false;
+            }
+            suspend_current_and_run_next();
+        }
+    }
+    fn unlock(&self) -> bool {
+        let task = current_task().unwrap();
+        let mut owner = self.owner.exclusive_access();
+        match owner.as_ref() {
+            Some(holder) if Arc::ptr_eq(holder, &task) => {
+                *owner = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A mutex whose waiters block until it is handed over to them, in FIFO order.
+///
+/// It implements priority inheritance: while a task waits, the owner runs with
+/// at least the priority of the waiter, so that a task of lower priority holding
+/// the mutex cannot be held up by tasks of medium priority and in turn hold up
+/// the waiter. The owner keeps the inherited priority until it releases the last
+/// mutex it holds.
+///
+/// An adaptive mutex spins rather than blocks while the owner runs on another
+/// hart, as it is likely to release the mutex before a switch to another task
+/// and back would be done.
+pub struct MutexBlocking {
+    adaptive: bool,
+    inner: ExclusiveCell<MutexBlockingInner>,
+}
+
+struct MutexBlockingInner {
+    owner: Option<Arc<TaskControlBlock>>,
+    wait_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl MutexBlocking {
+    /// Create a released mutex, spinning while the owner runs if `adaptive`
+    pub fn new(adaptive: bool) -> Self {
+        Self {
+            adaptive,
+            inner: ExclusiveCell::new(MutexBlockingInner {
+                owner: None,
+                wait_queue: VecDeque::new(),
+            }),
+        }
+    }
+}
+
+impl Mutex for MutexBlocking {
+    fn lock(&self) -> bool {
+        let task = current_task().unwrap();
+        loop {
+            let mut inner = self.inner.exclusive_access();
+            match inner.owner.as_ref() {
+                None => {
+                    inner.owner = Some(Arc::clone(&task));
+                    task.inner_exclusive_access().locks_held += 1;
+                    return true;
+                }
+                // handed over by `unlock`
+                Some(owner) if Arc::ptr_eq(owner, &task) => return true,
+                Some(owner) => {
+                    if current_has_signal() {
+                        return false;
+                    }
+                    if self.adaptive {
+                        let owner_inner = owner.inner_exclusive_access();
+                        let running = owner_inner.on_cpu && owner_inner.cpu != hart_id();
+                        drop(owner_inner);
+                        if running {
+                            drop(inner);
+                            spin_loop();
+                            continue;
+                        }
+                    }
+                    let priority = task.inner_exclusive_access().effective_priority();
+                    owner.inner_exclusive_access().inherit_priority(priority);
+                    block_current_and_run_next(|task| {
+                        inner.wait_queue.push_back(task);
+                        drop(inner);
+                    });
+                    // not handed over if woken up by a signal
+                    self.inner
+                        .exclusive_access()
+                        .wait_queue
+                        .retain(|waiter| !Arc::ptr_eq(waiter, &task));
+                }
+            }
+        }
+    }
+    fn unlock(&self) -> bool {
+        let task = current_task().unwrap();
+        let mut inner = self.inner.exclusive_access();
+        match inner.owner.as_ref() {
+            Some(owner) if Arc::ptr_eq(owner, &task) => {}
+            _ => return false,
+        }
+        let next = inner.wait_queue.pop_front();
+        inner.owner = next.clone();
+        if let Some(next) = next.as_ref() {
+            // the new owner inherits from the tasks still waiting
+            let priority = inner
+                .wait_queue
+                .iter()
+                .map(|waiter| waiter.inner_exclusive_access().effective_priority())
+                .max();
+            let mut next_inner = next.inner_exclusive_access();
+            next_inner.locks_held += 1;
+            if let Some(priority) = priority {
+                next_inner.inherit_priority(priority);
+            }
+        }
+        drop(inner);
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.locks_held -= 1;
+        if task_inner.locks_held == 0 {
+            task_inner.inherited_priority = 0;
+        }
+        drop(task_inner);
+        if let Some(next) = next {
+            wakeup_task(next);
+        }
+        true
+    }
+}
@@ -0,0 +1,111 @@
+//! Cells initialized once, for globals that cannot be built at compile time
+use crate::hart::hart_id;
+use core::any::type_name;
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+const UNINIT: u8 = 0;
+const RUNNING: u8 = 1;
+const READY: u8 = 2;
+
+/// A value set once, e.g. by the init function of its subsystem, so that the
+/// order in which globals are set up is explicit. Using it before it is set
+/// panics.
+pub struct Once<T> {
+    state: AtomicU8,
+    /// id of the hart running the initializer plus one
+    initializer: AtomicUsize,
+    data: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+
+impl<T> Once<T> {
+    /// Create an unset cell
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(UNINIT),
+            initializer: AtomicUsize::new(0),
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+    /// Set the value to the result of `f` unless it is set already, waiting while
+    /// another hart sets it, and return it. Panic if `f` uses the cell itself,
+    /// which would wait for itself forever.
+    pub fn call_once(&self, f: impl FnOnce() -> T) -> &T {
+        let me = hart_id() + 1;
+        if self
+            .state
+            .compare_exchange(UNINIT, RUNNING, Ordering::Acquire, Ordering::Acquire)
+            .is_ok()
+        {
+            self.initializer.store(me, Ordering::Relaxed);
+            unsafe {
+                (*self.data.get()).write(f());
+            }
+            self.state.store(READY, Ordering::Release);
+        }
+        while self.state.load(Ordering::Acquire) != READY {
+            if self.initializer.load(Ordering::Relaxed) == me {
+                panic!("{} used while being initialized", type_name::<T>());
+            }
+            spin_loop();
+        }
+        unsafe { (*self.data.get()).assume_init_ref() }
+    }
+    /// The value, `None` if not set yet
+    pub fn get(&self) -> Option<&T> {
+        match self.state.load(Ordering::Acquire) {
+            READY => Some(unsafe { (*self.data.get()).assume_init_ref() }),
+            _ => None,
+        }
+    }
+}
+
+impl<T> Deref for Once<T> {
+    type Target = T;
+    /// The value, panic if not set yet
+    fn deref(&self) -> &T {
+        match self.get() {
+            Some(value) => value,
+            None => panic!("{} used before initialized", type_name::<T>()),
+        }
+    }
+}
+
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == READY {
+            unsafe {
+                self.data.get_mut().assume_init_drop();
+            }
+        }
+    }
+}
+
+/// A value computed by `F` the first time it is used
+pub struct Lazy<T, F = fn() -> T> {
+    once: Once<T>,
+    init: F,
+}
+
+impl<T, F> Lazy<T, F> {
+    /// Create a cell computing its value by `init`
+    pub const fn new(init: F) -> Self {
+        Self {
+            once: Once::new(),
+            init,
+        }
+    }
+}
+
+impl<T, F: Fn() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+    /// The value, computed if not yet. Panic if computing it uses the cell itself.
+    fn deref(&self) -> &T {
+        self.once.call_once(|| (self.init)())
+    }
+}
@@ -0,0 +1,137 @@
+//! Spinning algorithms behind [`super::SpinLock`], chosen per lock
+use crate::config::MAX_HARTS;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// A lock without data, taken and released by harts
+pub trait RawSpinLock {
+    /// A released lock
+    const INIT: Self;
+    /// Spin until hart `hart` takes the lock, return whether it had to wait
+    fn lock(&self, hart: usize) -> bool;
+    /// Release the lock, held by hart `hart`
+    fn unlock(&self, hart: usize);
+}
+
+/// A test-and-set lock, the cheapest without contention. Harts spinning on it
+/// all bounce its cache line and may take it in any order.
+pub struct TasLock {
+    locked: AtomicBool,
+}
+
+impl RawSpinLock for TasLock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = Self {
+        locked: AtomicBool::new(false),
+    };
+    fn lock(&self, _hart: usize) -> bool {
+        let mut contended = false;
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            contended = true;
+            while self.locked.load(Ordering::Relaxed) {
+                spin_loop();
+            }
+        }
+        contended
+    }
+    fn unlock(&self, _hart: usize) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+/// A ticket lock, taken by harts in the order they arrive
+pub struct TicketLock {
+    next_ticket: AtomicUsize,
+    now_serving: AtomicUsize,
+}
+
+impl RawSpinLock for TicketLock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = Self {
+        next_ticket: AtomicUsize::new(0),
+        now_serving: AtomicUsize::new(0),
+    };
+    fn lock(&self, _hart: usize) -> bool {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        let mut contended = false;
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            contended = true;
+            spin_loop();
+        }
+        contended
+    }
+    fn unlock(&self, _hart: usize) {
+        let next = self.now_serving.load(Ordering::Relaxed) + 1;
+        self.now_serving.store(next, Ordering::Release);
+    }
+}
+
+/// Where a hart waits in an [`McsLock`]
+struct McsNode {
+    /// hart queued after this one plus one, 0 if none
+    next: AtomicUsize,
+    /// cleared by the previous hart when it hands the lock over
+    waiting: AtomicBool,
+}
+
+/// An MCS lock, taken by harts in the order they arrive. Each waiter spins on a
+/// flag of its own, so a release only touches the cache line of the next waiter.
+///
+/// A hart holds at most one place in a lock, so the lock keeps a node for every
+/// hart rather than waiters bringing their own.
+pub struct McsLock {
+    /// last hart queued plus one, 0 if free
+    tail: AtomicUsize,
+    nodes: [McsNode; MAX_HARTS],
+}
+
+impl RawSpinLock for McsLock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const NODE: McsNode = McsNode {
+            next: AtomicUsize::new(0),
+            waiting: AtomicBool::new(false),
+        };
+        Self {
+            tail: AtomicUsize::new(0),
+            nodes: [NODE; MAX_HARTS],
+        }
+    };
+    fn lock(&self, hart: usize) -> bool {
+        let node = &self.nodes[hart];
+        node.next.store(0, Ordering::Relaxed);
+        node.waiting.store(true, Ordering::Relaxed);
+        let prev = self.tail.swap(hart + 1, Ordering::AcqRel);
+        if prev == 0 {
+            return false;
+        }
+        self.nodes[prev - 1].next.store(hart + 1, Ordering::Release);
+        while node.waiting.load(Ordering::Acquire) {
+            spin_loop();
+        }
+        true
+    }
+    fn unlock(&self, hart: usize) {
+        let node = &self.nodes[hart];
+        if node.next.load(Ordering::Acquire) == 0 {
+            if self
+                .tail
+                .compare_exchange(hart + 1, 0, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                return;
+            }
+            // a hart is queuing up, wait until it links itself after this one
+            while node.next.load(Ordering::Acquire) == 0 {
+                spin_loop();
+            }
+        }
+        let next = node.next.load(Ordering::Acquire);
+        self.nodes[next - 1].waiting.store(false, Ordering::Release);
+    }
+}
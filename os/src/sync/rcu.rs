@@ -0,0 +1,121 @@
+//! Read-copy-update: data read without taking a lock, replaced as a whole by
+//! writers and freed once no hart may still be reading it
+use super::SpinLock;
+use crate::config::MAX_HARTS;
+use crate::hart::{hart_id, online_hart_mask};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// Number of quiescent states each hart passed through, indexed by hart id. A hart
+/// in a quiescent state holds no [`RcuReadGuard`].
+static QUIESCENT_COUNTS: [AtomicUsize; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+    [ZERO; MAX_HARTS]
+};
+
+/// Replaced data waiting for every hart to pass through a quiescent state after
+/// `counts` was taken
+struct Retired {
+    counts: [usize; MAX_HARTS],
+    _data: Box<dyn Send>,
+}
+
+static RETIRED: SpinLock<Vec<Retired>> = SpinLock::new(Vec::new());
+
+fn quiescent_counts() -> [usize; MAX_HARTS] {
+    core::array::from_fn(|hart| QUIESCENT_COUNTS[hart].load(Ordering::Acquire))
+}
+
+/// Free `data` once no hart may still be reading it
+fn retire(data: Box<dyn Send>) {
+    let counts = quiescent_counts();
+    RETIRED.lock().push(Retired {
+        counts,
+        _data: data,
+    });
+}
+
+/// Report that the current hart holds no [`RcuReadGuard`], e.g. between two tasks,
+/// and free the data no hart may still be reading
+pub fn rcu_quiescent() {
+    QUIESCENT_COUNTS[hart_id()].fetch_add(1, Ordering::Release);
+    let counts = quiescent_counts();
+    let online = online_hart_mask();
+    let mut retired = RETIRED.lock();
+    if retired.is_empty() {
+        return;
+    }
+    let (expired, waiting): (Vec<_>, Vec<_>) = core::mem::take(&mut *retired)
+        .into_iter()
+        .partition(|entry| {
+            (0..MAX_HARTS)
+                .filter(|hart| online & (1 << hart) != 0)
+                .all(|hart| counts[hart] > entry.counts[hart])
+        });
+    *retired = waiting;
+    // freeing may retire more data
+    drop(retired);
+    drop(expired);
+}
+
+/// Data read without a lock, e.g. on hot lookup paths, and replaced by a copy on
+/// each update. Updates are serialized by a lock and cost a copy of the data.
+pub struct RcuCell<T> {
+    ptr: AtomicPtr<T>,
+    writer: SpinLock<()>,
+}
+
+unsafe impl<T: Send + Sync> Sync for RcuCell<T> {}
+
+impl<T: Clone + Send + 'static> RcuCell<T> {
+    /// Wrap `value`
+    pub fn new(value: T) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Box::into_raw(Box::new(value))),
+            writer: SpinLock::new(()),
+        }
+    }
+    /// Read the current data, which stays valid while the guard is held. The guard
+    /// must be dropped before the current hart switches to another task.
+    pub fn read(&self) -> RcuReadGuard<'_, T> {
+        RcuReadGuard {
+            data: unsafe { &*self.ptr.load(Ordering::Acquire) },
+            _not_send: PhantomData,
+        }
+    }
+    /// Replace the data by a copy changed by `f`. Readers see either the old data
+    /// or the new one, the old one is freed once they are done with it.
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let _writer = self.writer.lock();
+        let old = self.ptr.load(Ordering::Relaxed);
+        let mut new = Box::new(unsafe { &*old }.clone());
+        let ret = f(&mut new);
+        self.ptr.store(Box::into_raw(new), Ordering::Release);
+        retire(unsafe { Box::from_raw(old) });
+        ret
+    }
+}
+
+impl<T> Drop for RcuCell<T> {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(*self.ptr.get_mut()) });
+    }
+}
+
+/// The data of an [`RcuCell`] being read
+pub struct RcuReadGuard<'a, T> {
+    data: &'a T,
+    /// kept on the hart that read it
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Deref for RcuReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
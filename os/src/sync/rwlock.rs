@@ -0,0 +1,91 @@
+//! Reader-writer locks, e.g. for the reader-writer locks of user programs
+use super::ExclusiveCell;
+use crate::task::{current_task, TaskControlBlock, WaitQueue};
+use alloc::sync::Arc;
+
+/// A lock held either by any number of readers or by a single writer, whose
+/// waiters block on a wait queue.
+///
+/// It prefers writers: readers wait while a writer waits, so that a steady
+/// stream of readers cannot starve writers.
+pub struct RwLock {
+    inner: ExclusiveCell<RwLockInner>,
+    wait_queue: WaitQueue,
+}
+
+struct RwLockInner {
+    /// number of readers holding the lock
+    readers: usize,
+    writer: Option<Arc<TaskControlBlock>>,
+    /// number of writers waiting for the lock
+    writers_waiting: usize,
+}
+
+impl RwLock {
+    /// Create a released lock
+    pub fn new() -> Self {
+        Self {
+            inner: ExclusiveCell::new(RwLockInner {
+                readers: 0,
+                writer: None,
+                writers_waiting: 0,
+            }),
+            wait_queue: WaitQueue::new(),
+        }
+    }
+    /// Lock it for reading, waiting while a writer holds it or waits for it.
+    /// Return false if a signal arrives first.
+    pub fn read(&self) -> bool {
+        self.wait_queue.wait_until(|| {
+            let mut inner = self.inner.exclusive_access();
+            if inner.writer.is_some() || inner.writers_waiting > 0 {
+                return false;
+            }
+            inner.readers += 1;
+            true
+        })
+    }
+    /// Lock it for writing by the current task, waiting while anyone else holds it.
+    /// Return false if a signal arrives first.
+    pub fn write(&self) -> bool {
+        let task = current_task().unwrap();
+        self.inner.exclusive_access().writers_waiting += 1;
+        let locked = self.wait_queue.wait_until(|| {
+            let mut inner = self.inner.exclusive_access();
+            if inner.writer.is_some() || inner.readers > 0 {
+                return false;
+            }
+            inner.writer = Some(Arc::clone(&task));
+            true
+        });
+        let mut inner = self.inner.exclusive_access();
+        inner.writers_waiting -= 1;
+        let readers_unblocked = !locked && inner.writers_waiting == 0 && inner.writer.is_none();
+        drop(inner);
+        if readers_unblocked {
+            self.wait_queue.wake_all();
+        }
+        locked
+    }
+    /// Release it, held for writing by the current task or else for reading.
+    /// Return false if it is not held by the current task.
+    pub fn unlock(&self) -> bool {
+        let task = current_task().unwrap();
+        let mut inner = self.inner.exclusive_access();
+        match inner.writer.as_ref() {
+            Some(writer) if Arc::ptr_eq(writer, &task) => inner.writer = None,
+            Some(_) => return false,
+            None if inner.readers > 0 => {
+                inner.readers -= 1;
+                if inner.readers > 0 {
+                    return true;
+                }
+            }
+            None => return false,
+        }
+        drop(inner);
+        // readers and writers alike check again, readers wait if a writer is first
+        self.wait_queue.wake_all();
+        true
+    }
+}
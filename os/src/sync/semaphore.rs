@@ -0,0 +1,37 @@
+//! Counting semaphores, e.g. for the semaphores of user programs
+use super::ExclusiveCell;
+use crate::task::WaitQueue;
+
+/// A count of resources, taken one at a time by tasks that block on a wait queue
+/// while there is none left
+pub struct Semaphore {
+    count: ExclusiveCell<usize>,
+    wait_queue: WaitQueue,
+}
+
+impl Semaphore {
+    /// Create a semaphore with `count` resources
+    pub fn new(count: usize) -> Self {
+        Self {
+            count: ExclusiveCell::new(count),
+            wait_queue: WaitQueue::new(),
+        }
+    }
+    /// Release a resource, waking up a task waiting for one
+    pub fn up(&self) {
+        *self.count.exclusive_access() += 1;
+        self.wait_queue.wake_one();
+    }
+    /// Take a resource, waiting until one is released if there is none left.
+    /// Return false if a signal arrives first.
+    pub fn down(&self) -> bool {
+        self.wait_queue.wait_until(|| {
+            let mut count = self.count.exclusive_access();
+            if *count == 0 {
+                return false;
+            }
+            *count -= 1;
+            true
+        })
+    }
+}
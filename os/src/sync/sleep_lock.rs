@@ -0,0 +1,61 @@
+//! Locks held by tasks across blocking, e.g. for waiting on a device
+use super::ExclusiveCell;
+use crate::task::WaitQueue;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+
+/// A lock whose waiters block until it is released, so that it may be held
+/// while the holder blocks, unlike a [`super::SpinLock`] spinning on the hart
+/// of a blocked holder forever. Waiting is not interrupted by signals.
+pub struct SleepLock<T> {
+    locked: ExclusiveCell<bool>,
+    wait_queue: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SleepLock<T> {}
+
+impl<T> SleepLock<T> {
+    /// Create a released lock
+    pub fn new(value: T) -> Self {
+        Self {
+            locked: ExclusiveCell::new(false),
+            wait_queue: WaitQueue::new(),
+            data: UnsafeCell::new(value),
+        }
+    }
+    /// Take the lock, blocking while another task holds it. Taking it without
+    /// a current task, e.g. while booting, must not wait.
+    pub fn lock(&self) -> SleepLockGuard<'_, T> {
+        let try_lock = || !core::mem::replace(&mut *self.locked.exclusive_access(), true);
+        if !try_lock() {
+            self.wait_queue.wait_until_uninterruptible(try_lock);
+        }
+        SleepLockGuard { lock: self }
+    }
+}
+
+/// Access to the data of a [`SleepLock`], releasing it when dropped
+pub struct SleepLockGuard<'a, T> {
+    lock: &'a SleepLock<T>,
+}
+
+impl<T> Deref for SleepLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SleepLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SleepLockGuard<'_, T> {
+    fn drop(&mut self) {
+        *self.lock.locked.exclusive_access() = false;
+        self.lock.wait_queue.wake_one();
+    }
+}
@@ -0,0 +1,166 @@
+//! Spinlocks shared by all harts
+use super::{lockdep, RawSpinLock, TasLock};
+use crate::config::MAX_HARTS;
+use crate::hart::hart_id;
+use core::any::type_name;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use riscv::register::sstatus;
+
+/// A lock serializing accesses from different harts by spinning with `L`, a
+/// [`TasLock`] unless a fair one is picked for a contended lock.
+///
+/// It must not be taken by interrupt handlers, use [`SpinNoIrqLock`] for data
+/// they access. Taking it again on the hart already holding it, e.g. from an
+/// interrupt handler or by a recursive call, would spin forever and panics
+/// instead, like a double borrow of a `RefCell`.
+pub struct SpinLock<T, L: RawSpinLock = TasLock> {
+    raw: L,
+    /// id of the hart holding the lock plus one, 0 if free
+    owner: AtomicUsize,
+    /// number of times a hart had to wait for the lock
+    contentions: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T, L: RawSpinLock> Sync for SpinLock<T, L> {}
+
+impl<T, L: RawSpinLock> SpinLock<T, L> {
+    /// Create a released lock
+    pub const fn new(value: T) -> Self {
+        Self {
+            raw: L::INIT,
+            owner: AtomicUsize::new(0),
+            contentions: AtomicUsize::new(0),
+            data: UnsafeCell::new(value),
+        }
+    }
+    /// Spin until no other hart holds the lock, then take it.
+    /// Panic if the current hart already holds it.
+    fn acquire(&self) {
+        let me = hart_id();
+        // only this hart stores its own id, so it reads it back only if it holds the lock
+        if self.owner.load(Ordering::Relaxed) == me + 1 {
+            panic!("already borrowed: BorrowMutError");
+        }
+        lockdep::lock_acquire(type_name::<T>());
+        if self.raw.lock(me) {
+            self.contentions.fetch_add(1, Ordering::Relaxed);
+        }
+        self.owner.store(me + 1, Ordering::Relaxed);
+    }
+    /// Release the lock, held by the current hart
+    fn release(&self) {
+        let owner = self.owner.swap(0, Ordering::Relaxed);
+        self.raw.unlock(owner - 1);
+        lockdep::lock_release(type_name::<T>());
+    }
+    /// Take the lock, spinning while another hart holds it
+    pub fn lock(&self) -> SpinLockGuard<'_, T, L> {
+        self.acquire();
+        SpinLockGuard {
+            lock: self,
+            no_irq: false,
+        }
+    }
+    /// Number of times a hart had to wait for the lock
+    pub fn contentions(&self) -> usize {
+        self.contentions.load(Ordering::Relaxed)
+    }
+}
+
+/// Number of `push_off` not matched by `pop_off` yet on each hart
+static NOIRQ_DEPTH: [AtomicUsize; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+    [ZERO; MAX_HARTS]
+};
+/// Whether interrupts were enabled on each hart at its outermost `push_off`
+static IRQ_WAS_ENABLED: [AtomicBool; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FALSE: AtomicBool = AtomicBool::new(false);
+    [FALSE; MAX_HARTS]
+};
+
+/// Disable interrupts of the current hart until the matching [`pop_off`].
+/// Calls nest, so that locks released in another order than taken leave
+/// interrupts disabled until the last one is released.
+pub fn push_off() {
+    let enabled = sstatus::read().sie();
+    unsafe {
+        sstatus::clear_sie();
+    }
+    let hart = hart_id();
+    if NOIRQ_DEPTH[hart].fetch_add(1, Ordering::Relaxed) == 0 {
+        IRQ_WAS_ENABLED[hart].store(enabled, Ordering::Relaxed);
+    }
+}
+
+/// Undo a [`push_off`], enabling interrupts again after the outermost one if
+/// they were enabled before it
+pub fn pop_off() {
+    let hart = hart_id();
+    let depth = NOIRQ_DEPTH[hart].fetch_sub(1, Ordering::Relaxed);
+    assert!(depth > 0, "pop_off without push_off");
+    if depth == 1 && IRQ_WAS_ENABLED[hart].load(Ordering::Relaxed) {
+        unsafe {
+            sstatus::set_sie();
+        }
+    }
+}
+
+/// A [`SpinLock`] keeping interrupts of the current hart disabled while held,
+/// so that it can be shared with interrupt handlers
+pub struct SpinNoIrqLock<T, L: RawSpinLock = TasLock>(SpinLock<T, L>);
+
+impl<T, L: RawSpinLock> SpinNoIrqLock<T, L> {
+    /// Create a released lock
+    pub const fn new(value: T) -> Self {
+        Self(SpinLock::new(value))
+    }
+    /// Disable interrupts and take the lock, spinning while another hart holds it.
+    /// Interrupts are enabled again once the hart releases the last lock of this
+    /// kind, if they were enabled before it took the first one.
+    pub fn lock(&self) -> SpinLockGuard<'_, T, L> {
+        push_off();
+        self.0.acquire();
+        SpinLockGuard {
+            lock: &self.0,
+            no_irq: true,
+        }
+    }
+    /// Number of times a hart had to wait for the lock
+    pub fn contentions(&self) -> usize {
+        self.0.contentions()
+    }
+}
+
+/// Access to the data of a [`SpinLock`] or [`SpinNoIrqLock`], released on drop
+pub struct SpinLockGuard<'a, T, L: RawSpinLock = TasLock> {
+    lock: &'a SpinLock<T, L>,
+    /// whether it was taken with interrupts disabled by `push_off`
+    no_irq: bool,
+}
+
+impl<T, L: RawSpinLock> Deref for SpinLockGuard<'_, T, L> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T, L: RawSpinLock> DerefMut for SpinLockGuard<'_, T, L> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T, L: RawSpinLock> Drop for SpinLockGuard<'_, T, L> {
+    fn drop(&mut self) {
+        self.lock.release();
+        if self.no_irq {
+            pop_off();
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Error numbers returned (negated) by syscalls, following Linux
+/// Operation not permitted
+pub const EPERM: isize = 1;
+/// No such process
+pub const ESRCH: isize = 3;
+/// Interrupted system call
+pub const EINTR: isize = 4;
+/// I/O error
+pub const EIO: isize = 5;
+/// Argument list too long
+pub const E2BIG: isize = 7;
+/// Exec format error
+pub const ENOEXEC: isize = 8;
+/// Bad file number
+pub const EBADF: isize = 9;
+/// No child processes
+pub const ECHILD: isize = 10;
+/// Try again, e.g. without blocking
+pub const EAGAIN: isize = 11;
+/// Out of memory
+pub const ENOMEM: isize = 12;
+/// Permission denied
+pub const EACCES: isize = 13;
+/// Bad address
+pub const EFAULT: isize = 14;
+/// Device or resource busy
+pub const EBUSY: isize = 16;
+/// No such device, or it does not support the operation
+pub const ENODEV: isize = 19;
+/// Invalid argument
+pub const EINVAL: isize = 22;
+/// Too many open files
+pub const EMFILE: isize = 24;
+/// Inappropriate ioctl for device
+pub const ENOTTY: isize = 25;
+/// Resource deadlock would occur
+pub const EDEADLK: isize = 35;
+/// Socket operation on non-socket
+pub const ENOTSOCK: isize = 88;
+/// Message too long
+pub const EMSGSIZE: isize = 90;
+/// Protocol not supported
+pub const EPROTONOSUPPORT: isize = 93;
+/// Operation not supported on transport endpoint
+pub const EOPNOTSUPP: isize = 95;
+/// Address family not supported by protocol
+pub const EAFNOSUPPORT: isize = 97;
+/// Address already in use
+pub const EADDRINUSE: isize = 98;
+/// Network is down
+pub const ENETDOWN: isize = 100;
+/// Connection reset by peer
+pub const ECONNRESET: isize = 104;
+/// No buffer space available
+pub const ENOBUFS: isize = 105;
+/// Transport endpoint is already connected
+pub const EISCONN: isize = 106;
+/// Connection timed out
+pub const ETIMEDOUT: isize = 110;
+/// Connection refused
+pub const ECONNREFUSED: isize = 111;
+/// No route to host
+pub const EHOSTUNREACH: isize = 113;
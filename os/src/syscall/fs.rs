@@ -1,23 +1,77 @@
 //! File and filesystem-related syscalls
-use crate::fs::{open_file, OpenFlags};
-use crate::mm::{translated_byte_buffer, translated_str, UserBuffer};
-use crate::task::{current_task, current_user_token};
+use super::errno::{EAGAIN, EBADF, EFAULT, EINTR, EINVAL, EIO, EMFILE, ENOTTY};
+use crate::config::PAGE_SIZE;
+use crate::drivers::gpu::{Rect, BYTES_PER_PIXEL};
+use crate::fs::{open, OpenFlags, Stat};
+use crate::mm::{copy_from_user, copy_str_from_user, copy_to_user, UserBuffer};
+use crate::random;
+use crate::task::{cond_resched, current_has_signal, current_process, current_user_token};
+
+/// Bytes read or written by `sys_read` and `sys_write` between preemption points
+const IO_CHUNK: usize = 8 * PAGE_SIZE;
+/// `request` of [`sys_ioctl`] on a framebuffer: get its [`FbInfo`]
+const FBIOGET_INFO: usize = 0x4600;
+/// `request` of [`sys_ioctl`] on a framebuffer: show the pixels of the
+/// [`Rect`] at `arg`, of the whole screen if null
+const FBIO_FLUSH: usize = 0x4601;
+/// `flags` of [`sys_getrandom`] accepted, though the pool never blocks:
+/// return rather than wait, and read from the blocking pool
+const GRND_NONBLOCK: u32 = 1 << 0;
+const GRND_RANDOM: u32 = 1 << 1;
+
+/// Resolution and layout of a framebuffer
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct FbInfo {
+    width: u32,
+    height: u32,
+    /// bytes of a row of pixels
+    stride: u32,
+    bits_per_pixel: u32,
+}
+
+/// Read or write the `len` bytes at `buf` by `transfer` in chunks of `IO_CHUNK` bytes,
+/// so that a large transfer can be preempted in between. Stop at a chunk not transferred
+/// in full. Return the number of bytes transferred, -EFAULT if the first chunk is not
+/// accessible.
+fn transfer_in_chunks(
+    token: usize,
+    buf: *const u8,
+    len: usize,
+    write: bool,
+    transfer: impl Fn(UserBuffer) -> usize,
+) -> isize {
+    let mut done = 0;
+    while done < len {
+        if done > 0 {
+            cond_resched();
+        }
+        let chunk = (len - done).min(IO_CHUNK);
+        let user_buf = match UserBuffer::from_user(token, buf.wrapping_add(done), chunk, write) {
+            Some(user_buf) => user_buf,
+            None if done == 0 => return -EFAULT,
+            None => break,
+        };
+        let size = transfer(user_buf);
+        done += size;
+        if size < chunk {
+            break;
+        }
+    }
+    done as isize
+}
 
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     let token = current_user_token();
-    let task = current_task().unwrap();
-    let inner = task.inner_exclusive_access();
-    if fd >= inner.fd_table.len() {
-        return -1;
-    }
-    if let Some(file) = &inner.fd_table[fd] {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if let Some(file) = inner.get_file(fd) {
         if !file.writable() {
             return -1;
         }
-        let file = file.clone();
-        // release current task TCB manually to avoid multi-borrow
+        // release current PCB manually to avoid multi-borrow
         drop(inner);
-        file.write(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+        transfer_in_chunks(token, buf, len, false, |user_buf| file.write(user_buf))
     } else {
         -1
     }
@@ -25,47 +79,132 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
 
 pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
     let token = current_user_token();
-    let task = current_task().unwrap();
-    let inner = task.inner_exclusive_access();
-    if fd >= inner.fd_table.len() {
-        return -1;
-    }
-    if let Some(file) = &inner.fd_table[fd] {
-        let file = file.clone();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if let Some(file) = inner.get_file(fd) {
         if !file.readable() {
             return -1;
         }
-        // release current task TCB manually to avoid multi-borrow
+        // release current PCB manually to avoid multi-borrow
         drop(inner);
-        file.read(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+        match transfer_in_chunks(token, buf, len, true, |user_buf| file.read(user_buf)) {
+            // interrupted before reading anything
+            0 if len > 0 && current_has_signal() => -EINTR,
+            size => size,
+        }
     } else {
         -1
     }
 }
 
 pub fn sys_open(path: *const u8, flags: u32) -> isize {
-    let task = current_task().unwrap();
+    let process = current_process();
     let token = current_user_token();
-    let path = translated_str(token, path);
-    if let Some(inode) = open_file(path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
-        let mut inner = task.inner_exclusive_access();
-        let fd = inner.alloc_fd();
-        inner.fd_table[fd] = Some(inode);
-        fd as isize
+    let path = match copy_str_from_user(token, path) {
+        Some(path) => path,
+        None => return -EFAULT,
+    };
+    if let Some(inode) = open(path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
+        let inner = process.inner_exclusive_access();
+        match inner.alloc_fd(inode) {
+            Some(fd) => fd as isize,
+            None => -EMFILE,
+        }
     } else {
         -1
     }
 }
 
+/// Write the status of file `fd` to `*st`. Return 0, -EBADF if `fd` is not open.
+pub fn sys_fstat(fd: usize, st: *mut Stat) -> isize {
+    let file = match current_process().inner_exclusive_access().get_file(fd) {
+        Some(file) => file,
+        None => return -EBADF,
+    };
+    match copy_to_user(current_user_token(), st, &file.stat()) {
+        Some(()) => 0,
+        None => -EFAULT,
+    }
+}
+
+/// Close file `fd`. Return 0, -EBADF if `fd` is not open.
 pub fn sys_close(fd: usize) -> isize {
-    let task = current_task().unwrap();
-    let mut inner = task.inner_exclusive_access();
-    if fd >= inner.fd_table.len() {
-        return -1;
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let mut fd_table = inner.fd_table.exclusive_access();
+    match fd_table.get_mut(fd).and_then(Option::take) {
+        Some(_) => 0,
+        None => -EBADF,
+    }
+}
+
+/// Carry out the device-specific `request` on file `fd` with argument `arg`,
+/// `FBIOGET_INFO` or `FBIO_FLUSH` on a framebuffer. Return 0, -EBADF if `fd`
+/// is not open and -ENOTTY if the file does not support `request`.
+pub fn sys_ioctl(fd: usize, request: usize, arg: usize) -> isize {
+    let file = match current_process().inner_exclusive_access().get_file(fd) {
+        Some(file) => file,
+        None => return -EBADF,
+    };
+    let framebuffer = match file.as_framebuffer() {
+        Some(framebuffer) => framebuffer,
+        None => return -ENOTTY,
+    };
+    let token = current_user_token();
+    match request {
+        FBIOGET_INFO => {
+            let (width, height) = framebuffer.resolution();
+            let info = FbInfo {
+                width,
+                height,
+                stride: framebuffer.stride() as u32,
+                bits_per_pixel: (BYTES_PER_PIXEL * 8) as u32,
+            };
+            match copy_to_user(token, arg as *mut FbInfo, &info) {
+                Some(()) => 0,
+                None => -EFAULT,
+            }
+        }
+        FBIO_FLUSH => {
+            let rect = if arg == 0 {
+                Rect {
+                    x: 0,
+                    y: 0,
+                    width: u32::MAX,
+                    height: u32::MAX,
+                }
+            } else {
+                match copy_from_user(token, arg as *const Rect) {
+                    Some(rect) => rect,
+                    None => return -EFAULT,
+                }
+            };
+            if framebuffer.flush(rect) {
+                0
+            } else {
+                -EIO
+            }
+        }
+        _ => -ENOTTY,
+    }
+}
+
+/// Fill the `len` bytes at `buf` from the entropy pool, once it is seeded.
+/// Return the number of bytes filled, -EAGAIN before the pool is seeded with
+/// `GRND_NONBLOCK`, -EINVAL for unknown `flags` and -EFAULT if `buf` is not
+/// accessible.
+pub fn sys_getrandom(buf: *mut u8, len: usize, flags: u32) -> isize {
+    if flags & !(GRND_NONBLOCK | GRND_RANDOM) != 0 {
+        return -EINVAL;
     }
-    if inner.fd_table[fd].is_none() {
-        return -1;
+    if flags & GRND_NONBLOCK != 0 && !random::is_seeded() {
+        return -EAGAIN;
     }
-    inner.fd_table[fd].take();
-    0
+    random::wait_seeded();
+    transfer_in_chunks(current_user_token(), buf, len, true, |mut user_buf| {
+        for slice in user_buf.buffers.iter_mut() {
+            random::fill(slice);
+        }
+        user_buf.len()
+    })
 }
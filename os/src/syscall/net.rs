@@ -0,0 +1,295 @@
+//! Socket syscalls, for UDP and TCP over IPv4
+use super::errno::{
+    EADDRINUSE, EAFNOSUPPORT, EBADF, ECONNREFUSED, ECONNRESET, EFAULT, EHOSTUNREACH, EINTR, EINVAL,
+    EISCONN, EMFILE, EMSGSIZE, ENETDOWN, ENOBUFS, ENOTSOCK, EOPNOTSUPP, EPROTONOSUPPORT, ETIMEDOUT,
+};
+use crate::fs::File;
+use crate::mm::{copy_from_user, copy_to_user, UserBuffer};
+use crate::net::{Ipv4Addr, SendError, TcpError, TcpSocket, UdpSocket, MAX_UDP_PAYLOAD};
+use crate::task::{current_process, current_user_token};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+const AF_INET: u16 = 2;
+const SOCK_STREAM: usize = 1;
+const SOCK_DGRAM: usize = 2;
+const IPPROTO_TCP: usize = 6;
+const IPPROTO_UDP: usize = 17;
+
+/// IPv4 socket address as `struct sockaddr_in`, the port and address in network byte order
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct SockAddrIn {
+    family: u16,
+    port: u16,
+    addr: [u8; 4],
+    zero: [u8; 8],
+}
+
+/// Open a socket of `domain` and `ty`, only `AF_INET` with `SOCK_DGRAM` or
+/// `SOCK_STREAM`, and `protocol` 0 or the one of the type. Return its fd.
+pub fn sys_socket(domain: usize, ty: usize, protocol: usize) -> isize {
+    if domain != AF_INET as usize {
+        return -EAFNOSUPPORT;
+    }
+    let socket: Arc<dyn File + Send + Sync> = match (ty, protocol) {
+        (SOCK_DGRAM, 0 | IPPROTO_UDP) => Arc::new(UdpSocket::new()),
+        (SOCK_STREAM, 0 | IPPROTO_TCP) => Arc::new(TcpSocket::new()),
+        _ => return -EPROTONOSUPPORT,
+    };
+    match current_process().inner_exclusive_access().alloc_fd(socket) {
+        Some(fd) => fd as isize,
+        None => -EMFILE,
+    }
+}
+
+/// The file of `fd` of the current process, which has to be a socket
+fn socket_file(fd: usize) -> Result<Arc<dyn File + Send + Sync>, isize> {
+    let file = current_process()
+        .inner_exclusive_access()
+        .get_file(fd)
+        .ok_or(EBADF)?;
+    if file.as_udp_socket().is_none() && file.as_tcp_socket().is_none() {
+        return Err(ENOTSOCK);
+    }
+    Ok(file)
+}
+
+/// The file of UDP socket `fd`, EOPNOTSUPP for a TCP one
+fn udp_socket_file(fd: usize) -> Result<Arc<dyn File + Send + Sync>, isize> {
+    let file = socket_file(fd)?;
+    if file.as_udp_socket().is_none() {
+        return Err(EOPNOTSUPP);
+    }
+    Ok(file)
+}
+
+/// The file of TCP socket `fd`, EOPNOTSUPP for a UDP one
+fn tcp_socket_file(fd: usize) -> Result<Arc<dyn File + Send + Sync>, isize> {
+    let file = socket_file(fd)?;
+    if file.as_tcp_socket().is_none() {
+        return Err(EOPNOTSUPP);
+    }
+    Ok(file)
+}
+
+fn tcp_errno(error: TcpError) -> isize {
+    match error {
+        TcpError::Refused => ECONNREFUSED,
+        TcpError::Reset => ECONNRESET,
+        TcpError::TimedOut => ETIMEDOUT,
+        TcpError::IsConnected => EISCONN,
+        TcpError::NotListening => EINVAL,
+        TcpError::AddrInUse => EADDRINUSE,
+        TcpError::Interrupted => EINTR,
+        TcpError::Send(error) => send_errno(error),
+    }
+}
+
+fn send_errno(error: SendError) -> isize {
+    match error {
+        SendError::NoDevice => ENETDOWN,
+        SendError::Unreachable => EHOSTUNREACH,
+        SendError::Busy => ENOBUFS,
+    }
+}
+
+/// Write the address of the peer to `addr` and its length to `*addrlen` unless `addr` is null
+fn write_sockaddr(addr: *mut SockAddrIn, addrlen: *mut u32, ip: Ipv4Addr, port: u16) -> isize {
+    if addr.is_null() {
+        return 0;
+    }
+    let token = current_user_token();
+    let sockaddr = SockAddrIn {
+        family: AF_INET,
+        port: port.to_be(),
+        addr: ip,
+        zero: [0; 8],
+    };
+    let len = core::mem::size_of::<SockAddrIn>() as u32;
+    if copy_to_user(token, addr, &sockaddr).is_none()
+        || copy_to_user(token, addrlen, &len).is_none()
+    {
+        return -EFAULT;
+    }
+    0
+}
+
+/// Check that `*addrlen` has room for an address unless `addr` is null
+fn check_addrlen(addr: *mut SockAddrIn, addrlen: *mut u32) -> isize {
+    if addr.is_null() {
+        return 0;
+    }
+    match copy_from_user(current_user_token(), addrlen) {
+        Some(addrlen) if addrlen as usize >= core::mem::size_of::<SockAddrIn>() => 0,
+        Some(_) => -EINVAL,
+        None => -EFAULT,
+    }
+}
+
+/// Read the IPv4 address of `addrlen` bytes at `addr`
+fn read_sockaddr(addr: *const SockAddrIn, addrlen: usize) -> Result<SockAddrIn, isize> {
+    if addrlen < core::mem::size_of::<SockAddrIn>() {
+        return Err(EINVAL);
+    }
+    let sockaddr = copy_from_user(current_user_token(), addr).ok_or(EFAULT)?;
+    if sockaddr.family != AF_INET {
+        return Err(EAFNOSUPPORT);
+    }
+    Ok(sockaddr)
+}
+
+/// Bind socket `fd` to the port of `addr`, a free one if 0, on every address
+pub fn sys_bind(fd: usize, addr: *const SockAddrIn, addrlen: usize) -> isize {
+    let file = match socket_file(fd) {
+        Ok(file) => file,
+        Err(errno) => return -errno,
+    };
+    let sockaddr = match read_sockaddr(addr, addrlen) {
+        Ok(sockaddr) => sockaddr,
+        Err(errno) => return -errno,
+    };
+    let port = u16::from_be(sockaddr.port);
+    let bound = match (file.as_udp_socket(), file.as_tcp_socket()) {
+        (Some(socket), _) if socket.port() == 0 => socket.bind(port),
+        (_, Some(socket)) if socket.port() == 0 => socket.bind(port),
+        _ => return -EINVAL,
+    };
+    if !bound {
+        return -EADDRINUSE;
+    }
+    0
+}
+
+/// Connect TCP socket `fd` to `addr`, blocking until the connection is
+/// established. Return 0, -ECONNREFUSED if the peer is not listening,
+/// -ETIMEDOUT if it does not answer and -EINTR if a signal arrives first.
+pub fn sys_connect(fd: usize, addr: *const SockAddrIn, addrlen: usize) -> isize {
+    let file = match tcp_socket_file(fd) {
+        Ok(file) => file,
+        Err(errno) => return -errno,
+    };
+    let sockaddr = match read_sockaddr(addr, addrlen) {
+        Ok(sockaddr) => sockaddr,
+        Err(errno) => return -errno,
+    };
+    let socket = file.as_tcp_socket().unwrap();
+    match socket.connect(sockaddr.addr, u16::from_be(sockaddr.port)) {
+        Ok(()) => 0,
+        Err(error) => -tcp_errno(error),
+    }
+}
+
+/// Listen for connections on TCP socket `fd`, queueing up to `backlog` of them
+pub fn sys_listen(fd: usize, backlog: usize) -> isize {
+    let file = match tcp_socket_file(fd) {
+        Ok(file) => file,
+        Err(errno) => return -errno,
+    };
+    match file.as_tcp_socket().unwrap().listen(backlog) {
+        Ok(()) => 0,
+        Err(error) => -tcp_errno(error),
+    }
+}
+
+/// Block until a connection to listening socket `fd` is established and
+/// return the fd of a new socket of it. Write the address of the peer to
+/// `addr` unless null, its length to `*addrlen`.
+pub fn sys_accept(fd: usize, addr: *mut SockAddrIn, addrlen: *mut u32) -> isize {
+    let file = match tcp_socket_file(fd) {
+        Ok(file) => file,
+        Err(errno) => return -errno,
+    };
+    let errno = check_addrlen(addr, addrlen);
+    if errno != 0 {
+        return errno;
+    }
+    let (socket, peer, peer_port) = match file.as_tcp_socket().unwrap().accept() {
+        Ok(accepted) => accepted,
+        Err(error) => return -tcp_errno(error),
+    };
+    let errno = write_sockaddr(addr, addrlen, peer, peer_port);
+    if errno != 0 {
+        return errno;
+    }
+    match current_process()
+        .inner_exclusive_access()
+        .alloc_fd(Arc::new(socket))
+    {
+        Some(fd) => fd as isize,
+        None => -EMFILE,
+    }
+}
+
+/// Send the `len` bytes at `buf` in a datagram from socket `fd` to `addr`.
+/// Return `len`, -EMSGSIZE if it does not fit in one packet, -EHOSTUNREACH if
+/// the next hop does not answer ARP and -ENOBUFS if the device is busy.
+pub fn sys_sendto(
+    fd: usize,
+    buf: *const u8,
+    len: usize,
+    _flags: usize,
+    addr: *const SockAddrIn,
+    addrlen: usize,
+) -> isize {
+    let file = match udp_socket_file(fd) {
+        Ok(file) => file,
+        Err(errno) => return -errno,
+    };
+    let socket = file.as_udp_socket().unwrap();
+    let sockaddr = match read_sockaddr(addr, addrlen) {
+        Ok(sockaddr) => sockaddr,
+        Err(errno) => return -errno,
+    };
+    if len > MAX_UDP_PAYLOAD {
+        return -EMSGSIZE;
+    }
+    let data: Vec<u8> = match UserBuffer::from_user(current_user_token(), buf, len, false) {
+        Some(user_buf) => user_buf
+            .buffers
+            .iter()
+            .flat_map(|b| b.iter().copied())
+            .collect(),
+        None => return -EFAULT,
+    };
+    match socket.send_to(sockaddr.addr, u16::from_be(sockaddr.port), &data) {
+        Ok(()) => len as isize,
+        Err(error) => -send_errno(error),
+    }
+}
+
+/// Block until a datagram arrives on socket `fd` and copy at most `len` bytes
+/// of it to `buf`, dropping the rest. Write the address of the sender to
+/// `addr` unless null, its length to `*addrlen`. Return the length copied,
+/// -EINTR if a signal arrives first.
+pub fn sys_recvfrom(
+    fd: usize,
+    buf: *mut u8,
+    len: usize,
+    _flags: usize,
+    addr: *mut SockAddrIn,
+    addrlen: *mut u32,
+) -> isize {
+    let file = match udp_socket_file(fd) {
+        Ok(file) => file,
+        Err(errno) => return -errno,
+    };
+    let socket = file.as_udp_socket().unwrap();
+    let errno = check_addrlen(addr, addrlen);
+    if errno != 0 {
+        return errno;
+    }
+    let user_buf = match UserBuffer::from_user(current_user_token(), buf, len, true) {
+        Some(user_buf) => user_buf,
+        None => return -EFAULT,
+    };
+    let (copied, src, src_port) = match socket.recv_from(user_buf) {
+        Some(received) => received,
+        None => return -EINTR,
+    };
+    let errno = write_sockaddr(addr, addrlen, src, src_port);
+    if errno != 0 {
+        return errno;
+    }
+    copied as isize
+}
@@ -1,17 +1,50 @@
+use super::errno::{
+    E2BIG, EACCES, EAGAIN, EBADF, EBUSY, ECHILD, EFAULT, EINTR, EINVAL, EIO, ENODEV, ENOEXEC,
+    ENOMEM, EPERM, ESRCH,
+};
+use super::thread::clone_thread;
+use crate::board::{QEMUExit, QEMU_EXIT_HANDLE};
+use crate::config::{CLOCK_FREQ, MAX_PRIORITY, MIN_PRIORITY, PAGE_SIZE, USER_STACK_SIZE};
 use crate::fs::{open_file, OpenFlags};
-use crate::mm::{translated_refmut, translated_str};
+use crate::hart::{hart_id, hart_offline, hart_online, online_hart_mask, HotplugError, ALL_HARTS};
+use crate::kcov::{kcov_switch_in, kcov_switch_out, KcovArea};
+use crate::logging::{clear_log, log_len, read_log, LOG_BUFFER_SIZE};
+use crate::mm::{
+    copy_from_user, copy_slice_to_user, copy_str_from_user, copy_to_user, LoadError, MapPermission,
+    PhysPageNum,
+};
+use crate::sbi::reboot;
 use crate::task::{
-    add_task, current_task, current_user_token, exit_current_and_run_next,
-    suspend_current_and_run_next,
+    add_task, all_processes, block_current_and_run_next, current_has_signal, current_process,
+    current_task, current_user_token, exit_current_and_run_next, exit_group_current_and_run_next,
+    pid2process, process_group, remove_from_pid2process, send_signal, suspend_current_and_run_next,
+    take_switches, CloneFlags, ProcessControlBlock, RLimit, SignalFlags, SwitchEvent,
+    TaskControlBlock, TaskStatus, COMM_LEN, CSIGNAL, MAX_NICE, MIN_NICE, RLIMIT_STACK,
+    RLIM_NLIMITS,
 };
-use crate::timer::get_time_ms;
+use crate::timer::{
+    add_timer, cancel_timer, get_time, get_time_ms, monotonic_ns, realtime_ns, start_timer,
+    ticks_to_us, timer_expire, ITimerVal, TimeSpec, TimeVal,
+};
+use alloc::boxed::Box;
+use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use easy_fs::block_cache_sync_all;
 
+/// Exit the current thread, and the process if it is the main thread
 pub fn sys_exit(exit_code: i32) -> ! {
     exit_current_and_run_next(exit_code);
     panic!("Unreachable in sys_exit!");
 }
 
+/// Exit the current process with all its threads, reporting `exit_code` to the parent
+pub fn sys_exit_group(exit_code: i32) -> ! {
+    exit_group_current_and_run_next(exit_code);
+    panic!("Unreachable in sys_exit_group!");
+}
+
 pub fn sys_yield() -> isize {
     suspend_current_and_run_next();
     0
@@ -21,70 +54,1216 @@ pub fn sys_get_time() -> isize {
     get_time_ms() as isize
 }
 
+/// The wall clock, in time since the Unix epoch
+const CLOCK_REALTIME: usize = 0;
+/// Time since boot, not affected by changes of the wall clock
+const CLOCK_MONOTONIC: usize = 1;
+
+/// Write the time of clock `clock_id` to `*tp`. Only `CLOCK_REALTIME` and
+/// `CLOCK_MONOTONIC` are supported.
+pub fn sys_clock_gettime(clock_id: usize, tp: *mut TimeSpec) -> isize {
+    let ns = match clock_id {
+        CLOCK_REALTIME => realtime_ns(),
+        CLOCK_MONOTONIC => monotonic_ns(),
+        _ => return -EINVAL,
+    };
+    match copy_to_user(current_user_token(), tp, &TimeSpec::from_nanos(ns)) {
+        Some(_) => 0,
+        None => -EFAULT,
+    }
+}
+
+/// Write the time since the Unix epoch to `*tv`. The timezone, obsolete, is ignored.
+pub fn sys_gettimeofday(tv: *mut TimeVal, _tz: usize) -> isize {
+    match copy_to_user(
+        current_user_token(),
+        tv,
+        &TimeVal::from_nanos(realtime_ns()),
+    ) {
+        Some(_) => 0,
+        None => -EFAULT,
+    }
+}
+
+/// Sleep for the interval `*req`, blocked on the timer queue. Return -EINTR
+/// if woken up early by a signal, writing the time left to `*rem` unless it
+/// is null.
+pub fn sys_nanosleep(req: *const TimeSpec, rem: *mut TimeSpec) -> isize {
+    let token = current_user_token();
+    let ticks = match copy_from_user(token, req) {
+        Some(req) => match req.to_ticks() {
+            Some(ticks) => ticks,
+            None => return -EINVAL,
+        },
+        None => return -EFAULT,
+    };
+    let expire = get_time().saturating_add(ticks);
+    // a signal wakes the task up early, as may a stale wakeup of an earlier wait
+    let mut timer = None;
+    while get_time() < expire && !current_has_signal() {
+        block_current_and_run_next(|task| {
+            if timer.is_none() {
+                timer = Some(add_timer(expire, task));
+            }
+        });
+    }
+    if let Some(timer) = timer {
+        cancel_timer(timer);
+    }
+    let left = expire.saturating_sub(get_time());
+    if left == 0 {
+        return 0;
+    }
+    if !rem.is_null() && copy_to_user(token, rem, &TimeSpec::from_ticks(left)).is_none() {
+        return -EFAULT;
+    }
+    -EINTR
+}
+
+/// The real interval timer, counting down in real time and raising `SIGALRM`
+const ITIMER_REAL: usize = 0;
+
+/// Value of the real interval timer of the current process
+fn current_itimer() -> ITimerVal {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let left = match inner.itimer.and_then(timer_expire) {
+        // an expired timer not yet fired is about to
+        Some(expire) => expire.saturating_sub(get_time()).max(1),
+        None => 0,
+    };
+    ITimerVal {
+        it_interval: TimeVal::from_ticks(inner.itimer_interval),
+        it_value: TimeVal::from_ticks(left),
+    }
+}
+
+/// Write the real interval timer of the current process to `*curr`.
+/// Only `ITIMER_REAL` is supported.
+pub fn sys_getitimer(which: usize, curr: *mut ITimerVal) -> isize {
+    if which != ITIMER_REAL {
+        return -EINVAL;
+    }
+    let itimer = current_itimer();
+    match copy_to_user(current_user_token(), curr, &itimer) {
+        Some(_) => 0,
+        None => -EFAULT,
+    }
+}
+
+/// Arm the real interval timer of the current process to raise `SIGALRM`
+/// after `new.it_value` and then every `new.it_interval`, or disarm it if
+/// `new.it_value` is 0. The old value is written to `*old` unless it is null.
+/// Only `ITIMER_REAL` is supported.
+pub fn sys_setitimer(which: usize, new: *const ITimerVal, old: *mut ITimerVal) -> isize {
+    if which != ITIMER_REAL {
+        return -EINVAL;
+    }
+    let token = current_user_token();
+    let new = match copy_from_user(token, new) {
+        Some(new) => new,
+        None => return -EFAULT,
+    };
+    let (value, interval) = match (new.it_value.to_ticks(), new.it_interval.to_ticks()) {
+        (Some(value), Some(interval)) => (value, interval),
+        _ => return -EINVAL,
+    };
+    let old_itimer = current_itimer();
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if let Some(itimer) = inner.itimer.take() {
+        cancel_timer(itimer);
+    }
+    inner.itimer_interval = interval;
+    if value != 0 {
+        let weak = Arc::downgrade(&process);
+        inner.itimer = Some(start_timer(
+            get_time().saturating_add(value),
+            interval,
+            Box::new(move || {
+                if let Some(process) = weak.upgrade() {
+                    send_signal(&process, SignalFlags::SIGALRM.lowest_signum().unwrap());
+                }
+            }),
+        ));
+    }
+    drop(inner);
+    if !old.is_null() && copy_to_user(token, old, &old_itimer).is_none() {
+        return -EFAULT;
+    }
+    0
+}
+
 pub fn sys_getpid() -> isize {
-    current_task().unwrap().pid.0 as isize
+    current_process().pid.0 as isize
+}
+
+/// Return the pid of the parent, 0 for processes without one like initproc
+pub fn sys_getppid() -> isize {
+    current_process().getppid() as isize
+}
+
+/// The current process if `pid` is 0, otherwise the process `pid`
+fn pid_or_current(pid: usize) -> Option<Arc<ProcessControlBlock>> {
+    match pid {
+        0 => Some(current_process()),
+        pid => pid2process(pid),
+    }
+}
+
+/// Return the process group of process `pid`, of the current process if it is 0
+pub fn sys_getpgid(pid: usize) -> isize {
+    match pid_or_current(pid) {
+        Some(process) => process.inner_exclusive_access().pgid as isize,
+        None => -ESRCH,
+    }
 }
 
-pub fn sys_fork() -> isize {
-    let current_task = current_task().unwrap();
-    let new_task = current_task.fork();
-    let new_pid = new_task.pid.0;
+/// Move process `pid`, the current process if it is 0, to process group `pgid`,
+/// a new group led by it if `pgid` is 0 or its pid. Only the current process and
+/// its children can be moved, within their session and not if they lead it.
+pub fn sys_setpgid(pid: usize, pgid: usize) -> isize {
+    let current = current_process();
+    let process = match pid {
+        0 => Arc::clone(&current),
+        pid if pid == current.getpid() => Arc::clone(&current),
+        pid => match pid2process(pid) {
+            Some(child) if child.getppid() == current.getpid() => child,
+            _ => return -ESRCH,
+        },
+    };
+    let pgid = match pgid {
+        0 => process.getpid(),
+        pgid => pgid,
+    };
+    let sid = current.inner_exclusive_access().sid;
+    {
+        let inner = process.inner_exclusive_access();
+        if inner.sid != sid || inner.sid == process.getpid() {
+            return -EPERM;
+        }
+    }
+    // joining an existing group, which must be in the same session
+    if pgid != process.getpid()
+        && !process_group(pgid)
+            .iter()
+            .any(|member| member.inner_exclusive_access().sid == sid)
+    {
+        return -EPERM;
+    }
+    process.inner_exclusive_access().pgid = pgid;
+    0
+}
+
+/// Return the session of process `pid`, of the current process if it is 0
+pub fn sys_getsid(pid: usize) -> isize {
+    match pid_or_current(pid) {
+        Some(process) => process.inner_exclusive_access().sid as isize,
+        None => -ESRCH,
+    }
+}
+
+/// Make the current process the leader of a new session and of a new process group
+/// in it, and return the session. A process group leader cannot, as the members of
+/// its group would be left in another session.
+pub fn sys_setsid() -> isize {
+    let process = current_process();
+    let pid = process.getpid();
+    if !process_group(pid).is_empty() {
+        return -EPERM;
+    }
+    let mut inner = process.inner_exclusive_access();
+    inner.pgid = pid;
+    inner.sid = pid;
+    pid as isize
+}
+
+/// Create a child running a copy of the calling thread, on `stack` unless it is 0.
+/// With `CLONE_THREAD` the child is a thread of the current process and its tid is
+/// returned, otherwise it is a process sharing the resources in `flags` and its pid
+/// is returned. A process that does not share the address space can only be created
+/// by a process with a single thread left. The parent is notified of the exit of the
+/// child with `SIGCHLD` whatever the signal in the low byte of `flags`.
+pub fn sys_clone(flags: usize, stack: usize) -> isize {
+    let flags = match u32::try_from(flags)
+        .ok()
+        .and_then(|flags| CloneFlags::from_bits(flags & !CSIGNAL))
+    {
+        Some(flags) => flags,
+        None => return -EINVAL,
+    };
+    if flags.contains(CloneFlags::CLONE_THREAD) {
+        // the threads of a process share all its resources
+        if !flags.contains(CloneFlags::CLONE_VM | CloneFlags::CLONE_FILES) {
+            return -EINVAL;
+        }
+        return clone_thread(stack);
+    }
+    let current_process = current_process();
+    if !flags.contains(CloneFlags::CLONE_VM)
+        && current_process.inner_exclusive_access().thread_count() > 1
+    {
+        return -1;
+    }
+    let new_process = match current_process.clone_process(flags) {
+        Some(new_process) => new_process,
+        None => return -ENOMEM,
+    };
+    let new_pid = new_process.pid.0;
+    let new_task = new_process.inner_exclusive_access().get_task(0);
     // modify trap context of new_task, because it returns immediately after switching
     let trap_cx = new_task.inner_exclusive_access().get_trap_cx();
     // we do not have to move to next instruction since we have done it before
-    // for child process, fork returns 0
+    // for child process, clone returns 0
     trap_cx.x[10] = 0;
+    if stack != 0 {
+        trap_cx.set_sp(stack);
+    }
     // add new task to scheduler
     add_task(new_task);
     new_pid as isize
 }
 
-pub fn sys_exec(path: *const u8) -> isize {
+/// Bytes of the arguments and environment passed to a program, including the pointers
+const ARG_MAX: usize = USER_STACK_SIZE / 4;
+
+/// Copy the strings of the array of string pointers at `ptrs` ending with a null pointer,
+/// empty if `ptrs` is null. Fail with -EFAULT if they are not accessible, and with
+/// -E2BIG if `*size` would grow beyond `ARG_MAX` counting each string and pointer.
+fn copy_str_array_from_user(
+    token: usize,
+    ptrs: *const usize,
+    size: &mut usize,
+) -> Result<Vec<String>, isize> {
+    let mut strings = Vec::new();
+    if ptrs.is_null() {
+        return Ok(strings);
+    }
+    loop {
+        let ptr_addr = ptrs as usize + strings.len() * size_of::<usize>();
+        let ptr = copy_from_user(token, ptr_addr as *const usize).ok_or(-EFAULT)?;
+        *size += size_of::<usize>();
+        if *size > ARG_MAX {
+            return Err(-E2BIG);
+        }
+        if ptr == 0 {
+            return Ok(strings);
+        }
+        let string = copy_str_from_user(token, ptr as *const u8).ok_or(-EFAULT)?;
+        *size += string.len() + 1;
+        strings.push(string);
+    }
+}
+
+/// Copy the path, arguments and environment of a program to run, see [`sys_exec`].
+/// The environment of the current process is taken if `envp` is null.
+fn copy_program_args(
+    path: *const u8,
+    argv: *const usize,
+    envp: *const usize,
+) -> Result<(String, Vec<String>, Vec<String>), isize> {
     let token = current_user_token();
-    let path = translated_str(token, path);
+    let path = copy_str_from_user(token, path).ok_or(-EFAULT)?;
+    // argc
+    let mut size = size_of::<usize>();
+    let argv = copy_str_array_from_user(token, argv, &mut size)?;
+    let envp = if envp.is_null() {
+        let environ = current_process().inner_exclusive_access().environ.clone();
+        size += environ
+            .iter()
+            .map(|var| size_of::<usize>() + var.len() + 1)
+            .sum::<usize>()
+            + size_of::<usize>();
+        if size > ARG_MAX {
+            return Err(-E2BIG);
+        }
+        environ
+    } else {
+        copy_str_array_from_user(token, envp, &mut size)?
+    };
+    Ok((path, argv, envp))
+}
+
+/// Errno of a program that could not be loaded
+fn load_errno(err: LoadError) -> isize {
+    match err {
+        LoadError::OutOfMemory => -ENOMEM,
+        LoadError::WritableExecutable => -ENOEXEC,
+    }
+}
+
+/// Replace the program of the current process, which must have a single thread left,
+/// passing it the arguments `argv` and the environment `envp`, arrays of string pointers
+/// ending with a null pointer. The environment of the process is passed if `envp` is
+/// null. Return argc, which the program finds in a0.
+pub fn sys_exec(path: *const u8, argv: *const usize, envp: *const usize) -> isize {
+    let (path, argv, envp) = match copy_program_args(path, argv, envp) {
+        Ok(args) => args,
+        Err(errno) => return errno,
+    };
+    let process = current_process();
+    if process.inner_exclusive_access().thread_count() > 1 {
+        return -1;
+    }
     if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
         let all_data = app_inode.read_all();
-        let task = current_task().unwrap();
-        task.exec(all_data.as_slice());
-        0
+        match process.exec(all_data.as_slice(), &argv, envp) {
+            Ok(()) => {
+                // named after the program
+                let name = path.rsplit('/').next().unwrap();
+                current_task()
+                    .unwrap()
+                    .inner_exclusive_access()
+                    .set_comm(name.as_bytes());
+                argv.len() as isize
+            }
+            Err(err) => load_errno(err),
+        }
     } else {
         -1
     }
 }
 
-/// If there is not a child process whose pid is same as given, return -1.
-/// Else if there is a child process but it is still running, return -2.
-pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
-    let task = current_task().unwrap();
-    // find a child process
+/// Create a child process running the program at `path` with the arguments `argv` and
+/// the environment `envp`, as [`sys_clone`] followed by [`sys_exec`] in the child would,
+/// but without copying the address space. Return the pid of the child.
+pub fn sys_spawn(path: *const u8, argv: *const usize, envp: *const usize) -> isize {
+    let (path, argv, envp) = match copy_program_args(path, argv, envp) {
+        Ok(args) => args,
+        Err(errno) => return errno,
+    };
+    let app_inode = match open_file(path.as_str(), OpenFlags::RDONLY) {
+        Some(app_inode) => app_inode,
+        None => return -1,
+    };
+    let all_data = app_inode.read_all();
+    // named after the program
+    let name = path.rsplit('/').next().unwrap();
+    match current_process().spawn(name, all_data.as_slice(), &argv, envp) {
+        Ok(child) => child.getpid() as isize,
+        Err(err) => load_errno(err),
+    }
+}
+
+/// Options of [`sys_prctl`], with the values of Linux
+const PR_SET_PDEATHSIG: usize = 1;
+const PR_GET_PDEATHSIG: usize = 2;
+const PR_SET_NAME: usize = 15;
+const PR_GET_NAME: usize = 16;
+/// not in Linux, which has capabilities instead
+const PR_SET_CHILD_MACHINE_CONTROL: usize = 0x1000;
+
+/// Operations on the current process and thread:
+/// - `PR_SET_PDEATHSIG`: send signal `arg` to the process when its parent exits, none if 0
+/// - `PR_GET_PDEATHSIG`: write that signal as an `i32` to `*arg`
+/// - `PR_SET_NAME`: name the thread after the string at `arg`, truncated to 15 bytes
+/// - `PR_GET_NAME`: write the name of the thread to `*arg`, 16 bytes padded with `\0`
+/// - `PR_SET_CHILD_MACHINE_CONTROL`: let the children created from now on control the
+///   machine (see `controls_machine`) if `arg` is not 0, or stop letting them. -EPERM
+///   unless the caller controls the machine itself.
+pub fn sys_prctl(option: usize, arg: usize) -> isize {
+    let token = current_user_token();
+    match option {
+        PR_SET_PDEATHSIG => {
+            if arg != 0 && SignalFlags::from_signum(arg).is_none() {
+                return -EINVAL;
+            }
+            current_process().inner_exclusive_access().pdeathsig = arg;
+        }
+        PR_GET_PDEATHSIG => {
+            let pdeathsig = current_process().inner_exclusive_access().pdeathsig as i32;
+            if copy_to_user(token, arg as *mut i32, &pdeathsig).is_none() {
+                return -EFAULT;
+            }
+        }
+        PR_SET_NAME => match copy_str_from_user(token, arg as *const u8) {
+            Some(name) => current_task()
+                .unwrap()
+                .inner_exclusive_access()
+                .set_comm(name.as_bytes()),
+            None => return -EFAULT,
+        },
+        PR_GET_NAME => {
+            let comm = current_task().unwrap().inner_exclusive_access().comm;
+            if copy_to_user(token, arg as *mut [u8; COMM_LEN], &comm).is_none() {
+                return -EFAULT;
+            }
+        }
+        PR_SET_CHILD_MACHINE_CONTROL => {
+            let process = current_process();
+            let mut inner = process.inner_exclusive_access();
+            if !inner.machine_control {
+                return -EPERM;
+            }
+            inner.grant_machine_control = arg != 0;
+        }
+        _ => return -EINVAL,
+    }
+    0
+}
+
+/// Do not block in `sys_waitpid` if no child has exited
+pub const WNOHANG: u32 = 1;
+
+/// Wait for a child process to exit and reap it, any child if `pid` is -1, any child
+/// in the process group of the caller if `pid` is 0 and in process group `-pid` if
+/// `pid` is less than -1. Its status word (see `wait_status`) is written to `*status` unless it is null.
+/// Return the pid of the child, -ECHILD if there is no such child, and 0 if
+/// none of them has exited yet and `options` has `WNOHANG`, blocking otherwise
+/// until one exits or a signal arrives (-EINTR).
+/// A traced child stopping for the caller is reported once, without reaping it,
+/// with the status word `0x7f | signum << 8` of the signal stopping it, as in Linux.
+pub fn sys_waitpid(pid: isize, status: *mut i32, options: u32) -> isize {
+    let process = current_process();
+    loop {
+        // ---- access current PCB exclusively
+        let mut inner = process.inner_exclusive_access();
+        let pgid = inner.pgid;
+        let wanted = |child_pid: usize, child_pgid: usize| match pid {
+            -1 => true,
+            0 => child_pgid == pgid,
+            pid if pid > 0 => child_pid == pid as usize,
+            pid => child_pgid == pid.unsigned_abs(),
+        };
+        // a single pass over the children, which may be many adopted orphans for initproc,
+        // or only over a child looked up by pid
+        let candidates = if pid > 0 {
+            match pid2process(pid as usize)
+                .and_then(|child| inner.children.iter().position(|p| Arc::ptr_eq(p, &child)))
+            {
+                Some(idx) => idx..idx + 1,
+                None => return -ECHILD,
+            }
+        } else {
+            0..inner.children.len()
+        };
+        let mut found = false;
+        let mut zombie = None;
+        let mut stopped = None;
+        for idx in candidates {
+            let p = &inner.children[idx];
+            // ++++ temporarily access child PCB exclusively
+            let mut child_inner = p.inner_exclusive_access();
+            if wanted(p.getpid(), child_inner.pgid) {
+                found = true;
+                if let Some(stop) = child_inner.ptrace.stopped() {
+                    if !stop.reported && stopped.is_none() {
+                        stopped = Some((idx, stop.signum));
+                    }
+                }
+                if child_inner.is_zombie {
+                    let (utime, stime) = child_inner.cpu_times();
+                    zombie = Some((
+                        idx,
+                        child_inner.wait_status(),
+                        utime + child_inner.cutime,
+                        stime + child_inner.cstime,
+                    ));
+                    break;
+                }
+            }
+            // ++++ release child PCB
+        }
+        if !found {
+            return -ECHILD;
+            // ---- release current PCB
+        }
+        if let Some((idx, wait_status, utime, stime)) = zombie {
+            // keep the child if its status cannot be reported
+            if !status.is_null()
+                && copy_to_user(inner.get_user_token(), status, &wait_status).is_none()
+            {
+                return -EFAULT;
+            }
+            // the child may still be releasing its resources on another hart,
+            // it is deallocated when that hart drops it
+            let child = inner.children.swap_remove(idx);
+            remove_from_pid2process(child.getpid());
+            inner.cutime += utime;
+            inner.cstime += stime;
+            return child.getpid() as isize;
+        }
+        if let Some((idx, signum)) = stopped {
+            let stop_status = 0x7f | (signum as i32) << 8;
+            if !status.is_null()
+                && copy_to_user(inner.get_user_token(), status, &stop_status).is_none()
+            {
+                return -EFAULT;
+            }
+            let child = &inner.children[idx];
+            if let Some(stop) = child.inner_exclusive_access().ptrace.stopped() {
+                stop.reported = true;
+            }
+            return child.getpid() as isize;
+        }
+        if options & WNOHANG != 0 {
+            return 0;
+        }
+        drop(inner);
+        // ---- release current PCB
+        if current_has_signal() {
+            return -EINTR;
+        }
+        suspend_current_and_run_next();
+    }
+}
+
+/// Set the priority of the current thread, return it or -1 if out of range
+pub fn sys_set_priority(prio: isize) -> isize {
+    if prio < MIN_PRIORITY as isize || prio > MAX_PRIORITY as isize {
+        return -1;
+    }
+    current_task().unwrap().inner_exclusive_access().priority = prio as usize;
+    prio
+}
+
+/// `which` of [`sys_getpriority`] and [`sys_setpriority`]: `who` is a pid
+const PRIO_PROCESS: usize = 0;
+/// `which` of [`sys_getpriority`] and [`sys_setpriority`]: `who` is a process group
+const PRIO_PGRP: usize = 1;
+
+/// Processes selected by `which` and `who`, the current process or its process
+/// group if `who` is 0. Fail with -EINVAL for an unknown `which` and -ESRCH if
+/// there is no such process.
+fn priority_targets(which: usize, who: usize) -> Result<Vec<Arc<ProcessControlBlock>>, isize> {
+    let processes = match which {
+        PRIO_PROCESS => pid_or_current(who).into_iter().collect(),
+        PRIO_PGRP => {
+            let pgid = match who {
+                0 => current_process().inner_exclusive_access().pgid,
+                pgid => pgid,
+            };
+            process_group(pgid)
+        }
+        _ => return Err(-EINVAL),
+    };
+    if processes.is_empty() {
+        Err(-ESRCH)
+    } else {
+        Ok(processes)
+    }
+}
+
+/// Lowest niceness of the threads of `process`, `None` if it has released them
+fn process_nice(process: &ProcessControlBlock) -> Option<i32> {
+    process
+        .inner_exclusive_access()
+        .tasks
+        .iter()
+        .flatten()
+        .map(|task| task.inner_exclusive_access().nice)
+        .min()
+}
+
+/// Return the lowest niceness of the processes selected by `which` and `who` (see
+/// `priority_targets`) as 20 minus it, in [1, 40], so that it is not taken for an error
+pub fn sys_getpriority(which: usize, who: usize) -> isize {
+    let processes = match priority_targets(which, who) {
+        Ok(processes) => processes,
+        Err(errno) => return errno,
+    };
+    match processes.iter().filter_map(|p| process_nice(p)).min() {
+        Some(nice) => 20 - nice as isize,
+        None => -ESRCH,
+    }
+}
 
-    // ---- access current PCB exclusively
-    let mut inner = task.inner_exclusive_access();
-    if !inner
-        .children
+/// Set the niceness of the threads of the processes selected by `which` and `who` (see
+/// `priority_targets`) to `nice`, clamped to [-20, 19]. It maps onto the weight of a
+/// thread in CFS and its priority in the other policies. Without users to tell who may
+/// do more, niceness can only be raised, i.e. priority only lowered (-EACCES).
+pub fn sys_setpriority(which: usize, who: usize, nice: isize) -> isize {
+    let processes = match priority_targets(which, who) {
+        Ok(processes) => processes,
+        Err(errno) => return errno,
+    };
+    let nice = nice.clamp(MIN_NICE as isize, MAX_NICE as isize) as i32;
+    if processes
         .iter()
-        .any(|p| pid == -1 || pid as usize == p.getpid())
+        .filter_map(|p| process_nice(p))
+        .any(|old_nice| nice < old_nice)
     {
+        return -EACCES;
+    }
+    for process in processes.iter() {
+        for task in process.inner_exclusive_access().tasks.iter().flatten() {
+            task.inner_exclusive_access().set_nice(nice);
+        }
+    }
+    0
+}
+
+/// The current thread if `pid` is 0, otherwise the main thread of process `pid`
+fn pid_to_task(pid: usize) -> Option<Arc<TaskControlBlock>> {
+    match pid {
+        0 => current_task(),
+        // the process may be exiting and have released its threads
+        pid => pid2process(pid)?
+            .inner_exclusive_access()
+            .tasks
+            .first()
+            .cloned()
+            .flatten(),
+    }
+}
+
+/// Restrict the thread `pid` (see `pid_to_task`) to the harts in the mask at `mask`,
+/// `len` bytes long, bit i for hart i. The mask must have an online hart. The thread
+/// moves to an allowed hart the next time it is put back to a ready queue, right away
+/// for the current thread.
+pub fn sys_sched_setaffinity(pid: usize, len: usize, mask: *const usize) -> isize {
+    if len < size_of::<usize>() {
+        return -EINVAL;
+    }
+    let mask = match copy_from_user(current_user_token(), mask) {
+        Some(mask) => mask & ALL_HARTS,
+        None => return -EFAULT,
+    };
+    if mask & online_hart_mask() == 0 {
+        return -EINVAL;
+    }
+    let task = match pid_to_task(pid) {
+        Some(task) => task,
+        None => return -ESRCH,
+    };
+    task.inner_exclusive_access().cpus_allowed = mask;
+    if pid == 0 && mask & (1 << hart_id()) == 0 {
+        drop(task);
+        suspend_current_and_run_next();
+    }
+    0
+}
+
+/// Write the mask of the harts the thread `pid` (see `pid_to_task`) may run on to
+/// `*mask`, `len` bytes long. Return the size of the mask written.
+pub fn sys_sched_getaffinity(pid: usize, len: usize, mask: *mut usize) -> isize {
+    if len < size_of::<usize>() {
+        return -EINVAL;
+    }
+    let cpus_allowed = match pid_to_task(pid) {
+        Some(task) => task.inner_exclusive_access().cpus_allowed,
+        None => return -ESRCH,
+    };
+    match copy_to_user(current_user_token(), mask, &cpus_allowed) {
+        Some(_) => size_of::<usize>() as isize,
+        None => -EFAULT,
+    }
+}
+
+/// Write the hart running the current thread to `*cpu` and the NUMA node, always 0,
+/// to `*node`, unless they are null
+pub fn sys_getcpu(cpu: *mut u32, node: *mut u32) -> isize {
+    let token = current_user_token();
+    if !cpu.is_null() && copy_to_user(token, cpu, &(hart_id() as u32)).is_none() {
+        return -EFAULT;
+    }
+    if !node.is_null() && copy_to_user(token, node, &0u32).is_none() {
+        return -EFAULT;
+    }
+    0
+}
+
+/// change data segment size
+pub fn sys_sbrk(size: i32) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if size > 0 && inner.exceeds_as_limit(size as usize) {
+        return -ENOMEM;
+    }
+    let old_brk = inner
+        .address_space
+        .exclusive_access()
+        .change_program_brk(size);
+    if let Some(old_brk) = old_brk {
+        old_brk as isize
+    } else {
+        -1
+    }
+}
+
+/// `flags` of [`sys_mmap`]: stores go to the file, only for file mappings
+const MAP_SHARED: usize = 0x01;
+/// `flags` of [`sys_mmap`]: stores are private, only for anonymous mappings
+const MAP_PRIVATE: usize = 0x02;
+/// `flags` of [`sys_mmap`]: anonymous memory, `fd` and `offset` are ignored
+const MAP_ANONYMOUS: usize = 0x20;
+
+/// Map `len` bytes with permission `prot` (bit 0: R, bit 1: W, bit 2: X), of
+/// anonymous memory with `MAP_ANONYMOUS`, or of file `fd` from `offset` with
+/// `MAP_SHARED`, only for a framebuffer. The kernel chooses the address if
+/// `start` is 0. Return the start address, -EBADF if `fd` is not open,
+/// -ENODEV if the file cannot be mapped, -EINVAL for a range out of it,
+/// -EACCES for memory both writable and executable, or -1.
+pub fn sys_mmap(
+    start: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    if len == 0 || prot & !0x7 != 0 || prot & 0x7 == 0 {
         return -1;
-        // ---- release current PCB
     }
-    let pair = inner.children.iter().enumerate().find(|(_, p)| {
-        // ++++ temporarily access child PCB exclusively
-        p.inner_exclusive_access().is_zombie() && (pid == -1 || pid as usize == p.getpid())
-        // ++++ release child PCB
-    });
-    if let Some((idx, _)) = pair {
-        let child = inner.children.remove(idx);
-        // confirm that child will be deallocated after being removed from children list
-        assert_eq!(Arc::strong_count(&child), 1);
-        let found_pid = child.getpid();
-        // ++++ temporarily access child PCB exclusively
-        let exit_code = child.inner_exclusive_access().exit_code;
-        // ++++ release child PCB
-        *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
-        found_pid as isize
+    let permission = MapPermission::from_bits((prot << 1) as u8).unwrap();
+    if permission.contains(MapPermission::W | MapPermission::X) {
+        return -EACCES;
+    }
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if inner.exceeds_as_limit(len) {
+        return -ENOMEM;
+    }
+    let start = if flags & MAP_ANONYMOUS != 0 {
+        inner
+            .address_space
+            .exclusive_access()
+            .memory_set
+            .mmap(start, len, permission)
+    } else {
+        if flags & (MAP_SHARED | MAP_PRIVATE) != MAP_SHARED {
+            return -EINVAL;
+        }
+        let file = match inner.get_file(fd) {
+            Some(file) => file,
+            None => return -EBADF,
+        };
+        let (first, pages) = match file.as_framebuffer() {
+            Some(framebuffer) => framebuffer.frames(),
+            None => return -ENODEV,
+        };
+        if offset % PAGE_SIZE != 0
+            || offset
+                .checked_add(len)
+                .map_or(true, |end| end > pages * PAGE_SIZE)
+        {
+            return -EINVAL;
+        }
+        inner
+            .address_space
+            .exclusive_access()
+            .memory_set
+            .mmap_frames(
+                start,
+                len,
+                permission,
+                PhysPageNum(first.0 + offset / PAGE_SIZE),
+            )
+    };
+    match start {
+        Some(start) => start as isize,
+        None => -1,
+    }
+}
+
+/// Unmap the pages created by mmap in `[start, start + len)`, possibly part of an area.
+/// Return 0, -EINVAL if the range is empty, unaligned or outside the mmap region.
+pub fn sys_munmap(start: usize, len: usize) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let unmapped = inner
+        .address_space
+        .exclusive_access()
+        .memory_set
+        .munmap(start, len);
+    if unmapped {
+        0
     } else {
-        -2
+        -EINVAL
     }
-    // ---- release current PCB automatically
+}
+
+/// `who` of [`sys_getrusage`]: the calling process
+const RUSAGE_SELF: isize = 0;
+/// `who` of [`sys_getrusage`]: the reaped children of the calling process
+const RUSAGE_CHILDREN: isize = -1;
+/// `who` of [`sys_getrusage`]: the calling thread
+const RUSAGE_THREAD: isize = 1;
+
+/// Resource usage of a process, a simplified `struct rusage`. Sizes are in kilobytes.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct RUsage {
+    /// time spent in user mode
+    pub ru_utime: TimeVal,
+    /// time spent in the kernel
+    pub ru_stime: TimeVal,
+    /// peak resident set size
+    pub ru_maxrss: usize,
+    /// current resident set size
+    pub ru_rss: usize,
+    /// size of the frames backing the heap grown by sbrk
+    pub ru_heap: usize,
+    /// size of the frames backing the areas created by mmap
+    pub ru_mmap: usize,
+}
+
+/// Get resource usage of the current process, of its reaped children or of the current
+/// thread. Only the CPU times are reported for the children. The threads share the
+/// memory of the process.
+pub fn sys_getrusage(who: isize, usage: *mut RUsage) -> isize {
+    if who != RUSAGE_SELF && who != RUSAGE_CHILDREN && who != RUSAGE_THREAD {
+        return -EINVAL;
+    }
+    let task = current_task().unwrap();
+    let mut task_inner = task.inner_exclusive_access();
+    // count the time in this syscall so far
+    task_inner.charge_time(false);
+    let thread_times = (task_inner.utime, task_inner.stime);
+    drop(task_inner);
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let (utime, stime) = match who {
+        RUSAGE_SELF => inner.cpu_times(),
+        RUSAGE_CHILDREN => (inner.cutime, inner.cstime),
+        _ => thread_times,
+    };
+    let mut rusage = RUsage {
+        ru_utime: TimeVal::from_ticks(utime),
+        ru_stime: TimeVal::from_ticks(stime),
+        ..Default::default()
+    };
+    let space = inner.address_space.exclusive_access();
+    if who != RUSAGE_CHILDREN {
+        let mem = space.memory_set.usage();
+        rusage.ru_maxrss = mem.peak_resident_pages * PAGE_SIZE / 1024;
+        rusage.ru_rss = mem.resident_pages * PAGE_SIZE / 1024;
+        rusage.ru_heap = mem.heap_pages * PAGE_SIZE / 1024;
+        rusage.ru_mmap = mem.mmap_pages * PAGE_SIZE / 1024;
+    }
+    if copy_to_user(space.memory_set.token(), usage, &rusage).is_none() {
+        return -EFAULT;
+    }
+    0
+}
+
+/// Write the soft and hard limits on `resource`, one of `RLIMIT_*`, to `*rlim`
+pub fn sys_getrlimit(resource: usize, rlim: *mut RLimit) -> isize {
+    if resource >= RLIM_NLIMITS {
+        return -EINVAL;
+    }
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let limit = inner.rlimits[resource];
+    if copy_to_user(inner.get_user_token(), rlim, &limit).is_none() {
+        return -EFAULT;
+    }
+    0
+}
+
+/// Set the limits on `resource` to `*rlim`. The soft limit must not exceed the hard
+/// limit, which cannot be raised (-EPERM). The user stacks have a fixed size, so
+/// `RLIMIT_STACK` cannot be changed. The limits of the resources other than
+/// `RLIMIT_CPU`, `RLIMIT_NOFILE` and `RLIMIT_AS` are recorded but not enforced.
+pub fn sys_setrlimit(resource: usize, rlim: *const RLimit) -> isize {
+    let limit = match copy_from_user(current_user_token(), rlim) {
+        Some(limit) => limit,
+        None => return -EFAULT,
+    };
+    if resource >= RLIM_NLIMITS || limit.rlim_cur > limit.rlim_max {
+        return -EINVAL;
+    }
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let old = inner.rlimits[resource];
+    if limit.rlim_max > old.rlim_max {
+        return -EPERM;
+    }
+    if resource == RLIMIT_STACK
+        && (limit.rlim_cur != old.rlim_cur || limit.rlim_max != old.rlim_max)
+    {
+        return -EINVAL;
+    }
+    inner.rlimits[resource] = limit;
+    0
+}
+
+/// Unit of the times reported by [`sys_times`] per second
+const CLK_TCK: usize = 100;
+
+/// CPU times of a process and of its reaped children in `1 / CLK_TCK` seconds, as in POSIX
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Tms {
+    /// time spent in user mode
+    pub tms_utime: usize,
+    /// time spent in the kernel
+    pub tms_stime: usize,
+    /// time spent in user mode by the reaped children
+    pub tms_cutime: usize,
+    /// time spent in the kernel by the reaped children
+    pub tms_cstime: usize,
+}
+
+/// Write the CPU times of the current process and of its reaped children to `*tms`
+/// unless it is null, and return the time since boot, all in `1 / CLK_TCK` seconds
+pub fn sys_times(tms: *mut Tms) -> isize {
+    let to_clock = |ticks: usize| ticks / (CLOCK_FREQ / CLK_TCK);
+    if !tms.is_null() {
+        // count the time in this syscall so far
+        current_task()
+            .unwrap()
+            .inner_exclusive_access()
+            .charge_time(false);
+        let process = current_process();
+        let inner = process.inner_exclusive_access();
+        let (utime, stime) = inner.cpu_times();
+        let times = Tms {
+            tms_utime: to_clock(utime),
+            tms_stime: to_clock(stime),
+            tms_cutime: to_clock(inner.cutime),
+            tms_cstime: to_clock(inner.cstime),
+        };
+        if copy_to_user(inner.get_user_token(), tms, &times).is_none() {
+            return -EFAULT;
+        }
+    }
+    to_clock(get_time()) as isize
+}
+
+/// Action of [`sys_syslog`]: read the kernel messages
+const SYSLOG_ACTION_READ_ALL: usize = 3;
+/// Action of [`sys_syslog`]: read the kernel messages, then clear them
+const SYSLOG_ACTION_READ_CLEAR: usize = 4;
+/// Action of [`sys_syslog`]: clear the kernel messages
+const SYSLOG_ACTION_CLEAR: usize = 5;
+/// Action of [`sys_syslog`]: number of bytes of the kernel messages
+const SYSLOG_ACTION_SIZE_UNREAD: usize = 9;
+/// Action of [`sys_syslog`]: size of the ring buffer of the kernel messages
+const SYSLOG_ACTION_SIZE_BUFFER: usize = 10;
+
+/// Do `action` on the ring buffer of kernel messages. Reading copies the
+/// newest `len` bytes printed since it was last cleared to `buf` and returns
+/// their number.
+pub fn sys_syslog(action: usize, buf: *mut u8, len: usize) -> isize {
+    match action {
+        SYSLOG_ACTION_READ_ALL | SYSLOG_ACTION_READ_CLEAR => {
+            if (len as isize) < 0 {
+                return -EINVAL;
+            }
+            let log = read_log(action == SYSLOG_ACTION_READ_CLEAR);
+            let newest = &log[log.len().saturating_sub(len)..];
+            if copy_slice_to_user(current_user_token(), buf, newest).is_none() {
+                return -EFAULT;
+            }
+            newest.len() as isize
+        }
+        SYSLOG_ACTION_CLEAR => {
+            clear_log();
+            0
+        }
+        SYSLOG_ACTION_SIZE_UNREAD => log_len() as isize,
+        SYSLOG_ACTION_SIZE_BUFFER => LOG_BUFFER_SIZE as isize,
+        _ => -EINVAL,
+    }
+}
+
+/// A thread as listed by [`sys_task_info`]
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct TaskInfo {
+    pub pid: usize,
+    /// 0 for kernel threads, which are the only thread of their process
+    pub tid: usize,
+    /// 0 if the process has no parent
+    pub ppid: usize,
+    pub priority: usize,
+    pub nice: isize,
+    /// hart the thread runs or last ran on
+    pub cpu: usize,
+    /// resident set size of the process in kilobytes
+    pub rss: usize,
+    /// `b'R'` if running or ready to run, `b'S'` if blocked
+    pub state: u8,
+    /// name of the thread padded with `\0`
+    pub comm: [u8; COMM_LEN],
+    /// number of times the thread switched out because it blocked or yielded
+    pub nvcsw: usize,
+    /// number of times the thread was preempted at the end of its time slice
+    pub nivcsw: usize,
+    /// time in microseconds spent running, in user mode or in the kernel
+    pub run_us: usize,
+    /// time in microseconds spent ready to run but waiting for a hart
+    pub wait_us: usize,
+}
+
+/// Write the threads that have not exited, in the order of their pids and tids, to the
+/// array of `count` entries at `buf`, and return the number of threads, which may be more
+/// than `count`.
+pub fn sys_task_info(buf: *mut TaskInfo, count: usize) -> isize {
+    let mut infos = Vec::new();
+    for process in all_processes() {
+        let ppid = process.getppid();
+        let inner = process.inner_exclusive_access();
+        let rss = inner
+            .address_space
+            .exclusive_access()
+            .memory_set
+            .usage()
+            .resident_pages
+            * PAGE_SIZE
+            / 1024;
+        let tasks: Vec<_> = inner.tasks.iter().flatten().cloned().collect();
+        drop(inner);
+        for task in tasks {
+            let task_inner = task.inner_exclusive_access();
+            let state = match task_inner.task_status {
+                TaskStatus::Ready | TaskStatus::Running => b'R',
+                TaskStatus::Blocked => b'S',
+                TaskStatus::Exited => continue,
+            };
+            infos.push(TaskInfo {
+                pid: process.getpid(),
+                tid: task_inner.res.as_ref().map_or(0, |res| res.tid),
+                ppid,
+                priority: task_inner.effective_priority(),
+                nice: task_inner.nice as isize,
+                cpu: task_inner.cpu,
+                rss,
+                state,
+                comm: task_inner.comm,
+                nvcsw: task_inner.nvcsw,
+                nivcsw: task_inner.nivcsw,
+                run_us: ticks_to_us(task_inner.run_time),
+                wait_us: ticks_to_us(task_inner.wait_time),
+            });
+        }
+    }
+    let len = infos.len().min(count);
+    if copy_slice_to_user(current_user_token(), buf, &infos[..len]).is_none() {
+        return -EFAULT;
+    }
+    infos.len() as isize
+}
+
+/// Move up to `count` of the oldest context switches traced on all harts to the array
+/// at `buf`, in the order of their time, and return their number. Each hart keeps
+/// its latest `SCHED_TRACE_LEN` switches, older ones are lost unless taken in time.
+pub fn sys_sched_trace(buf: *mut SwitchEvent, count: usize) -> isize {
+    let token = current_user_token();
+    let events = take_switches(count);
+    if copy_slice_to_user(token, buf, &events).is_none() {
+        return -EFAULT;
+    }
+    events.len() as isize
+}
+
+/// `cmd` of `sys_reboot`: reboot the machine
+const LINUX_REBOOT_CMD_RESTART: usize = 0x0123_4567;
+/// `cmd` of `sys_reboot`: power the machine off
+const LINUX_REBOOT_CMD_POWER_OFF: usize = 0x4321_fedc;
+
+/// Whether the current process may control the machine: the init program and the
+/// children it lets with `PR_SET_CHILD_MACHINE_CONTROL`, such as the shell. It is
+/// recorded when a process is created, so orphans adopted by the init program do not.
+fn controls_machine() -> bool {
+    current_process().inner_exclusive_access().machine_control
+}
+
+/// Reboot the machine with `LINUX_REBOOT_CMD_RESTART`, or power it off with
+/// `LINUX_REBOOT_CMD_POWER_OFF`, QEMU exiting with `code` modulo 256, once the
+/// block cache is written back. Return -EPERM unless the caller controls the
+/// machine (see `controls_machine`), -EINVAL for an unknown `cmd` and -ENODEV if
+/// the firmware cannot reboot.
+pub fn sys_reboot(cmd: usize, code: usize) -> isize {
+    if !controls_machine() {
+        return -EPERM;
+    }
+    let pid = current_process().getpid();
+    match cmd {
+        LINUX_REBOOT_CMD_RESTART => {
+            block_cache_sync_all();
+            info!("reboot asked by process {}", pid);
+            reboot();
+            -ENODEV
+        }
+        LINUX_REBOOT_CMD_POWER_OFF => {
+            block_cache_sync_all();
+            info!("power off asked by process {} with code {}", pid, code);
+            match code as u8 {
+                0 => QEMU_EXIT_HANDLE.exit_success(),
+                code => QEMU_EXIT_HANDLE.exit(code as u32),
+            }
+        }
+        _ => -EINVAL,
+    }
+}
+
+/// Take `hart` offline if `online` is 0, moving its tasks to the other harts, or
+/// bring it back online otherwise, and return once it is. Return -EPERM unless
+/// the caller controls the machine (see `controls_machine`), -EINVAL if there is
+/// no such hart, -EBUSY to take the last online hart offline, -EAGAIN if it is on
+/// its way offline or online and -EIO if the firmware does not start it.
+pub fn sys_set_hart_online(hart: usize, online: usize) -> isize {
+    if !controls_machine() {
+        return -EPERM;
+    }
+    let online = online != 0;
+    let result = if online {
+        hart_online(hart)
+    } else {
+        hart_offline(hart)
+    };
+    match result {
+        Ok(()) => {}
+        Err(HotplugError::NoSuchHart) => return -EINVAL,
+        Err(HotplugError::LastHart) => return -EBUSY,
+        Err(HotplugError::Busy) => return -EAGAIN,
+        Err(HotplugError::Firmware) => return -EIO,
+    }
+    // the caller may run on the hart going offline, which it leaves by yielding
+    while (online_hart_mask() & (1 << hart) != 0) != online {
+        suspend_current_and_run_next();
+    }
+    0
+}
+
+/// Start collecting the kernel coverage of the current thread
+const KCOV_ENABLE: usize = 0;
+/// Stop collecting it
+const KCOV_DISABLE: usize = 1;
+/// Copy out the addresses covered
+const KCOV_COLLECT: usize = 2;
+
+/// Coverage of the kernel code run by the current thread and the threads and
+/// processes it creates from then on:
+/// - `KCOV_ENABLE`: start collecting it, anew, -ENODEV if the kernel is not
+///   built with `KCOV=1`
+/// - `KCOV_DISABLE`: stop collecting it, for this thread only
+/// - `KCOV_COLLECT`: write up to `len` kernel addresses covered to `buf` in
+///   order, and return the number of all of them
+///
+/// Return -EINVAL for an unknown `cmd`, or to collect without coverage enabled.
+pub fn sys_kcov(cmd: usize, buf: *mut usize, len: usize) -> isize {
+    let task = current_task().unwrap();
+    let mut task_inner = task.inner_exclusive_access();
+    match cmd {
+        KCOV_ENABLE => {
+            let area = match KcovArea::new() {
+                Some(area) => area,
+                None => return -ENODEV,
+            };
+            kcov_switch_in(Some(&area));
+            task_inner.kcov = Some(area);
+        }
+        KCOV_DISABLE => {
+            // the area may be freed with the reference of the thread
+            kcov_switch_out();
+            task_inner.kcov = None;
+        }
+        KCOV_COLLECT => {
+            let area = match task_inner.kcov.clone() {
+                Some(area) => area,
+                None => return -EINVAL,
+            };
+            drop(task_inner);
+            let (pcs, count) = area.collect(len);
+            if copy_slice_to_user(current_user_token(), buf, &pcs).is_none() {
+                return -EFAULT;
+            }
+            return count as isize;
+        }
+        _ => return -EINVAL,
+    }
+    0
 }
@@ -0,0 +1,136 @@
+//! Tracing of child processes by a debugger
+use super::errno::{EFAULT, EINVAL, EIO, EPERM, ESRCH};
+use crate::mm::{copy_from_user, copy_to_user, force_copy_to_user};
+use crate::task::{current_process, current_user_token, pid2process, send_signal, SignalFlags};
+use alloc::sync::Arc;
+
+/// The caller is traced by its parent
+const PTRACE_TRACEME: usize = 0;
+/// Copy the word at `addr` of the tracee to `*data`
+const PTRACE_PEEKDATA: usize = 2;
+/// Write the word `data` at `addr` of the tracee, even in read-only code
+const PTRACE_POKEDATA: usize = 5;
+/// Resume the stopped thread delivering signal `data`, 0 for none
+const PTRACE_CONT: usize = 7;
+/// Kill the tracee
+const PTRACE_KILL: usize = 8;
+/// Copy the registers of the stopped thread to `*data`, see [`UserRegs`]
+const PTRACE_GETREGS: usize = 12;
+/// Set the registers of the stopped thread to `*data`
+const PTRACE_SETREGS: usize = 13;
+/// Trace the child, which is stopped by a `SIGSTOP`
+const PTRACE_ATTACH: usize = 16;
+/// Stop tracing the child, resuming the stopped thread delivering signal `data`
+const PTRACE_DETACH: usize = 17;
+
+/// Registers of a thread as `struct user_regs_struct` of Linux: the pc, then x1 to x31
+type UserRegs = [usize; 32];
+
+/// Carry out `request` on the child `pid` of the caller as its tracer, see the
+/// `PTRACE_*` constants. Return 0, -ESRCH if `pid` is not a child traced by the
+/// caller or the request needs a stopped thread and none is, -EPERM if it is
+/// traced already, -EIO if the memory of the tracee is not accessible and
+/// -EFAULT if `data` is not.
+pub fn sys_ptrace(request: usize, pid: usize, addr: usize, data: usize) -> isize {
+    let process = current_process();
+    if request == PTRACE_TRACEME {
+        let mut inner = process.inner_exclusive_access();
+        if inner.ptrace.traced {
+            return -EPERM;
+        }
+        inner.ptrace.traced = true;
+        return 0;
+    }
+    let child = match pid2process(pid) {
+        Some(child)
+            if process
+                .inner_exclusive_access()
+                .children
+                .iter()
+                .any(|p| Arc::ptr_eq(p, &child)) =>
+        {
+            child
+        }
+        _ => return -ESRCH,
+    };
+    let mut child_inner = child.inner_exclusive_access();
+    if request == PTRACE_ATTACH {
+        if child_inner.ptrace.traced {
+            return -EPERM;
+        }
+        child_inner.ptrace.traced = true;
+        drop(child_inner);
+        send_signal(&child, SignalFlags::SIGSTOP.lowest_signum().unwrap());
+        return 0;
+    }
+    if !child_inner.ptrace.traced {
+        return -ESRCH;
+    }
+    match request {
+        PTRACE_KILL => {
+            drop(child_inner);
+            send_signal(&child, SignalFlags::SIGKILL.lowest_signum().unwrap());
+            return 0;
+        }
+        PTRACE_CONT | PTRACE_DETACH => {
+            if data != 0 && SignalFlags::from_signum(data).is_none() {
+                return -EINVAL;
+            }
+            let resumed = child_inner.ptrace.resume(data);
+            if request == PTRACE_DETACH {
+                child_inner.ptrace.traced = false;
+            } else if !resumed {
+                return -ESRCH;
+            }
+            drop(child_inner);
+            if resumed {
+                child.ptrace_wait.wake_all();
+            }
+            return 0;
+        }
+        _ => {}
+    }
+    // the other requests inspect a stopped thread
+    let task = match child_inner.ptrace.stopped() {
+        Some(stop) => Arc::clone(&stop.task),
+        None => return -ESRCH,
+    };
+    let tracee_token = child_inner.get_user_token();
+    drop(child_inner);
+    let token = current_user_token();
+    match request {
+        PTRACE_PEEKDATA => {
+            let word = match copy_from_user(tracee_token, addr as *const usize) {
+                Some(word) => word,
+                None => return -EIO,
+            };
+            if copy_to_user(token, data as *mut usize, &word).is_none() {
+                return -EFAULT;
+            }
+        }
+        PTRACE_POKEDATA => {
+            if force_copy_to_user(tracee_token, addr as *mut usize, &data).is_none() {
+                return -EIO;
+            }
+        }
+        PTRACE_GETREGS => {
+            let cx = task.inner_exclusive_access().get_trap_cx();
+            let mut regs: UserRegs = cx.x;
+            regs[0] = cx.sepc;
+            if copy_to_user(token, data as *mut UserRegs, &regs).is_none() {
+                return -EFAULT;
+            }
+        }
+        PTRACE_SETREGS => {
+            let regs = match copy_from_user(token, data as *const UserRegs) {
+                Some(regs) => regs,
+                None => return -EFAULT,
+            };
+            let cx = task.inner_exclusive_access().get_trap_cx();
+            cx.sepc = regs[0];
+            cx.x[1..].copy_from_slice(&regs[1..]);
+        }
+        _ => return -EINVAL,
+    }
+    0
+}
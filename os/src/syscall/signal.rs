@@ -0,0 +1,101 @@
+//! Signal-related syscalls
+use super::errno::{EFAULT, EINVAL, ESRCH};
+use crate::mm::{copy_from_user, copy_to_user};
+use crate::task::{
+    current_process, current_task, current_user_token, exit_current_by_signal, pid2process,
+    process_group, send_signal, SignalAction, SignalFlags, SignalFrame, UNCATCHABLE,
+};
+
+/// Send signal `signum` to process `pid`, to the process group of the caller if `pid`
+/// is 0 and to process group `-pid` if `pid` is less than -1. Signal 0 only checks that
+/// the process or group exists.
+pub fn sys_kill(pid: isize, signum: usize) -> isize {
+    let processes = match pid {
+        -1 => return -EINVAL,
+        0 => {
+            // not locked while process_group locks every process
+            let pgid = current_process().inner_exclusive_access().pgid;
+            process_group(pgid)
+        }
+        pid if pid > 0 => pid2process(pid as usize).into_iter().collect(),
+        pid => process_group(pid.unsigned_abs()),
+    };
+    if processes.is_empty() {
+        return -ESRCH;
+    }
+    if signum == 0 {
+        return 0;
+    }
+    if SignalFlags::from_signum(signum).is_none() {
+        return -EINVAL;
+    }
+    for process in processes.iter() {
+        send_signal(process, signum);
+    }
+    0
+}
+
+/// Set the action of signal `signum` to `*action` unless it is null,
+/// the old action is written to `*old_action` unless it is null
+pub fn sys_sigaction(
+    signum: usize,
+    action: *const SignalAction,
+    old_action: *mut SignalAction,
+) -> isize {
+    let flag = match SignalFlags::from_signum(signum) {
+        Some(flag) => flag,
+        None => return -EINVAL,
+    };
+    let token = current_user_token();
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if !old_action.is_null()
+        && copy_to_user(token, old_action, &inner.signal_actions[signum]).is_none()
+    {
+        return -EFAULT;
+    }
+    if !action.is_null() {
+        if UNCATCHABLE.contains(flag) {
+            return -EINVAL;
+        }
+        match copy_from_user(token, action) {
+            Some(action) => inner.signal_actions[signum] = action,
+            None => return -EFAULT,
+        }
+    }
+    0
+}
+
+/// Replace the signal mask of the current thread, return the old one.
+/// `SIGKILL` and `SIGSTOP` cannot be masked.
+pub fn sys_sigprocmask(mask: u32) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let old_mask = inner.signal_mask;
+    inner.signal_mask = SignalFlags::from_bits_truncate(mask) - UNCATCHABLE;
+    old_mask.bits() as isize
+}
+
+/// Return from a signal handler, restoring the registers and signal mask
+/// saved in the [`SignalFrame`] at the user stack pointer
+pub fn sys_sigreturn() -> isize {
+    let token = current_user_token();
+    let task = current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let cx = inner.get_trap_cx();
+    let frame = match copy_from_user(token, cx.x[2] as *const SignalFrame) {
+        Some(frame) => frame,
+        None => {
+            drop(inner);
+            drop(task);
+            exit_current_by_signal(SignalFlags::SIGSEGV.lowest_signum().unwrap(), 0);
+            unreachable!();
+        }
+    };
+    // sstatus and the kernel fields are never taken from user memory
+    cx.x = frame.x;
+    cx.sepc = frame.sepc;
+    inner.signal_mask = frame.mask - UNCATCHABLE;
+    // returned in a0 by trap_handler
+    cx.x[10] as isize
+}
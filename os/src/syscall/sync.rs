@@ -0,0 +1,259 @@
+//! Synchronization syscalls, on objects shared by the threads of a process
+use super::errno::{EDEADLK, EINTR, EINVAL, EPERM};
+use crate::sync::{Condvar, Mutex, MutexBlocking, MutexSpin, Resource, RwLock, Semaphore};
+use crate::task::{current_process, current_task};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Put `object` in the lowest free slot of `list` and return its index as the id
+fn alloc_id<T>(list: &mut Vec<Option<T>>, object: T) -> usize {
+    match list.iter().position(Option::is_none) {
+        Some(id) => {
+            list[id] = Some(object);
+            id
+        }
+        None => {
+            list.push(Some(object));
+            list.len() - 1
+        }
+    }
+}
+
+/// Tid of the current thread
+fn current_tid() -> usize {
+    let task = current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    inner.res.as_ref().unwrap().tid
+}
+
+/// Take a unit of `res` by calling `take`, which returns false if a signal arrives
+/// first, and keep track of it for deadlock detection. Return -EDEADLK if detection
+/// is enabled and waiting for it may deadlock, and -EINTR if `take` fails.
+fn acquire_resource(res: Resource, take: impl FnOnce() -> bool) -> isize {
+    let process = current_process();
+    let tid = current_tid();
+    if !process
+        .inner_exclusive_access()
+        .deadlock_detector
+        .request(tid, res)
+    {
+        return -EDEADLK;
+    }
+    let taken = take();
+    let mut inner = process.inner_exclusive_access();
+    if taken {
+        inner.deadlock_detector.acquire(tid, res);
+        0
+    } else {
+        inner.deadlock_detector.cancel(tid, res);
+        -EINTR
+    }
+}
+
+/// Keep track of the current thread releasing a unit of `res`, for deadlock detection
+fn release_resource(res: Resource) {
+    let process = current_process();
+    let tid = current_tid();
+    process
+        .inner_exclusive_access()
+        .deadlock_detector
+        .release(tid, res);
+}
+
+/// Enable deadlock detection for the mutexes and semaphores of the current process
+/// if `enabled` is 1, disable it if 0. Return -EINVAL otherwise.
+pub fn sys_enable_deadlock_detect(enabled: usize) -> isize {
+    if enabled > 1 {
+        return -EINVAL;
+    }
+    let process = current_process();
+    process.inner_exclusive_access().deadlock_detector.enabled = enabled == 1;
+    0
+}
+
+/// Create a mutex of the current process and return its id. Its waiters yield if
+/// `kind` is 0 and block if 1, or if 2 spin while the owner runs and block otherwise.
+/// Return -EINVAL for any other `kind`.
+pub fn sys_mutex_create(kind: usize) -> isize {
+    let process = current_process();
+    let mutex: Arc<dyn Mutex> = match kind {
+        0 => Arc::new(MutexSpin::new()),
+        1 => Arc::new(MutexBlocking::new(false)),
+        2 => Arc::new(MutexBlocking::new(true)),
+        _ => return -EINVAL,
+    };
+    let mut inner = process.inner_exclusive_access();
+    let id = alloc_id(&mut inner.mutex_list, mutex);
+    inner.deadlock_detector.add_resource(Resource::Mutex(id), 1);
+    id as isize
+}
+
+/// The mutex `id` of the current process
+fn current_mutex(id: usize) -> Option<Arc<dyn Mutex>> {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    inner.mutex_list.get(id).cloned().flatten()
+}
+
+/// Lock mutex `id`, blocking while another thread holds it. Return -EINVAL if there
+/// is no such mutex, -EDEADLK if it may deadlock and -EINTR if a signal arrives first.
+pub fn sys_mutex_lock(id: usize) -> isize {
+    match current_mutex(id) {
+        Some(mutex) => acquire_resource(Resource::Mutex(id), || mutex.lock()),
+        None => -EINVAL,
+    }
+}
+
+/// Unlock mutex `id`. Return -EINVAL if there is no such mutex and -EPERM if the
+/// current thread does not hold it.
+pub fn sys_mutex_unlock(id: usize) -> isize {
+    match current_mutex(id) {
+        Some(mutex) if mutex.unlock() => {
+            release_resource(Resource::Mutex(id));
+            0
+        }
+        Some(_) => -EPERM,
+        None => -EINVAL,
+    }
+}
+
+/// Create a semaphore of the current process with `res_count` resources and return its id
+pub fn sys_semaphore_create(res_count: usize) -> isize {
+    let process = current_process();
+    let semaphore = Arc::new(Semaphore::new(res_count));
+    let mut inner = process.inner_exclusive_access();
+    let id = alloc_id(&mut inner.semaphore_list, semaphore);
+    inner
+        .deadlock_detector
+        .add_resource(Resource::Semaphore(id), res_count);
+    id as isize
+}
+
+/// The semaphore `id` of the current process
+fn current_semaphore(id: usize) -> Option<Arc<Semaphore>> {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    inner.semaphore_list.get(id).cloned().flatten()
+}
+
+/// Release a resource of semaphore `id`. Return -EINVAL if there is no such semaphore.
+pub fn sys_semaphore_up(id: usize) -> isize {
+    match current_semaphore(id) {
+        Some(semaphore) => {
+            semaphore.up();
+            release_resource(Resource::Semaphore(id));
+            0
+        }
+        None => -EINVAL,
+    }
+}
+
+/// Take a resource of semaphore `id`, blocking until one is released if there is none
+/// left. Return -EINVAL if there is no such semaphore, -EDEADLK if it may deadlock and
+/// -EINTR if a signal arrives first.
+pub fn sys_semaphore_down(id: usize) -> isize {
+    match current_semaphore(id) {
+        Some(semaphore) => acquire_resource(Resource::Semaphore(id), || semaphore.down()),
+        None => -EINVAL,
+    }
+}
+
+/// Create a condition variable of the current process and return its id
+pub fn sys_condvar_create() -> isize {
+    let process = current_process();
+    let condvar = Arc::new(Condvar::new());
+    let mut inner = process.inner_exclusive_access();
+    alloc_id(&mut inner.condvar_list, condvar) as isize
+}
+
+/// The condition variable `id` of the current process
+fn current_condvar(id: usize) -> Option<Arc<Condvar>> {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    inner.condvar_list.get(id).cloned().flatten()
+}
+
+/// Wake up the thread waiting the longest on condition variable `id`. Return -EINVAL
+/// if there is no such condition variable.
+pub fn sys_condvar_signal(id: usize) -> isize {
+    match current_condvar(id) {
+        Some(condvar) => {
+            condvar.signal();
+            0
+        }
+        None => -EINVAL,
+    }
+}
+
+/// Wake up all the threads waiting on condition variable `id`. Return -EINVAL if
+/// there is no such condition variable.
+pub fn sys_condvar_broadcast(id: usize) -> isize {
+    match current_condvar(id) {
+        Some(condvar) => {
+            condvar.broadcast();
+            0
+        }
+        None => -EINVAL,
+    }
+}
+
+/// Unlock mutex `mutex_id` and wait on condition variable `condvar_id` until woken
+/// up, then lock the mutex again. Return -EINVAL if either does not exist, -EPERM if
+/// the current thread does not hold the mutex and -EINTR, with the mutex locked,
+/// if a signal arrives first.
+pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
+    let (condvar, mutex) = match (current_condvar(condvar_id), current_mutex(mutex_id)) {
+        (Some(condvar), Some(mutex)) => (condvar, mutex),
+        _ => return -EINVAL,
+    };
+    match condvar.wait(mutex.as_ref()) {
+        Some(true) => 0,
+        Some(false) => -EINTR,
+        None => -EPERM,
+    }
+}
+
+/// Create a reader-writer lock of the current process and return its id
+pub fn sys_rwlock_create() -> isize {
+    let process = current_process();
+    let rwlock = Arc::new(RwLock::new());
+    let mut inner = process.inner_exclusive_access();
+    alloc_id(&mut inner.rwlock_list, rwlock) as isize
+}
+
+/// The reader-writer lock `id` of the current process
+fn current_rwlock(id: usize) -> Option<Arc<RwLock>> {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    inner.rwlock_list.get(id).cloned().flatten()
+}
+
+/// Lock reader-writer lock `id` for reading, blocking while a writer holds it or waits
+/// for it. Return -EINVAL if there is no such lock and -EINTR if a signal arrives first.
+pub fn sys_rwlock_read(id: usize) -> isize {
+    match current_rwlock(id) {
+        Some(rwlock) if rwlock.read() => 0,
+        Some(_) => -EINTR,
+        None => -EINVAL,
+    }
+}
+
+/// Lock reader-writer lock `id` for writing, blocking while anyone else holds it.
+/// Return -EINVAL if there is no such lock and -EINTR if a signal arrives first.
+pub fn sys_rwlock_write(id: usize) -> isize {
+    match current_rwlock(id) {
+        Some(rwlock) if rwlock.write() => 0,
+        Some(_) => -EINTR,
+        None => -EINVAL,
+    }
+}
+
+/// Release reader-writer lock `id`, held for reading or writing. Return -EINVAL if
+/// there is no such lock and -EPERM if the current thread does not hold it.
+pub fn sys_rwlock_unlock(id: usize) -> isize {
+    match current_rwlock(id) {
+        Some(rwlock) if rwlock.unlock() => 0,
+        Some(_) => -EPERM,
+        None => -EINVAL,
+    }
+}
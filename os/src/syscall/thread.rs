@@ -0,0 +1,148 @@
+//! Thread-related syscalls
+use super::errno::ENOMEM;
+use crate::config::USER_STACK_SIZE;
+use crate::mm::kernel_token;
+use crate::task::{add_task, current_process, current_task, ProcessControlBlock, TaskControlBlock};
+use crate::trap::{trap_handler, TrapContext};
+use alloc::sync::Arc;
+
+/// Create a thread of the current process running `entry(arg)`, return its tid
+pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
+    let process = current_process();
+    let task = current_task().unwrap();
+    let task_inner = task.inner_exclusive_access();
+    let (priority, nice, cpus_allowed, comm) = (
+        task_inner.priority,
+        task_inner.nice,
+        task_inner.cpus_allowed,
+        task_inner.comm,
+    );
+    let kcov = task_inner.kcov.clone();
+    drop(task_inner);
+    drop(task);
+    if process
+        .inner_exclusive_access()
+        .exceeds_as_limit(USER_STACK_SIZE)
+    {
+        return -ENOMEM;
+    }
+    // create a new thread with its own user stack and TrapContext
+    let new_task = match TaskControlBlock::new(Arc::clone(&process), None) {
+        Some(new_task) => new_task,
+        None => return -ENOMEM,
+    };
+    let mut new_task_inner = new_task.inner_exclusive_access();
+    new_task_inner.priority = priority;
+    new_task_inner.nice = nice;
+    new_task_inner.cpus_allowed = cpus_allowed;
+    new_task_inner.comm = comm;
+    new_task_inner.kcov = kcov;
+    let new_task_res = new_task_inner.res.as_ref().unwrap();
+    let new_task_tid = new_task_res.tid;
+    let new_task_trap_cx = new_task_inner.get_trap_cx();
+    *new_task_trap_cx = TrapContext::app_init_context(
+        entry,
+        new_task_res.ustack_top(),
+        kernel_token(),
+        new_task.kernel_stack.get_top(),
+        trap_handler as usize,
+    );
+    new_task_trap_cx.x[10] = arg;
+    drop(new_task_inner);
+    add_thread(&process, new_task, new_task_tid);
+    new_task_tid as isize
+}
+
+/// Create a thread of the current process running a copy of the calling thread, which
+/// returns 0 in it, on `stack`, or on its own user stack if it is 0. Return its tid.
+pub fn clone_thread(stack: usize) -> isize {
+    let task = current_task().unwrap();
+    let process = task.process.upgrade().unwrap();
+    if process
+        .inner_exclusive_access()
+        .exceeds_as_limit(USER_STACK_SIZE)
+    {
+        return -ENOMEM;
+    }
+    let new_task = match TaskControlBlock::new(Arc::clone(&process), None) {
+        Some(new_task) => new_task,
+        None => return -ENOMEM,
+    };
+    let task_inner = task.inner_exclusive_access();
+    let trap_cx = task_inner.get_trap_cx().clone();
+    let signal_mask = task_inner.signal_mask;
+    let (priority, nice, cpus_allowed, comm) = (
+        task_inner.priority,
+        task_inner.nice,
+        task_inner.cpus_allowed,
+        task_inner.comm,
+    );
+    let kcov = task_inner.kcov.clone();
+    drop(task_inner);
+    let mut new_task_inner = new_task.inner_exclusive_access();
+    new_task_inner.signal_mask = signal_mask;
+    new_task_inner.priority = priority;
+    new_task_inner.nice = nice;
+    new_task_inner.cpus_allowed = cpus_allowed;
+    new_task_inner.comm = comm;
+    new_task_inner.kcov = kcov;
+    let new_task_res = new_task_inner.res.as_ref().unwrap();
+    let new_task_tid = new_task_res.tid;
+    let ustack_top = new_task_res.ustack_top();
+    let new_task_trap_cx = new_task_inner.get_trap_cx();
+    *new_task_trap_cx = trap_cx;
+    new_task_trap_cx.kernel_sp = new_task.kernel_stack.get_top();
+    new_task_trap_cx.x[10] = 0;
+    new_task_trap_cx.set_sp(if stack != 0 { stack } else { ustack_top });
+    drop(new_task_inner);
+    add_thread(&process, new_task, new_task_tid);
+    new_task_tid as isize
+}
+
+/// Add the new thread `tid` to `process` and to the scheduler
+fn add_thread(process: &Arc<ProcessControlBlock>, task: Arc<TaskControlBlock>, tid: usize) {
+    let mut process_inner = process.inner_exclusive_access();
+    let tasks = &mut process_inner.tasks;
+    while tasks.len() < tid + 1 {
+        tasks.push(None);
+    }
+    tasks[tid] = Some(Arc::clone(&task));
+    drop(process_inner);
+    add_task(task);
+}
+
+pub fn sys_gettid() -> isize {
+    current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .res
+        .as_ref()
+        .unwrap()
+        .tid as isize
+}
+
+/// Reap the thread `tid` of the current process and return its exit code.
+/// If there is no such thread or it is the calling thread, return -1.
+/// Else if the thread is still running, return -2.
+pub fn sys_waittid(tid: usize) -> i32 {
+    let task = current_task().unwrap();
+    let process = task.process.upgrade().unwrap();
+    // a thread cannot wait for itself
+    if task.inner_exclusive_access().res.as_ref().unwrap().tid == tid {
+        return -1;
+    }
+    let mut process_inner = process.inner_exclusive_access();
+    let exit_code = match process_inner.tasks.get(tid) {
+        Some(Some(waited_task)) => waited_task.inner_exclusive_access().exit_code,
+        _ => return -1,
+    };
+    if let Some(exit_code) = exit_code {
+        // dealloc the exited thread, including its kernel stack
+        process_inner.tasks[tid] = None;
+        process_inner.dealloc_tid(tid);
+        exit_code
+    } else {
+        // waited thread has not exited
+        -2
+    }
+}
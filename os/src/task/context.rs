@@ -1,6 +1,10 @@
 //! Implementation of [`TaskContext`]
 use crate::trap::trap_return;
 
+extern "C" {
+    fn __kthread_start();
+}
+
 #[repr(C)]
 /// task context structure containing some registers
 pub struct TaskContext {
@@ -29,4 +33,18 @@ impl TaskContext {
             s: [0; 12],
         }
     }
+    /// `ra`, `sp` and the frame pointer `s0` the task resumes with
+    pub fn frame(&self) -> (usize, usize, usize) {
+        (self.ra, self.sp, self.s[0])
+    }
+    /// set Task Context{ra: __kthread_start, sp: kstack_ptr, s_0: entry}
+    pub fn goto_kthread_start(kstack_ptr: usize, entry: usize) -> Self {
+        let mut s = [0; 12];
+        s[0] = entry;
+        Self {
+            ra: __kthread_start as usize,
+            sp: kstack_ptr,
+            s,
+        }
+    }
 }
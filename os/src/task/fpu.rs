@@ -0,0 +1,120 @@
+//! Lazy switching of the floating-point state of user tasks
+//!
+//! A task starts with the FPU off, `FS` is Off in the `sstatus` of its
+//! TrapContext, so that its first FP instruction raises an illegal instruction
+//! exception. The FPU is then turned on for the task with cleared registers and
+//! the instruction runs again. From then on the F registers are saved in the
+//! [`FpContext`] of the task when it switches out after changing them, i.e. with
+//! `FS` Dirty, and loaded when it returns to user mode on a hart whose registers
+//! hold another state. Tasks never using the FPU cost nothing.
+use super::current_task;
+use crate::config::MAX_HARTS;
+use crate::hart::hart_id;
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use riscv::register::sstatus::{self, Sstatus, FS};
+
+global_asm!(include_str!("fpu.S"));
+
+extern "C" {
+    fn __save_fp(fp_cx_ptr: *mut FpContext);
+    fn __load_fp(fp_cx_ptr: *const FpContext);
+}
+
+/// Address of the [`FpContext`] last loaded into or saved from the F registers
+/// of each hart, 0 if none
+static FPU_OWNER: [AtomicUsize; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NONE: AtomicUsize = AtomicUsize::new(0);
+    [NONE; MAX_HARTS]
+};
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+/// F registers and fcsr of a task, saved while another task may use the FPU
+pub struct FpContext {
+    f: [u64; 32],
+    fcsr: usize,
+    /// hart whose registers it was last loaded into or saved from
+    cpu: usize,
+}
+
+impl FpContext {
+    /// Cleared registers, loaded into no hart
+    pub fn new() -> Self {
+        Self {
+            f: [0; 32],
+            fcsr: 0,
+            cpu: usize::MAX,
+        }
+    }
+    /// A copy for a child task, e.g. by fork, loaded into no hart
+    pub fn fork(&self) -> Self {
+        Self {
+            cpu: usize::MAX,
+            ..*self
+        }
+    }
+    /// Save the registers of this hart if the task owning it changed them since
+    /// they were loaded, as `FS` in `sstatus` of its TrapContext tells
+    pub fn save(&mut self, sstatus: &mut Sstatus) {
+        if sstatus.fs() != FS::Dirty {
+            return;
+        }
+        let hart = hart_id();
+        unsafe {
+            // the FPU of the kernel may be off, the task sets its own on sret
+            sstatus::set_fs(FS::Clean);
+            __save_fp(self);
+        }
+        self.cpu = hart;
+        FPU_OWNER[hart].store(self as *mut Self as usize, Ordering::Relaxed);
+        sstatus.set_fs(FS::Clean);
+    }
+    /// Load it into the registers of this hart unless they hold it already
+    pub fn load(&mut self) {
+        let hart = hart_id();
+        let this = self as *mut Self as usize;
+        // it may have been loaded into another hart and changed there meanwhile
+        if FPU_OWNER[hart].load(Ordering::Relaxed) == this && self.cpu == hart {
+            return;
+        }
+        unsafe {
+            sstatus::set_fs(FS::Clean);
+            __load_fp(self);
+        }
+        self.cpu = hart;
+        FPU_OWNER[hart].store(this, Ordering::Relaxed);
+    }
+}
+
+/// Turn the FPU on for the current task with cleared registers, after its first
+/// FP instruction trapped with `FS` Off. False if it was on, i.e. the illegal
+/// instruction is not an FP one.
+pub fn enable_current_fp() -> bool {
+    let task = current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let cx = inner.get_trap_cx();
+    if cx.sstatus.fs() != FS::Off {
+        return false;
+    }
+    inner.fp = FpContext::new();
+    cx.sstatus.set_fs(FS::Clean);
+    true
+}
+
+/// Forget what the F registers of the current hart hold, as it goes offline and
+/// they are lost when it stops
+pub fn forget_hart_fp() {
+    FPU_OWNER[hart_id()].store(0, Ordering::Relaxed);
+}
+
+/// Load the FP state of the current task before it returns to user mode, if it
+/// has the FPU on
+pub fn load_current_fp() {
+    let task = current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    if inner.get_trap_cx().sstatus.fs() != FS::Off {
+        inner.fp.load();
+    }
+}
@@ -0,0 +1,246 @@
+//!Implementation of [`RecycleAllocator`] and the ids and resources allocated by it
+use super::{AddressSpace, ProcessControlBlock};
+use crate::config::{
+    KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_BASE, USER_STACK_SIZE,
+};
+use crate::mm::{MapPermission, MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::sync::ExclusiveCell;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use lazy_static::*;
+///Allocator of small integer ids, recycled ids are handed out first
+#[derive(Clone)]
+pub struct RecycleAllocator {
+    current: usize,
+    recycled: Vec<usize>,
+}
+
+impl RecycleAllocator {
+    ///Create an empty `RecycleAllocator`
+    pub fn new() -> Self {
+        RecycleAllocator {
+            current: 0,
+            recycled: Vec::new(),
+        }
+    }
+    ///Allocate an id
+    pub fn alloc(&mut self) -> usize {
+        if let Some(id) = self.recycled.pop() {
+            id
+        } else {
+            self.current += 1;
+            self.current - 1
+        }
+    }
+    ///Recycle an id
+    pub fn dealloc(&mut self, id: usize) {
+        assert!(id < self.current);
+        assert!(
+            !self.recycled.iter().any(|i| *i == id),
+            "id {} has been deallocated!",
+            id
+        );
+        self.recycled.push(id);
+    }
+}
+
+lazy_static! {
+    pub static ref PID_ALLOCATOR: ExclusiveCell<RecycleAllocator> =
+        ExclusiveCell::new(RecycleAllocator::new());
+    pub static ref KSTACK_ALLOCATOR: ExclusiveCell<RecycleAllocator> =
+        ExclusiveCell::new(RecycleAllocator::new());
+}
+///Bind pid lifetime to `PidHandle`
+pub struct PidHandle(pub usize);
+
+impl Drop for PidHandle {
+    fn drop(&mut self) {
+        //println!("drop pid {}", self.0);
+        PID_ALLOCATOR.exclusive_access().dealloc(self.0);
+    }
+}
+///Allocate a pid from PID_ALLOCATOR
+pub fn pid_alloc() -> PidHandle {
+    PidHandle(PID_ALLOCATOR.exclusive_access().alloc())
+}
+
+/// Return (bottom, top) of a kernel stack in kernel space.
+pub fn kernel_stack_position(kstack_id: usize) -> (usize, usize) {
+    let top = TRAMPOLINE - kstack_id * (KERNEL_STACK_SIZE + PAGE_SIZE);
+    let bottom = top - KERNEL_STACK_SIZE;
+    (bottom, top)
+}
+///Kernelstack for a thread
+pub struct KernelStack(pub usize);
+
+impl KernelStack {
+    ///Allocate a kernelstack from KSTACK_ALLOCATOR, `None` if out of frames
+    pub fn new() -> Option<Self> {
+        let kstack_id = KSTACK_ALLOCATOR.exclusive_access().alloc();
+        let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(kstack_id);
+        if !KERNEL_SPACE.exclusive_access().insert_framed_area(
+            kernel_stack_bottom.into(),
+            kernel_stack_top.into(),
+            MapPermission::R | MapPermission::W,
+        ) {
+            KSTACK_ALLOCATOR.exclusive_access().dealloc(kstack_id);
+            return None;
+        }
+        Some(KernelStack(kstack_id))
+    }
+    #[allow(unused)]
+    ///Push a value on top of kernelstack
+    pub fn push_on_top<T>(&self, value: T) -> *mut T
+    where
+        T: Sized,
+    {
+        let kernel_stack_top = self.get_top();
+        let ptr_mut = (kernel_stack_top - core::mem::size_of::<T>()) as *mut T;
+        unsafe {
+            *ptr_mut = value;
+        }
+        ptr_mut
+    }
+    ///Get the value on the top of kernelstack
+    pub fn get_top(&self) -> usize {
+        let (_, kernel_stack_top) = kernel_stack_position(self.0);
+        kernel_stack_top
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        let (kernel_stack_bottom, _) = kernel_stack_position(self.0);
+        let kernel_stack_bottom_va: VirtAddr = kernel_stack_bottom.into();
+        KERNEL_SPACE
+            .exclusive_access()
+            .remove_area_with_start_vpn(kernel_stack_bottom_va.into());
+        KSTACK_ALLOCATOR.exclusive_access().dealloc(self.0);
+    }
+}
+
+/// Bottom of the TrapContext page in `slot` of an address space
+fn trap_cx_bottom_from_slot(slot: usize) -> usize {
+    TRAP_CONTEXT - slot * PAGE_SIZE
+}
+
+/// Bottom of the user stack in `slot` of an address space, above its guard page
+fn ustack_bottom_from_slot(slot: usize) -> usize {
+    USER_STACK_BASE + slot * (PAGE_SIZE + USER_STACK_SIZE) + PAGE_SIZE
+}
+
+///Thread id of a thread in its process, and the slot of its user stack and TrapContext in
+///the address space of the process. Slots are allocated by the address space, which may be
+///shared with other processes (`CLONE_VM`), so the slot is not always the tid.
+///The user stack and TrapContext are unmapped and the slot is recycled on drop,
+///the tid is recycled by `sys_waittid`.
+pub struct TaskUserRes {
+    pub tid: usize,
+    pub slot: usize,
+    pub process: Weak<ProcessControlBlock>,
+}
+
+impl TaskUserRes {
+    ///Allocate a tid in `process`. If `slot` is `None`, allocate a slot in its address space
+    ///and map the user stack and TrapContext there, otherwise they are already mapped in
+    ///`slot` (e.g. copied by fork). `None` if out of frames.
+    pub fn new(process: Arc<ProcessControlBlock>, slot: Option<usize>) -> Option<Self> {
+        let mut process_inner = process.inner_exclusive_access();
+        let slot = match slot {
+            Some(slot) => slot,
+            None => {
+                let mut space = process_inner.address_space.exclusive_access();
+                let slot = space.slot_allocator.alloc();
+                if !Self::alloc_user_res(slot, &mut space.memory_set) {
+                    space.slot_allocator.dealloc(slot);
+                    return None;
+                }
+                slot
+            }
+        };
+        let tid = process_inner.alloc_tid();
+        drop(process_inner);
+        Some(Self {
+            tid,
+            slot,
+            process: Arc::downgrade(&process),
+        })
+    }
+    ///Map the user stack and TrapContext in `slot` of `memory_set`, false if out of frames
+    pub fn alloc_user_res(slot: usize, memory_set: &mut MemorySet) -> bool {
+        let ustack_bottom = ustack_bottom_from_slot(slot);
+        let ustack_top = ustack_bottom + USER_STACK_SIZE;
+        if !memory_set.insert_framed_area(
+            ustack_bottom.into(),
+            ustack_top.into(),
+            MapPermission::R | MapPermission::W | MapPermission::U,
+        ) {
+            return false;
+        }
+        let trap_cx_bottom = trap_cx_bottom_from_slot(slot);
+        let trap_cx_top = trap_cx_bottom + PAGE_SIZE;
+        if !memory_set.insert_framed_area(
+            trap_cx_bottom.into(),
+            trap_cx_top.into(),
+            MapPermission::R | MapPermission::W,
+        ) {
+            memory_set.remove_area_with_start_vpn(VirtAddr::from(ustack_bottom).into());
+            return false;
+        }
+        true
+    }
+    ///Unmap the user stack and TrapContext in `slot` of `space` and recycle the slot
+    fn dealloc_slot(slot: usize, space: &mut AddressSpace) {
+        let ustack_bottom_va: VirtAddr = ustack_bottom_from_slot(slot).into();
+        space
+            .memory_set
+            .remove_area_with_start_vpn(ustack_bottom_va.into());
+        let trap_cx_bottom_va: VirtAddr = trap_cx_bottom_from_slot(slot).into();
+        space
+            .memory_set
+            .remove_area_with_start_vpn(trap_cx_bottom_va.into());
+        space.slot_allocator.dealloc(slot);
+    }
+    fn dealloc_user_res(&self) {
+        if let Some(process) = self.process.upgrade() {
+            let process_inner = process.inner_exclusive_access();
+            Self::dealloc_slot(
+                self.slot,
+                &mut process_inner.address_space.exclusive_access(),
+            );
+        }
+    }
+    ///Move to `slot` of the new address space of the process, where the user stack and
+    ///TrapContext are already mapped, releasing those in the old address space `old_space`
+    pub fn move_to_slot(&mut self, slot: usize, old_space: &mut AddressSpace) {
+        Self::dealloc_slot(self.slot, old_space);
+        self.slot = slot;
+    }
+    ///Address of the TrapContext in user space
+    pub fn trap_cx_user_va(&self) -> usize {
+        trap_cx_bottom_from_slot(self.slot)
+    }
+    ///Physical page of the TrapContext
+    pub fn trap_cx_ppn(&self) -> PhysPageNum {
+        let process = self.process.upgrade().unwrap();
+        let process_inner = process.inner_exclusive_access();
+        let trap_cx_bottom_va: VirtAddr = trap_cx_bottom_from_slot(self.slot).into();
+        process_inner
+            .address_space
+            .exclusive_access()
+            .memory_set
+            .translate(trap_cx_bottom_va.into())
+            .unwrap()
+            .ppn()
+    }
+    ///Top of the user stack
+    pub fn ustack_top(&self) -> usize {
+        ustack_bottom_from_slot(self.slot) + USER_STACK_SIZE
+    }
+}
+
+impl Drop for TaskUserRes {
+    fn drop(&mut self) {
+        self.dealloc_user_res();
+    }
+}
@@ -0,0 +1,35 @@
+//! Kernel threads
+//!
+//! A kernel thread is a task that only runs kernel code, on its own kernel
+//! stack and with the kernel address space. It is scheduled like any other
+//! task, but since interrupts stay disabled in S mode it is never preempted
+//! and has to give up the CPU by calling [`super::suspend_current_and_run_next`].
+//!
+//! Each kernel thread is the main thread of a process with an empty address
+//! space. These processes are adopted by `initproc`, which reaps them after
+//! they exit.
+use super::{exit_current_and_run_next, ProcessControlBlock, INITPROC};
+use alloc::sync::Arc;
+
+/// Spawn a kernel thread named `name` running `entry`, `None` if out of frames.
+/// The thread exits with code 0 when `entry` returns.
+pub fn kthread_spawn(name: &str, entry: fn()) -> Option<Arc<ProcessControlBlock>> {
+    // make sure initproc exists first, so that it still gets pid 0
+    let initproc = INITPROC.clone();
+    let process = ProcessControlBlock::new_kthread(name, entry)?;
+    process.inner_exclusive_access().parent = Some(Arc::downgrade(&initproc));
+    initproc
+        .inner_exclusive_access()
+        .children
+        .push(process.clone());
+    Some(process)
+}
+
+/// First Rust code of a kernel thread, entered from `__kthread_start`
+#[no_mangle]
+extern "C" fn kthread_main(entry: usize) -> ! {
+    let entry: fn() = unsafe { core::mem::transmute(entry) };
+    entry();
+    exit_current_and_run_next(0);
+    unreachable!("kernel thread resumed after exit");
+}
@@ -1,41 +1,448 @@
 //!Implementation of [`TaskManager`]
-use super::TaskControlBlock;
-use crate::sync::UPSafeCell;
-use alloc::collections::VecDeque;
+use super::{ProcessControlBlock, TaskControlBlock};
+use crate::cmdline;
+use crate::config::{
+    DEFAULT_TIME_SLICE_MS, MAX_HARTS, MLFQ_BOOST_INTERVAL_MS, MLFQ_LEVELS, SCHED_POLICY,
+};
+use crate::hart::{hart_id, online_hart_mask, wake_idle_hart, ALL_HARTS};
+use crate::kernel_test;
+use crate::sync::{McsLock, RcuCell, SpinNoIrqLock};
+use crate::timer::get_time_ms;
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::str::FromStr;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::*;
+/// Big enough stride so that `BIG_STRIDE / priority` stays precise
+pub const BIG_STRIDE: u64 = 1 << 20;
+
+/// Scheduling quantum in milliseconds, configurable at boot
+static TIME_SLICE_MS: AtomicUsize = AtomicUsize::new(DEFAULT_TIME_SLICE_MS);
+
+/// Set the scheduling quantum, used from the next task switch on
+pub fn set_time_slice_ms(ms: usize) {
+    assert!(ms > 0, "time slice must not be empty");
+    TIME_SLICE_MS.store(ms, Ordering::Relaxed);
+}
+
+/// Niceness of the tasks getting the most CPU time
+pub const MIN_NICE: i32 = -20;
+/// Niceness of the tasks getting the least CPU time
+pub const MAX_NICE: i32 = 19;
+
+/// Weight of a task with nice 0 in CFS
+pub const NICE_0_WEIGHT: u64 = 1024;
+
+/// Weights of nice -20..=19, each level is about 1.25 times the next, as in Linux
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+/// CFS weight of a task with niceness `nice`
+pub fn nice_to_weight(nice: i32) -> u64 {
+    NICE_TO_WEIGHT[(nice.clamp(MIN_NICE, MAX_NICE) - MIN_NICE) as usize]
+}
+
+/// Scheduling policies supported by [`TaskManager`]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SchedPolicy {
+    /// highest priority first, with aging
+    Priority,
+    /// stride scheduling, CPU time proportional to priority
+    Stride,
+    /// multi-level feedback queues
+    Mlfq,
+    /// completely fair scheduling by weighted virtual runtime
+    Cfs,
+}
+
+impl FromStr for SchedPolicy {
+    type Err = ();
+    /// The policy named `s` on the command line, e.g. `cfs`
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "priority" => Ok(Self::Priority),
+            "stride" => Ok(Self::Stride),
+            "mlfq" => Ok(Self::Mlfq),
+            "cfs" => Ok(Self::Cfs),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Whether stride pass `a` is before `b`. Passes may wrap around, but two
+/// ready tasks never differ by more than `BIG_STRIDE / MIN_PRIORITY`, which
+/// is far less than half of the `u64` range, so the wrapped difference tells.
+fn pass_before(a: u64, b: u64) -> bool {
+    (a.wrapping_sub(b) as i64) < 0
+}
+
 ///A array of `TaskControlBlock` that is thread-safe
 pub struct TaskManager {
     ready_queue: VecDeque<Arc<TaskControlBlock>>,
+    policy: SchedPolicy,
+    /// pass of the task fetched last, the lowest pass in stride scheduling
+    min_pass: u64,
+    /// ready queues of the MLFQ policy, index 0 runs first
+    mlfq_queues: Vec<VecDeque<Arc<TaskControlBlock>>>,
+    /// time of the last MLFQ priority boost in milliseconds
+    last_boost_ms: usize,
+    /// ready tasks of the CFS policy keyed by (vruntime, arrival order)
+    cfs_tree: BTreeMap<(u64, u64), Arc<TaskControlBlock>>,
+    /// number of tasks added so far, breaks vruntime ties in FIFO order
+    cfs_seq: u64,
+    /// vruntime of the task fetched last, the lowest vruntime in CFS
+    min_vruntime: u64,
 }
 
-/// A simple FIFO scheduler.
+/// A scheduler following `policy`.
+///
+/// With [`SchedPolicy::Priority`], tasks waiting in the ready queue age every
+/// time another task is picked, so low-priority tasks do not starve.
+///
+/// With [`SchedPolicy::Stride`], the task with the lowest pass runs and then
+/// advances its pass by `BIG_STRIDE / priority`.
+///
+/// With [`SchedPolicy::Mlfq`], the first task of the highest non-empty queue
+/// runs. A task that uses up its time slice moves one queue down, one that
+/// gives up the CPU early (e.g. waiting for I/O) moves one queue up, and all
+/// tasks go back to the top queue every `MLFQ_BOOST_INTERVAL_MS`.
+///
+/// With [`SchedPolicy::Cfs`], the task with the lowest vruntime runs. Its
+/// vruntime grows slower the higher its weight, i.e. the lower its nice.
+///
+/// Ready tasks that tie run in FIFO order.
 impl TaskManager {
     ///Creat an empty TaskManager
-    pub fn new() -> Self {
+    pub fn new(policy: SchedPolicy) -> Self {
         Self {
             ready_queue: VecDeque::new(),
+            policy,
+            min_pass: 0,
+            mlfq_queues: (0..MLFQ_LEVELS).map(|_| VecDeque::new()).collect(),
+            last_boost_ms: 0,
+            cfs_tree: BTreeMap::new(),
+            cfs_seq: 0,
+            min_vruntime: 0,
         }
     }
     ///Add a task to `TaskManager`
     pub fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let mut inner = task.inner_exclusive_access();
+        let slice_used_up = core::mem::take(&mut inner.slice_used_up);
+        match self.policy {
+            SchedPolicy::Priority => {}
+            SchedPolicy::Stride => {
+                // a new or long-blocked task must not catch up by running alone
+                if pass_before(inner.pass, self.min_pass) {
+                    inner.pass = self.min_pass;
+                }
+            }
+            SchedPolicy::Mlfq => {
+                inner.level = if slice_used_up {
+                    (inner.level + 1).min(MLFQ_LEVELS - 1)
+                } else {
+                    inner.level.saturating_sub(1)
+                };
+                let level = inner.level;
+                drop(inner);
+                self.mlfq_queues[level].push_back(task);
+                return;
+            }
+            SchedPolicy::Cfs => {
+                // a new or long-blocked task must not catch up by running alone
+                inner.vruntime = inner.vruntime.max(self.min_vruntime);
+                let key = (inner.vruntime, self.cfs_seq);
+                drop(inner);
+                self.cfs_seq += 1;
+                self.cfs_tree.insert(key, task);
+                return;
+            }
+        }
+        drop(inner);
         self.ready_queue.push_back(task);
     }
-    ///Remove the first task and return it,or `None` if `TaskManager` is empty
+    ///Remove the next task to run and return it,or `None` if `TaskManager` is empty
     pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        self.ready_queue.pop_front()
+        match self.policy {
+            SchedPolicy::Priority => self.fetch_priority(),
+            SchedPolicy::Stride => self.fetch_stride(),
+            SchedPolicy::Mlfq => self.fetch_mlfq(),
+            SchedPolicy::Cfs => self.fetch_cfs(),
+        }
+    }
+    ///Remove the task with the highest priority plus age
+    fn fetch_priority(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let (idx, _) = self
+            .ready_queue
+            .iter()
+            .enumerate()
+            .max_by_key(|(idx, task)| {
+                let inner = task.inner_exclusive_access();
+                (inner.effective_priority() + inner.age, Reverse(*idx))
+            })?;
+        let task = self.ready_queue.remove(idx).unwrap();
+        for waiting in self.ready_queue.iter() {
+            waiting.inner_exclusive_access().age += 1;
+        }
+        task.inner_exclusive_access().age = 0;
+        Some(task)
+    }
+    ///Remove the task with the lowest pass and advance its pass
+    fn fetch_stride(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let mut next: Option<(usize, u64)> = None;
+        for (idx, task) in self.ready_queue.iter().enumerate() {
+            let pass = task.inner_exclusive_access().pass;
+            match next {
+                Some((_, next_pass)) if !pass_before(pass, next_pass) => {}
+                _ => next = Some((idx, pass)),
+            }
+        }
+        let (idx, pass) = next?;
+        self.min_pass = pass;
+        let task = self.ready_queue.remove(idx).unwrap();
+        let mut inner = task.inner_exclusive_access();
+        inner.pass = pass.wrapping_add(BIG_STRIDE / inner.effective_priority() as u64);
+        drop(inner);
+        Some(task)
+    }
+    ///Remove the first task of the highest non-empty queue, boosting all tasks first if it is time
+    fn fetch_mlfq(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let now = get_time_ms();
+        if now - self.last_boost_ms >= MLFQ_BOOST_INTERVAL_MS {
+            self.last_boost_ms = now;
+            for level in 1..MLFQ_LEVELS {
+                while let Some(task) = self.mlfq_queues[level].pop_front() {
+                    task.inner_exclusive_access().level = 0;
+                    self.mlfq_queues[0].push_back(task);
+                }
+            }
+        }
+        self.mlfq_queues
+            .iter_mut()
+            .find(|queue| !queue.is_empty())?
+            .pop_front()
+    }
+    ///Remove the task with the lowest vruntime
+    fn fetch_cfs(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let key = *self.cfs_tree.keys().next()?;
+        self.min_vruntime = key.0;
+        self.cfs_tree.remove(&key)
+    }
+    ///Time slice in milliseconds of a task in MLFQ queue `level`. Lower
+    ///MLFQ queues get longer slices, other policies use the base quantum.
+    pub fn time_slice_ms(&self, level: usize) -> usize {
+        let slice = TIME_SLICE_MS.load(Ordering::Relaxed);
+        match self.policy {
+            SchedPolicy::Mlfq => slice << level,
+            _ => slice,
+        }
+    }
+    ///Number of ready tasks
+    pub fn len(&self) -> usize {
+        self.ready_queue.len()
+            + self.mlfq_queues.iter().map(VecDeque::len).sum::<usize>()
+            + self.cfs_tree.len()
+    }
+    ///Whether no task is ready
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    ///Remove the first ready task allowed to run on hart `cpu`, in queue order
+    ///regardless of the policy, for another hart to steal it
+    pub fn steal(&mut self, cpu: usize) -> Option<Arc<TaskControlBlock>> {
+        let allowed = |task: &Arc<TaskControlBlock>| task.inner_exclusive_access().allows(cpu);
+        if let Some(idx) = self.ready_queue.iter().position(&allowed) {
+            return self.ready_queue.remove(idx);
+        }
+        for queue in self.mlfq_queues.iter_mut() {
+            if let Some(idx) = queue.iter().position(&allowed) {
+                return queue.remove(idx);
+            }
+        }
+        let key = *self.cfs_tree.iter().find(|(_, task)| allowed(task))?.0;
+        self.cfs_tree.remove(&key)
+    }
+    ///Remove all the ready tasks, in queue order regardless of the policy
+    pub fn drain(&mut self) -> Vec<Arc<TaskControlBlock>> {
+        let mut tasks: Vec<_> = self.ready_queue.drain(..).collect();
+        for queue in self.mlfq_queues.iter_mut() {
+            tasks.extend(queue.drain(..));
+        }
+        tasks.extend(core::mem::take(&mut self.cfs_tree).into_values());
+        tasks
+    }
+    ///Remove `task` from the ready queue if it is there
+    pub fn remove(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready_queue.retain(|t| !Arc::ptr_eq(t, &task));
+        for queue in self.mlfq_queues.iter_mut() {
+            queue.retain(|t| !Arc::ptr_eq(t, &task));
+        }
+        self.cfs_tree.retain(|_, t| !Arc::ptr_eq(t, &task));
     }
 }
 
 lazy_static! {
-    pub static ref TASK_MANAGER: UPSafeCell<TaskManager> =
-        unsafe { UPSafeCell::new(TaskManager::new()) };
+    ///One ready queue for each hart, indexed by hart id. Idle harts stealing
+    ///tasks contend for them, so waiters queue up in an MCS lock.
+    pub static ref TASK_MANAGERS: [SpinNoIrqLock<TaskManager, McsLock>; MAX_HARTS] =
+        core::array::from_fn(|_| SpinNoIrqLock::new(TaskManager::new(*BOOT_POLICY)));
+    /// Policy of all the task managers, `sched=` on the command line or [`SCHED_POLICY`]
+    static ref BOOT_POLICY: SchedPolicy = cmdline::parse("sched").unwrap_or(SCHED_POLICY);
 }
-///Interface offered to add task
+///Interface offered to add task, to the queue of the hart it last ran on
+///so that it finds its data still in that hart's caches, or to the first
+///online hart it is allowed on if it may no longer run there. A task allowed
+///on offline harts only may run on all harts from then on.
 pub fn add_task(task: Arc<TaskControlBlock>) {
-    TASK_MANAGER.exclusive_access().add(task);
+    let (cpu, cpus_allowed, mut manager) = loop {
+        let mut inner = task.inner_exclusive_access();
+        let online = online_hart_mask();
+        if inner.cpus_allowed & online == 0 {
+            info!(
+                "task {} no longer restricted to offline harts",
+                inner.comm()
+            );
+            inner.cpus_allowed = ALL_HARTS;
+        }
+        let cpu = if inner.allows(inner.cpu) && online & (1 << inner.cpu) != 0 {
+            inner.cpu
+        } else {
+            (inner.cpus_allowed & online).trailing_zeros() as usize
+        };
+        let cpus_allowed = inner.cpus_allowed;
+        drop(inner);
+        let manager = TASK_MANAGERS[cpu].lock();
+        // a hart going offline moves its tasks away once no longer online
+        if online_hart_mask() & (1 << cpu) != 0 {
+            break (cpu, cpus_allowed, manager);
+        }
+    };
+    manager.add(task);
+    let queued = manager.len();
+    drop(manager);
+    // an idle hart would not notice it until its next timer interrupt. The only task
+    // queued on this hart, e.g. put back when its time slice is used up, is left to it.
+    if cpu != hart_id() || queued > 1 {
+        wake_idle_hart(cpu, cpus_allowed);
+    }
 }
-///Interface offered to pop the first task
+///Interface offered to pop the first task of the current hart, or to steal
+///one allowed on it from the hart with the most ready tasks if there is none
 pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
-    TASK_MANAGER.exclusive_access().fetch()
+    let me = hart_id();
+    loop {
+        let task = TASK_MANAGERS[me].lock().fetch();
+        match task {
+            // its affinity changed while it was ready, move it to a hart it is allowed on
+            Some(task) if !task.inner_exclusive_access().allows(me) => add_task(task),
+            Some(task) => return Some(task),
+            None => break,
+        }
+    }
+    // lock one queue at a time, the busiest one may be drained in the meantime
+    let mut victims = [(0, 0); MAX_HARTS];
+    for (cpu, victim) in victims.iter_mut().enumerate() {
+        if cpu != me {
+            *victim = (TASK_MANAGERS[cpu].lock().len(), cpu);
+        }
+    }
+    victims.sort_unstable_by(|a, b| b.cmp(a));
+    victims
+        .iter()
+        .filter(|(len, _)| *len > 0)
+        .find_map(|(_, cpu)| TASK_MANAGERS[*cpu].lock().steal(me))
+}
+///Interface offered to move the ready tasks of the current hart, once offline,
+///to the online harts
+pub fn migrate_tasks() {
+    let tasks = TASK_MANAGERS[hart_id()].lock().drain();
+    for task in tasks {
+        add_task(task);
+    }
+}
+///Interface offered to remove a task that should no longer run
+pub fn remove_task(task: Arc<TaskControlBlock>) {
+    for manager in TASK_MANAGERS.iter() {
+        manager.lock().remove(Arc::clone(&task));
+    }
+}
+///Interface offered to get the number of times a hart waited for the ready queue of `cpu`
+pub fn ready_queue_contentions(cpu: usize) -> usize {
+    TASK_MANAGERS[cpu].contentions()
+}
+///Interface offered to get the time slice of a task in MLFQ queue `level`
+pub fn time_slice_ms(level: usize) -> usize {
+    TASK_MANAGERS[hart_id()].lock().time_slice_ms(level)
+}
+
+lazy_static! {
+    ///Processes by pid, from creation until they are reaped, so that lookups by pid
+    ///do not go through all processes or children. Zombies are found until reaped,
+    ///as their pid is not reused until then. Lookups are far more frequent than
+    ///forks and reaps, so they read it without a lock and updates copy it.
+    pub static ref PID2PCB: RcuCell<BTreeMap<usize, Arc<ProcessControlBlock>>> =
+        RcuCell::new(BTreeMap::new());
+}
+///Find the process with `pid`, which may be a zombie, `None` if there is none
+pub fn pid2process(pid: usize) -> Option<Arc<ProcessControlBlock>> {
+    PID2PCB.read().get(&pid).map(Arc::clone)
+}
+///Register a new process to be found by `pid2process`
+pub fn insert_into_pid2process(pid: usize, process: Arc<ProcessControlBlock>) {
+    PID2PCB.update(|map| map.insert(pid, process));
+}
+///Processes that have not been reaped, in the order of their pids
+pub fn all_processes() -> Vec<Arc<ProcessControlBlock>> {
+    PID2PCB.read().values().cloned().collect()
+}
+///Processes in process group `pgid` that have not been reaped
+pub fn process_group(pgid: usize) -> Vec<Arc<ProcessControlBlock>> {
+    PID2PCB
+        .read()
+        .values()
+        .filter(|process| process.inner_exclusive_access().pgid == pgid)
+        .cloned()
+        .collect()
+}
+///Unregister a process once reaped, or once exited if no parent reaps it
+pub fn remove_from_pid2process(pid: usize) {
+    PID2PCB.update(|map| map.remove(&pid));
+}
+
+/// Stride scheduling runs tasks in proportion to their priorities
+fn stride_test() {
+    let priorities = [2, 4, 8];
+    let mut manager = TaskManager::new(SchedPolicy::Stride);
+    let mut processes = Vec::new();
+    for &priority in priorities.iter() {
+        // never run, only scheduled by `manager`
+        let process = ProcessControlBlock::new_kthread("stride_test", || {}).unwrap();
+        let task = process.inner_exclusive_access().get_task(0);
+        remove_task(Arc::clone(&task));
+        task.inner_exclusive_access().priority = priority;
+        manager.add(task);
+        processes.push(process);
+    }
+    let mut runs = [0usize; 3];
+    for _ in 0..140 {
+        let task = manager.fetch().unwrap();
+        let i = processes
+            .iter()
+            .position(|process| Arc::ptr_eq(&process.inner_exclusive_access().get_task(0), &task))
+            .unwrap();
+        runs[i] += 1;
+        manager.add(task);
+    }
+    assert_eq!(runs, [20, 40, 80]);
+    while manager.fetch().is_some() {}
+    for process in processes {
+        process.inner_exclusive_access().tasks.clear();
+        remove_from_pid2process(process.getpid());
+    }
 }
+kernel_test!(stride_test);
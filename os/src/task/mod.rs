@@ -3,75 +3,314 @@
 //! Everything about task management, like starting and switching tasks is
 //! implemented here.
 //!
-//! A single global instance of [`TaskManager`] called `TASK_MANAGER` controls
-//! all the tasks in the whole operating system.
+//! An instance of [`TaskManager`] for each hart in `TASK_MANAGERS` holds the
+//! tasks ready to run on it. A hart with nothing to run steals from the hart
+//! with the most ready tasks.
 //!
-//! A single global instance of [`Processor`] called `PROCESSOR` monitors running
-//! task(s) for each core.
+//! An instance of [`Processor`] for each hart in `PROCESSORS` monitors the
+//! task running on it.
 //!
-//! A single global instance of [`PidAllocator`] called `PID_ALLOCATOR` allocates
+//! A single global instance of [`RecycleAllocator`] called `PID_ALLOCATOR` allocates
 //! pid for user apps.
 //!
+//! A process ([`ProcessControlBlock`]) owns the address space and files and
+//! has one or more threads ([`TaskControlBlock`]), which are what gets
+//! scheduled. Thread 0 is the main thread, the process exits with it. The
+//! address space and fd table are reference counted, so that processes
+//! created by `sys_clone` can share them.
+//!
 //! Be careful when you see `__switch` ASM function in `switch.S`. Control flow around this function
 //! might not be what you expect.
 mod context;
+mod fpu;
+mod id;
+mod kthread;
 mod manager;
-mod pid;
+mod process;
 mod processor;
+mod ptrace;
+mod sched_trace;
+mod signal;
 mod switch;
 #[allow(clippy::module_inception)]
 #[allow(rustdoc::private_intra_doc_links)]
 mod task;
+mod wait_queue;
+mod watchdog;
+mod workqueue;
 
+use crate::cmdline;
+use crate::config::CLOCK_FREQ;
 use crate::fs::{open_file, OpenFlags};
+use crate::sync::ExclusiveCell;
+use crate::timer::{cancel_timer, check_timer, get_time, slice_expired};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 pub use context::TaskContext;
+pub use fpu::{enable_current_fp, load_current_fp, FpContext};
+use id::TaskUserRes;
+pub use id::{kernel_stack_position, pid_alloc, KernelStack, PidHandle, RecycleAllocator};
+pub use kthread::kthread_spawn;
 use lazy_static::*;
-pub use manager::{fetch_task, TaskManager};
-use switch::__switch;
-use task::{TaskControlBlock, TaskStatus};
-
-pub use manager::add_task;
-pub use pid::{pid_alloc, KernelStack, PidAllocator, PidHandle};
+pub use manager::{
+    add_task, all_processes, fetch_task, insert_into_pid2process, pid2process, process_group,
+    ready_queue_contentions, remove_from_pid2process, remove_task, set_time_slice_ms,
+    time_slice_ms, SchedPolicy, TaskManager, MAX_NICE, MIN_NICE,
+};
+pub use process::{
+    AddressSpace, CloneFlags, FdTable, ProcessControlBlock, RLimit, CSIGNAL, RLIMIT_AS, RLIMIT_CPU,
+    RLIMIT_NOFILE, RLIMIT_STACK, RLIM_INFINITY, RLIM_NLIMITS,
+};
 pub use processor::{
-    current_task, current_trap_cx, current_user_token, run_tasks, schedule, take_current_task,
-    Processor,
+    current_process, current_task, current_trap_cx, current_trap_cx_user_va, current_user_token,
+    run_tasks, schedule, take_current_task, Processor,
+};
+use ptrace::{ptrace_stop, Ptrace};
+use riscv::register::sip;
+pub use sched_trace::{take_switches, SwitchEvent};
+pub use signal::{
+    current_has_signal, exit_current_by_signal, force_signal_current, handle_signals,
+    notify_parent_of_exit, send_signal, SignalAction, SignalFlags, SignalFrame, BUS_ADRALN,
+    BUS_ADRERR, ILL_ILLOPC, MAX_SIG, SA_NOCLDWAIT, SEGV_ACCERR, SEGV_MAPERR, SIG_DFL, SIG_IGN,
+    TRAP_BRKPT, UNCATCHABLE,
 };
+use switch::__switch;
+pub use task::{TaskControlBlock, TaskStatus, COMM_LEN};
+pub use wait_queue::WaitQueue;
+use watchdog::{watchdog_init, watchdog_switch_in};
+pub use watchdog::{watchdog_kernel_enter, watchdog_kernel_leave};
+pub use workqueue::{queue_work, workqueue_init};
+/// Create the slab caches of process and task control blocks
+pub fn init() {
+    if let Some(ms) = cmdline::parse::<usize>("timeslice").filter(|&ms| ms > 0) {
+        set_time_slice_ms(ms);
+    }
+    process::PROCESS_CACHE.init_arc();
+    task::TASK_CACHE.init_arc();
+    watchdog_init();
+}
+
 /// Suspend the current 'Running' task and run the next task in task list.
 pub fn suspend_current_and_run_next() {
     // There must be an application running.
-    let task = take_current_task().unwrap();
+    let task = current_task().unwrap();
 
     // ---- access current TCB exclusively
     let mut task_inner = task.inner_exclusive_access();
     let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
     // Change status to Ready
     task_inner.task_status = TaskStatus::Ready;
+    task_inner.ready_since = get_time();
+    task_inner.update_vruntime();
     drop(task_inner);
-    // ---- release current PCB
+    // ---- release current TCB
+    drop(task);
 
-    // push back to ready queue.
-    add_task(task);
+    // the idle control flow pushes it back to ready queue once switched out
     // jump to scheduling cycle
     schedule(task_cx_ptr);
 }
 
-/// pid of usertests app in make run TEST=1
+/// Block the current 'Running' task until `wakeup_task` and run the next task in task list.
+/// `enqueue` puts the task where it is woken up from, e.g. with `add_timer`. It runs
+/// with the TCB locked, so that another hart cannot wake the task up before it is blocked.
+pub fn block_current_and_run_next(enqueue: impl FnOnce(Arc<TaskControlBlock>)) {
+    let task = current_task().unwrap();
+    let mut task_inner = task.inner_exclusive_access();
+    let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
+    enqueue(Arc::clone(&task));
+    task_inner.task_status = TaskStatus::Blocked;
+    task_inner.blocked_since = get_time();
+    task_inner.hung_reported = false;
+    task_inner.update_vruntime();
+    drop(task_inner);
+    drop(task);
+    schedule(task_cx_ptr);
+}
+
+/// Charge the time since the current task last entered or left user mode or was
+/// switched to, to its user time if `user` and to its kernel time otherwise
+pub fn charge_current_time(user: bool) {
+    current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .charge_time(user);
+}
+
+/// Enforce `RLIMIT_CPU` on the process of the current task, checked at each timer
+/// interrupt: send `SIGXCPU` once a second while its CPU time is over the soft
+/// limit, and `SIGKILL` once it is over the hard limit
+pub fn check_cpu_limit() {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let limit = inner.rlimits[RLIMIT_CPU];
+    // the soft limit is at most the hard one
+    if limit.rlim_cur == RLIM_INFINITY {
+        return;
+    }
+    let (utime, stime) = inner.cpu_times();
+    let secs = (utime + stime) / CLOCK_FREQ;
+    let signal = if secs >= limit.rlim_max {
+        SignalFlags::SIGKILL
+    } else if secs >= limit.rlim_cur && inner.last_sigxcpu.map_or(true, |last| secs > last) {
+        inner.last_sigxcpu = Some(secs);
+        SignalFlags::SIGXCPU
+    } else {
+        return;
+    };
+    drop(inner);
+    send_signal(&process, signal.lowest_signum().unwrap());
+}
+
+/// Make a 'Blocked' task ready. If it has not switched out yet, the idle
+/// control flow of its hart adds it to the ready queue once it has.
+pub fn wakeup_task(task: Arc<TaskControlBlock>) {
+    let mut task_inner = task.inner_exclusive_access();
+    if task_inner.task_status != TaskStatus::Blocked {
+        return;
+    }
+    task_inner.task_status = TaskStatus::Ready;
+    task_inner.ready_since = get_time();
+    let on_cpu = task_inner.on_cpu;
+    drop(task_inner);
+    if !on_cpu {
+        requeue_task(task);
+    }
+}
+
+/// Add a switched-out 'Ready' task to the ready queue unless its process has
+/// exited, checked under the lock of the process so that the task cannot be
+/// added after the exiting main thread has removed the other threads. The
+/// exiting thread itself, having an exit code, is added until it is done.
+fn requeue_task(task: Arc<TaskControlBlock>) {
+    if let Some(process) = task.process.upgrade() {
+        let process_inner = process.inner_exclusive_access();
+        if !process_inner.is_zombie || task.inner_exclusive_access().exit_code.is_some() {
+            add_task(task);
+        }
+    }
+}
+
+/// Called by the idle control flow once `task` has switched out: add it back to
+/// the ready queue if `ready`, and if its process has exited, wake up the thread
+/// tearing the process down in [`exit_process`]
+fn task_switched_out(task: Arc<TaskControlBlock>, ready: bool) {
+    if ready {
+        requeue_task(Arc::clone(&task));
+    }
+    // unless it is that thread, the only one blocking with an exit code
+    let task_inner = task.inner_exclusive_access();
+    let tearing_down =
+        task_inner.exit_code.is_some() && task_inner.task_status == TaskStatus::Blocked;
+    drop(task_inner);
+    if tearing_down {
+        return;
+    }
+    if let Some(process) = task.process.upgrade() {
+        if process.inner_exclusive_access().is_zombie {
+            process.exit_wait.wake_all();
+        }
+    }
+}
+
+/// Suspend the current 'Running' task because its time slice is used up,
+/// and run the next task in task list.
+pub fn preempt_current_and_run_next() {
+    current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .slice_used_up = true;
+    suspend_current_and_run_next();
+}
+
+/// Whether the time slice of the current task is used up. The kernel runs with
+/// interrupts disabled, the timer interrupt stays pending until it is taken.
+pub fn need_resched() -> bool {
+    sip::read().stimer() && slice_expired()
+}
+
+/// A preemption point for long paths in the kernel: run the expired kernel timers
+/// and the next task if the time slice of the current one is used up, as a timer
+/// interrupt in user mode would. No lock may be held, the other tasks on this hart
+/// may need it.
+pub fn cond_resched() {
+    // not stuck as long as it passes preemption points
+    watchdog_kernel_enter();
+    if sip::read().stimer() {
+        check_timer();
+        if need_resched() {
+            preempt_current_and_run_next();
+        }
+    }
+}
+
+/// pid of the init program, whose exit powers the machine off
 pub const IDLE_PID: usize = 0;
 
 use crate::board::QEMUExit;
 
-/// Exit the current 'Running' task and run the next task in task list.
+/// Exit the current 'Running' thread and run the next task in task list.
+/// The whole process exits with it if it is the main thread.
 pub fn exit_current_and_run_next(exit_code: i32) {
-    // take from Processor
-    let task = take_current_task().unwrap();
+    exit_current(exit_code, None, false);
+}
+
+/// Exit the whole process of the current 'Running' thread, from any of its
+/// threads, and run the next task in task list.
+pub fn exit_group_current_and_run_next(exit_code: i32) {
+    exit_current(exit_code, None, true);
+}
+
+/// Exit the current thread, and its process if `group` or if it is the main
+/// thread, recording `exit_code` and `term_signal` for the parent
+fn exit_current(exit_code: i32, term_signal: Option<(usize, u8)>, group: bool) {
+    // the idle control flow releases it from Processor once switched out
+    let task = current_task().unwrap();
+    let mut task_inner = task.inner_exclusive_access();
+    let process = task.process.upgrade().unwrap();
+    // kernel threads have no user resources and are always the main thread
+    let tid = task_inner.res.as_ref().map_or(0, |res| res.tid);
+    // record exit code
+    task_inner.task_status = TaskStatus::Exited;
+    task_inner.exit_code = Some(exit_code);
+    // its CPU time is counted in the process from now on
+    task_inner.charge_time(false);
+    let (utime, stime) = (task_inner.utime, task_inner.stime);
+    // unmap the user stack and TrapContext, the kernel stack is still in use
+    // and is released when the thread is reaped by sys_waittid. Releasing them
+    // locks the PCB, so only after the TCB.
+    let res = task_inner.res.take();
+    drop(task_inner);
+    drop(res);
+    let mut process_inner = process.inner_exclusive_access();
+    process_inner.utime += utime;
+    process_inner.stime += stime;
+    drop(process_inner);
 
-    let pid = task.getpid();
+    // the process exits with its main thread
+    if group || tid == 0 {
+        exit_process(&process, &task, exit_code, term_signal);
+    }
+    drop(task);
+    // drop process manually to maintain rc correctly
+    drop(process);
+    // we do not have to save task context
+    let mut _unused = TaskContext::zero_init();
+    schedule(&mut _unused as *mut _);
+}
+
+/// Stop the threads of `process` other than `current`, the exiting thread, and
+/// release the resources of the process. Only the first thread to get here tears
+/// the process down, the others find it a zombie already and only exit themselves.
+fn exit_process(
+    process: &Arc<ProcessControlBlock>,
+    current: &Arc<TaskControlBlock>,
+    exit_code: i32,
+    term_signal: Option<(usize, u8)>,
+) {
+    let pid = process.getpid();
     if pid == IDLE_PID {
-        println!(
-            "[kernel] Idle process exit with exit_code {} ...",
-            exit_code
-        );
+        info!("Idle process exit with exit_code {} ...", exit_code);
         if exit_code != 0 {
             //crate::sbi::shutdown(255); //255 == -1 for err hint
             crate::board::QEMU_EXIT_HANDLE.exit_failure();
@@ -81,45 +320,136 @@ pub fn exit_current_and_run_next(exit_code: i32) {
         }
     }
 
-    // **** access current TCB exclusively
-    let mut inner = task.inner_exclusive_access();
-    // Change status to Zombie
-    inner.task_status = TaskStatus::Zombie;
+    // **** access current PCB exclusively
+    let mut inner = process.inner_exclusive_access();
+    if inner.is_zombie {
+        return;
+    }
+    // Change status to Zombie, so that the other threads are not put back
+    // to the ready queue
+    inner.is_zombie = true;
     // Record exit code
     inner.exit_code = exit_code;
+    inner.term_signal = term_signal;
+    let children = core::mem::take(&mut inner.children);
+    let tasks: Vec<_> = inner
+        .tasks
+        .iter()
+        .flatten()
+        .filter(|task| !Arc::ptr_eq(task, current))
+        .cloned()
+        .collect();
+    drop(inner);
+    // **** release current PCB
+
+    // stop the other threads, blocking until those running on other harts
+    // switch out, which they do at the latest at the end of their time slice
+    for task in tasks.iter() {
+        remove_task(Arc::clone(task));
+    }
+    process.exit_wait.wait_until_uninterruptible(|| {
+        tasks
+            .iter()
+            .all(|task| !task.inner_exclusive_access().on_cpu)
+    });
+    // running again if it blocked
+    current.inner_exclusive_access().task_status = TaskStatus::Exited;
+
     // do not move to its parent but under initproc
+    reparent_to_initproc(children);
 
-    // ++++++ access initproc TCB exclusively
-    {
-        let mut initproc_inner = INITPROC.inner_exclusive_access();
-        for child in inner.children.iter() {
-            child.inner_exclusive_access().parent = Some(Arc::downgrade(&INITPROC));
-            initproc_inner.children.push(child.clone());
+    // collect the user resources of the other threads, which are released
+    // after the PCB since releasing them accesses it
+    let recycle_res: Vec<TaskUserRes> = tasks
+        .iter()
+        .filter_map(|task| task.inner_exclusive_access().res.take())
+        .collect();
+    // keep only the exiting thread, whose kernel stack is still in use, and
+    // count the CPU time of the stopped threads in the process
+    let mut inner = process.inner_exclusive_access();
+    for task in tasks.iter() {
+        let task_inner = task.inner_exclusive_access();
+        if task_inner.exit_code.is_none() {
+            inner.utime += task_inner.utime;
+            inner.stime += task_inner.stime;
         }
     }
-    // ++++++ release parent PCB
+    inner.tasks.retain(|task| {
+        task.as_ref()
+            .map_or(false, |task| Arc::ptr_eq(task, current))
+    });
+    drop(inner);
+    drop(tasks);
+    drop(recycle_res);
 
-    inner.children.clear();
-    // deallocate user space
-    inner.memory_set.recycle_data_pages();
+    let mut inner = process.inner_exclusive_access();
+    // deallocate user space, unless other processes still use it
+    if Arc::strong_count(&inner.address_space) == 1 {
+        inner
+            .address_space
+            .exclusive_access()
+            .memory_set
+            .recycle_data_pages();
+    }
+    // close files, unless other processes share the fd table
+    inner.fd_table = Arc::new(ExclusiveCell::new(Vec::new()));
+    // disarm the real interval timer, rearmed for good if periodic
+    if let Some(itimer) = inner.itimer.take() {
+        cancel_timer(itimer);
+    }
     drop(inner);
-    // **** release current PCB
-    // drop task manually to maintain rc correctly
-    drop(task);
-    // we do not have to save task context
-    let mut _unused = TaskContext::zero_init();
-    schedule(&mut _unused as *mut _);
+
+    notify_parent_of_exit(process);
+}
+
+/// Move the children of an exiting process under initproc, which reaps them.
+/// Each child is adopted under its own lock, as it may be exiting on another
+/// hart: then it notifies initproc if it sees its new parent, otherwise it has
+/// already exited and initproc is notified here instead of the old parent.
+/// The children that asked for it with prctl get their parent death signal.
+/// Traced children are detached, resuming a stopped thread without a signal.
+fn reparent_to_initproc(children: Vec<Arc<ProcessControlBlock>>) {
+    let mut zombies = Vec::new();
+    for child in children.iter() {
+        let mut child_inner = child.inner_exclusive_access();
+        child_inner.parent = Some(Arc::downgrade(&INITPROC));
+        let pdeathsig = child_inner.pdeathsig;
+        if child_inner.is_zombie {
+            zombies.push(Arc::clone(child));
+        }
+        child_inner.ptrace.traced = false;
+        let resumed = child_inner.ptrace.resume(0);
+        drop(child_inner);
+        if resumed {
+            child.ptrace_wait.wake_all();
+        }
+        if pdeathsig != 0 {
+            send_signal(child, pdeathsig);
+        }
+    }
+    INITPROC.inner_exclusive_access().children.extend(children);
+    for zombie in zombies.iter() {
+        notify_parent_of_exit(zombie);
+    }
 }
 
 lazy_static! {
-    ///Globle process that init user shell
-    pub static ref INITPROC: Arc<TaskControlBlock> = Arc::new({
-        let inode = open_file("initproc", OpenFlags::RDONLY).unwrap();
+    ///Globle process that init user shell, `initproc` unless another program is
+    ///given by `init=` or `test` on the command line
+    pub static ref INITPROC: Arc<ProcessControlBlock> = {
+        let name = match cmdline::get("init") {
+            Some(name) => name,
+            None if cmdline::flag("test") => "usertests",
+            None => "initproc",
+        };
+        let inode = open_file(name, OpenFlags::RDONLY)
+            .unwrap_or_else(|| panic!("no init program {}", name));
         let v = inode.read_all();
-        TaskControlBlock::new(v.as_slice())
-    });
+        ProcessControlBlock::new(name, v.as_slice())
+    };
 }
 ///Add init process to the manager
 pub fn add_initproc() {
-    add_task(INITPROC.clone());
+    // creating the process adds its main thread to the manager
+    let _initproc = INITPROC.clone();
 }
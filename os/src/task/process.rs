@@ -0,0 +1,680 @@
+//!Implementation of [`ProcessControlBlock`]
+use super::id::RecycleAllocator;
+use super::{
+    add_task, current_task, insert_into_pid2process, pid_alloc, remove_from_pid2process, PidHandle,
+    Ptrace, TaskControlBlock, TaskUserRes, WaitQueue,
+};
+use super::{SignalAction, MAX_SIG, SIG_IGN};
+use crate::config::{PAGE_SIZE, USER_AS_LIMIT, USER_NOFILE_LIMIT, USER_STACK_SIZE};
+use crate::fs::{File, Stdin, Stdout};
+use crate::mm::{copy_slice_to_user, LoadError, MemorySet, ObjectCache, VirtAddr, KERNEL_SPACE};
+use crate::sync::{
+    Condvar, DeadlockDetector, ExclusiveCell, ExclusiveCellGuard, Mutex, RwLock, Semaphore,
+};
+use crate::timer::TimerId;
+use crate::trap::{trap_handler, TrapContext};
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Open files of a process indexed by fd, shared by the processes cloned with `CLONE_FILES`
+pub type FdTable = Vec<Option<Arc<dyn File + Send + Sync>>>;
+
+/// Address space of a process with its heap and the slots of the user stacks and
+/// TrapContexts of its threads, shared by the processes cloned with `CLONE_VM`
+pub struct AddressSpace {
+    pub memory_set: MemorySet,
+    /// allocates slots to the threads in the address space
+    pub slot_allocator: RecycleAllocator,
+    pub heap_bottom: usize,
+    pub program_brk: usize,
+}
+
+impl AddressSpace {
+    /// Wrap `memory_set` with an empty heap starting at `heap_bottom`
+    pub fn new(memory_set: MemorySet, heap_bottom: usize) -> Self {
+        Self {
+            memory_set,
+            slot_allocator: RecycleAllocator::new(),
+            heap_bottom,
+            program_brk: heap_bottom,
+        }
+    }
+    /// change the location of the program break. return None if failed.
+    pub fn change_program_brk(&mut self, size: i32) -> Option<usize> {
+        let old_break = self.program_brk;
+        let new_brk = self.program_brk as isize + size as isize;
+        if new_brk < self.heap_bottom as isize {
+            return None;
+        }
+        let result = if size < 0 {
+            self.memory_set
+                .shrink_to(VirtAddr(self.heap_bottom), VirtAddr(new_brk as usize))
+        } else {
+            self.memory_set
+                .append_to(VirtAddr(self.heap_bottom), VirtAddr(new_brk as usize))
+        };
+        if result {
+            self.program_brk = new_brk as usize;
+            Some(old_break)
+        } else {
+            None
+        }
+    }
+}
+
+bitflags! {
+    /// Resources shared with the child by `sys_clone`, with the values of Linux
+    pub struct CloneFlags: u32 {
+        /// share the address space
+        const CLONE_VM = 0x100;
+        /// share the fd table
+        const CLONE_FILES = 0x400;
+        /// create a thread of the same process, requires `CLONE_VM` and `CLONE_FILES`
+        const CLONE_THREAD = 0x10000;
+    }
+}
+
+/// Bits of the `sys_clone` flags holding the signal sent to the parent on exit
+pub const CSIGNAL: u32 = 0xff;
+
+/// Resources limited by `sys_setrlimit`, numbered as in Linux: CPU time in seconds
+pub const RLIMIT_CPU: usize = 0;
+/// Size of the user stack of a thread
+pub const RLIMIT_STACK: usize = 3;
+/// Number of open files, one more than the highest fd
+pub const RLIMIT_NOFILE: usize = 7;
+/// Bytes mapped in the address space
+pub const RLIMIT_AS: usize = 9;
+/// Number of resources, including those whose limits are only recorded
+pub const RLIM_NLIMITS: usize = 16;
+/// Limit that does not limit
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+/// Soft and hard limit on a resource, as `struct rlimit` of POSIX
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct RLimit {
+    /// limit enforced
+    pub rlim_cur: usize,
+    /// ceiling of `rlim_cur`, which can be lowered but not raised
+    pub rlim_max: usize,
+}
+
+/// Limits of the processes created from an ELF by the kernel, inherited by their children
+fn default_rlimits() -> [RLimit; RLIM_NLIMITS] {
+    let fixed = |limit| RLimit {
+        rlim_cur: limit,
+        rlim_max: limit,
+    };
+    let mut rlimits = [fixed(RLIM_INFINITY); RLIM_NLIMITS];
+    rlimits[RLIMIT_STACK] = fixed(USER_STACK_SIZE);
+    rlimits[RLIMIT_NOFILE] = fixed(USER_NOFILE_LIMIT);
+    rlimits[RLIMIT_AS] = fixed(USER_AS_LIMIT);
+    rlimits
+}
+
+/// Environment of the processes created from an ELF by the kernel, i.e. initproc
+const INIT_ENVIRON: &[&str] = &["PATH=/", "HOME=/"];
+
+/// Lay out `argv` and `envp` at the top of the user stack ending at `stack_top`, as in
+/// Linux: argc at the returned stack pointer, then the pointers of `argv` and of `envp`,
+/// each ending with a null pointer, and the `\0`-terminated strings above them.
+/// Return the stack pointer and the addresses of the argv and envp pointer arrays.
+fn push_args(
+    token: usize,
+    stack_top: usize,
+    argv: &[String],
+    envp: &[String],
+) -> (usize, usize, usize) {
+    let mut strings: Vec<u8> = Vec::new();
+    let strings_base = stack_top - argv.iter().chain(envp).map(|s| s.len() + 1).sum::<usize>();
+    let mut words = vec![argv.len()];
+    for list in [argv, envp] {
+        for string in list.iter() {
+            words.push(strings_base + strings.len());
+            strings.extend_from_slice(string.as_bytes());
+            strings.push(0);
+        }
+        words.push(0);
+    }
+    let sp = (strings_base - words.len() * core::mem::size_of::<usize>()) & !0xf;
+    copy_slice_to_user(token, strings_base as *mut u8, &strings).unwrap();
+    copy_slice_to_user(token, sp as *mut usize, &words).unwrap();
+    let argv_base = sp + core::mem::size_of::<usize>();
+    let envp_base = argv_base + (argv.len() + 1) * core::mem::size_of::<usize>();
+    (sp, argv_base, envp_base)
+}
+
+/// Slab cache of process control blocks
+pub(super) static PROCESS_CACHE: ObjectCache<ProcessControlBlock> = ObjectCache::new();
+
+/// A process, owning an address space, files and one or more threads
+///
+/// The PCB is locked before the TCB of any of its threads when both are held, as in
+/// [`ProcessControlBlockInner::thread_count`]. The locks spin, so taking them the other
+/// way round may deadlock with another hart. Releasing the [`TaskUserRes`] of a thread
+/// locks the PCB, so it is never dropped under the TCB.
+pub struct ProcessControlBlock {
+    // immutable
+    pub pid: PidHandle,
+    /// threads stopped for the tracer, see `ptrace.rs`
+    pub ptrace_wait: WaitQueue,
+    /// the thread tearing the process down, waiting for the others to switch out
+    pub exit_wait: WaitQueue,
+    // mutable
+    inner: ExclusiveCell<ProcessControlBlockInner>,
+}
+
+pub struct ProcessControlBlockInner {
+    pub is_zombie: bool,
+    pub address_space: Arc<ExclusiveCell<AddressSpace>>,
+    pub parent: Option<Weak<ProcessControlBlock>>,
+    pub children: Vec<Arc<ProcessControlBlock>>,
+    pub exit_code: i32,
+    /// process group, the pid of its leader
+    pub pgid: usize,
+    /// session, the pid of its leader
+    pub sid: usize,
+    /// timer ticks spent in user mode by the threads that have exited
+    pub utime: usize,
+    /// timer ticks spent in the kernel by the threads that have exited
+    pub stime: usize,
+    /// timer ticks spent in user mode by the reaped children and their reaped children
+    pub cutime: usize,
+    /// timer ticks spent in the kernel by the reaped children and their reaped children
+    pub cstime: usize,
+    /// signal sent to the process when its parent exits, 0 for none
+    pub pdeathsig: usize,
+    /// signal that killed the process and the `si_code` of the fault raising it,
+    /// 0 if it was sent, reported instead of `exit_code`
+    pub term_signal: Option<(usize, u8)>,
+    /// actions of signals, indexed by signal number
+    pub signal_actions: [SignalAction; MAX_SIG + 1],
+    /// kernel timer raising `SIGALRM` for the real interval timer, `None` if disarmed
+    pub itimer: Option<TimerId>,
+    /// period of the real interval timer in timer ticks, 0 for a one-shot timer
+    pub itimer_interval: usize,
+    pub fd_table: Arc<ExclusiveCell<FdTable>>,
+    /// threads indexed by tid, `None` once reaped by `sys_waittid`
+    pub tasks: Vec<Option<Arc<TaskControlBlock>>>,
+    pub task_res_allocator: RecycleAllocator,
+    /// mutexes created by `sys_mutex_create`, indexed by id
+    pub mutex_list: Vec<Option<Arc<dyn Mutex>>>,
+    /// semaphores created by `sys_semaphore_create`, indexed by id
+    pub semaphore_list: Vec<Option<Arc<Semaphore>>>,
+    /// condition variables created by `sys_condvar_create`, indexed by id
+    pub condvar_list: Vec<Option<Arc<Condvar>>>,
+    /// reader-writer locks created by `sys_rwlock_create`, indexed by id
+    pub rwlock_list: Vec<Option<Arc<RwLock>>>,
+    /// holders and waiters of the mutexes and semaphores
+    pub deadlock_detector: DeadlockDetector,
+    /// limits on resources, indexed by `RLIMIT_*`
+    pub rlimits: [RLimit; RLIM_NLIMITS],
+    /// CPU time in seconds when `SIGXCPU` was last sent for exceeding `RLIMIT_CPU`
+    pub last_sigxcpu: Option<usize>,
+    /// `NAME=value` strings passed to the program by exec, inherited by children and
+    /// by the next program unless exec is given another environment
+    pub environ: Vec<String>,
+    /// tracing by the parent
+    pub ptrace: Ptrace,
+    /// may reboot or power off the machine and take harts offline, given to the init
+    /// program and to the children created while their parent has `grant_machine_control`
+    pub machine_control: bool,
+    /// whether the children created from now on get `machine_control`
+    pub grant_machine_control: bool,
+}
+
+impl ProcessControlBlockInner {
+    pub fn get_user_token(&self) -> usize {
+        self.address_space.exclusive_access().memory_set.token()
+    }
+    /// Status word reported to the parent by `sys_waitpid`, encoded as in Linux:
+    /// the low 8 bits of the exit code in bits 8..16 if the process exited,
+    /// the number of the signal that killed it in bits 0..7 otherwise. Unlike
+    /// Linux, a signal raised by a fault comes with its `si_code` in bits 8..16,
+    /// which tells e.g. an unmapped address from a write to a read-only page.
+    pub fn wait_status(&self) -> i32 {
+        match self.term_signal {
+            Some((signal, code)) => (signal & 0x7f) as i32 | (code as i32) << 8,
+            None => (self.exit_code & 0xff) << 8,
+        }
+    }
+    /// Timer ticks spent in user mode and in the kernel by the threads of the process,
+    /// including those that have exited
+    pub fn cpu_times(&self) -> (usize, usize) {
+        self.tasks
+            .iter()
+            .flatten()
+            .map(|task| {
+                let task_inner = task.inner_exclusive_access();
+                match task_inner.exit_code {
+                    // counted in the process once exited
+                    Some(_) => (0, 0),
+                    None => (task_inner.utime, task_inner.stime),
+                }
+            })
+            .fold((self.utime, self.stime), |(utime, stime), (u, s)| {
+                (utime + u, stime + s)
+            })
+    }
+    /// Put `file` at the lowest free fd and return the fd, `None` if it would be
+    /// beyond `RLIMIT_NOFILE`
+    pub fn alloc_fd(&self, file: Arc<dyn File + Send + Sync>) -> Option<usize> {
+        let mut fd_table = self.fd_table.exclusive_access();
+        let fd = (0..fd_table.len())
+            .find(|fd| fd_table[*fd].is_none())
+            .unwrap_or(fd_table.len());
+        if fd >= self.rlimits[RLIMIT_NOFILE].rlim_cur {
+            return None;
+        }
+        if fd == fd_table.len() {
+            fd_table.push(None);
+        }
+        fd_table[fd] = Some(file);
+        Some(fd)
+    }
+    /// The file at `fd`, `None` if it is not open
+    pub fn get_file(&self, fd: usize) -> Option<Arc<dyn File + Send + Sync>> {
+        self.fd_table.exclusive_access().get(fd)?.clone()
+    }
+    pub fn alloc_tid(&mut self) -> usize {
+        self.task_res_allocator.alloc()
+    }
+    pub fn dealloc_tid(&mut self, tid: usize) {
+        self.task_res_allocator.dealloc(tid)
+    }
+    /// Number of threads that have not exited yet
+    pub fn thread_count(&self) -> usize {
+        self.tasks
+            .iter()
+            .flatten()
+            .filter(|task| task.inner_exclusive_access().exit_code.is_none())
+            .count()
+    }
+    pub fn get_task(&self, tid: usize) -> Arc<TaskControlBlock> {
+        self.tasks[tid].as_ref().unwrap().clone()
+    }
+    /// Check whether mapping `grow` more bytes would exceed the address space limit
+    pub fn exceeds_as_limit(&self, grow: usize) -> bool {
+        match grow.checked_add(PAGE_SIZE - 1) {
+            Some(grow) => {
+                let mapped_size = self
+                    .address_space
+                    .exclusive_access()
+                    .memory_set
+                    .mapped_size();
+                mapped_size + grow / PAGE_SIZE * PAGE_SIZE > self.rlimits[RLIMIT_AS].rlim_cur
+            }
+            None => true,
+        }
+    }
+}
+
+impl ProcessControlBlock {
+    pub fn inner_exclusive_access(&self) -> ExclusiveCellGuard<'_, ProcessControlBlockInner> {
+        self.inner.exclusive_access()
+    }
+    /// Create a process without any thread in `address_space` with the files in `fd_table`,
+    /// leading a new process group and session
+    fn from_resources(
+        address_space: Arc<ExclusiveCell<AddressSpace>>,
+        parent: Option<Weak<ProcessControlBlock>>,
+        fd_table: Arc<ExclusiveCell<FdTable>>,
+    ) -> Arc<Self> {
+        let pid = pid_alloc();
+        let pgid = pid.0;
+        let process = PROCESS_CACHE.new_arc(Self {
+            pid,
+            ptrace_wait: WaitQueue::new(),
+            exit_wait: WaitQueue::new(),
+            inner: ExclusiveCell::new(ProcessControlBlockInner {
+                is_zombie: false,
+                address_space,
+                parent,
+                children: Vec::new(),
+                exit_code: 0,
+                pgid,
+                sid: pgid,
+                utime: 0,
+                stime: 0,
+                cutime: 0,
+                cstime: 0,
+                pdeathsig: 0,
+                term_signal: None,
+                signal_actions: [SignalAction::default(); MAX_SIG + 1],
+                itimer: None,
+                itimer_interval: 0,
+                fd_table,
+                tasks: Vec::new(),
+                task_res_allocator: RecycleAllocator::new(),
+                mutex_list: Vec::new(),
+                semaphore_list: Vec::new(),
+                condvar_list: Vec::new(),
+                rwlock_list: Vec::new(),
+                deadlock_detector: DeadlockDetector::new(),
+                rlimits: default_rlimits(),
+                last_sigxcpu: None,
+                environ: Vec::new(),
+                ptrace: Ptrace::default(),
+                machine_control: false,
+                grant_machine_control: false,
+            }),
+        });
+        insert_into_pid2process(process.getpid(), Arc::clone(&process));
+        process
+    }
+    /// Create a process named `name` from `elf_data` with the environment `INIT_ENVIRON`
+    /// and add its main thread to the scheduler
+    pub fn new(name: &str, elf_data: &[u8]) -> Arc<Self> {
+        // memory_set with elf program headers/trampoline/heap
+        let (memory_set, heap_bottom, entry_point) = MemorySet::from_elf(elf_data)
+            .unwrap_or_else(|err| panic!("cannot load {}: {:?}", name, err));
+        let fd_table: FdTable = vec![
+            // 0 -> stdin
+            Some(Arc::new(Stdin)),
+            // 1 -> stdout
+            Some(Arc::new(Stdout)),
+            // 2 -> stderr
+            Some(Arc::new(Stdout)),
+        ];
+        let process = Self::from_resources(
+            Arc::new(ExclusiveCell::new(AddressSpace::new(
+                memory_set,
+                heap_bottom,
+            ))),
+            None,
+            Arc::new(ExclusiveCell::new(fd_table)),
+        );
+        // create the main thread with its user stack and TrapContext
+        let task = TaskControlBlock::new(Arc::clone(&process), None).expect("Run out of frames!");
+        task.inner_exclusive_access().set_comm(name.as_bytes());
+        let mut inner = process.inner_exclusive_access();
+        inner.environ = INIT_ENVIRON.iter().map(|var| String::from(*var)).collect();
+        // the init program controls the machine
+        inner.machine_control = true;
+        drop(inner);
+        process.init_main_thread(&task, entry_point, &[]);
+        process
+            .inner_exclusive_access()
+            .tasks
+            .push(Some(task.clone()));
+        add_task(task);
+        process
+    }
+    /// Prepare the TrapContext of `task`, the new main thread, to enter the program at
+    /// `entry_point` with `argv` and the environment of the process on its user stack,
+    /// their addresses in a1 and a2
+    fn init_main_thread(&self, task: &Arc<TaskControlBlock>, entry_point: usize, argv: &[String]) {
+        let ustack_top = task
+            .inner_exclusive_access()
+            .res
+            .as_ref()
+            .unwrap()
+            .ustack_top();
+        let inner = self.inner_exclusive_access();
+        let (user_sp, argv_base, envp_base) =
+            push_args(inner.get_user_token(), ustack_top, argv, &inner.environ);
+        drop(inner);
+        let task_inner = task.inner_exclusive_access();
+        let trap_cx = task_inner.get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.exclusive_access().token(),
+            task.kernel_stack.get_top(),
+            trap_handler as usize,
+        );
+        trap_cx.x[11] = argv_base;
+        trap_cx.x[12] = envp_base;
+    }
+    /// Create a process with an empty address space whose only thread, named `name`,
+    /// runs `entry` in kernel mode, and add the thread to the scheduler. `None` if out
+    /// of frames.
+    pub fn new_kthread(name: &str, entry: fn()) -> Option<Arc<Self>> {
+        let process = Self::from_resources(
+            Arc::new(ExclusiveCell::new(AddressSpace::new(
+                MemorySet::new_bare()?,
+                0,
+            ))),
+            None,
+            Arc::new(ExclusiveCell::new(Vec::new())),
+        );
+        let task = TaskControlBlock::new_kthread(&process, entry)?;
+        task.inner_exclusive_access().set_comm(name.as_bytes());
+        process
+            .inner_exclusive_access()
+            .tasks
+            .push(Some(task.clone()));
+        add_task(task);
+        Some(process)
+    }
+    /// Replace the address space with a new one loaded from `elf_data`, leaving the
+    /// old one to the other processes sharing it, and pass `argv` and `envp` on the user
+    /// stack, their addresses in a1 and a2. `envp` becomes the environment of the process.
+    /// Only a process with a single thread left can exec. Keep the old address space
+    /// if the program cannot be loaded.
+    pub fn exec(
+        self: &Arc<Self>,
+        elf_data: &[u8],
+        argv: &[String],
+        envp: Vec<String>,
+    ) -> Result<(), LoadError> {
+        assert_eq!(self.inner_exclusive_access().thread_count(), 1);
+        // memory_set with elf program headers/trampoline/heap
+        let (memory_set, heap_bottom, entry_point) = MemorySet::from_elf(elf_data)?;
+        // the heap starts empty right after the program
+        let mut address_space = AddressSpace::new(memory_set, heap_bottom);
+        // the main thread keeps its tid, map its user stack and TrapContext again
+        let slot = address_space.slot_allocator.alloc();
+        if !TaskUserRes::alloc_user_res(slot, &mut address_space.memory_set) {
+            return Err(LoadError::OutOfMemory);
+        }
+
+        // **** access current PCB exclusively
+        let mut inner = self.inner_exclusive_access();
+        let task = inner.get_task(0);
+        // substitute the address space
+        let old_space = core::mem::replace(
+            &mut inner.address_space,
+            Arc::new(ExclusiveCell::new(address_space)),
+        );
+        inner.environ = envp;
+        // handlers are gone with the old program, ignored signals stay ignored
+        for action in inner.signal_actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SignalAction::default();
+            }
+        }
+        drop(inner);
+        // **** release current PCB
+
+        // **** access the TCB of the main thread exclusively
+        // release the user stack and TrapContext in the old address space
+        let mut task_inner = task.inner_exclusive_access();
+        let res = task_inner.res.as_mut().unwrap();
+        res.move_to_slot(slot, &mut old_space.exclusive_access());
+        let trap_cx_va = VirtAddr::from(res.trap_cx_user_va());
+        drop(task_inner);
+        // **** release the TCB
+        drop(old_space);
+        // update trap_cx ppn, looked up in the new address space
+        let trap_cx_ppn = self
+            .inner_exclusive_access()
+            .address_space
+            .exclusive_access()
+            .memory_set
+            .translate(trap_cx_va.into())
+            .unwrap()
+            .ppn();
+        task.inner_exclusive_access().trap_cx_ppn = trap_cx_ppn;
+        // initialize trap_cx
+        self.init_main_thread(&task, entry_point, argv);
+        Ok(())
+    }
+    /// Create a child process whose main thread is a copy of the current thread, `None`
+    /// if out of frames. The child shares the address space with `CLONE_VM` and the fd
+    /// table with `CLONE_FILES`, otherwise they are copied. Only a process with a single
+    /// thread left can copy its address space.
+    pub fn clone_process(self: &Arc<Self>, flags: CloneFlags) -> Option<Arc<Self>> {
+        let caller = current_task().unwrap();
+        let mut caller_inner = caller.inner_exclusive_access();
+        let caller_slot = caller_inner.res.as_ref().unwrap().slot;
+        let priority = caller_inner.priority;
+        let nice = caller_inner.nice;
+        let signal_mask = caller_inner.signal_mask;
+        let cpus_allowed = caller_inner.cpus_allowed;
+        let comm = caller_inner.comm;
+        let kcov = caller_inner.kcov.clone();
+        // the F registers of the caller are live, save them for the child
+        let caller_trap_cx = caller_inner.get_trap_cx();
+        caller_inner.fp.save(&mut caller_trap_cx.sstatus);
+        let fp = caller_inner.fp.fork();
+        let trap_cx = caller_trap_cx.clone();
+        drop(caller_inner);
+        // ---- hold parent PCB lock
+        let mut parent_inner = self.inner_exclusive_access();
+        let (address_space, slot) = if flags.contains(CloneFlags::CLONE_VM) {
+            // the main thread of the child gets a new slot in the shared address space
+            (Arc::clone(&parent_inner.address_space), None)
+        } else {
+            assert_eq!(parent_inner.thread_count(), 1);
+            let space = parent_inner.address_space.exclusive_access();
+            // copy user space(include trap context), the main thread of the child
+            // takes over the user stack and TrapContext of the caller
+            let copy = AddressSpace {
+                memory_set: MemorySet::from_existed_user(&space.memory_set)?,
+                slot_allocator: space.slot_allocator.clone(),
+                heap_bottom: space.heap_bottom,
+                program_brk: space.program_brk,
+            };
+            drop(space);
+            (Arc::new(ExclusiveCell::new(copy)), Some(caller_slot))
+        };
+        let fd_table = if flags.contains(CloneFlags::CLONE_FILES) {
+            Arc::clone(&parent_inner.fd_table)
+        } else {
+            let copy = parent_inner.fd_table.exclusive_access().clone();
+            Arc::new(ExclusiveCell::new(copy))
+        };
+        let child = Self::from_resources(address_space, Some(Arc::downgrade(self)), fd_table);
+        {
+            let mut child_inner = child.inner_exclusive_access();
+            child_inner.rlimits = parent_inner.rlimits;
+            child_inner.pgid = parent_inner.pgid;
+            child_inner.sid = parent_inner.sid;
+            child_inner.environ = parent_inner.environ.clone();
+            child_inner.signal_actions = parent_inner.signal_actions;
+            child_inner.machine_control = parent_inner.grant_machine_control;
+        }
+        let task = TaskControlBlock::new(Arc::clone(&child), slot)?;
+        // add child
+        parent_inner.children.push(child.clone());
+        drop(parent_inner);
+        // ---- release parent PCB
+        // **** access child TCB exclusively
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.priority = priority;
+        task_inner.nice = nice;
+        task_inner.signal_mask = signal_mask;
+        task_inner.cpus_allowed = cpus_allowed;
+        task_inner.comm = comm;
+        task_inner.kcov = kcov;
+        task_inner.fp = fp;
+        // the child returns from the same syscall on its own kernel stack
+        let child_trap_cx = task_inner.get_trap_cx();
+        *child_trap_cx = trap_cx;
+        child_trap_cx.kernel_sp = task.kernel_stack.get_top();
+        drop(task_inner);
+        // **** release child TCB
+        child.inner_exclusive_access().tasks.push(Some(task));
+        Some(child)
+    }
+    /// Create a child process running the program in `elf_data` with `argv` and the
+    /// environment `envp`, as fork followed by exec in the child would, but without
+    /// copying the address space, and add its main thread to the scheduler. The child
+    /// gets a copy of the fd table, and its main thread the scheduling parameters and
+    /// signal mask of the current thread.
+    pub fn spawn(
+        self: &Arc<Self>,
+        name: &str,
+        elf_data: &[u8],
+        argv: &[String],
+        envp: Vec<String>,
+    ) -> Result<Arc<Self>, LoadError> {
+        let (memory_set, heap_bottom, entry_point) = MemorySet::from_elf(elf_data)?;
+        let caller = current_task().unwrap();
+        let caller_inner = caller.inner_exclusive_access();
+        let priority = caller_inner.priority;
+        let nice = caller_inner.nice;
+        let signal_mask = caller_inner.signal_mask;
+        let cpus_allowed = caller_inner.cpus_allowed;
+        let kcov = caller_inner.kcov.clone();
+        drop(caller_inner);
+        let mut parent_inner = self.inner_exclusive_access();
+        let fd_table = parent_inner.fd_table.exclusive_access().clone();
+        let child = Self::from_resources(
+            Arc::new(ExclusiveCell::new(AddressSpace::new(
+                memory_set,
+                heap_bottom,
+            ))),
+            Some(Arc::downgrade(self)),
+            Arc::new(ExclusiveCell::new(fd_table)),
+        );
+        {
+            let mut child_inner = child.inner_exclusive_access();
+            child_inner.rlimits = parent_inner.rlimits;
+            child_inner.pgid = parent_inner.pgid;
+            child_inner.sid = parent_inner.sid;
+            child_inner.environ = envp;
+            child_inner.machine_control = parent_inner.grant_machine_control;
+            // as after exec, only ignored signals stay ignored
+            for (action, parent_action) in child_inner
+                .signal_actions
+                .iter_mut()
+                .zip(parent_inner.signal_actions.iter())
+            {
+                if parent_action.handler == SIG_IGN {
+                    *action = *parent_action;
+                }
+            }
+        }
+        let task = match TaskControlBlock::new(Arc::clone(&child), None) {
+            Some(task) => task,
+            None => {
+                drop(parent_inner);
+                remove_from_pid2process(child.getpid());
+                return Err(LoadError::OutOfMemory);
+            }
+        };
+        parent_inner.children.push(Arc::clone(&child));
+        drop(parent_inner);
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.priority = priority;
+        task_inner.nice = nice;
+        task_inner.signal_mask = signal_mask;
+        task_inner.cpus_allowed = cpus_allowed;
+        task_inner.set_comm(name.as_bytes());
+        task_inner.kcov = kcov;
+        drop(task_inner);
+        child.init_main_thread(&task, entry_point, argv);
+        child
+            .inner_exclusive_access()
+            .tasks
+            .push(Some(task.clone()));
+        add_task(task);
+        Ok(child)
+    }
+    pub fn getpid(&self) -> usize {
+        self.pid.0
+    }
+    /// pid of the parent, 0 if it has none
+    pub fn getppid(&self) -> usize {
+        self.inner_exclusive_access()
+            .parent
+            .as_ref()
+            .and_then(Weak::upgrade)
+            .map_or(0, |parent| parent.getpid())
+    }
+}
@@ -1,11 +1,21 @@
 //!Implementation of [`Processor`] and Intersection of control flow
 use super::__switch;
-use super::{fetch_task, TaskStatus};
-use super::{TaskContext, TaskControlBlock};
-use crate::sync::UPSafeCell;
-use crate::trap::TrapContext;
+use super::fpu::forget_hart_fp;
+use super::manager::migrate_tasks;
+use super::sched_trace::{record_switch, task_ids, IDLE_IDS};
+use super::{fetch_task, task_switched_out, time_slice_ms, TaskStatus};
+use super::{watchdog_kernel_leave, watchdog_switch_in};
+use super::{ProcessControlBlock, TaskContext, TaskControlBlock};
+use crate::config::MAX_HARTS;
+use crate::drivers::{exit_hart, handle_irq};
+use crate::hart::{clear_ipi, hart_id, offline_requested, set_idle, set_offline, stop_current};
+use crate::kcov::{kcov_switch_in, kcov_switch_out};
+use crate::sync::{rcu_quiescent, ExclusiveCell};
+use crate::timer::{check_timer, get_time, set_next_trigger, stop_timer};
+use crate::trap::{irq_enter, irq_exit, TrapContext};
 use alloc::sync::Arc;
 use lazy_static::*;
+use riscv::asm::wfi;
 ///Processor management structure
 pub struct Processor {
     ///The task currently executing on the current processor
@@ -37,43 +47,143 @@ impl Processor {
 }
 
 lazy_static! {
-    pub static ref PROCESSOR: UPSafeCell<Processor> = unsafe { UPSafeCell::new(Processor::new()) };
+    ///One processor for each hart, indexed by hart id
+    pub static ref PROCESSORS: [ExclusiveCell<Processor>; MAX_HARTS] =
+        core::array::from_fn(|_| ExclusiveCell::new(Processor::new()));
+}
+///The processor of the current hart
+fn local_processor() -> &'static ExclusiveCell<Processor> {
+    &PROCESSORS[hart_id()]
 }
 ///The main part of process execution and scheduling
 ///Loop `fetch_task` to get the process that needs to run, and switch the process through `__switch`
+///When nothing is ready the hart waits for an interrupt in `idle`, the timer is
+///only armed for the next sleeper or alarm (tickless idle) and re-armed with the
+///time slice of the next task switched to
+///
+///A task switching out stays current until it is back on the idle control flow,
+///only then it is put back to the ready queue, so that no other hart can
+///switch to it while its context is still being saved
+///
+///Switches are recorded in the trace of the hart, including those to and from idle
+///
+///A hart asked to go offline does so here, between two tasks
 pub fn run_tasks() {
+    // pid and tid of the task last switched out, idle while nothing was found to run
+    let mut prev = IDLE_IDS;
     loop {
-        let mut processor = PROCESSOR.exclusive_access();
+        if offline_requested() {
+            go_offline();
+        }
+        // timer interrupts are not taken here, look for sleepers to wake up
+        check_timer();
+        // no task runs on this hart, so it reads no RCU data
+        rcu_quiescent();
+        // a task added from now on wakes this hart up if nothing is found
+        set_idle(true);
+        let mut processor = local_processor().exclusive_access();
         if let Some(task) = fetch_task() {
+            set_idle(false);
             let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
             // access coming task TCB exclusively
             let mut task_inner = task.inner_exclusive_access();
             let next_task_cx_ptr = &task_inner.task_cx as *const TaskContext;
             task_inner.task_status = TaskStatus::Running;
+            task_inner.run_start = get_time();
+            task_inner.time_mark = task_inner.run_start;
+            task_inner.wait_time += task_inner.run_start - task_inner.ready_since;
+            task_inner.cpu = hart_id();
+            task_inner.on_cpu = true;
+            set_next_trigger(time_slice_ms(task_inner.level));
+            kcov_switch_in(task_inner.kcov.as_ref());
             drop(task_inner);
             // release coming task TCB manually
+            let next = task_ids(&task);
+            watchdog_switch_in(next, task.kernel_stack.get_top());
             processor.current = Some(task);
             // release processor manually
             drop(processor);
+            record_switch(prev, next);
             unsafe {
                 __switch(idle_task_cx_ptr, next_task_cx_ptr);
             }
+            kcov_switch_out();
+            // back from the task, put it back to the ready queue unless it exited
+            // or blocked, a blocked task woken up meanwhile is ready by now
+            let task = take_current_task().unwrap();
+            watchdog_kernel_leave();
+            prev = next;
+            let mut task_inner = task.inner_exclusive_access();
+            task_inner.on_cpu = false;
+            // the F registers of this hart are left to the next task
+            if task_inner.res.is_some() {
+                let trap_cx = task_inner.get_trap_cx();
+                task_inner.fp.save(&mut trap_cx.sstatus);
+            }
+            // the task switches out in the kernel
+            task_inner.charge_time(false);
+            task_inner.run_time += get_time() - task_inner.run_start;
+            let ready = task_inner.task_status == TaskStatus::Ready;
+            // a blocked task woken up meanwhile did not yield involuntarily either
+            match task_inner.task_status {
+                TaskStatus::Ready if task_inner.slice_used_up => task_inner.nivcsw += 1,
+                TaskStatus::Ready | TaskStatus::Blocked => task_inner.nvcsw += 1,
+                _ => {}
+            }
+            drop(task_inner);
+            task_switched_out(task, ready);
+        } else {
+            drop(processor);
+            if prev != IDLE_IDS {
+                record_switch(prev, IDLE_IDS);
+                prev = IDLE_IDS;
+            }
+            idle();
         }
     }
 }
+///Take the current hart offline with no task running on it: stop routing device
+///interrupts to it, move its ready tasks to the online harts and stop it
+fn go_offline() -> ! {
+    set_offline();
+    exit_hart();
+    migrate_tasks();
+    forget_hart_fp();
+    info!("hart {} offline", hart_id());
+    stop_current()
+}
+///Wait for an interrupt with nothing to run: the next kernel timer, e.g. of a sleeper,
+///the IPI of a hart adding a task or a device, e.g. console input. Interrupts stay
+///disabled in the kernel, `wfi` returns once one is pending in `sie` without taking
+///it, so device interrupts are handled here.
+fn idle() {
+    // no task to preempt until one becomes ready, only the kernel timers to run
+    stop_timer();
+    unsafe {
+        wfi();
+    }
+    set_idle(false);
+    clear_ipi();
+    irq_enter();
+    handle_irq();
+    irq_exit();
+}
 ///Take the current task,leaving a None in its place
 pub fn take_current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().take_current()
+    local_processor().exclusive_access().take_current()
 }
 ///Get running task
 pub fn current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().current()
+    local_processor().exclusive_access().current()
+}
+///Get the process of the running task
+pub fn current_process() -> Arc<ProcessControlBlock> {
+    current_task().unwrap().process.upgrade().unwrap()
 }
 ///Get token of the address space of current task
 pub fn current_user_token() -> usize {
     let task = current_task().unwrap();
-    let token = task.inner_exclusive_access().get_user_token();
-    token
+    task.get_user_token()
 }
 ///Get the mutable reference to trap context of current task
 pub fn current_trap_cx() -> &'static mut TrapContext {
@@ -82,9 +192,19 @@ pub fn current_trap_cx() -> &'static mut TrapContext {
         .inner_exclusive_access()
         .get_trap_cx()
 }
+///Get the user space address of the trap context of current task
+pub fn current_trap_cx_user_va() -> usize {
+    current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .res
+        .as_ref()
+        .unwrap()
+        .trap_cx_user_va()
+}
 ///Return to idle control flow for new scheduling
 pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
-    let mut processor = PROCESSOR.exclusive_access();
+    let mut processor = local_processor().exclusive_access();
     let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
     drop(processor);
     unsafe {
@@ -0,0 +1,96 @@
+//! Tracing of processes by their parent, enough for a small debugger
+//!
+//! A process traced after `PTRACE_TRACEME` or `PTRACE_ATTACH` stops on each
+//! signal but `SIGKILL` before handling it, e.g. on the `SIGTRAP` of an
+//! `ebreak`. Its parent, the tracer, learns of the stop by `sys_waitpid`,
+//! reads and writes the registers of the stopped thread and the memory of the
+//! process, and resumes it, delivering the signal or another one or none.
+//!
+//! One thread of a process is stopped at a time, the other ones keep running.
+use super::{current_task, ProcessControlBlock, SignalFlags, TaskControlBlock};
+use alloc::sync::Arc;
+
+/// Tracing state of a process
+#[derive(Default)]
+pub struct Ptrace {
+    /// whether the parent traces the process
+    pub traced: bool,
+    /// the thread stopped for the tracer, `None` while none is
+    pub stop: Option<PtraceStop>,
+}
+
+/// A thread stopped for the tracer
+pub struct PtraceStop {
+    /// the stopped thread
+    pub task: Arc<TaskControlBlock>,
+    /// signal stopping it
+    pub signum: usize,
+    /// whether `sys_waitpid` reported the stop to the tracer
+    pub reported: bool,
+    /// signal to deliver once resumed, 0 for none, `None` until the tracer resumes it
+    pub resume: Option<usize>,
+}
+
+impl Ptrace {
+    /// The stop of a thread not resumed yet
+    pub fn stopped(&mut self) -> Option<&mut PtraceStop> {
+        self.stop.as_mut().filter(|stop| stop.resume.is_none())
+    }
+    /// Resume the stopped thread delivering `signum`, 0 for none. Return false
+    /// if there is none, the waiters of [`ProcessControlBlock::ptrace_wait`]
+    /// have to be woken up otherwise.
+    pub fn resume(&mut self, signum: usize) -> bool {
+        match self.stopped() {
+            Some(stop) => {
+                stop.resume = Some(signum);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Stop the current thread on signal `signum` if its process is traced, until
+/// the tracer resumes it or `SIGKILL` arrives. Return the signal to deliver:
+/// `signum` if not traced, the one chosen by the tracer otherwise, 0 for none.
+pub fn ptrace_stop(signum: usize) -> usize {
+    let task = current_task().unwrap();
+    let process = task.process.upgrade().unwrap();
+    let sigkill = SignalFlags::SIGKILL.lowest_signum().unwrap();
+    loop {
+        let mut inner = process.inner_exclusive_access();
+        if !inner.ptrace.traced || signum == sigkill {
+            return signum;
+        }
+        if inner.ptrace.stop.is_none() {
+            inner.ptrace.stop = Some(PtraceStop {
+                task: Arc::clone(&task),
+                signum,
+                reported: false,
+                resume: None,
+            });
+            break;
+        }
+        drop(inner);
+        // another thread is stopped
+        if !process
+            .ptrace_wait
+            .wait_until_killable(|| process.inner_exclusive_access().ptrace.stop.is_none())
+        {
+            return 0;
+        }
+    }
+    drop(task);
+    process.ptrace_wait.wait_until_killable(|| {
+        let inner = process.inner_exclusive_access();
+        inner
+            .ptrace
+            .stop
+            .as_ref()
+            .map_or(true, |stop| stop.resume.is_some())
+    });
+    // a SIGKILL pending is handled next
+    let stop = process.inner_exclusive_access().ptrace.stop.take();
+    process.ptrace_wait.wake_all();
+    stop.and_then(|stop| stop.resume).unwrap_or(0)
+}
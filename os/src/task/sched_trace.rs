@@ -0,0 +1,92 @@
+//! Trace of the context switches of each hart, see [`record_switch`]
+use super::TaskControlBlock;
+use crate::config::{MAX_HARTS, SCHED_TRACE_LEN};
+use crate::hart::hart_id;
+use crate::sync::ExclusiveCell;
+use crate::timer::{get_time, ticks_to_us};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// pid and tid standing for the idle control flow of a hart in a [`SwitchEvent`]
+pub const SWITCH_IDLE: usize = usize::MAX;
+/// pid and tid of the idle control flow, see [`task_ids`]
+pub const IDLE_IDS: (usize, usize) = (SWITCH_IDLE, SWITCH_IDLE);
+
+/// A hart switching from task `prev` to task `next`, either of which may be idle
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct SwitchEvent {
+    /// time of the switch in microseconds since boot
+    pub time_us: usize,
+    pub cpu: usize,
+    pub prev_pid: usize,
+    pub prev_tid: usize,
+    pub next_pid: usize,
+    pub next_tid: usize,
+}
+
+lazy_static! {
+    /// The latest `SCHED_TRACE_LEN` switches of each hart, oldest first
+    static ref SCHED_TRACE: [ExclusiveCell<VecDeque<SwitchEvent>>; MAX_HARTS] =
+        core::array::from_fn(|_| ExclusiveCell::new(VecDeque::new()));
+}
+
+/// pid and tid of `task` as recorded by [`record_switch`], tid 0 for kernel threads.
+/// It takes the lock of the TCB.
+pub fn task_ids(task: &Arc<TaskControlBlock>) -> (usize, usize) {
+    let pid = task
+        .process
+        .upgrade()
+        .map_or(SWITCH_IDLE, |process| process.getpid());
+    let tid = task
+        .inner_exclusive_access()
+        .res
+        .as_ref()
+        .map_or(0, |res| res.tid);
+    (pid, tid)
+}
+
+/// Record the current hart switching from the task with the pid and tid `prev` to
+/// `next`, either of which may be `IDLE_IDS`, dropping the oldest switch of the hart
+/// once it has `SCHED_TRACE_LEN`
+pub fn record_switch(prev: (usize, usize), next: (usize, usize)) {
+    let (prev_pid, prev_tid) = prev;
+    let (next_pid, next_tid) = next;
+    let event = SwitchEvent {
+        time_us: ticks_to_us(get_time()),
+        cpu: hart_id(),
+        prev_pid,
+        prev_tid,
+        next_pid,
+        next_tid,
+    };
+    let mut trace = SCHED_TRACE[event.cpu].exclusive_access();
+    if trace.len() == SCHED_TRACE_LEN {
+        trace.pop_front();
+    }
+    trace.push_back(event);
+}
+
+/// Remove up to `count` of the oldest switches recorded on all harts and return them
+/// in the order of their time
+pub fn take_switches(count: usize) -> Vec<SwitchEvent> {
+    let mut traces: Vec<_> = SCHED_TRACE
+        .iter()
+        .map(|trace| trace.exclusive_access())
+        .collect();
+    let mut events = Vec::new();
+    while events.len() < count {
+        let oldest = traces
+            .iter()
+            .enumerate()
+            .filter_map(|(cpu, trace)| trace.front().map(|event| (event.time_us, cpu)))
+            .min();
+        match oldest {
+            Some((_, cpu)) => events.push(traces[cpu].pop_front().unwrap()),
+            None => break,
+        }
+    }
+    events
+}
@@ -0,0 +1,389 @@
+//! Signals
+//!
+//! Signals sent to a process are made pending on its main thread. Before a
+//! thread returns to user mode, [`handle_signals`] delivers its pending
+//! signals that are not masked: a signal with a user handler is delivered by
+//! saving the user registers in a [`SignalFrame`] on the user stack and
+//! entering the handler, which returns to the sigreturn trampoline mapped at
+//! `SIGRETURN_TRAMPOLINE` to restore them. Signals without a handler take
+//! their default action, terminating or being ignored.
+//!
+//! Faults of user programs raise `SIGSEGV`, `SIGBUS`, `SIGILL` or `SIGTRAP` on
+//! the faulting thread with [`force_signal_current`], so that a handler can
+//! recover from them. If one terminates the process, the kind of fault is
+//! reported to the parent along with the signal, see `wait_status`.
+//!
+//! A traced process stops for its tracer before handling a signal, see
+//! `ptrace.rs`.
+use super::{
+    current_task, exit_current, ptrace_stop, remove_from_pid2process, wakeup_task,
+    ProcessControlBlock, TaskControlBlock,
+};
+use crate::config::SIGRETURN_TRAMPOLINE;
+use crate::mm::copy_to_user;
+use alloc::sync::Arc;
+use core::arch::global_asm;
+use core::mem::size_of;
+
+global_asm!(include_str!("sigreturn.S"));
+
+/// Largest signal number
+pub const MAX_SIG: usize = 31;
+
+bitflags! {
+    /// A set of signals, bit `n` for signal number `n` as in Linux
+    pub struct SignalFlags: u32 {
+        const SIGHUP    = 1 << 1;
+        const SIGINT    = 1 << 2;
+        const SIGQUIT   = 1 << 3;
+        const SIGILL    = 1 << 4;
+        const SIGTRAP   = 1 << 5;
+        const SIGABRT   = 1 << 6;
+        const SIGBUS    = 1 << 7;
+        const SIGFPE    = 1 << 8;
+        const SIGKILL   = 1 << 9;
+        const SIGUSR1   = 1 << 10;
+        const SIGSEGV   = 1 << 11;
+        const SIGUSR2   = 1 << 12;
+        const SIGPIPE   = 1 << 13;
+        const SIGALRM   = 1 << 14;
+        const SIGTERM   = 1 << 15;
+        const SIGSTKFLT = 1 << 16;
+        const SIGCHLD   = 1 << 17;
+        const SIGCONT   = 1 << 18;
+        const SIGSTOP   = 1 << 19;
+        const SIGTSTP   = 1 << 20;
+        const SIGTTIN   = 1 << 21;
+        const SIGTTOU   = 1 << 22;
+        const SIGURG    = 1 << 23;
+        const SIGXCPU   = 1 << 24;
+        const SIGXFSZ   = 1 << 25;
+        const SIGVTALRM = 1 << 26;
+        const SIGPROF   = 1 << 27;
+        const SIGWINCH  = 1 << 28;
+        const SIGIO     = 1 << 29;
+        const SIGPWR    = 1 << 30;
+        const SIGSYS    = 1 << 31;
+    }
+}
+
+/// Signals raised by faults, entering a handler with the faulting address
+const FAULT_SIGNALS: SignalFlags = SignalFlags::from_bits_truncate(
+    SignalFlags::SIGSEGV.bits
+        | SignalFlags::SIGBUS.bits
+        | SignalFlags::SIGILL.bits
+        | SignalFlags::SIGTRAP.bits,
+);
+
+/// `si_code` of a fault as in Linux, `SIGSEGV`: no mapping at the address
+pub const SEGV_MAPERR: u8 = 1;
+/// `SIGSEGV`: mapped without the permission for the access
+pub const SEGV_ACCERR: u8 = 2;
+/// `SIGBUS`: misaligned address
+pub const BUS_ADRALN: u8 = 1;
+/// `SIGBUS`: no memory behind the physical address
+pub const BUS_ADRERR: u8 = 2;
+/// `SIGILL`: illegal opcode
+pub const ILL_ILLOPC: u8 = 1;
+/// `SIGTRAP`: breakpoint
+pub const TRAP_BRKPT: u8 = 1;
+
+/// Signals that can be neither caught, ignored nor masked
+pub const UNCATCHABLE: SignalFlags =
+    SignalFlags::from_bits_truncate(SignalFlags::SIGKILL.bits | SignalFlags::SIGSTOP.bits);
+
+/// Signals ignored by default, all others terminate the process. Stopping
+/// and continuing processes is not supported, so those signals are ignored.
+const DEFAULT_IGNORED: SignalFlags = SignalFlags::from_bits_truncate(
+    SignalFlags::SIGCHLD.bits
+        | SignalFlags::SIGCONT.bits
+        | SignalFlags::SIGSTOP.bits
+        | SignalFlags::SIGTSTP.bits
+        | SignalFlags::SIGTTIN.bits
+        | SignalFlags::SIGTTOU.bits
+        | SignalFlags::SIGURG.bits
+        | SignalFlags::SIGWINCH.bits,
+);
+
+impl SignalFlags {
+    /// The set holding only signal `signum`, `None` if it is not a signal number
+    pub fn from_signum(signum: usize) -> Option<Self> {
+        if signum == 0 || signum > MAX_SIG {
+            None
+        } else {
+            Self::from_bits(1 << signum)
+        }
+    }
+    /// Number of the lowest signal in the set
+    pub fn lowest_signum(&self) -> Option<usize> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.bits().trailing_zeros() as usize)
+        }
+    }
+}
+
+/// Handler value for the default action
+pub const SIG_DFL: usize = 0;
+/// Handler value to ignore the signal
+pub const SIG_IGN: usize = 1;
+
+/// Flag of the `SIGCHLD` action: children do not become zombies, they are
+/// reaped as soon as they exit
+pub const SA_NOCLDWAIT: u32 = 2;
+
+/// What to do on a signal, set by `sys_sigaction`
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SignalAction {
+    /// address of the handler, or [`SIG_DFL`] or [`SIG_IGN`]
+    pub handler: usize,
+    /// signals masked while the handler runs, besides the signal itself
+    pub mask: SignalFlags,
+    /// `SA_*` flags
+    pub flags: u32,
+}
+
+impl Default for SignalAction {
+    fn default() -> Self {
+        Self {
+            handler: SIG_DFL,
+            mask: SignalFlags::empty(),
+            flags: 0,
+        }
+    }
+}
+
+impl SignalAction {
+    /// Whether signal `signum` is discarded under this action
+    pub fn ignores(&self, signum: usize) -> bool {
+        let flag = SignalFlags::from_signum(signum).unwrap();
+        !UNCATCHABLE.contains(flag)
+            && (self.handler == SIG_IGN
+                || self.handler == SIG_DFL && DEFAULT_IGNORED.contains(flag))
+    }
+}
+
+/// User registers and signal mask saved on the user stack while a handler runs
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct SignalFrame {
+    pub x: [usize; 32],
+    pub sepc: usize,
+    pub mask: SignalFlags,
+}
+
+/// Make signal `signum` pending on the main thread of `process`, unless the
+/// process ignores it. A blocked thread is woken up to handle it, so blocking
+/// syscalls must check for pending signals when woken up.
+pub fn send_signal(process: &Arc<ProcessControlBlock>, signum: usize) {
+    let flag = SignalFlags::from_signum(signum).unwrap();
+    let inner = process.inner_exclusive_access();
+    if inner.is_zombie || inner.signal_actions[signum].ignores(signum) {
+        return;
+    }
+    let task = match inner.tasks.first() {
+        Some(Some(task)) => Arc::clone(task),
+        _ => return,
+    };
+    drop(inner);
+    let mut task_inner = task.inner_exclusive_access();
+    task_inner.signals |= flag;
+    let masked = task_inner.signal_mask.contains(flag);
+    drop(task_inner);
+    if !masked {
+        wakeup_task(task);
+    }
+}
+
+/// Notify the parent of the exiting process `child` with `SIGCHLD`. If the parent
+/// ignores `SIGCHLD` or set `SA_NOCLDWAIT`, the child is reaped right away
+/// instead of waiting as a zombie for `sys_waitpid`, as is a child without parent.
+pub fn notify_parent_of_exit(child: &Arc<ProcessControlBlock>) {
+    let parent = match child.inner_exclusive_access().parent.as_ref() {
+        Some(parent) => parent.upgrade(),
+        None => None,
+    };
+    let parent = match parent {
+        Some(parent) => parent,
+        None => {
+            remove_from_pid2process(child.getpid());
+            return;
+        }
+    };
+    let signum = SignalFlags::SIGCHLD.lowest_signum().unwrap();
+    let mut parent_inner = parent.inner_exclusive_access();
+    let action = parent_inner.signal_actions[signum];
+    if action.handler == SIG_IGN || action.flags & SA_NOCLDWAIT != 0 {
+        parent_inner
+            .children
+            .retain(|process| !Arc::ptr_eq(process, child));
+        remove_from_pid2process(child.getpid());
+    }
+    drop(parent_inner);
+    send_signal(&parent, signum);
+}
+
+/// Raise signal `signum` for a fault of kind `code` at `addr` on the current thread.
+/// The faulting instruction cannot make progress, so if the signal is masked or
+/// ignored its action is reset to the default and it is unmasked, as in Linux.
+pub fn force_signal_current(signum: usize, code: u8, addr: usize) {
+    let flag = SignalFlags::from_signum(signum).unwrap();
+    let task = current_task().unwrap();
+    let process = task.process.upgrade().unwrap();
+    let mut process_inner = process.inner_exclusive_access();
+    let mut task_inner = task.inner_exclusive_access();
+    let action = &mut process_inner.signal_actions[signum];
+    if action.handler == SIG_IGN || task_inner.signal_mask.contains(flag) {
+        *action = SignalAction::default();
+        task_inner.signal_mask.remove(flag);
+    }
+    drop(process_inner);
+    task_inner.signals |= flag;
+    task_inner.fault_addr = addr;
+    task_inner.fault_code = code;
+}
+
+/// Whether the current task has a pending signal that is not masked
+pub fn current_has_signal() -> bool {
+    let task = current_task().unwrap();
+    let task_inner = task.inner_exclusive_access();
+    !(task_inner.signals - task_inner.signal_mask).is_empty()
+}
+
+/// Terminate the process of the current thread by signal `signum`, which is
+/// reported to the parent with `code`, the `si_code` of the fault raising it or 0
+pub fn exit_current_by_signal(signum: usize, code: u8) {
+    exit_current(-(signum as i32), Some((signum, code)), true);
+}
+
+/// ABI names of the general registers, by number
+const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// Print what is known about the fault of the current thread killing its process
+/// by signal `signum`: the thread, the fault, its registers and the memory map
+fn report_fault(
+    process: &Arc<ProcessControlBlock>,
+    task: &TaskControlBlock,
+    signum: usize,
+    code: u8,
+) {
+    let address_space = Arc::clone(&process.inner_exclusive_access().address_space);
+    let task_inner = task.inner_exclusive_access();
+    let cx = task_inner.get_trap_cx();
+    warn!(
+        "pid {} ({}) killed by {:?} code {} at addr {:#x}, sepc = {:#x}",
+        process.getpid(),
+        task_inner.comm(),
+        SignalFlags::from_signum(signum).unwrap(),
+        code,
+        task_inner.fault_addr,
+        cx.sepc
+    );
+    for (i, chunk) in cx.x.chunks(4).enumerate() {
+        println!(
+            "  {:>4}: {:#018x} {:>4}: {:#018x} {:>4}: {:#018x} {:>4}: {:#018x}",
+            REG_NAMES[i * 4],
+            chunk[0],
+            REG_NAMES[i * 4 + 1],
+            chunk[1],
+            REG_NAMES[i * 4 + 2],
+            chunk[2],
+            REG_NAMES[i * 4 + 3],
+            chunk[3]
+        );
+    }
+    drop(task_inner);
+    let address_space = address_space.exclusive_access();
+    warn!(
+        "memory map, program break at {:#x}:",
+        address_space.program_brk
+    );
+    address_space
+        .memory_set
+        .print_areas(address_space.heap_bottom);
+}
+
+/// Deliver the pending signals of the current task that are not masked,
+/// called right before it returns to user mode
+pub fn handle_signals() {
+    loop {
+        let task = current_task().unwrap();
+        let process = task.process.upgrade().unwrap();
+        let mut task_inner = task.inner_exclusive_access();
+        let signum = match (task_inner.signals - task_inner.signal_mask).lowest_signum() {
+            Some(signum) => signum,
+            None => return,
+        };
+        task_inner
+            .signals
+            .remove(SignalFlags::from_signum(signum).unwrap());
+        drop(task_inner);
+        // a traced process stops for the tracer first, which picks the signal delivered
+        let signum = ptrace_stop(signum);
+        if signum == 0 {
+            task.inner_exclusive_access().fault_code = 0;
+            continue;
+        }
+        // the PCB is locked before the TCB
+        let process_inner = process.inner_exclusive_access();
+        let action = process_inner.signal_actions[signum];
+        let token = process_inner.get_user_token();
+        drop(process_inner);
+        let mut task_inner = task.inner_exclusive_access();
+        let flag = SignalFlags::from_signum(signum).unwrap();
+        // one fault at a time, the thread does not return to user mode with one pending
+        let fault_code = if FAULT_SIGNALS.contains(flag) {
+            core::mem::take(&mut task_inner.fault_code)
+        } else {
+            0
+        };
+        if action.ignores(signum) {
+            continue;
+        }
+        if action.handler == SIG_DFL || UNCATCHABLE.contains(flag) {
+            drop(task_inner);
+            if fault_code != 0 {
+                report_fault(&process, &task, signum, fault_code);
+            }
+            drop(process);
+            drop(task);
+            exit_current_by_signal(signum, fault_code);
+            unreachable!();
+        }
+        // enter the handler with the signal number in a0, the faulting address
+        // in a1 for SIGSEGV and SIGBUS, and the trampoline in ra
+        let cx = task_inner.get_trap_cx();
+        let frame = SignalFrame {
+            x: cx.x,
+            sepc: cx.sepc,
+            mask: task_inner.signal_mask,
+        };
+        let sp = (cx.x[2] - size_of::<SignalFrame>()) & !0xf;
+        if copy_to_user(token, sp as *mut SignalFrame, &frame).is_none() {
+            // no room on the user stack
+            drop(task_inner);
+            drop(process);
+            drop(task);
+            exit_current_by_signal(SignalFlags::SIGSEGV.lowest_signum().unwrap(), 0);
+            unreachable!();
+        }
+        cx.x[2] = sp;
+        cx.x[10] = signum;
+        cx.x[11] = if FAULT_SIGNALS.contains(flag) {
+            task_inner.fault_addr
+        } else {
+            0
+        };
+        cx.x[1] = SIGRETURN_TRAMPOLINE;
+        cx.sepc = action.handler;
+        task_inner.signal_mask |= action.mask | flag;
+        task_inner.signal_mask -= UNCATCHABLE;
+        return;
+    }
+}
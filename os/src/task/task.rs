@@ -1,186 +1,268 @@
 //!Implementation of [`TaskControlBlock`]
-use super::TaskContext;
-use super::{pid_alloc, KernelStack, PidHandle};
-use crate::config::TRAP_CONTEXT;
-use crate::fs::{File, Stdin, Stdout};
-use crate::mm::{MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
-use crate::sync::UPSafeCell;
-use crate::trap::{trap_handler, TrapContext};
+use super::id::TaskUserRes;
+use super::manager::{nice_to_weight, MAX_NICE, MIN_NICE, NICE_0_WEIGHT};
+use super::{FpContext, KernelStack, ProcessControlBlock, SignalFlags, TaskContext};
+use crate::config::{DEFAULT_PRIORITY, MAX_PRIORITY, MIN_PRIORITY};
+use crate::hart::{hart_id, ALL_HARTS};
+use crate::kcov::KcovArea;
+use crate::mm::{ObjectCache, PhysPageNum};
+use crate::sync::{ExclusiveCell, ExclusiveCellGuard};
+use crate::timer::get_time;
+use crate::trap::TrapContext;
 use alloc::sync::{Arc, Weak};
-use alloc::vec;
-use alloc::vec::Vec;
-use core::cell::RefMut;
 
+/// Size of the name of a task, including the terminating `\0`
+pub const COMM_LEN: usize = 16;
+
+/// Slab cache of task control blocks
+pub(super) static TASK_CACHE: ObjectCache<TaskControlBlock> = ObjectCache::new();
+
+/// A thread, the unit of scheduling. Its lock is taken after that of its
+/// process, see [`ProcessControlBlock`].
 pub struct TaskControlBlock {
     // immutable
-    pub pid: PidHandle,
+    pub process: Weak<ProcessControlBlock>,
     pub kernel_stack: KernelStack,
     // mutable
-    inner: UPSafeCell<TaskControlBlockInner>,
+    inner: ExclusiveCell<TaskControlBlockInner>,
 }
 
 pub struct TaskControlBlockInner {
+    /// `None` for kernel threads and threads that have exited
+    pub res: Option<TaskUserRes>,
     pub trap_cx_ppn: PhysPageNum,
-    pub base_size: usize,
     pub task_cx: TaskContext,
+    /// F registers while the task is switched out, used once its FPU is on
+    pub fp: FpContext,
     pub task_status: TaskStatus,
-    pub memory_set: MemorySet,
-    pub parent: Option<Weak<TaskControlBlock>>,
-    pub children: Vec<Arc<TaskControlBlock>>,
-    pub exit_code: i32,
-    pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    /// `Some` once the thread has exited
+    pub exit_code: Option<i32>,
+    /// scheduling priority, a larger value runs first
+    pub priority: usize,
+    /// priority inherited from the tasks waiting for a mutex it holds, 0 if none
+    pub inherited_priority: usize,
+    /// number of blocking mutexes held, it keeps an inherited priority until none is left
+    pub locks_held: usize,
+    /// number of times the task was passed over while ready, added to its priority
+    pub age: usize,
+    /// stride scheduling pass, advanced by `BIG_STRIDE / priority` each time it runs
+    pub pass: u64,
+    /// MLFQ ready queue, 0 is the highest
+    pub level: usize,
+    /// set when the task is preempted at the end of its time slice
+    pub slice_used_up: bool,
+    /// niceness in [-20, 19], a lower value gets more CPU time under CFS
+    pub nice: i32,
+    /// CPU time in timer ticks scaled by `NICE_0_WEIGHT / weight`
+    pub vruntime: u64,
+    /// time in timer ticks when the task was last switched to
+    pub run_start: usize,
+    /// timer ticks spent in user mode
+    pub utime: usize,
+    /// timer ticks spent in the kernel, e.g. in syscalls
+    pub stime: usize,
+    /// time in timer ticks when the task last entered or left user mode or was switched to
+    pub time_mark: usize,
+    /// time in timer ticks when the task was created or last became ready
+    pub ready_since: usize,
+    /// timer ticks spent switched to a hart, from being switched to until switched out
+    pub run_time: usize,
+    /// timer ticks spent ready but waiting for a hart
+    pub wait_time: usize,
+    /// number of times the task switched out because it blocked or yielded
+    pub nvcsw: usize,
+    /// number of times the task switched out because its time slice was used up
+    pub nivcsw: usize,
+    /// time in timer ticks when the task last blocked
+    pub blocked_since: usize,
+    /// set while blocked in a wait that signals cannot interrupt
+    pub uninterruptible: bool,
+    /// set once the watchdog reported the task blocked for too long, until it blocks again
+    pub hung_reported: bool,
+    /// hart the task last ran on, whose ready queue it is added to
+    pub cpu: usize,
+    /// set while the task is the current task of a hart, including while it switches out
+    pub on_cpu: bool,
+    /// harts the task may run on, bit i for hart i
+    pub cpus_allowed: usize,
+    /// pending signals
+    pub signals: SignalFlags,
+    /// signals not delivered until unmasked
+    pub signal_mask: SignalFlags,
+    /// faulting address reported with a pending signal raised by a fault
+    pub fault_addr: usize,
+    /// `si_code` of the fault raising the pending signal, e.g. `SEGV_MAPERR`
+    pub fault_code: u8,
+    /// name of the task padded with `\0`, the program it runs unless set by prctl
+    pub comm: [u8; COMM_LEN],
+    /// kernel code covered by the task, shared with the tasks it creates
+    pub kcov: Option<Arc<KcovArea>>,
 }
 
 impl TaskControlBlockInner {
     pub fn get_trap_cx(&self) -> &'static mut TrapContext {
         self.trap_cx_ppn.get_mut()
     }
-    pub fn get_user_token(&self) -> usize {
-        self.memory_set.token()
+    /// Set the name of the task to `name`, truncated to `COMM_LEN - 1` bytes
+    pub fn set_comm(&mut self, name: &[u8]) {
+        let len = name.len().min(COMM_LEN - 1);
+        self.comm = [0; COMM_LEN];
+        self.comm[..len].copy_from_slice(&name[..len]);
+    }
+    /// Name of the task, up to the first `\0` and any character cut by truncation
+    pub fn comm(&self) -> &str {
+        let len = self.comm.iter().position(|&c| c == 0).unwrap_or(COMM_LEN);
+        match core::str::from_utf8(&self.comm[..len]) {
+            Ok(comm) => comm,
+            Err(err) => core::str::from_utf8(&self.comm[..err.valid_up_to()]).unwrap(),
+        }
+    }
+    /// Priority the task is scheduled with, raised by priority inheritance
+    pub fn effective_priority(&self) -> usize {
+        self.priority.max(self.inherited_priority)
     }
-    fn get_status(&self) -> TaskStatus {
-        self.task_status
+    /// Run with at least `priority` until the task releases the mutexes it holds
+    pub fn inherit_priority(&mut self, priority: usize) {
+        self.inherited_priority = self.inherited_priority.max(priority);
     }
-    pub fn is_zombie(&self) -> bool {
-        self.get_status() == TaskStatus::Zombie
+    /// Whether the task may run on hart `cpu`
+    pub fn allows(&self, cpu: usize) -> bool {
+        self.cpus_allowed & (1 << cpu) != 0
     }
-    pub fn alloc_fd(&mut self) -> usize {
-        if let Some(fd) = (0..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
-            fd
+    /// Set the niceness, clamped to [-20, 19], and the priority of the priority and
+    /// stride policies in proportion to the CFS weight of the niceness
+    pub fn set_nice(&mut self, nice: i32) {
+        self.nice = nice.clamp(MIN_NICE, MAX_NICE);
+        let priority = DEFAULT_PRIORITY as u64 * nice_to_weight(self.nice) / NICE_0_WEIGHT;
+        self.priority = (priority as usize).clamp(MIN_PRIORITY, MAX_PRIORITY);
+    }
+    /// Charge the time since `time_mark` to `utime` if the task was in user mode,
+    /// to `stime` otherwise, and move `time_mark` to now
+    pub fn charge_time(&mut self, user: bool) {
+        let now = get_time();
+        let delta = now - self.time_mark;
+        if user {
+            self.utime += delta;
         } else {
-            self.fd_table.push(None);
-            self.fd_table.len() - 1
+            self.stime += delta;
         }
+        self.time_mark = now;
+    }
+    /// Charge the time since `run_start` to `vruntime`, weighted by `nice`
+    pub fn update_vruntime(&mut self) {
+        let delta = (get_time() - self.run_start) as u64;
+        self.vruntime += delta * NICE_0_WEIGHT / nice_to_weight(self.nice);
     }
 }
 
 impl TaskControlBlock {
-    pub fn inner_exclusive_access(&self) -> RefMut<'_, TaskControlBlockInner> {
+    pub fn inner_exclusive_access(&self) -> ExclusiveCellGuard<'_, TaskControlBlockInner> {
         self.inner.exclusive_access()
     }
-    pub fn new(elf_data: &[u8]) -> Self {
-        // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
-        let trap_cx_ppn = memory_set
-            .translate(VirtAddr::from(TRAP_CONTEXT).into())
-            .unwrap()
-            .ppn();
-        // alloc a pid and a kernel stack in kernel space
-        let pid_handle = pid_alloc();
-        let kernel_stack = KernelStack::new(&pid_handle);
+    /// Create a thread of `process` with its own tid and kernel stack, `None` if out of frames.
+    /// Its user stack and TrapContext are mapped in a new slot of the address space, unless
+    /// already mapped in `slot`.
+    pub fn new(process: Arc<ProcessControlBlock>, slot: Option<usize>) -> Option<Arc<Self>> {
+        let kernel_stack = KernelStack::new()?;
+        let res = TaskUserRes::new(Arc::clone(&process), slot)?;
+        let trap_cx_ppn = res.trap_cx_ppn();
         let kernel_stack_top = kernel_stack.get_top();
-        let task_control_block = Self {
-            pid: pid_handle,
+        Some(TASK_CACHE.new_arc(Self {
+            process: Arc::downgrade(&process),
             kernel_stack,
-            inner: unsafe {
-                UPSafeCell::new(TaskControlBlockInner {
-                    trap_cx_ppn,
-                    base_size: user_sp,
-                    task_cx: TaskContext::goto_trap_return(kernel_stack_top),
-                    task_status: TaskStatus::Ready,
-                    memory_set,
-                    parent: None,
-                    children: Vec::new(),
-                    exit_code: 0,
-                    fd_table: vec![
-                        // 0 -> stdin
-                        Some(Arc::new(Stdin)),
-                        // 1 -> stdout
-                        Some(Arc::new(Stdout)),
-                        // 2 -> stderr
-                        Some(Arc::new(Stdout)),
-                    ],
-                })
-            },
-        };
-        // prepare TrapContext in user space
-        let trap_cx = task_control_block.inner_exclusive_access().get_trap_cx();
-        *trap_cx = TrapContext::app_init_context(
-            entry_point,
-            user_sp,
-            KERNEL_SPACE.exclusive_access().token(),
-            kernel_stack_top,
-            trap_handler as usize,
-        );
-        task_control_block
-    }
-    pub fn exec(&self, elf_data: &[u8]) {
-        // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
-        let trap_cx_ppn = memory_set
-            .translate(VirtAddr::from(TRAP_CONTEXT).into())
-            .unwrap()
-            .ppn();
-
-        // **** access current TCB exclusively
-        let mut inner = self.inner_exclusive_access();
-        // substitute memory_set
-        inner.memory_set = memory_set;
-        // update trap_cx ppn
-        inner.trap_cx_ppn = trap_cx_ppn;
-        // initialize trap_cx
-        let trap_cx = TrapContext::app_init_context(
-            entry_point,
-            user_sp,
-            KERNEL_SPACE.exclusive_access().token(),
-            self.kernel_stack.get_top(),
-            trap_handler as usize,
-        );
-        *inner.get_trap_cx() = trap_cx;
-        // **** release current PCB
+            inner: ExclusiveCell::new(TaskControlBlockInner {
+                res: Some(res),
+                trap_cx_ppn,
+                task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+                fp: FpContext::new(),
+                task_status: TaskStatus::Ready,
+                exit_code: None,
+                priority: DEFAULT_PRIORITY,
+                inherited_priority: 0,
+                locks_held: 0,
+                age: 0,
+                pass: 0,
+                level: 0,
+                slice_used_up: false,
+                nice: 0,
+                vruntime: 0,
+                run_start: 0,
+                utime: 0,
+                stime: 0,
+                time_mark: 0,
+                ready_since: get_time(),
+                run_time: 0,
+                wait_time: 0,
+                nvcsw: 0,
+                nivcsw: 0,
+                blocked_since: 0,
+                uninterruptible: false,
+                hung_reported: false,
+                comm: [0; COMM_LEN],
+                cpu: hart_id(),
+                on_cpu: false,
+                cpus_allowed: ALL_HARTS,
+                signals: SignalFlags::empty(),
+                signal_mask: SignalFlags::empty(),
+                fault_addr: 0,
+                fault_code: 0,
+                kcov: None,
+            }),
+        }))
     }
-    pub fn fork(self: &Arc<TaskControlBlock>) -> Arc<TaskControlBlock> {
-        // ---- hold parent PCB lock
-        let mut parent_inner = self.inner_exclusive_access();
-        // copy user space(include trap context)
-        let memory_set = MemorySet::from_existed_user(&parent_inner.memory_set);
-        let trap_cx_ppn = memory_set
-            .translate(VirtAddr::from(TRAP_CONTEXT).into())
-            .unwrap()
-            .ppn();
-        // alloc a pid and a kernel stack in kernel space
-        let pid_handle = pid_alloc();
-        let kernel_stack = KernelStack::new(&pid_handle);
+    /// Create a kernel thread of `process` running `entry` on its own kernel stack,
+    /// `None` if out of frames. It has no TrapContext, as it never returns to user mode.
+    pub fn new_kthread(process: &Arc<ProcessControlBlock>, entry: fn()) -> Option<Arc<Self>> {
+        let kernel_stack = KernelStack::new()?;
         let kernel_stack_top = kernel_stack.get_top();
-        // copy fd table
-        let mut new_fd_table: Vec<Option<Arc<dyn File + Send + Sync>>> = Vec::new();
-        for fd in parent_inner.fd_table.iter() {
-            if let Some(file) = fd {
-                new_fd_table.push(Some(file.clone()));
-            } else {
-                new_fd_table.push(None);
-            }
-        }
-        let task_control_block = Arc::new(TaskControlBlock {
-            pid: pid_handle,
+        Some(TASK_CACHE.new_arc(Self {
+            process: Arc::downgrade(process),
             kernel_stack,
-            inner: unsafe {
-                UPSafeCell::new(TaskControlBlockInner {
-                    trap_cx_ppn,
-                    base_size: parent_inner.base_size,
-                    task_cx: TaskContext::goto_trap_return(kernel_stack_top),
-                    task_status: TaskStatus::Ready,
-                    memory_set,
-                    parent: Some(Arc::downgrade(self)),
-                    children: Vec::new(),
-                    exit_code: 0,
-                    fd_table: new_fd_table,
-                })
-            },
-        });
-        // add child
-        parent_inner.children.push(task_control_block.clone());
-        // modify kernel_sp in trap_cx
-        // **** access child PCB exclusively
-        let trap_cx = task_control_block.inner_exclusive_access().get_trap_cx();
-        trap_cx.kernel_sp = kernel_stack_top;
-        // return
-        task_control_block
-        // **** release child PCB
-        // ---- release parent PCB
+            inner: ExclusiveCell::new(TaskControlBlockInner {
+                res: None,
+                trap_cx_ppn: PhysPageNum(0),
+                task_cx: TaskContext::goto_kthread_start(kernel_stack_top, entry as usize),
+                fp: FpContext::new(),
+                task_status: TaskStatus::Ready,
+                exit_code: None,
+                priority: DEFAULT_PRIORITY,
+                inherited_priority: 0,
+                locks_held: 0,
+                age: 0,
+                pass: 0,
+                level: 0,
+                slice_used_up: false,
+                nice: 0,
+                vruntime: 0,
+                run_start: 0,
+                utime: 0,
+                stime: 0,
+                time_mark: 0,
+                ready_since: get_time(),
+                run_time: 0,
+                wait_time: 0,
+                nvcsw: 0,
+                nivcsw: 0,
+                blocked_since: 0,
+                uninterruptible: false,
+                hung_reported: false,
+                comm: [0; COMM_LEN],
+                cpu: hart_id(),
+                on_cpu: false,
+                cpus_allowed: ALL_HARTS,
+                signals: SignalFlags::empty(),
+                signal_mask: SignalFlags::empty(),
+                fault_addr: 0,
+                fault_code: 0,
+                kcov: None,
+            }),
+        }))
     }
-    pub fn getpid(&self) -> usize {
-        self.pid.0
+    /// Get the token of the address space of the process
+    pub fn get_user_token(&self) -> usize {
+        let process = self.process.upgrade().unwrap();
+        let inner = process.inner_exclusive_access();
+        inner.get_user_token()
     }
 }
 
@@ -188,5 +270,8 @@ impl TaskControlBlock {
 pub enum TaskStatus {
     Ready,
     Running,
-    Zombie,
+    /// waiting to be woken up by `wakeup_task`
+    Blocked,
+    /// exited, released by `Processor` once switched out
+    Exited,
 }
@@ -0,0 +1,106 @@
+//! Implementation of [`WaitQueue`]
+use super::{block_current_and_run_next, current_task, wakeup_task, SignalFlags, TaskControlBlock};
+use crate::sync::ExclusiveCell;
+use crate::timer::{add_timer, cancel_timer, get_time};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+/// Tasks blocked until some event, e.g. input arriving, woken up by whoever
+/// causes it to check what they wait for again.
+///
+/// The condition of a waiter is checked under the lock of the queue, so a
+/// waker changing it before calling [`WaitQueue::wake_one`] or
+/// [`WaitQueue::wake_all`] either is seen by the check or finds the waiter
+/// queued. Wakeups may be spurious, e.g. by a signal, waiters check again.
+pub struct WaitQueue {
+    waiters: ExclusiveCell<VecDeque<Arc<TaskControlBlock>>>,
+}
+
+impl WaitQueue {
+    /// Create an empty wait queue
+    pub fn new() -> Self {
+        Self {
+            waiters: ExclusiveCell::new(VecDeque::new()),
+        }
+    }
+    /// Block the current task until `condition` holds and return true, or return
+    /// false once a signal arrives. `condition` must not take the lock of this queue.
+    pub fn wait_until(&self, condition: impl FnMut() -> bool) -> bool {
+        self.wait_until_timeout(usize::MAX, condition)
+    }
+    /// Like [`WaitQueue::wait_until`], also returning false at time `expire` in
+    /// timer ticks
+    pub fn wait_until_timeout(&self, expire: usize, condition: impl FnMut() -> bool) -> bool {
+        self.wait(expire, SignalFlags::all(), condition)
+    }
+    /// Like [`WaitQueue::wait_until`], only returning false once `SIGKILL`
+    /// arrives, e.g. while stopped for a tracer
+    pub fn wait_until_killable(&self, condition: impl FnMut() -> bool) -> bool {
+        self.wait(usize::MAX, SignalFlags::SIGKILL, condition)
+    }
+    /// Block the current task until `condition` holds, even if a signal arrives,
+    /// e.g. while a device transfers data to the stack of the task
+    pub fn wait_until_uninterruptible(&self, condition: impl FnMut() -> bool) {
+        self.wait(usize::MAX, SignalFlags::empty(), condition);
+    }
+    /// Wait until `condition` holds, one of the `interrupting` signals is
+    /// pending and not masked, or time `expire`
+    fn wait(
+        &self,
+        expire: usize,
+        interrupting: SignalFlags,
+        mut condition: impl FnMut() -> bool,
+    ) -> bool {
+        let task = current_task().unwrap();
+        // watched for lost wakeups while blocked
+        task.inner_exclusive_access().uninterruptible = interrupting.is_empty();
+        // armed once for `expire` and cancelled when the wait ends
+        let mut timer = None;
+        let woken = loop {
+            let mut waiters = self.waiters.exclusive_access();
+            if condition() {
+                break true;
+            }
+            let task_inner = task.inner_exclusive_access();
+            let pending = task_inner.signals - task_inner.signal_mask;
+            drop(task_inner);
+            if pending.intersects(interrupting) || get_time() >= expire {
+                break false;
+            }
+            block_current_and_run_next(|task| {
+                waiters.push_back(Arc::clone(&task));
+                drop(waiters);
+                if expire != usize::MAX && timer.is_none() {
+                    timer = Some(add_timer(expire, task));
+                }
+            });
+            // woken up by a timer or a signal, or a wakeup that is stale by now
+            self.waiters
+                .exclusive_access()
+                .retain(|waiter| !Arc::ptr_eq(waiter, &task));
+        };
+        if let Some(timer) = timer {
+            cancel_timer(timer);
+        }
+        task.inner_exclusive_access().uninterruptible = false;
+        woken
+    }
+    /// Wake up the task waiting the longest, false if there is none
+    pub fn wake_one(&self) -> bool {
+        let waiter = self.waiters.exclusive_access().pop_front();
+        match waiter {
+            Some(task) => {
+                wakeup_task(task);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Wake up all the waiting tasks
+    pub fn wake_all(&self) {
+        let waiters = core::mem::take(&mut *self.waiters.exclusive_access());
+        for task in waiters {
+            wakeup_task(task);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Watchdog for tasks stuck in the kernel or blocked for too long
+//!
+//! A periodic kernel timer looks for
+//! - soft lockups: the current task of a hart has run in the kernel for
+//!   `SOFT_LOCKUP_MS` without returning to user mode, switching out or passing
+//!   a preemption point. Interrupts stay disabled in the kernel, so the stuck
+//!   hart is usually found by the timer running on another one, which can only
+//!   scan its kernel stack for return addresses.
+//! - hung tasks: a task blocked uninterruptibly for `HUNG_TASK_MS`, e.g. by a
+//!   lost wakeup. Interruptible waits, e.g. for input, may last forever. The
+//!   tasks are walked by the worker thread.
+//!
+//! Each stint in the kernel and each block is reported once.
+use super::{all_processes, queue_work, TaskControlBlock, TaskStatus};
+use crate::backtrace::{print_backtrace, print_stack_scan, print_switched_out};
+use crate::config::{CLOCK_FREQ, MAX_HARTS};
+use crate::hart::{hart_id, online_hart_mask};
+use crate::timer::{get_time, start_timer};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Interval of the checks
+const WATCHDOG_PERIOD_MS: usize = 1000;
+/// Time a task may run in the kernel without a break
+const SOFT_LOCKUP_MS: usize = 5000;
+/// Time a task may stay blocked uninterruptibly
+const HUNG_TASK_MS: usize = 30_000;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+/// Time in timer ticks since when the current task of each hart runs in the
+/// kernel, 0 while it runs in user mode or the hart has no task
+static KERNEL_SINCE: [AtomicUsize; MAX_HARTS] = [ZERO; MAX_HARTS];
+/// `KERNEL_SINCE` of each hart once reported as a soft lockup
+static LOCKUP_REPORTED: [AtomicUsize; MAX_HARTS] = [ZERO; MAX_HARTS];
+/// pid, tid and kernel stack top of the current task of each hart, read
+/// without taking the locks a stuck hart may hold
+static RUNNING_PID: [AtomicUsize; MAX_HARTS] = [ZERO; MAX_HARTS];
+static RUNNING_TID: [AtomicUsize; MAX_HARTS] = [ZERO; MAX_HARTS];
+static RUNNING_KSTACK: [AtomicUsize; MAX_HARTS] = [ZERO; MAX_HARTS];
+
+fn ms_to_ticks(ms: usize) -> usize {
+    CLOCK_FREQ / 1000 * ms
+}
+
+fn ticks_to_ms(ticks: usize) -> usize {
+    ticks / (CLOCK_FREQ / 1000)
+}
+
+/// Start the periodic checks
+pub fn watchdog_init() {
+    let period = ms_to_ticks(WATCHDOG_PERIOD_MS);
+    start_timer(get_time() + period, period, Box::new(watchdog_check));
+}
+
+/// Record the task with pid and tid `ids` and the kernel stack ending at
+/// `kstack_top` being switched to on the current hart, in the kernel
+pub fn watchdog_switch_in(ids: (usize, usize), kstack_top: usize) {
+    let hart = hart_id();
+    RUNNING_PID[hart].store(ids.0, Ordering::Relaxed);
+    RUNNING_TID[hart].store(ids.1, Ordering::Relaxed);
+    RUNNING_KSTACK[hart].store(kstack_top, Ordering::Relaxed);
+    watchdog_kernel_enter();
+}
+
+/// Record the current task of the hart entering the kernel, or passing a
+/// preemption point in it
+pub fn watchdog_kernel_enter() {
+    KERNEL_SINCE[hart_id()].store(get_time(), Ordering::Relaxed);
+}
+
+/// Record the current task of the hart returning to user mode or switching out
+pub fn watchdog_kernel_leave() {
+    KERNEL_SINCE[hart_id()].store(0, Ordering::Relaxed);
+}
+
+fn watchdog_check() {
+    let now = get_time();
+    check_soft_lockups(now);
+    // walking all the tasks takes too long for the timer interrupt
+    queue_work(move || check_hung_tasks(now));
+}
+
+fn check_soft_lockups(now: usize) {
+    let online = online_hart_mask();
+    for hart in (0..MAX_HARTS).filter(|hart| online & (1 << hart) != 0) {
+        let since = KERNEL_SINCE[hart].load(Ordering::Relaxed);
+        if since == 0
+            || now < since + ms_to_ticks(SOFT_LOCKUP_MS)
+            || LOCKUP_REPORTED[hart].swap(since, Ordering::Relaxed) == since
+        {
+            continue;
+        }
+        error!(
+            "soft lockup on hart {}: pid {} tid {} in the kernel for {} ms",
+            hart,
+            RUNNING_PID[hart].load(Ordering::Relaxed),
+            RUNNING_TID[hart].load(Ordering::Relaxed),
+            ticks_to_ms(now - since),
+        );
+        if hart == hart_id() {
+            // the timer interrupted a device handler of the stuck task
+            print_backtrace();
+        } else {
+            print_stack_scan(RUNNING_KSTACK[hart].load(Ordering::Relaxed));
+        }
+    }
+}
+
+fn check_hung_tasks(now: usize) {
+    let tasks: Vec<Arc<TaskControlBlock>> = all_processes()
+        .iter()
+        .flat_map(|process| {
+            let inner = process.inner_exclusive_access();
+            inner.tasks.iter().flatten().cloned().collect::<Vec<_>>()
+        })
+        .collect();
+    for task in tasks {
+        let mut inner = task.inner_exclusive_access();
+        if inner.task_status != TaskStatus::Blocked
+            || !inner.uninterruptible
+            || inner.hung_reported
+            || now < inner.blocked_since + ms_to_ticks(HUNG_TASK_MS)
+        {
+            continue;
+        }
+        inner.hung_reported = true;
+        let (ra, sp, fp) = inner.task_cx.frame();
+        let blocked_ms = ticks_to_ms(now - inner.blocked_since);
+        let tid = inner.res.as_ref().map_or(0, |res| res.tid);
+        error!(
+            "hung task: pid {} tid {} ({}) blocked for {} ms",
+            task.process.upgrade().map_or(0, |process| process.getpid()),
+            tid,
+            inner.comm(),
+            blocked_ms,
+        );
+        drop(inner);
+        print_switched_out(ra, sp, fp);
+    }
+}
@@ -0,0 +1,51 @@
+//! Work deferred to a kernel thread
+//!
+//! Interrupt handlers and softirqs cannot block and hold up the task they
+//! interrupt. Longer work is queued as a closure to the worker thread
+//! `kworker`, which runs the work items in order, in task context.
+use super::{cond_resched, kthread_spawn, WaitQueue};
+use crate::sync::{Lazy, SpinNoIrqLock};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+
+/// A function run once by the worker thread
+type Work = Box<dyn FnOnce() + Send>;
+
+/// Work items queued for the worker thread
+struct WorkQueue {
+    works: SpinNoIrqLock<VecDeque<Work>>,
+    /// the worker thread while the queue is empty
+    wait_queue: WaitQueue,
+}
+
+static WORK_QUEUE: Lazy<WorkQueue> = Lazy::new(|| WorkQueue {
+    works: SpinNoIrqLock::new(VecDeque::new()),
+    wait_queue: WaitQueue::new(),
+});
+
+/// Start the worker thread, once initproc can be created. Work queued before
+/// waits for it.
+pub fn workqueue_init() {
+    kthread_spawn("kworker", worker).expect("out of frames for the worker thread");
+}
+
+/// Run `work` later in the worker thread, e.g. from an interrupt handler
+pub fn queue_work(work: impl FnOnce() + Send + 'static) {
+    WORK_QUEUE.works.lock().push_back(Box::new(work));
+    WORK_QUEUE.wait_queue.wake_one();
+}
+
+fn worker() {
+    loop {
+        let mut work = None;
+        // kernel threads get no signals
+        WORK_QUEUE.wait_queue.wait_until(|| {
+            work = WORK_QUEUE.works.lock().pop_front();
+            work.is_some()
+        });
+        if let Some(work) = work {
+            work();
+        }
+        cond_resched();
+    }
+}
@@ -1,11 +1,23 @@
 //! RISC-V timer-related functionality
 
-use crate::config::CLOCK_FREQ;
+use crate::config::{CLOCK_FREQ, MAX_HARTS};
+use crate::drivers::RTC;
+use crate::hart::hart_id;
 use crate::sbi::set_timer;
+use crate::sync::ExclusiveCell;
+use crate::task::{wakeup_task, TaskControlBlock};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
 use riscv::register::time;
 
-const TICKS_PER_SEC: usize = 100;
 const MSEC_PER_SEC: usize = 1000;
+const USEC_PER_SEC: usize = 1_000_000;
+const NSEC_PER_SEC: usize = 1_000_000_000;
+const SECS_PER_DAY: usize = 86_400;
 ///get current time
 pub fn get_time() -> usize {
     time::read()
@@ -14,7 +26,344 @@ pub fn get_time() -> usize {
 pub fn get_time_ms() -> usize {
     time::read() / (CLOCK_FREQ / MSEC_PER_SEC)
 }
-/// set the next timer interrupt
-pub fn set_next_trigger() {
-    set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
+/// length of `ticks` timer ticks in microseconds
+pub fn ticks_to_us(ticks: usize) -> usize {
+    ticks / CLOCK_FREQ * USEC_PER_SEC + ticks % CLOCK_FREQ * USEC_PER_SEC / CLOCK_FREQ
+}
+/// length of `ticks` timer ticks in nanoseconds
+fn ticks_to_ns(ticks: usize) -> usize {
+    ticks / CLOCK_FREQ * NSEC_PER_SEC + ticks % CLOCK_FREQ * NSEC_PER_SEC / CLOCK_FREQ
+}
+
+/// nanoseconds since the Unix epoch at time 0 in timer ticks, set up by [`init`]
+static REALTIME_OFFSET_NS: AtomicUsize = AtomicUsize::new(0);
+
+/// Set up the realtime clock from the RTC, starting at the epoch on boards without one
+pub fn init() {
+    let monotonic = monotonic_ns();
+    let realtime = RTC.as_ref().map_or(0, |rtc| rtc.read_ns() as usize);
+    REALTIME_OFFSET_NS.store(realtime.saturating_sub(monotonic), Ordering::Relaxed);
+    let secs = realtime / NSEC_PER_SEC;
+    let (year, month, day) = civil_from_days(secs / SECS_PER_DAY);
+    let secs = secs % SECS_PER_DAY;
+    info!(
+        "realtime clock: {}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    );
+}
+/// Year, month and day of the `days`-th day since the Unix epoch, in the
+/// Gregorian calendar
+fn civil_from_days(days: usize) -> (usize, usize, usize) {
+    // counted in 400-year eras from 0000-03-01, so that leap days end the years
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = era * 400 + year_of_era + (month <= 2) as usize;
+    (year, month, day)
+}
+/// nanoseconds since boot, counted by the timer
+pub fn monotonic_ns() -> usize {
+    ticks_to_ns(get_time())
+}
+/// nanoseconds since the Unix epoch
+pub fn realtime_ns() -> usize {
+    REALTIME_OFFSET_NS.load(Ordering::Relaxed) + monotonic_ns()
+}
+
+/// time in timer ticks when the time slice of the task running on each hart ends,
+/// `usize::MAX` while the hart is idle
+static SLICE_END: [AtomicUsize; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NEVER: AtomicUsize = AtomicUsize::new(usize::MAX);
+    [NEVER; MAX_HARTS]
+};
+
+/// end the time slice of the current hart `ms` milliseconds from now
+pub fn set_next_trigger(ms: usize) {
+    SLICE_END[hart_id()].store(
+        get_time() + CLOCK_FREQ / MSEC_PER_SEC * ms,
+        Ordering::Relaxed,
+    );
+    program_timer();
+}
+/// end the time slice of the current hart, leaving timer interrupts for the kernel timers
+pub fn stop_timer() {
+    SLICE_END[hart_id()].store(usize::MAX, Ordering::Relaxed);
+    program_timer();
+}
+/// whether the time slice of the current hart has ended
+pub fn slice_expired() -> bool {
+    get_time() >= SLICE_END[hart_id()].load(Ordering::Relaxed)
+}
+/// set the timer interrupt of the current hart at the end of its time slice or at
+/// the next kernel timer, whichever comes first
+fn program_timer() {
+    let slice_end = SLICE_END[hart_id()].load(Ordering::Relaxed);
+    set_timer(next_expire().map_or(slice_end, |expire| expire.min(slice_end)));
+}
+
+/// Time interval in seconds and nanoseconds, as in POSIX
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TimeSpec {
+    pub tv_sec: usize,
+    pub tv_nsec: usize,
+}
+
+impl TimeSpec {
+    /// Length in timer ticks, saturating, `None` if `tv_nsec` is out of range
+    pub fn to_ticks(&self) -> Option<usize> {
+        if self.tv_nsec >= NSEC_PER_SEC {
+            return None;
+        }
+        Some(
+            self.tv_sec
+                .saturating_mul(CLOCK_FREQ)
+                .saturating_add(self.tv_nsec * CLOCK_FREQ / NSEC_PER_SEC),
+        )
+    }
+    /// Interval of `ticks` timer ticks
+    pub fn from_ticks(ticks: usize) -> Self {
+        Self {
+            tv_sec: ticks / CLOCK_FREQ,
+            tv_nsec: ticks % CLOCK_FREQ * NSEC_PER_SEC / CLOCK_FREQ,
+        }
+    }
+    /// Interval of `ns` nanoseconds
+    pub fn from_nanos(ns: usize) -> Self {
+        Self {
+            tv_sec: ns / NSEC_PER_SEC,
+            tv_nsec: ns % NSEC_PER_SEC,
+        }
+    }
+}
+
+/// Time interval in seconds and microseconds, as in POSIX
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TimeVal {
+    pub tv_sec: usize,
+    pub tv_usec: usize,
+}
+
+impl TimeVal {
+    /// Length in timer ticks, saturating, `None` if `tv_usec` is out of range
+    pub fn to_ticks(&self) -> Option<usize> {
+        if self.tv_usec >= USEC_PER_SEC {
+            return None;
+        }
+        Some(
+            self.tv_sec
+                .saturating_mul(CLOCK_FREQ)
+                .saturating_add(self.tv_usec * CLOCK_FREQ / USEC_PER_SEC),
+        )
+    }
+    /// Interval of `ticks` timer ticks, rounded up to a microsecond
+    pub fn from_ticks(ticks: usize) -> Self {
+        let usec = (ticks % CLOCK_FREQ * USEC_PER_SEC + CLOCK_FREQ - 1) / CLOCK_FREQ;
+        Self {
+            tv_sec: ticks / CLOCK_FREQ + usec / USEC_PER_SEC,
+            tv_usec: usec % USEC_PER_SEC,
+        }
+    }
+    /// Interval of `ns` nanoseconds, rounded down to a microsecond
+    pub fn from_nanos(ns: usize) -> Self {
+        Self {
+            tv_sec: ns / NSEC_PER_SEC,
+            tv_usec: ns % NSEC_PER_SEC / (NSEC_PER_SEC / USEC_PER_SEC),
+        }
+    }
+}
+
+/// Value of an interval timer, as in POSIX `setitimer`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ITimerVal {
+    /// period after the first expiration, 0 for a one-shot timer
+    pub it_interval: TimeVal,
+    /// time left until the next expiration, 0 if disarmed
+    pub it_value: TimeVal,
+}
+
+/// Identifier of a kernel timer, never reused
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimerId(u64);
+
+/// A kernel timer calling `callback` at time `expire` in timer ticks, then
+/// every `period` ticks unless `period` is 0
+struct Timer {
+    id: TimerId,
+    expire: usize,
+    period: usize,
+    callback: Box<dyn FnMut() + Send>,
+}
+
+/// number of slots of the timer wheel
+const WHEEL_SLOTS: usize = 256;
+/// timer ticks covered by a slot of the timer wheel
+const SLOT_TICKS: usize = CLOCK_FREQ / MSEC_PER_SEC;
+
+/// Kernel timers hashed by expiry into slots of `SLOT_TICKS` ticks, wrapping
+/// around every `WHEEL_SLOTS` slots. A slot holds the timers of all rounds,
+/// those of later rounds are skipped until the wheel comes around to them.
+struct TimerWheel {
+    slots: Vec<Vec<Timer>>,
+    /// slot number, i.e. time in units of `SLOT_TICKS`, checked next. Timers
+    /// expiring before it are added to its slot.
+    cursor: usize,
+    /// slot number and expiry of each armed timer, including one being fired
+    armed: BTreeMap<TimerId, (usize, usize)>,
+    next_id: u64,
+}
+
+impl TimerWheel {
+    fn new() -> Self {
+        Self {
+            slots: (0..WHEEL_SLOTS).map(|_| Vec::new()).collect(),
+            cursor: get_time() / SLOT_TICKS,
+            armed: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+    fn insert(&mut self, timer: Timer) {
+        let slot = (timer.expire / SLOT_TICKS).max(self.cursor);
+        self.armed.insert(timer.id, (slot, timer.expire));
+        self.slots[slot % WHEEL_SLOTS].push(timer);
+    }
+    fn remove(&mut self, id: TimerId) -> bool {
+        match self.armed.remove(&id) {
+            Some((slot, _)) => {
+                // not in its slot while being fired
+                self.slots[slot % WHEEL_SLOTS].retain(|timer| timer.id != id);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Time of the earliest timer, looking for the first slot from the cursor
+    /// with a timer of the current round
+    fn next_expire(&self) -> Option<usize> {
+        for slot in self.cursor..self.cursor + WHEEL_SLOTS {
+            let expire = self.slots[slot % WHEEL_SLOTS]
+                .iter()
+                .filter(|timer| timer.expire / SLOT_TICKS <= slot)
+                .map(|timer| timer.expire)
+                .min();
+            if expire.is_some() {
+                return expire;
+            }
+        }
+        // all in later rounds
+        self.armed.values().map(|(_, expire)| *expire).min()
+    }
+    /// Take the timers expired by time `now`, moving the cursor up to it
+    fn take_expired(&mut self, now: usize) -> Vec<Timer> {
+        let now_slot = now / SLOT_TICKS;
+        let mut expired = Vec::new();
+        // a full turn visits every slot
+        let first = self.cursor.max(now_slot.saturating_sub(WHEEL_SLOTS - 1));
+        for slot in first..=now_slot {
+            let timers = &mut self.slots[slot % WHEEL_SLOTS];
+            let mut i = 0;
+            while i < timers.len() {
+                if timers[i].expire <= now {
+                    expired.push(timers.swap_remove(i));
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        // timers left in the slot of `now` expire later in it
+        self.cursor = self.cursor.max(now_slot);
+        for timer in expired.iter() {
+            if timer.period == 0 {
+                self.armed.remove(&timer.id);
+            }
+        }
+        expired
+    }
+}
+
+lazy_static! {
+    /// Armed kernel timers
+    static ref TIMER_WHEEL: ExclusiveCell<TimerWheel> = ExclusiveCell::new(TimerWheel::new());
+}
+
+/// Call `callback` at time `expire` in timer ticks, then every `period` ticks
+/// until cancelled unless `period` is 0. Callbacks run with interrupts disabled,
+/// on whichever hart checks the timers first.
+pub fn start_timer(expire: usize, period: usize, callback: Box<dyn FnMut() + Send>) -> TimerId {
+    let mut wheel = TIMER_WHEEL.exclusive_access();
+    let id = TimerId(wheel.next_id);
+    wheel.next_id += 1;
+    wheel.insert(Timer {
+        id,
+        expire,
+        period,
+        callback,
+    });
+    drop(wheel);
+    program_timer();
+    id
+}
+
+/// Disarm timer `id`, false if it has expired or been cancelled already
+pub fn cancel_timer(id: TimerId) -> bool {
+    TIMER_WHEEL.exclusive_access().remove(id)
+}
+
+/// Time in timer ticks of the next expiration of timer `id`, `None` if it is not armed
+pub fn timer_expire(id: TimerId) -> Option<usize> {
+    TIMER_WHEEL
+        .exclusive_access()
+        .armed
+        .get(&id)
+        .map(|(_, expire)| *expire)
+}
+
+/// Wake up `task` at time `expire` in timer ticks. The timer holds `task` until it
+/// expires or is cancelled with [`cancel_timer`].
+pub fn add_timer(expire: usize, task: Arc<TaskControlBlock>) -> TimerId {
+    start_timer(expire, 0, Box::new(move || wakeup_task(Arc::clone(&task))))
+}
+
+/// Time in timer ticks of the next kernel timer to expire, `None` if there is none
+pub fn next_expire() -> Option<usize> {
+    TIMER_WHEEL.exclusive_access().next_expire()
+}
+
+/// Run the callbacks of the expired kernel timers and rearm the periodic ones,
+/// called from the timer interrupt
+pub fn check_timer() {
+    let now = get_time();
+    // release the wheel before running the callbacks, which take other locks
+    let expired = TIMER_WHEEL.exclusive_access().take_expired(now);
+    for mut timer in expired {
+        (timer.callback)();
+        if timer.period != 0 {
+            // skip the periods that have already passed
+            timer.expire += ((now - timer.expire) / timer.period + 1) * timer.period;
+            let mut wheel = TIMER_WHEEL.exclusive_access();
+            // unless cancelled by the callback or on another hart meanwhile
+            if wheel.armed.contains_key(&timer.id) {
+                wheel.insert(timer);
+            }
+        }
+    }
+    program_timer();
 }
@@ -1,8 +1,9 @@
 //! Implementation of [`TrapContext`]
-use riscv::register::sstatus::{self, Sstatus, SPP};
+use super::irq_handler;
+use riscv::register::sstatus::{self, Sstatus, FS, SPP};
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 ///trap context structure containing sstatus, sepc and registers
 pub struct TrapContext {
     /// general regs[0..31]
@@ -17,6 +18,10 @@ pub struct TrapContext {
     pub kernel_sp: usize,
     /// Addr of trap_handler function
     pub trap_handler: usize,
+    /// tp of the kernel, the id of the hart the task last returned to user mode from
+    pub kernel_tp: usize,
+    /// Addr of irq_handler function, entered on the fast path of interrupts
+    pub irq_handler: usize,
 }
 
 impl TrapContext {
@@ -35,6 +40,8 @@ impl TrapContext {
         let mut sstatus = sstatus::read();
         // set CPU privilege to User after trapping back
         sstatus.set_spp(SPP::User);
+        // the FPU is turned on at the first FP instruction
+        sstatus.set_fs(FS::Off);
         let mut cx = Self {
             x: [0; 32],
             sstatus,
@@ -42,6 +49,8 @@ impl TrapContext {
             kernel_satp,
             kernel_sp,
             trap_handler,
+            kernel_tp: 0,
+            irq_handler: irq_handler as usize,
         };
         cx.set_sp(sp);
         cx
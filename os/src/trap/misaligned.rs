@@ -0,0 +1,111 @@
+//! Emulation of misaligned loads and stores of user programs
+//!
+//! Harts may raise an exception on a misaligned access instead of carrying it
+//! out. The faulting instruction is decoded, the access is done byte by byte
+//! through the page table of the user address space, and the program resumes
+//! after the instruction, as if the hart had supported it.
+use super::TrapContext;
+use crate::mm::{copy_from_user, copy_slice_to_user};
+
+/// Why a misaligned access could not be emulated
+pub enum MisalignedError {
+    /// not an integer load or store, or the instruction could not be fetched
+    Unsupported,
+    /// the bytes accessed are not accessible to the program
+    Fault,
+}
+
+/// A decoded load or store of `width` bytes, in an instruction of `len` bytes
+struct Access {
+    store: bool,
+    /// rd of a load, rs2 of a store
+    reg: usize,
+    width: usize,
+    signed: bool,
+    len: usize,
+}
+
+/// Fetch the instruction at `pc`, a compressed one in the lower 16 bits
+fn fetch(token: usize, pc: usize) -> Option<u32> {
+    let low = copy_from_user(token, pc as *const u16)? as u32;
+    if low & 0b11 != 0b11 {
+        return Some(low);
+    }
+    let high = copy_from_user(token, (pc + 2) as *const u16)? as u32;
+    Some(low | high << 16)
+}
+
+/// Decode the integer loads and stores of RV64I and RV64C
+fn decode(inst: u32) -> Option<Access> {
+    let bits = |lo: u32, len: u32| ((inst >> lo) & ((1 << len) - 1)) as usize;
+    let access = |store, reg, width, signed, len| {
+        Some(Access {
+            store,
+            reg,
+            width,
+            signed,
+            len,
+        })
+    };
+    if inst & 0b11 == 0b11 {
+        let funct3 = bits(12, 3);
+        return match bits(0, 7) {
+            // LB, LH, LW, LD, LBU, LHU, LWU
+            0x03 if funct3 != 7 => access(false, bits(7, 5), 1 << (funct3 & 3), funct3 < 4, 4),
+            // SB, SH, SW, SD
+            0x23 if funct3 < 4 => access(true, bits(20, 5), 1 << funct3, false, 4),
+            _ => None,
+        };
+    }
+    // the registers x8-x15 of the quadrant 0 instructions
+    let reg_prime = 8 + bits(2, 3);
+    match (bits(0, 2), bits(13, 3)) {
+        // C.LW, C.LD
+        (0b00, 0b010) => access(false, reg_prime, 4, true, 2),
+        (0b00, 0b011) => access(false, reg_prime, 8, false, 2),
+        // C.SW, C.SD
+        (0b00, 0b110) => access(true, reg_prime, 4, false, 2),
+        (0b00, 0b111) => access(true, reg_prime, 8, false, 2),
+        // C.LWSP, C.LDSP
+        (0b10, 0b010) => access(false, bits(7, 5), 4, true, 2),
+        (0b10, 0b011) => access(false, bits(7, 5), 8, false, 2),
+        // C.SWSP, C.SDSP
+        (0b10, 0b110) => access(true, bits(2, 5), 4, false, 2),
+        (0b10, 0b111) => access(true, bits(2, 5), 8, false, 2),
+        _ => None,
+    }
+}
+
+/// Carry out the misaligned access at `addr` of the instruction at `sepc` in
+/// `cx` and move `sepc` past it
+pub fn emulate_misaligned(
+    token: usize,
+    cx: &mut TrapContext,
+    addr: usize,
+) -> Result<(), MisalignedError> {
+    let inst = fetch(token, cx.sepc).ok_or(MisalignedError::Unsupported)?;
+    let access = decode(inst).ok_or(MisalignedError::Unsupported)?;
+    let width = access.width;
+    if access.store {
+        let bytes = (cx.x[access.reg] as u64).to_le_bytes();
+        // all or nothing, the range is checked before any byte is written
+        copy_slice_to_user(token, addr as *mut u8, &bytes[..width])
+            .ok_or(MisalignedError::Fault)?;
+    } else {
+        let mut bytes = [0u8; 8];
+        for (i, byte) in bytes[..width].iter_mut().enumerate() {
+            *byte = copy_from_user(token, (addr + i) as *const u8).ok_or(MisalignedError::Fault)?;
+        }
+        let mut value = u64::from_le_bytes(bytes);
+        if access.signed && width < 8 {
+            let shift = 64 - 8 * width;
+            value = ((value << shift) as i64 >> shift) as u64;
+        }
+        // writes to x0 are discarded
+        if access.reg != 0 {
+            cx.x[access.reg] = value as usize;
+        }
+    }
+    cx.sepc += access.len;
+    Ok(())
+}
@@ -1,46 +1,85 @@
 //! Trap handling functionality
 //!
-//! For rCore, we have a single trap entry point, namely `__alltraps`. At
-//! initialization in [`init()`], we set the `stvec` CSR to point to it.
+//! Traps from user space enter through the vector table `__trapvec` at the
+//! start of the trampoline, `stvec` points to it in vectored mode whenever the
+//! hart returns to user mode.
 //!
-//! All traps go through `__alltraps`, which is defined in `trap.S`. The
-//! assembly language code does just enough work restore the kernel space
-//! context, ensuring that Rust code safely runs, and transfers control to
-//! [`trap_handler()`].
+//! Exceptions, syscalls and IPIs go through `__alltraps`, which is defined in
+//! `trap.S`. The assembly language code does just enough work restore the
+//! kernel space context, ensuring that Rust code safely runs, and transfers
+//! control to [`trap_handler()`]. Timer and external interrupts take the fast
+//! path `__irqtrap` to [`irq_handler()`] instead, saving only the registers
+//! that it may change, and return to the interrupted context right away unless
+//! there are signals to deliver.
 //!
 //! It then calls different functionality based on what exactly the exception
 //! was. For example, timer interrupts trigger task preemption, and syscalls go
-//! to [`syscall()`].
+//! to [`syscall()`]. Pending signals are delivered before returning to user
+//! mode.
+//!
+//! Interrupts stay disabled in the kernel, except while a device interrupt is
+//! handled: interrupts of higher priority nest in its handler through
+//! `__kerneltrap` in `kernel_trap.S`, on the same kernel stack. The timer has
+//! the highest priority, devices are ordered by their PLIC priorities.
+//! Handlers leave what can wait to softirqs, run once out of the outermost
+//! handler, see `softirq.rs`.
 mod context;
+mod misaligned;
+mod softirq;
 
-use crate::config::{TRAMPOLINE, TRAP_CONTEXT};
+use crate::config::{HARDWARE_PTE_AD, MAX_HARTS, TRAMPOLINE};
+use crate::drivers::handle_irq;
+use crate::hart::{clear_ipi, hart_id};
+use crate::mm::{PTEFlags, PageTable, VirtAddr};
+use crate::sync::lockdep;
 use crate::syscall::syscall;
 use crate::task::{
-    current_trap_cx, current_user_token, exit_current_and_run_next, suspend_current_and_run_next,
+    charge_current_time, check_cpu_limit, current_has_signal, current_process, current_trap_cx,
+    current_trap_cx_user_va, current_user_token, enable_current_fp, force_signal_current,
+    handle_signals, load_current_fp, preempt_current_and_run_next, watchdog_kernel_enter,
+    watchdog_kernel_leave, SignalFlags, BUS_ADRALN, BUS_ADRERR, ILL_ILLOPC, SEGV_ACCERR,
+    SEGV_MAPERR, TRAP_BRKPT,
 };
-use crate::timer::set_next_trigger;
+use crate::timer::{check_timer, slice_expired};
 use core::arch::{asm, global_asm};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use misaligned::{emulate_misaligned, MisalignedError};
 use riscv::register::{
     mtvec::TrapMode,
     scause::{self, Exception, Interrupt, Trap},
     sie, stval, stvec,
 };
+use softirq::do_softirq;
 
 global_asm!(include_str!("trap.S"));
+global_asm!(include_str!("kernel_trap.S"));
+
+/// Most interrupt handlers nesting on a hart, one for each PLIC priority and the timer
+const MAX_IRQ_DEPTH: usize = 8;
+/// Number of interrupt handlers running on each hart, each nesting in the one before
+static IRQ_DEPTH: [AtomicUsize; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+    [ZERO; MAX_HARTS]
+};
+
 /// initialize CSR `stvec` as the entry of `__alltraps`
 pub fn init() {
     set_kernel_trap_entry();
 }
 
 fn set_kernel_trap_entry() {
+    extern "C" {
+        fn __kerneltrap();
+    }
     unsafe {
-        stvec::write(trap_from_kernel as usize, TrapMode::Direct);
+        stvec::write(__kerneltrap as usize, TrapMode::Direct);
     }
 }
 
 fn set_user_trap_entry() {
     unsafe {
-        stvec::write(TRAMPOLINE as usize, TrapMode::Direct);
+        stvec::write(TRAMPOLINE as usize, TrapMode::Vectored);
     }
 }
 /// enable timer interrupt in sie CSR
@@ -49,11 +88,72 @@ pub fn enable_timer_interrupt() {
         sie::set_stimer();
     }
 }
+/// enable software interrupt in sie CSR, for the IPIs waking up idle harts
+pub fn enable_software_interrupt() {
+    unsafe {
+        sie::set_ssoft();
+    }
+}
+/// enable external interrupt in sie CSR, for the devices routed by the PLIC
+pub fn enable_external_interrupt() {
+    unsafe {
+        sie::set_sext();
+    }
+}
+
+/// Mark the current hart as running an interrupt handler until `irq_exit`
+pub fn irq_enter() {
+    let depth = IRQ_DEPTH[hart_id()].fetch_add(1, Ordering::Relaxed) + 1;
+    assert!(depth <= MAX_IRQ_DEPTH, "interrupt handlers nested too deep");
+    lockdep::irq_enter();
+}
+
+/// Mark the end of the interrupt handler entered last by `irq_enter`, and run
+/// the softirqs raised by the handlers once out of the outermost one
+pub fn irq_exit() {
+    lockdep::irq_exit();
+    if IRQ_DEPTH[hart_id()].fetch_sub(1, Ordering::Relaxed) == 1 {
+        // masked by a timer interrupt nesting in a device handler, see `trap_from_kernel`
+        unsafe {
+            sie::set_stimer();
+        }
+        do_softirq();
+    }
+}
+
+/// `si_code` of a `SIGSEGV` at user address `addr`: no mapping or no permission
+fn segv_code(addr: usize) -> u8 {
+    match PageTable::from_token(current_user_token()).get_flags(VirtAddr::from(addr).floor()) {
+        Some(_) => SEGV_ACCERR,
+        None => SEGV_MAPERR,
+    }
+}
+
+/// Set the accessed or dirty bit whose absence raised the user page `fault`
+/// at `addr`, on harts leaving them to software. Return false if the fault
+/// has another cause.
+fn resolve_accessed_dirty(fault: Trap, addr: usize) -> bool {
+    if HARDWARE_PTE_AD {
+        return false;
+    }
+    let access = match fault {
+        Trap::Exception(Exception::StorePageFault) => PTEFlags::W,
+        Trap::Exception(Exception::InstructionPageFault) => PTEFlags::X,
+        _ => PTEFlags::R,
+    };
+    current_process()
+        .inner_exclusive_access()
+        .memory_set
+        .handle_accessed_dirty_fault(VirtAddr::from(addr).floor(), access)
+}
 
 #[no_mangle]
 /// handle an interrupt, exception, or system call from user space
 pub fn trap_handler() -> ! {
     set_kernel_trap_entry();
+    // the time since returning to user mode was spent there
+    charge_current_time(true);
+    watchdog_kernel_enter();
     let scause = scause::read();
     let stval = stval::read();
     match scause.cause() {
@@ -62,34 +162,106 @@ pub fn trap_handler() -> ! {
             let mut cx = current_trap_cx();
             cx.sepc += 4;
             // get system call return value
-            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]);
+            let result = syscall(
+                cx.x[17],
+                [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]],
+            );
             // cx is changed during sys_exec, so we have to call it again
             cx = current_trap_cx();
             cx.x[10] = result as usize;
         }
-        Trap::Exception(Exception::StoreFault)
-        | Trap::Exception(Exception::StorePageFault)
-        | Trap::Exception(Exception::InstructionFault)
+        Trap::Exception(Exception::StorePageFault)
         | Trap::Exception(Exception::InstructionPageFault)
-        | Trap::Exception(Exception::LoadFault)
         | Trap::Exception(Exception::LoadPageFault) => {
-            println!(
-                "[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, kernel killed it.",
+            if !resolve_accessed_dirty(scause.cause(), stval) {
+                warn!(
+                    "{:?} in application, bad addr = {:#x}, bad instruction = {:#x}, raising SIGSEGV.",
+                    scause.cause(),
+                    stval,
+                    current_trap_cx().sepc,
+                );
+                force_signal_current(
+                    SignalFlags::SIGSEGV.lowest_signum().unwrap(),
+                    segv_code(stval),
+                    stval,
+                );
+            }
+        }
+        Trap::Exception(Exception::LoadMisaligned)
+        | Trap::Exception(Exception::StoreMisaligned) => {
+            match emulate_misaligned(current_user_token(), current_trap_cx(), stval) {
+                Ok(()) => {}
+                Err(MisalignedError::Fault) => {
+                    warn!(
+                        "{:?} in application, bad addr = {:#x}, bad instruction = {:#x}, raising SIGSEGV.",
+                        scause.cause(),
+                        stval,
+                        current_trap_cx().sepc,
+                    );
+                    force_signal_current(
+                        SignalFlags::SIGSEGV.lowest_signum().unwrap(),
+                        segv_code(stval),
+                        stval,
+                    );
+                }
+                Err(MisalignedError::Unsupported) => {
+                    warn!(
+                        "{:?} in application, bad addr = {:#x}, bad instruction = {:#x}, raising SIGBUS.",
+                        scause.cause(),
+                        stval,
+                        current_trap_cx().sepc,
+                    );
+                    force_signal_current(
+                        SignalFlags::SIGBUS.lowest_signum().unwrap(),
+                        BUS_ADRALN,
+                        stval,
+                    );
+                }
+            }
+        }
+        Trap::Exception(Exception::StoreFault)
+        | Trap::Exception(Exception::InstructionFault)
+        | Trap::Exception(Exception::LoadFault) => {
+            warn!(
+                "{:?} in application, bad addr = {:#x}, bad instruction = {:#x}, raising SIGBUS.",
                 scause.cause(),
                 stval,
                 current_trap_cx().sepc,
             );
-            // page fault exit code
-            exit_current_and_run_next(-2);
+            // mapped, but no memory behind the physical address
+            force_signal_current(
+                SignalFlags::SIGBUS.lowest_signum().unwrap(),
+                BUS_ADRERR,
+                stval,
+            );
+        }
+        Trap::Exception(Exception::IllegalInstruction) if enable_current_fp() => {
+            // the first FP instruction of the task, run it again with the FPU on
         }
         Trap::Exception(Exception::IllegalInstruction) => {
-            println!("[kernel] IllegalInstruction in application, kernel killed it.");
-            // illegal instruction exit code
-            exit_current_and_run_next(-3);
+            let sepc = current_trap_cx().sepc;
+            warn!(
+                "IllegalInstruction in application, bad instruction = {:#x}, raising SIGILL.",
+                sepc
+            );
+            force_signal_current(
+                SignalFlags::SIGILL.lowest_signum().unwrap(),
+                ILL_ILLOPC,
+                sepc,
+            );
         }
-        Trap::Interrupt(Interrupt::SupervisorTimer) => {
-            set_next_trigger();
-            suspend_current_and_run_next();
+        Trap::Exception(Exception::Breakpoint) => {
+            // e.g. left by a debugger, the handler decides where to resume
+            let sepc = current_trap_cx().sepc;
+            force_signal_current(
+                SignalFlags::SIGTRAP.lowest_signum().unwrap(),
+                TRAP_BRKPT,
+                sepc,
+            );
+        }
+        Trap::Interrupt(Interrupt::SupervisorSoft) => {
+            // an IPI for the idle loop arriving after the hart found a task
+            clear_ipi();
         }
         _ => {
             panic!(
@@ -99,23 +271,82 @@ pub fn trap_handler() -> ! {
             );
         }
     }
+    // deliver signals, e.g. sent by the syscall, before returning to user mode
+    handle_signals();
     //println!("before trap_return");
     trap_return();
 }
 
+#[no_mangle]
+/// handle a timer or external interrupt from user space, entered by `__irqtrap`
+/// with only the registers it may change saved in the TrapContext. Return 0 to
+/// resume the interrupted context right away, otherwise the address of the
+/// handler to continue at on the full path, once the other registers are saved.
+pub extern "C" fn irq_handler() -> usize {
+    set_kernel_trap_entry();
+    // the time since returning to user mode was spent there
+    charge_current_time(true);
+    watchdog_kernel_enter();
+    let scause = scause::read();
+    match scause.cause() {
+        Trap::Interrupt(Interrupt::SupervisorTimer) => {
+            irq_enter();
+            check_timer();
+            check_cpu_limit();
+            irq_exit();
+            // otherwise raised for a kernel timer
+            if slice_expired() {
+                // the scheduler arms the timer for the next task
+                preempt_current_and_run_next();
+                // the task may go on on another hart
+                unsafe {
+                    asm!("fence.i");
+                }
+            }
+        }
+        Trap::Interrupt(Interrupt::SupervisorExternal) => {
+            irq_enter();
+            handle_irq();
+            irq_exit();
+        }
+        _ => unreachable!("{:?} on the interrupt fast path", scause.cause()),
+    }
+    // signal frames take all the registers
+    if current_has_signal() {
+        return signal_handler as usize;
+    }
+    prepare_return();
+    0
+}
+
+#[no_mangle]
+/// deliver the signals pending after an interrupt, on the full path from `__irqtrap`
+pub fn signal_handler() -> ! {
+    handle_signals();
+    trap_return();
+}
+
+/// account for the current task leaving the kernel to user mode
+fn prepare_return() {
+    set_user_trap_entry();
+    charge_current_time(false);
+    load_current_fp();
+    watchdog_kernel_leave();
+}
+
 #[no_mangle]
 /// set the new addr of __restore asm function in TRAMPOLINE page,
 /// set the reg a0 = trap_cx_ptr, reg a1 = phy addr of usr page table,
 /// finally, jump to new addr of __restore asm function
 pub fn trap_return() -> ! {
-    set_user_trap_entry();
-    let trap_cx_ptr = TRAP_CONTEXT;
+    prepare_return();
+    let trap_cx_ptr = current_trap_cx_user_va();
     let user_satp = current_user_token();
     extern "C" {
-        fn __alltraps();
+        fn __trapvec();
         fn __restore();
     }
-    let restore_va = __restore as usize - __alltraps as usize + TRAMPOLINE;
+    let restore_va = __restore as usize - __trapvec as usize + TRAMPOLINE;
     unsafe {
         asm!(
             "fence.i",
@@ -129,12 +360,39 @@ pub fn trap_return() -> ! {
 }
 
 #[no_mangle]
-/// Unimplement: traps/interrupts/exceptions from kernel mode
-/// Todo: Chapter 9: I/O device
-pub fn trap_from_kernel() -> ! {
-    use riscv::register::sepc;
-    println!("stval = {:#x}, sepc = {:#x}", stval::read(), sepc::read());
-    panic!("a trap {:?} from kernel!", scause::read().cause());
+/// handle an interrupt nesting in the handler of a device interrupt of lower
+/// priority, called by `__kerneltrap`. Exceptions of the kernel are fatal.
+pub fn trap_from_kernel() {
+    let scause = scause::read();
+    match scause.cause() {
+        Trap::Interrupt(Interrupt::SupervisorTimer) => {
+            irq_enter();
+            check_timer();
+            if slice_expired() {
+                // no task switch in an interrupt handler, the task is preempted once
+                // back from it, mask the timer interrupt pending until then
+                unsafe {
+                    sie::clear_stimer();
+                }
+            }
+            irq_exit();
+        }
+        Trap::Interrupt(Interrupt::SupervisorExternal) => {
+            irq_enter();
+            handle_irq();
+            irq_exit();
+        }
+        Trap::Interrupt(Interrupt::SupervisorSoft) => {
+            // an IPI for the idle loop, which checks for tasks after the handler
+            clear_ipi();
+        }
+        _ => {
+            use riscv::register::sepc;
+            error!("stval = {:#x}, sepc = {:#x}", stval::read(), sepc::read());
+            panic!("a trap {:?} from kernel!", scause.cause());
+        }
+    }
 }
 
 pub use context::TrapContext;
+pub use softirq::{open_softirq, raise_softirq, Softirq};
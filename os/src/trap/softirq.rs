@@ -0,0 +1,79 @@
+//! Softirqs, the bottom halves of interrupt handlers
+//!
+//! A device interrupt handler only does what cannot wait, e.g. acknowledging
+//! the device, and raises a softirq for the rest, e.g. waking up the tasks
+//! waiting for it. The softirqs raised on a hart run once its outermost
+//! interrupt handler returns, still with interrupts disabled but with the
+//! interrupt completed at the PLIC, so that the device may raise the next one.
+//!
+//! Work that may block or take long goes to the worker thread instead, see
+//! [`crate::task::queue_work`].
+use crate::config::MAX_HARTS;
+use crate::hart::hart_id;
+use crate::sync::SpinNoIrqLock;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Kinds of deferred work, run in this order when several are pending
+#[derive(Copy, Clone, Debug)]
+pub enum Softirq {
+    /// completed requests of the block device
+    Block,
+    /// frames received by the network device
+    NetRx,
+    /// input received by the UARTs or the virtio console
+    Tty,
+    /// events received by the input devices
+    Input,
+}
+
+/// Number of kinds of [`Softirq`]
+const NR_SOFTIRQS: usize = 4;
+/// Rounds of softirqs raised again by their handlers run in a row, the rest
+/// is left pending for the next interrupt
+const MAX_SOFTIRQ_RESTART: usize = 10;
+
+/// handler of each kind of softirq, `None` until opened
+static SOFTIRQ_HANDLERS: SpinNoIrqLock<[Option<fn()>; NR_SOFTIRQS]> =
+    SpinNoIrqLock::new([None; NR_SOFTIRQS]);
+/// Bitmap of the softirqs raised on each hart and not run yet
+static PENDING: [AtomicUsize; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+    [ZERO; MAX_HARTS]
+};
+
+/// Call `handler` for the softirqs `softirq` raised. Panic if it has a handler already.
+pub fn open_softirq(softirq: Softirq, handler: fn()) {
+    let mut handlers = SOFTIRQ_HANDLERS.lock();
+    assert!(
+        handlers[softirq as usize].is_none(),
+        "softirq {:?} opened twice",
+        softirq
+    );
+    handlers[softirq as usize] = Some(handler);
+}
+
+/// Run the handler of `softirq` on the current hart once it leaves the
+/// interrupt handlers. Raising it again before that runs the handler once.
+pub fn raise_softirq(softirq: Softirq) {
+    PENDING[hart_id()].fetch_or(1 << softirq as usize, Ordering::Relaxed);
+}
+
+/// Run the softirqs pending on the current hart, called by `irq_exit` out of
+/// the outermost interrupt handler
+pub(super) fn do_softirq() {
+    let pending = &PENDING[hart_id()];
+    for _ in 0..MAX_SOFTIRQ_RESTART {
+        let raised = pending.swap(0, Ordering::Relaxed);
+        if raised == 0 {
+            return;
+        }
+        let handlers = *SOFTIRQ_HANDLERS.lock();
+        for softirq in (0..NR_SOFTIRQS).filter(|softirq| raised & (1 << softirq) != 0) {
+            match handlers[softirq] {
+                Some(handler) => handler(),
+                None => panic!("softirq {} raised without a handler", softirq),
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, getcpu, sched_getaffinity, sched_setaffinity, waitpid, wexitstatus, yield_, EINVAL,
+};
+
+/// Harts a test may find, bits of an affinity mask
+const MAX_HARTS: usize = 64;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut all = 0;
+    assert!(sched_getaffinity(0, &mut all) > 0);
+    assert_ne!(all, 0);
+    // a mask without any online hart is refused
+    assert_eq!(sched_setaffinity(0, 0), -EINVAL);
+
+    // pinned to a hart, the thread only runs there
+    let mut harts = 0;
+    let mut last = 0;
+    for hart in 0..MAX_HARTS {
+        if all & (1 << hart) == 0 || sched_setaffinity(0, 1 << hart) != 0 {
+            continue;
+        }
+        harts += 1;
+        last = hart;
+        for _ in 0..10 {
+            assert_eq!(getcpu(), hart as isize);
+            yield_();
+        }
+    }
+    assert!(harts > 0);
+
+    // children inherit the mask, and it can be changed from the parent
+    assert_eq!(sched_setaffinity(0, all), 0);
+    let pid = fork();
+    if pid == 0 {
+        let mut mask = 0;
+        assert!(sched_getaffinity(0, &mut mask) > 0);
+        assert_eq!(mask, all);
+        // wait for the parent to pin us
+        while mask == all {
+            yield_();
+            sched_getaffinity(0, &mut mask);
+        }
+        for _ in 0..10 {
+            yield_();
+            assert_eq!(getcpu(), last as isize);
+        }
+        exit(0);
+    }
+    assert_eq!(sched_setaffinity(pid as usize, 1 << last), 0);
+    let mut mask = 0;
+    assert!(sched_getaffinity(pid as usize, &mut mask) > 0);
+    assert_eq!(mask, 1 << last);
+    let mut status = 0;
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    assert_eq!(wexitstatus(status), 0);
+    println!("affinity passed on {} harts!", harts);
+    0
+}
@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{
+    alarm, exit, fork, getitimer, setitimer, sigaction, sleep, waitpid, wifsignaled, wtermsig,
+    ITimerVal, SignalAction, TimeVal, ITIMER_REAL, SIGALRM,
+};
+
+static ALARMS: AtomicUsize = AtomicUsize::new(0);
+
+fn handler(_signum: i32) {
+    ALARMS.fetch_add(1, Ordering::SeqCst);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // the default action of SIGALRM terminates
+    let pid = fork();
+    if pid == 0 {
+        alarm(1);
+        sleep(10000);
+        exit(0);
+    }
+    let mut status = 0;
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    assert!(wifsignaled(status) && wtermsig(status) == SIGALRM);
+
+    let action = SignalAction {
+        handler: handler as usize,
+        ..Default::default()
+    };
+    assert_eq!(sigaction(SIGALRM, Some(&action), None), 0);
+
+    // alarm returns the seconds left of the previous one, and 0 cancels it
+    assert_eq!(alarm(5), 0);
+    assert_eq!(alarm(0), 5);
+    sleep(100);
+    assert_eq!(ALARMS.load(Ordering::SeqCst), 0);
+
+    // a periodic timer fires until disarmed
+    let period = TimeVal {
+        tv_sec: 0,
+        tv_usec: 50_000,
+    };
+    let itimer = ITimerVal {
+        it_interval: period,
+        it_value: period,
+    };
+    assert_eq!(setitimer(ITIMER_REAL, &itimer, None), 0);
+    let mut curr = ITimerVal::default();
+    assert_eq!(getitimer(ITIMER_REAL, &mut curr), 0);
+    assert_eq!(curr.it_interval.tv_usec, 50_000);
+    while ALARMS.load(Ordering::SeqCst) < 3 {
+        sleep(10);
+    }
+    assert_eq!(setitimer(ITIMER_REAL, &ITimerVal::default(), None), 0);
+    let fired = ALARMS.load(Ordering::SeqCst);
+    sleep(200);
+    assert_eq!(ALARMS.load(Ordering::SeqCst), fired);
+    println!("alarm passed!");
+    0
+}
@@ -0,0 +1,50 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    clock_gettime, gettimeofday, sleep, TimeSpec, TimeVal, CLOCK_MONOTONIC, CLOCK_REALTIME,
+};
+
+/// 2020-01-01T00:00:00Z, the wall clock comes from the RTC and is later than it
+const EPOCH_2020: usize = 1_577_836_800;
+
+fn nanos(ts: &TimeSpec) -> usize {
+    ts.tv_sec * 1_000_000_000 + ts.tv_nsec
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut start = TimeSpec::default();
+    assert_eq!(clock_gettime(CLOCK_MONOTONIC, &mut start), 0);
+    let mut prev = nanos(&start);
+    for _ in 0..1000 {
+        let mut now = TimeSpec::default();
+        assert_eq!(clock_gettime(CLOCK_MONOTONIC, &mut now), 0);
+        assert!(now.tv_nsec < 1_000_000_000);
+        assert!(nanos(&now) >= prev);
+        prev = nanos(&now);
+    }
+    sleep(100);
+    let mut end = TimeSpec::default();
+    clock_gettime(CLOCK_MONOTONIC, &mut end);
+    let elapsed = nanos(&end) - nanos(&start);
+    println!("slept {} nsecs for 100 msecs", elapsed);
+    assert!(elapsed >= 100_000_000);
+
+    let mut realtime = TimeSpec::default();
+    assert_eq!(clock_gettime(CLOCK_REALTIME, &mut realtime), 0);
+    let mut tv = TimeVal::default();
+    assert_eq!(gettimeofday(&mut tv), 0);
+    println!("realtime {}.{:09}", realtime.tv_sec, realtime.tv_nsec);
+    assert!(realtime.tv_sec >= EPOCH_2020);
+    assert!(tv.tv_usec < 1_000_000);
+    assert!(tv.tv_sec >= realtime.tv_sec && tv.tv_sec <= realtime.tv_sec + 1);
+
+    // CLOCK_PROCESS_CPUTIME_ID is not supported
+    assert_eq!(clock_gettime(2, &mut realtime), -22);
+    println!("clock passed!");
+    0
+}
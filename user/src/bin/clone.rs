@@ -0,0 +1,64 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{clone, close, open, waitpid, waittid, wexitstatus, CloneFlags, OpenFlags};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+static mut STACK: [u8; 16384] = [0; 16384];
+
+fn add(arg: usize) -> i32 {
+    COUNTER.fetch_add(arg, Ordering::SeqCst);
+    0
+}
+
+fn open_file(_arg: usize) -> i32 {
+    open("clone_file\0", OpenFlags::CREATE | OpenFlags::WRONLY) as i32
+}
+
+fn wait_exit_code(pid: isize) -> i32 {
+    let mut status = 0;
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    wexitstatus(status)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let stack_top = unsafe { STACK.as_ptr() as usize + STACK.len() };
+    let thread_flags = CloneFlags::CLONE_VM | CloneFlags::CLONE_FILES | CloneFlags::CLONE_THREAD;
+
+    // a thread shares the memory of the process
+    let tid = clone(thread_flags, stack_top, add, 1);
+    assert!(tid > 0);
+    assert_eq!(waittid(tid as usize), 0);
+    assert_eq!(COUNTER.load(Ordering::SeqCst), 1);
+
+    // so does a process sharing the address space
+    let pid = clone(CloneFlags::CLONE_VM, stack_top, add, 2);
+    assert_eq!(wait_exit_code(pid), 0);
+    assert_eq!(COUNTER.load(Ordering::SeqCst), 3);
+
+    // but not a process with a copy of it
+    let pid = clone(CloneFlags::empty(), stack_top, add, 4);
+    assert_eq!(wait_exit_code(pid), 0);
+    assert_eq!(COUNTER.load(Ordering::SeqCst), 3);
+
+    // a file opened by a process sharing the fd table stays open after it exits
+    let pid = clone(
+        CloneFlags::CLONE_VM | CloneFlags::CLONE_FILES,
+        stack_top,
+        open_file,
+        0,
+    );
+    let fd = wait_exit_code(pid);
+    assert!(fd > 2);
+    assert_eq!(close(fd as usize), 0);
+
+    // a thread must share everything
+    assert!(clone(CloneFlags::CLONE_THREAD, stack_top, add, 8) < 0);
+    println!("clone passed!");
+    0
+}
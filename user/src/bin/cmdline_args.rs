@@ -0,0 +1,20 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    assert_eq!(argc, argv.len());
+    println!("argc = {}", argc);
+    for (i, arg) in argv.iter().enumerate() {
+        println!("argv[{}] = {}", i, arg);
+    }
+    // usertests runs it as "cmdline_args 1 2 3"
+    if argc == 4 {
+        assert_eq!(argv[0], "cmdline_args");
+        assert_eq!(argv[1..], ["1", "2", "3"]);
+    }
+    0
+}
@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, get_time, getpid, getrusage, times, waitpid, RUsage, Tms, RUSAGE_CHILDREN,
+    RUSAGE_SELF, RUSAGE_THREAD,
+};
+
+/// Spin in user mode for `ms` milliseconds, making a syscall now and then
+fn spin(ms: isize) {
+    let start = get_time();
+    while get_time() - start < ms {
+        let mut x = 0usize;
+        for i in 0..10000 {
+            unsafe {
+                core::ptr::write_volatile(&mut x, x.wrapping_add(i));
+            }
+        }
+    }
+}
+
+fn usecs(usage: &RUsage) -> (usize, usize) {
+    (
+        usage.ru_utime.tv_sec * 1_000_000 + usage.ru_utime.tv_usec,
+        usage.ru_stime.tv_sec * 1_000_000 + usage.ru_stime.tv_usec,
+    )
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    spin(100);
+    // many syscalls to spend time in the kernel
+    for _ in 0..10000 {
+        getpid();
+    }
+    let mut usage = RUsage::default();
+    assert_eq!(getrusage(RUSAGE_SELF, &mut usage), 0);
+    let (utime, stime) = usecs(&usage);
+    println!("self: user {}us, sys {}us", utime, stime);
+    assert!(utime > 0 && stime > 0);
+    let mut thread = RUsage::default();
+    assert_eq!(getrusage(RUSAGE_THREAD, &mut thread), 0);
+    assert!(usecs(&thread).0 >= utime);
+
+    // the time of a child is counted once it is reaped
+    let mut before = Tms::default();
+    let start = times(&mut before);
+    let pid = fork();
+    if pid == 0 {
+        spin(200);
+        exit(0);
+    }
+    let mut status = 0;
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    let mut after = Tms::default();
+    let end = times(&mut after);
+    println!(
+        "child: user {} ticks, sys {} ticks, real {} ticks",
+        after.tms_cutime - before.tms_cutime,
+        after.tms_cstime - before.tms_cstime,
+        end - start
+    );
+    assert!(after.tms_cutime > before.tms_cutime);
+    assert!(end > start);
+    let mut children = RUsage::default();
+    assert_eq!(getrusage(RUSAGE_CHILDREN, &mut children), 0);
+    assert!(usecs(&children).0 > 0);
+    println!("cputime passed!");
+    0
+}
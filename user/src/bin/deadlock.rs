@@ -0,0 +1,68 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use user_lib::{
+    enable_deadlock_detect, exit, mutex_blocking_create, mutex_lock, mutex_unlock,
+    semaphore_create, semaphore_down, semaphore_up, sleep, thread_create, waittid, EDEADLK,
+};
+
+static mut MUTEX_A: usize = 0;
+static mut MUTEX_B: usize = 0;
+static B_LOCKED: AtomicBool = AtomicBool::new(false);
+
+fn locks_b_then_a() -> ! {
+    unsafe {
+        assert_eq!(mutex_lock(MUTEX_B), 0);
+        B_LOCKED.store(true, Ordering::SeqCst);
+        // waits for the main thread to give up
+        assert_eq!(mutex_lock(MUTEX_A), 0);
+        mutex_unlock(MUTEX_A);
+        mutex_unlock(MUTEX_B);
+    }
+    exit(0)
+}
+
+fn takes_two(sem: usize) -> ! {
+    assert_eq!(semaphore_down(sem), 0);
+    // waits for the main thread to give up its unit
+    assert_eq!(semaphore_down(sem), 0);
+    semaphore_up(sem);
+    semaphore_up(sem);
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(enable_deadlock_detect(true), 0);
+    unsafe {
+        MUTEX_A = mutex_blocking_create() as usize;
+        MUTEX_B = mutex_blocking_create() as usize;
+        // locking a mutex again
+        assert_eq!(mutex_lock(MUTEX_A), 0);
+        assert_eq!(mutex_lock(MUTEX_A), -EDEADLK);
+        // locking in opposite orders
+        let tid = thread_create(locks_b_then_a as usize, 0);
+        while !B_LOCKED.load(Ordering::SeqCst) {
+            sleep(1);
+        }
+        sleep(10);
+        assert_eq!(mutex_lock(MUTEX_B), -EDEADLK);
+        mutex_unlock(MUTEX_A);
+        assert_eq!(waittid(tid as usize), 0);
+        // a semaphore with a unit for each of the threads, both wanting two
+        let sem = semaphore_create(2) as usize;
+        assert_eq!(semaphore_down(sem), 0);
+        let tid = thread_create(takes_two as usize, sem);
+        sleep(10);
+        // the other thread holds the other unit and waits for this one
+        assert_eq!(semaphore_down(sem), -EDEADLK);
+        semaphore_up(sem);
+        assert_eq!(waittid(tid as usize), 0);
+    }
+    println!("deadlock passed!");
+    0
+}
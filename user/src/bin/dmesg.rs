@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec;
+use user_lib::{
+    syslog, write, SYSLOG_ACTION_CLEAR, SYSLOG_ACTION_READ_ALL, SYSLOG_ACTION_READ_CLEAR,
+    SYSLOG_ACTION_SIZE_BUFFER,
+};
+
+/// Print the kernel messages; `-c` clears them after printing, `-C` only
+/// clears them
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let action = match argv.get(1).copied() {
+        None => SYSLOG_ACTION_READ_ALL,
+        Some("-c") if argc == 2 => SYSLOG_ACTION_READ_CLEAR,
+        Some("-C") if argc == 2 => {
+            return syslog(SYSLOG_ACTION_CLEAR, &mut []) as i32;
+        }
+        Some(_) => {
+            println!("usage: dmesg [-c | -C]");
+            return -1;
+        }
+    };
+    let size = syslog(SYSLOG_ACTION_SIZE_BUFFER, &mut []);
+    if size < 0 {
+        println!("dmesg: syslog failed: {}", size);
+        return -1;
+    }
+    let mut buf = vec![0u8; size as usize];
+    let len = syslog(action, &mut buf);
+    if len < 0 {
+        println!("dmesg: syslog failed: {}", len);
+        return -1;
+    }
+    // the oldest message may be cut in the middle of a character, so the
+    // bytes are written as they are
+    write(1, &buf[..len as usize]);
+    0
+}
@@ -0,0 +1,63 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exec, execve, fork, getenv, setenv, unsetenv, waitpid, wexitstatus};
+
+fn run(argv: &[*const u8], inherit: bool) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        if inherit {
+            exec("env\0", argv);
+        } else {
+            execve("env\0", argv, &[core::ptr::null::<u8>()]);
+        }
+        panic!("unreachable!");
+    }
+    let mut status = 0;
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    wexitstatus(status)
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc > 1 {
+        // run by the test below
+        let expected = if argv[1] == "inherited" {
+            Some("bar")
+        } else {
+            None
+        };
+        assert_eq!(getenv("FOO").as_deref(), expected);
+        assert_eq!(getenv("GONE"), None);
+        return 0;
+    }
+
+    assert_eq!(setenv("FOO", "baz", true), 0);
+    assert_eq!(setenv("FOO", "bar", false), 0);
+    assert_eq!(getenv("FOO").as_deref(), Some("baz"));
+    assert_eq!(setenv("FOO", "bar", true), 0);
+    assert_eq!(getenv("FOO").as_deref(), Some("bar"));
+    assert_eq!(setenv("GONE", "1", true), 0);
+    assert_eq!(unsetenv("GONE"), 0);
+    assert_eq!(getenv("GONE"), None);
+    assert!(setenv("A=B", "1", true) < 0);
+
+    // exec passes the environment on, unless given another one
+    let inherited = [
+        "env\0".as_ptr(),
+        "inherited\0".as_ptr(),
+        core::ptr::null::<u8>(),
+    ];
+    assert_eq!(run(&inherited, true), 0);
+    let empty = [
+        "env\0".as_ptr(),
+        "empty\0".as_ptr(),
+        core::ptr::null::<u8>(),
+    ];
+    assert_eq!(run(&empty, false), 0);
+    println!("env passed!");
+    0
+}
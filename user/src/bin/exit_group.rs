@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, exit_group, fork, thread_create, waitpid, waittid, wexitstatus, wifexited, wifsignaled,
+    wtermsig, yield_, SIGSEGV,
+};
+
+const SPINNERS: usize = 3;
+
+fn spinner(_arg: usize) -> ! {
+    loop {
+        yield_();
+    }
+}
+
+fn exiter(code: usize) -> ! {
+    exit_group(code as i32)
+}
+
+fn faulter(_arg: usize) -> ! {
+    unsafe {
+        (0 as *mut u8).write_volatile(0);
+    }
+    exit(0)
+}
+
+/// Run `last` in a thread of a child process after spinning threads,
+/// while the main thread waits for a spinner, and return the wait status
+fn run_child(last: fn(usize) -> !, arg: usize) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        let mut spinners = [0; SPINNERS];
+        for tid in spinners.iter_mut() {
+            *tid = thread_create(spinner as usize, 0);
+            assert!(*tid > 0);
+        }
+        assert!(thread_create(last as usize, arg) > 0);
+        waittid(spinners[0] as usize);
+        panic!("unreachable!");
+    }
+    let mut status = 0;
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    status
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // any thread can exit the process, stopping the others
+    let status = run_child(exiter, 7);
+    assert!(wifexited(status) && wexitstatus(status) == 7);
+    // a fatal signal kills the process whichever thread it hits
+    let status = run_child(faulter, 0);
+    assert!(wifsignaled(status) && wtermsig(status) == SIGSEGV);
+    println!("exit_group passed!");
+    0
+}
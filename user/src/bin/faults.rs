@@ -0,0 +1,107 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{
+    exit, fault_status, fork, getpid, kill, sigaction, waitpid, wexitstatus, wfaultcode, wifexited,
+    SignalAction, ILL_ILLOPC, SEGV_ACCERR, SEGV_MAPERR, SIGILL, SIGSEGV, SIGTRAP, TRAP_BRKPT,
+};
+
+/// Address of the illegal instruction executed by `illegal`
+static ILLEGAL_PC: AtomicUsize = AtomicUsize::new(0);
+
+fn unmapped() {
+    unsafe {
+        (0x10 as *mut u8).write_volatile(0);
+    }
+}
+
+fn read_only() {
+    unsafe {
+        (read_only as usize as *mut u8).write_volatile(0);
+    }
+}
+
+fn illegal() {
+    unsafe {
+        asm!(
+            "la {pc}, 2f",
+            "sd {pc}, 0({addr})",
+            "2: unimp",
+            addr = in(reg) &ILLEGAL_PC as *const AtomicUsize,
+            pc = out(reg) _,
+        );
+    }
+}
+
+fn breakpoint() {
+    unsafe {
+        asm!("ebreak");
+    }
+}
+
+fn killed() {
+    kill(getpid() as usize, SIGSEGV);
+}
+
+fn on_sigill(signum: i32, addr: usize) {
+    // returning would run into it again
+    exit(
+        if signum == SIGILL && addr == ILLEGAL_PC.load(Ordering::SeqCst) {
+            0
+        } else {
+            1
+        },
+    );
+}
+
+/// Run `f` in a child and return its wait status
+fn status_of(f: fn()) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        f();
+        exit(100);
+    }
+    let mut status = 0;
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    status
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // the cause of each fault is reported with its signal
+    let cases: [(fn(), i32); 4] = [
+        (unmapped, fault_status(SIGSEGV, SEGV_MAPERR)),
+        (read_only, fault_status(SIGSEGV, SEGV_ACCERR)),
+        (illegal, fault_status(SIGILL, ILL_ILLOPC)),
+        (breakpoint, fault_status(SIGTRAP, TRAP_BRKPT)),
+    ];
+    for (f, expected) in cases {
+        assert_eq!(status_of(f), expected);
+    }
+    // a signal sent without a fault has no fault code
+    let status = status_of(killed);
+    assert_eq!(status, SIGSEGV);
+    assert_eq!(wfaultcode(status), 0);
+    // unlike exiting with a negative code
+    let status = status_of(|| exit(-SIGSEGV));
+    assert!(wifexited(status));
+    assert_eq!(wexitstatus(status) as i8 as i32, -SIGSEGV);
+
+    // a handler of SIGILL gets the address of the instruction
+    let status = status_of(|| {
+        let action = SignalAction {
+            handler: on_sigill as usize,
+            ..Default::default()
+        };
+        assert_eq!(sigaction(SIGILL, Some(&action), None), 0);
+        illegal();
+    });
+    assert_eq!(status, 0);
+    println!("faults passed!");
+    0
+}
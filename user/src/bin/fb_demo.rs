@@ -0,0 +1,84 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, ioctl, mmap_file, munmap, open, read, FbInfo, FbRect, OpenFlags, EINVAL, ENODEV, ENOTTY,
+    FBIOGET_INFO, FBIO_FLUSH,
+};
+
+fn pixel(r: u32, g: u32, b: u32) -> u32 {
+    r << 16 | g << 8 | b
+}
+
+/// Draw a gradient with nested squares into the framebuffer mapped from
+/// `/dev/fb0`. Run QEMU with `make run GUI=on` to see it.
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("/dev/fb0\0", OpenFlags::RDWR);
+    if fd < 0 {
+        println!("fb_demo: no framebuffer, skipped");
+        return 0;
+    }
+    let fd = fd as usize;
+    let mut info = FbInfo::default();
+    assert_eq!(ioctl(fd, FBIOGET_INFO, &mut info as *mut _ as usize), 0);
+    assert_eq!(info.bits_per_pixel, 32);
+    assert_eq!(info.stride, info.width * 4);
+    let (width, height) = (info.width as usize, info.height as usize);
+    println!("fb_demo: {}x{}", width, height);
+    // only devices take ioctls and mappings of files
+    assert_eq!(
+        ioctl(1, FBIOGET_INFO, &mut info as *mut _ as usize),
+        -ENOTTY
+    );
+    assert_eq!(mmap_file(0, 4096, 0x3, 1, 0), -ENODEV);
+
+    let len = info.stride as usize * height;
+    assert_eq!(mmap_file(0, len + 4096 * 2, 0x3, fd, 0), -EINVAL);
+    let addr = mmap_file(0, len, 0x3, fd, 0);
+    assert!(addr > 0);
+    let pixels = unsafe { core::slice::from_raw_parts_mut(addr as *mut u32, width * height) };
+    for y in 0..height {
+        for x in 0..width {
+            let r = (x * 255 / width) as u32;
+            let g = (y * 255 / height) as u32;
+            pixels[y * width + x] = pixel(r, g, 0x40);
+        }
+    }
+    // nested squares in the middle
+    let size = width.min(height);
+    let (left, top) = ((width - size) / 2, (height - size) / 2);
+    for (i, &color) in [0xffffff, 0x2040c0, 0xe0a020, 0x101010].iter().enumerate() {
+        let inset = size / 10 * (i + 1);
+        for y in top + inset..top + size - inset {
+            for x in left + inset..left + size - inset {
+                pixels[y * width + x] = color;
+            }
+        }
+    }
+    assert_eq!(ioctl(fd, FBIO_FLUSH, 0), 0);
+
+    // the same pixels through read
+    let mut row = [0u8; 16];
+    assert_eq!(read(fd, &mut row), 16);
+    for (i, bytes) in row.chunks(4).enumerate() {
+        let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        assert_eq!(value, pixels[i]);
+    }
+    // a rectangle, clipped to the screen
+    let rect = FbRect {
+        x: info.width - 10,
+        y: info.height - 10,
+        width: 100,
+        height: 100,
+    };
+    assert_eq!(ioctl(fd, FBIO_FLUSH, &rect as *const _ as usize), 0);
+
+    assert_eq!(munmap(addr as usize, len), 0);
+    close(fd);
+    println!("fb_demo passed!");
+    0
+}
@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    clock_gettime, close, fstat, open, write, OpenFlags, Stat, TimeSpec, CLOCK_REALTIME, EBADF,
+    S_IFREG,
+};
+
+/// 2020-01-01 00:00:00 UTC, before which the RTC cannot be
+const YEAR_2020: usize = 1_577_836_800;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut now = TimeSpec::default();
+    assert_eq!(clock_gettime(CLOCK_REALTIME, &mut now), 0);
+    assert!(now.tv_sec > YEAR_2020);
+
+    let fd = open("file_time\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, b"stamped"), 7);
+    let mut st = Stat::default();
+    assert_eq!(fstat(fd, &mut st), 0);
+    close(fd);
+    assert_eq!(st.mode, S_IFREG);
+    assert_eq!(st.size, 7);
+    // stamped in whole seconds
+    let mut after = TimeSpec::default();
+    assert_eq!(clock_gettime(CLOCK_REALTIME, &mut after), 0);
+    assert!(st.mtime.tv_sec >= now.tv_sec && st.mtime.tv_sec <= after.tv_sec);
+
+    // the apps are stamped when the image is packed
+    let fd = open("initproc\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    assert_eq!(fstat(fd as usize, &mut st), 0);
+    close(fd as usize);
+    assert!(st.mtime.tv_sec > YEAR_2020 && st.mtime.tv_sec <= after.tv_sec);
+
+    // not a file on the filesystem
+    assert_eq!(fstat(1, &mut st), 0);
+    assert_eq!(st.mode, 0);
+    assert_eq!(fstat(99, &mut st), -EBADF);
+    println!("file_time passed!");
+    0
+}
@@ -0,0 +1,94 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::asm;
+use user_lib::{exit, waitpid, wexitstatus, wifexited, SIGCHLD};
+
+const CHILDREN: usize = 4;
+const ROUNDS: usize = 100;
+const SYSCALL_YIELD: usize = 124;
+const SYSCALL_CLONE: usize = 220;
+
+/// Fork with `value` in fs2, return the pid and fs2 as seen after the fork
+fn fork_with_fs2(value: f64) -> (isize, f64) {
+    let pid: isize;
+    let after: u64;
+    unsafe {
+        asm!(
+            "fmv.d.x fs2, {value}",
+            "ecall",
+            "fmv.x.d {after}, fs2",
+            value = in(reg) value.to_bits(),
+            after = lateout(reg) after,
+            inlateout("a0") SIGCHLD as usize => pid,
+            in("a1") 0,
+            in("a2") 0,
+            in("a7") SYSCALL_CLONE,
+            out("fs2") _,
+        );
+    }
+    (pid, f64::from_bits(after))
+}
+
+/// Add `step` to fs0 `ROUNDS` times with the rounding mode `rm`, yielding after each
+/// addition, return fs0 and the rounding mode at the end
+fn accumulate(step: f64, rm: usize) -> (f64, usize) {
+    let sum: u64;
+    let rm_after: usize;
+    unsafe {
+        asm!(
+            "fsrm {rm}",
+            "fmv.d.x fs0, zero",
+            "fmv.d.x fs1, {step}",
+            "1:",
+            "fadd.d fs0, fs0, fs1",
+            "ecall",
+            "addi {n}, {n}, -1",
+            "bnez {n}, 1b",
+            "fmv.x.d {sum}, fs0",
+            "frrm {rm_after}",
+            rm = in(reg) rm,
+            step = in(reg) step.to_bits(),
+            n = inout(reg) ROUNDS => _,
+            sum = lateout(reg) sum,
+            rm_after = lateout(reg) rm_after,
+            out("a0") _,
+            in("a7") SYSCALL_YIELD,
+            out("fs0") _,
+            out("fs1") _,
+        );
+    }
+    (f64::from_bits(sum), rm_after)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut pids = [0; CHILDREN];
+    for (i, pid) in pids.iter_mut().enumerate() {
+        let (forked, inherited) = fork_with_fs2(0.5 + i as f64);
+        if forked == 0 {
+            // the child starts with the FP state of the parent
+            assert_eq!(inherited, 0.5 + i as f64);
+            let step = (i + 1) as f64;
+            let (sum, rm) = accumulate(step, i + 1);
+            assert_eq!(sum, step * ROUNDS as f64);
+            assert_eq!(rm, i + 1);
+            exit(0);
+        }
+        *pid = forked;
+    }
+    // tasks switched in between keep the registers of the parent
+    let (sum, rm) = accumulate(0.25, 0);
+    assert_eq!(sum, 0.25 * ROUNDS as f64);
+    assert_eq!(rm, 0);
+    for pid in pids {
+        let mut status = 0;
+        assert_eq!(waitpid(pid as usize, &mut status), pid);
+        assert!(wifexited(status) && wexitstatus(status) == 0);
+    }
+    println!("fpu passed!");
+    0
+}
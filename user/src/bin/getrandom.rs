@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, getrandom, open, read, write, OpenFlags, EINVAL, GRND_NONBLOCK};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut a = [0u8; 64];
+    let mut b = [0u8; 64];
+    assert_eq!(getrandom(&mut a, 0), 64);
+    assert_eq!(getrandom(&mut b, GRND_NONBLOCK), 64);
+    // 64 equal bytes twice over would be no randomness at all
+    assert_ne!(a, b);
+    assert!(a.iter().any(|&byte| byte != a[0]));
+    assert_eq!(getrandom(&mut a, 1 << 8), -EINVAL);
+    // many blocks of the generator at once
+    let mut big = [0u8; 5000];
+    assert_eq!(getrandom(&mut big, 0), 5000);
+    assert!(big[4096..].iter().any(|&byte| byte != 0));
+
+    let fd = open("/dev/urandom\0", OpenFlags::RDWR);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(read(fd, &mut b), 64);
+    assert_ne!(a, b);
+    // mixed into the pool
+    assert_eq!(write(fd, b"entropy"), 7);
+    close(fd);
+    println!("getrandom passed!");
+    0
+}
@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    getcpu, sched_getaffinity, sched_setaffinity, set_hart_online, sleep, yield_, EAGAIN, EBUSY,
+    EINVAL, EPERM,
+};
+
+/// Hart taken offline and back online
+const HART: usize = 1;
+
+/// Bring `hart` back online, retrying while it is still on its way to stop
+fn bring_online(hart: usize) {
+    loop {
+        match set_hart_online(hart, true) {
+            0 => return,
+            err if err == -EAGAIN => sleep(1),
+            err => panic!("bringing hart {} online failed: {}", hart, err),
+        }
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut all = 0;
+    assert!(sched_getaffinity(0, &mut all) > 0);
+    // only the processes the init program lets may, and only with a hart to spare
+    match sched_setaffinity(0, 1 << HART) {
+        0 => {}
+        err if err == -EINVAL => {
+            println!("hotplug: no hart {} online, skipped", HART);
+            return 0;
+        }
+        err => panic!("sched_setaffinity failed: {}", err),
+    }
+    assert_eq!(getcpu(), HART as isize);
+
+    // pinned to the hart going offline, the caller moves away and may run anywhere
+    match set_hart_online(HART, false) {
+        0 => {}
+        err if err == -EPERM => {
+            println!("hotplug: not allowed to control the machine, skipped");
+            assert_eq!(sched_setaffinity(0, all), 0);
+            return 0;
+        }
+        err => panic!("taking hart {} offline failed: {}", HART, err),
+    }
+    let mut mask = 0;
+    assert!(sched_getaffinity(0, &mut mask) > 0);
+    assert_eq!(mask, all);
+    for _ in 0..10 {
+        assert_ne!(getcpu(), HART as isize);
+        yield_();
+    }
+    // no task may be restricted to it, nor can it go offline twice
+    assert_eq!(sched_setaffinity(0, 1 << HART), -EINVAL);
+    assert_eq!(set_hart_online(HART, false), 0);
+
+    // the last online hart stays
+    let me = getcpu() as usize;
+    let mut offline = 1 << HART;
+    for hart in (0..usize::BITS as usize).filter(|&hart| all & (1 << hart) != 0 && hart != me) {
+        if hart != HART && set_hart_online(hart, false) == 0 {
+            offline |= 1 << hart;
+        }
+    }
+    assert_eq!(set_hart_online(me, false), -EBUSY);
+    for hart in (0..usize::BITS as usize).filter(|&hart| offline & (1 << hart) != 0) {
+        if hart != HART {
+            bring_online(hart);
+        }
+    }
+
+    // back online, tasks run there again
+    bring_online(HART);
+    assert_eq!(sched_setaffinity(0, 1 << HART), 0);
+    for _ in 0..10 {
+        assert_eq!(getcpu(), HART as isize);
+        yield_();
+    }
+    assert_eq!(sched_setaffinity(0, all), 0);
+    println!("hotplug passed!");
+    0
+}
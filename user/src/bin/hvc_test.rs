@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, open, read, write, OpenFlags};
+
+/// Write to the ports of the virtio console, landing in the files given by
+/// `HVC_LOG` on the host
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("/dev/hvc0\0", OpenFlags::RDWR | OpenFlags::NONBLOCK);
+    if fd < 0 {
+        println!("hvc_test: no virtio console, skipped");
+        return 0;
+    }
+    let fd = fd as usize;
+    assert!(open("/dev/hvc9\0", OpenFlags::RDWR) < 0);
+    let message = b"hvc_test: hello from hvc0\n";
+    assert_eq!(write(fd, message), message.len() as isize);
+    // a file taking the output sends no input
+    let mut buf = [0u8; 16];
+    assert_eq!(read(fd, &mut buf), 0);
+    close(fd);
+    let fd = open("/dev/hvc1\0", OpenFlags::WRONLY);
+    if fd >= 0 {
+        let message = b"hvc_test: hello from hvc1\n";
+        assert_eq!(write(fd as usize, message), message.len() as isize);
+        close(fd as usize);
+    }
+    println!("hvc_test passed!");
+    0
+}
@@ -4,24 +4,41 @@
 #[macro_use]
 extern crate user_lib;
 
-use user_lib::{exec, fork, wait, yield_};
+use user_lib::{
+    exec, fork, prctl, wait, wexitstatus, wifsignaled, wtermsig, yield_,
+    PR_SET_CHILD_MACHINE_CONTROL,
+};
 
 #[no_mangle]
 fn main() -> i32 {
+    // only the shell may reboot the machine and take harts offline
+    prctl(PR_SET_CHILD_MACHINE_CONTROL, 1);
     if fork() == 0 {
-        exec("user_shell\0");
+        exec("user_shell\0", &[core::ptr::null::<u8>()]);
     } else {
+        prctl(PR_SET_CHILD_MACHINE_CONTROL, 0);
+        // reap the children of the shell and the orphans adopted from exiting processes
         loop {
-            let mut exit_code: i32 = 0;
-            let pid = wait(&mut exit_code);
-            if pid == -1 {
+            let mut status: i32 = 0;
+            let pid = wait(&mut status);
+            if pid < 0 {
+                // no child left to wait for, or interrupted by a signal
                 yield_();
                 continue;
             }
-            println!(
-                "[initproc] Released a zombie process, pid={}, exit_code={}",
-                pid, exit_code,
-            );
+            if wifsignaled(status) {
+                println!(
+                    "[initproc] Released a zombie process, pid={}, killed by signal {}",
+                    pid,
+                    wtermsig(status),
+                );
+            } else {
+                println!(
+                    "[initproc] Released a zombie process, pid={}, exit_code={}",
+                    pid,
+                    wexitstatus(status) as i8,
+                );
+            }
         }
     }
     0
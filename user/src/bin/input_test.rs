@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, open, read, read_events, write, InputEvent, OpenFlags};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(
+        "/dev/input/event0\0",
+        OpenFlags::RDONLY | OpenFlags::NONBLOCK,
+    );
+    if fd < 0 {
+        println!("input_test: no input device, skipped");
+        return 0;
+    }
+    let fd = fd as usize;
+    assert!(open("/dev/input/event0\0", OpenFlags::CREATE) < 0);
+    assert!(open("/dev/input/event2\0", OpenFlags::RDONLY) < 0);
+    // only whole events are read
+    let mut small = [0u8; 15];
+    assert_eq!(read(fd, &mut small), 0);
+    // without waiting, whatever has been typed into the window so far
+    let mut events = [InputEvent::default(); 8];
+    let count = read_events(fd, &mut events);
+    assert!(count >= 0);
+    for event in &events[..count as usize] {
+        println!("input_test: {:?}", event);
+    }
+    assert!(write(fd, b"x") < 0);
+    close(fd);
+    println!("input_test passed!");
+    0
+}
@@ -0,0 +1,64 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    clock_gettime, close, exit, fork, open, sleep, waitpid, wexitstatus, write, OpenFlags,
+    TimeSpec, CLOCK_MONOTONIC,
+};
+
+const SLEEPS: usize = 50;
+const SLEEP_MS: usize = 10;
+
+fn now_ns() -> usize {
+    let mut ts = TimeSpec::default();
+    clock_gettime(CLOCK_MONOTONIC, &mut ts);
+    ts.tv_sec * 1_000_000_000 + ts.tv_nsec
+}
+
+/// Keep the disk busy, raising a stream of virtio interrupts
+fn write_file() -> ! {
+    let buffer = [0x5au8; 1024];
+    let fd = open("io_timer\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd >= 0);
+    for _ in 0..1024 {
+        write(fd as usize, &buffer);
+    }
+    close(fd as usize);
+    exit(0);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        write_file();
+    }
+    // the timer interrupts the disk interrupt handlers, so sleepers wake up on time
+    let start = now_ns();
+    let mut worst = 0;
+    for _ in 0..SLEEPS {
+        let before = now_ns();
+        sleep(SLEEP_MS);
+        let slept = now_ns() - before;
+        assert!(slept >= SLEEP_MS * 1_000_000);
+        worst = worst.max(slept);
+    }
+    let elapsed = now_ns() - start;
+    println!(
+        "{} sleeps of {} msecs took {} msecs, the longest {} usecs",
+        SLEEPS,
+        SLEEP_MS,
+        elapsed / 1_000_000,
+        worst / 1000
+    );
+    // generous for the scheduling delays of a slow emulator
+    assert!(elapsed < SLEEPS * SLEEP_MS * 1_000_000 * 3);
+    let mut status = 0;
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    assert_eq!(wexitstatus(status), 0);
+    println!("io_timer passed!");
+    0
+}
@@ -0,0 +1,77 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{setitimer, sigaction, ITimerVal, SignalAction, TimeVal, ITIMER_REAL, SIGALRM};
+
+const SPINS: usize = 1 << 20;
+const ALARMS_WANTED: usize = 5;
+
+static ALARMS: AtomicUsize = AtomicUsize::new(0);
+
+fn handler(_signum: i32) {
+    ALARMS.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Spin with a known value in each register the kernel may use, timer
+/// interrupts returning on the fast path in between, false if one changed
+fn spin_with_registers(seed: usize) -> bool {
+    let mut regs = [0usize; 24];
+    for (i, reg) in regs.iter_mut().enumerate() {
+        *reg = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ i;
+    }
+    let expected = regs;
+    let [t0, t1, t2, t3, t4, t5, t6, a0, a1, a2, a3, a4, a5, a6, a7, s2, s3, s4, s5, s6, s7, s8, s9, s10] =
+        &mut regs;
+    unsafe {
+        asm!(
+            "1:",
+            "addi {n}, {n}, -1",
+            "bnez {n}, 1b",
+            n = inout(reg) SPINS => _,
+            inout("t0") *t0, inout("t1") *t1, inout("t2") *t2, inout("t3") *t3,
+            inout("t4") *t4, inout("t5") *t5, inout("t6") *t6,
+            inout("a0") *a0, inout("a1") *a1, inout("a2") *a2, inout("a3") *a3,
+            inout("a4") *a4, inout("a5") *a5, inout("a6") *a6, inout("a7") *a7,
+            inout("s2") *s2, inout("s3") *s3, inout("s4") *s4, inout("s5") *s5,
+            inout("s6") *s6, inout("s7") *s7, inout("s8") *s8, inout("s9") *s9,
+            inout("s10") *s10,
+        );
+    }
+    regs == expected
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let action = SignalAction {
+        handler: handler as usize,
+        ..Default::default()
+    };
+    assert_eq!(sigaction(SIGALRM, Some(&action), None), 0);
+    // signals delivered after a timer interrupt leave the fast path
+    let period = TimeVal {
+        tv_sec: 0,
+        tv_usec: 20_000,
+    };
+    let itimer = ITimerVal {
+        it_interval: period,
+        it_value: period,
+    };
+    assert_eq!(setitimer(ITIMER_REAL, &itimer, None), 0);
+    let mut rounds = 0;
+    while ALARMS.load(Ordering::SeqCst) < ALARMS_WANTED {
+        assert!(
+            spin_with_registers(rounds),
+            "registers lost in round {}",
+            rounds
+        );
+        rounds += 1;
+    }
+    assert_eq!(setitimer(ITIMER_REAL, &ITimerVal::default(), None), 0);
+    println!("irq_regs passed after {} rounds!", rounds);
+    0
+}
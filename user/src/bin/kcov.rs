@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use user_lib::{kcov, spawn, waitpid, wexitstatus, ENODEV, KCOV_COLLECT, KCOV_ENABLE};
+
+/// Run a program with its arguments and print the kernel addresses it covers,
+/// one per line, e.g. for `addr2line` on the host
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc < 2 {
+        println!("usage: kcov <program> [args...]");
+        return -1;
+    }
+    match kcov(KCOV_ENABLE, &mut []) {
+        0 => {}
+        err if err == -ENODEV => {
+            println!("kcov: the kernel is not built with KCOV=1");
+            return -1;
+        }
+        err => {
+            println!("kcov: enabling coverage failed: {}", err);
+            return -1;
+        }
+    }
+    let args: Vec<String> = argv[1..]
+        .iter()
+        .map(|arg| {
+            let mut arg = String::from(*arg);
+            arg.push('\0');
+            arg
+        })
+        .collect();
+    let mut args_addr: Vec<*const u8> = args.iter().map(|arg| arg.as_ptr()).collect();
+    args_addr.push(core::ptr::null::<u8>());
+    let pid = spawn(args[0].as_str(), args_addr.as_slice());
+    if pid < 0 {
+        println!("kcov: cannot run {}", argv[1]);
+        return -1;
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    let count = kcov(KCOV_COLLECT, &mut []);
+    let mut pcs = vec![0; count as usize];
+    let count = kcov(KCOV_COLLECT, &mut pcs) as usize;
+    for pc in pcs.iter().take(count) {
+        println!("{:#x}", pc);
+    }
+    println!(
+        "kcov: {} exited with code {}, {} kernel addresses covered",
+        argv[1],
+        wexitstatus(status),
+        count
+    );
+    0
+}
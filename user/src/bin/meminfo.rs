@@ -0,0 +1,27 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, open, read, OpenFlags};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("/proc/meminfo\0", OpenFlags::RDONLY);
+    if fd < 0 {
+        println!("Error occured when opening /proc/meminfo");
+        return -1;
+    }
+    let fd = fd as usize;
+    let mut buf = [0u8; 256];
+    loop {
+        let size = read(fd, &mut buf) as usize;
+        if size == 0 {
+            break;
+        }
+        print!("{}", core::str::from_utf8(&buf[..size]).unwrap());
+    }
+    close(fd);
+    0
+}
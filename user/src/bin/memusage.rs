@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, getrusage, mmap, munmap, open, read, sbrk, OpenFlags, RUsage, RUSAGE_SELF};
+
+fn report(stage: &str) -> RUsage {
+    let mut usage = RUsage::default();
+    assert_eq!(getrusage(RUSAGE_SELF, &mut usage), 0);
+    println!(
+        "{}: rss = {}KiB, maxrss = {}KiB, heap = {}KiB, mmap = {}KiB",
+        stage, usage.ru_rss, usage.ru_maxrss, usage.ru_heap, usage.ru_mmap
+    );
+    usage
+}
+
+/// Print `/proc/self/status` and check that it reports `mmap_kb` of mmap areas
+fn print_status(mmap_kb: usize) {
+    let fd = open("/proc/self/status\0", OpenFlags::RDONLY);
+    assert!(fd >= 0);
+    let mut buf = [0u8; 256];
+    let size = read(fd as usize, &mut buf);
+    assert!(size > 0);
+    close(fd as usize);
+    let status = core::str::from_utf8(&buf[..size as usize]).unwrap();
+    print!("{}", status);
+    let mmap = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmMmap:"))
+        .unwrap();
+    assert_eq!(
+        mmap.trim_end_matches("kB").trim().parse(),
+        Ok(mmap_kb)
+    );
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let start = report("start");
+    assert!(sbrk(4096 * 4) >= 0);
+    let heap = report("after sbrk");
+    assert_eq!(heap.ru_heap, start.ru_heap + 16);
+    assert_eq!(heap.ru_rss, start.ru_rss + 16);
+    let addr = mmap(0, 4096 * 8, 0x3);
+    assert!(addr > 0);
+    let mapped = report("after mmap");
+    assert_eq!(mapped.ru_mmap, 32);
+    print_status(32);
+    assert_eq!(munmap(addr as usize, 4096 * 8), 0);
+    assert!(sbrk(-4096 * 4) >= 0);
+    let end = report("end");
+    assert_eq!(end.ru_rss, start.ru_rss);
+    assert_eq!(end.ru_maxrss, mapped.ru_rss);
+    println!("memusage passed!");
+    0
+}
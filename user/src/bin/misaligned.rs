@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::asm;
+
+/// Load from `addr` with `$inst`, which the hart may trap and the kernel emulates
+macro_rules! load {
+    ($inst:literal, $addr:expr) => {{
+        let value: usize;
+        unsafe {
+            asm!(concat!($inst, " {value}, 0({addr})"), addr = in(reg) $addr, value = out(reg) value);
+        }
+        value
+    }};
+}
+
+/// Store `value` to `addr` with `$inst`
+macro_rules! store {
+    ($inst:literal, $addr:expr, $value:expr) => {
+        unsafe {
+            asm!(concat!($inst, " {value}, 0({addr})"), addr = in(reg) $addr, value = in(reg) $value);
+        }
+    };
+}
+
+/// Little-endian value of `width` bytes of `buf` at `offset`, sign-extended if `signed`
+fn expected(buf: &[u8], offset: usize, width: usize, signed: bool) -> usize {
+    let mut bytes = [0u8; 8];
+    bytes[..width].copy_from_slice(&buf[offset..offset + width]);
+    let value = u64::from_le_bytes(bytes);
+    let shift = 64 - 8 * width as u32;
+    if signed && shift != 0 {
+        ((value << shift) as i64 >> shift) as usize
+    } else {
+        value as usize
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut buf = [0u64; 4];
+    let bytes = unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 32) };
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = 0x81 + i as u8 * 7;
+    }
+    let base = bytes.as_mut_ptr() as usize;
+    for offset in 1..8 {
+        let addr = base + offset;
+        assert_eq!(load!("lh", addr), expected(bytes, offset, 2, true));
+        assert_eq!(load!("lhu", addr), expected(bytes, offset, 2, false));
+        assert_eq!(load!("lw", addr), expected(bytes, offset, 4, true));
+        assert_eq!(load!("lwu", addr), expected(bytes, offset, 4, false));
+        assert_eq!(load!("ld", addr), expected(bytes, offset, 8, false));
+    }
+
+    let value: usize = 0x0123_4567_89ab_cdef;
+    for offset in 1..8 {
+        let addr = base + 8 + offset;
+        store!("sd", addr, value);
+        assert_eq!(load!("ld", addr), value);
+        store!("sw", addr, !value);
+        assert_eq!(load!("lwu", addr), !value & 0xffff_ffff);
+        store!("sh", addr, value);
+        assert_eq!(load!("lhu", addr), value & 0xffff);
+        // the bytes after those stored are left alone
+        assert_eq!(bytes[8 + offset + 2], (!value >> 16) as u8);
+    }
+    println!("misaligned passed!");
+    0
+}
@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fault_status, fork, getrusage, mmap, munmap, waitpid, RUsage, RUSAGE_SELF, SEGV_MAPERR,
+    SIGSEGV,
+};
+
+const PAGE_SIZE: usize = 4096;
+const PAGES: usize = 4;
+
+fn mmap_kb() -> usize {
+    let mut usage = RUsage::default();
+    assert_eq!(getrusage(RUSAGE_SELF, &mut usage), 0);
+    usage.ru_mmap
+}
+
+/// Touch the page at `addr` in a child and return its wait status
+fn touch_in_child(addr: usize) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        unsafe {
+            (addr as *const u8).read_volatile();
+        }
+        exit(100);
+    }
+    let mut status = 0;
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    status
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let before = mmap_kb();
+    let start = mmap(0, PAGE_SIZE * PAGES, 0x3);
+    assert!(start > 0);
+    let start = start as usize;
+    for i in 0..PAGES {
+        unsafe {
+            ((start + i * PAGE_SIZE) as *mut u8).write_volatile(i as u8);
+        }
+    }
+    let mapped = mmap_kb();
+    assert_eq!(mapped, before + PAGES * PAGE_SIZE / 1024);
+
+    // unmapping the middle splits the area in two
+    assert_eq!(munmap(start + PAGE_SIZE, PAGE_SIZE * 2), 0);
+    assert_eq!(mmap_kb(), mapped - 8);
+    for i in [0, PAGES - 1] {
+        let value = unsafe { ((start + i * PAGE_SIZE) as *const u8).read_volatile() };
+        assert_eq!(value, i as u8);
+    }
+    for i in 1..PAGES - 1 {
+        assert_eq!(
+            touch_in_child(start + i * PAGE_SIZE),
+            fault_status(SIGSEGV, SEGV_MAPERR)
+        );
+    }
+
+    // both halves can still be unmapped on their own
+    assert_eq!(munmap(start, PAGE_SIZE), 0);
+    assert_eq!(munmap(start + (PAGES - 1) * PAGE_SIZE, PAGE_SIZE), 0);
+    assert_eq!(mmap_kb(), before);
+    println!("mmap_split passed!");
+    0
+}
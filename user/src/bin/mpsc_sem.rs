@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{exit, semaphore_create, semaphore_down, semaphore_up, thread_create, waittid};
+
+const PRODUCERS: usize = 4;
+const ITEMS: usize = 100;
+const BUFFER_SIZE: usize = 8;
+
+/// Ring buffer shared by the producers and the consumer
+static mut BUFFER: [usize; BUFFER_SIZE] = [0; BUFFER_SIZE];
+static mut HEAD: usize = 0;
+static mut TAIL: usize = 0;
+
+/// Ids of the semaphores guarding `BUFFER`
+static mut MUTEX: usize = 0;
+static mut EMPTY: usize = 0;
+static mut FULL: usize = 0;
+
+fn producer(id: usize) -> ! {
+    for i in 0..ITEMS {
+        unsafe {
+            semaphore_down(EMPTY);
+            semaphore_down(MUTEX);
+            BUFFER[TAIL] = id * ITEMS + i;
+            TAIL = (TAIL + 1) % BUFFER_SIZE;
+            semaphore_up(MUTEX);
+            semaphore_up(FULL);
+        }
+    }
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    unsafe {
+        // a semaphore with a single resource serves as a mutex
+        MUTEX = semaphore_create(1) as usize;
+        EMPTY = semaphore_create(BUFFER_SIZE) as usize;
+        FULL = semaphore_create(0) as usize;
+    }
+    let tids: Vec<_> = (0..PRODUCERS)
+        .map(|id| thread_create(producer as usize, id) as usize)
+        .collect();
+    // consume every item exactly once, each producer's in order
+    let mut next = [0; PRODUCERS];
+    for _ in 0..PRODUCERS * ITEMS {
+        let item = unsafe {
+            semaphore_down(FULL);
+            semaphore_down(MUTEX);
+            let item = BUFFER[HEAD];
+            HEAD = (HEAD + 1) % BUFFER_SIZE;
+            semaphore_up(MUTEX);
+            semaphore_up(EMPTY);
+            item
+        };
+        let (id, i) = (item / ITEMS, item % ITEMS);
+        assert_eq!(next[id], i);
+        next[id] += 1;
+    }
+    for tid in tids {
+        assert_eq!(waittid(tid), 0);
+    }
+    assert!(next.iter().all(|&count| count == ITEMS));
+    println!("mpsc_sem passed!");
+    0
+}
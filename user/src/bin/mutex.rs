@@ -0,0 +1,109 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use user_lib::{
+    exit, getpid, mutex_adaptive_create, mutex_blocking_create, mutex_create, mutex_lock,
+    mutex_unlock, set_priority, task_info, thread_create, waittid, yield_, TaskInfo, EINVAL, EPERM,
+};
+
+const THREADS: usize = 4;
+const ROUNDS: usize = 1000;
+const LOW_PRIORITY: isize = 2;
+const HIGH_PRIORITY: isize = 64;
+
+static mut COUNTER: usize = 0;
+static LOCKED: AtomicBool = AtomicBool::new(false);
+static RELEASE: AtomicBool = AtomicBool::new(false);
+
+fn adder(mutex: usize) -> ! {
+    for round in 0..ROUNDS {
+        mutex_lock(mutex);
+        let count = unsafe { core::ptr::read_volatile(&COUNTER) };
+        if round % 100 == 0 {
+            yield_();
+        }
+        unsafe {
+            core::ptr::write_volatile(&mut COUNTER, count + 1);
+        }
+        mutex_unlock(mutex);
+    }
+    exit(0)
+}
+
+/// Add with `THREADS` threads under the mutex created by `create`
+fn race(create: fn() -> isize) {
+    let mutex = create();
+    assert!(mutex >= 0);
+    unsafe {
+        COUNTER = 0;
+    }
+    let tids: Vec<_> = (0..THREADS)
+        .map(|_| thread_create(adder as usize, mutex as usize) as usize)
+        .collect();
+    for tid in tids {
+        assert_eq!(waittid(tid), 0);
+    }
+    assert_eq!(unsafe { COUNTER }, THREADS * ROUNDS);
+}
+
+fn low(mutex: usize) -> ! {
+    set_priority(LOW_PRIORITY);
+    assert_eq!(mutex_lock(mutex), 0);
+    LOCKED.store(true, Ordering::SeqCst);
+    while !RELEASE.load(Ordering::SeqCst) {
+        yield_();
+    }
+    assert_eq!(mutex_unlock(mutex), 0);
+    exit(0)
+}
+
+fn high(mutex: usize) -> ! {
+    set_priority(HIGH_PRIORITY);
+    while !LOCKED.load(Ordering::SeqCst) {
+        yield_();
+    }
+    assert_eq!(mutex_lock(mutex), 0);
+    assert_eq!(mutex_unlock(mutex), 0);
+    exit(0)
+}
+
+/// Thread `tid` of the caller as listed by `task_info`
+fn thread_info(tid: usize) -> TaskInfo {
+    let mut tasks = [TaskInfo::default(); 64];
+    let count = (task_info(&mut tasks) as usize).min(tasks.len());
+    let pid = getpid() as usize;
+    *tasks[..count]
+        .iter()
+        .find(|task| task.pid == pid && task.tid == tid)
+        .unwrap()
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    race(mutex_create);
+    race(mutex_blocking_create);
+    race(mutex_adaptive_create);
+
+    let mutex = mutex_blocking_create() as usize;
+    assert_eq!(mutex_unlock(mutex), -EPERM);
+    assert_eq!(mutex_lock(mutex + 100), -EINVAL);
+
+    // the holder runs with the priority of the waiter
+    let low_tid = thread_create(low as usize, mutex) as usize;
+    let high_tid = thread_create(high as usize, mutex) as usize;
+    while thread_info(high_tid).state != b'S' {
+        yield_();
+    }
+    assert_eq!(thread_info(low_tid).priority, HIGH_PRIORITY as usize);
+    RELEASE.store(true, Ordering::SeqCst);
+    assert_eq!(waittid(low_tid), 0);
+    assert_eq!(waittid(high_tid), 0);
+    println!("mutex passed!");
+    0
+}
@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{get_time, nanosleep, TimeSpec};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let req = TimeSpec {
+        tv_sec: 0,
+        tv_nsec: 200_000_000,
+    };
+    let mut rem = TimeSpec {
+        tv_sec: 1,
+        tv_nsec: 1,
+    };
+    let start = get_time();
+    assert_eq!(nanosleep(&req, Some(&mut rem)), 0);
+    let elapsed = get_time() - start;
+    println!("slept {} msecs for 200 msecs", elapsed);
+    assert!(elapsed >= 200);
+    // only written when interrupted
+    assert_eq!((rem.tv_sec, rem.tv_nsec), (1, 1));
+    // tv_nsec must be below one second
+    let bad = TimeSpec {
+        tv_sec: 0,
+        tv_nsec: 1_000_000_000,
+    };
+    assert_eq!(nanosleep(&bad, None), -22);
+    println!("nanosleep passed!");
+    0
+}
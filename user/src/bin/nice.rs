@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, getpgrp, getpid, getpriority, nice, setpriority, waitpid, wexitstatus, EACCES,
+    EINVAL, ESRCH, PRIO_PGRP, PRIO_PROCESS,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let base = getpriority(PRIO_PROCESS, 0).unwrap();
+    assert_eq!(getpriority(PRIO_PROCESS, getpid() as usize), Ok(base));
+    assert_eq!(getpriority(3, 0), Err(-EINVAL));
+    assert_eq!(getpriority(PRIO_PROCESS, usize::MAX), Err(-ESRCH));
+
+    // a child inherits the niceness and can only raise it
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(getpriority(PRIO_PROCESS, 0), Ok(base));
+        let raised = (base + 5).min(19);
+        assert_eq!(nice(5), Ok(raised));
+        assert_eq!(nice(-1), Err(-EACCES));
+        // out of range values are clamped
+        assert_eq!(setpriority(PRIO_PROCESS, 0, 100), 0);
+        assert_eq!(getpriority(PRIO_PROCESS, 0), Ok(19));
+        exit(0);
+    }
+    let mut status = 0;
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    assert_eq!(wexitstatus(status), 0);
+
+    // the niceness of a group is that of its member with the highest priority
+    let pgrp = getpgrp() as usize;
+    assert!(getpriority(PRIO_PGRP, pgrp).unwrap() <= base);
+    assert_eq!(getpriority(PRIO_PGRP, 0), getpriority(PRIO_PGRP, pgrp));
+    println!("nice passed!");
+    0
+}
@@ -0,0 +1,37 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, getpid, getppid, sleep, waitpid, wexitstatus, yield_};
+
+/// pid of initproc, the first process
+const INITPROC_PID: isize = 0;
+const ORPHANS: usize = 32;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let child = fork();
+    if child == 0 {
+        let parent = getpid();
+        for _ in 0..ORPHANS {
+            if fork() == 0 {
+                // adopted by initproc once the parent has exited
+                while getppid() == parent {
+                    yield_();
+                }
+                assert_eq!(getppid(), INITPROC_PID);
+                exit(0);
+            }
+        }
+        exit(0);
+    }
+    let mut status = 0;
+    assert_eq!(waitpid(child as usize, &mut status), child);
+    assert_eq!(wexitstatus(status), 0);
+    // the orphans exit and are reaped by initproc
+    sleep(100);
+    println!("orphans passed!");
+    0
+}
@@ -0,0 +1,93 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exit, ioctl, mmap_file, munmap, open, read_events, sleep, FbInfo, FbRect, InputEvent,
+    OpenFlags, BTN_LEFT, EV_KEY, EV_REL, EV_SYN, FBIOGET_INFO, FBIO_FLUSH, KEY_ESC, REL_X, REL_Y,
+};
+
+const BACKGROUND: u32 = 0xf0f0e0;
+const INK: u32 = 0x2040c0;
+/// Side of the square of pixels drawn at the pointer
+const BRUSH: u32 = 6;
+
+fn open_or_exit(path: &str, flags: OpenFlags) -> usize {
+    let fd = open(path, flags);
+    if fd < 0 {
+        println!("paint: cannot open {}", path.trim_end_matches('\0'));
+        exit(-1);
+    }
+    fd as usize
+}
+
+/// Draw with the mouse on the framebuffer, holding the left button, until Esc
+/// is pressed. Run QEMU with `make run GUI=on`, clicking into the window to
+/// grab the mouse.
+#[no_mangle]
+pub fn main() -> i32 {
+    let fb = open_or_exit("/dev/fb0\0", OpenFlags::RDWR);
+    let keyboard = open_or_exit("/dev/input/event0\0", OpenFlags::NONBLOCK);
+    let mouse = open_or_exit("/dev/input/event1\0", OpenFlags::NONBLOCK);
+    let mut info = FbInfo::default();
+    assert_eq!(ioctl(fb, FBIOGET_INFO, &mut info as *mut _ as usize), 0);
+    let (width, height) = (info.width as usize, info.height as usize);
+    let len = info.stride as usize * height;
+    let addr = mmap_file(0, len, 0x3, fb, 0);
+    assert!(addr > 0);
+    let pixels = unsafe { core::slice::from_raw_parts_mut(addr as *mut u32, width * height) };
+    pixels.fill(BACKGROUND);
+    assert_eq!(ioctl(fb, FBIO_FLUSH, 0), 0);
+
+    let (mut x, mut y) = (info.width / 2, info.height / 2);
+    let mut drawing = false;
+    let mut events = [InputEvent::default(); 32];
+    'outer: loop {
+        let mut idle = true;
+        let count = read_events(keyboard, &mut events);
+        for event in &events[..count.max(0) as usize] {
+            idle = false;
+            if event.ty == EV_KEY && event.code == KEY_ESC && event.value == 1 {
+                break 'outer;
+            }
+        }
+        let count = read_events(mouse, &mut events);
+        for event in &events[..count.max(0) as usize] {
+            idle = false;
+            match (event.ty, event.code) {
+                (EV_REL, REL_X) => {
+                    x = (x as i32 + event.value).clamp(0, info.width as i32 - 1) as u32;
+                }
+                (EV_REL, REL_Y) => {
+                    y = (y as i32 + event.value).clamp(0, info.height as i32 - 1) as u32;
+                }
+                (EV_KEY, BTN_LEFT) => drawing = event.value == 1,
+                (EV_SYN, _) if drawing => {
+                    let rect = FbRect {
+                        x: x.saturating_sub(BRUSH / 2),
+                        y: y.saturating_sub(BRUSH / 2),
+                        width: BRUSH,
+                        height: BRUSH,
+                    };
+                    for row in rect.y..(rect.y + BRUSH).min(info.height) {
+                        for col in rect.x..(rect.x + BRUSH).min(info.width) {
+                            pixels[row as usize * width + col as usize] = INK;
+                        }
+                    }
+                    ioctl(fb, FBIO_FLUSH, &rect as *const _ as usize);
+                }
+                _ => {}
+            }
+        }
+        if idle {
+            sleep(10);
+        }
+    }
+    munmap(addr as usize, len);
+    close(mouse);
+    close(keyboard);
+    close(fb);
+    0
+}
@@ -0,0 +1,77 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, getpgid, getpgrp, getpid, getppid, getsid, killpg, setpgid, setsid, sleep, waitpid,
+    waitpid_nohang, wexitstatus, wifsignaled, wtermsig, yield_, EPERM, ESRCH, SIGKILL,
+};
+
+fn sleep_forever() -> ! {
+    loop {
+        sleep(100);
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = getpid() as usize;
+    let ppid = getppid() as usize;
+    let pgrp = getpgrp() as usize;
+    let sid = getsid(0) as usize;
+    assert_eq!(getpgid(pid) as usize, pgrp);
+    assert_eq!(getsid(pid) as usize, sid);
+    // only the caller and its children can be moved
+    assert_eq!(setpgid(ppid, 0), -ESRCH);
+
+    // a child starts in the process group and session of its parent
+    let child = fork();
+    if child == 0 {
+        assert_eq!(getppid() as usize, pid);
+        assert_eq!(getpgrp() as usize, pgrp);
+        assert_eq!(getsid(0) as usize, sid);
+        let me = getpid() as usize;
+        assert_eq!(setsid() as usize, me);
+        assert_eq!(getpgrp() as usize, me);
+        // a process group leader cannot start another session
+        assert_eq!(setsid(), -EPERM);
+        exit(0);
+    }
+    let mut status = 0;
+    assert_eq!(waitpid(child as usize, &mut status), child);
+    assert_eq!(wexitstatus(status), 0);
+
+    // put two children in a new process group and kill the group
+    let leader = fork();
+    if leader == 0 {
+        sleep_forever();
+    }
+    let leader = leader as usize;
+    assert_eq!(setpgid(leader, 0), 0);
+    let member = fork();
+    if member == 0 {
+        sleep_forever();
+    }
+    let member = member as usize;
+    assert_eq!(setpgid(member, leader), 0);
+    assert_eq!(getpgid(member) as usize, leader);
+    assert_eq!(killpg(leader, SIGKILL), 0);
+    for _ in 0..2 {
+        let reaped = loop {
+            match waitpid_nohang(-(leader as isize), &mut status) {
+                0 => {
+                    yield_();
+                }
+                reaped => break reaped as usize,
+            }
+        };
+        assert!(reaped == leader || reaped == member);
+        assert!(wifsignaled(status) && wtermsig(status) == SIGKILL);
+    }
+    assert!(waitpid_nohang(-(leader as isize), &mut status) < 0);
+    assert_eq!(killpg(leader, SIGKILL), -ESRCH);
+    println!("pgrp passed!");
+    0
+}
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, get_time, getpgid, kill, sleep, waitpid, wexitstatus, ECHILD, ESRCH};
+
+/// Enough children to make going through all of them show
+const CHILDREN: usize = 200;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut pids = [0usize; CHILDREN];
+    for (i, pid) in pids.iter_mut().enumerate() {
+        let ret = fork();
+        if ret == 0 {
+            exit(i as i32);
+        }
+        assert!(ret > 0);
+        *pid = ret as usize;
+    }
+    // exited children are still found by pid until reaped
+    sleep(50);
+    let last = pids[CHILDREN - 1];
+    assert!(getpgid(last) >= 0);
+    assert_eq!(kill(last, 0), 0);
+
+    // reap them one by one by pid, the latest first
+    let start = get_time();
+    for (i, &pid) in pids.iter().enumerate().rev() {
+        let mut status = 0;
+        assert_eq!(waitpid(pid, &mut status), pid as isize);
+        assert_eq!(wexitstatus(status), i as i32);
+        // gone once reaped
+        assert_eq!(kill(pid, 0), -ESRCH);
+        assert_eq!(waitpid(pid, &mut status), -ECHILD);
+    }
+    println!(
+        "reaped {} children by pid in {}ms",
+        CHILDREN,
+        get_time() - start
+    );
+    println!("pid_lookup passed!");
+    0
+}
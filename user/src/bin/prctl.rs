@@ -0,0 +1,92 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use user_lib::{
+    exit, fork, getpid, getppid, kill, prctl, sigaction, waitpid, wexitstatus, yield_,
+    SignalAction, COMM_LEN, EINVAL, PR_GET_NAME, PR_GET_PDEATHSIG, PR_SET_NAME, PR_SET_PDEATHSIG,
+    SIGUSR1, SIGUSR2,
+};
+
+static NOTIFIED: AtomicBool = AtomicBool::new(false);
+
+fn notified(_signum: i32) {
+    NOTIFIED.store(true, Ordering::SeqCst);
+}
+
+fn name() -> [u8; COMM_LEN] {
+    let mut comm = [0u8; COMM_LEN];
+    assert_eq!(prctl(PR_GET_NAME, comm.as_mut_ptr() as usize), 0);
+    comm
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // named after the program by exec
+    assert_eq!(&name()[..6], b"prctl\0");
+    assert_eq!(prctl(PR_SET_NAME, "renamed\0".as_ptr() as usize), 0);
+    assert_eq!(&name()[..8], b"renamed\0");
+    // truncated to COMM_LEN - 1 bytes
+    assert_eq!(
+        prctl(PR_SET_NAME, "a_rather_long_task_name\0".as_ptr() as usize),
+        0
+    );
+    assert_eq!(&name(), b"a_rather_long_t\0");
+
+    assert_eq!(prctl(PR_SET_PDEATHSIG, 100), -EINVAL);
+    assert_eq!(prctl(1000, 0), -EINVAL);
+
+    // a grandchild tells us it got its parent death signal
+    let action = SignalAction {
+        handler: notified as usize,
+        ..Default::default()
+    };
+    assert_eq!(sigaction(SIGUSR2, Some(&action), None), 0);
+    let me = getpid() as usize;
+    let child = fork();
+    if child == 0 {
+        let parent = getpid();
+        if fork() == 0 {
+            assert_eq!(sigaction(SIGUSR1, Some(&action), None), 0);
+            assert_eq!(prctl(PR_SET_PDEATHSIG, SIGUSR1 as usize), 0);
+            let mut pdeathsig = 0i32;
+            assert_eq!(
+                prctl(PR_GET_PDEATHSIG, &mut pdeathsig as *mut _ as usize),
+                0
+            );
+            assert_eq!(pdeathsig, SIGUSR1);
+            // the signal is not inherited by a child
+            if fork() == 0 {
+                assert_eq!(
+                    prctl(PR_GET_PDEATHSIG, &mut pdeathsig as *mut _ as usize),
+                    0
+                );
+                assert_eq!(pdeathsig, 0);
+                exit(0);
+            }
+            // ready for the parent to exit
+            kill(parent as usize, SIGUSR2);
+            while !NOTIFIED.load(Ordering::SeqCst) {
+                yield_();
+            }
+            assert_ne!(getppid(), parent);
+            kill(me, SIGUSR2);
+            exit(0);
+        }
+        while !NOTIFIED.load(Ordering::SeqCst) {
+            yield_();
+        }
+        exit(0);
+    }
+    let mut status = 0;
+    assert_eq!(waitpid(child as usize, &mut status), child);
+    assert_eq!(wexitstatus(status), 0);
+    while !NOTIFIED.load(Ordering::SeqCst) {
+        yield_();
+    }
+    println!("prctl passed!");
+    0
+}
@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, get_time, set_priority, wait, yield_};
+
+const CHILDREN: isize = 3;
+const RUN_MS: isize = 500;
+
+/// Count loops until the deadline, yielding often so that the scheduler decides
+fn spin(deadline: isize) -> i32 {
+    let mut count = 0;
+    while get_time() < deadline {
+        count += 1;
+        yield_();
+    }
+    count
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(set_priority(1), -1);
+    assert_eq!(set_priority(1 << 20), -1);
+    assert_eq!(set_priority(16), 16);
+    let deadline = get_time() + RUN_MS;
+    for i in 0..CHILDREN {
+        if fork() == 0 {
+            let prio = 8 << i;
+            set_priority(prio);
+            let count = spin(deadline);
+            println!("priority {}: {} rounds", prio, count);
+            exit(0);
+        }
+    }
+    let mut exit_code = 0;
+    for _ in 0..CHILDREN {
+        assert!(wait(&mut exit_code) > 0);
+        assert_eq!(exit_code, 0);
+    }
+    println!("priority passed!");
+    0
+}
@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::vec;
+use user_lib::{getpid, task_info, TaskInfo};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // tasks may be created between the two calls, leave some room
+    let mut tasks = vec![TaskInfo::default(); task_info(&mut []) as usize + 8];
+    let count = task_info(&mut tasks) as usize;
+    tasks.truncate(count);
+    println!(
+        "{:>5} {:>5} {:>5} S {:>3} {:>4} {:>3} {:>7} COMMAND",
+        "PID", "TID", "PPID", "PRI", "NI", "CPU", "RSS"
+    );
+    for task in tasks.iter() {
+        println!(
+            "{:>5} {:>5} {:>5} {} {:>3} {:>4} {:>3} {:>6}K {}",
+            task.pid,
+            task.tid,
+            task.ppid,
+            task.state as char,
+            task.priority,
+            task.nice,
+            task.cpu,
+            task.rss,
+            task.comm()
+        );
+    }
+    // ps itself is running
+    let me = getpid() as usize;
+    assert!(tasks
+        .iter()
+        .any(|task| task.pid == me && task.state == b'R' && task.comm() == "ps"));
+    0
+}
@@ -0,0 +1,126 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{
+    exit, fork, ptrace, waitpid, wexitstatus, wifexited, wifsignaled, wifstopped, wstopsig,
+    wtermsig, UserRegs, PTRACE_CONT, PTRACE_GETREGS, PTRACE_PEEKDATA, PTRACE_POKEDATA,
+    PTRACE_SETREGS, PTRACE_TRACEME, SIGTRAP,
+};
+
+/// `ebreak`
+const EBREAK: u32 = 0x0010_0073;
+/// `c.ebreak`
+const C_EBREAK: u16 = 0x9002;
+
+static POKED: AtomicUsize = AtomicUsize::new(0);
+
+/// Stop for the tracer with `value` in a0, return a0 as left by the tracer
+fn breakpoint(value: usize) -> usize {
+    let mut a0 = value;
+    unsafe {
+        asm!("ebreak", inout("a0") a0);
+    }
+    a0
+}
+
+#[inline(never)]
+fn target(x: usize) -> usize {
+    x * 3 + 1
+}
+
+/// Wait for child `pid` to stop on `SIGTRAP` and return its registers
+fn wait_trap(pid: usize) -> UserRegs {
+    let mut status = 0;
+    assert_eq!(waitpid(pid, &mut status), pid as isize);
+    assert!(wifstopped(status) && wstopsig(status) == SIGTRAP);
+    let mut regs: UserRegs = [0; 32];
+    assert_eq!(
+        ptrace(PTRACE_GETREGS, pid, 0, &mut regs as *mut _ as usize),
+        0
+    );
+    regs
+}
+
+fn peek(pid: usize, addr: usize) -> usize {
+    let mut word = 0usize;
+    assert_eq!(
+        ptrace(PTRACE_PEEKDATA, pid, addr, &mut word as *mut _ as usize),
+        0
+    );
+    word
+}
+
+/// Length of the instruction at `addr` of child `pid`, compressed or not
+fn inst_len(pid: usize, addr: usize) -> usize {
+    if peek(pid, addr) & 0b11 == 0b11 {
+        4
+    } else {
+        2
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(ptrace(PTRACE_TRACEME, 0, 0, 0), 0);
+        assert_eq!(breakpoint(1), 42);
+        assert_eq!(POKED.load(Ordering::SeqCst), 7);
+        // stops at the breakpoint set in the code by the tracer
+        exit(target(5) as i32);
+    }
+    let pid = pid as usize;
+
+    // step over the ebreak, changing a0 and memory
+    let mut regs = wait_trap(pid);
+    assert_eq!(regs[10], 1);
+    let inst = peek(pid, regs[0]);
+    assert!(inst as u32 == EBREAK || inst as u16 == C_EBREAK);
+    regs[0] += inst_len(pid, regs[0]);
+    regs[10] = 42;
+    assert_eq!(
+        ptrace(PTRACE_SETREGS, pid, 0, &regs as *const _ as usize),
+        0
+    );
+    let poked = &POKED as *const _ as usize;
+    assert_eq!(ptrace(PTRACE_POKEDATA, pid, poked, 7), 0);
+    assert_eq!(peek(pid, poked), 7);
+    assert_eq!(POKED.load(Ordering::SeqCst), 0);
+
+    // set a breakpoint at the start of target, in read-only code
+    let addr = target as usize;
+    let original = peek(pid, addr);
+    let patched = original & !0xffff_ffff | EBREAK as usize;
+    assert_eq!(ptrace(PTRACE_POKEDATA, pid, addr, patched), 0);
+    assert_eq!(ptrace(PTRACE_CONT, pid, 0, 0), 0);
+    let regs = wait_trap(pid);
+    assert_eq!(regs[0], addr);
+    assert_eq!(regs[10], 5);
+    // restore the code and resume at it, suppressing SIGTRAP
+    assert_eq!(ptrace(PTRACE_POKEDATA, pid, addr, original), 0);
+    assert_eq!(ptrace(PTRACE_CONT, pid, 0, 0), 0);
+    let mut status = 0;
+    assert_eq!(waitpid(pid, &mut status), pid as isize);
+    assert!(wifexited(status) && wexitstatus(status) == 16);
+
+    // SIGTRAP delivered on resume takes its default action
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(ptrace(PTRACE_TRACEME, 0, 0, 0), 0);
+        breakpoint(0);
+        exit(0);
+    }
+    let pid = pid as usize;
+    wait_trap(pid);
+    assert_eq!(ptrace(PTRACE_CONT, pid, 0, SIGTRAP as usize), 0);
+    let mut status = 0;
+    assert_eq!(waitpid(pid, &mut status), pid as isize);
+    assert!(wifsignaled(status) && wtermsig(status) == SIGTRAP);
+    println!("ptrace passed!");
+    0
+}
@@ -0,0 +1,123 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use user_lib::{
+    close, exit, fork, getrlimit, mmap, open, setrlimit, sigaction, waitpid, wexitstatus,
+    wifsignaled, wtermsig, OpenFlags, RLimit, SignalAction, EINVAL, EMFILE, EPERM, RLIMIT_AS,
+    RLIMIT_CPU, RLIMIT_NOFILE, RLIMIT_STACK, RLIM_INFINITY, SIGKILL, SIGXCPU,
+};
+
+const NOFILE: usize = 5;
+
+static XCPU: AtomicBool = AtomicBool::new(false);
+
+fn on_xcpu(_signum: i32) {
+    XCPU.store(true, Ordering::SeqCst);
+}
+
+/// Spin in user mode until `done`
+fn burn(done: impl Fn() -> bool) {
+    while !done() {}
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut limit = RLimit::default();
+    assert_eq!(getrlimit(RLIMIT_CPU, &mut limit), 0);
+    assert_eq!(limit.rlim_cur, RLIM_INFINITY);
+    assert_eq!(getrlimit(100, &mut limit), -EINVAL);
+
+    // the soft limit is at most the hard one, which can only be lowered
+    assert_eq!(getrlimit(RLIMIT_NOFILE, &mut limit), 0);
+    let nofile = limit;
+    let raised = RLimit {
+        rlim_cur: nofile.rlim_max,
+        rlim_max: nofile.rlim_max + 1,
+    };
+    assert_eq!(setrlimit(RLIMIT_NOFILE, &raised), -EPERM);
+    let inverted = RLimit {
+        rlim_cur: 2,
+        rlim_max: 1,
+    };
+    assert_eq!(setrlimit(RLIMIT_NOFILE, &inverted), -EINVAL);
+    assert_eq!(getrlimit(RLIMIT_STACK, &mut limit), 0);
+    let stack = RLimit {
+        rlim_cur: limit.rlim_cur / 2,
+        rlim_max: limit.rlim_max,
+    };
+    assert_eq!(setrlimit(RLIMIT_STACK, &stack), -EINVAL);
+
+    // fds 0, 1 and 2 are taken by the standard streams
+    let low = RLimit {
+        rlim_cur: NOFILE,
+        rlim_max: nofile.rlim_max,
+    };
+    assert_eq!(setrlimit(RLIMIT_NOFILE, &low), 0);
+    let mut fds = [0; NOFILE - 3];
+    for fd in fds.iter_mut() {
+        *fd = open("rlimit\0", OpenFlags::RDONLY);
+        assert!(*fd >= 3);
+    }
+    assert_eq!(open("rlimit\0", OpenFlags::RDONLY), -EMFILE);
+    for fd in fds {
+        close(fd as usize);
+    }
+
+    // limits are inherited
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(getrlimit(RLIMIT_NOFILE, &mut limit), 0);
+        assert_eq!(limit, low);
+        // no mapping beyond RLIMIT_AS
+        let tiny = RLimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        assert_eq!(setrlimit(RLIMIT_AS, &tiny), 0);
+        assert!(mmap(0, 4096, 0x3) < 0);
+        exit(0);
+    }
+    let mut status = 0;
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    assert_eq!(wexitstatus(status), 0);
+
+    // SIGXCPU over the soft CPU limit
+    let pid = fork();
+    if pid == 0 {
+        let action = SignalAction {
+            handler: on_xcpu as usize,
+            ..Default::default()
+        };
+        assert_eq!(sigaction(SIGXCPU, Some(&action), None), 0);
+        let cpu = RLimit {
+            rlim_cur: 1,
+            rlim_max: 10,
+        };
+        assert_eq!(setrlimit(RLIMIT_CPU, &cpu), 0);
+        burn(|| XCPU.load(Ordering::SeqCst));
+        exit(0);
+    }
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    assert_eq!(wexitstatus(status), 0);
+
+    // SIGKILL over the hard one
+    let pid = fork();
+    if pid == 0 {
+        let cpu = RLimit {
+            rlim_cur: 1,
+            rlim_max: 1,
+        };
+        assert_eq!(setrlimit(RLIMIT_CPU, &cpu), 0);
+        burn(|| false);
+        exit(0);
+    }
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    assert!(wifsignaled(status));
+    assert_eq!(wtermsig(status), SIGKILL);
+    println!("rlimit passed!");
+    0
+}
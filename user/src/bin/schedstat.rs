@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use user_lib::{
+    get_time, getpid, gettid, sched_trace, sleep, task_info, yield_, SwitchEvent, TaskInfo,
+    SWITCH_IDLE,
+};
+
+/// Statistics of the current thread
+fn my_stats() -> TaskInfo {
+    let mut tasks = vec![TaskInfo::default(); task_info(&mut []) as usize + 8];
+    let count = task_info(&mut tasks) as usize;
+    let (pid, tid) = (getpid() as usize, gettid() as usize);
+    *tasks[..count.min(tasks.len())]
+        .iter()
+        .find(|task| task.pid == pid && task.tid == tid)
+        .unwrap()
+}
+
+/// Name of the task switched from or to in a trace
+fn who(pid: usize, tid: usize) -> String {
+    if pid == SWITCH_IDLE {
+        String::from("idle")
+    } else {
+        format!("{}:{}", pid, tid)
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let start = my_stats();
+    for _ in 0..5 {
+        yield_();
+    }
+    sleep(10);
+    let after_yield = my_stats();
+    // yielding and sleeping give up the CPU
+    assert!(after_yield.nvcsw >= start.nvcsw + 6);
+
+    // spin over a few time slices
+    let begin = get_time();
+    while get_time() - begin < 100 {}
+    let after_spin = my_stats();
+    assert!(after_spin.nivcsw > after_yield.nivcsw);
+    assert!(after_spin.run_us >= after_yield.run_us + 50_000);
+    println!(
+        "voluntary {} involuntary {} run {}us wait {}us",
+        after_spin.nvcsw, after_spin.nivcsw, after_spin.run_us, after_spin.wait_us
+    );
+
+    // drop what is traced so far, then switch out and find it in the trace
+    let mut events = vec![SwitchEvent::default(); 64];
+    while sched_trace(&mut events) as usize == events.len() {}
+    for _ in 0..3 {
+        yield_();
+    }
+    let count = sched_trace(&mut events) as usize;
+    let events = &events[..count];
+    let (pid, tid) = (getpid() as usize, gettid() as usize);
+    assert!(events
+        .iter()
+        .any(|event| event.prev_pid == pid && event.prev_tid == tid));
+    assert!(events
+        .windows(2)
+        .all(|pair| pair[0].time_us <= pair[1].time_us));
+    for event in events.iter().take(8) {
+        println!(
+            "{:>10}us cpu{} {} -> {}",
+            event.time_us,
+            event.cpu,
+            who(event.prev_pid, event.prev_tid),
+            who(event.next_pid, event.next_tid)
+        );
+    }
+    println!("schedstat passed!");
+    0
+}
@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicI32, Ordering};
+use user_lib::{
+    exit, fork, getpid, kill, sigaction, sigprocmask, sleep, waitpid, wifsignaled, wtermsig,
+    SignalAction, SignalFlags, SIGKILL, SIGTERM, SIGUSR1,
+};
+
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+
+fn handler(signum: i32) {
+    println!("handler got signal {}", signum);
+    RECEIVED.store(signum, Ordering::SeqCst);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // a handler runs before kill returns to the sender itself
+    let action = SignalAction {
+        handler: handler as usize,
+        ..Default::default()
+    };
+    assert_eq!(sigaction(SIGUSR1, Some(&action), None), 0);
+    assert_eq!(kill(getpid() as usize, SIGUSR1), 0);
+    assert_eq!(RECEIVED.load(Ordering::SeqCst), SIGUSR1);
+
+    // a masked signal stays pending until unmasked
+    RECEIVED.store(0, Ordering::SeqCst);
+    sigprocmask(SignalFlags::SIGUSR1.bits());
+    assert_eq!(kill(getpid() as usize, SIGUSR1), 0);
+    assert_eq!(RECEIVED.load(Ordering::SeqCst), 0);
+    sigprocmask(0);
+    assert_eq!(RECEIVED.load(Ordering::SeqCst), SIGUSR1);
+
+    // SIGKILL cannot be caught
+    assert!(sigaction(SIGKILL, Some(&action), None) < 0);
+
+    // the default action of SIGTERM terminates, even a sleeping child
+    let pid = fork();
+    if pid == 0 {
+        sleep(10000);
+        exit(0);
+    }
+    sleep(50);
+    assert_eq!(kill(pid as usize, SIGTERM), 0);
+    let mut status = 0;
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    assert!(wifsignaled(status) && wtermsig(status) == SIGTERM);
+    println!("sig_simple passed!");
+    0
+}
@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{
+    exit, fork, sigaction, sleep, wait, waitpid_nohang, SignalAction, ECHILD, SA_NOCLDWAIT, SIGCHLD,
+};
+
+const CHILDREN: usize = 3;
+
+static REAPED: AtomicUsize = AtomicUsize::new(0);
+
+fn on_sigchld(_signum: i32) {
+    // several children may have exited by the time the handler runs
+    let mut status = 0;
+    while waitpid_nohang(-1, &mut status) > 0 {
+        REAPED.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // reap children asynchronously from the SIGCHLD handler
+    let action = SignalAction {
+        handler: on_sigchld as usize,
+        ..Default::default()
+    };
+    assert_eq!(sigaction(SIGCHLD, Some(&action), None), 0);
+    for i in 0..CHILDREN {
+        if fork() == 0 {
+            sleep(20 * (i + 1));
+            exit(0);
+        }
+    }
+    while REAPED.load(Ordering::SeqCst) < CHILDREN {
+        sleep(10);
+    }
+    println!("reaped {} children from the SIGCHLD handler", CHILDREN);
+
+    // with SA_NOCLDWAIT, exited children never become zombies
+    let action = SignalAction {
+        flags: SA_NOCLDWAIT,
+        ..Default::default()
+    };
+    assert_eq!(sigaction(SIGCHLD, Some(&action), None), 0);
+    if fork() == 0 {
+        exit(0);
+    }
+    // blocks until the child has exited, which leaves nothing to wait for
+    let mut status = 0;
+    assert_eq!(wait(&mut status), -ECHILD);
+    println!("sigchld passed!");
+    0
+}
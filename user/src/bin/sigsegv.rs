@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, sigaction, waitpid, wifsignaled, wtermsig, SignalAction, SIGSEGV};
+
+const BAD_ADDR: usize = 0x10;
+
+fn handler(signum: i32, addr: usize) {
+    println!("handler got signal {} at {:#x}", signum, addr);
+    // returning would fault again, so leave from the handler
+    exit(if signum == SIGSEGV && addr == BAD_ADDR {
+        0
+    } else {
+        1
+    });
+}
+
+fn fault() {
+    unsafe {
+        (BAD_ADDR as *mut u8).write_volatile(0);
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // without a handler the fault terminates the process by SIGSEGV
+    let pid = fork();
+    if pid == 0 {
+        fault();
+        exit(1);
+    }
+    let mut status = 0;
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    assert!(wifsignaled(status) && wtermsig(status) == SIGSEGV);
+
+    // a handler is entered with the faulting address
+    let pid = fork();
+    if pid == 0 {
+        let action = SignalAction {
+            handler: handler as usize,
+            ..Default::default()
+        };
+        assert_eq!(sigaction(SIGSEGV, Some(&action), None), 0);
+        fault();
+        exit(1);
+    }
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    assert_eq!(status, 0);
+    println!("sigsegv passed!");
+    0
+}
@@ -0,0 +1,63 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::format;
+use user_lib::{getenv, getpid, getppid, setenv, spawn, spawnve, waitpid, wexitstatus, wifexited};
+
+/// Exit code of the spawned copies of this program
+const CHILD_EXIT: i32 = 42;
+
+fn wait_for(pid: isize) -> i32 {
+    assert!(pid > 0);
+    let mut status = 0;
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    assert!(wifexited(status));
+    wexitstatus(status)
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc > 1 {
+        // spawned below, with the pid of the parent
+        let expected = if argv[1] == "inherited" {
+            Some("yes")
+        } else {
+            None
+        };
+        assert_eq!(getenv("SPAWNED").as_deref(), expected);
+        assert_eq!(argv[2].parse::<isize>().unwrap(), getppid());
+        return CHILD_EXIT;
+    }
+
+    let pid = format!("{}\0", getpid());
+    assert_eq!(setenv("SPAWNED", "yes", true), 0);
+    let inherited = [
+        "spawn\0".as_ptr(),
+        "inherited\0".as_ptr(),
+        pid.as_ptr(),
+        core::ptr::null::<u8>(),
+    ];
+    assert_eq!(wait_for(spawn("spawn\0", &inherited)), CHILD_EXIT);
+    let empty = [
+        "spawn\0".as_ptr(),
+        "empty\0".as_ptr(),
+        pid.as_ptr(),
+        core::ptr::null::<u8>(),
+    ];
+    assert_eq!(
+        wait_for(spawnve("spawn\0", &empty, &[core::ptr::null::<u8>()])),
+        CHILD_EXIT
+    );
+
+    // a missing program fails in the caller, not in a child
+    assert!(spawn("no_such_program\0", &[core::ptr::null::<u8>()]) < 0);
+    let hello = ["hello_world\0".as_ptr(), core::ptr::null::<u8>()];
+    assert_eq!(wait_for(spawn("hello_world\0", &hello)), 0);
+    println!("spawn passed!");
+    0
+}
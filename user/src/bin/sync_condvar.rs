@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{
+    condvar_broadcast, condvar_create, condvar_signal, condvar_wait, exit, mutex_blocking_create,
+    mutex_lock, mutex_unlock, sleep, thread_create, waittid, EINVAL, EPERM,
+};
+
+const WAITERS: usize = 4;
+
+/// Set by the main thread under `MUTEX`
+static mut READY: bool = false;
+static mut WOKEN: usize = 0;
+static mut GO: bool = false;
+
+static mut MUTEX: usize = 0;
+static mut CONDVAR: usize = 0;
+
+fn first() -> ! {
+    unsafe {
+        mutex_lock(MUTEX);
+        while !READY {
+            assert_eq!(condvar_wait(CONDVAR, MUTEX), 0);
+        }
+        mutex_unlock(MUTEX);
+    }
+    println!("first: woken up once ready");
+    exit(0)
+}
+
+fn waiter() -> ! {
+    unsafe {
+        mutex_lock(MUTEX);
+        while !GO {
+            condvar_wait(CONDVAR, MUTEX);
+        }
+        WOKEN += 1;
+        mutex_unlock(MUTEX);
+    }
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    unsafe {
+        MUTEX = mutex_blocking_create() as usize;
+        CONDVAR = condvar_create() as usize;
+        // signal wakes up a waiter
+        let tid = thread_create(first as usize, 0);
+        sleep(10);
+        mutex_lock(MUTEX);
+        READY = true;
+        condvar_signal(CONDVAR);
+        mutex_unlock(MUTEX);
+        assert_eq!(waittid(tid as usize), 0);
+        // broadcast wakes up all of them
+        let tids: Vec<_> = (0..WAITERS)
+            .map(|_| thread_create(waiter as usize, 0) as usize)
+            .collect();
+        sleep(10);
+        mutex_lock(MUTEX);
+        GO = true;
+        condvar_broadcast(CONDVAR);
+        mutex_unlock(MUTEX);
+        for tid in tids {
+            assert_eq!(waittid(tid), 0);
+        }
+        assert_eq!(WOKEN, WAITERS);
+        // the mutex must be held
+        assert_eq!(condvar_wait(CONDVAR, MUTEX), -EPERM);
+        assert_eq!(condvar_wait(100, MUTEX), -EINVAL);
+        assert_eq!(condvar_signal(100), -EINVAL);
+    }
+    println!("sync_condvar passed!");
+    0
+}
@@ -0,0 +1,102 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use user_lib::{
+    exit, rwlock_create, rwlock_read, rwlock_unlock, rwlock_write, sleep, thread_create, waittid,
+    EINVAL, EPERM,
+};
+
+const READERS: usize = 4;
+const WRITERS: usize = 2;
+const ROUNDS: usize = 100;
+
+static mut RWLOCK: usize = 0;
+/// Both are updated by writers under `RWLOCK` and must be equal for readers
+static mut A: usize = 0;
+static mut B: usize = 0;
+
+static WRITER_DONE: AtomicBool = AtomicBool::new(false);
+static READERS_IN: AtomicUsize = AtomicUsize::new(0);
+
+fn reader() -> ! {
+    for _ in 0..ROUNDS {
+        unsafe {
+            rwlock_read(RWLOCK);
+            assert_eq!(A, B);
+            rwlock_unlock(RWLOCK);
+        }
+    }
+    exit(0)
+}
+
+fn writer() -> ! {
+    for _ in 0..ROUNDS {
+        unsafe {
+            rwlock_write(RWLOCK);
+            A += 1;
+            B += 1;
+            rwlock_unlock(RWLOCK);
+        }
+    }
+    exit(0)
+}
+
+fn waiting_writer() -> ! {
+    unsafe {
+        rwlock_write(RWLOCK);
+        // the readers arriving while it waited got in after it
+        assert_eq!(READERS_IN.load(Ordering::SeqCst), 1);
+        WRITER_DONE.store(true, Ordering::SeqCst);
+        rwlock_unlock(RWLOCK);
+    }
+    exit(0)
+}
+
+fn late_reader() -> ! {
+    unsafe {
+        rwlock_read(RWLOCK);
+        assert!(WRITER_DONE.load(Ordering::SeqCst));
+        READERS_IN.fetch_add(1, Ordering::SeqCst);
+        rwlock_unlock(RWLOCK);
+    }
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    unsafe {
+        RWLOCK = rwlock_create() as usize;
+        // readers never see a write half done
+        let mut tids: Vec<_> = (0..READERS)
+            .map(|_| thread_create(reader as usize, 0) as usize)
+            .collect();
+        tids.extend((0..WRITERS).map(|_| thread_create(writer as usize, 0) as usize));
+        for tid in tids {
+            assert_eq!(waittid(tid), 0);
+        }
+        assert_eq!(A, WRITERS * ROUNDS);
+        // a waiting writer goes before readers arriving after it
+        assert_eq!(rwlock_read(RWLOCK), 0);
+        READERS_IN.store(1, Ordering::SeqCst);
+        let writer_tid = thread_create(waiting_writer as usize, 0);
+        sleep(10);
+        let reader_tid = thread_create(late_reader as usize, 0);
+        sleep(10);
+        assert!(!WRITER_DONE.load(Ordering::SeqCst));
+        assert_eq!(rwlock_unlock(RWLOCK), 0);
+        assert_eq!(waittid(writer_tid as usize), 0);
+        assert_eq!(waittid(reader_tid as usize), 0);
+        // it must be held to be released
+        assert_eq!(rwlock_unlock(RWLOCK), -EPERM);
+        assert_eq!(rwlock_read(100), -EINVAL);
+    }
+    println!("sync_rwlock passed!");
+    0
+}
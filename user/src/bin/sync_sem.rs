@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use user_lib::{
+    exit, semaphore_create, semaphore_down, semaphore_up, sleep, thread_create, waittid, EINVAL,
+};
+
+static UP_DONE: AtomicBool = AtomicBool::new(false);
+
+fn first(sem: usize) -> ! {
+    // blocks until the second thread releases the resource
+    assert_eq!(semaphore_down(sem), 0);
+    assert!(UP_DONE.load(Ordering::SeqCst));
+    println!("first: resource taken after the second thread released it");
+    exit(0)
+}
+
+fn second(sem: usize) -> ! {
+    sleep(10);
+    UP_DONE.store(true, Ordering::SeqCst);
+    assert_eq!(semaphore_up(sem), 0);
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // no resource at first
+    let sem = semaphore_create(0);
+    assert!(sem >= 0);
+    let tids = [
+        thread_create(first as usize, sem as usize),
+        thread_create(second as usize, sem as usize),
+    ];
+    for tid in tids {
+        assert_eq!(waittid(tid as usize), 0);
+    }
+    // the resources are counted
+    let sem = semaphore_create(2) as usize;
+    assert_eq!(semaphore_down(sem), 0);
+    assert_eq!(semaphore_down(sem), 0);
+    assert_eq!(semaphore_up(sem), 0);
+    assert_eq!(semaphore_down(sem), 0);
+    assert_eq!(semaphore_up(100), -EINVAL);
+    assert_eq!(semaphore_down(100), -EINVAL);
+    println!("sync_sem passed!");
+    0
+}
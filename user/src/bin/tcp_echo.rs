@@ -0,0 +1,63 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    accept, bind, close, listen, read, socket, write, SockAddrIn, AF_INET, SOCK_STREAM,
+};
+
+/// Forwarded from port `TCP_PORT` of the host by the Makefile of the kernel
+const ECHO_PORT: u16 = 2001;
+
+/// Send the bytes received on each connection to `ECHO_PORT` back, one
+/// connection at a time, until a line reads "quit". Try it from the host with
+/// e.g. `nc localhost 6201`.
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = socket(AF_INET, SOCK_STREAM, 0);
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    if bind(fd, &SockAddrIn::new([0; 4], ECHO_PORT)) != 0 || listen(fd, 4) != 0 {
+        println!("tcp_echo: port {} in use", ECHO_PORT);
+        return -1;
+    }
+    println!("tcp_echo: listening on port {}", ECHO_PORT);
+    let mut buf = [0u8; 1500];
+    loop {
+        let mut peer = SockAddrIn::default();
+        let conn = accept(fd, &mut peer);
+        if conn < 0 {
+            println!("tcp_echo: accept failed with {}", conn);
+            return -1;
+        }
+        let conn = conn as usize;
+        let [a, b, c, d] = peer.addr;
+        println!(
+            "tcp_echo: connection from {}.{}.{}.{}:{}",
+            a,
+            b,
+            c,
+            d,
+            peer.port()
+        );
+        loop {
+            let len = read(conn, &mut buf);
+            if len <= 0 {
+                break;
+            }
+            let data = &buf[..len as usize];
+            if write(conn, data) != len {
+                break;
+            }
+            if data.strip_suffix(b"\n").unwrap_or(data) == b"quit" {
+                close(conn);
+                close(fd);
+                return 0;
+            }
+        }
+        println!("tcp_echo: connection closed");
+        close(conn);
+    }
+}
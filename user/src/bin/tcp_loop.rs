@@ -0,0 +1,105 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    accept, bind, close, connect, exit, fork, listen, read, sendto, socket, waitpid, wexitstatus,
+    write, SockAddrIn, AF_INET, EADDRINUSE, ECONNREFUSED, EISCONN, EOPNOTSUPP, SOCK_STREAM,
+};
+
+const LOOPBACK: [u8; 4] = [127, 0, 0, 1];
+const SERVER_PORT: u16 = 5001;
+/// More than the window, so that the client blocks until the server reads
+const DATA_LEN: usize = 40000;
+
+fn tcp_socket() -> usize {
+    let fd = socket(AF_INET, SOCK_STREAM, 0);
+    assert!(fd >= 0);
+    fd as usize
+}
+
+fn byte(i: usize) -> u8 {
+    (i % 251) as u8
+}
+
+fn client() -> i32 {
+    let fd = tcp_socket();
+    assert_eq!(connect(fd, &SockAddrIn::new(LOOPBACK, SERVER_PORT)), 0);
+    assert_eq!(
+        connect(fd, &SockAddrIn::new(LOOPBACK, SERVER_PORT)),
+        -EISCONN
+    );
+    let mut buf = [0u8; 1000];
+    for chunk in 0..DATA_LEN / buf.len() {
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = byte(chunk * 1000 + i);
+        }
+        assert_eq!(write(fd, &buf), buf.len() as isize);
+    }
+    assert_eq!(read(fd, &mut buf), 2);
+    assert_eq!(&buf[..2], b"ok");
+    // the server closed the connection
+    assert_eq!(read(fd, &mut buf), 0);
+    close(fd);
+    0
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // nothing listening
+    let fd = tcp_socket();
+    assert_eq!(
+        connect(fd, &SockAddrIn::new(LOOPBACK, SERVER_PORT)),
+        -ECONNREFUSED
+    );
+    close(fd);
+
+    let server = tcp_socket();
+    assert_eq!(bind(server, &SockAddrIn::new([0; 4], SERVER_PORT)), 0);
+    let other = tcp_socket();
+    assert_eq!(
+        bind(other, &SockAddrIn::new([0; 4], SERVER_PORT)),
+        -EADDRINUSE
+    );
+    close(other);
+    assert_eq!(listen(server, 1), 0);
+    assert_eq!(
+        sendto(server, b"x", &SockAddrIn::new(LOOPBACK, SERVER_PORT)),
+        -EOPNOTSUPP
+    );
+
+    let pid = fork();
+    if pid == 0 {
+        close(server);
+        exit(client());
+    }
+    let mut peer = SockAddrIn::default();
+    let conn = accept(server, &mut peer);
+    assert!(conn >= 0);
+    let conn = conn as usize;
+    assert_eq!(peer.addr, LOOPBACK);
+    assert!(peer.port() >= 49152);
+
+    let mut buf = [0u8; 1500];
+    let mut received = 0;
+    while received < DATA_LEN {
+        let len = read(conn, &mut buf);
+        assert!(len > 0);
+        for (i, &b) in buf[..len as usize].iter().enumerate() {
+            assert_eq!(b, byte(received + i));
+        }
+        received += len as usize;
+    }
+    assert_eq!(received, DATA_LEN);
+    assert_eq!(write(conn, b"ok"), 2);
+    close(conn);
+
+    let mut status = 0;
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    assert_eq!(wexitstatus(status), 0);
+    close(server);
+    println!("tcp_loop passed!");
+    0
+}
@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{exit, gettid, thread_create, waittid};
+
+const THREADS: usize = 3;
+const ROUNDS: usize = 1000;
+
+static mut COUNTERS: [usize; THREADS] = [0; THREADS];
+
+fn worker(index: usize) -> ! {
+    for _ in 0..ROUNDS {
+        unsafe {
+            COUNTERS[index] += 1;
+        }
+    }
+    println!("thread {} (tid {}) done", index, gettid());
+    exit(index as i32 + 1)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(gettid(), 0);
+    let mut tids = Vec::new();
+    for index in 0..THREADS {
+        let tid = thread_create(worker as usize, index);
+        assert!(tid > 0);
+        tids.push(tid as usize);
+    }
+    for (index, tid) in tids.iter().enumerate() {
+        assert_eq!(waittid(*tid), index as isize + 1);
+    }
+    // reaped threads cannot be waited for again
+    assert_eq!(waittid(tids[0]), -1);
+    assert!(unsafe { COUNTERS.iter().all(|&count| count == ROUNDS) });
+    println!("threads passed!");
+    0
+}
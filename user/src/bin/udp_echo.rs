@@ -0,0 +1,50 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{bind, recvfrom, sendto, socket, SockAddrIn, AF_INET, SOCK_DGRAM};
+
+/// Forwarded from port `UDP_PORT` of the host by the Makefile of the kernel
+const ECHO_PORT: u16 = 2000;
+
+/// Send each datagram received on `ECHO_PORT` back to its sender, until one
+/// reads "quit". Try it from the host with e.g. `nc -u localhost 6200`.
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = socket(AF_INET, SOCK_DGRAM, 0);
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    if bind(fd, &SockAddrIn::new([0; 4], ECHO_PORT)) != 0 {
+        println!("udp_echo: port {} in use", ECHO_PORT);
+        return -1;
+    }
+    println!("udp_echo: listening on port {}", ECHO_PORT);
+    let mut buf = [0u8; 1500];
+    loop {
+        let mut from = SockAddrIn::default();
+        let len = recvfrom(fd, &mut buf, &mut from);
+        if len < 0 {
+            println!("udp_echo: recvfrom failed with {}", len);
+            return -1;
+        }
+        let data = &buf[..len as usize];
+        let [a, b, c, d] = from.addr;
+        println!(
+            "udp_echo: {} bytes from {}.{}.{}.{}:{}",
+            len,
+            a,
+            b,
+            c,
+            d,
+            from.port()
+        );
+        if sendto(fd, data, &from) < 0 {
+            println!("udp_echo: cannot reply");
+        }
+        if data.strip_suffix(b"\n").unwrap_or(data) == b"quit" {
+            return 0;
+        }
+    }
+}
@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    bind, close, read, recvfrom, sendto, socket, SockAddrIn, AF_INET, EADDRINUSE, EMSGSIZE,
+    LOCAL_IP, MAX_UDP_PAYLOAD, SOCK_DGRAM,
+};
+
+const LOOPBACK: [u8; 4] = [127, 0, 0, 1];
+const SERVER_PORT: u16 = 5000;
+
+fn udp_socket() -> usize {
+    let fd = socket(AF_INET, SOCK_DGRAM, 0);
+    assert!(fd >= 0);
+    fd as usize
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let server = udp_socket();
+    assert_eq!(bind(server, &SockAddrIn::new([0; 4], SERVER_PORT)), 0);
+    let other = udp_socket();
+    assert_eq!(
+        bind(other, &SockAddrIn::new([0; 4], SERVER_PORT)),
+        -EADDRINUSE
+    );
+    close(other);
+
+    // the client is bound to an ephemeral port by its first datagram
+    let client = udp_socket();
+    let server_addr = SockAddrIn::new(LOOPBACK, SERVER_PORT);
+    assert_eq!(sendto(client, b"ping", &server_addr), 4);
+    let mut buf = [0u8; 16];
+    let mut from = SockAddrIn::default();
+    assert_eq!(recvfrom(server, &mut buf, &mut from), 4);
+    assert_eq!(&buf[..4], b"ping");
+    assert_eq!(from.addr, LOOPBACK);
+    assert!(from.port() >= 49152);
+    assert_eq!(sendto(server, b"pong", &from), 4);
+    assert_eq!(recvfrom(client, &mut buf, &mut from), 4);
+    assert_eq!(&buf[..4], b"pong");
+    assert_eq!(from.port(), SERVER_PORT);
+
+    // to the address of the guest, truncated and read as a file
+    let local_addr = SockAddrIn::new(LOCAL_IP, SERVER_PORT);
+    assert_eq!(sendto(client, b"0123456789", &local_addr), 10);
+    assert_eq!(sendto(client, b"next", &local_addr), 4);
+    assert_eq!(recvfrom(server, &mut buf[..3], &mut from), 3);
+    assert_eq!(&buf[..3], b"012");
+    assert_eq!(from.addr, LOCAL_IP);
+    assert_eq!(read(server, &mut buf), 4);
+    assert_eq!(&buf[..4], b"next");
+
+    let big = [0u8; MAX_UDP_PAYLOAD + 1];
+    assert_eq!(sendto(client, &big, &server_addr), -EMSGSIZE);
+    assert_eq!(
+        sendto(client, &big[1..], &server_addr),
+        MAX_UDP_PAYLOAD as isize
+    );
+    let mut big_buf = [1u8; MAX_UDP_PAYLOAD];
+    assert_eq!(
+        recvfrom(server, &mut big_buf, &mut from),
+        MAX_UDP_PAYLOAD as isize
+    );
+    assert!(big_buf.iter().all(|&byte| byte == 0));
+
+    // closing frees the port
+    close(server);
+    let server = udp_socket();
+    assert_eq!(bind(server, &SockAddrIn::new([0; 4], SERVER_PORT)), 0);
+    close(server);
+    close(client);
+    println!("udp_loop passed!");
+    0
+}
@@ -13,8 +13,22 @@ const DL: u8 = 0x7fu8;
 const BS: u8 = 0x08u8;
 
 use alloc::string::String;
+use alloc::vec::Vec;
 use user_lib::console::getchar;
-use user_lib::{exec, fork, waitpid};
+use user_lib::{
+    reboot, spawn, times, waitpid, wexitstatus, Tms, CLK_TCK, LINUX_REBOOT_CMD_POWER_OFF,
+    LINUX_REBOOT_CMD_RESTART,
+};
+
+/// Print a time of `clocks` in `1 / CLK_TCK` seconds as seconds
+fn print_time(name: &str, clocks: usize) {
+    println!(
+        "{}\t{}.{:02}s",
+        name,
+        clocks / CLK_TCK,
+        clocks % CLK_TCK * 100 / CLK_TCK
+    );
+}
 
 #[no_mangle]
 pub fn main() -> i32 {
@@ -27,20 +41,74 @@ pub fn main() -> i32 {
             LF | CR => {
                 println!("");
                 if !line.is_empty() {
-                    line.push('\0');
-                    let pid = fork();
-                    if pid == 0 {
-                        // child process
-                        if exec(line.as_str()) == -1 {
-                            println!("Error when executing!");
-                            return -4;
+                    // split the line into the program and its arguments
+                    let args: Vec<String> = line
+                        .split_whitespace()
+                        .map(|arg| {
+                            let mut arg = String::from(arg);
+                            arg.push('\0');
+                            arg
+                        })
+                        .collect();
+                    if args.is_empty() {
+                        line.clear();
+                        print!(">> ");
+                        continue;
+                    }
+                    // `poweroff [code]` and `reboot` are run by the shell, which may
+                    match args[0].as_str() {
+                        "poweroff\0" => {
+                            let code = args.get(1).map_or(Some(0), |code| {
+                                code.trim_end_matches('\0').parse::<i32>().ok()
+                            });
+                            match code {
+                                Some(code) => {
+                                    let err = reboot(LINUX_REBOOT_CMD_POWER_OFF, code);
+                                    println!("poweroff failed: {}", err);
+                                }
+                                None => println!("usage: poweroff [code]"),
+                            }
+                            line.clear();
+                            print!(">> ");
+                            continue;
                         }
-                        unreachable!();
+                        "reboot\0" => {
+                            let err = reboot(LINUX_REBOOT_CMD_RESTART, 0);
+                            println!("reboot failed: {}", err);
+                            line.clear();
+                            print!(">> ");
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    // `time cmd` reports the CPU time of the command
+                    let timed = args[0] == "time\0" && args.len() > 1;
+                    let args = if timed { &args[1..] } else { &args[..] };
+                    let mut args_addr: Vec<*const u8> =
+                        args.iter().map(|arg| arg.as_ptr()).collect();
+                    args_addr.push(core::ptr::null::<u8>());
+                    let mut before = Tms::default();
+                    let start = times(&mut before);
+                    // no need to copy the shell only for exec to throw the copy away
+                    let pid = spawn(args[0].as_str(), args_addr.as_slice());
+                    if pid < 0 {
+                        println!("Error when executing!");
                     } else {
-                        let mut exit_code: i32 = 0;
-                        let exit_pid = waitpid(pid as usize, &mut exit_code);
+                        let mut status: i32 = 0;
+                        let exit_pid = waitpid(pid as usize, &mut status);
                         assert_eq!(pid, exit_pid);
-                        println!("Shell: Process {} exited with code {}", pid, exit_code);
+                        println!(
+                            "Shell: Process {} exited with code {}",
+                            pid,
+                            wexitstatus(status) as i8
+                        );
+                        if timed {
+                            let mut after = Tms::default();
+                            let end = times(&mut after);
+                            print_time("real", (end - start) as usize);
+                            print_time("user", after.tms_cutime - before.tms_cutime);
+                            print_time("sys", after.tms_cstime - before.tms_cstime);
+                        }
                     }
                     line.clear();
                 }
@@ -5,9 +5,9 @@
 extern crate user_lib;
 
 // not in SUCC_TESTS & FAIL_TESTS
-// count_lines, infloop, user_shell, usertests
+// count_lines, infloop, kcov, user_shell, usertests
 
-// item of TESTS : app_name(argv_0), argv_1, argv_2, argv_3, exit_code
+// item of TESTS : app_name(argv_0), argv_1, argv_2, argv_3, wait status
 static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("filetest_simple\0", "\0", "\0", "\0", 0),
     ("cat_filea\0", "\0", "\0", "\0", 0),
@@ -19,19 +19,94 @@ static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("forktree\0", "\0", "\0", "\0", 0),
     ("hello_world\0", "\0", "\0", "\0", 0),
     ("huge_write\0", "\0", "\0", "\0", 0),
+    ("io_timer\0", "\0", "\0", "\0", 0),
     ("matrix\0", "\0", "\0", "\0", 0),
     ("sleep_simple\0", "\0", "\0", "\0", 0),
     ("sleep\0", "\0", "\0", "\0", 0),
+    ("nanosleep\0", "\0", "\0", "\0", 0),
+    ("clock\0", "\0", "\0", "\0", 0),
+    ("misaligned\0", "\0", "\0", "\0", 0),
+    ("fpu\0", "\0", "\0", "\0", 0),
+    ("irq_regs\0", "\0", "\0", "\0", 0),
+    ("ptrace\0", "\0", "\0", "\0", 0),
+    ("udp_loop\0", "\0", "\0", "\0", 0),
+    ("tcp_loop\0", "\0", "\0", "\0", 0),
+    ("fb_demo\0", "\0", "\0", "\0", 0),
+    ("input_test\0", "\0", "\0", "\0", 0),
+    ("file_time\0", "\0", "\0", "\0", 0),
+    ("hvc_test\0", "\0", "\0", "\0", 0),
+    ("getrandom\0", "\0", "\0", "\0", 0),
+    ("waitpid_nohang\0", "\0", "\0", "\0", 0),
+    ("sig_simple\0", "\0", "\0", "\0", 0),
+    ("sigchld\0", "\0", "\0", "\0", 0),
+    ("sigsegv\0", "\0", "\0", "\0", 0),
+    ("alarm\0", "\0", "\0", "\0", 0),
+    ("clone\0", "\0", "\0", "\0", 0),
+    ("threads\0", "\0", "\0", "\0", 0),
+    ("cmdline_args\0", "1\0", "2\0", "3\0", 0),
+    ("env\0", "\0", "\0", "\0", 0),
+    ("pgrp\0", "\0", "\0", "\0", 0),
+    ("orphans\0", "\0", "\0", "\0", 0),
+    ("exit_group\0", "\0", "\0", "\0", 0),
+    ("affinity\0", "\0", "\0", "\0", 0),
+    ("hotplug\0", "\0", "\0", "\0", 0),
+    ("nice\0", "\0", "\0", "\0", 0),
+    ("priority\0", "\0", "\0", "\0", 0),
+    ("cputime\0", "\0", "\0", "\0", 0),
+    ("prctl\0", "\0", "\0", "\0", 0),
+    ("ps\0", "\0", "\0", "\0", 0),
+    ("mutex\0", "\0", "\0", "\0", 0),
+    ("rlimit\0", "\0", "\0", "\0", 0),
+    ("schedstat\0", "\0", "\0", "\0", 0),
+    ("faults\0", "\0", "\0", "\0", 0),
+    ("memusage\0", "\0", "\0", "\0", 0),
+    ("mmap_split\0", "\0", "\0", "\0", 0),
+    ("spawn\0", "\0", "\0", "\0", 0),
+    ("pid_lookup\0", "\0", "\0", "\0", 0),
+    ("sync_sem\0", "\0", "\0", "\0", 0),
+    ("mpsc_sem\0", "\0", "\0", "\0", 0),
+    ("sync_condvar\0", "\0", "\0", "\0", 0),
+    ("sync_rwlock\0", "\0", "\0", "\0", 0),
+    ("deadlock\0", "\0", "\0", "\0", 0),
     ("yield\0", "\0", "\0", "\0", 0),
 ];
 
-static FAIL_TESTS: &[(&str, &str, &str, &str, i32)] = &[("stack_overflow\0", "\0", "\0", "\0", -2)];
+// killed by SIGSEGV running into the unmapped guard page
+static FAIL_TESTS: &[(&str, &str, &str, &str, i32)] = &[(
+    "stack_overflow\0",
+    "\0",
+    "\0",
+    "\0",
+    fault_status(SIGSEGV, SEGV_MAPERR),
+)];
 
-use user_lib::{exec, fork, waitpid};
+use user_lib::{
+    alarm, exec, fault_status, fork, getpid, kcov, kill, prctl, reboot, sigaction, waitpid,
+    waitpid_nohang, wexitstatus, wfaultcode, wifsignaled, wtermsig, SignalAction, EINTR,
+    KCOV_COLLECT, KCOV_ENABLE, LINUX_REBOOT_CMD_POWER_OFF, PR_SET_CHILD_MACHINE_CONTROL,
+    SEGV_MAPERR, SIGALRM, SIGKILL, SIGSEGV,
+};
+
+/// seconds a test may run before the watchdog kills it
+const TEST_TIMEOUT_SECS: usize = 60;
+
+/// the watchdog alarm only needs to interrupt `waitpid`
+fn watchdog(_signum: i32) {}
+
+/// Exit code of the run, the number of failed tests. As the init program, with
+/// `make run TEST=1`, power off for QEMU to exit with it.
+fn finish(failed: i32) -> i32 {
+    if getpid() == 0 {
+        reboot(LINUX_REBOOT_CMD_POWER_OFF, failed);
+    }
+    failed
+}
 
 fn run_tests(tests: &[(&str, &str, &str, &str, i32)]) -> i32 {
     let mut pass_num = 0;
-    let mut arr: [*const u8; 4] = [
+    // argv passed to exec, the last null pointer ends it
+    let mut arr: [*const u8; 5] = [
+        core::ptr::null::<u8>(),
         core::ptr::null::<u8>(),
         core::ptr::null::<u8>(),
         core::ptr::null::<u8>(),
@@ -65,20 +140,45 @@ fn run_tests(tests: &[(&str, &str, &str, &str, i32)]) -> i32 {
 
         let pid = fork();
         if pid == 0 {
-            exec(test.0);
+            exec(test.0, &arr[..]);
             panic!("unreachable!");
         } else {
-            let mut exit_code: i32 = Default::default();
-            let wait_pid = waitpid(pid as usize, &mut exit_code);
+            let mut status: i32 = Default::default();
+            alarm(TEST_TIMEOUT_SECS);
+            let mut wait_pid = waitpid(pid as usize, &mut status);
+            if wait_pid == -EINTR {
+                println!(
+                    "\x1b[31mUsertests: Test {} in Process {} timed out\x1b[0m",
+                    test.0, pid
+                );
+                kill(pid as usize, SIGKILL);
+                wait_pid = waitpid(pid as usize, &mut status);
+            }
+            alarm(0);
             assert_eq!(pid, wait_pid);
-            if exit_code == test.4 {
-                // summary apps with  exit_code
+            // as initproc with make run TEST=1, reap the orphans adopted so far
+            while waitpid_nohang(-1, &mut Default::default()) > 0 {}
+            if status == test.4 {
+                // summary apps with expected wait status
                 pass_num = pass_num + 1;
             }
-            println!(
-                "\x1b[32mUsertests: Test {} in Process {} exited with code {}\x1b[0m",
-                test.0, pid, exit_code
-            );
+            if wifsignaled(status) {
+                println!(
+                    "\x1b[32mUsertests: Test {} in Process {} killed by signal {} (fault code {})\x1b[0m",
+                    test.0,
+                    pid,
+                    wtermsig(status),
+                    wfaultcode(status)
+                );
+            } else {
+                // the exit code is reported modulo 256
+                println!(
+                    "\x1b[32mUsertests: Test {} in Process {} exited with code {}\x1b[0m",
+                    test.0,
+                    pid,
+                    wexitstatus(status) as i8 as i32
+                );
+            }
         }
     }
     pass_num
@@ -86,15 +186,32 @@ fn run_tests(tests: &[(&str, &str, &str, &str, i32)]) -> i32 {
 
 #[no_mangle]
 pub fn main() -> i32 {
+    let action = SignalAction {
+        handler: watchdog as usize,
+        ..Default::default()
+    };
+    sigaction(SIGALRM, Some(&action), None);
+    // as the init program, let the tests control the machine, e.g. hotplug
+    if getpid() == 0 {
+        prctl(PR_SET_CHILD_MACHINE_CONTROL, 1);
+    }
+    // with a kernel built with KCOV=1, report the kernel code the tests cover
+    let covered = kcov(KCOV_ENABLE, &mut []) == 0;
     let succ_num = run_tests(SUCC_TESTS);
     let err_num = run_tests(FAIL_TESTS);
+    if covered {
+        println!(
+            "Usertests: {} kernel addresses covered",
+            kcov(KCOV_COLLECT, &mut [])
+        );
+    }
     if succ_num == SUCC_TESTS.len() as i32 && err_num == FAIL_TESTS.len() as i32 {
         println!(
             "{} of sueecssed apps, {} of failed apps run correctly. \nUsertests passed!",
             SUCC_TESTS.len(),
             FAIL_TESTS.len()
         );
-        return 0;
+        return finish(0);
     }
     if succ_num != SUCC_TESTS.len() as i32 {
         println!(
@@ -111,5 +228,5 @@ pub fn main() -> i32 {
         );
     }
     println!(" Usertests failed!");
-    return -1;
+    finish(SUCC_TESTS.len() as i32 - succ_num + FAIL_TESTS.len() as i32 - err_num)
 }
@@ -18,7 +18,7 @@ static TESTS: &[&str] = &[
     "yield\0",
 ];
 
-use user_lib::{exec, fork, waitpid};
+use user_lib::{exec, fork, waitpid, wexitstatus};
 
 #[no_mangle]
 pub fn main() -> i32 {
@@ -26,15 +26,17 @@ pub fn main() -> i32 {
         println!("Usertests: Running {}", test);
         let pid = fork();
         if pid == 0 {
-            exec(*test);
+            exec(*test, &[test.as_ptr(), core::ptr::null::<u8>()]);
             panic!("unreachable!");
         } else {
-            let mut exit_code: i32 = Default::default();
-            let wait_pid = waitpid(pid as usize, &mut exit_code);
+            let mut status: i32 = Default::default();
+            let wait_pid = waitpid(pid as usize, &mut status);
             assert_eq!(pid, wait_pid);
             println!(
                 "\x1b[32mUsertests: Test {} in Process {} exited with code {}\x1b[0m",
-                test, pid, exit_code
+                test,
+                pid,
+                wexitstatus(status) as i8
             );
         }
     }
@@ -0,0 +1,30 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, sleep, waitpid_nohang, wexitstatus, wifexited, ECHILD};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut status: i32 = 0;
+    assert_eq!(waitpid_nohang(-1, &mut status), -ECHILD);
+    let pid = fork();
+    if pid == 0 {
+        sleep(100);
+        exit(7);
+    }
+    // the child is still sleeping
+    assert_eq!(waitpid_nohang(pid, &mut status), 0);
+    let mut polls = 0;
+    while waitpid_nohang(pid, &mut status) == 0 {
+        polls += 1;
+        sleep(10);
+    }
+    assert!(wifexited(status) && wexitstatus(status) == 7);
+    println!("child {} exited after {} polls", pid, polls);
+    assert_eq!(waitpid_nohang(-1, &mut status), -ECHILD);
+    println!("waitpid_nohang passed!");
+    0
+}
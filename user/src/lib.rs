@@ -12,6 +12,8 @@ extern crate alloc;
 #[macro_use]
 extern crate bitflags;
 
+use alloc::string::String;
+use alloc::vec::Vec;
 use buddy_system_allocator::LockedHeap;
 use syscall::*;
 
@@ -29,17 +31,93 @@ pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
 
 #[no_mangle]
 #[link_section = ".text.entry"]
-pub extern "C" fn _start() -> ! {
+pub extern "C" fn _start(argc: usize, argv: usize, envp: usize) -> ! {
     unsafe {
         HEAP.lock()
             .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
     }
-    exit(main());
+    // the kernel leaves the argument strings on the stack, argv points to their addresses
+    let mut v: Vec<&'static str> = Vec::new();
+    for i in 0..argc {
+        v.push(str_at(argv + i * core::mem::size_of::<usize>()));
+    }
+    // so does envp for the environment, ending with a null pointer
+    for i in 0.. {
+        let ptr = envp + i * core::mem::size_of::<usize>();
+        if unsafe { (ptr as *const usize).read_volatile() } == 0 {
+            break;
+        }
+        let mut var = String::from(str_at(ptr));
+        var.push('\0');
+        unsafe {
+            ENVIRON.push(var);
+        }
+    }
+    exit(main(argc, v.as_slice()));
+}
+
+/// The `\0`-terminated string whose address is at `ptr`
+fn str_at(ptr: usize) -> &'static str {
+    let str_start = unsafe { (ptr as *const usize).read_volatile() };
+    let len = (0usize..)
+        .find(|i| unsafe { ((str_start + *i) as *const u8).read_volatile() == 0 })
+        .unwrap();
+    core::str::from_utf8(unsafe { core::slice::from_raw_parts(str_start as *const u8, len) })
+        .unwrap()
+}
+
+/// Environment variables as `NAME=value\0` strings, passed on by `exec`
+static mut ENVIRON: Vec<String> = Vec::new();
+
+/// Index of the environment variable `name` in `ENVIRON`
+fn env_index(name: &str) -> Option<usize> {
+    unsafe {
+        ENVIRON
+            .iter()
+            .position(|var| var.split('=').next() == Some(name))
+    }
+}
+
+/// Value of the environment variable `name`
+pub fn getenv(name: &str) -> Option<String> {
+    let var = unsafe { &ENVIRON[env_index(name)?] };
+    Some(String::from(&var[name.len() + 1..var.len() - 1]))
+}
+
+/// Set the environment variable `name` to `value`, unless it is set and `overwrite`
+/// is false. Return -EINVAL if `name` is empty or contains `=`.
+pub fn setenv(name: &str, value: &str, overwrite: bool) -> isize {
+    if name.is_empty() || name.contains('=') {
+        return -EINVAL;
+    }
+    let var = alloc::format!("{}={}\0", name, value);
+    unsafe {
+        match env_index(name) {
+            Some(i) if overwrite => ENVIRON[i] = var,
+            Some(_) => {}
+            None => ENVIRON.push(var),
+        }
+    }
+    0
+}
+
+/// Remove the environment variable `name`. Return -EINVAL if `name` is empty or
+/// contains `=`.
+pub fn unsetenv(name: &str) -> isize {
+    if name.is_empty() || name.contains('=') {
+        return -EINVAL;
+    }
+    if let Some(i) = env_index(name) {
+        unsafe {
+            ENVIRON.remove(i);
+        }
+    }
+    0
 }
 
 #[linkage = "weak"]
 #[no_mangle]
-fn main() -> i32 {
+fn main(_argc: usize, _argv: &[&str]) -> i32 {
     panic!("Cannot find main!");
 }
 
@@ -50,9 +128,536 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        /// reads of a device return 0 rather than wait for data
+        const NONBLOCK = 1 << 11;
+    }
+}
+
+pub const SIGHUP: i32 = 1;
+pub const SIGINT: i32 = 2;
+pub const SIGQUIT: i32 = 3;
+pub const SIGILL: i32 = 4;
+pub const SIGTRAP: i32 = 5;
+pub const SIGABRT: i32 = 6;
+pub const SIGBUS: i32 = 7;
+pub const SIGFPE: i32 = 8;
+pub const SIGKILL: i32 = 9;
+pub const SIGUSR1: i32 = 10;
+pub const SIGSEGV: i32 = 11;
+pub const SIGUSR2: i32 = 12;
+pub const SIGPIPE: i32 = 13;
+pub const SIGALRM: i32 = 14;
+pub const SIGTERM: i32 = 15;
+pub const SIGSTKFLT: i32 = 16;
+pub const SIGCHLD: i32 = 17;
+pub const SIGCONT: i32 = 18;
+pub const SIGSTOP: i32 = 19;
+pub const SIGTSTP: i32 = 20;
+pub const SIGTTIN: i32 = 21;
+pub const SIGTTOU: i32 = 22;
+pub const SIGURG: i32 = 23;
+pub const SIGXCPU: i32 = 24;
+pub const SIGXFSZ: i32 = 25;
+pub const SIGVTALRM: i32 = 26;
+pub const SIGPROF: i32 = 27;
+pub const SIGWINCH: i32 = 28;
+pub const SIGIO: i32 = 29;
+pub const SIGPWR: i32 = 30;
+pub const SIGSYS: i32 = 31;
+
+/// Kind of fault raising a signal, reported with it by `wfaultcode`, as `si_code` of
+/// Linux. `SIGSEGV`: no mapping at the address
+pub const SEGV_MAPERR: i32 = 1;
+/// `SIGSEGV`: mapped without the permission for the access
+pub const SEGV_ACCERR: i32 = 2;
+/// `SIGBUS`: no memory behind the physical address
+pub const BUS_ADRERR: i32 = 2;
+/// `SIGILL`: illegal opcode
+pub const ILL_ILLOPC: i32 = 1;
+/// `SIGTRAP`: breakpoint
+pub const TRAP_BRKPT: i32 = 1;
+
+bitflags! {
+    /// A set of signals, bit `n` for signal number `n`
+    pub struct SignalFlags: u32 {
+        const SIGHUP    = 1 << 1;
+        const SIGINT    = 1 << 2;
+        const SIGQUIT   = 1 << 3;
+        const SIGILL    = 1 << 4;
+        const SIGTRAP   = 1 << 5;
+        const SIGABRT   = 1 << 6;
+        const SIGBUS    = 1 << 7;
+        const SIGFPE    = 1 << 8;
+        const SIGKILL   = 1 << 9;
+        const SIGUSR1   = 1 << 10;
+        const SIGSEGV   = 1 << 11;
+        const SIGUSR2   = 1 << 12;
+        const SIGPIPE   = 1 << 13;
+        const SIGALRM   = 1 << 14;
+        const SIGTERM   = 1 << 15;
+        const SIGSTKFLT = 1 << 16;
+        const SIGCHLD   = 1 << 17;
+        const SIGCONT   = 1 << 18;
+        const SIGSTOP   = 1 << 19;
+        const SIGTSTP   = 1 << 20;
+        const SIGTTIN   = 1 << 21;
+        const SIGTTOU   = 1 << 22;
+        const SIGURG    = 1 << 23;
+        const SIGXCPU   = 1 << 24;
+        const SIGXFSZ   = 1 << 25;
+        const SIGVTALRM = 1 << 26;
+        const SIGPROF   = 1 << 27;
+        const SIGWINCH  = 1 << 28;
+        const SIGIO     = 1 << 29;
+        const SIGPWR    = 1 << 30;
+        const SIGSYS    = 1 << 31;
+    }
+}
+
+/// Handler value for the default action of a signal
+pub const SIG_DFL: usize = 0;
+/// Handler value to ignore a signal
+pub const SIG_IGN: usize = 1;
+
+/// Flag of the `SIGCHLD` action: exited children are reaped right away
+pub const SA_NOCLDWAIT: u32 = 2;
+
+/// What to do on a signal, `handler` is the address of a `fn(i32)`,
+/// or `SIG_DFL` or `SIG_IGN`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SignalAction {
+    pub handler: usize,
+    pub mask: SignalFlags,
+    pub flags: u32,
+}
+
+impl Default for SignalAction {
+    fn default() -> Self {
+        Self {
+            handler: SIG_DFL,
+            mask: SignalFlags::empty(),
+            flags: 0,
+        }
+    }
+}
+
+/// `who` of `getrusage`: the calling process
+pub const RUSAGE_SELF: isize = 0;
+/// `who` of `getrusage`: the reaped children of the calling process, only CPU times
+pub const RUSAGE_CHILDREN: isize = -1;
+/// `who` of `getrusage`: the calling thread
+pub const RUSAGE_THREAD: isize = 1;
+
+/// Option of `sys_waitpid`: return 0 instead of blocking if no child has exited
+pub const WNOHANG: u32 = 1;
+/// Flag of `getrandom`: return rather than wait for entropy
+pub const GRND_NONBLOCK: u32 = 1;
+/// Action of `syslog`: read the kernel messages into the buffer
+pub const SYSLOG_ACTION_READ_ALL: usize = 3;
+/// Action of `syslog`: read the kernel messages, then clear them
+pub const SYSLOG_ACTION_READ_CLEAR: usize = 4;
+/// Action of `syslog`: clear the kernel messages
+pub const SYSLOG_ACTION_CLEAR: usize = 5;
+/// Action of `syslog`: return the size of the kernel message buffer
+pub const SYSLOG_ACTION_SIZE_BUFFER: usize = 10;
+bitflags! {
+    /// Resources the child shares with the caller of `clone`
+    pub struct CloneFlags: u32 {
+        /// share the address space
+        const CLONE_VM = 0x100;
+        /// share the fd table
+        const CLONE_FILES = 0x400;
+        /// create a thread of the same process, requires `CLONE_VM` and `CLONE_FILES`
+        const CLONE_THREAD = 0x10000;
+    }
+}
+
+/// `which` of `getpriority` and `setpriority`: `who` is a pid
+pub const PRIO_PROCESS: usize = 0;
+/// `which` of `getpriority` and `setpriority`: `who` is a process group
+pub const PRIO_PGRP: usize = 1;
+
+/// Option of `prctl`: send signal `arg` to the caller when its parent exits, none if 0
+pub const PR_SET_PDEATHSIG: usize = 1;
+/// Option of `prctl`: write the parent death signal as an `i32` to `*arg`
+pub const PR_GET_PDEATHSIG: usize = 2;
+/// Option of `prctl`: name the calling thread after the `\0` terminated string at `arg`
+pub const PR_SET_NAME: usize = 15;
+/// Option of `prctl`: write the name of the calling thread to `*arg`, `[u8; COMM_LEN]`
+pub const PR_GET_NAME: usize = 16;
+/// Option of `prctl`: let the children created from now on reboot the machine and take
+/// harts offline if `arg` is not 0, or stop letting them. Only for a caller that may.
+pub const PR_SET_CHILD_MACHINE_CONTROL: usize = 0x1000;
+/// Size of the name of a thread, including the terminating `\0`
+pub const COMM_LEN: usize = 16;
+
+/// Command of `reboot`: reboot the machine
+pub const LINUX_REBOOT_CMD_RESTART: usize = 0x0123_4567;
+/// Command of `reboot`: power the machine off, QEMU exiting with the code given
+pub const LINUX_REBOOT_CMD_POWER_OFF: usize = 0x4321_fedc;
+
+/// Command of `kcov`: collect the kernel coverage of the caller and of the threads
+/// and processes it creates, anew, `-ENODEV` unless the kernel is built with `KCOV=1`
+pub const KCOV_ENABLE: usize = 0;
+/// Command of `kcov`: stop collecting the kernel coverage of the calling thread
+pub const KCOV_DISABLE: usize = 1;
+/// Command of `kcov`: fill `pcs` with the kernel addresses covered, return their number
+pub const KCOV_COLLECT: usize = 2;
+
+/// Request of `ptrace`: the caller is traced by its parent
+pub const PTRACE_TRACEME: usize = 0;
+/// Request of `ptrace`: write the word at `addr` of the tracee to `*data`
+pub const PTRACE_PEEKDATA: usize = 2;
+/// Request of `ptrace`: write the word `data` at `addr` of the tracee, even in code
+pub const PTRACE_POKEDATA: usize = 5;
+/// Request of `ptrace`: resume the stopped tracee delivering signal `data`, 0 for none
+pub const PTRACE_CONT: usize = 7;
+/// Request of `ptrace`: kill the tracee
+pub const PTRACE_KILL: usize = 8;
+/// Request of `ptrace`: write the registers of the stopped tracee to `*data`, `UserRegs`
+pub const PTRACE_GETREGS: usize = 12;
+/// Request of `ptrace`: set the registers of the stopped tracee to `*data`, `UserRegs`
+pub const PTRACE_SETREGS: usize = 13;
+/// Request of `ptrace`: trace child `pid`, which is stopped by a `SIGSTOP`
+pub const PTRACE_ATTACH: usize = 16;
+/// Request of `ptrace`: stop tracing, resuming the tracee delivering signal `data`
+pub const PTRACE_DETACH: usize = 17;
+
+/// Registers of a stopped tracee: the pc at index 0, then x1 to x31
+pub type UserRegs = [usize; 32];
+
+/// `domain` of `socket`: IPv4
+pub const AF_INET: usize = 2;
+/// `ty` of `socket`: a byte stream, TCP over IPv4
+pub const SOCK_STREAM: usize = 1;
+/// `ty` of `socket`: datagrams, UDP over IPv4
+pub const SOCK_DGRAM: usize = 2;
+/// Address of the guest in the user networking of QEMU
+pub const LOCAL_IP: [u8; 4] = [10, 0, 2, 15];
+/// Address of the host in the user networking of QEMU
+pub const GATEWAY_IP: [u8; 4] = [10, 0, 2, 2];
+/// Largest payload of a datagram
+pub const MAX_UDP_PAYLOAD: usize = 1472;
+
+/// `flags` of `mmap_file`: stores go to the file
+const MAP_SHARED: usize = 0x01;
+/// `flags` of `mmap`: stores are private to the process
+const MAP_PRIVATE: usize = 0x02;
+/// `flags` of `mmap`: anonymous memory
+const MAP_ANONYMOUS: usize = 0x20;
+
+/// `request` of `ioctl` on a framebuffer: get its `FbInfo` at `arg`
+pub const FBIOGET_INFO: usize = 0x4600;
+/// `request` of `ioctl` on a framebuffer: show the pixels of the `FbRect` at
+/// `arg`, of the whole screen if 0
+pub const FBIO_FLUSH: usize = 0x4601;
+
+/// Resolution and layout of a framebuffer, whose pixels are `0x00RRGGBB`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FbInfo {
+    pub width: u32,
+    pub height: u32,
+    /// bytes of a row of pixels
+    pub stride: u32,
+    pub bits_per_pixel: u32,
+}
+
+/// A rectangle of pixels of a framebuffer
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FbRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// An event read from `/dev/input/event*`, with the time it was received
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct InputEvent {
+    /// microseconds since boot
+    pub time_us: u64,
+    pub ty: u16,
+    /// key, button or axis
+    pub code: u16,
+    /// 1 for a key pressed and 0 for released, the motion or the position
+    pub value: i32,
+}
+
+/// `ty` of `InputEvent`: the end of a group of events
+pub const EV_SYN: u16 = 0;
+/// `ty` of `InputEvent`: a key or button
+pub const EV_KEY: u16 = 1;
+/// `ty` of `InputEvent`: a relative motion
+pub const EV_REL: u16 = 2;
+/// `ty` of `InputEvent`: an absolute position
+pub const EV_ABS: u16 = 3;
+/// `code` of `EV_REL`: horizontal motion
+pub const REL_X: u16 = 0;
+/// `code` of `EV_REL`: vertical motion
+pub const REL_Y: u16 = 1;
+/// `code` of `EV_KEY`: the escape key
+pub const KEY_ESC: u16 = 1;
+/// `code` of `EV_KEY`: the left mouse button
+pub const BTN_LEFT: u16 = 0x110;
+
+/// Operation not permitted
+pub const EPERM: isize = 1;
+/// No such process
+pub const ESRCH: isize = 3;
+/// Interrupted by a signal, returned (negated) by blocking calls
+pub const EINTR: isize = 4;
+/// I/O error, returned (negated) by `ptrace` for memory of the tracee not accessible
+pub const EIO: isize = 5;
+/// Bad file descriptor
+pub const EBADF: isize = 9;
+/// No child processes, returned (negated) by `wait` and `waitpid`
+pub const ECHILD: isize = 10;
+/// Try again, returned (negated) e.g. by `set_hart_online` for a hart on its way
+pub const EAGAIN: isize = 11;
+/// Permission denied
+pub const EACCES: isize = 13;
+/// Busy, returned (negated) by `set_hart_online` to take the last online hart offline
+pub const EBUSY: isize = 16;
+/// No such device, returned (negated) by `mmap_file` for a file that cannot be mapped
+pub const ENODEV: isize = 19;
+/// Invalid argument
+pub const EINVAL: isize = 22;
+/// Too many open files
+pub const EMFILE: isize = 24;
+/// Not a device supporting the request, returned (negated) by `ioctl`
+pub const ENOTTY: isize = 25;
+/// Waiting would deadlock, returned (negated) by locks with deadlock detection enabled
+pub const EDEADLK: isize = 35;
+/// Not a socket
+pub const ENOTSOCK: isize = 88;
+/// Datagram too long for a packet, returned (negated) by `sendto`
+pub const EMSGSIZE: isize = 90;
+/// Address already in use, returned (negated) by `bind`
+pub const EADDRINUSE: isize = 98;
+/// Operation not supported by the socket, e.g. `sendto` on a TCP socket
+pub const EOPNOTSUPP: isize = 95;
+/// Connection reset by the peer
+pub const ECONNRESET: isize = 104;
+/// The socket is connected or listening already, returned (negated) by `connect`
+pub const EISCONN: isize = 106;
+/// No answer from the peer, returned (negated) by `connect`
+pub const ETIMEDOUT: isize = 110;
+/// Nothing listening on the port of the peer, returned (negated) by `connect`
+pub const ECONNREFUSED: isize = 111;
+/// No answer from the next hop to the destination, returned (negated) by `sendto`
+pub const EHOSTUNREACH: isize = 113;
+
+/// `resource` of `getrlimit` and `setrlimit`: CPU time in seconds, `SIGXCPU` is raised
+/// every second over the soft limit and `SIGKILL` over the hard one
+pub const RLIMIT_CPU: usize = 0;
+/// `resource` of `getrlimit` and `setrlimit`: size of the user stack, fixed
+pub const RLIMIT_STACK: usize = 3;
+/// `resource` of `getrlimit` and `setrlimit`: one more than the highest fd
+pub const RLIMIT_NOFILE: usize = 7;
+/// `resource` of `getrlimit` and `setrlimit`: bytes mapped in the address space
+pub const RLIMIT_AS: usize = 9;
+/// Limit that does not limit
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+/// Soft and hard limit on a resource, the soft one is enforced
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RLimit {
+    pub rlim_cur: usize,
+    pub rlim_max: usize,
+}
+
+/// Whether a status from `wait` says the child exited normally
+pub fn wifexited(status: i32) -> bool {
+    status & 0x7f == 0
+}
+/// Exit code of a child that exited normally
+pub fn wexitstatus(status: i32) -> i32 {
+    (status >> 8) & 0xff
+}
+/// Whether a status from `wait` says the child was killed by a signal
+pub fn wifsignaled(status: i32) -> bool {
+    status & 0x7f != 0 && status & 0x7f != 0x7f
+}
+/// Signal that killed the child
+pub fn wtermsig(status: i32) -> i32 {
+    status & 0x7f
+}
+/// Whether a status from `wait` says the traced child stopped for the caller
+pub fn wifstopped(status: i32) -> bool {
+    status & 0xff == 0x7f
+}
+/// Signal that stopped the traced child
+pub fn wstopsig(status: i32) -> i32 {
+    (status >> 8) & 0xff
+}
+/// Kind of fault raising the signal that killed the child, e.g. `SEGV_MAPERR`,
+/// 0 if the signal was sent. An extension of the kernel, always 0 in Linux.
+pub fn wfaultcode(status: i32) -> i32 {
+    (status >> 8) & 0xff
+}
+/// Status from `wait` of a child killed by signal `signum` raised by a fault of kind `code`
+pub const fn fault_status(signum: i32, code: i32) -> i32 {
+    signum | code << 8
+}
+
+/// Resource usage of a process, sizes are in kilobytes
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RUsage {
+    pub ru_utime: TimeVal,
+    pub ru_stime: TimeVal,
+    pub ru_maxrss: usize,
+    pub ru_rss: usize,
+    pub ru_heap: usize,
+    pub ru_mmap: usize,
+}
+
+/// Time interval in seconds and nanoseconds
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TimeSpec {
+    pub tv_sec: usize,
+    pub tv_nsec: usize,
+}
+
+/// `mode` of `Stat`: a regular file
+pub const S_IFREG: u32 = 0o100000;
+
+/// Status of a file, as written by `fstat`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Stat {
+    /// type of the file, `S_IFREG` for one on the filesystem and 0 for others
+    pub mode: u32,
+    padding: u32,
+    /// bytes of data
+    pub size: u64,
+    /// last modification, since the Unix epoch
+    pub mtime: TimeSpec,
+}
+
+/// IPv4 socket address as `struct sockaddr_in`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SockAddrIn {
+    /// `AF_INET`
+    pub family: u16,
+    /// in network byte order
+    pub port: u16,
+    pub addr: [u8; 4],
+    pub zero: [u8; 8],
+}
+
+impl SockAddrIn {
+    /// The address of `port` at `addr`
+    pub fn new(addr: [u8; 4], port: u16) -> Self {
+        Self {
+            family: AF_INET as u16,
+            port: port.to_be(),
+            addr,
+            zero: [0; 8],
+        }
+    }
+    /// The port in host byte order
+    pub fn port(&self) -> u16 {
+        u16::from_be(self.port)
+    }
+}
+
+/// Time interval in seconds and microseconds
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TimeVal {
+    pub tv_sec: usize,
+    pub tv_usec: usize,
+}
+
+/// Value of an interval timer: `SIGALRM` is raised after `it_value`,
+/// then every `it_interval` unless it is 0
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ITimerVal {
+    pub it_interval: TimeVal,
+    pub it_value: TimeVal,
+}
+
+/// The interval timer counting down in real time
+pub const ITIMER_REAL: usize = 0;
+
+/// The wall clock, in time since the Unix epoch
+pub const CLOCK_REALTIME: usize = 0;
+/// Time since boot, not affected by changes of the wall clock
+pub const CLOCK_MONOTONIC: usize = 1;
+
+/// CPU times of a process and of its reaped children in `1 / CLK_TCK` seconds
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Tms {
+    pub tms_utime: usize,
+    pub tms_stime: usize,
+    pub tms_cutime: usize,
+    pub tms_cstime: usize,
+}
+
+/// Unit of the times of `times` per second
+pub const CLK_TCK: usize = 100;
+
+/// A thread listed by `task_info`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TaskInfo {
+    pub pid: usize,
+    /// 0 for kernel threads
+    pub tid: usize,
+    pub ppid: usize,
+    pub priority: usize,
+    pub nice: isize,
+    pub cpu: usize,
+    /// resident set size of the process in kilobytes
+    pub rss: usize,
+    /// `b'R'` if running or ready to run, `b'S'` if blocked
+    pub state: u8,
+    /// name padded with `\0`
+    pub comm: [u8; COMM_LEN],
+    /// switches out because it blocked or yielded
+    pub nvcsw: usize,
+    /// switches out because its time slice was used up
+    pub nivcsw: usize,
+    /// time in microseconds spent running
+    pub run_us: usize,
+    /// time in microseconds spent ready but waiting for a hart
+    pub wait_us: usize,
+}
+
+impl TaskInfo {
+    /// Name of the thread, without the padding
+    pub fn comm(&self) -> &str {
+        let len = self.comm.iter().position(|&c| c == 0).unwrap_or(COMM_LEN);
+        core::str::from_utf8(&self.comm[..len]).unwrap_or("?")
     }
 }
 
+/// pid and tid of the idle control flow of a hart in a `SwitchEvent`
+pub const SWITCH_IDLE: usize = usize::MAX;
+
+/// A hart switching from one task to another, listed by `sched_trace`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SwitchEvent {
+    /// microseconds since boot
+    pub time_us: usize,
+    pub cpu: usize,
+    pub prev_pid: usize,
+    pub prev_tid: usize,
+    pub next_pid: usize,
+    pub next_tid: usize,
+}
+
 pub fn open(path: &str, flags: OpenFlags) -> isize {
     sys_open(path, flags.bits)
 }
@@ -65,50 +670,440 @@ pub fn read(fd: usize, buf: &mut [u8]) -> isize {
 pub fn write(fd: usize, buf: &[u8]) -> isize {
     sys_write(fd, buf)
 }
+/// Write the status of file `fd` to `st`
+pub fn fstat(fd: usize, st: &mut Stat) -> isize {
+    sys_fstat(fd, st as *mut _)
+}
+/// Fill `buf` with random bytes, return the number filled
+pub fn getrandom(buf: &mut [u8], flags: u32) -> isize {
+    sys_getrandom(buf, flags)
+}
+/// Do `action` on the kernel messages, reading them into `buf`, return the
+/// number of bytes read or the size asked for
+pub fn syslog(action: usize, buf: &mut [u8]) -> isize {
+    sys_syslog(action, buf)
+}
+/// Read input events from `fd` into `events`, return the number read
+pub fn read_events(fd: usize, events: &mut [InputEvent]) -> isize {
+    let buf = unsafe {
+        core::slice::from_raw_parts_mut(
+            events.as_mut_ptr() as *mut u8,
+            events.len() * core::mem::size_of::<InputEvent>(),
+        )
+    };
+    match sys_read(fd, buf) {
+        len if len < 0 => len,
+        len => len / core::mem::size_of::<InputEvent>() as isize,
+    }
+}
+/// Carry out the device-specific `request` on file `fd` with argument `arg`
+pub fn ioctl(fd: usize, request: usize, arg: usize) -> isize {
+    sys_ioctl(fd, request, arg)
+}
+/// Open a socket, only `AF_INET` with `SOCK_DGRAM` or `SOCK_STREAM` and
+/// `protocol` 0. Return its fd.
+pub fn socket(domain: usize, ty: usize, protocol: usize) -> isize {
+    sys_socket(domain, ty, protocol)
+}
+/// Bind socket `fd` to the port of `addr`, a free one if 0
+pub fn bind(fd: usize, addr: &SockAddrIn) -> isize {
+    sys_bind(fd, addr)
+}
+/// Block until TCP socket `fd` is connected to `addr`, binding it to a free
+/// port if not bound
+pub fn connect(fd: usize, addr: &SockAddrIn) -> isize {
+    sys_connect(fd, addr)
+}
+/// Listen for connections on TCP socket `fd`, binding it to a free port if
+/// not bound, queueing up to `backlog` of them
+pub fn listen(fd: usize, backlog: usize) -> isize {
+    sys_listen(fd, backlog)
+}
+/// Block until a connection to listening socket `fd` is established. Return
+/// the fd of a socket of it, and its peer in `addr`.
+pub fn accept(fd: usize, addr: &mut SockAddrIn) -> isize {
+    sys_accept(fd, addr)
+}
+/// Send `buf` in a datagram from socket `fd` to `addr`, binding it to a free
+/// port if not bound. Return the length sent.
+pub fn sendto(fd: usize, buf: &[u8], addr: &SockAddrIn) -> isize {
+    sys_sendto(fd, buf, addr)
+}
+/// Block until a datagram arrives on socket `fd` and copy it to `buf`,
+/// truncated, and its sender to `addr`. Return the length copied.
+pub fn recvfrom(fd: usize, buf: &mut [u8], addr: &mut SockAddrIn) -> isize {
+    sys_recvfrom(fd, buf, addr)
+}
 pub fn exit(exit_code: i32) -> ! {
     sys_exit(exit_code);
 }
+/// Exit the process with all its threads, from any of them
+pub fn exit_group(exit_code: i32) -> ! {
+    sys_exit_group(exit_code);
+}
 pub fn yield_() -> isize {
     sys_yield()
 }
+pub fn kill(pid: usize, signum: i32) -> isize {
+    sys_kill(pid as isize, signum)
+}
+/// Send signal `signum` to every process in process group `pgid`,
+/// the group of the caller if it is 0
+pub fn killpg(pgid: usize, signum: i32) -> isize {
+    sys_kill(-(pgid as isize), signum)
+}
+pub fn sigaction(
+    signum: i32,
+    action: Option<&SignalAction>,
+    old_action: Option<&mut SignalAction>,
+) -> isize {
+    sys_sigaction(
+        signum,
+        action.map_or(core::ptr::null(), |a| a as *const _),
+        old_action.map_or(core::ptr::null_mut(), |a| a as *mut _),
+    )
+}
+/// Replace the signal mask, return the old one
+pub fn sigprocmask(mask: u32) -> isize {
+    sys_sigprocmask(mask)
+}
+/// Return from a signal handler, done by the kernel's trampoline when the handler returns
+pub fn sigreturn() -> isize {
+    sys_sigreturn()
+}
+pub fn set_priority(prio: isize) -> isize {
+    sys_set_priority(prio)
+}
+/// Carry out `cmd` (`LINUX_REBOOT_CMD_*`), powering off with exit code `code`. Only
+/// the init program and the processes it starts, such as the shell, may; the call
+/// returns only if it fails.
+pub fn reboot(cmd: usize, code: i32) -> isize {
+    sys_reboot(cmd, code as usize)
+}
+/// Niceness of process `who`, the caller if it is 0, or the lowest niceness in
+/// process group `who`, the group of the caller if it is 0, with `PRIO_PGRP`.
+/// Errors are returned as `Err` since any value in [-20, 19] is a valid niceness.
+pub fn getpriority(which: usize, who: usize) -> Result<isize, isize> {
+    match sys_getpriority(which, who) {
+        err if err < 0 => Err(err),
+        prio => Ok(20 - prio),
+    }
+}
+/// Set the niceness of the processes selected as in `getpriority`. It can only be raised.
+pub fn setpriority(which: usize, who: usize, nice: isize) -> isize {
+    sys_setpriority(which, who, nice)
+}
+/// Add `inc` to the niceness of the caller and return the new niceness
+pub fn nice(inc: isize) -> Result<isize, isize> {
+    let nice = getpriority(PRIO_PROCESS, 0)?;
+    match setpriority(PRIO_PROCESS, 0, nice + inc) {
+        0 => getpriority(PRIO_PROCESS, 0),
+        err => Err(err),
+    }
+}
 pub fn get_time() -> isize {
     sys_get_time()
 }
+/// Restrict thread `pid`, the caller if it is 0 or the main thread of process `pid`
+/// otherwise, to the harts in `mask`, bit i for hart i
+pub fn sched_setaffinity(pid: usize, mask: usize) -> isize {
+    sys_sched_setaffinity(pid, core::mem::size_of::<usize>(), &mask as *const _)
+}
+/// Mask of the harts thread `pid` may run on, see `sched_setaffinity`
+pub fn sched_getaffinity(pid: usize, mask: &mut usize) -> isize {
+    sys_sched_getaffinity(pid, core::mem::size_of::<usize>(), mask as *mut _)
+}
+pub fn prctl(option: usize, arg: usize) -> isize {
+    sys_prctl(option, arg)
+}
+/// Carry out `cmd` (`KCOV_*`) on the kernel coverage of the calling thread
+pub fn kcov(cmd: usize, pcs: &mut [usize]) -> isize {
+    sys_kcov(cmd, pcs.as_mut_ptr(), pcs.len())
+}
+/// Take `hart` offline, moving its threads to the other harts, or bring it back
+/// online, returning once it is. Only the init program and the processes it starts,
+/// such as the shell, may.
+pub fn set_hart_online(hart: usize, online: bool) -> isize {
+    sys_set_hart_online(hart, online as usize)
+}
+/// Hart running the caller
+pub fn getcpu() -> isize {
+    let mut cpu = 0u32;
+    match sys_getcpu(&mut cpu as *mut _, core::ptr::null_mut()) {
+        0 => cpu as isize,
+        err => err,
+    }
+}
 pub fn getpid() -> isize {
     sys_getpid()
 }
+pub fn getppid() -> isize {
+    sys_getppid()
+}
+pub fn setpgid(pid: usize, pgid: usize) -> isize {
+    sys_setpgid(pid, pgid)
+}
+pub fn getpgid(pid: usize) -> isize {
+    sys_getpgid(pid)
+}
+pub fn getpgrp() -> isize {
+    sys_getpgid(0)
+}
+pub fn getsid(pid: usize) -> isize {
+    sys_getsid(pid)
+}
+pub fn setsid() -> isize {
+    sys_setsid()
+}
+pub fn getrlimit(resource: usize, rlim: &mut RLimit) -> isize {
+    sys_getrlimit(resource, rlim as *mut _)
+}
+/// Set the limits on `resource`, the hard limit can only be lowered
+pub fn setrlimit(resource: usize, rlim: &RLimit) -> isize {
+    sys_setrlimit(resource, rlim as *const _)
+}
+pub fn getrusage(who: isize, usage: &mut RUsage) -> isize {
+    sys_getrusage(who, usage as *mut _)
+}
+/// Write the CPU times of the caller and its reaped children to `tms` and
+/// return the time since boot, in `1 / CLK_TCK` seconds
+pub fn times(tms: &mut Tms) -> isize {
+    sys_times(tms as *mut _)
+}
+/// Fill `tasks` with the threads that have not exited and return their number,
+/// which is more than `tasks.len()` if they do not all fit
+pub fn task_info(tasks: &mut [TaskInfo]) -> isize {
+    sys_task_info(tasks.as_mut_ptr(), tasks.len())
+}
+/// Move the oldest context switches traced by the kernel to `events` and return
+/// their number. Only the latest switches of each hart are kept.
+pub fn sched_trace(events: &mut [SwitchEvent]) -> isize {
+    sys_sched_trace(events.as_mut_ptr(), events.len())
+}
+pub fn sbrk(size: i32) -> isize {
+    sys_sbrk(size)
+}
+pub fn mmap(start: usize, len: usize, prot: usize) -> isize {
+    sys_mmap(start, len, prot, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0)
+}
+/// Map `len` bytes of file `fd` from `offset`, shared with it, e.g. a framebuffer
+pub fn mmap_file(start: usize, len: usize, prot: usize, fd: usize, offset: usize) -> isize {
+    sys_mmap(start, len, prot, MAP_SHARED, fd, offset)
+}
+pub fn munmap(start: usize, len: usize) -> isize {
+    sys_munmap(start, len)
+}
 pub fn fork() -> isize {
-    sys_fork()
+    sys_clone(SIGCHLD as usize, 0)
+}
+/// Run `entry(arg)` in a child sharing the resources in `flags` on the stack ending at
+/// `stack_top`, which must outlive it. The child exits with the return value of `entry`.
+/// Return the tid of the child with `CLONE_THREAD`, its pid otherwise.
+pub fn clone(flags: CloneFlags, stack_top: usize, entry: fn(usize) -> i32, arg: usize) -> isize {
+    sys_clone_entry(
+        (flags.bits | SIGCHLD as u32) as usize,
+        stack_top & !0xf,
+        entry,
+        arg,
+    )
+}
+/// Run the program at `path` with the arguments `args`, pointers to `\0`-terminated
+/// strings ending with a null pointer, and the environment of the calling process
+pub fn exec(path: &str, args: &[*const u8]) -> isize {
+    let mut envp: Vec<*const u8> = unsafe { ENVIRON.iter().map(|var| var.as_ptr()).collect() };
+    envp.push(core::ptr::null::<u8>());
+    sys_exec(path, args, envp.as_slice())
+}
+/// Like `exec`, with the environment `envp` of `NAME=value` strings
+pub fn execve(path: &str, argv: &[*const u8], envp: &[*const u8]) -> isize {
+    sys_exec(path, argv, envp)
+}
+/// Start the program at `path` in a new child process with the arguments `args` and
+/// the environment of the calling process, as `fork` followed by `exec` in the child
+/// would but without copying the address space. Return the pid of the child.
+pub fn spawn(path: &str, args: &[*const u8]) -> isize {
+    let mut envp: Vec<*const u8> = unsafe { ENVIRON.iter().map(|var| var.as_ptr()).collect() };
+    envp.push(core::ptr::null::<u8>());
+    sys_spawn(path, args, envp.as_slice())
+}
+/// Like `spawn`, with the environment `envp` of `NAME=value` strings
+pub fn spawnve(path: &str, argv: &[*const u8], envp: &[*const u8]) -> isize {
+    sys_spawn(path, argv, envp)
 }
-pub fn exec(path: &str) -> isize {
-    sys_exec(path)
+pub fn wait(status: &mut i32) -> isize {
+    sys_waitpid(-1, status as *mut _, 0)
 }
-pub fn wait(exit_code: &mut i32) -> isize {
+pub fn waitpid(pid: usize, status: &mut i32) -> isize {
+    sys_waitpid(pid as isize, status as *mut _, 0)
+}
+/// Carry out `request` (`PTRACE_*`) on child `pid`. A traced child stops before
+/// handling each signal but `SIGKILL`, reported by `waitpid` with `wifstopped`.
+pub fn ptrace(request: usize, pid: usize, addr: usize, data: usize) -> isize {
+    sys_ptrace(request, pid, addr, data)
+}
+/// Like `waitpid`, or `wait` if `pid` is -1, but return 0 instead of
+/// blocking if no child has exited yet
+pub fn waitpid_nohang(pid: isize, status: &mut i32) -> isize {
+    sys_waitpid(pid, status as *mut _, WNOHANG)
+}
+pub fn thread_create(entry: usize, arg: usize) -> isize {
+    sys_thread_create(entry, arg)
+}
+pub fn gettid() -> isize {
+    sys_gettid()
+}
+pub fn waittid(tid: usize) -> isize {
     loop {
-        match sys_waitpid(-1, exit_code as *mut _) {
+        match sys_waittid(tid) {
             -2 => {
                 yield_();
             }
-            // -1 or a real pid
-            exit_pid => return exit_pid,
+            // -1 or the exit code
+            exit_code => return exit_code,
         }
     }
 }
-
-pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
+/// Make locking a mutex or taking a semaphore fail with `-EDEADLK` rather than wait
+/// if the threads may then deadlock, or wait regardless if `enabled` is false
+pub fn enable_deadlock_detect(enabled: bool) -> isize {
+    sys_enable_deadlock_detect(enabled as usize)
+}
+/// Create a mutex whose waiters yield the CPU, return its id
+pub fn mutex_create() -> isize {
+    sys_mutex_create(0)
+}
+/// Create a mutex whose waiters block, with priority inheritance, return its id
+pub fn mutex_blocking_create() -> isize {
+    sys_mutex_create(1)
+}
+/// Create a mutex whose waiters spin while the holder runs on another hart and block
+/// otherwise, with priority inheritance, return its id
+pub fn mutex_adaptive_create() -> isize {
+    sys_mutex_create(2)
+}
+/// Lock mutex `id`, waiting while another thread holds it
+pub fn mutex_lock(id: usize) -> isize {
     loop {
-        match sys_waitpid(pid as isize, exit_code as *mut _) {
-            -2 => {
-                yield_();
-            }
-            // -1 or a real pid
-            exit_pid => return exit_pid,
+        match sys_mutex_lock(id) {
+            // a signal handler ran, keep waiting
+            err if err == -EINTR => {}
+            ret => return ret,
         }
     }
 }
-pub fn sleep(period_ms: usize) {
-    let start = sys_get_time();
-    while sys_get_time() < start + period_ms as isize {
-        sys_yield();
+pub fn mutex_unlock(id: usize) -> isize {
+    sys_mutex_unlock(id)
+}
+/// Create a semaphore with `res_count` resources, return its id
+pub fn semaphore_create(res_count: usize) -> isize {
+    sys_semaphore_create(res_count)
+}
+/// Release a resource of semaphore `id`
+pub fn semaphore_up(id: usize) -> isize {
+    sys_semaphore_up(id)
+}
+/// Take a resource of semaphore `id`, waiting until one is released if none is left
+pub fn semaphore_down(id: usize) -> isize {
+    loop {
+        match sys_semaphore_down(id) {
+            // a signal handler ran, keep waiting
+            err if err == -EINTR => {}
+            ret => return ret,
+        }
+    }
+}
+/// Create a condition variable, return its id
+pub fn condvar_create() -> isize {
+    sys_condvar_create()
+}
+/// Wake up the thread waiting the longest on condition variable `condvar_id`
+pub fn condvar_signal(condvar_id: usize) -> isize {
+    sys_condvar_signal(condvar_id)
+}
+/// Wake up all the threads waiting on condition variable `condvar_id`
+pub fn condvar_broadcast(condvar_id: usize) -> isize {
+    sys_condvar_broadcast(condvar_id)
+}
+/// Unlock mutex `mutex_id`, held by the caller, and wait on condition variable
+/// `condvar_id`, then lock the mutex again. Wakeups may be spurious, callers check
+/// what they wait for again.
+pub fn condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
+    match sys_condvar_wait(condvar_id, mutex_id) {
+        // a signal handler ran, the mutex is locked again
+        err if err == -EINTR => 0,
+        ret => ret,
+    }
+}
+/// Create a reader-writer lock, return its id
+pub fn rwlock_create() -> isize {
+    sys_rwlock_create()
+}
+/// Lock reader-writer lock `id` for reading, waiting while a writer holds it or waits for it
+pub fn rwlock_read(id: usize) -> isize {
+    loop {
+        match sys_rwlock_read(id) {
+            // a signal handler ran, keep waiting
+            err if err == -EINTR => {}
+            ret => return ret,
+        }
+    }
+}
+/// Lock reader-writer lock `id` for writing, waiting while anyone else holds it
+pub fn rwlock_write(id: usize) -> isize {
+    loop {
+        match sys_rwlock_write(id) {
+            // a signal handler ran, keep waiting
+            err if err == -EINTR => {}
+            ret => return ret,
+        }
     }
 }
+/// Release reader-writer lock `id`, held for reading or writing
+pub fn rwlock_unlock(id: usize) -> isize {
+    sys_rwlock_unlock(id)
+}
+pub fn nanosleep(req: &TimeSpec, rem: Option<&mut TimeSpec>) -> isize {
+    sys_nanosleep(
+        req as *const _,
+        rem.map_or(core::ptr::null_mut(), |rem| rem as *mut _),
+    )
+}
+/// Write the time of clock `clock_id`, `CLOCK_REALTIME` or `CLOCK_MONOTONIC`, to `tp`
+pub fn clock_gettime(clock_id: usize, tp: &mut TimeSpec) -> isize {
+    sys_clock_gettime(clock_id, tp as *mut _)
+}
+/// Write the time since the Unix epoch to `tv`
+pub fn gettimeofday(tv: &mut TimeVal) -> isize {
+    sys_gettimeofday(tv as *mut _)
+}
+pub fn getitimer(which: usize, curr: &mut ITimerVal) -> isize {
+    sys_getitimer(which, curr as *mut _)
+}
+pub fn setitimer(which: usize, new: &ITimerVal, old: Option<&mut ITimerVal>) -> isize {
+    sys_setitimer(
+        which,
+        new as *const _,
+        old.map_or(core::ptr::null_mut(), |old| old as *mut _),
+    )
+}
+/// Raise `SIGALRM` in `seconds`, or cancel the alarm if 0. Return the
+/// seconds left of the previous alarm, rounded up, 0 if there was none.
+pub fn alarm(seconds: usize) -> usize {
+    let new = ITimerVal {
+        it_value: TimeVal {
+            tv_sec: seconds,
+            tv_usec: 0,
+        },
+        ..Default::default()
+    };
+    let mut old = ITimerVal::default();
+    setitimer(ITIMER_REAL, &new, Some(&mut old));
+    old.it_value.tv_sec + (old.it_value.tv_usec > 0) as usize
+}
+pub fn sleep(period_ms: usize) {
+    let req = TimeSpec {
+        tv_sec: period_ms / 1000,
+        tv_nsec: period_ms % 1000 * 1_000_000,
+    };
+    nanosleep(&req, None);
+}